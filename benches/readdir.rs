@@ -0,0 +1,85 @@
+//! Lists a directory of 10,000 entries with every inode table made
+//! unreadable, showing that listing never touches an inode, then looks up
+//! its first and last entries.
+//!
+//! ```text
+//! cargo bench --bench readdir --features testing
+//! ```
+
+extern crate ext2;
+
+use std::time::Instant;
+
+use ext2::fs::mkfs::Builder;
+use ext2::fs::sync::Synced;
+use ext2::fs::Ext2;
+use ext2::sector::Size512;
+use ext2::sys::inode::{FileType, Permissions};
+use ext2::volume::{Fault, Faulty, Shared};
+
+const ENTRIES: usize = 10_000;
+
+fn main() {
+    let volume = Shared::new(Faulty::new(vec![0_u8; 16 << 20]));
+    Builder::new()
+        .inodes(ENTRIES as u32 + 256)
+        .build::<Size512, _>(&mut &volume)
+        .unwrap();
+
+    let fs = Synced::<Ext2<Size512, _>>::new(&volume).unwrap();
+    fs.mkdir(b"/big", Permissions::U_READ | Permissions::U_EXEC)
+        .unwrap();
+    let start = Instant::now();
+    for i in 0..ENTRIES {
+        let path = format!("/big/file-{:05}", i);
+        fs.create_file(path.as_bytes(), Permissions::U_READ)
+            .unwrap();
+    }
+    println!("{:>10}: {:10.3} ms", "create", millis(start));
+
+    let dir = fs.lookup(b"/big").unwrap();
+    {
+        let fs = fs.inner();
+        let inode_blocks =
+            fs.inodes_count() * fs.inode_size() / fs.block_size();
+        let sectors = (inode_blocks * fs.block_size() / 512) as u64;
+        let mut volume = volume.write();
+        for group in fs.block_groups() {
+            let start = group.inode_table_block() as u64
+                * (fs.block_size() / 512) as u64;
+            volume.inject(Fault::FailSectors(start..start + sectors));
+        }
+    }
+
+    let reads = volume.read().reads();
+    let start = Instant::now();
+    let mut count = 0;
+    for entry in dir.directory().unwrap() {
+        let entry = entry.unwrap();
+        assert!(entry.file_type.is_some());
+        count += 1;
+    }
+    let reads = volume.read().reads() - reads;
+    println!("{:>10}: {:10.3} ms, {} reads", "list", millis(start), reads);
+    // `.` and `..` on top of the files
+    assert_eq!(count, ENTRIES + 2);
+
+    let entry = dir.directory().unwrap().last().unwrap().unwrap();
+    assert_eq!(entry.file_type, Some(FileType::File));
+    assert!(entry.metadata().is_err());
+
+    // finding an entry stops reading at the block holding it
+    for name in &["file-00000", "file-09999"] {
+        let reads = volume.read().reads();
+        let start = Instant::now();
+        let entry = dir.directory().unwrap().find(name.as_bytes()).unwrap();
+        assert!(entry.is_some());
+        let reads = volume.read().reads() - reads;
+        println!("{:>10}: {:10.3} ms, {} reads", name, millis(start), reads);
+    }
+}
+
+fn millis(start: Instant) -> f64 {
+    let time = start.elapsed();
+    time.as_secs() as f64 * 1e3 + time.subsec_nanos() as f64 * 1e-6
+}
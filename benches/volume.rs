@@ -0,0 +1,75 @@
+//! Compares the sequential read throughput of `FileVolume` and `MmapVolume`
+//! over a scratch file, 1 GiB unless `EXT2_BENCH_MIB` says otherwise.
+//!
+//! ```text
+//! cargo bench --features mmap
+//! ```
+
+extern crate ext2;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use ext2::sector::{Address, Size512};
+use ext2::volume::{FileVolume, MmapVolume, Volume};
+
+/// Size of each read, in bytes
+const CHUNK: u64 = 64 * 1024;
+
+/// Reads all of `volume` in order, returning the time taken and a checksum
+/// that keeps the reads from being optimized out
+fn read_all<V: Volume<u8, Size512>>(volume: &V, len: u64) -> (Duration, u64) {
+    let start = Instant::now();
+    let mut sum = 0_u64;
+    let mut offset = 0;
+    while offset < len {
+        let end = (offset + CHUNK).min(len);
+        let range = Address::from(offset)..Address::from(end);
+        let slice = volume.slice(range).map_err(|err| err.into()).unwrap();
+        sum = sum
+            .wrapping_add(slice.iter().step_by(512).map(|&b| b as u64).sum());
+        offset = end;
+    }
+    (start.elapsed(), sum)
+}
+
+fn report(name: &str, len: u64, (time, _): (Duration, u64)) {
+    let secs = time.as_secs() as f64 + time.subsec_nanos() as f64 * 1e-9;
+    let mib = len as f64 / (1 << 20) as f64;
+    println!("{:>10}: {:8.3} s, {:10.1} MiB/s", name, secs, mib / secs);
+}
+
+fn main() {
+    let mib = env::var("EXT2_BENCH_MIB")
+        .ok()
+        .and_then(|mib| mib.parse().ok())
+        .unwrap_or(1024_u64);
+    let len = mib << 20;
+    let path =
+        env::temp_dir().join(format!("ext2-rs-bench-{}", std::process::id()));
+
+    {
+        let mut file = File::create(&path).unwrap();
+        let chunk: Vec<u8> = (0..CHUNK).map(|i| i as u8).collect();
+        for _ in 0..len / CHUNK {
+            file.write_all(&chunk).unwrap();
+        }
+        file.sync_all().unwrap();
+    }
+
+    let file_volume = FileVolume::new(File::open(&path).unwrap());
+    let file = File::open(&path).unwrap();
+    let mmap_volume = unsafe { MmapVolume::map(&file).unwrap() };
+
+    // one pass each to warm the page cache, then the measured ones
+    let file_sum = read_all(&file_volume, len).1;
+    let mmap_sum = read_all(&mmap_volume, len).1;
+    assert_eq!(file_sum, mmap_sum);
+    report("FileVolume", len, read_all(&file_volume, len));
+    report("MmapVolume", len, read_all(&mmap_volume, len));
+
+    drop(mmap_volume);
+    fs::remove_file(&path).unwrap();
+}
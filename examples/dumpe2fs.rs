@@ -0,0 +1,44 @@
+//! Prints the superblock and the block group descriptors of an ext2 image,
+//! like `dumpe2fs` does.
+//!
+//! ```text
+//! cargo run --example dumpe2fs --features std -- ext2.img
+//! ```
+
+extern crate ext2;
+
+use std::env;
+use std::process;
+
+use ext2::fs::Ext2;
+use ext2::sector::Size512;
+use ext2::sys::block_group::BlockGroupDescriptor;
+use ext2::volume::FileVolume;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: dumpe2fs <image>");
+            process::exit(2);
+        }
+    };
+
+    let volume = FileVolume::open(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    // features don't matter for reading the metadata
+    let fs = Ext2::<Size512, _>::new_forced(volume).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+
+    print!("{}", fs.superblock().summary());
+    println!();
+    println!();
+    print!(
+        "{}",
+        BlockGroupDescriptor::table_summary(fs.block_groups(), fs.superblock())
+    );
+}
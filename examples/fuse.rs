@@ -0,0 +1,288 @@
+//! Mounts an ext2 image read-only through FUSE, so that any tool can be run
+//! against it. Unmount it with `fusermount -u <mountpoint>`.
+//!
+//! ```text
+//! cargo run --example fuse --features fuse -- ext2.img /mnt
+//! ```
+//!
+//! Inode numbers are those of the image, except that the root directory,
+//! inode 2, is inode 1 to the kernel, as FUSE wants it.
+
+extern crate ext2;
+extern crate fuser;
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::os::unix::ffi::OsStrExt;
+use std::process;
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory,
+    ReplyEntry, ReplyStatfs, Request,
+};
+
+use ext2::error::Error;
+use ext2::fs::sync::{Inode, Synced};
+use ext2::fs::{Ext2, Metadata, MountOptions};
+use ext2::sector::Size512;
+use ext2::sys::inode::FileType;
+use ext2::volume::FileVolume;
+
+/// Inode number of the root directory of an ext2 file system
+const EXT2_ROOT: u32 = 2;
+
+/// How long the kernel may keep attributes and entries, which never change
+/// under a read-only mount
+const TTL: Duration = Duration::from_secs(60);
+
+struct Ext2Fuse {
+    fs: Synced<Ext2<Size512, FileVolume>>,
+    block_size: u32,
+}
+
+impl Ext2Fuse {
+    fn inode(&self, ino: u64) -> Result<Inode<Size512, FileVolume>, Error> {
+        self.fs.open_inode(from_fuse(ino))
+    }
+
+    fn attr(&self, metadata: &Metadata) -> FileAttr {
+        let time = |secs: u32| UNIX_EPOCH + Duration::from_secs(secs as u64);
+        FileAttr {
+            ino: to_fuse(metadata.ino()),
+            size: metadata.len(),
+            blocks: metadata.blocks() as u64,
+            atime: time(metadata.accessed()),
+            mtime: time(metadata.modified()),
+            ctime: time(metadata.created()),
+            crtime: time(metadata.created()),
+            kind: kind(metadata.file_type()),
+            perm: metadata.permissions().bits(),
+            nlink: metadata.nlink() as u32,
+            uid: metadata.uid() as u32,
+            gid: metadata.gid() as u32,
+            rdev: metadata.device().map_or(0, |(major, minor)| {
+                // the encoding of `makedev(3)`
+                (minor & 0xff) | (major << 8) | ((minor & !0xff) << 12)
+            }),
+            blksize: self.block_size,
+            flags: 0,
+        }
+    }
+
+    fn lookup_entry(
+        &self,
+        parent: u64,
+        name: &OsStr,
+    ) -> Result<FileAttr, Error> {
+        let dir = self.inode(parent)?;
+        let mut entries = dir.directory().ok_or(Error::NotADirectory {
+            inode: dir.ino(),
+            name: name.to_string_lossy().into_owned(),
+        })?;
+        let entry = entries.find(name.as_bytes())?.ok_or(Error::NotFound {
+            name: name.to_string_lossy().into_owned(),
+            parent: dir.ino(),
+        })?;
+        let inode = self.fs.open_inode(entry.ino)?;
+        Ok(self.attr(&inode.metadata()))
+    }
+
+    fn read_at(
+        &self,
+        ino: u64,
+        offset: u64,
+        size: usize,
+    ) -> Result<Vec<u8>, Error> {
+        let inode = self.inode(ino)?;
+        let size = size.min(inode.size64().saturating_sub(offset) as usize);
+        let mut buf = vec![0; size];
+        inode.read_exact_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Adds the entries of directory `ino` from `offset` on to `reply`,
+    /// until it's full
+    fn read_dir(
+        &self,
+        ino: u64,
+        offset: u64,
+        reply: &mut ReplyDirectory,
+    ) -> Result<(), Error> {
+        let dir = self.inode(ino)?;
+        let mut entries = dir.entries_from(offset).ok_or(
+            Error::NotADirectory {
+                inode: dir.ino(),
+                name: String::new(),
+            },
+        )?;
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let file_type = match entry.file_type {
+                Some(file_type) => file_type,
+                // file systems without the filetype feature only keep it
+                // in the inode
+                None => self.fs.open_inode(entry.ino)?.file_type(),
+            };
+            // the offset of an entry is where the next one is
+            let full = reply.add(
+                to_fuse(entry.ino),
+                entries.pos() as i64,
+                kind(file_type),
+                OsStr::from_bytes(&entry.name),
+            );
+            if full {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Filesystem for Ext2Fuse {
+    fn lookup(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        reply: ReplyEntry,
+    ) {
+        match self.lookup_entry(parent, name) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err.errno()),
+        }
+    }
+
+    fn getattr(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: Option<u64>,
+        reply: ReplyAttr,
+    ) {
+        match self.inode(ino) {
+            Ok(inode) => reply.attr(&TTL, &self.attr(&inode.metadata())),
+            Err(err) => reply.error(err.errno()),
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request, ino: u64, reply: ReplyData) {
+        match self.inode(ino).and_then(|inode| inode.read_link()) {
+            Ok(target) => reply.data(&target),
+            Err(err) => reply.error(err.errno()),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        match self.read_at(ino, offset as u64, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err.errno()),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        match self.read_dir(ino, offset as u64, &mut reply) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err.errno()),
+        }
+    }
+
+    fn statfs(&mut self, _req: &Request, _ino: u64, reply: ReplyStatfs) {
+        let stats = self.fs.read().stats();
+        reply.statfs(
+            stats.blocks,
+            stats.free_blocks,
+            stats.available_blocks,
+            stats.inodes,
+            stats.free_inodes,
+            stats.block_size,
+            stats.max_name_len,
+            stats.frag_size,
+        );
+    }
+}
+
+/// The inode number FUSE knows inode `ino` by
+fn to_fuse(ino: u32) -> u64 {
+    match ino {
+        EXT2_ROOT => fuser::FUSE_ROOT_ID,
+        ino => ino as u64,
+    }
+}
+
+/// The inode FUSE knows as `ino`, or 0, which no inode is, for inode
+/// numbers that can't be ext2's
+fn from_fuse(ino: u64) -> u32 {
+    match ino {
+        fuser::FUSE_ROOT_ID => EXT2_ROOT,
+        // inode 2 is only known as the root
+        2 => 0,
+        ino if ino > u32::MAX as u64 => 0,
+        ino => ino as u32,
+    }
+}
+
+fn kind(file_type: FileType) -> fuser::FileType {
+    match file_type {
+        FileType::Directory => fuser::FileType::Directory,
+        FileType::Symlink => fuser::FileType::Symlink,
+        FileType::CharDevice => fuser::FileType::CharDevice,
+        FileType::BlockDevice => fuser::FileType::BlockDevice,
+        FileType::Fifo => fuser::FileType::NamedPipe,
+        FileType::Socket => fuser::FileType::Socket,
+        FileType::File | FileType::Unknown => fuser::FileType::RegularFile,
+    }
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 3 {
+        eprintln!("usage: fuse <image> <mountpoint>");
+        process::exit(2);
+    }
+    let (path, mountpoint) = (&args[1], &args[2]);
+
+    // the image only needs to be readable
+    let volume = File::open(path).map(FileVolume::new).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    let mut options = MountOptions::new();
+    options.read_only(true);
+    let fs = Synced::<Ext2<Size512, _>>::new_with_options(volume, &options)
+        .unwrap_or_else(|err| {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        });
+    let block_size = fs.read().block_size() as u32;
+
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(path.clone()),
+        MountOption::Subtype(String::from("ext2")),
+        MountOption::DefaultPermissions,
+    ];
+    let fuse = Ext2Fuse { fs, block_size };
+    if let Err(err) = fuser::mount2(fuse, mountpoint, &options) {
+        eprintln!("{}: {}", mountpoint, err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,47 @@
+//! Lists the root directory of an ext2 image.
+//!
+//! ```text
+//! cargo run --example ls --features std -- ext2.img
+//! ```
+
+extern crate ext2;
+
+use std::env;
+use std::process;
+
+use ext2::fs::sync::Synced;
+use ext2::fs::Ext2;
+use ext2::sector::Size512;
+use ext2::volume::FileVolume;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: ls <image>");
+            process::exit(2);
+        }
+    };
+
+    let volume = FileVolume::open(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+
+    let root = fs.root_inode();
+    for entry in root.directory().expect("root is not a directory") {
+        match entry {
+            Ok(entry) => {
+                println!("{:>8} {}", entry.ino, entry.name_lossy())
+            }
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+#ifndef EXT2_H
+#define EXT2_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ An open file or directory. It stays usable after `ext2_umount`, until
+ it's closed.
+ */
+struct ext2_file;
+
+/*
+ A mounted file system
+ */
+struct ext2_fs;
+
+/*
+ Reads `len` bytes at byte `offset` of the volume into `buf`, returning
+ 0, or a negative errno value on failure
+ */
+typedef int (*ext2_read_fn)(void *ctx,
+                            uint64_t offset,
+                            uint8_t *buf,
+                            size_t len);
+
+/*
+ Writes the `len` bytes of `buf` at byte `offset` of the volume,
+ returning 0, or a negative errno value on failure
+ */
+typedef int (*ext2_write_fn)(void *ctx,
+                             uint64_t offset,
+                             const uint8_t *buf,
+                             size_t len);
+
+/*
+ An entry of a directory, as read by `ext2_readdir`
+ */
+struct ext2_dirent {
+  uint32_t ino;
+  /*
+   The `EXT2_FT_*` type of the entry, 0 if the file system doesn't
+   record it
+   */
+  uint8_t file_type;
+  uint8_t name_len;
+  /*
+   The name, NUL-terminated
+   */
+  char name[256];
+};
+
+/*
+ Information about a file, as read by `ext2_stat`
+ */
+struct ext2_stat {
+  uint64_t size;
+  uint32_t ino;
+  /*
+   The space the file takes up, in 512-byte sectors
+   */
+  uint32_t blocks;
+  uint32_t atime;
+  uint32_t mtime;
+  uint32_t ctime;
+  /*
+   The device number of a device, 0 for other files
+   */
+  uint32_t dev_major;
+  uint32_t dev_minor;
+  /*
+   The file type and permission bits, as `st_mode`
+   */
+  uint16_t mode;
+  uint16_t nlink;
+  uint16_t uid;
+  uint16_t gid;
+};
+
+/*
+ Mounts the file system on the volume accessed by `read` and `write`,
+ which are passed `ctx`, as `Ext2::mount` does. Without `write` it's
+ mounted read-only. Returns null if it can't be mounted.
+
+ # Safety
+
+ `read` and `write` have to be safe to call with `ctx` for as long as the
+ file system or any of its files are around.
+ */
+struct ext2_fs *ext2_mount(ext2_read_fn read,
+                           ext2_write_fn write,
+                           void *ctx);
+
+/*
+ Unmounts `fs` as `Ext2::unmount` does, freeing it even if that fails
+
+ # Safety
+
+ `fs` has to come from `ext2_mount`, and isn't to be used afterwards.
+ */
+int ext2_umount(struct ext2_fs *fs);
+
+/*
+ Opens the file or directory at the absolute `path`, following symlinks,
+ and stores it in `*file`
+
+ # Safety
+
+ `fs` has to come from `ext2_mount`, `path` has to be NUL-terminated and
+ `file` has to be valid for writes.
+ */
+int ext2_open(const struct ext2_fs *fs,
+              const char *path,
+              struct ext2_file **file);
+
+/*
+ Reads up to `len` bytes from the position of `file` into `buf`, moving
+ the position past them. Returns how many bytes were read, 0 at the end
+ of the file, or a negative errno value.
+
+ # Safety
+
+ `file` has to come from `ext2_open`, and `buf` has to be valid for
+ writes of `len` bytes.
+ */
+ptrdiff_t ext2_read(struct ext2_file *file,
+                    uint8_t *buf,
+                    size_t len);
+
+/*
+ Reads the next entry of the directory `dir` into `*entry`. Returns 1 if
+ there was one, 0 past the last one, or a negative errno value.
+
+ # Safety
+
+ `dir` has to come from `ext2_open`, and `entry` has to be valid for
+ writes.
+ */
+int ext2_readdir(struct ext2_file *dir,
+                 struct ext2_dirent *entry);
+
+/*
+ Reads the information about the file at the absolute `path`, following
+ symlinks, into `*stat`
+
+ # Safety
+
+ `fs` has to come from `ext2_mount`, `path` has to be NUL-terminated and
+ `stat` has to be valid for writes.
+ */
+int ext2_stat(const struct ext2_fs *fs,
+              const char *path,
+              struct ext2_stat *stat);
+
+/*
+ Closes `file`
+
+ # Safety
+
+ `file` has to come from `ext2_open`, and isn't to be used afterwards.
+ */
+void ext2_close(struct ext2_file *file);
+
+#endif  /* EXT2_H */
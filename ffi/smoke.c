@@ -0,0 +1,80 @@
+/*
+ * Mounts an image through the C interface, then reads a file and lists a
+ * directory of it. Built against the library with:
+ *
+ *     cargo rustc --release --features "ffi std" --crate-type staticlib
+ *     cc -Wall -Werror -I ffi ffi/smoke.c target/release/libext2.a \
+ *         -lpthread -ldl -lm -o target/smoke
+ *     target/smoke ext2.img
+ */
+
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "ext2.h"
+
+static int read_image(void *ctx, uint64_t offset, uint8_t *buf, size_t len)
+{
+	FILE *image = ctx;
+
+	if (fseek(image, (long)offset, SEEK_SET) != 0)
+		return -errno;
+	if (fread(buf, 1, len, image) != len)
+		return -EIO;
+	return 0;
+}
+
+#define CHECK(cond)                                                     \
+	do {                                                            \
+		if (!(cond)) {                                          \
+			fprintf(stderr, "%s:%d: %s\n", __FILE__,        \
+				__LINE__, #cond);                       \
+			return 1;                                       \
+		}                                                       \
+	} while (0)
+
+int main(int argc, char **argv)
+{
+	struct ext2_fs *fs;
+	struct ext2_file *file;
+	struct ext2_stat stat;
+	struct ext2_dirent entry;
+	char buf[64];
+	FILE *image;
+	ptrdiff_t read;
+	int found = 0;
+	int ret;
+
+	CHECK(argc == 2);
+	image = fopen(argv[1], "rb");
+	CHECK(image != NULL);
+	fs = ext2_mount(read_image, NULL, image);
+	CHECK(fs != NULL);
+
+	CHECK(ext2_stat(fs, "/home/funky/README.md", &stat) == 0);
+	CHECK(stat.ino == 14 && stat.size == 31);
+	CHECK((stat.mode & 0170000) == 0100000);
+
+	CHECK(ext2_open(fs, "/home/funky/README.md", &file) == 0);
+	read = ext2_read(file, (uint8_t *)buf, sizeof(buf));
+	CHECK(read == 31);
+	CHECK(ext2_read(file, (uint8_t *)buf, sizeof(buf)) == 0);
+	ext2_close(file);
+	fwrite(buf, 1, read, stdout);
+
+	CHECK(ext2_open(fs, "/home/funky", &file) == 0);
+	while ((ret = ext2_readdir(file, &entry)) == 1) {
+		CHECK(strlen(entry.name) == entry.name_len);
+		printf("%8u %s\n", entry.ino, entry.name);
+		if (strcmp(entry.name, "README.md") == 0)
+			found = entry.ino == 14;
+	}
+	CHECK(ret == 0 && found);
+	ext2_close(file);
+
+	CHECK(ext2_open(fs, "/home/funky/missing", &file) == -ENOENT);
+	CHECK(ext2_umount(fs) == 0);
+	fclose(image);
+	return 0;
+}
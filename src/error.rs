@@ -1,11 +1,19 @@
-use core::fmt::{self, Display};
+use core::any::Any;
+use core::fmt::{self, Debug, Display};
+use alloc::boxed::Box;
 use alloc::string::String;
 
-#[cfg(any(test, not(feature = "no_std")))]
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
 use std::io;
 
-/// The set of all possible errors
+/// The result of the crate's fallible operations. The error type defaults
+/// to this crate's, but can be given for volumes with their own.
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+/// The set of all possible errors. More may be added without a major
+/// version bump, so matches on it need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Other(String),
     BadMagic {
@@ -15,7 +23,7 @@ pub enum Error {
         index: usize,
     },
     AddressOutOfBounds {
-        sector: u32,
+        sector: u64,
         offset: u32,
         size: usize,
     },
@@ -23,6 +31,24 @@ pub enum Error {
         by_blocks: u32,
         by_inodes: u32,
     },
+    BadLogBlockSize {
+        log_block_size: u32,
+    },
+    BadGroupSize {
+        blocks_per_group: u32,
+        inodes_per_group: u32,
+    },
+    BadFreeInodesCount {
+        inodes_count: u32,
+        free_inodes_count: u32,
+    },
+    BadFirstDataBlock {
+        first_data_block: u32,
+        blocks_count: u32,
+    },
+    BadInodeSize {
+        size: usize,
+    },
     InodeNotFound {
         inode: u32,
     },
@@ -30,18 +56,243 @@ pub enum Error {
         inode: u32,
         name: String,
     },
+    IsADirectory {
+        inode: u32,
+        name: String,
+    },
+    NotASymlink {
+        inode: u32,
+    },
+    InvalidUtf8 {
+        inode: u32,
+    },
     NotAbsolute {
         name: String,
     },
+    /// `name` is the path up to the missing entry, or the entry's name
+    /// alone; `parent` is the directory it's missing from
     NotFound {
         name: String,
+        parent: u32,
+    },
+    TooManySymlinks {
+        name: String,
+    },
+    /// Resolving `name` would have led out of the directory it was to be
+    /// resolved beneath
+    EscapesBeneath {
+        name: String,
     },
-    #[cfg(any(test, not(feature = "no_std")))]
+    /// The directory at `name` is inode no. `inode`, which is one of the
+    /// directories it was reached through
+    DirectoryCycle {
+        inode: u32,
+        name: String,
+    },
+    OutOfSpace,
+    InvalidBlockSize {
+        size: usize,
+    },
+    VolumeTooSmall {
+        blocks: u32,
+    },
+    ShrinkUnsupported {
+        blocks: u32,
+        new_blocks: u32,
+    },
+    DescriptorTableFull {
+        groups: u32,
+    },
+    ReadOnlyFilesystem,
+    /// Inode no. `inode` has the `IMMUTABLE` or `APPEND_ONLY` flag, which
+    /// rules out the change
+    OperationNotPermitted {
+        inode: u32,
+    },
+    AlreadyExists {
+        name: String,
+    },
+    NameTooLong {
+        name: String,
+    },
+    InvalidName {
+        name: String,
+    },
+    /// A device number too big to be stored, with a `major` of more than 12
+    /// bits or a `minor` of more than 20
+    InvalidDevice {
+        major: u32,
+        minor: u32,
+    },
+    /// `name` is a FIFO, a socket or a device, with no contents to read or
+    /// write as those of a file
+    SpecialFile {
+        inode: u32,
+        name: String,
+    },
+    DirectoryNotEmpty {
+        name: String,
+    },
+    InvalidRename {
+        from: String,
+        to: String,
+    },
+    InvalidPartitionTable {
+        reason: &'static str,
+    },
+    UnsupportedFeature {
+        incompat: u32,
+    },
+    FilesystemHasErrors {
+        policy: u16,
+    },
+    /// Block `block`, which an inode points at for its extended
+    /// attributes, doesn't start with the magic number
+    BadXattrMagic {
+        block: u32,
+        magic: u32,
+    },
+    Corrupt {
+        reason: &'static str,
+    },
+    /// The deleted inode no. `inode` can't be recovered: block `block` of
+    /// it, or the inode itself if `None`, has been reused since
+    Overwritten {
+        inode: u32,
+        block: Option<u32>,
+    },
+    ArithmeticOverflow,
+    /// Seeking `delta` bytes from `base` would have gone before the start
+    /// of the file
+    InvalidSeek {
+        base: u64,
+        delta: i64,
+    },
+    /// A read needing more of the file found its end at byte `offset`
+    UnexpectedEof {
+        offset: u64,
+    },
+    Unsupported {
+        operation: &'static str,
+    },
+    /// An error of the storage under a volume, see `Error::volume`
+    Volume {
+        inner: Box<dyn VolumeError>,
+    },
+    /// `source` was hit while resolving the path component at byte
+    /// `offset` of a path, reading inode `inode`
+    WithComponent {
+        offset: usize,
+        inode: u32,
+        source: Box<Error>,
+    },
+    #[cfg(any(test, feature = "std", not(feature = "no_std")))]
     Io {
         inner: io::Error,
     },
 }
 
+/// An error of the storage under a volume, such as a block device driver's,
+/// kept whole in `Error::Volume` so that callers can get it back. Every
+/// `Debug + Display` type that can be shared between threads is one.
+pub trait VolumeError: Any + Debug + Display + Send + Sync {
+    #[doc(hidden)]
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<E: Any + Debug + Display + Send + Sync> VolumeError for E {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Error {
+    /// Wraps `err`, an error of the storage under a volume. A volume with an
+    /// error type of its own implements `Into<Error>` for it with this.
+    pub fn volume<E: VolumeError>(err: E) -> Error {
+        Error::Volume {
+            inner: Box::new(err),
+        }
+    }
+
+    /// Returns the error of type `E` wrapped by `Error::volume` behind this
+    /// error, if it's there, looking through the context added on the way
+    pub fn volume_error<E: VolumeError>(&self) -> Option<&E> {
+        match *self {
+            Error::Volume {
+                ref inner,
+            } => (**inner).as_any().downcast_ref(),
+            Error::WithComponent {
+                ref source,
+                ..
+            } => source.volume_error(),
+            _ => None,
+        }
+    }
+
+    /// The Linux errno value closest to this error, for exposing the
+    /// filesystem through a POSIX-like interface. Volume I/O errors keep
+    /// their OS error code if they have one.
+    pub fn errno(&self) -> i32 {
+        match *self {
+            Error::Other(_) => errno::EIO,
+            Error::BadMagic { .. } => errno::EINVAL,
+            Error::OutOfBounds { .. } => errno::EINVAL,
+            Error::AddressOutOfBounds { .. } => errno::EIO,
+            // a superblock that doesn't add up can't be mounted
+            Error::BadBlockGroupCount { .. }
+            | Error::BadLogBlockSize { .. }
+            | Error::BadGroupSize { .. }
+            | Error::BadFreeInodesCount { .. }
+            | Error::BadFirstDataBlock { .. }
+            | Error::BadInodeSize { .. } => errno::EINVAL,
+            Error::InodeNotFound { .. } => errno::ENOENT,
+            Error::NotADirectory { .. } => errno::ENOTDIR,
+            Error::IsADirectory { .. } => errno::EISDIR,
+            Error::NotASymlink { .. } => errno::EINVAL,
+            Error::InvalidUtf8 { .. } => errno::EILSEQ,
+            Error::NotAbsolute { .. } => errno::EINVAL,
+            Error::NotFound { .. } => errno::ENOENT,
+            Error::TooManySymlinks { .. } => errno::ELOOP,
+            Error::EscapesBeneath { .. } => errno::EXDEV,
+            Error::DirectoryCycle { .. } => errno::ELOOP,
+            Error::OutOfSpace => errno::ENOSPC,
+            Error::InvalidBlockSize { .. } => errno::EINVAL,
+            Error::VolumeTooSmall { .. } => errno::EINVAL,
+            Error::ShrinkUnsupported { .. } => errno::EOPNOTSUPP,
+            Error::DescriptorTableFull { .. } => errno::ENOSPC,
+            Error::ReadOnlyFilesystem => errno::EROFS,
+            Error::OperationNotPermitted { .. } => errno::EPERM,
+            Error::AlreadyExists { .. } => errno::EEXIST,
+            Error::NameTooLong { .. } => errno::ENAMETOOLONG,
+            Error::InvalidName { .. } => errno::EINVAL,
+            Error::InvalidDevice { .. } => errno::EINVAL,
+            Error::SpecialFile { .. } => errno::ENXIO,
+            Error::DirectoryNotEmpty { .. } => errno::ENOTEMPTY,
+            Error::InvalidRename { .. } => errno::EINVAL,
+            Error::InvalidPartitionTable { .. } => errno::EINVAL,
+            Error::UnsupportedFeature { .. } => errno::EINVAL,
+            Error::FilesystemHasErrors { .. } => errno::EIO,
+            Error::BadXattrMagic { .. } => errno::EIO,
+            Error::Corrupt { .. } => errno::EIO,
+            Error::Overwritten { .. } => errno::EIO,
+            Error::ArithmeticOverflow => errno::EOVERFLOW,
+            Error::InvalidSeek { .. } => errno::EINVAL,
+            Error::UnexpectedEof { .. } => errno::EIO,
+            Error::Unsupported { .. } => errno::EOPNOTSUPP,
+            Error::Volume { .. } => errno::EIO,
+            Error::WithComponent {
+                ref source,
+                ..
+            } => source.errno(),
+            #[cfg(any(test, feature = "std", not(feature = "no_std")))]
+            Error::Io {
+                ref inner,
+            } => inner.raw_os_error().unwrap_or(errno::EIO),
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -51,17 +302,47 @@ impl Display for Error {
             } => write!(f, "invalid magic value: {}", magic),
             Error::OutOfBounds {
                 index,
-            } => write!(f, "index ouf of bounds: {}", index),
+            } => write!(f, "index out of bounds: {}", index),
             Error::AddressOutOfBounds {
                 sector,
                 offset,
                 size,
-            } => write!(f, "address ouf of bounds: {}:{} with a block size of: {}",
+            } => write!(f, "address out of bounds: {}:{} with a sector size of: {}",
                    sector, offset, size),
             Error::BadBlockGroupCount {
                 by_blocks,
                 by_inodes,
             } => write!(f, "conflicting block group count data; by blocks: {}, by inodes: {}", by_blocks, by_inodes),
+            Error::BadLogBlockSize {
+                log_block_size,
+            } => write!(f, "invalid block size: 1024 << {}", log_block_size),
+            Error::BadGroupSize {
+                blocks_per_group,
+                inodes_per_group,
+            } => write!(
+                f,
+                "invalid group size: {} blocks, {} inodes",
+                blocks_per_group, inodes_per_group
+            ),
+            Error::BadFreeInodesCount {
+                inodes_count,
+                free_inodes_count,
+            } => write!(
+                f,
+                "more free inodes than inodes: {} of {}",
+                free_inodes_count, inodes_count
+            ),
+            Error::BadFirstDataBlock {
+                first_data_block,
+                blocks_count,
+            } => write!(
+                f,
+                "invalid first data block: {} of {}",
+                first_data_block, blocks_count
+            ),
+            Error::BadInodeSize {
+                size,
+            } => write!(f, "invalid inode size: {}", size),
             Error::InodeNotFound {
                 inode,
             } => write!(f, "couldn't find inode no. {}", &inode),
@@ -69,13 +350,131 @@ impl Display for Error {
                 inode,
                 ref name,
             } => write!(f, "inode no. {} at: {} is not a directory", inode, &name),
+            Error::IsADirectory {
+                inode,
+                ref name,
+            } => write!(f, "inode no. {} at: {} is a directory", inode, name),
+            Error::NotASymlink {
+                inode,
+            } => write!(f, "inode no. {} is not a symbolic link", inode),
+            Error::InvalidUtf8 {
+                inode,
+            } => write!(f, "inode no. {} doesn't hold valid UTF-8", inode),
             Error::NotAbsolute {
                 ref name,
             } => write!(f, "{} is not an absolute path", &name),
             Error::NotFound {
                 ref name,
-            } => write!(f, "couldn't find {}", &name),
-            #[cfg(any(test, not(feature = "no_std")))]
+                parent,
+            } => write!(f, "couldn't find {} in inode no. {}", name, parent),
+            Error::TooManySymlinks {
+                ref name,
+            } => write!(f, "too many levels of symbolic links in {}", name),
+            Error::EscapesBeneath {
+                ref name,
+            } => write!(f, "{} leads out of the directory it's resolved beneath", name),
+            Error::DirectoryCycle {
+                inode,
+                ref name,
+            } => write!(f, "{} leads back to its ancestor inode no. {}", name, inode),
+            Error::OutOfSpace => write!(f, "no space left on volume"),
+            Error::InvalidBlockSize {
+                size,
+            } => write!(f, "invalid block size: {}", size),
+            Error::VolumeTooSmall {
+                blocks,
+            } => write!(f, "volume too small for a filesystem: {} blocks", blocks),
+            Error::ShrinkUnsupported {
+                blocks,
+                new_blocks,
+            } => write!(
+                f,
+                "can't shrink the filesystem from {} to {} blocks",
+                blocks, new_blocks
+            ),
+            Error::DescriptorTableFull {
+                groups,
+            } => write!(f, "no room in the descriptor table for {} groups", groups),
+            Error::ReadOnlyFilesystem => write!(f, "read-only filesystem"),
+            Error::OperationNotPermitted {
+                inode,
+            } => write!(f, "operation not permitted on inode no. {}", inode),
+            Error::AlreadyExists {
+                ref name,
+            } => write!(f, "{} already exists", name),
+            Error::NameTooLong {
+                ref name,
+            } => write!(f, "file name too long: {}", name),
+            Error::InvalidName {
+                ref name,
+            } => write!(f, "invalid file name: {}", name),
+            Error::InvalidDevice {
+                major,
+                minor,
+            } => write!(f, "device number {}:{} too big", major, minor),
+            Error::SpecialFile {
+                inode,
+                ref name,
+            } => write!(f, "inode no. {} at: {} is a special file", inode, name),
+            Error::DirectoryNotEmpty {
+                ref name,
+            } => write!(f, "directory {} is not empty", name),
+            Error::InvalidRename {
+                ref from,
+                ref to,
+            } => write!(f, "cannot move {} into itself at: {}", from, to),
+            Error::InvalidPartitionTable {
+                reason,
+            } => write!(f, "invalid partition table: {}", reason),
+            Error::UnsupportedFeature {
+                incompat,
+            } => write!(f, "unsupported required features: {:#x}", incompat),
+            Error::FilesystemHasErrors {
+                policy,
+            } => write!(
+                f,
+                "filesystem has errors and error policy {} refuses mounting it",
+                policy
+            ),
+            Error::BadXattrMagic {
+                block,
+                magic,
+            } => write!(f, "bad extended attribute block magic in block {}: {:#x}", block, magic),
+            Error::Corrupt {
+                reason,
+            } => write!(f, "corrupt filesystem: {}", reason),
+            Error::Overwritten {
+                inode,
+                block: Some(block),
+            } => write!(f, "block {} of deleted inode no. {} was reused", block, inode),
+            Error::Overwritten {
+                inode,
+                block: None,
+            } => write!(f, "deleted inode no. {} was reused", inode),
+            Error::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            Error::InvalidSeek {
+                base,
+                delta,
+            } => write!(f, "invalid seek by {} from byte {}", delta, base),
+            Error::UnexpectedEof {
+                offset,
+            } => write!(f, "unexpected end of file at byte {}", offset),
+            Error::Unsupported {
+                operation,
+            } => write!(f, "unsupported operation: {}", operation),
+            Error::Volume {
+                ref inner,
+            } => write!(f, "volume error: {}", inner),
+            Error::WithComponent {
+                offset,
+                inode,
+                ref source,
+            } => write!(
+                f,
+                "{} in path component at byte {}, inode no. {}",
+                source, offset, inode
+            ),
+            #[cfg(any(test, feature = "std", not(feature = "no_std")))]
             Error::Io {
                 ref inner,
             } => write!(f, "io error: {}", inner),
@@ -89,11 +488,331 @@ impl From<Infallible> for Error {
     }
 }
 
-#[cfg(any(test, not(feature = "no_std")))]
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::Io {
+                ref inner,
+            } => Some(inner),
+            Error::WithComponent {
+                ref source,
+                ..
+            } => Some(&**source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
 impl From<io::Error> for Error {
     fn from(inner: io::Error) -> Error {
         Error::Io { inner }
     }
 }
 
+/// Gives back the `io::Error` an `Error::Io` wraps, and wraps any other
+/// error with the kind closest to `Error::errno`
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Io { inner } => inner,
+            err => io::Error::new(err.io_kind(), err),
+        }
+    }
+}
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl Error {
+    fn io_kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::Corrupt { .. }
+            | Error::BadXattrMagic { .. }
+            | Error::Overwritten { .. } => io::ErrorKind::InvalidData,
+            Error::UnexpectedEof { .. } => io::ErrorKind::UnexpectedEof,
+            Error::Io {
+                ref inner,
+            } => inner.kind(),
+            Error::WithComponent {
+                ref source,
+                ..
+            } => source.io_kind(),
+            _ => match self.errno() {
+                errno::ENOENT => io::ErrorKind::NotFound,
+                errno::EPERM => io::ErrorKind::PermissionDenied,
+                errno::EROFS => io::ErrorKind::ReadOnlyFilesystem,
+                errno::EEXIST => io::ErrorKind::AlreadyExists,
+                errno::ENOTDIR => io::ErrorKind::NotADirectory,
+                errno::EISDIR => io::ErrorKind::IsADirectory,
+                errno::ENOTEMPTY => io::ErrorKind::DirectoryNotEmpty,
+                errno::ENOSPC => io::ErrorKind::StorageFull,
+                errno::ENAMETOOLONG => io::ErrorKind::InvalidFilename,
+                errno::EXDEV => io::ErrorKind::CrossesDevices,
+                errno::EOPNOTSUPP => io::ErrorKind::Unsupported,
+                errno::EINVAL | errno::EILSEQ | errno::EOVERFLOW => {
+                    io::ErrorKind::InvalidInput
+                }
+                _ => io::ErrorKind::Other,
+            },
+        }
+    }
+}
+
+#[cfg(any(test, feature = "embedded-io"))]
+impl embedded_io::Error for Error {
+    /// The kind closest to `Error::errno`, except for metadata found not to
+    /// add up, which is `InvalidData`
+    fn kind(&self) -> embedded_io::ErrorKind {
+        use embedded_io::ErrorKind;
+
+        match *self {
+            Error::Corrupt { .. }
+            | Error::BadXattrMagic { .. }
+            | Error::Overwritten { .. } => ErrorKind::InvalidData,
+            Error::WithComponent {
+                ref source,
+                ..
+            } => source.kind(),
+            _ => match self.errno() {
+                errno::ENOENT => ErrorKind::NotFound,
+                errno::EPERM | errno::EROFS => ErrorKind::PermissionDenied,
+                errno::EEXIST => ErrorKind::AlreadyExists,
+                errno::EINVAL
+                | errno::ENOTDIR
+                | errno::EISDIR
+                | errno::ENOTEMPTY
+                | errno::ENAMETOOLONG
+                | errno::EXDEV
+                | errno::ELOOP
+                | errno::EILSEQ
+                | errno::EOVERFLOW => ErrorKind::InvalidInput,
+                errno::EOPNOTSUPP => ErrorKind::Unsupported,
+                _ => ErrorKind::Other,
+            },
+        }
+    }
+}
+
 pub enum Infallible {}
+
+/// The Linux errno values `Error::errno` maps errors to
+pub mod errno {
+    pub const EPERM: i32 = 1;
+    pub const ENOENT: i32 = 2;
+    pub const EIO: i32 = 5;
+    pub const ENXIO: i32 = 6;
+    pub const EEXIST: i32 = 17;
+    pub const EXDEV: i32 = 18;
+    pub const ENOTDIR: i32 = 20;
+    pub const EISDIR: i32 = 21;
+    pub const EINVAL: i32 = 22;
+    pub const ENOSPC: i32 = 28;
+    pub const EROFS: i32 = 30;
+    pub const ENAMETOOLONG: i32 = 36;
+    pub const ENOTEMPTY: i32 = 39;
+    pub const ELOOP: i32 = 40;
+    pub const EOVERFLOW: i32 = 75;
+    pub const EILSEQ: i32 = 84;
+    pub const EOPNOTSUPP: i32 = 95;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as StdError;
+    use std::io;
+
+    use super::errno::*;
+    use super::Error;
+
+    #[test]
+    fn display_and_source() {
+        let err = Error::AddressOutOfBounds {
+            sector: 8,
+            offset: 16,
+            size: 512,
+        };
+        assert_eq!(
+            err.to_string(),
+            "address out of bounds: 8:16 with a sector size of: 512"
+        );
+        assert!(err.source().is_none());
+
+        let err = Error::from(io::Error::new(io::ErrorKind::UnexpectedEof, "disk"));
+        assert_eq!(err.to_string(), "io error: disk");
+        assert_eq!(err.source().unwrap().to_string(), "disk");
+    }
+
+    #[test]
+    fn embedded_io_kind() {
+        use embedded_io::{Error as _, ErrorKind};
+
+        let not_found = Error::NotFound {
+            name: String::from("/a"),
+            parent: 2,
+        };
+        assert_eq!(not_found.kind(), ErrorKind::NotFound);
+        let within = Error::WithComponent {
+            offset: 0,
+            inode: 2,
+            source: Box::new(not_found),
+        };
+        assert_eq!(within.kind(), ErrorKind::NotFound);
+        let kinds = [
+            (Error::ReadOnlyFilesystem, ErrorKind::PermissionDenied),
+            (Error::Corrupt { reason: "" }, ErrorKind::InvalidData),
+            (
+                Error::InvalidSeek { base: 0, delta: -1 },
+                ErrorKind::InvalidInput,
+            ),
+            (
+                Error::Unsupported { operation: "" },
+                ErrorKind::Unsupported,
+            ),
+            (Error::OutOfSpace, ErrorKind::Other),
+        ];
+        for (err, kind) in kinds.iter() {
+            assert_eq!(err.kind(), *kind, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn into_io() {
+        let kinds = [
+            (
+                Error::NotFound {
+                    name: String::from("/a"),
+                    parent: 2,
+                },
+                io::ErrorKind::NotFound,
+            ),
+            (Error::ReadOnlyFilesystem, io::ErrorKind::ReadOnlyFilesystem),
+            (Error::OutOfSpace, io::ErrorKind::StorageFull),
+            (Error::Corrupt { reason: "" }, io::ErrorKind::InvalidData),
+            (
+                Error::UnexpectedEof { offset: 10 },
+                io::ErrorKind::UnexpectedEof,
+            ),
+        ];
+        for (err, kind) in kinds {
+            let message = err.to_string();
+            let err = io::Error::from(err);
+            assert_eq!(err.kind(), kind);
+            assert_eq!(err.to_string(), message);
+            assert!(err.get_ref().unwrap().is::<Error>());
+        }
+
+        // the kind of the error behind the path component
+        let err = io::Error::from(Error::WithComponent {
+            offset: 0,
+            inode: 2,
+            source: Box::new(Error::IsADirectory {
+                inode: 12,
+                name: String::from("/a"),
+            }),
+        });
+        assert_eq!(err.kind(), io::ErrorKind::IsADirectory);
+
+        // an I/O error comes back out as it went in
+        let inner = io::Error::from_raw_os_error(EIO);
+        let err = io::Error::from(Error::from(inner));
+        assert_eq!(err.raw_os_error(), Some(EIO));
+    }
+
+    #[test]
+    fn errno() {
+        let name = String::from("/a");
+        let table = [
+            (
+                Error::NotFound {
+                    name: name.clone(),
+                    parent: 2,
+                },
+                ENOENT,
+            ),
+            (
+                Error::NotADirectory {
+                    inode: 12,
+                    name: name.clone(),
+                },
+                ENOTDIR,
+            ),
+            (
+                Error::IsADirectory {
+                    inode: 12,
+                    name: name.clone(),
+                },
+                EISDIR,
+            ),
+            (Error::OutOfSpace, ENOSPC),
+            (Error::ReadOnlyFilesystem, EROFS),
+            (Error::OperationNotPermitted { inode: 12 }, EPERM),
+            (Error::NameTooLong { name: name.clone() }, ENAMETOOLONG),
+            (Error::AlreadyExists { name: name.clone() }, EEXIST),
+            (Error::DirectoryNotEmpty { name: name.clone() }, ENOTEMPTY),
+            (Error::EscapesBeneath { name: name.clone() }, EXDEV),
+            (
+                Error::DirectoryCycle {
+                    inode: 12,
+                    name: name.clone(),
+                },
+                ELOOP,
+            ),
+            (
+                Error::SpecialFile {
+                    inode: 12,
+                    name: name.clone(),
+                },
+                ENXIO,
+            ),
+            (Error::TooManySymlinks { name }, ELOOP),
+            (
+                Error::AddressOutOfBounds {
+                    sector: 0,
+                    offset: 0,
+                    size: 512,
+                },
+                EIO,
+            ),
+            (Error::Corrupt { reason: "" }, EIO),
+            (
+                Error::Overwritten {
+                    inode: 14,
+                    block: Some(162),
+                },
+                EIO,
+            ),
+            (
+                Error::BadXattrMagic {
+                    block: 29,
+                    magic: 0,
+                },
+                EIO,
+            ),
+            (Error::BadMagic { magic: 0 }, EINVAL),
+            (Error::InvalidUtf8 { inode: 12 }, EILSEQ),
+            (Error::ArithmeticOverflow, EOVERFLOW),
+            (
+                Error::InvalidDevice {
+                    major: 4096,
+                    minor: 0,
+                },
+                EINVAL,
+            ),
+            (
+                Error::WithComponent {
+                    offset: 1,
+                    inode: 2,
+                    source: Box::new(Error::OutOfSpace),
+                },
+                ENOSPC,
+            ),
+            (Error::Unsupported { operation: "" }, EOPNOTSUPP),
+            (io::Error::from_raw_os_error(13).into(), 13),
+            (io::Error::new(io::ErrorKind::UnexpectedEof, "").into(), EIO),
+        ];
+        for (err, errno) in table.iter() {
+            assert_eq!(err.errno(), *errno, "{}", err);
+        }
+    }
+}
@@ -0,0 +1,563 @@
+//! A C interface, for kernels written in C. The volume is read and written
+//! through functions the caller provides, and files and file systems are
+//! handed out as opaque pointers.
+//!
+//! Functions returning `int` return 0 on success and a negative errno
+//! value on failure, as given by `Error::errno`, or as returned by the
+//! volume functions for their own failures. `ffi/ext2.h` declares all of
+//! it, and is generated by `cbindgen --config ffi/cbindgen.toml`.
+
+#![allow(non_camel_case_types)]
+
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::{self, Display};
+use core::ops::Range;
+use core::ptr;
+use core::slice;
+
+use alloc::boxed::Box;
+
+use genfs::File;
+
+use error::{errno, Error, Result};
+use fs::sync::{Inode, Synced};
+use fs::{Ext2, MountOptions};
+use sector::{Address, Size512};
+use sys::inode::TypePerm;
+use volume::size::Size;
+use volume::{Volume, VolumeCommit, VolumeSlice};
+
+/// Reads `len` bytes at byte `offset` of the volume into `buf`, returning
+/// 0, or a negative errno value on failure
+pub type ext2_read_fn = Option<
+    unsafe extern "C" fn(
+        ctx: *mut c_void,
+        offset: u64,
+        buf: *mut u8,
+        len: usize,
+    ) -> c_int,
+>;
+
+/// Writes the `len` bytes of `buf` at byte `offset` of the volume,
+/// returning 0, or a negative errno value on failure
+pub type ext2_write_fn = Option<
+    unsafe extern "C" fn(
+        ctx: *mut c_void,
+        offset: u64,
+        buf: *const u8,
+        len: usize,
+    ) -> c_int,
+>;
+
+/// A volume accessed through the functions given to `ext2_mount`. Its size
+/// isn't known, so accesses past its end fail however `read` and `write`
+/// fail them.
+pub struct CallbackVolume {
+    read: unsafe extern "C" fn(*mut c_void, u64, *mut u8, usize) -> c_int,
+    write: ext2_write_fn,
+    ctx: *mut c_void,
+}
+
+/// The negative errno value a volume function failed with, see
+/// `Error::volume_error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallbackError {
+    pub errno: c_int,
+}
+
+impl Display for CallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "volume function failed with errno {}", -self.errno)
+    }
+}
+
+/// Turns what a volume function returned into a `Result`
+fn check(ret: c_int) -> Result<()> {
+    match ret {
+        0 => Ok(()),
+        errno => Err(Error::volume(CallbackError { errno })),
+    }
+}
+
+impl Volume<u8, Size512> for CallbackVolume {
+    type Error = Error;
+
+    fn size(&self) -> Size<Size512> {
+        Size::Unbounded
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, Size512>>,
+    ) -> Result<()> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let write = self.write.ok_or(Error::ReadOnlyFilesystem)?;
+        let offset = slice.address().into_index();
+        check(unsafe { write(self.ctx, offset, slice.as_ptr(), slice.len()) })
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<Size512>>,
+    ) -> VolumeSlice<'_, u8, Size512> {
+        self.slice(range).unwrap_or_else(|err| {
+            panic!("couldn't read from the volume: {}", err)
+        })
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<Size512>>,
+    ) -> Result<VolumeSlice<'_, u8, Size512>> {
+        let len = range
+            .end
+            .checked_sub(range.start)
+            .ok_or(Error::OutOfBounds {
+                index: range.end.into_index() as usize,
+            })?
+            .into_index() as usize;
+        let mut buf = vec![0; len];
+        let offset = range.start.into_index();
+        check(unsafe { (self.read)(self.ctx, offset, buf.as_mut_ptr(), len) })?;
+        Ok(VolumeSlice::new_owned(buf, range.start))
+    }
+}
+
+/// A mounted file system
+pub struct ext2_fs {
+    fs: Synced<Ext2<Size512, CallbackVolume>>,
+}
+
+/// An open file or directory. It stays usable after `ext2_umount`, until
+/// it's closed.
+pub struct ext2_file {
+    inode: Inode<Size512, CallbackVolume>,
+    /// Where `ext2_readdir` carries on from
+    dir_pos: u64,
+}
+
+/// An entry of a directory, as read by `ext2_readdir`
+#[repr(C)]
+pub struct ext2_dirent {
+    pub ino: u32,
+    /// The `EXT2_FT_*` type of the entry, 0 if the file system doesn't
+    /// record it
+    pub file_type: u8,
+    pub name_len: u8,
+    /// The name, NUL-terminated
+    pub name: [c_char; 256],
+}
+
+/// Information about a file, as read by `ext2_stat`
+#[repr(C)]
+pub struct ext2_stat {
+    pub size: u64,
+    pub ino: u32,
+    /// The space the file takes up, in 512-byte sectors
+    pub blocks: u32,
+    pub atime: u32,
+    pub mtime: u32,
+    pub ctime: u32,
+    /// The device number of a device, 0 for other files
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    /// The file type and permission bits, as `st_mode`
+    pub mode: u16,
+    pub nlink: u16,
+    pub uid: u16,
+    pub gid: u16,
+}
+
+/// The negative errno value for `err`
+fn neg_errno(err: &Error) -> c_int {
+    match err.volume_error::<CallbackError>() {
+        Some(err) => err.errno,
+        None => -err.errno(),
+    }
+}
+
+/// The path `path` points at, if it isn't null
+unsafe fn path<'a>(path: *const c_char) -> Option<&'a [u8]> {
+    if path.is_null() {
+        return None;
+    }
+    Some(CStr::from_ptr(path).to_bytes())
+}
+
+/// Mounts the file system on the volume accessed by `read` and `write`,
+/// which are passed `ctx`, as `Ext2::mount` does. Without `write` it's
+/// mounted read-only. Returns null if it can't be mounted.
+///
+/// # Safety
+///
+/// `read` and `write` have to be safe to call with `ctx` for as long as the
+/// file system or any of its files are around.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_mount(
+    read: ext2_read_fn,
+    write: ext2_write_fn,
+    ctx: *mut c_void,
+) -> *mut ext2_fs {
+    let read = match read {
+        Some(read) => read,
+        None => return ptr::null_mut(),
+    };
+    let volume = CallbackVolume { read, write, ctx };
+    let mut options = MountOptions::new();
+    options.read_only(write.is_none());
+    let mounted = Synced::new_with_options(volume, &options).and_then(|fs| {
+        {
+            let mut inner = fs.inner();
+            let now = inner.now();
+            inner.mount(now)?;
+        }
+        Ok(fs)
+    });
+    match mounted {
+        Ok(fs) => Box::into_raw(Box::new(ext2_fs { fs })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Unmounts `fs` as `Ext2::unmount` does, freeing it even if that fails
+///
+/// # Safety
+///
+/// `fs` has to come from `ext2_mount`, and isn't to be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_umount(fs: *mut ext2_fs) -> c_int {
+    if fs.is_null() {
+        return -errno::EINVAL;
+    }
+    let fs = Box::from_raw(fs);
+    let unmounted = fs.fs.inner().unmount();
+    match unmounted {
+        Ok(()) => 0,
+        Err(err) => neg_errno(&err),
+    }
+}
+
+/// Opens the file or directory at the absolute `path`, following symlinks,
+/// and stores it in `*file`
+///
+/// # Safety
+///
+/// `fs` has to come from `ext2_mount`, `path` has to be NUL-terminated and
+/// `file` has to be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_open(
+    fs: *const ext2_fs,
+    path: *const c_char,
+    file: *mut *mut ext2_file,
+) -> c_int {
+    let (fs, path) = match (fs.as_ref(), self::path(path)) {
+        (Some(fs), Some(path)) if !file.is_null() => (fs, path),
+        _ => return -errno::EINVAL,
+    };
+    match fs.fs.lookup(path) {
+        Ok(inode) => {
+            let opened = ext2_file { inode, dir_pos: 0 };
+            *file = Box::into_raw(Box::new(opened));
+            0
+        }
+        Err(err) => neg_errno(&err),
+    }
+}
+
+/// Reads up to `len` bytes from the position of `file` into `buf`, moving
+/// the position past them. Returns how many bytes were read, 0 at the end
+/// of the file, or a negative errno value.
+///
+/// # Safety
+///
+/// `file` has to come from `ext2_open`, and `buf` has to be valid for
+/// writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_read(
+    file: *mut ext2_file,
+    buf: *mut u8,
+    len: usize,
+) -> isize {
+    let file = match file.as_mut() {
+        Some(file) if !buf.is_null() => file,
+        _ => return -errno::EINVAL as isize,
+    };
+    let buf = slice::from_raw_parts_mut(buf, len);
+    match File::read(&file.inode, buf) {
+        Ok(read) => read as isize,
+        Err(err) => neg_errno(&err) as isize,
+    }
+}
+
+/// Reads the next entry of the directory `dir` into `*entry`. Returns 1 if
+/// there was one, 0 past the last one, or a negative errno value. An entry
+/// whose name doesn't fit `ext2_dirent`, which only a corrupt directory has,
+/// is `-EIO`.
+///
+/// # Safety
+///
+/// `dir` has to come from `ext2_open`, and `entry` has to be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_readdir(
+    dir: *mut ext2_file,
+    entry: *mut ext2_dirent,
+) -> c_int {
+    let (dir, entry) = match (dir.as_mut(), entry.as_mut()) {
+        (Some(dir), Some(entry)) => (dir, entry),
+        _ => return -errno::EINVAL,
+    };
+    let mut entries = match dir.inode.entries_from(dir.dir_pos) {
+        Some(entries) => entries,
+        None => return -errno::ENOTDIR,
+    };
+    let next = match entries.next() {
+        Some(Ok(next)) => next,
+        Some(Err(err)) => return neg_errno(&err),
+        None => return 0,
+    };
+    dir.dir_pos = entries.pos();
+    if next.name.len() >= entry.name.len() {
+        return -errno::EIO;
+    }
+    entry.ino = next.ino;
+    entry.file_type = next.file_type.map_or(0, |file_type| file_type.dirent());
+    entry.name_len = next.name.len() as u8;
+    for (to, &from) in entry.name.iter_mut().zip(&next.name) {
+        *to = from as c_char;
+    }
+    entry.name[next.name.len()] = 0;
+    1
+}
+
+/// Reads the information about the file at the absolute `path`, following
+/// symlinks, into `*stat`
+///
+/// # Safety
+///
+/// `fs` has to come from `ext2_mount`, `path` has to be NUL-terminated and
+/// `stat` has to be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_stat(
+    fs: *const ext2_fs,
+    path: *const c_char,
+    stat: *mut ext2_stat,
+) -> c_int {
+    let (fs, path, stat) = match (fs.as_ref(), self::path(path), stat.as_mut())
+    {
+        (Some(fs), Some(path), Some(stat)) => (fs, path, stat),
+        _ => return -errno::EINVAL,
+    };
+    let metadata = match fs.fs.metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) => return neg_errno(&err),
+    };
+    let (dev_major, dev_minor) = metadata.device().unwrap_or((0, 0));
+    *stat = ext2_stat {
+        size: metadata.len(),
+        ino: metadata.ino(),
+        blocks: metadata.blocks(),
+        atime: metadata.accessed(),
+        mtime: metadata.modified(),
+        ctime: metadata.created(),
+        dev_major,
+        dev_minor,
+        mode: TypePerm::new(metadata.file_type(), metadata.permissions())
+            .bits(),
+        nlink: metadata.nlink(),
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+    };
+    0
+}
+
+/// Closes `file`
+///
+/// # Safety
+///
+/// `file` has to come from `ext2_open`, and isn't to be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn ext2_close(file: *mut ext2_file) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ffi::{c_char, c_int, c_void, CStr};
+    use core::ptr;
+
+    use error::errno;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::Size512;
+    use sys::inode::Permissions;
+    use sys::superblock::FS_CLEAN;
+
+    use super::{
+        ext2_close, ext2_dirent, ext2_mount, ext2_open, ext2_read,
+        ext2_readdir, ext2_stat, ext2_umount,
+    };
+
+    fn cstr(bytes: &'static [u8]) -> *const c_char {
+        CStr::from_bytes_with_nul(bytes).unwrap().as_ptr()
+    }
+
+    /// A volume function over the `Vec<u8>` `ctx` points at
+    unsafe extern "C" fn read(
+        ctx: *mut c_void,
+        offset: u64,
+        buf: *mut u8,
+        len: usize,
+    ) -> c_int {
+        let image = &*(ctx as *const Vec<u8>);
+        match image.get(offset as usize..offset as usize + len) {
+            Some(bytes) => {
+                ptr::copy_nonoverlapping(bytes.as_ptr(), buf, len);
+                0
+            }
+            None => -errno::EIO,
+        }
+    }
+
+    unsafe extern "C" fn write(
+        ctx: *mut c_void,
+        offset: u64,
+        buf: *const u8,
+        len: usize,
+    ) -> c_int {
+        let image = &mut *(ctx as *mut Vec<u8>);
+        match image.get_mut(offset as usize..offset as usize + len) {
+            Some(bytes) => {
+                ptr::copy_nonoverlapping(buf, bytes.as_mut_ptr(), len);
+                0
+            }
+            None => -errno::EIO,
+        }
+    }
+
+    unsafe extern "C" fn failing(
+        _ctx: *mut c_void,
+        _offset: u64,
+        _buf: *mut u8,
+        _len: usize,
+    ) -> c_int {
+        -errno::ENXIO
+    }
+
+    #[test]
+    fn read_only() {
+        let mut image = std::fs::read("ext2.img").unwrap();
+        let ctx = &mut image as *mut Vec<u8> as *mut c_void;
+        unsafe {
+            let fs = ext2_mount(Some(read), None, ctx);
+            assert!(!fs.is_null());
+
+            let path = cstr(b"/home/funky/README.md\0");
+            let mut stat = core::mem::zeroed::<ext2_stat>();
+            assert_eq!(ext2_stat(fs, path, &mut stat), 0);
+            assert_eq!((stat.ino, stat.size), (14, 31));
+            assert_eq!(stat.mode & 0o170000, 0o100000);
+
+            let mut file = ptr::null_mut();
+            assert_eq!(ext2_open(fs, path, &mut file), 0);
+            let mut buf = [0; 64];
+            let read = ext2_read(file, buf.as_mut_ptr(), buf.len());
+            assert_eq!(read, 31);
+            let fs_ref = &(*fs).fs;
+            let mut content = Vec::new();
+            let readme = fs_ref.lookup(b"/home/funky/README.md").unwrap();
+            readme.read_to_end(&mut content).unwrap();
+            assert_eq!(&buf[..31], &content[..]);
+            assert_eq!(ext2_read(file, buf.as_mut_ptr(), buf.len()), 0);
+            let mut entry = core::mem::zeroed::<ext2_dirent>();
+            assert_eq!(ext2_readdir(file, &mut entry), -errno::ENOTDIR);
+            ext2_close(file);
+
+            let mut dir = ptr::null_mut();
+            assert_eq!(ext2_open(fs, cstr(b"/home/funky\0"), &mut dir), 0);
+            let mut names = Vec::new();
+            while ext2_readdir(dir, &mut entry) == 1 {
+                let name = CStr::from_ptr(entry.name.as_ptr()).to_bytes();
+                assert_eq!(name.len(), entry.name_len as usize);
+                names.push(name.to_vec());
+            }
+            let expected = fs_ref
+                .lookup(b"/home/funky")
+                .unwrap()
+                .directory()
+                .unwrap()
+                .map(|entry| entry.unwrap().name)
+                .collect::<Vec<_>>();
+            assert_eq!(names, expected);
+            ext2_close(dir);
+
+            let missing = cstr(b"/home/funky/missing\0");
+            assert_eq!(ext2_open(fs, missing, &mut file), -errno::ENOENT);
+            assert_eq!(ext2_stat(fs, ptr::null(), &mut stat), -errno::EINVAL);
+            assert_eq!(ext2_umount(fs), 0);
+        }
+        assert!(image == std::fs::read("ext2.img").unwrap());
+    }
+
+    #[test]
+    fn long_name() {
+        // without the filetype feature, the length of a name takes two
+        // bytes, and a corrupt one can be longer than a name can be
+        let volume = std::fs::read("fixtures/rev0.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o644);
+        fs.mkdir(b"/dir", Permissions::from_bits_truncate(0o755))
+            .unwrap();
+        fs.create_file(b"/dir/overlong-entry", perm).unwrap();
+        fs.create_file(b"/dir/after", perm).unwrap();
+        let mut image = fs.inner().volume.clone();
+        drop(fs);
+        let at = image
+            .windows(14)
+            .position(|window| window == b"overlong-entry")
+            .unwrap();
+        // the entry runs over the one after it, far enough for the name
+        image[at - 4..at - 2].copy_from_slice(&(8 + 300_u16).to_le_bytes());
+        image[at - 2..at].copy_from_slice(&300_u16.to_le_bytes());
+
+        let ctx = &mut image as *mut Vec<u8> as *mut c_void;
+        unsafe {
+            let fs = ext2_mount(Some(read), None, ctx);
+            assert!(!fs.is_null());
+            let mut dir = ptr::null_mut();
+            assert_eq!(ext2_open(fs, cstr(b"/dir\0"), &mut dir), 0);
+            let mut entry = core::mem::zeroed::<ext2_dirent>();
+            assert_eq!(ext2_readdir(dir, &mut entry), 1);
+            assert_eq!(CStr::from_ptr(entry.name.as_ptr()).to_bytes(), b".");
+            assert_eq!(ext2_readdir(dir, &mut entry), 1);
+            assert_eq!(ext2_readdir(dir, &mut entry), -errno::EIO);
+            ext2_close(dir);
+            assert_eq!(ext2_umount(fs), 0);
+        }
+    }
+
+    #[test]
+    fn mount_and_unmount() {
+        let mut image = std::fs::read("ext2.img").unwrap();
+        let ctx = &mut image as *mut Vec<u8> as *mut c_void;
+        unsafe {
+            let fs = ext2_mount(Some(read), Some(write), ctx);
+            assert!(!fs.is_null());
+            // marked as mounted until unmounted
+            let state = { (*fs).fs.read().superblock().state };
+            assert_eq!(state & FS_CLEAN, 0);
+            assert_eq!(ext2_umount(fs), 0);
+        }
+        assert!(::fs::sync::tests::fsck(&image));
+
+        unsafe {
+            assert!(ext2_mount(Some(failing), None, ctx).is_null());
+            assert!(ext2_mount(None, None, ctx).is_null());
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Permission checks, the way a kernel makes them on behalf of a process
+
+use alloc::vec::Vec;
+
+use sys::acl::{Acl, AclPerm};
+use sys::inode::Permissions;
+
+use super::Metadata;
+
+bitflags! {
+    /// What `access` is asked about, as `access(2)` takes it
+    #[derive(Default)]
+    pub struct Access: u16 {
+        const EXECUTE = 0x1;
+        const WRITE = 0x2;
+        const READ = 0x4;
+    }
+}
+
+/// Whether the user `uid`, a member of the groups `gids`, may do what
+/// `requested` asks to the file `metadata` describes, going by its
+/// permission bits alone.
+///
+/// The superuser may read and write anything, and execute a file if any
+/// execute bit is set on it; directories it may always search. Anyone else
+/// gets the owner's bits if they own the file, the group's if they're in
+/// its group, and the others' bits otherwise, and only those: the owner
+/// isn't let in on the group's bits. Nothing requested is always allowed.
+pub fn access(
+    metadata: &Metadata,
+    uid: u16,
+    gids: &[u16],
+    requested: Access,
+) -> bool {
+    if uid == 0 {
+        return root_access(metadata, requested);
+    }
+    let perm = metadata.permissions().bits();
+    let granted = if uid == metadata.uid() {
+        perm >> 6
+    } else if gids.contains(&metadata.gid()) {
+        perm >> 3
+    } else {
+        perm
+    };
+    Access::from_bits_truncate(granted).contains(requested)
+}
+
+/// Like `access`, consulting the file's access ACL if it has one, as
+/// `Inode::get_acl` returns it. The ACL then stands in for the permission
+/// bits for everyone but the superuser: named users and groups get what
+/// their entries grant, bounded by the mask.
+pub fn access_with_acl(
+    metadata: &Metadata,
+    acl: Option<&Acl>,
+    uid: u16,
+    gids: &[u16],
+    requested: Access,
+) -> bool {
+    let acl = match acl {
+        Some(acl) if uid != 0 => acl,
+        _ => return access(metadata, uid, gids, requested),
+    };
+    let groups = gids.iter().map(|&gid| u32::from(gid)).collect::<Vec<_>>();
+    // no u16 gid is this one, so it only matches groups in `groups`
+    let granted = acl.effective_permissions(
+        u32::from(metadata.uid()),
+        u32::from(metadata.gid()),
+        u32::from(uid),
+        !0,
+        &groups,
+    );
+    let requested = AclPerm::from_bits_truncate(requested.bits());
+    granted.contains(requested)
+}
+
+/// Whether the sticky bit of the directory `parent` lets the user `uid`
+/// remove or rename its entry for `child`: it does unless the bit is set
+/// and they own neither, or they're the superuser. Removing an entry also
+/// takes write and search permission on `parent`, which is for `access` to
+/// check.
+pub fn may_delete(parent: &Metadata, child: &Metadata, uid: u16) -> bool {
+    !parent.permissions().contains(Permissions::STICKY)
+        || uid == 0
+        || uid == parent.uid()
+        || uid == child.uid()
+}
+
+fn root_access(metadata: &Metadata, requested: Access) -> bool {
+    let any_exec =
+        Permissions::U_EXEC | Permissions::G_EXEC | Permissions::O_EXEC;
+    !requested.contains(Access::EXECUTE)
+        || metadata.is_dir()
+        || metadata.permissions().intersects(any_exec)
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::Metadata;
+    use sys::acl::Acl;
+    use sys::inode::{FileType, Inode as RawInode, Permissions, TypePerm};
+
+    use super::{access, access_with_acl, may_delete, Access};
+
+    const R: Access = Access::READ;
+    const W: Access = Access::WRITE;
+    const X: Access = Access::EXECUTE;
+
+    fn metadata(kind: FileType, mode: u16, uid: u16, gid: u16) -> Metadata {
+        let inode = RawInode {
+            type_perm: TypePerm::new(
+                kind,
+                Permissions::from_bits_truncate(mode),
+            ),
+            uid,
+            gid,
+            ..RawInode::default()
+        };
+        Metadata::new(11, &inode, false)
+    }
+
+    #[test]
+    fn permission_bits() {
+        // mode, owner, group, uid, gids, requested, allowed
+        type Row = (u16, u16, u16, u16, &'static [u16], Access, bool);
+        let rwx = R | W | X;
+        let table: &[Row] = &[
+            // the owner gets the owner's bits
+            (0o640, 1000, 100, 1000, &[], R, true),
+            (0o640, 1000, 100, 1000, &[], R | W, true),
+            (0o640, 1000, 100, 1000, &[], X, false),
+            (0o740, 1000, 100, 1000, &[100], rwx, true),
+            // and only those, even if the group or others get more
+            (0o077, 1000, 100, 1000, &[100], R, false),
+            (0o407, 1000, 100, 1000, &[], W, false),
+            // a member of the group gets the group's bits
+            (0o640, 1000, 100, 1001, &[100], R, true),
+            (0o640, 1000, 100, 1001, &[100], W, false),
+            (0o640, 1000, 100, 1001, &[5, 6, 100], R, true),
+            (0o604, 1000, 100, 1001, &[100], R, false),
+            // anyone else gets the others' bits
+            (0o644, 1000, 100, 1001, &[5], R, true),
+            (0o644, 1000, 100, 1001, &[], W, false),
+            (0o647, 1000, 100, 1001, &[], rwx, true),
+            (0o640, 1000, 100, 1001, &[], R, false),
+            // nothing requested is allowed, whatever the mode
+            (0o000, 1000, 100, 1001, &[], Access::empty(), true),
+            // the superuser may read and write anything
+            (0o000, 1000, 100, 0, &[], R | W, true),
+            // and execute what anyone may execute
+            (0o000, 1000, 100, 0, &[], X, false),
+            (0o001, 1000, 100, 0, &[], X, true),
+            (0o010, 1000, 100, 0, &[], rwx, true),
+            (0o100, 1000, 100, 0, &[], X, true),
+            (0o644, 0, 0, 0, &[0], X, false),
+        ];
+        for &(mode, owner, group, uid, gids, requested, allowed) in table {
+            let file = metadata(FileType::File, mode, owner, group);
+            assert_eq!(
+                access(&file, uid, gids, requested),
+                allowed,
+                "{:o} owned by {}:{}, {} in {:?} asking for {:?}",
+                mode,
+                owner,
+                group,
+                uid,
+                gids,
+                requested,
+            );
+            // no ACL is the same as none being consulted
+            assert_eq!(
+                access_with_acl(&file, None, uid, gids, requested),
+                allowed
+            );
+        }
+
+        // the superuser may search any directory
+        let dir = metadata(FileType::Directory, 0o000, 1000, 100);
+        assert!(access(&dir, 0, &[], R | W | X));
+        assert!(!access(&dir, 1000, &[100], X));
+    }
+
+    #[test]
+    fn acl() {
+        // u::rw-,u:1001:rwx,g::r--,g:200:-w-,m::rw-,o::--- on a file whose
+        // group bits are the mask's, as setfacl leaves them
+        let mut bytes = vec![2, 0, 0, 0];
+        for &(tag, perm, id) in &[
+            (1_u16, 6_u16, !0_u32),
+            (2, 7, 1001),
+            (4, 4, !0),
+            (8, 2, 200),
+            (0x10, 6, !0),
+            (0x20, 0, !0),
+        ] {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&perm.to_le_bytes());
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        let acl = Acl::from_xattr(&bytes).unwrap();
+        let file = metadata(FileType::File, 0o660, 1000, 100);
+
+        // uid, gids, requested, allowed
+        let table: &[(u16, &[u16], Access, bool)] = &[
+            (1000, &[], R | W, true),
+            (1000, &[], X, false),
+            // the named user's execute bit is masked out
+            (1001, &[], R | W, true),
+            (1001, &[], X, false),
+            // the owning group gets its entry, not the mode's group bits
+            (1002, &[100], R, true),
+            (1002, &[100], W, false),
+            // matching groups add up
+            (1002, &[200], W, true),
+            (1002, &[100, 200], R | W, true),
+            // everyone else gets nothing
+            (1002, &[], R, false),
+            (1002, &[300], Access::empty(), true),
+            // the superuser goes by the mode
+            (0, &[], R | W, true),
+            (0, &[], X, false),
+        ];
+        for &(uid, gids, requested, allowed) in table {
+            assert_eq!(
+                access_with_acl(&file, Some(&acl), uid, gids, requested),
+                allowed,
+                "{} in {:?} asking for {:?}",
+                uid,
+                gids,
+                requested,
+            );
+        }
+        // without the ACL, 1001 is just another user
+        assert!(!access(&file, 1001, &[], R));
+    }
+
+    #[test]
+    fn sticky() {
+        let tmp = metadata(FileType::Directory, 0o1777, 0, 0);
+        let open = metadata(FileType::Directory, 0o777, 0, 0);
+        let owned = metadata(FileType::Directory, 0o1777, 1002, 0);
+        let child = metadata(FileType::File, 0o644, 1000, 100);
+        // parent, uid, allowed
+        let table: &[(&Metadata, u16, bool)] = &[
+            (&tmp, 1000, true),
+            (&tmp, 1001, false),
+            (&tmp, 0, true),
+            (&open, 1001, true),
+            (&owned, 1001, false),
+            (&owned, 1002, true),
+        ];
+        for &(parent, uid, allowed) in table {
+            assert_eq!(may_delete(parent, &child, uid), allowed);
+        }
+    }
+}
@@ -0,0 +1,318 @@
+//! Caches of parsed inodes and of directory entries, see
+//! `MountOptions::inode_cache` and `MountOptions::dentry_cache`
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+use spin::Mutex;
+
+use sector::{Address, SectorSize};
+use sys::inode::Inode as RawInode;
+
+/// Up to a fixed number of values by key. Once full, the clock algorithm
+/// picks the value to make room: the slots are swept in turn, and the first
+/// one not looked up since the last sweep passed it is taken.
+struct Clock<K, T> {
+    capacity: usize,
+    slots: Vec<Slot<K, T>>,
+    /// The slot of each key in the cache
+    index: BTreeMap<K, usize>,
+    /// The next slot the clock looks at
+    hand: usize,
+}
+
+struct Slot<K, T> {
+    /// The key and value held, if any
+    entry: Option<(K, T)>,
+    /// Whether the value was looked up since the clock last passed
+    referenced: bool,
+}
+
+impl<K: Ord + Copy, T> Clock<K, T> {
+    fn new(capacity: usize) -> Clock<K, T> {
+        Clock {
+            capacity,
+            slots: Vec::new(),
+            index: BTreeMap::new(),
+            hand: 0,
+        }
+    }
+
+    fn get(&mut self, key: &K) -> Option<&T> {
+        let index = *self.index.get(key)?;
+        let slot = &mut self.slots[index];
+        slot.referenced = true;
+        slot.entry.as_ref().map(|(_, value)| value)
+    }
+
+    fn insert(&mut self, key: K, value: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        let slot = Slot {
+            entry: Some((key, value)),
+            referenced: false,
+        };
+        if let Some(&index) = self.index.get(&key) {
+            self.slots[index] = slot;
+            return;
+        }
+        if self.slots.len() < self.capacity {
+            self.index.insert(key, self.slots.len());
+            self.slots.push(slot);
+            return;
+        }
+        loop {
+            let hand = self.hand;
+            self.hand = (hand + 1) % self.capacity;
+            let victim = &mut self.slots[hand];
+            if victim.referenced {
+                victim.referenced = false;
+                continue;
+            }
+            if let Some((old, _)) = victim.entry {
+                self.index.remove(&old);
+            }
+            *victim = slot;
+            self.index.insert(key, hand);
+            return;
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(index) = self.index.remove(key) {
+            self.slots[index] = Slot {
+                entry: None,
+                referenced: false,
+            };
+        }
+    }
+
+    fn remove_range(&mut self, keys: RangeInclusive<K>) {
+        while let Some(&key) =
+            self.index.range(keys.clone()).next().map(|(key, _)| key)
+        {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Up to a fixed number of inodes as last read from their inode tables,
+/// along with their addresses there, evicted by the clock algorithm.
+///
+/// The cache doesn't see the volume, so every write to an inode has to
+/// `invalidate` it. With a capacity of 0 nothing is ever kept.
+pub(crate) struct InodeCache<S: SectorSize> {
+    inner: Mutex<Clock<u32, (RawInode, Address<S>)>>,
+}
+
+impl<S: SectorSize> InodeCache<S> {
+    pub fn new(capacity: usize) -> InodeCache<S> {
+        InodeCache {
+            inner: Mutex::new(Clock::new(capacity)),
+        }
+    }
+
+    /// Returns inode `inode` and its address, if it's in the cache
+    pub fn get(&self, inode: u32) -> Option<(RawInode, Address<S>)> {
+        self.inner.lock().get(&inode).copied()
+    }
+
+    /// Keeps inode `inode`, read from `addr` as `raw`
+    pub fn insert(&self, inode: u32, raw: RawInode, addr: Address<S>) {
+        self.inner.lock().insert(inode, (raw, addr));
+    }
+
+    /// Drops inode `inode` from the cache, if it's there
+    pub fn invalidate(&self, inode: u32) {
+        self.inner.lock().remove(&inode);
+    }
+}
+
+/// Up to a fixed number of directory entries looked up by name, evicted by
+/// the clock algorithm. Names found missing are kept too, so looking them
+/// up again doesn't scan the directory either.
+///
+/// Entries are keyed by their directory and a hash of their name, with the
+/// name kept to tell collisions apart, so that a lookup doesn't allocate.
+/// Every change to an entry has to `invalidate` it, and a directory that's
+/// freed has to `invalidate_dir` all of its entries. With a capacity of 0
+/// nothing is ever kept.
+pub(crate) struct DentryCache {
+    inner: Mutex<Dentries>,
+}
+
+struct Dentries {
+    clock: Clock<(u32, u64), Dentry>,
+    /// Bumped by every invalidation, so that what was looked up before one
+    /// isn't kept after it
+    generation: u64,
+}
+
+struct Dentry {
+    name: Vec<u8>,
+    /// The inode the entry points at, or `None` if there's no such entry
+    inode: Option<u32>,
+}
+
+impl DentryCache {
+    pub fn new(capacity: usize) -> DentryCache {
+        DentryCache {
+            inner: Mutex::new(Dentries {
+                clock: Clock::new(capacity),
+                generation: 0,
+            }),
+        }
+    }
+
+    /// Returns what entry `name` of directory `dir` was found to point at,
+    /// `Some(None)` if it was found missing, or `None` if it's not in the
+    /// cache
+    pub fn get(&self, dir: u32, name: &[u8]) -> Option<Option<u32>> {
+        let mut inner = self.inner.lock();
+        match inner.clock.get(&(dir, hash(name))) {
+            Some(dentry) if dentry.name == name => Some(dentry.inode),
+            _ => None,
+        }
+    }
+
+    /// Returns the current generation, to be taken before the directory is
+    /// looked at and passed on to `insert`
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    /// Keeps entry `name` of directory `dir` as pointing at `inode`, or as
+    /// missing, unless anything was invalidated since `generation`, in
+    /// which case the directory may have changed under the lookup
+    pub fn insert(
+        &self,
+        dir: u32,
+        name: &[u8],
+        inode: Option<u32>,
+        generation: u64,
+    ) {
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        let dentry = Dentry {
+            name: name.to_vec(),
+            inode,
+        };
+        inner.clock.insert((dir, hash(name)), dentry);
+    }
+
+    /// Drops entry `name` of directory `dir` from the cache, if it's there
+    pub fn invalidate(&self, dir: u32, name: &[u8]) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.clock.remove(&(dir, hash(name)));
+    }
+
+    /// Drops every entry of directory `dir` from the cache
+    pub fn invalidate_dir(&self, dir: u32) {
+        let mut inner = self.inner.lock();
+        inner.generation += 1;
+        inner.clock.remove_range((dir, 0)..=(dir, !0));
+    }
+}
+
+/// The FNV-1a hash of `name`
+fn hash(name: &[u8]) -> u64 {
+    name.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use sector::{Address, Size512};
+    use sys::inode::Inode as RawInode;
+
+    use super::{DentryCache, InodeCache};
+
+    fn raw(uid: u16) -> RawInode {
+        RawInode {
+            uid,
+            ..RawInode::default()
+        }
+    }
+
+    fn uid(cache: &InodeCache<Size512>, inode: u32) -> Option<u16> {
+        cache.get(inode).map(|(raw, _)| raw.uid)
+    }
+
+    #[test]
+    fn clock() {
+        let cache = InodeCache::<Size512>::new(3);
+        let addr = Address::from(4096_u64);
+        for inode in 1..=3 {
+            cache.insert(inode, raw(inode as u16), addr);
+        }
+        assert_eq!(cache.get(2).map(|(_, addr)| addr), Some(addr));
+        assert_eq!(uid(&cache, 3), Some(3));
+
+        // 1 wasn't looked up, so it makes room
+        cache.insert(4, raw(4), addr);
+        assert_eq!(uid(&cache, 1), None);
+        // then 2 and 3 get a second chance, and 4 goes
+        cache.insert(5, raw(5), addr);
+        assert_eq!(uid(&cache, 4), None);
+        assert_eq!(
+            [2, 3, 5]
+                .iter()
+                .map(|&inode| uid(&cache, inode))
+                .collect::<Vec<_>>(),
+            vec![Some(2), Some(3), Some(5)]
+        );
+
+        // inserting again replaces
+        cache.insert(5, raw(50), addr);
+        assert_eq!(uid(&cache, 5), Some(50));
+
+        cache.invalidate(2);
+        assert_eq!(uid(&cache, 2), None);
+        // the emptied slot is next for the clock, so nothing else goes
+        cache.insert(6, raw(6), addr);
+        assert_eq!(uid(&cache, 6), Some(6));
+        assert_eq!(uid(&cache, 3), Some(3));
+        assert_eq!(uid(&cache, 5), Some(50));
+    }
+
+    #[test]
+    fn disabled() {
+        let cache = InodeCache::<Size512>::new(0);
+        cache.insert(1, raw(1), Address::from(4096_u64));
+        assert_eq!(uid(&cache, 1), None);
+        cache.invalidate(1);
+    }
+
+    #[test]
+    fn dentries() {
+        let cache = DentryCache::new(4);
+        let generation = cache.generation();
+        cache.insert(2, b"home", Some(12), generation);
+        cache.insert(2, b"missing", None, generation);
+        cache.insert(12, b"funky", Some(13), generation);
+        assert_eq!(cache.get(2, b"home"), Some(Some(12)));
+        assert_eq!(cache.get(2, b"missing"), Some(None));
+        assert_eq!(cache.get(2, b"funky"), None);
+        assert_eq!(cache.get(12, b"funky"), Some(Some(13)));
+
+        cache.invalidate(2, b"missing");
+        assert_eq!(cache.get(2, b"missing"), None);
+        cache.invalidate_dir(2);
+        assert_eq!(cache.get(2, b"home"), None);
+        assert_eq!(cache.get(12, b"funky"), Some(Some(13)));
+
+        // a lookup that started before an invalidation isn't kept
+        cache.insert(2, b"home", Some(12), generation);
+        assert_eq!(cache.get(2, b"home"), None);
+
+        let disabled = DentryCache::new(0);
+        disabled.insert(2, b"home", Some(12), disabled.generation());
+        assert_eq!(disabled.get(2, b"home"), None);
+    }
+}
@@ -0,0 +1,764 @@
+//! Consistency checks of the allocation metadata against what's actually in
+//! use, like the later passes of `e2fsck`
+
+use alloc::collections::btree_map::Entry;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+use sys::dirent::DirEntryIter;
+use sys::inode::{FileType, Inode as RawInode, InodeBlock};
+use sys::superblock::FS_ERR;
+use volume::Volume;
+
+use super::Ext2;
+
+/// Inode number of the root directory
+const ROOT: u32 = 2;
+/// Owner recorded for the blocks of the group metadata
+const METADATA: u32 = !0;
+
+/// Something wrong `check` found with a filesystem. Blocks and inodes are
+/// numbered as on the volume; a block's owner is the inode it belongs to,
+/// or `None` for the superblock and descriptor table backups, the usage
+/// bitmaps and the inode tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Finding {
+    /// Block `block` is in use but free in its group's block bitmap
+    BlockMarkedFree { block: u32 },
+    /// Block `block` is used in its group's block bitmap but nothing uses it
+    BlockMarkedUsed { block: u32 },
+    /// Block `block` belongs to `first` but `second` claims it too
+    BlockClaimedTwice {
+        block: u32,
+        first: Option<u32>,
+        second: Option<u32>,
+    },
+    /// Inode `inode` is in use but free in its group's inode bitmap
+    InodeMarkedFree { inode: u32 },
+    /// Inode `inode` is used in its group's inode bitmap but is neither
+    /// reserved nor linked to
+    InodeMarkedUsed { inode: u32 },
+    /// Inode `inode` has `links` links but no directory entry reachable
+    /// from the root leads to it
+    UnreachableInode { inode: u32, links: u16 },
+    /// Inode `inode` has `recorded` links, but `counted` directory entries
+    /// lead to it
+    WrongLinkCount {
+        inode: u32,
+        recorded: u16,
+        counted: u32,
+    },
+    /// The free block count of group `group`, or of the superblock if
+    /// `None`, is `recorded` instead of `counted`
+    WrongFreeBlocks {
+        group: Option<u32>,
+        recorded: u32,
+        counted: u32,
+    },
+    /// The free inode count of group `group`, or of the superblock if
+    /// `None`, is `recorded` instead of `counted`
+    WrongFreeInodes {
+        group: Option<u32>,
+        recorded: u32,
+        counted: u32,
+    },
+    /// The directory count of group `group` is `recorded` instead of
+    /// `counted`
+    WrongDirsCount {
+        group: u32,
+        recorded: u32,
+        counted: u32,
+    },
+    /// The blocks of inode `inode`, or the entries of the directory it is,
+    /// couldn't be walked past corruption, as `Error::Corrupt` describes
+    Corrupt { inode: u32, reason: &'static str },
+}
+
+/// What `check` found, in the order it was found: the block and inode
+/// claims first, then the bitmaps and counts group by group, and the
+/// superblock's counts last
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Whether nothing was found wrong
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+/// Checks that the usage bitmaps and the free and directory counts of `fs`
+/// agree with what's actually in use, without changing anything.
+///
+/// Usage is rebuilt from scratch: the group metadata is in use, and so is
+/// every inode reached from the root directory, every reserved inode and
+/// every inode that's linked to without being reachable, along with the
+/// blocks these hold. Inodes are also checked to have as many links as
+/// directory entries lead to them, and blocks to belong to one owner at
+/// most, extended attribute blocks being shared between inodes.
+///
+/// Corruption is reported rather than returned as an error; errors are
+/// left for the volume failing to read.
+pub fn check<S: SectorSize, V: Volume<u8, S>>(
+    fs: &Ext2<S, V>,
+) -> Result<Report> {
+    let superblock = fs.superblock();
+    let blocks = superblock.blocks_count - superblock.first_data_block;
+    let inodes = superblock.inodes_count as usize;
+    let mut checker = Checker {
+        fs,
+        owners: vec![0; blocks as usize],
+        used: vec![false; inodes],
+        dirs: vec![false; inodes],
+        recorded: vec![0; inodes],
+        links: vec![0; inodes],
+        xattr_blocks: BTreeSet::new(),
+        findings: Vec::new(),
+    };
+    checker.claim_metadata()?;
+    checker.walk_tree()?;
+    checker.scan_inodes()?;
+    checker.compare_groups()?;
+    Ok(Report {
+        findings: checker.findings,
+    })
+}
+
+/// Fixes what's safe to fix of what `check` reported in `report`, and
+/// checks `fs` again, returning what's left.
+///
+/// Bitmaps are made to match the usage `check` found, freeing the blocks
+/// nothing uses and the inodes nothing links to; inodes freed that way get
+/// a deletion time if they had none. Free and directory counts are set to
+/// those counted. Blocks claimed twice, unreachable inodes, wrong link
+/// counts and corruption are left alone, as fixing them means guessing
+/// which data to keep, and so are inodes with links that are neither in
+/// use nor reserved.
+///
+/// Once the check comes back clean, the superblock is marked as free of
+/// errors. Repairing with a report that's already been acted on changes
+/// nothing, so `check` and `repair` may be run any number of times.
+pub fn repair<S: SectorSize, V: Volume<u8, S>>(
+    fs: &mut Ext2<S, V>,
+    report: &Report,
+) -> Result<Report> {
+    fs.check_writable()?;
+    let mut block_bitmaps = BTreeMap::new();
+    let mut inode_bitmaps = BTreeMap::new();
+    let mut superblock = false;
+    for finding in &report.findings {
+        match *finding {
+            Finding::BlockMarkedFree { block }
+            | Finding::BlockMarkedUsed { block } => {
+                let (group, index) = match fs.block_position(block) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                let bitmap = match block_bitmaps.entry(group) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        entry.insert(fs.block_bitmap(group)?)
+                    }
+                };
+                match *finding {
+                    Finding::BlockMarkedFree { .. } => bitmap.set(index),
+                    _ => bitmap.clear(index),
+                }
+            }
+            Finding::InodeMarkedFree { inode }
+            | Finding::InodeMarkedUsed { inode } => {
+                let (group, index) = fs.inode_position(inode)?;
+                let free = match *finding {
+                    Finding::InodeMarkedUsed { .. } => {
+                        let mut raw = fs.read_inode(inode)?;
+                        if raw.hard_links != 0 {
+                            continue;
+                        }
+                        if raw.dtime == 0 {
+                            raw.dtime = fs.now();
+                            fs.write_inode(inode, &raw)?;
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                let bitmap = match inode_bitmaps.entry(group) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        entry.insert(fs.inode_bitmap(group)?)
+                    }
+                };
+                if free {
+                    bitmap.clear(index);
+                } else {
+                    bitmap.set(index);
+                }
+            }
+            Finding::WrongFreeBlocks { group, counted, .. } => match group {
+                Some(group) => {
+                    fs.group_descriptor_mut(group as usize).free_blocks_count =
+                        counted as u16;
+                }
+                None => {
+                    fs.superblock.inner.free_blocks_count = counted;
+                    superblock = true;
+                }
+            },
+            Finding::WrongFreeInodes { group, counted, .. } => match group {
+                Some(group) => {
+                    fs.group_descriptor_mut(group as usize).free_inodes_count =
+                        counted as u16;
+                }
+                None => {
+                    fs.superblock.inner.free_inodes_count = counted;
+                    superblock = true;
+                }
+            },
+            Finding::WrongDirsCount { group, counted, .. } => {
+                fs.group_descriptor_mut(group as usize).dirs_count =
+                    counted as u16;
+            }
+            _ => (),
+        }
+    }
+
+    for (group, bitmap) in block_bitmaps {
+        fs.write_block_bitmap(group, bitmap)?;
+    }
+    for (group, bitmap) in inode_bitmaps {
+        fs.write_inode_bitmap(group, bitmap)?;
+    }
+    fs.write_block_groups()?;
+    if superblock {
+        fs.write_superblock()?;
+    }
+
+    let left = check(fs)?;
+    if left.is_clean() && fs.superblock.inner.state & FS_ERR != 0 {
+        fs.superblock.inner.state &= !FS_ERR;
+        fs.write_superblock()?;
+    }
+    Ok(left)
+}
+
+struct Checker<'a, S: SectorSize, V: 'a + Volume<u8, S>> {
+    fs: &'a Ext2<S, V>,
+    /// The owner of each block from the first data block on, 0 for none
+    owners: Vec<u32>,
+    /// Whether each inode is in use, inode 1 first
+    used: Vec<bool>,
+    /// Whether each inode in use is a directory
+    dirs: Vec<bool>,
+    /// The link count of each inode in use
+    recorded: Vec<u16>,
+    /// The number of directory entries leading to each inode
+    links: Vec<u32>,
+    /// Extended attribute blocks already claimed
+    xattr_blocks: BTreeSet<u32>,
+    findings: Vec<Finding>,
+}
+
+impl<'a, S: SectorSize, V: Volume<u8, S>> Checker<'a, S, V> {
+    /// Records `owner` as the owner of `block`, unless someone else already
+    /// is. Blocks outside the filesystem are left to whoever reads them.
+    fn claim(&mut self, block: u32, owner: u32) {
+        let superblock = self.fs.superblock();
+        if block < superblock.first_data_block
+            || block >= superblock.blocks_count
+        {
+            return;
+        }
+        let slot =
+            &mut self.owners[(block - superblock.first_data_block) as usize];
+        if *slot == 0 {
+            *slot = owner;
+            return;
+        }
+        let as_option =
+            |owner| if owner == METADATA { None } else { Some(owner) };
+        self.findings.push(Finding::BlockClaimedTwice {
+            block,
+            first: as_option(*slot),
+            second: as_option(owner),
+        });
+    }
+
+    /// Claims the superblock and descriptor table backups, the usage
+    /// bitmaps and the inode table of every group
+    fn claim_metadata(&mut self) -> Result<()> {
+        let superblock = self.fs.superblock();
+        let groups = self.fs.block_group_count()? as u32;
+        let descriptor_size = 32;
+        let gdt_blocks = (groups as usize * descriptor_size)
+            .div_ceil(superblock.block_size()) as u32;
+        let table_blocks = superblock.inode_table_blocks();
+        for group in 0..groups {
+            if superblock.has_backup(group) {
+                let start = self.fs.group_start(group as usize);
+                for block in start..start + 1 + gdt_blocks {
+                    self.claim(block, METADATA);
+                }
+            }
+            let descriptor = self.fs.block_groups()[group as usize];
+            self.claim(descriptor.block_usage_addr, METADATA);
+            self.claim(descriptor.inode_usage_addr, METADATA);
+            let table = descriptor.inode_table_block;
+            for block in table..table + table_blocks {
+                self.claim(block, METADATA);
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the inodes reachable from the root as used, counting the
+    /// entries leading to each
+    fn walk_tree(&mut self) -> Result<()> {
+        let mut pending = Vec::new();
+        if let Some(blocks) = self.visit(ROOT)? {
+            pending.push((ROOT, blocks));
+        }
+        let features = self.fs.superblock().features_req();
+        let log_block_size = self.fs.log_block_size();
+        while let Some((dir, blocks)) = pending.pop() {
+            for block in blocks {
+                let start = Address::with_block_size(block, 0, log_block_size);
+                let end =
+                    Address::with_block_size(block + 1, 0, log_block_size);
+                let data = self
+                    .fs
+                    .volume
+                    .slice(start..end)
+                    .map_err(|err| err.into())?;
+                let mut children = Vec::new();
+                for entry in DirEntryIter::new(&data, features) {
+                    let entry = match entry {
+                        Ok(entry) => entry,
+                        Err(Error::Corrupt { reason }) => {
+                            self.findings
+                                .push(Finding::Corrupt { inode: dir, reason });
+                            break;
+                        }
+                        Err(err) => return Err(err),
+                    };
+                    let inode = entry.header.inode;
+                    if inode == 0 {
+                        continue;
+                    }
+                    if inode as usize > self.links.len() {
+                        self.findings.push(Finding::Corrupt {
+                            inode: dir,
+                            reason: "directory entry for an inode outside \
+                                     the filesystem",
+                        });
+                        continue;
+                    }
+                    self.links[inode as usize - 1] += 1;
+                    if entry.name != b"." && entry.name != b".." {
+                        children.push(inode);
+                    }
+                }
+                for inode in children {
+                    if let Some(blocks) = self.visit(inode)? {
+                        pending.push((inode, blocks));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks inode `inode` as used and claims its blocks, unless it already
+    /// is. Returns the data blocks of a directory seen for the first time.
+    fn visit(&mut self, inode: u32) -> Result<Option<Vec<u32>>> {
+        if self.used[inode as usize - 1] {
+            return Ok(None);
+        }
+        let raw = self.fs.read_inode(inode)?;
+        let blocks = self.use_inode(inode, &raw)?;
+        Ok(if self.dirs[inode as usize - 1] {
+            Some(blocks)
+        } else {
+            None
+        })
+    }
+
+    /// Marks the reserved inodes and those linked to from outside the tree
+    /// as used
+    fn scan_inodes(&mut self) -> Result<()> {
+        let first_inode = self.fs.first_inode();
+        for inode in 1..=self.used.len() as u32 {
+            if self.used[inode as usize - 1] {
+                continue;
+            }
+            let raw = self.fs.read_inode(inode)?;
+            if inode >= first_inode {
+                let links = raw.hard_links;
+                if links == 0
+                    || raw.dtime != 0
+                    || raw.type_perm.file_type() == FileType::Unknown
+                {
+                    continue;
+                }
+                self.findings
+                    .push(Finding::UnreachableInode { inode, links });
+            }
+            self.use_inode(inode, &raw)?;
+        }
+        Ok(())
+    }
+
+    /// Marks inode `inode` as used and claims its blocks, returning its data
+    /// blocks if it's a directory
+    fn use_inode(&mut self, inode: u32, raw: &RawInode) -> Result<Vec<u32>> {
+        let index = inode as usize - 1;
+        let file_type = raw.type_perm.file_type();
+        self.used[index] = true;
+        self.dirs[index] = file_type == FileType::Directory;
+        self.recorded[index] = raw.hard_links;
+
+        let superblock = self.fs.superblock();
+        let xattr = raw.ext_attribute_block;
+        let xattr_sectors = if xattr != 0 && !superblock.is_rev_0() {
+            if self.fs.check_block(xattr).is_err() {
+                self.findings.push(Finding::Corrupt {
+                    inode,
+                    reason: "block pointer outside the filesystem",
+                });
+            } else if self.xattr_blocks.insert(xattr) {
+                self.claim(xattr, inode);
+            }
+            (self.fs.block_size() >> 9) as u32
+        } else {
+            0
+        };
+        // devices and fast symlinks keep other things in the block pointers
+        let has_blocks = match file_type {
+            FileType::File | FileType::Directory => true,
+            FileType::Symlink => {
+                raw.size(superblock) >= 60 || raw.sectors_count != xattr_sectors
+            }
+            _ => inode < self.fs.first_inode(),
+        };
+        let mut data = Vec::new();
+        if !has_blocks {
+            return Ok(data);
+        }
+        for block in raw.blocks(&self.fs.volume, superblock) {
+            match block {
+                Ok(block) => {
+                    self.claim(block.block(), inode);
+                    if let InodeBlock::Data { block, .. } = block {
+                        if self.dirs[index] {
+                            data.push(block);
+                        }
+                    }
+                }
+                Err(Error::Corrupt { reason }) => {
+                    self.findings.push(Finding::Corrupt { inode, reason });
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(data)
+    }
+
+    /// Compares the bitmaps and counts of every group, and the totals of
+    /// the superblock, with the usage found
+    fn compare_groups(&mut self) -> Result<()> {
+        let superblock = self.fs.superblock();
+        let first_data_block = superblock.first_data_block;
+        let inodes_per_group = superblock.inodes_per_group;
+        let (mut free_blocks, mut free_inodes) = (0, 0);
+        for group in 0..self.fs.block_group_count()? {
+            let start = self.fs.group_start(group);
+            let bitmap = self.fs.block_bitmap(group)?;
+            let mut free = 0;
+            for index in 0..bitmap.len() {
+                let block = start + index as u32;
+                let used =
+                    self.owners[(block - first_data_block) as usize] != 0;
+                match (used, bitmap.get(index)) {
+                    (true, false) => {
+                        self.findings.push(Finding::BlockMarkedFree { block })
+                    }
+                    (false, true) => {
+                        self.findings.push(Finding::BlockMarkedUsed { block })
+                    }
+                    _ => (),
+                }
+                if !used {
+                    free += 1;
+                }
+            }
+
+            let bitmap = self.fs.inode_bitmap(group)?;
+            let (mut free_in_group, mut dirs) = (0, 0);
+            for index in 0..bitmap.len() {
+                let inode = group as u32 * inodes_per_group + index as u32 + 1;
+                let i = inode as usize - 1;
+                match (self.used[i], bitmap.get(index)) {
+                    (true, false) => {
+                        self.findings.push(Finding::InodeMarkedFree { inode })
+                    }
+                    (false, true) => {
+                        self.findings.push(Finding::InodeMarkedUsed { inode })
+                    }
+                    _ => (),
+                }
+                if !self.used[i] {
+                    free_in_group += 1;
+                    continue;
+                }
+                if self.dirs[i] {
+                    dirs += 1;
+                }
+                // inodes no entry leads to have been reported already
+                let recorded = self.recorded[i];
+                if self.links[i] != 0 && recorded as u32 != self.links[i] {
+                    self.findings.push(Finding::WrongLinkCount {
+                        inode,
+                        recorded,
+                        counted: self.links[i],
+                    });
+                }
+            }
+
+            let descriptor = self.fs.block_groups()[group];
+            let group = group as u32;
+            if descriptor.free_blocks_count as u32 != free {
+                self.findings.push(Finding::WrongFreeBlocks {
+                    group: Some(group),
+                    recorded: descriptor.free_blocks_count as u32,
+                    counted: free,
+                });
+            }
+            if descriptor.free_inodes_count as u32 != free_in_group {
+                self.findings.push(Finding::WrongFreeInodes {
+                    group: Some(group),
+                    recorded: descriptor.free_inodes_count as u32,
+                    counted: free_in_group,
+                });
+            }
+            if descriptor.dirs_count as u32 != dirs {
+                self.findings.push(Finding::WrongDirsCount {
+                    group,
+                    recorded: descriptor.dirs_count as u32,
+                    counted: dirs,
+                });
+            }
+            free_blocks += free;
+            free_inodes += free_in_group;
+        }
+
+        if superblock.free_blocks_count != free_blocks {
+            self.findings.push(Finding::WrongFreeBlocks {
+                group: None,
+                recorded: superblock.free_blocks_count,
+                counted: free_blocks,
+            });
+        }
+        if superblock.free_inodes_count != free_inodes {
+            self.findings.push(Finding::WrongFreeInodes {
+                group: None,
+                recorded: superblock.free_inodes_count,
+                counted: free_inodes,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::sync::tests::{debugfs, fsck, mkfs};
+    use fs::Ext2;
+    use sector::Size512;
+    use sys::superblock::{FS_CLEAN, FS_ERR};
+
+    use super::{check, repair, Finding};
+
+    fn open(volume: Vec<u8>) -> Ext2<Size512, Vec<u8>> {
+        Ext2::new(volume).unwrap()
+    }
+
+    #[test]
+    fn clean() {
+        for path in &[
+            "ext2.img",
+            "fixtures/devices.img",
+            "fixtures/htree.img",
+            "fixtures/inode256.img",
+            "fixtures/large.img",
+            "fixtures/latin1.img",
+            "fixtures/rev0.img",
+            "fixtures/xattr.img",
+        ] {
+            let fs = open(std::fs::read(path).unwrap());
+            assert_eq!(check(&fs).unwrap().findings, vec![], "{}", path);
+        }
+        // several groups, backups and a resize inode
+        if let Some(volume) = mkfs(20 << 20) {
+            assert!(check(&open(volume)).unwrap().is_clean());
+        }
+    }
+
+    #[test]
+    fn corrupted() {
+        // on ext2.img, the root directory is in block 148 and README.md,
+        // inode 14, in block 162; block 163 is the first free one
+        let table: &[(&[&str], Vec<Finding>)] = &[
+            (
+                &["freeb 162"],
+                vec![Finding::BlockMarkedFree { block: 162 }],
+            ),
+            (&["setb 163"], vec![Finding::BlockMarkedUsed { block: 163 }]),
+            (
+                &["freei <14>"],
+                vec![Finding::InodeMarkedFree { inode: 14 }],
+            ),
+            (&["seti <20>"], vec![Finding::InodeMarkedUsed { inode: 20 }]),
+            (
+                &["set_bg 0 free_blocks_count 3000", "ssv free_inodes_count 7"],
+                vec![
+                    Finding::WrongFreeBlocks {
+                        group: Some(0),
+                        recorded: 3000,
+                        counted: 3388,
+                    },
+                    Finding::WrongFreeInodes {
+                        group: None,
+                        recorded: 7,
+                        counted: 1008,
+                    },
+                ],
+            ),
+            (
+                &["sif <14> block[1] 148"],
+                vec![Finding::BlockClaimedTwice {
+                    block: 148,
+                    first: Some(2),
+                    second: Some(14),
+                }],
+            ),
+            (
+                &["sif <14> block[1] 1"],
+                vec![Finding::BlockClaimedTwice {
+                    block: 1,
+                    first: None,
+                    second: Some(14),
+                }],
+            ),
+            (
+                &["set_bg 0 used_dirs_count 5"],
+                vec![Finding::WrongDirsCount {
+                    group: 0,
+                    recorded: 5,
+                    counted: 4,
+                }],
+            ),
+            (
+                &["unlink /home/funky/README.md"],
+                vec![Finding::UnreachableInode {
+                    inode: 14,
+                    links: 1,
+                }],
+            ),
+            (
+                &["sif <14> links_count 3"],
+                vec![Finding::WrongLinkCount {
+                    inode: 14,
+                    recorded: 3,
+                    counted: 1,
+                }],
+            ),
+            (
+                &["sif <14> block[1] 5000"],
+                vec![Finding::Corrupt {
+                    inode: 14,
+                    reason: "block pointer outside the filesystem",
+                }],
+            ),
+        ];
+        let image = std::fs::read("ext2.img").unwrap();
+        for &(requests, ref expected) in table {
+            let volume = match debugfs(&image, requests) {
+                Some(volume) => volume,
+                None => return,
+            };
+            let report = check(&open(volume)).unwrap();
+            assert_eq!(&report.findings, expected, "{:?}", requests);
+        }
+    }
+
+    #[test]
+    fn repairs() {
+        // the requests, and what's left after repairing
+        let table: &[(&[&str], Vec<Finding>)] = &[
+            (
+                &["freeb 162", "setb 163", "freei <14>", "seti <20>"],
+                vec![],
+            ),
+            (
+                &[
+                    "set_bg 0 free_blocks_count 3000",
+                    "set_bg 0 used_dirs_count 9",
+                    "ssv free_inodes_count 7",
+                ],
+                vec![],
+            ),
+            // an orphan: its inode and blocks are freed
+            (
+                &["unlink /home/funky/README.md", "sif <14> links_count 0"],
+                vec![],
+            ),
+            (
+                &["sif <14> block[1] 148", "setb 163"],
+                vec![Finding::BlockClaimedTwice {
+                    block: 148,
+                    first: Some(2),
+                    second: Some(14),
+                }],
+            ),
+            (
+                &["unlink /home/funky/README.md"],
+                vec![Finding::UnreachableInode {
+                    inode: 14,
+                    links: 1,
+                }],
+            ),
+        ];
+        let image = std::fs::read("ext2.img").unwrap();
+        for &(requests, ref left) in table {
+            let mut volume = match debugfs(&image, requests) {
+                Some(volume) => volume,
+                None => return,
+            };
+            // marked as having errors, as a kernel finding them would
+            volume[1024 + 58] = (FS_CLEAN | FS_ERR) as u8;
+            let mut fs = open(volume);
+            fs.set_clock(|| 0x5b000000);
+
+            let report = check(&fs).unwrap();
+            assert!(!report.is_clean());
+            assert_eq!(&repair(&mut fs, &report).unwrap().findings, left);
+            assert_eq!(&check(&fs).unwrap().findings, left);
+            // once more changes nothing
+            let repaired = fs.volume.clone();
+            assert_eq!(&repair(&mut fs, &report).unwrap().findings, left);
+            assert!(fs.volume == repaired, "{:?}", requests);
+
+            let state = fs.superblock().state();
+            if left.is_empty() {
+                assert_eq!(state, FS_CLEAN);
+                assert!(fsck(&fs.volume), "{:?}", requests);
+            } else {
+                assert_eq!(state, FS_CLEAN | FS_ERR);
+            }
+        }
+    }
+}
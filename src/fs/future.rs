@@ -0,0 +1,959 @@
+//! A file system mounted read-only on an `AsyncVolume`, for kernels whose
+//! drivers don't block.
+//!
+//! Every operation is written without I/O: it asks for one read at a time
+//! and is resumed with the bytes read, and a future drives it by polling the
+//! volume. Nothing but `core::future` is assumed of the executor. Blocks of
+//! files are mapped along the same `BlockPath`s as on the blocking side.
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+use sys::block_group::BlockGroupDescriptor;
+use sys::dirent::DirEntryIter;
+use sys::inode::{BlockPath, FileType, Inode as RawInode};
+use sys::superblock::{FeaturesROnly, Superblock, EXT2_MAGIC};
+use volume::future::AsyncVolume;
+
+use super::sync::check_absolute;
+use super::{check_descriptors, descriptor_table, Metadata};
+
+/// Inode number of the root directory
+const ROOT: u32 = 2;
+
+/// A file system on an `AsyncVolume`, mounted read-only by `mount`
+pub struct AsyncExt2<S: SectorSize, V: AsyncVolume<u8, S>> {
+    volume: V,
+    layout: Layout,
+    _phantom: PhantomData<S>,
+}
+
+/// What operations need to know of the file system
+struct Layout {
+    superblock: Superblock,
+    block_groups: Vec<BlockGroupDescriptor>,
+}
+
+impl Layout {
+    fn log_block_size(&self) -> u32 {
+        self.superblock.log_block_size + 10
+    }
+
+    fn block_size(&self) -> u64 {
+        1 << self.log_block_size()
+    }
+
+    fn large_files(&self) -> bool {
+        self.superblock
+            .features_ronly()
+            .contains(FeaturesROnly::RONLY_FILE_SIZE_64)
+    }
+
+    /// The address of byte `offset` of block `block`, which has to be within
+    /// the file system, as it may come from a corrupt pointer
+    fn block<S: SectorSize>(
+        &self,
+        block: u32,
+        offset: u64,
+    ) -> Result<Address<S>> {
+        let superblock = &self.superblock;
+        if block < superblock.first_data_block
+            || block >= superblock.blocks_count
+        {
+            return Err(Error::Corrupt {
+                reason: "block pointer outside the filesystem",
+            });
+        }
+        Ok(Address::with_block_size(
+            block,
+            offset as i32,
+            self.log_block_size(),
+        ))
+    }
+
+    /// The address of inode `inode` in its group's inode table
+    fn inode<S: SectorSize>(&self, inode: u32) -> Result<Address<S>> {
+        let (group, index) = self
+            .superblock
+            .inode_group_and_index(inode)
+            .ok_or(Error::InodeNotFound { inode })?;
+        let size = self.superblock.inode_size() as u64;
+        let table = Address::with_block_size(
+            self.block_groups[group as usize].inode_table_block,
+            0,
+            self.log_block_size(),
+        );
+        table
+            .checked_add(Address::from(index as u64 * size))
+            .ok_or(Error::ArithmeticOverflow)
+    }
+}
+
+/// What an operation does next
+enum Step<S: SectorSize, T> {
+    /// Reads `len` bytes from the address, to be resumed with
+    Read(Address<S>, usize),
+    Done(T),
+}
+
+/// An operation on the file system that reads the volume only by asking
+/// for it
+trait Operation<S: SectorSize> {
+    type Output;
+
+    /// Starts the operation if `bytes` is `None`, or resumes it with the
+    /// bytes of the read it last asked for
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        bytes: Option<&[u8]>,
+    ) -> Result<Step<S, Self::Output>>;
+}
+
+/// Reads an inode from its inode table
+struct ReadInode {
+    inode: u32,
+}
+
+impl<S: SectorSize> Operation<S> for ReadInode {
+    type Output = RawInode;
+
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        bytes: Option<&[u8]>,
+    ) -> Result<Step<S, RawInode>> {
+        match bytes {
+            None => {
+                let size = layout.superblock.inode_size() as usize;
+                if size < 128 {
+                    return Err(Error::BadInodeSize { size });
+                }
+                Ok(Step::Read(layout.inode(self.inode)?, 128))
+            }
+            Some(bytes) => {
+                let mut raw = [0; 128];
+                raw.copy_from_slice(&bytes[..128]);
+                Ok(Step::Done(RawInode::from_bytes(&raw)))
+            }
+        }
+    }
+}
+
+/// Maps a block of a file to the block holding it on the volume, `None` for
+/// a hole, reading the indirect blocks on the way
+struct MapBlock {
+    path: Option<BlockPath>,
+    /// Levels of indirect blocks gone through
+    level: usize,
+}
+
+impl MapBlock {
+    fn new(raw: &RawInode, index: u64, layout: &Layout) -> MapBlock {
+        MapBlock {
+            path: BlockPath::new(raw, index, layout.log_block_size()),
+            level: 0,
+        }
+    }
+}
+
+impl<S: SectorSize> Operation<S> for MapBlock {
+    type Output = Option<u32>;
+
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        bytes: Option<&[u8]>,
+    ) -> Result<Step<S, Option<u32>>> {
+        let path = match self.path {
+            Some(path) => path,
+            None => return Ok(Step::Done(None)),
+        };
+        let block = match bytes {
+            None => path.root,
+            Some(bytes) => {
+                self.level += 1;
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+        };
+        if block == 0 {
+            return Ok(Step::Done(None));
+        }
+        match path.indices().get(self.level) {
+            Some(&index) => {
+                Ok(Step::Read(layout.block(block, index as u64 * 4)?, 4))
+            }
+            None => {
+                layout.block::<S>(block, 0).map(|_| Step::Done(Some(block)))
+            }
+        }
+    }
+}
+
+/// Goes through the blocks of a directory
+struct DirBlocks {
+    dir: RawInode,
+    /// Index of the block being read
+    index: u64,
+    blocks: u64,
+    map: MapBlock,
+    /// Whether the block has been mapped, and is being read
+    mapped: bool,
+}
+
+impl DirBlocks {
+    fn new(dir: RawInode, layout: &Layout) -> DirBlocks {
+        let size = dir.size_with(layout.large_files());
+        DirBlocks {
+            map: MapBlock::new(&dir, 0, layout),
+            blocks: size.div_ceil(layout.block_size()),
+            index: 0,
+            dir,
+            mapped: false,
+        }
+    }
+
+    /// Reads the next block of the directory, resumed with the contents of
+    /// each block read. Done with `true` once the contents of a block are
+    /// in, and with `false` past the last one.
+    fn resume<S: SectorSize>(
+        &mut self,
+        layout: &Layout,
+        mut bytes: Option<&[u8]>,
+    ) -> Result<Step<S, bool>> {
+        loop {
+            if self.index >= self.blocks {
+                return Ok(Step::Done(false));
+            }
+            if self.mapped {
+                if bytes.is_some() {
+                    return Ok(Step::Done(true));
+                }
+                // the block was handled, on to the next one
+                self.mapped = false;
+                self.next(layout);
+                continue;
+            }
+            match self.map.resume(layout, bytes.take())? {
+                Step::Read(start, len) => return Ok(Step::Read(start, len)),
+                Step::Done(Some(block)) => {
+                    self.mapped = true;
+                    let start = layout.block(block, 0)?;
+                    return Ok(Step::Read(start, layout.block_size() as usize));
+                }
+                // holes hold no entries
+                Step::Done(None) => self.next(layout),
+            }
+        }
+    }
+
+    fn next(&mut self, layout: &Layout) {
+        self.index += 1;
+        self.map = MapBlock::new(&self.dir, self.index, layout);
+    }
+}
+
+/// Resolves an absolute path to its inode, following no symlinks
+struct Lookup {
+    path: Vec<u8>,
+    /// The component being looked up
+    start: usize,
+    end: usize,
+    /// The inode the path led to so far
+    inode: u32,
+    state: LookupState,
+}
+
+enum LookupState {
+    Inode(ReadInode),
+    Dir(Box<DirBlocks>),
+}
+
+impl Lookup {
+    fn new(path: &[u8]) -> Lookup {
+        Lookup {
+            path: path.to_vec(),
+            start: 0,
+            end: 0,
+            inode: ROOT,
+            state: LookupState::Inode(ReadInode { inode: ROOT }),
+        }
+    }
+
+    /// Moves on to the next component, returning whether there's one
+    fn next_component(&mut self) -> bool {
+        while self.end < self.path.len() {
+            self.start = self.end + 1;
+            self.end = self.path[self.start..]
+                .iter()
+                .position(|&byte| byte == b'/')
+                .map_or(self.path.len(), |len| self.start + len);
+            let name = &self.path[self.start..self.end];
+            if !name.is_empty() && name != b"." {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl<S: SectorSize> Operation<S> for Lookup {
+    type Output = (u32, RawInode);
+
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        mut bytes: Option<&[u8]>,
+    ) -> Result<Step<S, (u32, RawInode)>> {
+        loop {
+            match self.state {
+                LookupState::Inode(ref mut read) => {
+                    let raw = match read.resume(layout, bytes.take())? {
+                        Step::Read(start, len) => {
+                            return Ok(Step::Read(start, len))
+                        }
+                        Step::Done(raw) => raw,
+                    };
+                    if !self.next_component() {
+                        return Ok(Step::Done((self.inode, raw)));
+                    }
+                    if raw.type_perm.file_type() != FileType::Directory {
+                        return Err(Error::NotADirectory {
+                            inode: self.inode,
+                            name: lossy(&self.path[..self.start - 1]),
+                        });
+                    }
+                    self.state =
+                        LookupState::Dir(Box::new(DirBlocks::new(raw, layout)));
+                }
+                LookupState::Dir(ref mut blocks) => {
+                    let block = bytes.take();
+                    match blocks.resume(layout, block)? {
+                        Step::Read(start, len) => {
+                            return Ok(Step::Read(start, len))
+                        }
+                        Step::Done(false) => {
+                            return Err(Error::NotFound {
+                                name: lossy(&self.path[..self.end]),
+                                parent: self.inode,
+                            })
+                        }
+                        Step::Done(true) => (),
+                    }
+                    let name = &self.path[self.start..self.end];
+                    let features = layout.superblock.features_req();
+                    let mut found = None;
+                    for entry in DirEntryIter::new(block.unwrap(), features) {
+                        let entry = entry?;
+                        if entry.header.in_use() && entry.name == name {
+                            found = Some(entry.header.inode);
+                            break;
+                        }
+                    }
+                    if let Some(inode) = found {
+                        self.inode = inode;
+                        self.state = LookupState::Inode(ReadInode { inode });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lists a directory, looked up first
+struct ListDir {
+    lookup: Lookup,
+    blocks: Option<DirBlocks>,
+    entries: Vec<Entry>,
+}
+
+impl<S: SectorSize> Operation<S> for ListDir {
+    type Output = Vec<Entry>;
+
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        mut bytes: Option<&[u8]>,
+    ) -> Result<Step<S, Vec<Entry>>> {
+        loop {
+            let blocks = match self.blocks {
+                Some(ref mut blocks) => blocks,
+                None => {
+                    let (inode, raw) =
+                        match self.lookup.resume(layout, bytes.take())? {
+                            Step::Read(start, len) => {
+                                return Ok(Step::Read(start, len))
+                            }
+                            Step::Done(found) => found,
+                        };
+                    if raw.type_perm.file_type() != FileType::Directory {
+                        return Err(Error::NotADirectory {
+                            inode,
+                            name: lossy(&self.lookup.path),
+                        });
+                    }
+                    self.blocks = Some(DirBlocks::new(raw, layout));
+                    continue;
+                }
+            };
+            let block = bytes.take();
+            match blocks.resume(layout, block)? {
+                Step::Read(start, len) => return Ok(Step::Read(start, len)),
+                Step::Done(false) => {
+                    let entries = core::mem::take(&mut self.entries);
+                    return Ok(Step::Done(entries));
+                }
+                Step::Done(true) => (),
+            }
+            let features = layout.superblock.features_req();
+            for entry in DirEntryIter::new(block.unwrap(), features) {
+                let entry = entry?;
+                if entry.header.in_use() {
+                    self.entries.push(Entry {
+                        name: entry.name.to_vec(),
+                        ino: entry.header.inode,
+                        file_type: entry.header.file_type,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Reads `position..end` of a file, holes as zeroes
+struct ReadRange {
+    raw: RawInode,
+    position: u64,
+    end: u64,
+    out: Vec<u8>,
+    map: Option<MapBlock>,
+}
+
+impl ReadRange {
+    fn new(raw: RawInode, position: u64, end: u64) -> ReadRange {
+        ReadRange {
+            raw,
+            position,
+            end,
+            out: Vec::new(),
+            map: None,
+        }
+    }
+}
+
+impl<S: SectorSize> Operation<S> for ReadRange {
+    type Output = Vec<u8>;
+
+    fn resume(
+        &mut self,
+        layout: &Layout,
+        mut bytes: Option<&[u8]>,
+    ) -> Result<Step<S, Vec<u8>>> {
+        let block_size = layout.block_size();
+        let fast_symlink = self.raw.type_perm.file_type() == FileType::Symlink
+            && self.raw.sectors_count == 0;
+        if fast_symlink {
+            // the target is kept in place of the block pointers
+            let bytes = self.raw.to_bytes();
+            let end = self.end.min(60) as usize;
+            let start = self.position.min(end as u64) as usize;
+            return Ok(Step::Done(bytes[40..][start..end].to_vec()));
+        }
+        loop {
+            let index = self.position / block_size;
+            // up to the end of the block, or of the range
+            let len = (block_size - self.position % block_size)
+                .min(self.end.saturating_sub(self.position));
+            if len == 0 {
+                return Ok(Step::Done(core::mem::take(&mut self.out)));
+            }
+            let map = match self.map {
+                Some(ref mut map) => map,
+                None => {
+                    if let Some(bytes) = bytes.take() {
+                        self.out.extend_from_slice(bytes);
+                        self.position += len;
+                        self.map = Some(MapBlock::new(
+                            &self.raw,
+                            self.position / block_size,
+                            layout,
+                        ));
+                    } else {
+                        self.map =
+                            Some(MapBlock::new(&self.raw, index, layout));
+                    }
+                    continue;
+                }
+            };
+            match map.resume(layout, bytes.take())? {
+                Step::Read(start, len) => return Ok(Step::Read(start, len)),
+                Step::Done(Some(block)) => {
+                    self.map = None;
+                    let start =
+                        layout.block(block, self.position % block_size)?;
+                    return Ok(Step::Read(start, len as usize));
+                }
+                Step::Done(None) => {
+                    let filled = self.out.len() + len as usize;
+                    self.out.resize(filled, 0);
+                    self.position += len;
+                    self.map = Some(MapBlock::new(
+                        &self.raw,
+                        self.position / block_size,
+                        layout,
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Drives an operation to completion by polling the volume for each read
+/// it asks for
+struct Drive<'a, S: SectorSize, V: 'a + AsyncVolume<u8, S>, O> {
+    fs: &'a AsyncExt2<S, V>,
+    op: O,
+    read: Option<(Address<S>, Vec<u8>)>,
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>, O: Operation<S>>
+    Drive<'a, S, V, O>
+{
+    fn new(fs: &'a AsyncExt2<S, V>, op: O) -> Drive<'a, S, V, O> {
+        Drive { fs, op, read: None }
+    }
+
+    fn poll_op(&mut self, cx: &mut Context<'_>) -> Poll<Result<O::Output>> {
+        let layout = &self.fs.layout;
+        loop {
+            let step = match self.read.take() {
+                Some((start, mut buf)) => {
+                    match self.fs.volume.poll_read(cx, start, &mut buf) {
+                        Poll::Pending => {
+                            self.read = Some((start, buf));
+                            return Poll::Pending;
+                        }
+                        Poll::Ready(Err(err)) => {
+                            return Poll::Ready(Err(err.into()))
+                        }
+                        Poll::Ready(Ok(())) => {
+                            self.op.resume(layout, Some(&buf))
+                        }
+                    }
+                }
+                None => self.op.resume(layout, None),
+            };
+            match step {
+                Ok(Step::Read(start, len)) => {
+                    self.read = Some((start, vec![0; len]))
+                }
+                Ok(Step::Done(output)) => return Poll::Ready(Ok(output)),
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+}
+
+impl<S: SectorSize, V: AsyncVolume<u8, S>> AsyncExt2<S, V> {
+    /// Mounts the file system on `volume` read-only, checking it as
+    /// `Ext2::new` does
+    pub fn mount(volume: V) -> Mount<S, V> {
+        Mount {
+            volume: Some(volume),
+            superblock: None,
+            read: None,
+        }
+    }
+
+    pub fn superblock(&self) -> &Superblock {
+        &self.layout.superblock
+    }
+
+    pub fn block_groups(&self) -> &[BlockGroupDescriptor] {
+        &self.layout.block_groups
+    }
+
+    pub fn volume(&self) -> &V {
+        &self.volume
+    }
+
+    /// Opens the file at `abs_path`. Symlinks aren't followed: one ending
+    /// the path is opened itself, and one on the way fails with
+    /// `Error::NotADirectory`.
+    pub fn open<P: AsRef<[u8]>>(&self, abs_path: P) -> Open<'_, S, V> {
+        let abs_path = abs_path.as_ref();
+        Open {
+            refused: check_absolute(abs_path).err(),
+            drive: Drive::new(self, Lookup::new(abs_path)),
+        }
+    }
+
+    /// Lists the directory at `abs_path`, `.` and `..` included, following
+    /// no symlinks like `open`
+    pub fn read_dir<P: AsRef<[u8]>>(&self, abs_path: P) -> ReadDir<'_, S, V> {
+        let abs_path = abs_path.as_ref();
+        let op = ListDir {
+            lookup: Lookup::new(abs_path),
+            blocks: None,
+            entries: Vec::new(),
+        };
+        ReadDir {
+            refused: check_absolute(abs_path).err(),
+            drive: Drive::new(self, op),
+        }
+    }
+}
+
+/// Future of `AsyncExt2::mount`
+pub struct Mount<S: SectorSize, V: AsyncVolume<u8, S>> {
+    volume: Option<V>,
+    superblock: Option<Superblock>,
+    read: Option<(Address<S>, Vec<u8>)>,
+}
+
+impl<S: SectorSize, V: AsyncVolume<u8, S>> Mount<S, V> {
+    /// Takes in the bytes read, returning the next read or the file system
+    fn step(&mut self, bytes: &[u8]) -> Result<Option<AsyncExt2<S, V>>> {
+        let superblock = match self.superblock.take() {
+            Some(superblock) => superblock,
+            None => {
+                let mut raw = [0; 1024];
+                raw.copy_from_slice(bytes);
+                let mut superblock = Superblock::from_bytes(&raw);
+                if superblock.magic != EXT2_MAGIC {
+                    return Err(Error::BadMagic {
+                        magic: superblock.magic,
+                    });
+                }
+                superblock.block_group = 0;
+                let (start, count) = descriptor_table::<S>(&superblock, 0)?;
+                self.read = Some((start, vec![0; count * 32]));
+                self.superblock = Some(superblock);
+                return Ok(None);
+            }
+        };
+        let block_groups = bytes
+            .chunks(32)
+            .map(|bytes| {
+                let mut raw = [0; 32];
+                raw.copy_from_slice(bytes);
+                BlockGroupDescriptor::from_bytes(&raw)
+            })
+            .collect::<Vec<_>>();
+        let volume = self.volume.take().expect("mount polled after completion");
+        check_descriptors(&superblock, &block_groups, volume.size())?;
+        let unsupported = superblock.unsupported_features_req();
+        if !unsupported.is_empty() {
+            return Err(Error::UnsupportedFeature {
+                incompat: unsupported.bits(),
+            });
+        }
+        Ok(Some(AsyncExt2 {
+            volume,
+            layout: Layout {
+                superblock,
+                block_groups,
+            },
+            _phantom: PhantomData,
+        }))
+    }
+}
+
+impl<S: SectorSize, V: AsyncVolume<u8, S>> Future for Mount<S, V> {
+    type Output = Result<AsyncExt2<S, V>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let (start, mut buf) = match this.read.take() {
+                Some(read) => read,
+                None => (Address::from(1024_usize), vec![0; 1024]),
+            };
+            let volume =
+                this.volume.as_ref().expect("mount polled after completion");
+            match volume.poll_read(cx, start, &mut buf) {
+                Poll::Pending => {
+                    this.read = Some((start, buf));
+                    return Poll::Pending;
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Ready(Ok(())) => match this.step(&buf) {
+                    Ok(Some(fs)) => return Poll::Ready(Ok(fs)),
+                    Ok(None) => (),
+                    Err(err) => return Poll::Ready(Err(err)),
+                },
+            }
+        }
+    }
+}
+
+impl<S: SectorSize, V: AsyncVolume<u8, S>> Unpin for Mount<S, V> {}
+
+/// Future of `AsyncExt2::open`
+pub struct Open<'a, S: SectorSize, V: 'a + AsyncVolume<u8, S>> {
+    /// Why the path was refused, if it was
+    refused: Option<Error>,
+    drive: Drive<'a, S, V, Lookup>,
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>> Future for Open<'a, S, V> {
+    type Output = Result<AsyncFile<'a, S, V>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(err) = this.refused.take() {
+            return Poll::Ready(Err(err));
+        }
+        let fs = this.drive.fs;
+        this.drive.poll_op(cx).map_ok(|(inode, raw)| AsyncFile {
+            fs,
+            inode,
+            raw,
+            position: 0,
+        })
+    }
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>> Unpin for Open<'a, S, V> {}
+
+/// Future of `AsyncExt2::read_dir`
+pub struct ReadDir<'a, S: SectorSize, V: 'a + AsyncVolume<u8, S>> {
+    /// Why the path was refused, if it was
+    refused: Option<Error>,
+    drive: Drive<'a, S, V, ListDir>,
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>> Future for ReadDir<'a, S, V> {
+    type Output = Result<Vec<Entry>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(err) = this.refused.take() {
+            return Poll::Ready(Err(err));
+        }
+        this.drive.poll_op(cx)
+    }
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>> Unpin for ReadDir<'a, S, V> {}
+
+/// An entry `AsyncExt2::read_dir` found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The name as stored, which needn't be UTF-8
+    pub name: Vec<u8>,
+    pub ino: u32,
+    /// The type of the inode, if the file system records it in entries
+    pub file_type: Option<FileType>,
+}
+
+/// A file opened by `AsyncExt2::open`, read from a position that moves
+/// along
+pub struct AsyncFile<'a, S: SectorSize, V: 'a + AsyncVolume<u8, S>> {
+    fs: &'a AsyncExt2<S, V>,
+    inode: u32,
+    raw: RawInode,
+    position: u64,
+}
+
+impl<'a, S: SectorSize, V: AsyncVolume<u8, S>> AsyncFile<'a, S, V> {
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(self.inode, &self.raw, self.fs.layout.large_files())
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves the position to `position`, which may be past the end
+    pub fn seek(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Reads from the position into `buf`, moving the position past what
+    /// was read, and returns how many bytes that was: fewer than `buf`
+    /// holds only at the end of the file
+    pub fn read<'f>(&'f mut self, buf: &'f mut [u8]) -> FileRead<'f, 'a, S, V> {
+        let end = self.position.saturating_add(buf.len() as u64);
+        FileRead::new(self, end, Output::Slice(buf))
+    }
+
+    /// Reads from the position to the end of the file, appending to `buf`,
+    /// and returns how many bytes were read
+    pub fn read_to_end<'f>(
+        &'f mut self,
+        buf: &'f mut Vec<u8>,
+    ) -> FileRead<'f, 'a, S, V> {
+        FileRead::new(self, u64::MAX, Output::Vec(buf))
+    }
+}
+
+/// Where a `FileRead` puts what it read
+enum Output<'f> {
+    Slice(&'f mut [u8]),
+    Vec(&'f mut Vec<u8>),
+}
+
+/// Future of `AsyncFile::read` and `AsyncFile::read_to_end`
+pub struct FileRead<'f, 'a: 'f, S: SectorSize, V: 'a + AsyncVolume<u8, S>> {
+    position: &'f mut u64,
+    output: Output<'f>,
+    drive: Drive<'a, S, V, ReadRange>,
+}
+
+impl<'f, 'a, S: SectorSize, V: AsyncVolume<u8, S>> FileRead<'f, 'a, S, V> {
+    fn new(
+        file: &'f mut AsyncFile<'a, S, V>,
+        end: u64,
+        output: Output<'f>,
+    ) -> FileRead<'f, 'a, S, V> {
+        let size = file.raw.size_with(file.fs.layout.large_files());
+        let op = ReadRange::new(file.raw, file.position, end.min(size));
+        FileRead {
+            drive: Drive::new(file.fs, op),
+            position: &mut file.position,
+            output,
+        }
+    }
+}
+
+impl<'f, 'a, S: SectorSize, V: AsyncVolume<u8, S>> Future
+    for FileRead<'f, 'a, S, V>
+{
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let read = match this.drive.poll_op(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Ready(Ok(read)) => read,
+        };
+        match this.output {
+            Output::Slice(ref mut buf) => {
+                buf[..read.len()].copy_from_slice(&read)
+            }
+            Output::Vec(ref mut buf) => buf.extend_from_slice(&read),
+        }
+        *this.position += read.len() as u64;
+        Poll::Ready(Ok(read.len()))
+    }
+}
+
+impl<'f, 'a, S: SectorSize, V: AsyncVolume<u8, S>> Unpin
+    for FileRead<'f, 'a, S, V>
+{
+}
+
+fn lossy(name: &[u8]) -> String {
+    String::from_utf8_lossy(name).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std;
+
+    use error::Error;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::Size512;
+    use volume::future::tests::{block_on, Stalling};
+    use volume::future::Blocking;
+
+    use super::AsyncExt2;
+
+    fn image() -> Vec<u8> {
+        std::fs::read("ext2.img").unwrap()
+    }
+
+    /// The contents of `path` as read by the blocking side
+    fn read_sync(path: &str) -> Vec<u8> {
+        let fs = Synced::<Ext2<Size512, _>>::new(image()).unwrap();
+        let mut content = Vec::new();
+        fs.lookup(path).unwrap().read_to_end(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn read() {
+        let volume = Blocking::new(image());
+        let fs = block_on(AsyncExt2::<Size512, _>::mount(volume)).unwrap();
+        assert_eq!({ fs.superblock().rev_major }, 1);
+        assert_eq!(fs.block_groups().len(), 1);
+
+        let mut readme = block_on(fs.open("/home/funky/README.md")).unwrap();
+        assert_eq!(readme.metadata().ino(), 14);
+        let mut buf = [0; 64];
+        let read = block_on(readme.read(&mut buf)).unwrap();
+        assert_eq!(read, 31);
+        assert_eq!(&buf[..read], &read_sync("/home/funky/README.md")[..]);
+        assert_eq!(block_on(readme.read(&mut buf)).unwrap(), 0);
+
+        readme.seek(27);
+        let mut rest = Vec::new();
+        assert_eq!(block_on(readme.read_to_end(&mut rest)).unwrap(), 4);
+        assert_eq!(&rest[..], &buf[27..31]);
+
+        // through the doubly indirect block
+        let mut unl = block_on(fs.open("/home/funky/unl")).unwrap();
+        let mut content = Vec::new();
+        block_on(unl.read_to_end(&mut content)).unwrap();
+        assert_eq!(content.len(), 537600);
+        assert!(content == read_sync("/home/funky/unl"));
+    }
+
+    #[test]
+    fn read_dir() {
+        let volume = Blocking::new(image());
+        let fs = block_on(AsyncExt2::<Size512, _>::mount(volume)).unwrap();
+        let sync = Synced::<Ext2<Size512, _>>::new(image()).unwrap();
+        let dir = sync.lookup(b"/home/funky").unwrap();
+        let expected = dir
+            .directory()
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect::<Vec<_>>();
+
+        let entries = block_on(fs.read_dir("/home/funky")).unwrap();
+        let names = entries
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        assert!(names.iter().any(|name| name == b"README.md"));
+
+        match block_on(fs.read_dir("/home/funky/README.md")) {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match block_on(fs.open("/home/funky/missing")) {
+            Err(Error::NotFound { parent, .. }) => assert_eq!(parent, 13),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        match block_on(fs.open("home")) {
+            Err(Error::NotAbsolute { .. }) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn pending() {
+        let volume = Stalling::new(Blocking::new(image()));
+        let fs = block_on(AsyncExt2::<Size512, _>::mount(volume)).unwrap();
+        let mut readme = block_on(fs.open("/home/funky/README.md")).unwrap();
+        let mut content = Vec::new();
+        block_on(readme.read_to_end(&mut content)).unwrap();
+        assert_eq!(content, read_sync("/home/funky/README.md"));
+        // every read was polled twice
+        assert_eq!(fs.volume().polls.get() % 2, 0);
+        assert!(fs.volume().polls.get() > 2);
+    }
+}
@@ -0,0 +1,609 @@
+//! Formatting of blank volumes
+
+use core::mem;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+use sys::bitmap::Bitmap;
+use sys::block_group::BlockGroupDescriptor;
+use sys::dirent::dirent_len;
+use sys::inode::{self, FileType, Inode, Permissions, TypePerm};
+use sys::superblock::{
+    FeaturesROnly, FeaturesRequired, Superblock, ERR_IGNORE, EXT2_MAGIC,
+    FS_CLEAN, OS_LINUX,
+};
+use volume::{Volume, VolumeCommit};
+
+use super::sync::write_dirent;
+
+/// Inode of the root directory
+pub(crate) const ROOT_INODE: u32 = 2;
+/// First inode not reserved for the filesystem itself, which is where the
+/// lost+found directory goes
+const FIRST_INODE: u32 = 11;
+/// Size of the inodes in the inode tables
+const INODE_SIZE: usize = 128;
+/// Bytes worth of blocks given to lost+found up front, so that `e2fsck` can
+/// reconnect some inodes without having to allocate. This fits in the direct
+/// blocks whatever the block size.
+const LOST_AND_FOUND_SIZE: usize = 12 * 1024;
+
+/// Formats a volume as an empty ext2 filesystem, holding just the root
+/// directory and lost+found.
+///
+/// The filesystem is a revision 1 one with 128-byte inodes, typed directory
+/// entries and sparse superblock backups, much like what `mke2fs -t ext2`
+/// produces.
+///
+/// ```
+/// # use ext2::fs::mkfs::Builder;
+/// # use ext2::fs::sync::Synced;
+/// # use ext2::fs::Ext2;
+/// # use ext2::sector::Size512;
+/// let mut volume = vec![0_u8; 4 << 20];
+/// Builder::new().label(b"disk").build::<Size512, _>(&mut volume).unwrap();
+/// let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+/// assert!(fs.lookup(b"/lost+found").unwrap().is_dir());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Builder {
+    block_size: usize,
+    blocks: Option<u32>,
+    inodes: Option<u32>,
+    bytes_per_inode: usize,
+    reserved_percent: u32,
+    label: [u8; 16],
+    fs_id: [u8; 16],
+    time: u32,
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    /// Returns a builder for filesystems with 1 KiB blocks, an inode for
+    /// every 8 KiB and 5% of the blocks reserved for the superuser
+    pub fn new() -> Builder {
+        Builder {
+            block_size: 1024,
+            blocks: None,
+            inodes: None,
+            bytes_per_inode: 8192,
+            reserved_percent: 5,
+            label: [0; 16],
+            fs_id: [0; 16],
+            time: 0,
+        }
+    }
+
+    /// Sets the block size, which must be 1024, 2048 or 4096 bytes
+    pub fn block_size(&mut self, size: usize) -> &mut Builder {
+        self.block_size = size;
+        self
+    }
+
+    /// Sets the number of blocks of the filesystem, which otherwise covers
+    /// the whole volume. Volumes of unbounded size need this.
+    pub fn blocks(&mut self, count: u32) -> &mut Builder {
+        self.blocks = Some(count);
+        self
+    }
+
+    /// Sets the number of inodes. This is rounded up to fill whole inode
+    /// table blocks in every block group, and takes precedence over
+    /// `bytes_per_inode`.
+    pub fn inodes(&mut self, count: u32) -> &mut Builder {
+        self.inodes = Some(count);
+        self
+    }
+
+    /// Creates an inode for every `bytes` bytes of the filesystem
+    pub fn bytes_per_inode(&mut self, bytes: usize) -> &mut Builder {
+        self.bytes_per_inode = bytes;
+        self
+    }
+
+    /// Sets the percentage of blocks reserved for the superuser
+    pub fn reserved_percent(&mut self, percent: u8) -> &mut Builder {
+        self.reserved_percent = percent.min(50) as u32;
+        self
+    }
+
+    /// Sets the volume name, which is cut off after 16 bytes
+    pub fn label(&mut self, label: &[u8]) -> &mut Builder {
+        let len = label.len().min(16);
+        self.label = [0; 16];
+        self.label[..len].copy_from_slice(&label[..len]);
+        self
+    }
+
+    /// Sets the filesystem ID (the UUID reported by `blkid`)
+    pub fn fs_id(&mut self, fs_id: [u8; 16]) -> &mut Builder {
+        self.fs_id = fs_id;
+        self
+    }
+
+    /// Sets the POSIX time stamped on the root directory, lost+found and
+    /// the superblock
+    pub fn time(&mut self, time: u32) -> &mut Builder {
+        self.time = time;
+        self
+    }
+
+    /// Lays out a fresh filesystem on `volume`. Everything the filesystem
+    /// uses, up to the inode tables, is overwritten; the contents of the
+    /// free blocks are left as they are.
+    pub fn build<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &mut V,
+    ) -> Result<()> {
+        let layout = self.layout(volume)?;
+        let block_size = self.block_size;
+        let log_block_size = layout.superblock.log_block_size + 10;
+        let block_addr = |block: u32| -> Address<S> {
+            Address::with_block_size(block, 0, log_block_size)
+        };
+
+        // root and lost+found take the first blocks after the metadata of
+        // group 0
+        let root_block = layout.group_start(0) + layout.overhead(0);
+        let lost_blocks = (LOST_AND_FOUND_SIZE / block_size) as u32;
+
+        let mut superblock = layout.superblock;
+        let mut descriptors = Vec::with_capacity(layout.groups as usize);
+        for group in 0..layout.groups {
+            let (extra_blocks, used_inodes) = if group == 0 {
+                (1 + lost_blocks, FIRST_INODE)
+            } else {
+                (0, 0)
+            };
+            let mut descriptor =
+                layout.init_group(volume, group, extra_blocks, used_inodes)?;
+            if group == 0 {
+                descriptor.dirs_count = 2;
+            }
+            superblock.free_blocks_count += descriptor.free_blocks_count as u32;
+            superblock.free_inodes_count += descriptor.free_inodes_count as u32;
+            descriptors.push(descriptor);
+        }
+
+        let root = self.directory(
+            block_size,
+            Permissions::from_bits_truncate(0o755),
+            3,
+            root_block,
+            1,
+        );
+        let lost = self.directory(
+            block_size,
+            Permissions::from_bits_truncate(0o700),
+            2,
+            root_block + 1,
+            lost_blocks,
+        );
+        let inode_addr = |inode: u32| {
+            let table = { descriptors[0].inode_table_block };
+            block_addr(table)
+                .checked_add(Address::from((inode as usize - 1) * INODE_SIZE))
+                .ok_or(Error::ArithmeticOverflow)
+        };
+        root.write_inode(volume, inode_addr(ROOT_INODE)?)?;
+        lost.write_inode(volume, inode_addr(FIRST_INODE)?)?;
+
+        let mut data = vec![0; block_size];
+        let entries: [(&[u8], u32); 3] = [
+            (b".", ROOT_INODE),
+            (b"..", ROOT_INODE),
+            (b"lost+found", FIRST_INODE),
+        ];
+        write_entries(&mut data, &entries);
+        commit_block(volume, data, block_addr(root_block))?;
+        for i in 0..lost_blocks {
+            let mut data = vec![0; block_size];
+            if i == 0 {
+                let entries: [(&[u8], u32); 2] =
+                    [(b".", FIRST_INODE), (b"..", ROOT_INODE)];
+                write_entries(&mut data, &entries);
+            } else {
+                write_dirent(&mut data, 0, block_size, b"", 0);
+            }
+            commit_block(volume, data, block_addr(root_block + 1 + i))?;
+        }
+
+        for group in 0..layout.groups {
+            if !superblock.has_backup(group) {
+                continue;
+            }
+            let start = layout.group_start(group);
+            let mut copy = superblock;
+            copy.block_group = group as u16;
+            let offset = if group == 0 {
+                Address::from(1024_usize)
+            } else {
+                block_addr(start)
+            };
+            copy.write_back(volume, offset)?;
+            BlockGroupDescriptor::write_table(
+                &descriptors,
+                volume,
+                block_addr(start + 1),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Works out the geometry of a filesystem covering `volume`
+    fn layout<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &V,
+    ) -> Result<Layout> {
+        let block_size = self.block_size;
+        if block_size != 1024 && block_size != 2048 && block_size != 4096 {
+            return Err(Error::InvalidBlockSize { size: block_size });
+        }
+
+        let mut blocks_count = match (self.blocks, volume.size().try_len()) {
+            (Some(blocks), _) => blocks,
+            (None, Some(size)) => (size.into_index() / block_size as u64)
+                .min(u32::MAX as u64) as u32,
+            (None, None) => {
+                return Err(Error::Other(String::from(
+                    "the block count of an unbounded volume must be given",
+                )))
+            }
+        };
+
+        let mut superblock = Superblock::default();
+        superblock.blocks_count = blocks_count;
+        superblock.first_data_block = if block_size == 1024 { 1 } else { 0 };
+        superblock.log_block_size = block_size.trailing_zeros() - 10;
+        superblock.log_frag_size = block_size.trailing_zeros() as i32 - 10;
+        superblock.blocks_per_group = block_size as u32 * 8;
+        superblock.frags_per_group = block_size as u32 * 8;
+        superblock.wtime = self.time;
+        superblock.max_mnt_count = -1;
+        superblock.magic = EXT2_MAGIC;
+        superblock.state = FS_CLEAN;
+        superblock.errors = ERR_IGNORE;
+        superblock.lastcheck = self.time;
+        superblock.creator_os = OS_LINUX;
+        superblock.rev_major = 1;
+        superblock.first_inode = FIRST_INODE;
+        superblock.inode_size = INODE_SIZE as u16;
+        superblock.features_req = FeaturesRequired::REQ_DIRECTORY_TYPE;
+        superblock.features_ronly = FeaturesROnly::RONLY_SPARSE;
+        superblock.fs_id = self.fs_id;
+        superblock.volume_name = self.label;
+        let too_small = Error::VolumeTooSmall {
+            blocks: blocks_count,
+        };
+        if blocks_count <= superblock.first_data_block {
+            return Err(too_small);
+        }
+
+        let blocks_per_group = superblock.blocks_per_group;
+        let mut groups = (blocks_count - superblock.first_data_block)
+            .div_ceil(blocks_per_group);
+        let inodes_per_block = (block_size / INODE_SIZE) as u32;
+        let inodes = match self.inodes {
+            Some(inodes) => inodes as u64,
+            None => {
+                blocks_count as u64 * block_size as u64
+                    / self.bytes_per_inode.max(1) as u64
+            }
+        };
+        let inodes_per_group = (inodes.div_ceil(groups as u64) as u32)
+            .max(FIRST_INODE + 1)
+            .next_multiple_of(inodes_per_block)
+            .min(block_size as u32 * 8);
+
+        let mut layout = Layout {
+            superblock,
+            groups,
+            gdt_blocks: 0,
+            inode_table_blocks: inodes_per_group / inodes_per_block,
+        };
+        // drop a last group too small to hold its own metadata and some
+        // data, like mke2fs does
+        loop {
+            layout.groups = groups;
+            layout.gdt_blocks = (groups as usize
+                * mem::size_of::<BlockGroupDescriptor>())
+            .div_ceil(block_size) as u32;
+            let last = groups - 1;
+            if last == 0 || !layout.too_small(last) {
+                break;
+            }
+            groups -= 1;
+            blocks_count = layout.group_start(groups);
+            layout.superblock.blocks_count = blocks_count;
+        }
+
+        let lost_blocks = (LOST_AND_FOUND_SIZE / block_size) as u32;
+        if layout.group_blocks(0) < layout.overhead(0) + 1 + lost_blocks {
+            return Err(too_small);
+        }
+
+        superblock = layout.superblock;
+        superblock.inodes_per_group = inodes_per_group;
+        superblock.inodes_count = inodes_per_group * groups;
+        superblock.r_blocks_count =
+            (blocks_count as u64 * self.reserved_percent as u64 / 100) as u32;
+        layout.superblock = superblock;
+        Ok(layout)
+    }
+
+    /// Returns a directory inode with the given permissions and link count,
+    /// owning `count` blocks starting at `block`
+    fn directory(
+        &self,
+        block_size: usize,
+        perm: Permissions,
+        hard_links: u16,
+        block: u32,
+        count: u32,
+    ) -> Inode {
+        let mut direct_pointer = [0; 12];
+        for (i, pointer) in
+            direct_pointer.iter_mut().take(count as usize).enumerate()
+        {
+            *pointer = block + i as u32;
+        }
+        Inode {
+            type_perm: TypePerm::new(FileType::Directory, perm),
+            size_low: count * block_size as u32,
+            atime: self.time,
+            ctime: self.time,
+            mtime: self.time,
+            hard_links,
+            sectors_count: count * (block_size / 512) as u32,
+            direct_pointer,
+            ..Default::default()
+        }
+    }
+}
+
+/// Geometry of a filesystem being laid out
+pub(crate) struct Layout {
+    /// Superblock, of which the free counts may still be missing
+    pub(crate) superblock: Superblock,
+    pub(crate) groups: u32,
+    pub(crate) gdt_blocks: u32,
+    pub(crate) inode_table_blocks: u32,
+}
+
+impl Layout {
+    /// Writes fresh usage bitmaps and a zeroed inode table for group
+    /// `group`, and returns its descriptor. Besides the metadata, the
+    /// `extra_blocks` blocks following it and the first `used_inodes`
+    /// inodes are marked as used.
+    pub(crate) fn init_group<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &mut V,
+        group: u32,
+        extra_blocks: u32,
+        used_inodes: u32,
+    ) -> Result<BlockGroupDescriptor> {
+        let block_size = self.superblock.block_size();
+        let log_block_size = self.superblock.log_block_size + 10;
+        let block_addr = |block: u32| -> Address<S> {
+            Address::with_block_size(block, 0, log_block_size)
+        };
+
+        // the bitmaps and the inode table follow the backups, if any
+        let bitmaps = self.group_start(group) + self.overhead(group)
+            - 2
+            - self.inode_table_blocks;
+        let mut descriptor = BlockGroupDescriptor::default();
+        descriptor.block_usage_addr = bitmaps;
+        descriptor.inode_usage_addr = bitmaps + 1;
+        descriptor.inode_table_block = bitmaps + 2;
+
+        // bits past the end of the group are set as padding
+        let blocks = self.group_blocks(group);
+        let used_blocks = self.overhead(group) + extra_blocks;
+        let inodes = self.superblock.inodes_per_group;
+        let bits = block_size as u32 * 8;
+        let mut block_bitmap = Bitmap::new(vec![0; block_size], bits as usize);
+        for index in (0..used_blocks).chain(blocks..bits) {
+            block_bitmap.set(index as usize);
+        }
+        let mut inode_bitmap = Bitmap::new(vec![0; block_size], bits as usize);
+        for index in (0..used_inodes).chain(inodes..bits) {
+            inode_bitmap.set(index as usize);
+        }
+        descriptor.free_blocks_count = (blocks - used_blocks) as u16;
+        descriptor.free_inodes_count = (inodes - used_inodes) as u16;
+
+        let table = vec![0; self.inode_table_blocks as usize * block_size];
+        commit_block(volume, block_bitmap.into_inner(), block_addr(bitmaps))?;
+        commit_block(
+            volume,
+            inode_bitmap.into_inner(),
+            block_addr(bitmaps + 1),
+        )?;
+        commit_block(volume, table, block_addr(bitmaps + 2))?;
+        Ok(descriptor)
+    }
+
+    /// First block of group `group`
+    pub(crate) fn group_start(&self, group: u32) -> u32 {
+        self.superblock.group_first_block(group).unwrap_or(u32::MAX)
+    }
+
+    /// Number of blocks in group `group`, which is less than a full group
+    /// for the last one
+    pub(crate) fn group_blocks(&self, group: u32) -> u32 {
+        self.superblock.group_block_count(group)
+    }
+
+    /// Number of blocks at the start of group `group` taken up by metadata:
+    /// the superblock and descriptor table backups, the usage bitmaps and
+    /// the inode table
+    pub(crate) fn overhead(&self, group: u32) -> u32 {
+        let backup = if self.superblock.has_backup(group) {
+            1 + self.gdt_blocks
+        } else {
+            0
+        };
+        backup + 2 + self.inode_table_blocks
+    }
+
+    /// Whether group `group`, as the last group, is too small to hold its
+    /// metadata and a useful amount of data
+    pub(crate) fn too_small(&self, group: u32) -> bool {
+        self.group_blocks(group) <= self.overhead(group) + 50
+    }
+}
+
+/// Fills a directory block with `entries`, the last one taking up the rest
+/// of the block
+fn write_entries(data: &mut [u8], entries: &[(&[u8], u32)]) {
+    let mut position = 0;
+    for (i, &(name, inode)) in entries.iter().enumerate() {
+        let rec_len = if i == entries.len() - 1 {
+            data.len() - position
+        } else {
+            dirent_len(name.len())
+        };
+        write_dirent(
+            &mut data[position..],
+            inode,
+            rec_len,
+            name,
+            inode::DIRECTORY,
+        );
+        position += rec_len;
+    }
+}
+
+fn commit_block<S: SectorSize, V: Volume<u8, S>>(
+    volume: &mut V,
+    data: Vec<u8>,
+    offset: Address<S>,
+) -> Result<()> {
+    volume
+        .commit(Some(VolumeCommit::new(data, offset)))
+        .map_err(|err| err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use error::Error;
+    use sector::Size512;
+    use sys::inode::Permissions;
+
+    use super::super::sync::tests::fsck;
+    use super::super::sync::Synced;
+    use super::super::Ext2;
+    use super::Builder;
+
+    #[test]
+    fn build() {
+        for &(block_size, size) in &[
+            (1024, 4 << 20),
+            // 8 groups, with backups in groups 1, 3, 5 and 7
+            (1024, 64 << 20),
+            (2048, 8 << 20),
+            (4096, 16 << 20),
+            // a last group too small to keep
+            (1024, (8 << 20) + 20 * 1024),
+        ] {
+            let mut volume = vec![0xa5_u8; size];
+            Builder::new()
+                .block_size(block_size)
+                .build::<Size512, _>(&mut volume)
+                .unwrap();
+            assert!(
+                fsck(&volume),
+                "{} byte blocks, {} bytes",
+                block_size,
+                size
+            );
+
+            let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+            assert_eq!(fs.inner().block_size(), block_size);
+            let lost = fs.lookup(b"/lost+found").unwrap();
+            assert!(lost.is_dir());
+            assert_eq!(lost.size(), 12 * 1024);
+            let root = fs.root_inode();
+            let entries: Vec<_> = root
+                .directory()
+                .unwrap()
+                .map(|entry| {
+                    let entry = entry.unwrap();
+                    (entry.name, entry.ino)
+                })
+                .collect();
+            assert_eq!(
+                entries,
+                [
+                    (b".".to_vec(), 2),
+                    (b"..".to_vec(), 2),
+                    (b"lost+found".to_vec(), 11)
+                ]
+            );
+
+            let mut file =
+                fs.create_file(b"/file", Permissions::U_READ).unwrap();
+            let data = vec![b'x'; 300 * 1024];
+            assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+            fs.mkdir(b"/dir", Permissions::U_READ).unwrap();
+            assert!(fsck(&fs.inner().volume));
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut volume = vec![0_u8; 8 << 20];
+        Builder::new()
+            .inodes(100)
+            .reserved_percent(10)
+            .label(b"scratch")
+            .fs_id([7; 16])
+            .time(1234)
+            .build::<Size512, _>(&mut volume)
+            .unwrap();
+        assert!(fsck(&volume));
+
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let fs = fs.inner();
+        let superblock = &fs.superblock.inner;
+        // one group of 13 inode table blocks, 8 inodes each
+        assert_eq!({ superblock.inodes_count }, 104);
+        assert_eq!({ superblock.r_blocks_count }, 819);
+        assert_eq!(str::from_utf8(&superblock.volume_name[..7]), Ok("scratch"));
+        assert_eq!(superblock.volume_name[7], 0);
+        assert_eq!(superblock.fs_id, [7; 16]);
+        assert_eq!({ superblock.wtime }, 1234);
+        assert_eq!({ fs.read_inode(2).unwrap().mtime }, 1234);
+    }
+
+    #[test]
+    fn errors() {
+        let mut volume = vec![0_u8; 8 << 20];
+        match Builder::new()
+            .block_size(512)
+            .build::<Size512, _>(&mut volume)
+        {
+            Err(Error::InvalidBlockSize { size: 512 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let mut volume = vec![0_u8; 16 * 1024];
+        match Builder::new().build::<Size512, _>(&mut volume) {
+            Err(Error::VolumeTooSmall { blocks: 16 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(volume.iter().all(|&byte| byte == 0));
+    }
+}
@@ -2,14 +2,36 @@ use core::mem;
 
 use alloc::vec::Vec;
 
-use error::Error;
+use error::{Error, Result};
 use sector::{Address, SectorSize};
-use volume::Volume;
-use sys::superblock::Superblock;
+use volume::size::Size;
+use volume::{Volume, VolumeCommit};
+use sys::superblock::{
+    is_sparse_group, FeaturesROnly, Superblock, Uuid, ERR_IGNORE, ERR_RONLY,
+    FS_CLEAN, FS_ERR,
+};
 use sys::block_group::BlockGroupDescriptor;
-use sys::inode::Inode as RawInode;
+use sys::bitmap::Bitmap;
+use sys::inode::{FileType, Flags, Inode as RawInode, Permissions};
 
+use self::cache::{DentryCache, InodeCache};
+use self::mkfs::{Layout, ROOT_INODE};
+
+#[cfg(any(test, feature = "async"))]
+pub mod future;
+pub mod mkfs;
+pub mod recovery;
 pub mod sync;
+mod access;
+mod cache;
+mod check;
+mod options;
+mod walk;
+pub use self::access::{access, access_with_acl, may_delete, Access};
+pub use self::check::{check, repair, Finding, Report};
+pub use self::options::{AtimePolicy, MountOptions};
+pub use self::walk::{walk, Walk};
+pub use sys::dirent::MAX_NAME_LEN;
 
 pub(crate) struct Struct<T, S: SectorSize> {
     pub inner: T,
@@ -23,43 +45,1349 @@ impl<T, S: SectorSize> From<(T, Address<S>)> for Struct<T, S> {
     }
 }
 
+/// Statistics about a filesystem, the ones `statvfs(3)` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStats {
+    /// Size of a block in bytes
+    pub block_size: u32,
+    /// Size of a fragment in bytes
+    pub frag_size: u32,
+    /// Number of blocks in the filesystem
+    pub blocks: u64,
+    /// Number of unallocated blocks
+    pub free_blocks: u64,
+    /// Number of unallocated blocks users other than the superuser may
+    /// allocate: the free blocks that aren't reserved
+    pub available_blocks: u64,
+    /// Number of inodes in the filesystem
+    pub inodes: u64,
+    /// Number of unallocated inodes
+    pub free_inodes: u64,
+    /// Longest file name, in bytes
+    pub max_name_len: u32,
+    /// File system ID
+    pub uuid: Uuid,
+}
+
+/// Information about a file, the ones `stat(2)` reports, as read from its
+/// inode when the `Metadata` was made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    ino: u32,
+    file_type: FileType,
+    permissions: Permissions,
+    len: u64,
+    uid: u16,
+    gid: u16,
+    nlink: u16,
+    atime: u32,
+    mtime: u32,
+    ctime: u32,
+    blocks: u32,
+    flags: Flags,
+    device: Option<(u32, u32)>,
+}
+
+impl Metadata {
+    /// The metadata of inode `ino`, whose size is read as
+    /// `RawInode::size_with` does
+    pub(crate) fn new(
+        ino: u32,
+        inode: &RawInode,
+        large_file: bool,
+    ) -> Metadata {
+        let (file_type, permissions) = { inode.type_perm }.split();
+        Metadata {
+            ino,
+            file_type,
+            permissions,
+            len: inode.size_with(large_file),
+            uid: inode.uid,
+            gid: inode.gid,
+            nlink: inode.hard_links,
+            atime: inode.atime,
+            mtime: inode.mtime,
+            ctime: inode.ctime,
+            blocks: inode.sectors_count,
+            flags: inode.flags,
+            device: inode.device(),
+        }
+    }
+
+    /// The inode number
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Directory
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type == FileType::Symlink
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        self.permissions
+    }
+
+    /// The size of the file in bytes, or of the target of a symlink
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn uid(&self) -> u16 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u16 {
+        self.gid
+    }
+
+    /// The number of hard links to the inode
+    pub fn nlink(&self) -> u16 {
+        self.nlink
+    }
+
+    /// Last access time, in seconds since the epoch
+    pub fn accessed(&self) -> u32 {
+        self.atime
+    }
+
+    /// Last modification time of the contents, in seconds since the epoch
+    pub fn modified(&self) -> u32 {
+        self.mtime
+    }
+
+    /// The inode's `ctime`, in seconds since the epoch. Ext2 keeps no
+    /// separate creation time: this is set when the inode is created and
+    /// again whenever the inode changes, as by `set_permissions`.
+    pub fn created(&self) -> u32 {
+        self.ctime
+    }
+
+    /// The space the file takes up, in 512-byte sectors, indirect blocks
+    /// included
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// The major and minor number of a device, or `None` for other files
+    pub fn device(&self) -> Option<(u32, u32)> {
+        self.device
+    }
+}
+
+/// How much a tree of files takes up, the way `du` counts it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Sum of the sizes of the files, directories and symlinks
+    pub apparent_size: u64,
+    /// Sum of the space allocated to them in bytes, indirect blocks included
+    pub allocated_size: u64,
+    /// Number of files other than directories: regular files, symlinks,
+    /// devices, FIFOs and sockets
+    pub files: u64,
+    /// Number of directories, the top one included
+    pub directories: u64,
+}
+
+impl Usage {
+    fn add(&mut self, metadata: &Metadata) {
+        self.apparent_size += metadata.len();
+        self.allocated_size += u64::from(metadata.blocks()) * 512;
+        if metadata.is_dir() {
+            self.directories += 1;
+        } else {
+            self.files += 1;
+        }
+    }
+}
+
+/// Where `Ext2::allocate_block` looks for a free block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockGoal {
+    /// As close after the given block as possible
+    Near(u32),
+    /// Anywhere in the given block group, but nowhere else
+    Group(usize),
+}
+
+/// A window of blocks that allocations for one inode are steered into. The
+/// blocks aren't marked as used; other allocations merely avoid them while
+/// there is room elsewhere.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reservation {
+    inode: u32,
+    start: u32,
+    end: u32,
+}
+
+/// Size of the first window reserved for an inode, in blocks
+const MIN_RESERVATION: u32 = 8;
+/// Size reservation windows stop growing at, in blocks
+const MAX_RESERVATION: u32 = 1024;
+/// Number of windows kept before the oldest ones are dropped
+const MAX_RESERVATIONS: usize = 64;
+/// Number of inodes kept in memory unless `MountOptions::inode_cache` says
+/// otherwise
+pub const DEFAULT_INODE_CACHE: usize = 64;
+
 /// Safe wrapper for raw sys structs
 pub struct Ext2<S: SectorSize, V: Volume<u8, S>> {
     // TODO: should this have some different vis?
     pub(crate) volume: V,
     pub(crate) superblock: Struct<Superblock, S>,
     pub(crate) block_groups: Struct<Vec<BlockGroupDescriptor>, S>,
+    /// Whether each descriptor was modified since it was last written back
+    dirty_groups: Vec<bool>,
+    pub(crate) options: MountOptions,
+    pub(crate) reservations: Vec<Reservation>,
+    inode_cache: InodeCache<S>,
+    pub(crate) dentry_cache: DentryCache,
+}
+
+/// The default source of timestamps for modified inodes. Without `std` there is
+/// no way to tell the time, so this reports the epoch until a clock is set with
+/// `MountOptions::clock`.
+#[cfg(all(not(test), not(feature = "std"), feature = "no_std"))]
+fn default_clock() -> u32 {
+    0
+}
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+fn default_clock() -> u32 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+/// Checks `superblock`, read from the start of group `group`, and returns
+/// where the descriptor table following it is and how many descriptors it
+/// holds
+pub(crate) fn descriptor_table<S: SectorSize>(
+    superblock: &Superblock,
+    group: u32,
+) -> Result<(Address<S>, usize)> {
+    superblock.check()?;
+    let count = superblock
+        .block_group_count()
+        .map(|count| count as usize)
+        .map_err(|(a, b)| Error::BadBlockGroupCount {
+            by_blocks: a,
+            by_inodes: b,
+        })?;
+    let first_block = superblock
+        .group_first_block(group)
+        .ok_or(Error::OutOfBounds {
+            index: group as usize,
+        })?;
+    let offset = Address::with_block_size(
+        first_block + 1,
+        0,
+        superblock.log_block_size + 10,
+    );
+    Ok((offset, count))
+}
+
+/// Checks that the bitmaps and inode tables `block_groups` point at are
+/// within both the filesystem and a volume of `size`, as they're read
+/// without further checks
+pub(crate) fn check_descriptors<S: SectorSize>(
+    superblock: &Superblock,
+    block_groups: &[BlockGroupDescriptor],
+    size: Size<S>,
+) -> Result<()> {
+    let blocks_count = superblock.blocks_count as u64;
+    let table_blocks = superblock.inode_table_blocks() as u64;
+    let mut end = 0;
+    for descriptor in block_groups {
+        for &(start, len) in &[
+            (descriptor.block_usage_addr, 1),
+            (descriptor.inode_usage_addr, 1),
+            (descriptor.inode_table_block, table_blocks),
+        ] {
+            if start < superblock.first_data_block
+                || start as u64 + len > blocks_count
+            {
+                return Err(Error::Corrupt {
+                    reason: "block group metadata outside the filesystem",
+                });
+            }
+            end = end.max(start as u64 + len);
+        }
+    }
+    let end = Address::from(end << (superblock.log_block_size + 10));
+    if size < end {
+        return Err(Error::Corrupt {
+            reason: "block group metadata past the end of the volume",
+        });
+    }
+    Ok(())
 }
 
 impl<S: SectorSize, V: Volume<u8, S>> Ext2<S, V> {
-    pub fn new(volume: V) -> Result<Ext2<S, V>, Error> {
-        let superblock = unsafe { Struct::from(Superblock::find(&volume)?) };
+    /// Mounts the filesystem on `volume`. Fails with `Error::BadMagic` if
+    /// there's no ext2 superblock on it, with the error of
+    /// `Superblock::check` if the superblock doesn't add up, and with
+    /// `Error::UnsupportedFeature` if it needs features this crate doesn't
+    /// support to be read correctly. If it only needs them to be written
+    /// correctly, it's mounted read-only instead.
+    pub fn new(volume: V) -> Result<Ext2<S, V>> {
+        Ext2::new_with_options(volume, &MountOptions::new())
+    }
+
+    /// Mounts the filesystem on `volume` writable, whatever features it
+    /// needs. Reading or writing a filesystem with features this crate
+    /// doesn't support may well misinterpret or corrupt it. The superblock
+    /// is still checked as in `new`.
+    pub fn new_forced(volume: V) -> Result<Ext2<S, V>> {
+        let mut options = MountOptions::new();
+        options.check_features(false);
+        Ext2::new_with_options(volume, &options)
+    }
+
+    /// Mounts the filesystem on `volume` with `options`, checking the
+    /// superblock as `new` does
+    pub fn new_with_options(
+        volume: V,
+        options: &MountOptions,
+    ) -> Result<Ext2<S, V>> {
+        let (superblock, _) = Superblock::find(&volume)?;
+        let (superblock, block_groups) =
+            Ext2::read_layout(&volume, superblock, 0)?;
+        let mut fs = Ext2::with_layout(volume, superblock, block_groups);
+        fs.options = options.clone();
+        fs.inode_cache = InodeCache::new(options.inode_cache);
+        fs.dentry_cache = DentryCache::new(options.dentry_cache);
+        if options.check_features {
+            fs.check_features()
+        } else {
+            Ok(fs)
+        }
+    }
+
+    /// Like `new`, but if the primary superblock or descriptor table can't
+    /// be read, mounts from the first backup copy that can be instead, and
+    /// returns the block group it was found in along with the filesystem.
+    ///
+    /// Backups are looked for where `mke2fs` puts them by default, at the
+    /// start of groups 1, 3, 5, 7, 9, 25, 27... of 8 * block size blocks,
+    /// for 1, 2 and 4 KiB blocks. The counts of free blocks and inodes in a
+    /// backup are only as recent as its last update.
+    ///
+    /// Whichever copy was read, writes go to the primary locations:
+    /// writing the superblock back, as allocating does, restores it.
+    pub fn new_with_recovery(volume: V) -> Result<(Ext2<S, V>, u32)> {
+        let primary = Superblock::find(&volume).and_then(|(superblock, _)| {
+            Ext2::read_layout(&volume, superblock, 0)
+        });
+        let err = match primary {
+            Ok((superblock, block_groups)) => {
+                let fs = Ext2::with_layout(volume, superblock, block_groups);
+                return Ok((fs.check_features()?, 0));
+            }
+            Err(err) => err,
+        };
+
+        for log_block_size in 0..3 {
+            let blocks_per_group = 8 << (log_block_size + 10);
+            let first_data_block = if log_block_size == 0 { 1 } else { 0 };
+            for group in 1.. {
+                if !is_sparse_group(group) {
+                    continue;
+                }
+                let block = match group
+                    .checked_mul(blocks_per_group)
+                    .and_then(|block| block.checked_add(first_data_block))
+                {
+                    Some(block) => block,
+                    None => break,
+                };
+                let offset =
+                    Address::with_block_size(block, 0, log_block_size + 10);
+                match offset.checked_add(Address::from(1024_usize)) {
+                    Some(end) if volume.size() >= end => (),
+                    _ => break,
+                }
+                let superblock = match Superblock::find_at(&volume, offset) {
+                    Ok(superblock) => superblock,
+                    Err(_) => continue,
+                };
+                // a copy of a filesystem laid out differently only happens
+                // to be here
+                if superblock.log_block_size != log_block_size
+                    || superblock.blocks_per_group != blocks_per_group
+                {
+                    continue;
+                }
+                if let Ok((superblock, block_groups)) =
+                    Ext2::read_layout(&volume, superblock, group)
+                {
+                    let fs =
+                        Ext2::with_layout(volume, superblock, block_groups);
+                    return Ok((fs.check_features()?, group));
+                }
+            }
+        }
+        Err(err)
+    }
+
+    /// Checks `superblock`, read from the start of group `group`, and reads
+    /// the descriptor table that follows it
+    fn read_layout(
+        volume: &V,
+        mut superblock: Superblock,
+        group: u32,
+    ) -> Result<(Superblock, Vec<BlockGroupDescriptor>)> {
+        superblock.block_group = 0;
+        let (offset, count) = descriptor_table(&superblock, group)?;
+        let (block_groups, _) =
+            BlockGroupDescriptor::find_descriptor_table(volume, offset, count)?;
+        check_descriptors(&superblock, &block_groups, volume.size())?;
+        Ok((superblock, block_groups))
+    }
+
+    /// Mounts `volume` with the superblock and descriptor table read by
+    /// `read_layout`, at their primary locations
+    fn with_layout(
+        volume: V,
+        superblock: Superblock,
+        block_groups: Vec<BlockGroupDescriptor>,
+    ) -> Ext2<S, V> {
         let block_groups_offset = Address::with_block_size(
-            superblock.inner.first_data_block + 1,
+            superblock.gdt_start_block(),
             0,
-            superblock.inner.log_block_size + 10,
+            superblock.log_block_size + 10,
         );
-        let block_groups_count = superblock
-            .inner
-            .block_group_count()
-            .map(|count| count as usize)
-            .map_err(|(a, b)| Error::BadBlockGroupCount {
-                by_blocks: a,
-                by_inodes: b,
-            })?;
-        let block_groups = unsafe {
-            BlockGroupDescriptor::find_descriptor_table(
-                &volume,
-                block_groups_offset,
-                block_groups_count,
-            )?
-        };
-        let block_groups = Struct::from(block_groups);
-        Ok(Ext2 {
+        let dirty_groups = vec![false; block_groups.len()];
+        Ext2 {
             volume,
+            superblock: Struct::from((superblock, Address::from(1024_usize))),
+            block_groups: Struct::from((block_groups, block_groups_offset)),
+            dirty_groups,
+            options: MountOptions::new(),
+            reservations: Vec::new(),
+            inode_cache: InodeCache::new(DEFAULT_INODE_CACHE),
+            dentry_cache: DentryCache::new(0),
+        }
+    }
+
+    /// Fails if the filesystem needs features this crate doesn't support to
+    /// be read, and marks it read-only if it needs them to be written
+    fn check_features(mut self) -> Result<Ext2<S, V>> {
+        let superblock = &self.superblock.inner;
+        let unsupported = superblock.unsupported_features_req();
+        if !unsupported.is_empty() {
+            return Err(Error::UnsupportedFeature {
+                incompat: unsupported.bits(),
+            });
+        }
+        if !superblock.unsupported_features_ronly().is_empty() {
+            self.options.read_only = true;
+        }
+        Ok(self)
+    }
+
+    /// Sets the function used to obtain the current POSIX time when stamping
+    /// modified inodes
+    pub fn set_clock(&mut self, clock: fn() -> u32) {
+        self.options.clock = clock;
+    }
+
+    /// Marks the filesystem as read-only (or writable again). While it is
+    /// read-only, every operation that would modify the volume fails with
+    /// `Error::ReadOnlyFilesystem` before touching it. Making a filesystem
+    /// mounted read-only for its features writable is as risky as mounting
+    /// it with `new_forced`.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.options.read_only = read_only;
+    }
+
+    /// Returns whether the filesystem was marked as read-only
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    /// Sets whether writes of zeroes into holes leave the holes alone rather
+    /// than allocating blocks to hold the zeroes. Either way, writing past
+    /// the end of a file doesn't allocate the blocks in between.
+    pub fn set_sparse_writes(&mut self, sparse_writes: bool) {
+        self.options.sparse_writes = sparse_writes;
+    }
+
+    /// Sets when reading a file updates its access time, which is never by
+    /// default so that reads don't write to the volume. Nothing is written
+    /// while the filesystem is read-only, whatever the policy.
+    pub fn set_atime_policy(&mut self, policy: AtimePolicy) {
+        self.options.atime = policy;
+    }
+
+    pub fn atime_policy(&self) -> AtimePolicy {
+        self.options.atime
+    }
+
+    /// The options the filesystem was mounted with, as changed since
+    pub fn options(&self) -> &MountOptions {
+        &self.options
+    }
+
+    /// Fails with `Error::ReadOnlyFilesystem` if the filesystem is read-only
+    pub(crate) fn check_writable(&self) -> Result<()> {
+        if self.options.read_only {
+            Err(Error::ReadOnlyFilesystem)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes `data` to the volume at `addr`. Every write to the volume
+    /// goes through this or another method checking that the filesystem
+    /// isn't read-only right before it's made.
+    pub(crate) fn commit(
+        &mut self,
+        data: Vec<u8>,
+        addr: Address<S>,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.volume
+            .commit(Some(VolumeCommit::new(data, addr)))
+            .map_err(|err| err.into())
+    }
+
+    /// Makes sure everything written so far has reached the volume's
+    /// underlying storage
+    pub fn flush(&mut self) -> Result<()> {
+        self.volume.flush().map_err(|err| err.into())
+    }
+
+    /// Returns the current POSIX time according to the configured clock
+    pub fn now(&self) -> u32 {
+        (self.options.clock)()
+    }
+
+    /// Allocates a free block as close to `goal` as possible, steering clear
+    /// of the blocks reserved for files being written to while there is room
+    /// elsewhere. The block usage bitmap, the group descriptor and the
+    /// superblock are updated and written back to the volume.
+    ///
+    /// Unless `privileged` is set, the blocks reserved for the superuser are
+    /// off limits (see `Superblock::may_use_reserved`).
+    ///
+    /// The contents of the returned block are left untouched.
+    pub fn allocate_block(
+        &mut self,
+        goal: BlockGoal,
+        privileged: bool,
+    ) -> Result<u32> {
+        self.check_space(privileged)?;
+        let block = match self.find_free_block(goal, true)? {
+            Some(block) => block,
+            None => match self.find_free_block(goal, false)? {
+                Some(block) => block,
+                None => return Err(Error::OutOfSpace),
+            },
+        };
+        self.claim_block(block)?;
+        Ok(block)
+    }
+
+    /// Allocates a block for inode `inode`, near `goal` like `allocate_block`
+    /// does. The block is taken from a window of blocks reserved for the
+    /// inode, so that files growing side by side don't interleave their
+    /// blocks. Once a window is used up, the next one is twice as large.
+    pub(crate) fn allocate_block_for(
+        &mut self,
+        inode: u32,
+        goal: u32,
+        privileged: bool,
+    ) -> Result<u32> {
+        self.check_space(privileged)?;
+        let mut size = MIN_RESERVATION;
+        let position = self.reservations.iter().position(|r| r.inode == inode);
+        if let Some(position) = position {
+            let window = self.reservations.remove(position);
+            size = window.end - window.start;
+            if window.start <= goal && goal <= window.end {
+                if let Some(block) = self.find_free_in(goal, window.end)? {
+                    self.reservations.push(window);
+                    self.claim_block(block)?;
+                    return Ok(block);
+                }
+                // the file has outgrown its window
+                size *= 2;
+            }
+        }
+
+        let goal = BlockGoal::Near(goal);
+        let block = match self.find_free_block(goal, true)? {
+            Some(block) => block,
+            None => match self.find_free_block(goal, false)? {
+                Some(block) => block,
+                None => return Err(Error::OutOfSpace),
+            },
+        };
+        // windows don't span groups
+        let group = self.superblock.inner.block_group_of(block).unwrap_or(0);
+        let end = (block + size.min(MAX_RESERVATION))
+            .min(self.group_start(group as usize + 1))
+            .min(self.superblock.inner.blocks_count);
+        let window = Reservation {
+            inode,
+            start: block,
+            end,
+        };
+        if self.reservations.len() == MAX_RESERVATIONS {
+            self.reservations.remove(0);
+        }
+        self.reservations.push(window);
+        self.claim_block(block)?;
+        Ok(block)
+    }
+
+    /// Drops the window of blocks reserved for inode `inode`, if any
+    pub(crate) fn discard_reservation(&mut self, inode: u32) {
+        self.reservations.retain(|r| r.inode != inode);
+    }
+
+    /// Fails with `Error::OutOfSpace` if there are no free blocks left that
+    /// the caller may use
+    fn check_space(&self, privileged: bool) -> Result<()> {
+        self.check_writable()?;
+        let superblock = &self.superblock.inner;
+        let reserved = if privileged {
+            0
+        } else {
+            superblock.r_blocks_count
+        };
+        if superblock.free_blocks_count <= reserved {
+            return Err(Error::OutOfSpace);
+        }
+        Ok(())
+    }
+
+    /// Returns the group of `block` and its index in the group
+    fn block_position(&self, block: u32) -> Option<(usize, usize)> {
+        self.superblock
+            .inner
+            .block_group_and_index(block)
+            .map(|(group, index)| (group as usize, index as usize))
+    }
+
+    /// Returns the first block of group `group`, saturating past the last
+    /// block a filesystem can have
+    fn group_start(&self, group: usize) -> u32 {
+        self.superblock
+            .inner
+            .group_first_block(group as u32)
+            .unwrap_or(u32::MAX)
+    }
+
+    /// Whether `block` lies in the window reserved for any inode
+    fn is_reserved(&self, block: u32) -> bool {
+        self.reservations
+            .iter()
+            .any(|r| r.start <= block && block < r.end)
+    }
+
+    /// Looks for a free block in `start..end`, which must lie in one group
+    fn find_free_in(&self, start: u32, end: u32) -> Result<Option<u32>> {
+        let (group, index) = match self.block_position(start) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let bitmap = self.block_bitmap(group)?;
+        let len = (index + (end - start) as usize).min(bitmap.len());
+        Ok((index..len)
+            .find(|&index| !bitmap.get(index))
+            .map(|index| self.group_start(group) + index as u32))
+    }
+
+    /// Looks for a free block following `goal`: for `BlockGoal::Near`, the
+    /// goal itself, then the rest of its byte in the bitmap and the rest of
+    /// its group, then the start of its group, and finally the other groups
+    /// round-robin. Blocks in reservation windows are skipped if
+    /// `avoid_reserved` is set.
+    fn find_free_block(
+        &self,
+        goal: BlockGoal,
+        avoid_reserved: bool,
+    ) -> Result<Option<u32>> {
+        let count = self.block_group_count()?;
+        let (first_group, goal_index, groups) = match goal {
+            BlockGoal::Near(block) => match self.block_position(block) {
+                Some((group, index)) => (group, index, count),
+                None => (0, 0, count),
+            },
+            BlockGoal::Group(group) if group < count => (group, 0, 1),
+            BlockGoal::Group(group) => {
+                return Err(Error::OutOfBounds { index: group })
+            }
+        };
+
+        for i in 0..groups {
+            let group = (first_group + i) % count;
+            if self.block_groups.inner[group].free_blocks_count == 0 {
+                continue;
+            }
+            let bitmap = self.block_bitmap(group)?;
+            let start = self.group_start(group);
+            let goal = if i == 0 { goal_index } else { 0 };
+            let found = (goal..bitmap.len()).chain(0..goal).find(|&index| {
+                !bitmap.get(index)
+                    && (!avoid_reserved
+                        || !self.is_reserved(start + index as u32))
+            });
+            if let Some(index) = found {
+                return Ok(Some(start + index as u32));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Fails if `block` isn't one of the filesystem's blocks, as block
+    /// pointers read off a corrupt volume may not be
+    pub(crate) fn check_block(&self, block: u32) -> Result<()> {
+        let superblock = &self.superblock.inner;
+        if block < superblock.first_data_block
+            || block >= superblock.blocks_count
+        {
+            return Err(Error::Corrupt {
+                reason: "block pointer outside the filesystem",
+            });
+        }
+        Ok(())
+    }
+
+    /// Marks the free block `block` as used and updates the free block counts
+    fn claim_block(&mut self, block: u32) -> Result<()> {
+        let (group, index) = match self.block_position(block) {
+            Some(position) => position,
+            None => {
+                return Err(Error::OutOfBounds {
+                    index: block as usize,
+                })
+            }
+        };
+        let mut bitmap = self.block_bitmap(group)?;
+        bitmap.set(index);
+        self.write_block_bitmap(group, bitmap)?;
+
+        // the counts may be off on a corrupt filesystem, so they saturate
+        let descriptor = self.group_descriptor_mut(group);
+        descriptor.free_blocks_count =
+            { descriptor.free_blocks_count }.saturating_sub(1);
+        let superblock = &mut self.superblock.inner;
+        superblock.free_blocks_count =
+            { superblock.free_blocks_count }.saturating_sub(1);
+        self.write_block_groups()?;
+        self.write_superblock()
+    }
+
+    /// Marks `block` as free in its group's block usage bitmap and updates the
+    /// free block counts
+    pub fn free_block(&mut self, block: u32) -> Result<()> {
+        self.check_writable()?;
+        let (group, index) = match self.block_position(block) {
+            Some(position) => position,
+            None => {
+                return Err(Error::OutOfBounds {
+                    index: block as usize,
+                })
+            }
+        };
+
+        let mut bitmap = self.block_bitmap(group)?;
+        if !bitmap.get(index) {
+            return Ok(());
+        }
+        bitmap.clear(index);
+        self.write_block_bitmap(group, bitmap)?;
+
+        let descriptor = self.group_descriptor_mut(group);
+        descriptor.free_blocks_count =
+            { descriptor.free_blocks_count }.saturating_add(1);
+        let superblock = &mut self.superblock.inner;
+        superblock.free_blocks_count =
+            { superblock.free_blocks_count }.saturating_add(1);
+        self.write_block_groups()?;
+        self.write_superblock()
+    }
+
+    /// Allocates a free inode for a new child of directory `parent`. The
+    /// block group is picked with the Orlov heuristic: directories created in
+    /// the root directory go to the group with the fewest directories among
+    /// those with an above-average number of free inodes and free blocks,
+    /// while other directories and files stay in or close to their parent's
+    /// group. Inodes below the first non-reserved inode are never handed out.
+    /// The inode usage bitmap, the group descriptor and the superblock are
+    /// updated and written back to the volume; when `is_dir` is set, the
+    /// group's directory count is incremented as well.
+    ///
+    /// The inode itself is left untouched; it's up to the caller to
+    /// initialize it.
+    pub fn allocate_inode(
+        &mut self,
+        parent: u32,
+        is_dir: bool,
+    ) -> Result<u32> {
+        self.check_writable()?;
+        let count = self.block_group_count()?;
+        let inodes_per_group = self.superblock.inner.inodes_per_group;
+        let parent_group = if parent == 0 {
+            0
+        } else {
+            ((parent - 1) / inodes_per_group) as usize % count
+        };
+        let first_group = if !is_dir {
+            self.find_group_other(parent, parent_group, count)
+        } else if parent == ROOT_INODE {
+            self.find_group_toplevel(count)
+        } else {
+            self.find_group_dir(parent_group, count)
+        }
+        .unwrap_or(parent_group);
+
+        for i in 0..count {
+            let group = (first_group + i) % count;
+            if let Some(index) = self.take_free_inode(group, is_dir)? {
+                return Ok(group as u32 * inodes_per_group + index as u32 + 1);
+            }
+        }
+
+        Err(Error::OutOfSpace)
+    }
+
+    /// Picks the group for a directory created in the root directory: the
+    /// one with the fewest directories among those with at least the
+    /// average number of free inodes and free blocks
+    fn find_group_toplevel(&self, count: usize) -> Option<usize> {
+        let superblock = &self.superblock.inner;
+        let avg_free_inodes = superblock.free_inodes_count / count as u32;
+        let avg_free_blocks = superblock.free_blocks_count / count as u32;
+        self.block_groups.inner[..count]
+            .iter()
+            .enumerate()
+            .filter(|&(_, descr)| {
+                descr.free_inodes_count != 0
+                    && u32::from(descr.free_inodes_count) >= avg_free_inodes
+                    && u32::from(descr.free_blocks_count) >= avg_free_blocks
+            })
+            .min_by_key(|&(_, descr)| descr.dirs_count)
+            .map(|(group, _)| group)
+    }
+
+    /// Picks the group for a directory nested deeper in the tree: the first
+    /// one from the parent's group on that neither holds too many
+    /// directories nor is short on free inodes or free blocks, falling back
+    /// to the first with an above-average number of free inodes
+    fn find_group_dir(
+        &self,
+        parent_group: usize,
+        count: usize,
+    ) -> Option<usize> {
+        let superblock = &self.superblock.inner;
+        let groups = count as u32;
+        let avg_free_inodes = superblock.free_inodes_count / groups;
+        let avg_free_blocks = superblock.free_blocks_count / groups;
+        let dirs: u32 = self.block_groups.inner[..count]
+            .iter()
+            .map(|descr| u32::from(descr.dirs_count))
+            .sum();
+        let max_dirs = dirs / groups + superblock.inodes_per_group / 16;
+        let min_inodes =
+            avg_free_inodes.saturating_sub(superblock.inodes_per_group / 4);
+        let min_blocks =
+            avg_free_blocks.saturating_sub(superblock.blocks_per_group / 4);
+
+        let groups = (0..count).map(|i| (parent_group + i) % count);
+        groups
+            .clone()
+            .find(|&group| {
+                let descr = &self.block_groups.inner[group];
+                descr.free_inodes_count != 0
+                    && u32::from(descr.dirs_count) < max_dirs
+                    && u32::from(descr.free_inodes_count) >= min_inodes
+                    && u32::from(descr.free_blocks_count) >= min_blocks
+            })
+            .or_else(|| {
+                groups.clone().find(|&group| {
+                    let descr = &self.block_groups.inner[group];
+                    descr.free_inodes_count != 0
+                        && u32::from(descr.free_inodes_count) >= avg_free_inodes
+                })
+            })
+    }
+
+    /// Picks the group for a file: the parent's group if it has both free
+    /// inodes and free blocks, otherwise one found by quadratic probing
+    /// from a start derived from the parent, so that the files of different
+    /// directories don't all spill over into the same group
+    fn find_group_other(
+        &self,
+        parent: u32,
+        parent_group: usize,
+        count: usize,
+    ) -> Option<usize> {
+        let usable = |group: usize| {
+            let descr = &self.block_groups.inner[group];
+            descr.free_inodes_count != 0 && descr.free_blocks_count != 0
+        };
+        if usable(parent_group) {
+            return Some(parent_group);
+        }
+
+        let mut group = (parent_group + parent as usize) % count;
+        let mut step = 1;
+        while step < count {
+            group = (group + step) % count;
+            if usable(group) {
+                return Some(group);
+            }
+            step <<= 1;
+        }
+        None
+    }
+
+    /// Takes the first free inode of group `group`, returning its index in
+    /// the group
+    fn take_free_inode(
+        &mut self,
+        group: usize,
+        is_dir: bool,
+    ) -> Result<Option<usize>> {
+        if self.block_groups.inner[group].free_inodes_count == 0 {
+            return Ok(None);
+        }
+
+        let inodes_per_group = self.superblock.inner.inodes_per_group;
+        let mut bitmap = self.inode_bitmap(group)?;
+        // bit `n` of group `g` stands for inode `g * inodes_per_group + n + 1`,
+        // so skip the reserved ones at the start of group 0
+        let skip = (self.first_inode() - 1)
+            .saturating_sub(group as u32 * inodes_per_group)
+            .min(inodes_per_group) as usize;
+        let index = match (skip..bitmap.len()).find(|&i| !bitmap.get(i)) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        bitmap.set(index);
+        self.write_inode_bitmap(group, bitmap)?;
+
+        let descriptor = self.group_descriptor_mut(group);
+        descriptor.free_inodes_count =
+            { descriptor.free_inodes_count }.saturating_sub(1);
+        if is_dir {
+            descriptor.dirs_count = { descriptor.dirs_count }.saturating_add(1);
+        }
+        let superblock = &mut self.superblock.inner;
+        superblock.free_inodes_count =
+            { superblock.free_inodes_count }.saturating_sub(1);
+        self.write_block_groups()?;
+        self.write_superblock()?;
+        Ok(Some(index))
+    }
+
+    /// Frees inode `inode`: sets its deletion time, clears its bit in the
+    /// inode usage bitmap and updates the free inode (and, for directories,
+    /// the directory) counts.
+    ///
+    /// The data blocks of the inode are not freed.
+    pub fn free_inode(&mut self, inode: u32) -> Result<()> {
+        self.check_writable()?;
+        if inode < self.first_inode()
+            || inode > self.superblock.inner.inodes_count
+        {
+            return Err(Error::InodeNotFound { inode });
+        }
+        self.discard_reservation(inode);
+
+        let (group, index) = self.inode_position(inode)?;
+
+        let mut bitmap = self.inode_bitmap(group)?;
+        if !bitmap.get(index) {
+            return Ok(());
+        }
+
+        let mut raw = self.read_inode(inode)?;
+        raw.dtime = self.now();
+        self.write_inode(inode, &raw)?;
+        self.dentry_cache.invalidate_dir(inode);
+
+        bitmap.clear(index);
+        self.write_inode_bitmap(group, bitmap)?;
+
+        let descriptor = self.group_descriptor_mut(group);
+        descriptor.free_inodes_count =
+            { descriptor.free_inodes_count }.saturating_add(1);
+        if FileType::from_type_perm(raw.type_perm) == FileType::Directory {
+            descriptor.dirs_count = { descriptor.dirs_count }.saturating_sub(1);
+        }
+        let superblock = &mut self.superblock.inner;
+        superblock.free_inodes_count =
+            { superblock.free_inodes_count }.saturating_add(1);
+        self.write_block_groups()?;
+        self.write_superblock()
+    }
+
+    /// Reads inode `inode` from its group's inode table, or from the inode
+    /// cache if it's been read since it was last written
+    pub fn read_inode(&self, inode: u32) -> Result<RawInode> {
+        self.read_inode_at(inode).map(|(raw, _)| raw)
+    }
+
+    /// Like `read_inode`, also returning the address of the inode
+    pub(crate) fn read_inode_at(
+        &self,
+        inode: u32,
+    ) -> Result<(RawInode, Address<S>)> {
+        if let Some(cached) = self.inode_cache.get(inode) {
+            return Ok(cached);
+        }
+        let offset = self.inode_address(inode)?;
+        let (raw, addr) =
+            RawInode::find_inode(&self.volume, offset, self.inode_size())?;
+        self.inode_cache.insert(inode, raw, addr);
+        Ok((raw, addr))
+    }
+
+    /// Writes `raw` to the slot of inode `inode` in its group's inode table
+    pub fn write_inode(
+        &mut self,
+        inode: u32,
+        raw: &RawInode,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let offset = self.inode_address(inode)?;
+        self.inode_cache.invalidate(inode);
+        raw.write_inode(&mut self.volume, offset)
+    }
+
+    /// Returns the address of inode `inode` in its group's inode table
+    pub(crate) fn inode_address(
+        &self,
+        inode: u32,
+    ) -> Result<Address<S>> {
+        let (group, index) = self.inode_position(inode)?;
+        // large inodes in large groups are further into the table than an
+        // `i32` offset reaches
+        let table = Address::with_block_size(
+            self.block_groups.inner[group].inode_table_block,
+            0,
+            self.log_block_size(),
+        );
+        table
+            .checked_add(Address::from(index as u64 * self.inode_size() as u64))
+            .ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Returns the group of inode `inode` and its index in the group's inode
+    /// table
+    fn inode_position(&self, inode: u32) -> Result<(usize, usize)> {
+        self.superblock
+            .inner
+            .inode_group_and_index(inode)
+            .map(|(group, index)| (group as usize, index as usize))
+            .ok_or(Error::InodeNotFound { inode })
+    }
+
+    /// Reads the inode usage bitmap of block group `group`
+    pub fn inode_bitmap(&self, group: usize) -> Result<Bitmap> {
+        let offset = Address::with_block_size(
+            self.block_groups.inner[group].inode_usage_addr,
+            0,
+            self.log_block_size(),
+        );
+        let len = self.superblock.inner.inodes_per_group as usize;
+        Bitmap::find(&self.volume, offset, self.block_size(), len)
+            .map(|(bitmap, _)| bitmap)
+    }
+
+    fn write_inode_bitmap(
+        &mut self,
+        group: usize,
+        bitmap: Bitmap,
+    ) -> Result<()> {
+        let offset = Address::with_block_size(
+            self.block_groups.inner[group].inode_usage_addr,
+            0,
+            self.log_block_size(),
+        );
+        self.commit(bitmap.into_inner(), offset)
+    }
+
+    /// Reads the block usage bitmap of block group `group`
+    pub fn block_bitmap(&self, group: usize) -> Result<Bitmap> {
+        let superblock = &self.superblock.inner;
+        let offset = Address::with_block_size(
+            self.block_groups.inner[group].block_usage_addr,
+            0,
+            self.log_block_size(),
+        );
+        // the last block group may be shorter than the others
+        let len = superblock.group_block_count(group as u32);
+        Bitmap::find(&self.volume, offset, self.block_size(), len as usize)
+            .map(|(bitmap, _)| bitmap)
+    }
+
+    fn write_block_bitmap(
+        &mut self,
+        group: usize,
+        bitmap: Bitmap,
+    ) -> Result<()> {
+        let offset = Address::with_block_size(
+            self.block_groups.inner[group].block_usage_addr,
+            0,
+            self.log_block_size(),
+        );
+        self.commit(bitmap.into_inner(), offset)
+    }
+
+    /// Returns the superblock as it is in memory, which is at least as
+    /// recent as the one on the volume
+    pub fn superblock(&self) -> &Superblock {
+        &self.superblock.inner
+    }
+
+    /// Returns the block group descriptor table as it is in memory
+    pub fn block_groups(&self) -> &[BlockGroupDescriptor] {
+        &self.block_groups.inner
+    }
+
+    /// Returns the descriptor of block group `group` as it is in memory.
+    /// The table is read once when the filesystem is mounted, so this never
+    /// reads the volume.
+    ///
+    /// Panics if `group` is past the last block group.
+    pub fn group_descriptor(&self, group: usize) -> &BlockGroupDescriptor {
+        &self.block_groups.inner[group]
+    }
+
+    /// Returns the descriptor of block group `group` to be modified, marking
+    /// it to be written back by the next `write_block_groups`
+    pub(crate) fn group_descriptor_mut(
+        &mut self,
+        group: usize,
+    ) -> &mut BlockGroupDescriptor {
+        self.dirty_groups[group] = true;
+        &mut self.block_groups.inner[group]
+    }
+
+    /// Records that the filesystem is being mounted at POSIX time `time`,
+    /// like Linux does: the mount count goes up and the filesystem is marked
+    /// as not clean until `unmount`, which is how `e2fsck` tells whether a
+    /// check is due.
+    ///
+    /// If the filesystem is marked as having errors, what happens depends on
+    /// its error policy: `ERR_IGNORE` mounts it anyway, `ERR_RONLY` mounts
+    /// it read-only and anything else fails with
+    /// `Error::FilesystemHasErrors`. Read-only filesystems are left as they
+    /// are.
+    pub fn mount(&mut self, time: u32) -> Result<()> {
+        let superblock = &self.superblock.inner;
+        if superblock.state & FS_ERR != 0 {
+            match superblock.errors {
+                ERR_IGNORE => (),
+                ERR_RONLY => self.options.read_only = true,
+                policy => return Err(Error::FilesystemHasErrors { policy }),
+            }
+        }
+        if self.options.read_only {
+            return Ok(());
+        }
+
+        let superblock = &mut self.superblock.inner;
+        superblock.mnt_count = superblock.mnt_count.wrapping_add(1);
+        superblock.state &= !FS_CLEAN;
+        superblock.mtime = time;
+        self.write_superblock()
+    }
+
+    /// Records that the filesystem was unmounted cleanly, undoing `mount`,
+    /// and flushes the volume
+    pub fn unmount(&mut self) -> Result<()> {
+        if self.options.read_only {
+            return Ok(());
+        }
+        let now = self.now();
+        let superblock = &mut self.superblock.inner;
+        superblock.state |= FS_CLEAN;
+        superblock.wtime = now;
+        self.write_superblock()?;
+        self.flush()
+    }
+
+    /// Writes the in-memory superblock back to the volume. Only the primary
+    /// copy is updated; see `write_backups`.
+    pub(crate) fn write_superblock(&mut self) -> Result<()> {
+        self.check_writable()?;
+        self.superblock
+            .inner
+            .write_back(&mut self.volume, self.superblock.offset)
+    }
+
+    /// Writes the in-memory descriptors modified since they were last
+    /// written back to the primary descriptor table on the volume, leaving
+    /// the others alone
+    pub(crate) fn write_block_groups(&mut self) -> Result<()> {
+        self.check_writable()?;
+        for group in 0..self.dirty_groups.len() {
+            if !self.dirty_groups[group] {
+                continue;
+            }
+            let offset = self
+                .block_groups
+                .offset
+                .checked_add(Address::from(
+                    group * mem::size_of::<BlockGroupDescriptor>(),
+                ))
+                .ok_or(Error::ArithmeticOverflow)?;
+            self.block_groups.inner[group]
+                .write_descriptor(&mut self.volume, offset)?;
+            self.dirty_groups[group] = false;
+        }
+        Ok(())
+    }
+
+    /// Copies the in-memory superblock and block group descriptor table to
+    /// the backups kept at the start of the groups that hold one.
+    ///
+    /// Allocations only update the primary copies, which is enough for
+    /// everything but recovering from a damaged primary superblock, so this
+    /// should be called before the volume is put away.
+    pub fn write_backups(&mut self) -> Result<()> {
+        self.check_writable()?;
+        let log_block_size = self.log_block_size();
+        for group in self.superblock.inner.backup_groups() {
+            let block = self.group_start(group as usize);
+
+            let mut superblock = self.superblock.inner;
+            superblock.block_group = group as u16;
+            let offset = Address::with_block_size(block, 0, log_block_size);
+            superblock.write_back(&mut self.volume, offset)?;
+
+            let offset = Address::with_block_size(block + 1, 0, log_block_size);
+            BlockGroupDescriptor::write_table(
+                &self.block_groups.inner,
+                &mut self.volume,
+                offset,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Grows the filesystem to `blocks_count` blocks, which the volume has
+    /// to be large enough to hold. The last block group is filled up first,
+    /// then new groups are added after it, each with its own bitmaps and
+    /// inode table, and with backups of the superblock and descriptor table
+    /// where the sparse superblock feature wants them. Like mke2fs, this
+    /// leaves out a new last group too small to be worth its metadata.
+    ///
+    /// Reserved descriptor table blocks aren't supported, so the descriptor
+    /// table has to fit in the blocks it already takes up. Shrinking isn't
+    /// supported either.
+    pub fn resize(&mut self, blocks_count: u32) -> Result<()> {
+        self.check_writable()?;
+        let mut superblock = self.superblock.inner;
+        if blocks_count < superblock.blocks_count {
+            return Err(Error::ShrinkUnsupported {
+                blocks: superblock.blocks_count,
+                new_blocks: blocks_count,
+            });
+        }
+        let block_size = self.block_size();
+        if let Some(size) = self.volume.size().try_len() {
+            if size.into_index() < blocks_count as u64 * block_size as u64 {
+                return Err(Error::VolumeTooSmall {
+                    blocks: blocks_count,
+                });
+            }
+        }
+
+        let descriptor_size = mem::size_of::<BlockGroupDescriptor>();
+        let old_groups = self.block_group_count()? as u32;
+        superblock.blocks_count = blocks_count;
+        let mut layout = Layout {
             superblock,
-            block_groups,
-        })
+            groups: (blocks_count - superblock.first_data_block)
+                .div_ceil(superblock.blocks_per_group),
+            gdt_blocks: (old_groups as usize * descriptor_size)
+                .div_ceil(block_size) as u32,
+            inode_table_blocks: superblock.inode_table_blocks(),
+        };
+        let last = layout.groups - 1;
+        if last >= old_groups && layout.too_small(last) {
+            layout.groups -= 1;
+            layout.superblock.blocks_count = layout.group_start(last);
+        }
+        if layout.groups as usize * descriptor_size
+            > layout.gdt_blocks as usize * block_size
+        {
+            return Err(Error::DescriptorTableFull {
+                groups: layout.groups,
+            });
+        }
+
+        // the blocks past the end of the old last group were padding
+        let last = old_groups - 1;
+        let old_len = self.block_bitmap(last as usize)?.len() as u32;
+        let new_len = layout.group_blocks(last);
+        if new_len > old_len {
+            let bitmap = self.block_bitmap(last as usize)?;
+            let mut bitmap = Bitmap::new(bitmap.into_inner(), new_len as usize);
+            for index in old_len..new_len {
+                bitmap.clear(index as usize);
+            }
+            self.write_block_bitmap(last as usize, bitmap)?;
+            self.block_groups.inner[last as usize].free_blocks_count +=
+                (new_len - old_len) as u16;
+            layout.superblock.free_blocks_count += new_len - old_len;
+        }
+
+        for group in old_groups..layout.groups {
+            let descriptor =
+                layout.init_group(&mut self.volume, group, 0, 0)?;
+            layout.superblock.free_blocks_count +=
+                descriptor.free_blocks_count as u32;
+            layout.superblock.free_inodes_count +=
+                descriptor.free_inodes_count as u32;
+            layout.superblock.inodes_count += superblock.inodes_per_group;
+            self.block_groups.inner.push(descriptor);
+        }
+        // the whole table is written back below
+        self.dirty_groups = vec![false; self.block_groups.inner.len()];
+
+        // keep the same share of reserved blocks
+        let new_blocks = layout.superblock.blocks_count;
+        layout.superblock.r_blocks_count =
+            (superblock.r_blocks_count as u64 * new_blocks as u64
+                / self.superblock.inner.blocks_count as u64) as u32;
+        self.superblock.inner = layout.superblock;
+        BlockGroupDescriptor::write_table(
+            &self.block_groups.inner,
+            &mut self.volume,
+            self.block_groups.offset,
+        )?;
+        self.write_superblock()?;
+        self.write_backups()
     }
 
     pub fn version(&self) -> (u32, u16) {
@@ -69,13 +1397,40 @@ impl<S: SectorSize, V: Volume<u8, S>> Ext2<S, V> {
         )
     }
 
-    pub fn inode_size(&self) -> usize {
-        if self.version().0 == 0 {
-            mem::size_of::<RawInode>()
-        } else {
-            // note: inodes bigger than 128 are not supported
-            self.superblock.inner.inode_size as usize
+    /// Whether files may reach 4 GiB, with the upper half of their size in
+    /// `size_high`, which takes the large file feature
+    pub fn large_files(&self) -> bool {
+        self.superblock
+            .inner
+            .features_ronly()
+            .contains(FeaturesROnly::RONLY_FILE_SIZE_64)
+    }
+
+    /// Turns on the large file feature, for a file about to reach 4 GiB.
+    /// Revision 0 file systems have no features, so they can't have such
+    /// files at all.
+    pub(crate) fn enable_large_files(&mut self) -> Result<()> {
+        if self.large_files() {
+            return Ok(());
         }
+        let superblock = &mut self.superblock.inner;
+        if superblock.is_rev_0() {
+            return Err(Error::Unsupported {
+                operation: "files of 4 GiB or more on revision 0",
+            });
+        }
+        superblock.features_ronly =
+            superblock.features_ronly() | FeaturesROnly::RONLY_FILE_SIZE_64;
+        self.write_superblock()
+    }
+
+    pub fn inode_size(&self) -> usize {
+        self.superblock.inner.inode_size() as usize
+    }
+
+    /// Returns the first non-reserved inode
+    pub fn first_inode(&self) -> u32 {
+        self.superblock.inner.first_inode()
     }
 
     pub fn inodes_count(&self) -> usize {
@@ -86,7 +1441,7 @@ impl<S: SectorSize, V: Volume<u8, S>> Ext2<S, V> {
         self.superblock.inner.inodes_count as _
     }
 
-    pub fn block_group_count(&self) -> Result<usize, Error> {
+    pub fn block_group_count(&self) -> Result<usize> {
         self.superblock
             .inner
             .block_group_count()
@@ -105,6 +1460,64 @@ impl<S: SectorSize, V: Volume<u8, S>> Ext2<S, V> {
         self.superblock.inner.free_blocks_count as _
     }
 
+    /// Returns statistics about the filesystem, with the free counts the
+    /// superblock keeps
+    pub fn stats(&self) -> FsStats {
+        let superblock = &self.superblock.inner;
+        self.stats_with(
+            superblock.free_blocks_count as u64,
+            superblock.free_inodes_count as u64,
+        )
+    }
+
+    /// Like `stats`, but with the free counts summed up from the block
+    /// group descriptors. The superblock's totals can drift from those on a
+    /// filesystem that wasn't unmounted cleanly.
+    pub fn counted_stats(&self) -> FsStats {
+        let (free_blocks, free_inodes) = self.block_groups.inner.iter().fold(
+            (0, 0),
+            |(blocks, inodes), descriptor| {
+                (
+                    blocks + descriptor.free_blocks_count as u64,
+                    inodes + descriptor.free_inodes_count as u64,
+                )
+            },
+        );
+        self.stats_with(free_blocks, free_inodes)
+    }
+
+    fn stats_with(&self, free_blocks: u64, free_inodes: u64) -> FsStats {
+        let superblock = &self.superblock.inner;
+        FsStats {
+            block_size: superblock.block_size() as u32,
+            frag_size: superblock.frag_size() as u32,
+            blocks: superblock.blocks_count as u64,
+            free_blocks,
+            available_blocks: free_blocks
+                .saturating_sub(superblock.reserved_blocks() as u64),
+            inodes: superblock.inodes_count as u64,
+            free_inodes,
+            max_name_len: MAX_NAME_LEN as u32,
+            uuid: superblock.uuid(),
+        }
+    }
+
+    /// Returns the number of blocks reserved for the superuser
+    pub fn reserved_block_count(&self) -> usize {
+        self.superblock.inner.reserved_blocks() as _
+    }
+
+    /// Sets the number of blocks reserved for the superuser and writes the
+    /// superblock back
+    pub fn set_reserved_block_count(
+        &mut self,
+        count: u32,
+    ) -> Result<()> {
+        self.check_writable()?;
+        self.superblock.inner.r_blocks_count = count;
+        self.write_superblock()
+    }
+
     pub fn block_size(&self) -> usize {
         self.superblock.inner.block_size()
     }
@@ -127,10 +1540,19 @@ mod tests {
     use std::fs::File;
     use std::cell::RefCell;
 
+    use error::Error;
     use sector::{Address, Size512};
-    use volume::Volume;
+    use sys::inode::Permissions;
+    use sys::superblock::{
+        Superblock, ERR_IGNORE, ERR_PANIC, ERR_RONLY, FS_CLEAN, FS_ERR,
+    };
+    use volume::tests::Recorder;
+    use volume::{Fault, Faulty, Metered, Volume};
 
-    use super::Ext2;
+    use super::mkfs::Builder;
+    use super::sync::tests::{fsck, fsck_with, mkfs};
+    use super::sync::Synced;
+    use super::{BlockGoal, Ext2, MountOptions};
 
     #[test]
     fn file_len() {
@@ -168,4 +1590,610 @@ mod tests {
         println!("version: {}.{}", vers.0, vers.1);
         assert_eq!(128, fs.inode_size());
     }
+
+    #[test]
+    fn bad_superblock() {
+        match Ext2::<Size512, _>::new(vec![0_u8; 4096]) {
+            Err(Error::BadMagic { magic: 0 }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // inodes_per_group zeroed, as a fuzzer would
+        let mut volume = std::fs::read("ext2.img").unwrap();
+        volume[1024 + 40..1024 + 44].copy_from_slice(&[0; 4]);
+        match Ext2::<Size512, _>::new_forced(volume) {
+            Err(Error::BadGroupSize {
+                inodes_per_group: 0,
+                ..
+            }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        let mut volume = std::fs::read("ext2.img").unwrap();
+        volume[1024 + 88..1024 + 90].copy_from_slice(&100_u16.to_le_bytes());
+        match Ext2::<Size512, _>::new_forced(volume) {
+            Err(Error::BadInodeSize { size: 100 }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // the inode table of group 0 moved past the last block
+        let mut volume = std::fs::read("ext2.img").unwrap();
+        volume[2048 + 8..2048 + 12].copy_from_slice(&4096_u32.to_le_bytes());
+        match Ext2::<Size512, _>::new(volume) {
+            Err(Error::Corrupt { .. }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn mount() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let count = fs.superblock().mnt_count();
+        assert_eq!(fs.superblock().state(), FS_CLEAN);
+
+        fs.mount(1234).unwrap();
+        let on_disk = |fs: &Ext2<Size512, Vec<u8>>| {
+            Superblock::find::<Size512, _>(&fs.volume).unwrap().0
+        };
+        assert_eq!(on_disk(&fs).mnt_count(), count + 1);
+        assert_eq!(on_disk(&fs).mtime(), 1234);
+        assert_eq!(on_disk(&fs).state() & FS_CLEAN, 0);
+        fs.unmount().unwrap();
+        assert_eq!(on_disk(&fs).state(), FS_CLEAN);
+        assert!(fsck(&fs.volume));
+
+        // errors, with each policy
+        fs.superblock.inner.state = FS_CLEAN | FS_ERR;
+        fs.superblock.inner.errors = ERR_IGNORE;
+        fs.mount(0).unwrap();
+        assert!(!fs.is_read_only());
+        fs.superblock.inner.errors = ERR_PANIC;
+        match fs.mount(0) {
+            Err(Error::FilesystemHasErrors { policy: ERR_PANIC }) => (),
+            other => panic!("{:?}", other),
+        }
+        fs.superblock.inner.errors = ERR_RONLY;
+        let mnt_count = fs.superblock().mnt_count();
+        fs.mount(0).unwrap();
+        assert!(fs.is_read_only());
+        assert_eq!(fs.superblock().mnt_count(), mnt_count);
+        fs.unmount().unwrap();
+    }
+
+    #[test]
+    fn stats() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let stats = fs.stats();
+        assert_eq!(stats.block_size, 1024);
+        assert_eq!(stats.frag_size, 1024);
+        assert_eq!(stats.blocks, 4096);
+        assert_eq!(stats.free_blocks, 3388);
+        assert_eq!(
+            stats.available_blocks,
+            3388 - fs.reserved_block_count() as u64
+        );
+        assert_eq!(stats.inodes, 1024);
+        assert_eq!(stats.free_inodes, 1008);
+        assert_eq!(stats.max_name_len, 255);
+        assert_eq!(
+            stats.uuid.to_string(),
+            "b6eb7283-2f21-4e15-8f0b-082b2ef69005"
+        );
+        assert_eq!(fs.counted_stats(), stats);
+
+        // the superblock's counts drifting doesn't affect the descriptors'
+        fs.superblock.inner.free_blocks_count = 10;
+        fs.superblock.inner.free_inodes_count = 20;
+        assert_eq!(fs.stats().free_blocks, 10);
+        assert_eq!(fs.stats().available_blocks, 0);
+        assert_eq!(fs.stats().free_inodes, 20);
+        assert_eq!(fs.counted_stats(), stats);
+    }
+
+    #[test]
+    fn recovery() {
+        for &(block_size, size) in &[(1024, 20 << 20), (2048, 40 << 20)] {
+            let mut volume = vec![0_u8; size];
+            Builder::new()
+                .block_size(block_size)
+                .build::<Size512, _>(&mut volume)
+                .unwrap();
+            let (fs, group) =
+                Ext2::<Size512, _>::new_with_recovery(volume.clone()).unwrap();
+            assert_eq!(group, 0);
+            drop(fs);
+
+            volume[..2048].iter_mut().for_each(|byte| *byte = 0);
+            match Ext2::<Size512, _>::new(volume.clone()) {
+                Err(Error::BadMagic { magic: 0 }) => (),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+            let (fs, group) =
+                Ext2::<Size512, _>::new_with_recovery(volume).unwrap();
+            assert_eq!(group, 1, "{} byte blocks", block_size);
+            let fs = Synced::with_inner(fs);
+            let names = fs
+                .root_inode()
+                .directory()
+                .unwrap()
+                .map(|entry| entry.unwrap().name)
+                .collect::<Vec<_>>();
+            assert!(names.contains(&b"lost+found".to_vec()));
+
+            // allocating writes the superblock back to the primary location
+            fs.mkdir(b"/dir", Permissions::U_READ).unwrap();
+            let volume = fs.inner().volume.clone();
+            assert!(fsck(&volume));
+            let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+            assert!(fs.lookup(b"/dir").unwrap().is_dir());
+        }
+    }
+
+    #[test]
+    fn unsupported_features() {
+        // ext2.img with `features` or'ed into the feature word at `offset`
+        // of its superblock
+        let image = |offset: usize, features: u32| {
+            let mut volume = std::fs::read("ext2.img").unwrap();
+            for (i, byte) in features.to_le_bytes().iter().enumerate() {
+                volume[1024 + offset + i] |= byte;
+            }
+            volume
+        };
+        const REQ: usize = 96;
+        const RONLY: usize = 100;
+
+        // extents, 64bit, a journal needing recovery and an unknown bit
+        for &features in &[0x40, 0x80, 0x04, 0x8000_0000] {
+            match Ext2::<Size512, _>::new(image(REQ, features)) {
+                Err(Error::UnsupportedFeature { incompat }) => {
+                    assert_eq!(incompat, features)
+                }
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+            let forced = Ext2::<Size512, _>::new_forced(image(REQ, features));
+            assert!(forced.is_ok());
+        }
+        // only the unsupported bits are reported
+        match Ext2::<Size512, _>::new(image(REQ, 0xc0)) {
+            Err(Error::UnsupportedFeature { incompat: 0xc0 }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // a journal alone is compatible
+        let fs = Ext2::<Size512, _>::new(image(92, 0x04)).unwrap();
+        assert!(!fs.is_read_only());
+
+        // huge files, btree directories and an unknown bit can be read but
+        // not written
+        for &features in &[0x08, 0x04, 0x8000_0000] {
+            let fs = Synced::with_inner(
+                Ext2::<Size512, _>::new(image(RONLY, features)).unwrap(),
+            );
+            assert!(fs.inner().is_read_only());
+            let mut data = Vec::new();
+            fs.lookup(b"/home/funky/README.md")
+                .unwrap()
+                .read_to_end(&mut data)
+                .unwrap();
+            assert_eq!(data.len(), 31);
+            match fs.create_file(b"/new", Permissions::U_READ) {
+                Err(Error::ReadOnlyFilesystem) => (),
+                other => panic!("{:?}", other.map(|_| ())),
+            }
+
+            let forced = Ext2::<Size512, _>::new_forced(image(RONLY, features));
+            assert!(!forced.unwrap().is_read_only());
+        }
+    }
+
+    #[test]
+    fn allocate_block() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+
+        let free = fs.free_block_count();
+        let block = fs.allocate_block(BlockGoal::Near(0), true).unwrap();
+        assert_eq!(fs.free_block_count(), free - 1);
+        assert!(fs.block_bitmap(0).unwrap().get(block as usize - 1));
+
+        // the counts must have been written back to the volume
+        let volume = fs.volume;
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        assert_eq!(fs.free_block_count(), free - 1);
+        assert_eq!(
+            { fs.block_groups.inner[0].free_blocks_count } as usize,
+            free - 1
+        );
+        assert!(fs.block_bitmap(0).unwrap().get(block as usize - 1));
+    }
+
+    #[test]
+    fn allocate_block_write_order() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(Recorder::new(volume)).unwrap();
+        let bitmap = fs.block_groups.inner[0].block_usage_addr as u64 * 1024;
+        fs.volume.commits.clear();
+
+        // the bitmap goes first, so that a block is never handed out twice
+        // if the counts don't make it to the disk
+        fs.allocate_block(BlockGoal::Near(0), true).unwrap();
+        let addresses: Vec<_> =
+            fs.volume.commits.iter().map(|&(addr, _)| addr).collect();
+        assert_eq!(addresses, [bitmap, 2048, 1024]);
+
+        assert_eq!(fs.volume.flushes, 0);
+        fs.flush().unwrap();
+        assert_eq!(fs.volume.flushes, 1);
+    }
+
+    #[test]
+    fn allocate_block_until_full() {
+        let mut volume = std::fs::read("ext2.img").unwrap();
+        {
+            // clear the padding bits past the end of the (only, partial)
+            // group so that the allocator has to respect `blocks_count`
+            let fs = Ext2::<Size512, _>::new(&mut volume[..]).unwrap();
+            let bitmap = fs.block_groups.inner[0].block_usage_addr as usize;
+            drop(fs);
+            for byte in &mut volume[bitmap * 1024 + 512..][..512] {
+                *byte = 0;
+            }
+        }
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+
+        let free = fs.free_block_count();
+        let mut blocks = Vec::new();
+        loop {
+            match fs.allocate_block(BlockGoal::Near(0), true) {
+                Ok(block) => blocks.push(block),
+                Err(Error::OutOfSpace) => break,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+        assert_eq!(blocks.len(), free);
+        assert_eq!(fs.free_block_count(), 0);
+        assert!(blocks.iter().all(|&block| block < 4096));
+
+        for block in blocks {
+            fs.free_block(block).unwrap();
+        }
+        assert_eq!(fs.free_block_count(), free);
+    }
+
+    #[test]
+    fn allocate_block_goal() {
+        let mut volume = vec![0_u8; 64 << 20];
+        Builder::new().build::<Size512, _>(&mut volume).unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+
+        // the goal itself, then the next free block
+        for &(goal, block) in
+            &[(3000, 3000), (3000, 3001), (2999, 2999), (2999, 3002)]
+        {
+            let allocated = fs.allocate_block(BlockGoal::Near(goal), true);
+            assert_eq!(allocated.unwrap(), block);
+        }
+        // past the end of the goal's group, and around to group 0
+        let last = 8 * 8192;
+        assert_eq!(
+            fs.allocate_block(BlockGoal::Near(last - 1), true).unwrap(),
+            last - 1
+        );
+        let block = fs.allocate_block(BlockGoal::Near(last - 1), true).unwrap();
+        assert_eq!((block - 1) / 8192, 7);
+
+        let block = fs.allocate_block(BlockGoal::Group(5), true).unwrap();
+        assert_eq!((block - 1) / 8192, 5);
+        match fs.allocate_block(BlockGoal::Group(8), true) {
+            Err(Error::OutOfBounds { index: 8 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn allocate_block_contiguous() {
+        let mut volume = vec![0_u8; 16 << 20];
+        Builder::new().build::<Size512, _>(&mut volume).unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        // two files growing side by side, a block at a time
+        let mut a = fs.create_file(b"/a", Permissions::U_READ).unwrap();
+        let mut b = fs.create_file(b"/b", Permissions::U_READ).unwrap();
+        let data = [b'x'; 1024];
+        for i in 0..1000 {
+            assert_eq!(a.write_at(i * 1024, &data).unwrap(), 1024);
+            assert_eq!(b.write_at(i * 1024, &data).unwrap(), 1024);
+        }
+        for file in &[a, b] {
+            let blocks: Vec<_> =
+                (0..1000).map(|i| file.block(i).unwrap().get()).collect();
+            let adjacent = blocks
+                .windows(2)
+                .filter(|pair| pair[1] == pair[0] + 1)
+                .count();
+            assert!(adjacent > 999 * 9 / 10, "{} adjacent", adjacent);
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn allocate_inode() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+
+        let free = { fs.superblock.inner.free_inodes_count };
+        let dirs = { fs.block_groups.inner[0].dirs_count };
+
+        let dir = fs.allocate_inode(2, true).unwrap();
+        assert!(dir >= fs.first_inode());
+        assert_eq!({ fs.block_groups.inner[0].dirs_count }, dirs + 1);
+
+        let mut inodes = vec![dir];
+        loop {
+            match fs.allocate_inode(2, false) {
+                Ok(inode) => inodes.push(inode),
+                Err(Error::OutOfSpace) => break,
+                Err(err) => panic!("{:?}", err),
+            }
+        }
+        assert_eq!(inodes.len(), free as usize);
+        assert!(inodes.iter().all(|inode| (11..=1024).contains(inode)));
+        assert_eq!({ fs.superblock.inner.free_inodes_count }, 0);
+        assert_eq!({ fs.block_groups.inner[0].free_inodes_count }, 0);
+
+        // mark the directory inode as such so that freeing it drops the count
+        let offset = fs.inode_address(dir).unwrap();
+        fs.volume[offset.into_index() as usize + 1] = 0x40;
+
+        for inode in inodes {
+            fs.free_inode(inode).unwrap();
+        }
+
+        let volume = fs.volume;
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        assert_eq!({ fs.superblock.inner.free_inodes_count }, free);
+        assert_eq!({ fs.block_groups.inner[0].free_inodes_count }, free as u16);
+        assert_eq!({ fs.block_groups.inner[0].dirs_count }, dirs);
+        assert_eq!(fs.inode_bitmap(0).unwrap().count_free(), free as usize);
+    }
+
+    #[test]
+    fn allocate_inode_orlov() {
+        let mut volume = vec![0_u8; 64 << 20];
+        Builder::new().build::<Size512, _>(&mut volume).unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let group = |inode: u32| (inode - 1) / 1024;
+
+        // directories in the root spread over the groups, skipping group 0
+        // which is below average after the root and lost+found
+        let mut groups: Vec<_> = (0..7)
+            .map(|_| group(fs.allocate_inode(2, true).unwrap()))
+            .collect();
+        groups.sort();
+        assert_eq!(groups, [1, 2, 3, 4, 5, 6, 7]);
+
+        // everything else stays close to its parent
+        let parent = 3 * 1024 + 1;
+        assert_eq!(group(fs.allocate_inode(parent, true).unwrap()), 3);
+        assert_eq!(group(fs.allocate_inode(parent, false).unwrap()), 3);
+        assert_eq!(group(fs.allocate_inode(2, false).unwrap()), 0);
+
+        // unless the parent's group is out of blocks
+        fs.block_groups.inner[3].free_blocks_count = 0;
+        assert_ne!(group(fs.allocate_inode(parent, false).unwrap()), 3);
+    }
+
+    #[test]
+    fn free_reserved_inode() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        assert!(fs.free_inode(2).is_err());
+        assert!(fs.free_inode(0).is_err());
+        assert!(fs.free_inode(1025).is_err());
+    }
+
+    #[test]
+    fn inode_round_trip() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume.clone()).unwrap();
+        for inode in 1..=fs.superblock.inner.inodes_count {
+            let raw = fs.read_inode(inode).unwrap();
+            fs.write_inode(inode, &raw).unwrap();
+        }
+        assert!(fs.volume == volume);
+        assert!(fs.read_inode(0).is_err());
+        assert!(fs.read_inode(1025).is_err());
+    }
+
+    #[test]
+    fn write_inode() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let mut raw = fs.read_inode(14).unwrap();
+        raw.mtime = 0x12345678;
+        raw.direct_pointer[11] = 0x9abcdef0;
+        fs.write_inode(14, &raw).unwrap();
+
+        let offset = fs.inode_address(14).unwrap().into_index() as usize;
+        let bytes = &fs.volume[offset..offset + 128];
+        assert_eq!(bytes[16..20], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(bytes[84..88], [0xf0, 0xde, 0xbc, 0x9a]);
+        assert_eq!(bytes[..], raw.to_bytes()[..]);
+    }
+
+    #[test]
+    fn backup_groups() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        assert!(fs.superblock.inner.backup_groups().is_empty());
+        let sparse = [0, 1, 3, 5, 7, 9, 25, 27, 49, 81, 125, 243, 343];
+        for group in 0..400 {
+            assert_eq!(
+                fs.superblock.inner.has_backup(group),
+                sparse.contains(&group)
+            );
+        }
+    }
+
+    #[test]
+    fn write_backups() {
+        // 8 groups of 8 MiB, with backups in groups 1, 3, 5 and 7
+        let volume = match mkfs(64 << 20) {
+            Some(volume) => volume,
+            None => return,
+        };
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert_eq!(fs.inner().superblock.inner.backup_groups(), [1, 3, 5, 7]);
+
+        let data = vec![b'x'; 20 << 20];
+        let mut file = fs.create_file(b"/file", Permissions::U_READ).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        fs.mkdir(b"/dir", Permissions::U_READ).unwrap();
+
+        let mut fs = fs.inner();
+        // the backups still hold the counts from before
+        assert!(!fsck_with(&fs.volume, &["-b", "8193", "-B", "1024"]));
+        fs.write_backups().unwrap();
+        assert!(fsck(&fs.volume));
+        for &block in &["8193", "24577", "40961", "57345"] {
+            assert!(fsck_with(&fs.volume, &["-b", block, "-B", "1024"]));
+        }
+    }
+
+    #[test]
+    fn resize() {
+        let mut volume = vec![0_u8; 64 << 20];
+        Builder::new()
+            .blocks(5000)
+            .build::<Size512, _>(&mut volume)
+            .unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let data = vec![b'x'; 3 << 20];
+        let mut file = fs.create_file(b"/file", Permissions::U_READ).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+
+        // fills up group 0 and adds a partial group 2
+        let (free_blocks, inodes) = {
+            let superblock = &fs.inner().superblock.inner;
+            ({ superblock.free_blocks_count }, { superblock.inodes_count })
+        };
+        fs.inner().resize(20000).unwrap();
+        {
+            let fs = fs.inner();
+            let superblock = &fs.superblock.inner;
+            assert_eq!(fs.block_group_count().unwrap(), 3);
+            assert_eq!({ superblock.blocks_count }, 20000);
+            assert_eq!({ superblock.inodes_count }, inodes * 3);
+            assert!({ superblock.free_blocks_count } > free_blocks + 14000);
+            assert!(fsck(&fs.volume));
+            assert!(fsck_with(&fs.volume, &["-b", "8193", "-B", "1024"]));
+        }
+
+        // a last group of 30 blocks isn't worth keeping
+        fs.inner().resize(3 * 8192 + 1 + 30).unwrap();
+        assert_eq!({ fs.inner().superblock.inner.blocks_count }, 3 * 8192 + 1);
+        assert!(fsck(&fs.inner().volume));
+
+        fs.inner().resize(65536).unwrap();
+        assert_eq!(fs.inner().block_group_count().unwrap(), 8);
+        assert!(fsck(&fs.inner().volume));
+        let data = vec![b'y'; 40 << 20];
+        let mut file = fs.create_file(b"/big", Permissions::U_READ).unwrap();
+        assert_eq!(file.write_at(0, &data).unwrap(), data.len());
+        let mut fs = fs.inner();
+        fs.write_backups().unwrap();
+        assert!(fsck(&fs.volume));
+        for &block in &["8193", "24577", "40961", "57345"] {
+            assert!(fsck_with(&fs.volume, &["-b", block, "-B", "1024"]));
+        }
+    }
+
+    #[test]
+    fn resize_errors() {
+        // one descriptor table block, which holds 32 descriptors
+        let mut volume = vec![0_u8; 33 * 8192 * 1024];
+        Builder::new()
+            .blocks(8193)
+            .build::<Size512, _>(&mut volume)
+            .unwrap();
+        let mut fs = Ext2::<Size512, _>::new(volume).unwrap();
+        match fs.resize(8000) {
+            Err(Error::ShrinkUnsupported {
+                blocks: 8193,
+                new_blocks: 8000,
+            }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.resize(33 * 8192 + 1) {
+            Err(Error::VolumeTooSmall { blocks }) => {
+                assert_eq!(blocks, 33 * 8192 + 1)
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.resize(32 * 8192 + 1000) {
+            Err(Error::DescriptorTableFull { groups: 33 }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!({ fs.superblock.inner.blocks_count }, 8193);
+
+        fs.resize(32 * 8192 + 1).unwrap();
+        assert_eq!(fs.block_group_count().unwrap(), 32);
+    }
+
+    #[test]
+    fn descriptor_table_in_memory() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut options = MountOptions::new();
+        options.inode_cache(0);
+        let fs = Synced::<Ext2<Size512, _>>::new_with_options(
+            Faulty::new(Metered::new(volume)),
+            &options,
+        )
+        .unwrap();
+        // with 1 KiB blocks, the table is in block 2
+        fs.inner().volume.inject(Fault::FailSectors(4..6));
+        fs.inner().volume.inner().reset();
+        for _ in 0..100 {
+            let mut contents = Vec::new();
+            fs.lookup("/home/funky/README.md")
+                .unwrap()
+                .read_to_end(&mut contents)
+                .unwrap();
+            assert_eq!(contents.len(), 31);
+        }
+        // the inodes on the way were read every time, but never the table
+        assert!(fs.inner().volume.inner().stats().reads >= 100 * 4);
+        let fs = fs.inner();
+        assert_eq!(
+            fs.group_descriptor(0).inode_table_block(),
+            fs.block_groups()[0].inode_table_block()
+        );
+    }
+
+    #[test]
+    fn write_dirty_descriptors() {
+        let mut volume = vec![0_u8; 4 * 8192 * 1024];
+        Builder::new()
+            .blocks(4 * 8192)
+            .build::<Size512, _>(&mut volume)
+            .unwrap();
+        let mut fs = Ext2::<Size512, _>::new(Recorder::new(volume)).unwrap();
+        let table = fs.block_groups.offset.into_index();
+        fs.volume.commits.clear();
+
+        fs.group_descriptor_mut(1).dirs_count = 5;
+        fs.group_descriptor_mut(3).dirs_count = 7;
+        fs.write_block_groups().unwrap();
+        assert_eq!(fs.volume.commits, [(table + 32, 32), (table + 96, 32)]);
+        fs.volume.commits.clear();
+        fs.write_block_groups().unwrap();
+        assert!(fs.volume.commits.is_empty());
+
+        let fs = Ext2::<Size512, _>::new(fs.volume.inner).unwrap();
+        assert_eq!({ fs.group_descriptor(1).dirs_count }, 5);
+        assert_eq!({ fs.group_descriptor(3).dirs_count }, 7);
+    }
 }
@@ -0,0 +1,161 @@
+//! The options a filesystem is mounted with
+
+use sys::inode::{Flags, Inode as RawInode};
+
+use super::{default_clock, DEFAULT_INODE_CACHE};
+use super::sync::MAX_SYMLINKS;
+
+/// How a filesystem is to be mounted by `Ext2::new_with_options`. Every
+/// option starts out as `Ext2::new` has it:
+///
+/// ```
+/// # use ext2::fs::{AtimePolicy, Ext2, MountOptions};
+/// # use ext2::sector::Size512;
+/// let volume = std::fs::read("ext2.img").unwrap();
+/// let mut options = MountOptions::new();
+/// options.read_only(true).atime(AtimePolicy::Relatime);
+/// let fs = Ext2::<Size512, _>::new_with_options(volume, &options).unwrap();
+/// assert!(fs.is_read_only());
+/// ```
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    pub(crate) read_only: bool,
+    pub(crate) check_features: bool,
+    pub(crate) atime: AtimePolicy,
+    pub(crate) clock: fn() -> u32,
+    pub(crate) sparse_writes: bool,
+    pub(crate) max_symlinks: usize,
+    pub(crate) inode_cache: usize,
+    pub(crate) dentry_cache: usize,
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions::new()
+    }
+}
+
+impl MountOptions {
+    /// Returns the options of a writable mount that refuses filesystems
+    /// with unsupported features, never updates access times, takes the
+    /// time from the system clock where there is one, follows up to
+    /// `MAX_SYMLINKS` symlinks per path and caches `DEFAULT_INODE_CACHE`
+    /// inodes but no directory entries
+    pub fn new() -> MountOptions {
+        MountOptions {
+            read_only: false,
+            check_features: true,
+            atime: AtimePolicy::Never,
+            clock: default_clock,
+            sparse_writes: false,
+            max_symlinks: MAX_SYMLINKS,
+            inode_cache: DEFAULT_INODE_CACHE,
+            dentry_cache: 0,
+        }
+    }
+
+    /// Mounts the filesystem read-only: every operation that would modify
+    /// the volume fails with `Error::ReadOnlyFilesystem`, which is checked
+    /// again right before anything is written to it. A filesystem needing
+    /// features that are only supported for reading is mounted read-only
+    /// whatever this says.
+    pub fn read_only(&mut self, read_only: bool) -> &mut MountOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Whether to check the features the filesystem needs, refusing it if
+    /// it needs unsupported ones to be read and mounting it read-only if it
+    /// needs them to be written. Turning this off mounts it as it is, which
+    /// may well misinterpret or corrupt it.
+    pub fn check_features(&mut self, check: bool) -> &mut MountOptions {
+        self.check_features = check;
+        self
+    }
+
+    /// Sets when reading a file updates its access time
+    pub fn atime(&mut self, policy: AtimePolicy) -> &mut MountOptions {
+        self.atime = policy;
+        self
+    }
+
+    /// Sets the function giving the current POSIX time, which modified
+    /// inodes are stamped with. Without `std`, the default reports the
+    /// epoch.
+    pub fn clock(&mut self, clock: fn() -> u32) -> &mut MountOptions {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether writes of zeroes into holes leave the holes alone rather
+    /// than allocating blocks to hold the zeroes
+    pub fn sparse_writes(&mut self, sparse: bool) -> &mut MountOptions {
+        self.sparse_writes = sparse;
+        self
+    }
+
+    /// Sets how many symlinks resolving a single path may follow before it
+    /// fails with `Error::TooManySymlinks`
+    pub fn max_symlinks(&mut self, max: usize) -> &mut MountOptions {
+        self.max_symlinks = max;
+        self
+    }
+
+    /// Sets how many inodes are kept in memory once read, so that those
+    /// read over and over, such as the directories paths go through, are
+    /// only read off the volume once. Each takes up about 150 bytes; 0
+    /// turns the cache off.
+    pub fn inode_cache(&mut self, capacity: usize) -> &mut MountOptions {
+        self.inode_cache = capacity;
+        self
+    }
+
+    /// Sets how many directory entries are kept in memory once looked up,
+    /// names found missing included, so that resolving the same paths
+    /// again doesn't read the directories they go through. Each takes up
+    /// about 50 bytes plus its name; 0, the default, turns the cache off.
+    /// Together with the inode cache, a path resolved before can be
+    /// resolved again without reading the volume at all.
+    pub fn dentry_cache(&mut self, capacity: usize) -> &mut MountOptions {
+        self.dentry_cache = capacity;
+        self
+    }
+}
+
+/// When reading a file stamps it with the time it was accessed, see
+/// `MountOptions::atime`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AtimePolicy {
+    /// Reads leave the access time alone, like the `noatime` mount option
+    #[default]
+    Never,
+    /// Every read updates the access time unless it's the current time
+    /// already, like `strictatime`
+    Always,
+    /// A read updates the access time if it's no later than the modification
+    /// or change time, or at least a day old, like `relatime`
+    Relatime,
+}
+
+/// Seconds after which `AtimePolicy::Relatime` updates an access time anyway
+const RELATIME_INTERVAL: u32 = 24 * 60 * 60;
+
+impl AtimePolicy {
+    /// Whether reading `inode` at `now` is to update its access time. Inodes
+    /// with the `DONT_ATIME` flag are never updated.
+    pub(crate) fn wants_update(self, inode: &RawInode, now: u32) -> bool {
+        if { inode.flags }.contains(Flags::DONT_ATIME) {
+            return false;
+        }
+        let atime = inode.atime;
+        match self {
+            AtimePolicy::Never => false,
+            AtimePolicy::Always => atime != now,
+            AtimePolicy::Relatime => {
+                atime <= inode.mtime
+                    || atime <= inode.ctime
+                    || now.wrapping_sub(atime) >= RELATIME_INTERVAL
+            }
+        }
+    }
+}
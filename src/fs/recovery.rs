@@ -0,0 +1,267 @@
+//! Finding deleted files and recovering their contents.
+//!
+//! Deleting a file frees its inode and blocks and sets its deletion time,
+//! but may leave the rest of the inode as it was, block pointers included,
+//! as `debugfs` and older kernels do. Until the blocks are allocated again
+//! the contents are still there to be read.
+
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+use sys::inode::{FileType, Inode as RawInode, InodeBlock};
+use volume::Volume;
+
+use super::{Ext2, Metadata};
+
+/// A deleted inode `list_deleted` found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeletedInode {
+    /// When the inode was deleted, in POSIX time
+    pub dtime: u32,
+    /// The inode as it was left when deleted. Its length may have been
+    /// reset to 0, in which case `recover` goes by its blocks instead.
+    pub metadata: Metadata,
+}
+
+/// Lists the free inodes that were files, directories or symlinks and have
+/// a deletion time and a length or block pointers left, in inode order.
+/// Whether their contents can still be recovered is for `recover` to find
+/// out.
+pub fn list_deleted<S: SectorSize, V: Volume<u8, S>>(
+    fs: &Ext2<S, V>,
+) -> Result<Vec<DeletedInode>> {
+    let inodes_per_group = fs.superblock().inodes_per_group;
+    let first_inode = fs.first_inode();
+    let mut deleted = Vec::new();
+    for group in 0..fs.block_group_count()? {
+        let bitmap = fs.inode_bitmap(group)?;
+        for index in 0..bitmap.len() {
+            let inode = group as u32 * inodes_per_group + index as u32 + 1;
+            if inode < first_inode || bitmap.get(index) {
+                continue;
+            }
+            let raw = fs.read_inode(inode)?;
+            if !is_deleted(&raw) {
+                continue;
+            }
+            let metadata = Metadata::new(inode, &raw, fs.large_files());
+            let kept = !metadata.is_empty()
+                || { raw.direct_pointer }.iter().any(|&block| block != 0)
+                || raw.indirect_pointer != 0
+                || raw.doubly_indirect != 0
+                || raw.triply_indirect != 0;
+            let has_contents = matches!(
+                metadata.file_type(),
+                FileType::File | FileType::Directory | FileType::Symlink
+            );
+            if kept && has_contents {
+                deleted.push(DeletedInode {
+                    dtime: raw.dtime,
+                    metadata,
+                });
+            }
+        }
+    }
+    Ok(deleted)
+}
+
+/// Streams the contents of the deleted inode `inode` to `out`, a block at a
+/// time, holes as zeroes, and returns their length. Without a length left
+/// in the inode, every block up to the last one is streamed in full.
+///
+/// Before anything is streamed, the inode and every block it points at,
+/// indirect blocks included, are checked to still be free: one that's been
+/// allocated again fails with `Error::Overwritten`, as its old contents
+/// may be gone. A block that was reused and freed again can't be told
+/// apart, so what's recovered is only as good as the blocks were left.
+pub fn recover<S: SectorSize, V: Volume<u8, S>, F: FnMut(&[u8])>(
+    fs: &Ext2<S, V>,
+    inode: u32,
+    mut out: F,
+) -> Result<u64> {
+    let raw = fs.read_inode(inode)?;
+    let (group, index) = fs.inode_position(inode)?;
+    if !is_deleted(&raw) || fs.inode_bitmap(group)?.get(index) {
+        return Err(Error::Overwritten { inode, block: None });
+    }
+
+    let block_size = fs.block_size();
+    let len = raw.size_with(fs.large_files());
+    if raw.type_perm.file_type() == FileType::Symlink
+        && len < 60
+        && raw.sectors_count == 0
+    {
+        // the target is kept in place of the block pointers
+        let bytes = raw.to_bytes();
+        out(&bytes[40..40 + len as usize]);
+        return Ok(len);
+    }
+
+    let mut bitmaps = BTreeMap::new();
+    let mut data = Vec::new();
+    for block in raw.blocks(&fs.volume, fs.superblock()) {
+        let block = block?;
+        let (group, index) = match fs.block_position(block.block()) {
+            Some(position) => position,
+            None => {
+                return Err(Error::Corrupt {
+                    reason: "block pointer outside the filesystem",
+                })
+            }
+        };
+        let bitmap = match bitmaps.entry(group) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(fs.block_bitmap(group)?),
+        };
+        if bitmap.get(index) {
+            return Err(Error::Overwritten {
+                inode,
+                block: Some(block.block()),
+            });
+        }
+        if let InodeBlock::Data { index, block } = block {
+            data.push((index, block));
+        }
+    }
+
+    let len = match data.last() {
+        Some(&(index, _)) if len == 0 => (index + 1) * block_size as u64,
+        _ => len,
+    };
+    let zeroes = vec![0; block_size];
+    let mut position = 0;
+    let pad = |out: &mut F, position: &mut u64, end: u64| {
+        while *position < end {
+            let count = (end - *position).min(block_size as u64);
+            out(&zeroes[..count as usize]);
+            *position += count;
+        }
+    };
+    let log_block_size = fs.log_block_size();
+    for (index, block) in data {
+        let offset = index * block_size as u64;
+        if offset >= len {
+            break;
+        }
+        pad(&mut out, &mut position, offset);
+        let start = Address::with_block_size(block, 0, log_block_size);
+        let end = Address::with_block_size(block + 1, 0, log_block_size);
+        let bytes = fs.volume.slice(start..end).map_err(|err| err.into())?;
+        let count = (len - offset).min(block_size as u64);
+        if (bytes.len() as u64) < count {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        out(&bytes[..count as usize]);
+        position += count;
+    }
+    pad(&mut out, &mut position, len);
+    Ok(len)
+}
+
+/// Whether `raw` was deleted and hasn't been reused since
+fn is_deleted(raw: &RawInode) -> bool {
+    raw.dtime != 0 && raw.hard_links == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use fs::sync::tests::debugfs;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::Size512;
+
+    use super::{list_deleted, recover};
+
+    fn recovered(fs: &Ext2<Size512, Vec<u8>>, inode: u32) -> Vec<u8> {
+        let mut contents = Vec::new();
+        let len = recover(fs, inode, |bytes| contents.extend_from_slice(bytes))
+            .unwrap();
+        assert_eq!(len, contents.len() as u64);
+        contents
+    }
+
+    #[test]
+    fn undelete() {
+        let image = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(image.clone()).unwrap();
+        let read = |path: &str| {
+            let mut contents = Vec::new();
+            fs.lookup(path).unwrap().read_to_end(&mut contents).unwrap();
+            contents
+        };
+        // unl has indirect blocks
+        let (readme, unl) =
+            (read("/home/funky/README.md"), read("/home/funky/unl"));
+
+        let volume = match debugfs(
+            &image,
+            &["rm /home/funky/README.md", "rm /home/funky/unl"],
+        ) {
+            Some(volume) => volume,
+            None => return,
+        };
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let deleted = list_deleted(&fs).unwrap();
+        let found = deleted
+            .iter()
+            .map(|inode| (inode.metadata.ino(), inode.metadata.len()))
+            .collect::<Vec<_>>();
+        // inode 17 was deleted when the image was made
+        assert_eq!(found, vec![(14, 31), (15, 537600), (17, 4096)]);
+        assert!(deleted.iter().all(|inode| inode.dtime != 0));
+        assert!(recovered(&fs, 14) == readme);
+        assert!(recovered(&fs, 15) == unl);
+
+        // without a length, whole blocks are recovered
+        let mut fs = fs;
+        let mut raw = fs.read_inode(14).unwrap();
+        raw.size_low = 0;
+        fs.write_inode(14, &raw).unwrap();
+        let contents = recovered(&fs, 14);
+        assert_eq!(contents.len(), fs.block_size());
+        assert_eq!(&contents[..31], &readme[..]);
+    }
+
+    #[test]
+    fn overwritten() {
+        let image = std::fs::read("ext2.img").unwrap();
+        // README.md is in block 162
+        let volume =
+            match debugfs(&image, &["rm /home/funky/README.md", "setb 162"]) {
+                Some(volume) => volume,
+                None => return,
+            };
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        let mut streamed = false;
+        match recover(&fs, 14, |_| streamed = true) {
+            Err(Error::Overwritten {
+                inode: 14,
+                block: Some(162),
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(!streamed);
+
+        // an inode in use isn't deleted
+        match recover(&fs, 13, |_| ()) {
+            Err(Error::Overwritten {
+                inode: 13,
+                block: None,
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+        let fs = Ext2::<Size512, _>::new(image).unwrap();
+        let deleted = list_deleted(&fs).unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].metadata.ino(), 17);
+        assert_eq!(recovered(&fs, 17).len(), 4096);
+    }
+}
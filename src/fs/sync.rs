@@ -2,33 +2,91 @@ use core::fmt::{self, Debug};
 use core::iter::Iterator;
 use core::num::NonZeroU32;
 
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
 use genfs::*;
-use spin::{Mutex, MutexGuard};
+use spin::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use error::Error;
+use error::{Error, Result};
 use sector::{Address, SectorSize};
-use sys::inode::Inode as RawInode;
-use volume::Volume;
-
-use super::Ext2;
-
+use sys::dirent::{dirent_len, DirEntryHeader, DirEntryIter, DirEntryRef};
+use sys::inode::{
+    self, BlockPath, BlockWalk, FileType, Flags, Inode as RawInode,
+    InodeBlock, Permissions, TypePerm,
+};
+use sys::acl::Acl;
+use sys::htree::{
+    dx_hash, dx_node_entries, dx_probe, DxRoot, DX_HASH_LEGACY_UNSIGNED,
+    DX_HASH_TEA,
+};
+use sys::superblock::{
+    FeaturesOptional, FeaturesRequired, FLAGS_UNSIGNED_HASH,
+};
+use sys::xattr::{self, XattrIter};
+use volume::{Volume, VolumeSlice};
+
+use super::{
+    walk, AtimePolicy, Ext2, Metadata, MountOptions, Usage, MAX_NAME_LEN,
+};
+
+/// Permissions of files created through `Fs::open`, i.e. 0666 with a umask of
+/// 022 applied
+const DEFAULT_FILE_PERM: Permissions =
+    Permissions::from_bits_truncate(0o644);
+
+/// Permissions of directories created through `Fs::create_dir`, i.e. 0777 with
+/// a umask of 022 applied
+const DEFAULT_DIR_PERM: Permissions =
+    Permissions::from_bits_truncate(0o755);
+
+/// Custom `OpenOptions` flag that makes `Fs::open` return a symlink in the
+/// final path component itself instead of following it
+pub const O_NOFOLLOW: u32 = 0o400000;
+
+/// Maximum number of symlinks followed while resolving a single path
+pub const MAX_SYMLINKS: usize = 40;
+
+/// Bytes of an inode table `Inodes` reads at once
+const INODE_READAHEAD: usize = 16 << 10;
+
+/// A value shared behind a reader-writer lock, with handles as cheap to
+/// clone as an `Arc`. This is how a filesystem is shared, between inodes and
+/// between threads: lookups, reads and directory listings lock it for
+/// reading, so any number of them run side by side, while anything that
+/// modifies it locks it for writing.
+///
+/// The lock comes first in the lock order. The inode and directory entry
+/// caches of `Ext2` have locks of their own, taken with it held for as long
+/// as a lookup in them takes, with no other lock taken meanwhile. The lock
+/// isn't reentrant: taking it again while holding it spins forever.
+///
+/// `Synced<Ext2<S, V>>` is `Send` and `Sync` if the volume is both, since
+/// readers use it at the same time. Volumes counting their accesses in a
+/// `Cell`, such as `Metered`, can't be shared this way.
 pub struct Synced<T> {
-    inner: Arc<Mutex<T>>,
+    inner: Arc<RwLock<T>>,
 }
 
 impl<T> Synced<T> {
     pub fn with_inner(inner: T) -> Synced<T> {
         Synced {
-            inner: Arc::new(Mutex::new(inner)),
+            inner: Arc::new(RwLock::new(inner)),
         }
     }
 
-    pub fn inner(&self) -> MutexGuard<T> {
-        self.inner.lock()
+    /// Locks the value for writing, waiting for every reader to be done
+    pub fn inner(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.write()
+    }
+
+    /// Locks the value for reading, alongside any other readers
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read()
     }
 }
 
@@ -41,25 +99,84 @@ impl<T> Clone for Synced<T> {
 }
 
 impl<S: SectorSize, V: Volume<u8, S>> Synced<Ext2<S, V>> {
-    pub fn new(volume: V) -> Result<Synced<Ext2<S, V>>, Error> {
+    pub fn new(volume: V) -> Result<Synced<Ext2<S, V>>> {
         Ext2::new(volume).map(Synced::with_inner)
     }
 
+    /// Like `new`, but through `Ext2::new_forced`
+    pub fn new_forced(volume: V) -> Result<Synced<Ext2<S, V>>> {
+        Ext2::new_forced(volume).map(Synced::with_inner)
+    }
+
+    /// Like `new`, but through `Ext2::new_with_options`
+    pub fn new_with_options(
+        volume: V,
+        options: &MountOptions,
+    ) -> Result<Synced<Ext2<S, V>>> {
+        Ext2::new_with_options(volume, options).map(Synced::with_inner)
+    }
+
+    /// Like `new`, but through `Ext2::new_with_recovery`
+    pub fn new_with_recovery(
+        volume: V,
+    ) -> Result<(Synced<Ext2<S, V>>, u32)> {
+        Ext2::new_with_recovery(volume)
+            .map(|(fs, group)| (Synced::with_inner(fs), group))
+    }
+
     pub fn root_inode(&self) -> Inode<S, V> {
         self.inode_nth(2).unwrap()
     }
 
     pub fn inode_nth(&self, index: usize) -> Option<Inode<S, V>> {
-        self.inodes_nth(index).next()
+        self.open_inode_unlinked(index as u32).ok()
     }
 
     pub fn inodes(&self) -> Inodes<S, V> {
         self.inodes_nth(1)
     }
 
+    /// Opens inode `ino` directly rather than through a path, as for NFS
+    /// file handles. The inode has to be in use: an inode with no links or
+    /// a deletion time is `Error::InodeNotFound`, as is a number past the
+    /// last inode. Reserved inodes, such as the bad blocks inode, are always
+    /// in use.
+    pub fn open_inode(&self, ino: u32) -> Result<Inode<S, V>> {
+        let inode = self.open_inode_unlinked(ino)?;
+        let first = self.read().superblock.inner.first_inode();
+        let free = inode.inner.hard_links == 0 || inode.inner.dtime != 0;
+        if ino >= first && free {
+            return Err(Error::InodeNotFound { inode: ino });
+        }
+        Ok(inode)
+    }
+
+    /// Opens inode `ino` like `open_inode`, whether or not it's in use, for
+    /// tools recovering deleted files
+    pub fn open_inode_unlinked(&self, ino: u32) -> Result<Inode<S, V>> {
+        self.inode_locked(&self.read(), ino)
+    }
+
+    /// Reads inode `ino` through `fs`, the file system this guards, for
+    /// callers already holding its lock
+    fn inode_locked(&self, fs: &Ext2<S, V>, ino: u32) -> Result<Inode<S, V>> {
+        let (raw, addr) = fs.read_inode_at(ino)?;
+        Ok(Inode::new(self.clone(), raw, addr, ino, fs.large_files()))
+    }
+
+    /// Lists the directory with inode `ino`, which has to be in use as with
+    /// `open_inode`
+    pub fn read_dir_inode(&self, ino: u32) -> Result<Directory<S, V>> {
+        let inode = self.open_inode(ino)?;
+        inode.directory().ok_or(Error::NotADirectory {
+            inode: ino,
+            name: String::new(),
+        })
+    }
+
     pub fn inodes_nth(&self, index: usize) -> Inodes<S, V> {
         assert!(index > 0, "inodes are 1-indexed");
-        let inner = self.inner();
+        let inner = self.read();
         Inodes {
             fs: self.clone(),
             log_block_size: inner.log_block_size(),
@@ -67,111 +184,720 @@ impl<S: SectorSize, V: Volume<u8, S>> Synced<Ext2<S, V>> {
             inodes_per_group: inner.inodes_count(),
             inodes_count: inner.total_inodes_count(),
             index,
+            table: None,
         }
     }
 
-    pub fn sector_size(&self) -> usize {
-        S::SIZE
+    /// Resolves `abs_path` to its inode, following symlinks anywhere in the
+    /// path.
+    ///
+    /// Paths are bytes, as names on ext2 are, so names that aren't UTF-8
+    /// can be reached too; `&str` works just as well. Repeated slashes count
+    /// as one, `.` stays in the directory and `..` goes up to its parent,
+    /// with the root being its own parent. A trailing slash requires the
+    /// path to resolve to a directory.
+    pub fn lookup<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+    ) -> Result<Inode<S, V>> {
+        self.resolve(abs_path.as_ref(), true)
     }
 
-    pub fn log_sector_size(&self) -> u32 {
-        S::LOG_SIZE
+    /// Resolves `abs_path` to its inode like `lookup`, except that a symlink
+    /// in the final component is returned itself rather than followed
+    pub fn lookup_nofollow<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+    ) -> Result<Inode<S, V>> {
+        self.resolve(abs_path.as_ref(), false)
     }
-}
 
-impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
-    type Path = [u8];
-    type PathOwned = Vec<u8>;
-    type File = Inode<S, V>;
-    type Dir = Directory<S, V>;
-    type DirEntry = DirectoryEntry;
-    type Metadata = (); // TODO
-    type Permissions = (); // TODO
-    type Error = Error;
+    /// The names of the extended attributes of the inode at `abs_path`, see
+    /// `Inode::list_xattrs`. A symlink in the final component is followed,
+    /// as `getfattr` does.
+    pub fn list_xattrs<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+    ) -> Result<Vec<Vec<u8>>> {
+        self.lookup(abs_path)?.list_xattrs()
+    }
 
-    fn open(
+    /// The value of the extended attribute `name` of the inode at
+    /// `abs_path`, see `Inode::get_xattr`. A symlink in the final component
+    /// is followed, as `getfattr` does.
+    pub fn get_xattr<P: AsRef<[u8]>, N: AsRef<[u8]>>(
         &self,
-        abs_path: &Self::Path,
-        _options: &OpenOptions<Self::Permissions>,
-    ) -> Result<Self::File, Self::Error> {
-        fn inner<'a, S, V, I>(
-            fs: &Synced<Ext2<S, V>>,
-            inode: Inode<S, V>,
-            mut path: I,
-            abs_path: &[u8],
-        ) -> Result<Inode<S, V>, Error>
-        where
-            S: SectorSize,
-            V: Volume<u8, S>,
-            I: Iterator<Item = &'a [u8]>,
-        {
-            let name = match path.next() {
-                Some(name) => name,
-                None => return Ok(inode),
+        abs_path: P,
+        name: N,
+    ) -> Result<Option<Vec<u8>>> {
+        self.lookup(abs_path)?.get_xattr(name)
+    }
+
+    /// The access ACL of the inode at `abs_path`, see `Inode::get_acl`. A
+    /// symlink in the final component is followed, as `getfacl` does.
+    pub fn get_acl<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<Option<Acl>> {
+        self.lookup(abs_path)?.get_acl()
+    }
+
+    /// The default ACL of the directory at `abs_path`, see
+    /// `Inode::get_default_acl`
+    pub fn get_default_acl<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+    ) -> Result<Option<Acl>> {
+        self.lookup(abs_path)?.get_default_acl()
+    }
+
+    /// The metadata of the inode at `abs_path`, following a symlink in the
+    /// final component like `stat`
+    pub fn metadata<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<Metadata> {
+        Ok(self.lookup(abs_path)?.metadata())
+    }
+
+    /// Adds up the sizes of the files under `abs_path` and the space they
+    /// take up, as `du` does. A file with several hard links in the tree is
+    /// only counted once, and symlinks aren't followed past `abs_path`
+    /// itself.
+    pub fn disk_usage<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<Usage> {
+        let abs_path = abs_path.as_ref();
+        let top = self.metadata(abs_path)?;
+        let mut usage = Usage::default();
+        usage.add(&top);
+        if !top.is_dir() {
+            return Ok(usage);
+        }
+
+        let mut seen = BTreeSet::new();
+        for entry in walk(self, abs_path) {
+            let metadata = entry?.1.metadata()?;
+            if metadata.is_dir()
+                || metadata.nlink() < 2
+                || seen.insert(metadata.ino())
+            {
+                usage.add(&metadata);
+            }
+        }
+        Ok(usage)
+    }
+
+    fn resolve(
+        &self,
+        abs_path: &[u8],
+        follow: bool,
+    ) -> Result<Inode<S, V>> {
+        check_absolute(abs_path)?;
+        let mut hops = 0;
+        let root = self.root_inode();
+        self.walk(root, abs_path, abs_path, follow, &mut hops, &mut None)
+    }
+
+    /// Walks `path` starting from the directory `inode`. Symlinks are
+    /// followed in every component but the last, which is only followed if
+    /// `follow` is set; `hops` counts the symlinks followed so far.
+    ///
+    /// If `depth` is set, the walk is kept beneath the directory `depth`
+    /// levels above `inode`: no `..` may lead out of it, and no symlink may
+    /// be absolute.
+    ///
+    /// A missing entry or a file where a directory was expected is reported
+    /// with `path` up to the component at fault. Any other error hit while
+    /// resolving a component, such as the volume failing to read, comes
+    /// wrapped in `Error::WithComponent` with that component's offset in
+    /// `path`.
+    fn walk(
+        &self,
+        mut inode: Inode<S, V>,
+        path: &[u8],
+        abs_path: &[u8],
+        follow: bool,
+        hops: &mut usize,
+        depth: &mut Option<usize>,
+    ) -> Result<Inode<S, V>> {
+        let mut offset = 0;
+        for name in path.split(|byte| *byte == b'/') {
+            let start = offset;
+            let end = start + name.len();
+            offset = end + 1;
+            if name.is_empty() {
+                continue;
+            }
+            let in_component = |inode: u32| {
+                move |err| Error::WithComponent {
+                    offset: start,
+                    inode,
+                    source: Box::new(err),
+                }
             };
 
-            let mut dir =
-                inode.directory().ok_or_else(|| Error::NotADirectory {
+            if !inode.is_dir() {
+                return Err(Error::NotADirectory {
                     inode: inode.num,
-                    name: String::from_utf8_lossy(abs_path).into_owned(),
-                })?;
+                    name: String::from_utf8_lossy(
+                        &path[..start.saturating_sub(1)],
+                    )
+                    .into_owned(),
+                });
+            }
+            if name == b"." {
+                continue;
+            }
+            if name == b".." {
+                match *depth {
+                    Some(0) => {
+                        return Err(Error::EscapesBeneath {
+                            name: String::from_utf8_lossy(abs_path)
+                                .into_owned(),
+                        })
+                    }
+                    Some(ref mut depth) => *depth -= 1,
+                    None => (),
+                }
+            }
 
-            let entry = dir
-                .find(|entry| {
-                    entry.is_err() || entry.as_ref().unwrap().name == name
-                })
-                .ok_or_else(|| Error::NotFound {
-                    name: String::from_utf8_lossy(abs_path).into_owned(),
-                })??;
+            let (cached, generation) = {
+                let fs = self.read();
+                let cache = &fs.dentry_cache;
+                (cache.get(inode.num, name), cache.generation())
+            };
+            let found = match cached {
+                Some(found) => found,
+                None => {
+                    let found = inode
+                        .entry(name)
+                        .map_err(in_component(inode.num))?
+                        .map(|entry| entry.ino);
+                    let fs = self.read();
+                    fs.dentry_cache.insert(inode.num, name, found, generation);
+                    found
+                }
+            };
+            let found = found.ok_or_else(|| Error::NotFound {
+                name: String::from_utf8_lossy(&path[..end]).into_owned(),
+                parent: inode.num,
+            })?;
+            let mut next = self
+                .inode_nth(found as usize)
+                .ok_or(Error::InodeNotFound { inode: found })
+                .map_err(in_component(inode.num))?;
+
+            let last = path[end..].iter().all(|&byte| byte == b'/');
+            // only a symlink ending the path may be left unfollowed, and
+            // not with a slash after it, which asks for a directory
+            let trailing = end < path.len();
+            if next.is_symlink() && (follow || trailing) {
+                *hops += 1;
+                if *hops > self.read().options.max_symlinks {
+                    return Err(Error::TooManySymlinks {
+                        name: String::from_utf8_lossy(abs_path).into_owned(),
+                    });
+                }
+                // relative targets start from the directory holding the link
+                let target = next.read_link().map_err(in_component(next.num))?;
+                let start = if target.first() == Some(&b'/') {
+                    if depth.is_some() {
+                        return Err(Error::EscapesBeneath {
+                            name: String::from_utf8_lossy(abs_path)
+                                .into_owned(),
+                        });
+                    }
+                    self.root_inode()
+                } else {
+                    inode
+                };
+                next = self.walk(start, &target, abs_path, true, hops, depth)?;
+            } else if name != b".." {
+                if let Some(ref mut depth) = *depth {
+                    *depth += 1;
+                }
+            }
+            if last && trailing && !next.is_dir() {
+                return Err(Error::NotADirectory {
+                    inode: next.num,
+                    name: String::from_utf8_lossy(&path[..end]).into_owned(),
+                });
+            }
+            inode = next;
+        }
+
+        Ok(inode)
+    }
+
+    /// Creates an empty regular file at `abs_path` with the permission bits
+    /// of `perm`, owned by root
+    pub fn create_file<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        perm: Permissions,
+    ) -> Result<Inode<S, V>> {
+        self.create(abs_path.as_ref(), FileType::File, perm)
+    }
+
+    /// Creates an empty directory at `abs_path` with the permission bits of
+    /// `perm`, owned by root. The parent directory has to exist already.
+    pub fn mkdir<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        perm: Permissions,
+    ) -> Result<Inode<S, V>> {
+        self.create(abs_path.as_ref(), FileType::Directory, perm)
+    }
+
+    /// Creates a special file at `abs_path`, owned by root: a character or
+    /// block device with the number `major` and `minor`, or a FIFO or a
+    /// socket, for which the number is ignored. Other kinds of files have
+    /// their own ways to be created and are `Error::Unsupported`.
+    pub fn mknod<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        file_type: FileType,
+        perm: Permissions,
+        major: u32,
+        minor: u32,
+    ) -> Result<Inode<S, V>> {
+        let device = match file_type {
+            FileType::CharDevice | FileType::BlockDevice => true,
+            FileType::Fifo | FileType::Socket => false,
+            _ => return Err(Error::Unsupported { operation: "mknod" }),
+        };
+        if device && (major > 0xfff || minor > 0xfffff) {
+            return Err(Error::InvalidDevice { major, minor });
+        }
+
+        let abs_path = abs_path.as_ref();
+        let (parent, name) = split_path(abs_path)?;
+        check_name(name)?;
+        let parent = self.lookup(parent)?;
+        let device = if device { Some((major, minor)) } else { None };
+        self.create_in(parent, name, abs_path, file_type, perm, device)
+    }
+
+    /// Allocates and links a new inode of type `kind` at `abs_path`.
+    /// Directories get a first block holding their `.` and `..` entries.
+    fn create(
+        &self,
+        abs_path: &[u8],
+        kind: FileType,
+        perm: Permissions,
+    ) -> Result<Inode<S, V>> {
+        let (parent, name) = split_path(abs_path)?;
+        check_name(name)?;
+        let parent = self.lookup(parent)?;
+        self.create_in(parent, name, abs_path, kind, perm, None)
+    }
+
+    /// Allocates and links a new inode of type `kind` named `name` in the
+    /// directory `parent`. `path` is how the caller named it, for errors.
+    fn create_in(
+        &self,
+        mut parent: Inode<S, V>,
+        name: &[u8],
+        path: &[u8],
+        kind: FileType,
+        perm: Permissions,
+        device: Option<(u32, u32)>,
+    ) -> Result<Inode<S, V>> {
+        if !parent.is_dir() {
+            return Err(Error::NotADirectory {
+                inode: parent.num,
+                name: String::from_utf8_lossy(path).into_owned(),
+            });
+        }
+        if name.is_empty() || parent.entry(name)?.is_some() {
+            return Err(Error::AlreadyExists {
+                name: String::from_utf8_lossy(path).into_owned(),
+            });
+        }
+        // before anything is allocated
+        parent.check_flags(true)?;
+
+        let is_dir = kind == FileType::Directory;
+        let file_type = kind.dirent();
+        let mut inode = {
+            let mut fs = self.inner();
+            let num = fs.allocate_inode(parent.num, is_dir)?;
+            let now = fs.now();
+            let mut raw = RawInode {
+                type_perm: TypePerm::new(kind, perm),
+                atime: now,
+                ctime: now,
+                mtime: now,
+                hard_links: if is_dir { 2 } else { 1 },
+                ..Default::default()
+            };
+            if let Some((major, minor)) = device {
+                raw.set_device(major, minor);
+            }
+            let addr = fs.inode_address(num)?;
+            fs.write_inode(num, &raw)?;
+            Inode::new(self.clone(), raw, addr, num, fs.large_files())
+        };
+
+        let mut result = Ok(());
+        if is_dir {
+            let mut fs = self.inner();
+            result = inode.init_directory(&mut fs, parent.num);
+        }
+        if result.is_ok() {
+            result = parent.add_entry(name, inode.num, file_type);
+        }
+        if let Err(err) = result {
+            inode.destroy(&mut self.inner())?;
+            return Err(err);
+        }
 
-            let inode = fs
-                .inode_nth(entry.inode)
-                .ok_or(Error::InodeNotFound { inode: inode.num })?;
+        if is_dir {
+            // the new `..` entry links back to the parent
+            parent.link()?;
+        }
+        Ok(inode)
+    }
 
-            inner(fs, inode, path, abs_path)
+    /// Removes the file or symlink at `abs_path`. Its blocks and inode are
+    /// freed once no other links to it remain.
+    pub fn unlink<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<()> {
+        let abs_path = abs_path.as_ref();
+        let mut inode = self.lookup_nofollow(abs_path)?;
+        if inode.is_dir() {
+            return Err(Error::IsADirectory {
+                inode: inode.num,
+                name: String::from_utf8_lossy(abs_path).into_owned(),
+            });
         }
 
-        if abs_path.is_empty() || abs_path[0] != b'/' {
-            return Err(Error::NotAbsolute {
+        inode.check_flags(false)?;
+
+        let (parent, name) = split_path(abs_path)?;
+        let mut parent = self.lookup(parent)?;
+        parent.remove_entry(name)?;
+        inode.unlink()
+    }
+
+    /// Removes the file or symlink at `abs_path` like `unlink`, zeroing its
+    /// blocks as they are freed whether or not it has the `SECURE_DEL` flag.
+    /// The flag is set on the inode, so if other links to it remain, its
+    /// blocks are zeroed once the last one is removed.
+    pub fn shred<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<()> {
+        let abs_path = abs_path.as_ref();
+        let mut inode = self.lookup_nofollow(abs_path)?;
+        if inode.is_dir() {
+            return Err(Error::IsADirectory {
+                inode: inode.num,
                 name: String::from_utf8_lossy(abs_path).into_owned(),
             });
         }
 
-        if abs_path == b"/" {
-            return Ok(self.root_inode());
+        inode.check_flags(false)?;
+
+        {
+            let mut fs = self.inner();
+            inode.reload(&fs)?;
+            inode.inner.flags = { inode.inner.flags } | Flags::SECURE_DEL;
+            inode.write_back(&mut fs)?;
         }
+        self.unlink(abs_path)
+    }
 
-        let mut path = abs_path.split(|byte| *byte == b'/');
-        path.next();
-        let root = self.root_inode();
+    /// Removes the empty directory at `abs_path`. A directory counts as empty
+    /// if it has no entries besides `.` and `..`, however many blocks it
+    /// has grown to.
+    pub fn rmdir<P: AsRef<[u8]>>(&self, abs_path: P) -> Result<()> {
+        let abs_path = abs_path.as_ref();
+        let mut dir = self.lookup_nofollow(abs_path)?;
+        if !dir.is_dir() {
+            return Err(Error::NotADirectory {
+                inode: dir.num,
+                name: String::from_utf8_lossy(abs_path).into_owned(),
+            });
+        }
+        if dir.num == 2 {
+            return Err(Error::InvalidName {
+                name: String::from_utf8_lossy(abs_path).into_owned(),
+            });
+        }
+        dir.check_flags(false)?;
+        for entry in dir.directory().into_iter().flatten() {
+            let entry = entry?;
+            if entry.name != b"." && entry.name != b".." {
+                return Err(Error::DirectoryNotEmpty {
+                    name: String::from_utf8_lossy(abs_path).into_owned(),
+                });
+            }
+        }
+
+        let (parent, name) = split_path(abs_path)?;
+        let mut parent = self.lookup(parent)?;
+        parent.remove_entry(name)?;
+        // the directory's `..` no longer links to the parent
+        parent.unlink()?;
+
+        let mut fs = self.inner();
+        dir.reload(&fs)?;
+        dir.destroy(&mut fs)
+    }
+
+    /// Replaces the permission bits of the inode at `abs_path` with those of
+    /// `perm`, following a symlink in the final component like `chmod`
+    pub fn set_permissions<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        perm: Permissions,
+    ) -> Result<()> {
+        self.lookup(abs_path)?.set_permissions(perm)
+    }
+
+    /// Changes the owner and group of the inode at `abs_path`, following a
+    /// symlink in the final component like `chown`
+    pub fn set_owner<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        uid: u16,
+        gid: u16,
+    ) -> Result<()> {
+        self.lookup(abs_path)?.set_owner(uid, gid)
+    }
+
+    /// Replaces the flags of the inode at `abs_path` with `flags`, following
+    /// a symlink in the final component, see `Inode::set_flags`
+    pub fn set_flags<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        flags: Flags,
+    ) -> Result<()> {
+        self.lookup(abs_path)?.set_flags(flags)
+    }
+
+    /// Sets the access and modification times of the inode at `abs_path`,
+    /// leaving those passed as `None` as they are. The change time is set to
+    /// the current time either way, as with `utimensat`.
+    pub fn set_times<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+    ) -> Result<()> {
+        self.lookup(abs_path)?.set_times(atime, mtime)
+    }
+
+    /// Moves the entry at `from` to `to`, replacing whatever `to` pointed at
+    /// before. A directory may replace an empty directory, anything else may
+    /// only replace a non-directory. Directories can't be moved into their
+    /// own subtree.
+    pub fn rename<P: AsRef<[u8]>, Q: AsRef<[u8]>>(
+        &self,
+        from: P,
+        to: Q,
+    ) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let (from_dir, from_name) = split_path(from)?;
+        let (to_dir, to_name) = split_path(to)?;
+        check_name(to_name)?;
+        for &name in &[from_name, to_name] {
+            if name.is_empty() || name == b"." || name == b".." {
+                return Err(Error::InvalidName {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                });
+            }
+        }
+
+        let mut from_parent = self.lookup(from_dir)?;
+        let mut to_parent = self.lookup(to_dir)?;
+        for (parent, path) in &[(&from_parent, from), (&to_parent, to)] {
+            if !parent.is_dir() {
+                return Err(Error::NotADirectory {
+                    inode: parent.num,
+                    name: String::from_utf8_lossy(path).into_owned(),
+                });
+            }
+        }
+
+        // from here on the file system is held throughout, so that no one
+        // sees the rename half done or gets to change the entries checked
+        let mut fs = self.inner();
+        fs.check_writable()?;
+        from_parent.reload(&fs)?;
+        to_parent.reload(&fs)?;
+        // either may have been removed since it was looked up
+        for (parent, path) in &[(&from_parent, from_dir), (&to_parent, to_dir)]
+        {
+            if !parent.in_use() {
+                return Err(Error::NotFound {
+                    name: String::from_utf8_lossy(path).into_owned(),
+                    parent: parent.num,
+                });
+            }
+        }
+        let source = from_parent.find_locked(&fs, from_name)?.ok_or_else(|| {
+            Error::NotFound {
+                name: String::from_utf8_lossy(from).into_owned(),
+                parent: from_parent.num,
+            }
+        })?;
+        let mut source = self.inode_locked(&fs, source)?;
+        let target = match to_parent.find_locked(&fs, to_name)? {
+            Some(target) => Some(self.inode_locked(&fs, target)?),
+            None => None,
+        };
+        // checked up front, as the entries are changed one at a time
+        source.check_flags(false)?;
+        from_parent.check_flags(false)?;
+        to_parent.check_flags(target.is_none())?;
+        if let Some(ref target) = target {
+            // renaming a file onto another link to itself does nothing
+            if target.num == source.num {
+                return Ok(());
+            }
+            target.check_flags(false)?;
+            if source.is_dir() && !target.is_dir() {
+                return Err(Error::NotADirectory {
+                    inode: target.num,
+                    name: String::from_utf8_lossy(to).into_owned(),
+                });
+            }
+            if !source.is_dir() && target.is_dir() {
+                return Err(Error::IsADirectory {
+                    inode: target.num,
+                    name: String::from_utf8_lossy(to).into_owned(),
+                });
+            }
+            if target.is_dir() && !target.is_empty_locked(&fs)? {
+                return Err(Error::DirectoryNotEmpty {
+                    name: String::from_utf8_lossy(to).into_owned(),
+                });
+            }
+        }
+
+        let moves_dir = source.is_dir() && from_parent.num != to_parent.num;
+        if moves_dir {
+            // walk up from the new parent to make sure it's not below source
+            let mut ancestor = to_parent.clone();
+            while ancestor.num != 2 {
+                if ancestor.num == source.num {
+                    return Err(Error::InvalidRename {
+                        from: String::from_utf8_lossy(from).into_owned(),
+                        to: String::from_utf8_lossy(to).into_owned(),
+                    });
+                }
+                let parent = ancestor.find_locked(&fs, b"..")?.ok_or_else(
+                    || Error::NotFound {
+                        name: String::from_utf8_lossy(to).into_owned(),
+                        parent: ancestor.num,
+                    },
+                )?;
+                ancestor = self.inode_locked(&fs, parent)?;
+            }
+        }
+
+        let file_type = source.dirent_type();
+        match target {
+            Some(mut target) => {
+                to_parent.replace_entry_locked(
+                    &mut fs,
+                    to_name,
+                    source.num,
+                    file_type,
+                )?;
+                if target.is_dir() {
+                    // the replaced directory's `..` goes away with it
+                    to_parent.unlink_locked(&mut fs)?;
+                    target.reload(&fs)?;
+                    target.destroy(&mut fs)?;
+                } else {
+                    target.unlink_locked(&mut fs)?;
+                }
+            }
+            None => to_parent.add_entry_locked(
+                &mut fs,
+                to_name,
+                source.num,
+                file_type,
+            )?,
+        }
+        from_parent.remove_entry_locked(&mut fs, from_name)?;
+
+        if moves_dir {
+            source.replace_entry_locked(
+                &mut fs,
+                b"..",
+                to_parent.num,
+                inode::DIRECTORY,
+            )?;
+            from_parent.unlink_locked(&mut fs)?;
+            to_parent.link_locked(&mut fs)?;
+        }
+        Ok(())
+    }
+
+    /// A handle on the root directory
+    pub fn root_dir(&self) -> DirHandle<S, V> {
+        DirHandle {
+            fs: self.clone(),
+            ino: 2,
+            beneath: false,
+        }
+    }
+
+    /// A handle on the directory at `abs_path`, to resolve paths from
+    pub fn open_dir<P: AsRef<[u8]>>(
+        &self,
+        abs_path: P,
+    ) -> Result<DirHandle<S, V>> {
+        let abs_path = abs_path.as_ref();
+        check_absolute(abs_path)?;
+        self.root_dir().open_dir_at(abs_path)
+    }
+
+    pub fn sector_size(&self) -> usize {
+        S::SIZE
+    }
+
+    pub fn log_sector_size(&self) -> u32 {
+        S::LOG_SIZE
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
+    type Path = [u8];
+    type PathOwned = Vec<u8>;
+    type File = Inode<S, V>;
+    type Dir = Directory<S, V>;
+    type DirEntry = DirectoryEntry<S, V>;
+    type Metadata = Metadata;
+    type Permissions = (); // TODO
+    type Error = Error;
 
-        inner(self, root, path, abs_path)
+    fn open(
+        &self,
+        abs_path: &Self::Path,
+        options: &OpenOptions<Self::Permissions>,
+    ) -> Result<Self::File, Self::Error> {
+        check_absolute(abs_path)?;
+        self.root_dir().open_at(abs_path, options)
     }
 
-    fn remove_file(&mut self, _path: &Self::Path) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn remove_file(&mut self, path: &Self::Path) -> Result<(), Self::Error> {
+        self.unlink(path)
     }
 
     fn metadata(
         &self,
-        _path: &Self::Path,
+        path: &Self::Path,
     ) -> Result<Self::Metadata, Self::Error> {
-        unimplemented!()
+        Synced::metadata(self, path)
     }
 
     fn symlink_metadata(
         &self,
-        _path: &Self::Path,
+        path: &Self::Path,
     ) -> Result<Self::Metadata, Self::Error> {
-        unimplemented!()
+        Ok(self.lookup_nofollow(path)?.metadata())
     }
 
     fn rename(
         &mut self,
-        _from: &Self::Path,
-        _to: &Self::Path,
+        from: &Self::Path,
+        to: &Self::Path,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Synced::rename(self, from, to)
     }
 
     fn copy(
@@ -179,7 +905,9 @@ impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
         _from: &Self::Path,
         _to: &Self::Path,
     ) -> Result<u64, Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "copy",
+        })
     }
 
     fn hard_link(
@@ -187,7 +915,9 @@ impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
         _src: &Self::Path,
         _dst: &Self::Path,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "hard_link",
+        })
     }
 
     fn symlink(
@@ -195,48 +925,88 @@ impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
         _src: &Self::Path,
         _dst: &Self::Path,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "symlink",
+        })
     }
 
     fn read_link(
         &self,
-        _path: &Self::Path,
+        path: &Self::Path,
     ) -> Result<Self::PathOwned, Self::Error> {
-        unimplemented!()
+        self.lookup_nofollow(path)?.read_link()
     }
 
     fn canonicalize(
         &self,
         _path: &Self::Path,
     ) -> Result<Self::PathOwned, Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "canonicalize",
+        })
     }
 
     fn create_dir(
         &mut self,
-        _path: &Self::Path,
-        _options: &DirOptions<Self::Permissions>,
+        path: &Self::Path,
+        options: &DirOptions<Self::Permissions>,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        let mut recursive = false;
+        option_fields(options, |name, value| {
+            if name == "recursive" {
+                recursive = value == "true";
+            }
+        });
+        if !recursive {
+            return self.mkdir(path, DEFAULT_DIR_PERM).map(|_| ());
+        }
+
+        let mut end = 0;
+        while end < path.len() {
+            end = path[end + 1..]
+                .iter()
+                .position(|&byte| byte == b'/')
+                .map_or(path.len(), |position| end + 1 + position);
+            match self.mkdir(&path[..end], DEFAULT_DIR_PERM) {
+                Err(Error::AlreadyExists { .. }) => {
+                    let inode = self.lookup(&path[..end])?;
+                    if !inode.is_dir() {
+                        return Err(Error::NotADirectory {
+                            inode: inode.num,
+                            name: String::from_utf8_lossy(path).into_owned(),
+                        });
+                    }
+                }
+                result => {
+                    result?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    fn remove_dir(&mut self, _path: &Self::Path) -> Result<(), Self::Error> {
-        unimplemented!()
+    fn remove_dir(&mut self, path: &Self::Path) -> Result<(), Self::Error> {
+        self.rmdir(path)
     }
 
     fn remove_dir_all(
         &mut self,
         _path: &Self::Path,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "remove_dir_all",
+        })
     }
 
+    /// Entries listed this way know their full path, see `DirEntry::path`
     fn read_dir(&self, path: &Self::Path) -> Result<Self::Dir, Self::Error> {
         let inode = self.open(path, OpenOptions::new().read(true))?;
-        inode.directory().ok_or(Error::NotADirectory {
+        let mut dir = inode.directory().ok_or(Error::NotADirectory {
             inode: inode.num,
             name: String::from_utf8_lossy(path).into_owned(),
-        })
+        })?;
+        dir.path = Some(Arc::from(path));
+        Ok(dir)
     }
 
     fn set_permissions(
@@ -244,7 +1014,9 @@ impl<S: SectorSize, V: Volume<u8, S>> Fs for Synced<Ext2<S, V>> {
         _path: &Self::Path,
         _perm: Self::Permissions,
     ) -> Result<(), Self::Error> {
-        unimplemented!()
+        Err(Error::Unsupported {
+            operation: "set_permissions",
+        })
     }
 }
 
@@ -254,46 +1026,239 @@ impl<S: SectorSize, V: Volume<u8, S>> Debug for Synced<Ext2<S, V>> {
     }
 }
 
+/// A directory to resolve paths from, as with a directory file descriptor
+/// and the `*at` calls. Relative paths start from the directory and
+/// absolute ones from the root. Only the inode number is kept, so handles
+/// are cheap to clone and keep around; the inode is loaded afresh for each
+/// call.
 #[derive(Debug, Clone)]
-pub struct Inodes<S: SectorSize, V: Volume<u8, S>> {
+pub struct DirHandle<S: SectorSize, V: Volume<u8, S>> {
     fs: Synced<Ext2<S, V>>,
-    log_block_size: u32,
-    inode_size: usize,
-    inodes_per_group: usize,
-    inodes_count: usize,
-    index: usize,
+    ino: u32,
+    beneath: bool,
 }
 
-impl<S: SectorSize, V: Volume<u8, S>> Iterator for Inodes<S, V> {
-    type Item = Inode<S, V>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.inodes_count {
-            let block_group = (self.index - 1) / self.inodes_per_group;
-            let index = (self.index - 1) % self.inodes_per_group;
-            self.index += 1;
-
-            let fs = self.fs.inner();
+impl<S: SectorSize, V: Volume<u8, S>> DirHandle<S, V> {
+    /// The inode number of the directory
+    pub fn ino(&self) -> u32 {
+        self.ino
+    }
 
-            let inodes_block =
-                fs.block_groups.inner[block_group].inode_table_block;
+    /// Keeps every path resolved through this handle, or the handles opened
+    /// from it, beneath its directory, like `RESOLVE_BENEATH` of `openat2`.
+    /// Absolute paths, absolute symlinks and `..` leading above the
+    /// directory fail with `Error::EscapesBeneath`.
+    pub fn beneath(mut self) -> DirHandle<S, V> {
+        self.beneath = true;
+        self
+    }
 
-            let offset = Address::with_block_size(
-                inodes_block,
-                (index * self.inode_size) as i32,
-                self.log_block_size,
-            );
-            let raw = unsafe {
-                RawInode::find_inode(&fs.volume, offset, self.inode_size).ok()
+    /// Opens the file at `path` like `Fs::open`
+    pub fn open_at<P: AsRef<[u8]>>(
+        &self,
+        path: P,
+        options: &OpenOptions<()>,
+    ) -> Result<Inode<S, V>> {
+        let path = path.as_ref();
+        let flags = OpenFlags::new(options);
+        match self.resolve_at(path, !flags.nofollow) {
+            Ok(_) if flags.create_new => Err(Error::AlreadyExists {
+                name: String::from_utf8_lossy(path).into_owned(),
+            }),
+            Ok(mut inode) => {
+                match inode.file_type() {
+                    FileType::Fifo
+                    | FileType::Socket
+                    | FileType::CharDevice
+                    | FileType::BlockDevice => {
+                        return Err(Error::SpecialFile {
+                            inode: inode.num,
+                            name: String::from_utf8_lossy(path).into_owned(),
+                        })
+                    }
+                    _ => (),
+                }
+                if flags.write || flags.append {
+                    inode.check_flags(flags.append)?;
+                }
+                if flags.truncate && (flags.write || flags.append) {
+                    inode.set_len(0)?;
+                }
+                inode.append = flags.append;
+                Ok(inode)
+            }
+            Err(Error::NotFound { .. }) if flags.create || flags.create_new => {
+                let (parent, name) = split_relative(path);
+                check_name(name)?;
+                let parent = self.resolve_at(parent, true)?;
+                let mut inode = self.fs.create_in(
+                    parent,
+                    name,
+                    path,
+                    FileType::File,
+                    DEFAULT_FILE_PERM,
+                    None,
+                )?;
+                inode.append = flags.append;
+                Ok(inode)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Resolves `path` to its inode, following symlinks
+    pub fn metadata_at<P: AsRef<[u8]>>(
+        &self,
+        path: P,
+    ) -> Result<Inode<S, V>> {
+        self.resolve_at(path.as_ref(), true)
+    }
+
+    /// Resolves `path` to its inode, returning a symlink in the final
+    /// component itself
+    pub fn symlink_metadata_at<P: AsRef<[u8]>>(
+        &self,
+        path: P,
+    ) -> Result<Inode<S, V>> {
+        self.resolve_at(path.as_ref(), false)
+    }
+
+    /// Lists the directory at `path`
+    pub fn read_dir_at<P: AsRef<[u8]>>(
+        &self,
+        path: P,
+    ) -> Result<Directory<S, V>> {
+        let path = path.as_ref();
+        let inode = self.resolve_at(path, true)?;
+        inode.directory().ok_or(Error::NotADirectory {
+            inode: inode.num,
+            name: String::from_utf8_lossy(path).into_owned(),
+        })
+    }
+
+    /// A handle on the directory at `path`, kept beneath its own directory
+    /// if this one is
+    pub fn open_dir_at<P: AsRef<[u8]>>(
+        &self,
+        path: P,
+    ) -> Result<DirHandle<S, V>> {
+        let path = path.as_ref();
+        let inode = self.resolve_at(path, true)?;
+        if !inode.is_dir() {
+            return Err(Error::NotADirectory {
+                inode: inode.num,
+                name: String::from_utf8_lossy(path).into_owned(),
+            });
+        }
+        Ok(DirHandle {
+            fs: self.fs.clone(),
+            ino: inode.num,
+            beneath: self.beneath,
+        })
+    }
+
+    fn resolve_at(&self, path: &[u8], follow: bool) -> Result<Inode<S, V>> {
+        let start = if path.first() == Some(&b'/') {
+            if self.beneath {
+                return Err(Error::EscapesBeneath {
+                    name: String::from_utf8_lossy(path).into_owned(),
+                });
+            }
+            self.fs.root_inode()
+        } else {
+            self.fs
+                .inode_nth(self.ino as usize)
+                .ok_or(Error::InodeNotFound { inode: self.ino })?
+        };
+        let mut hops = 0;
+        let mut depth = if self.beneath { Some(0) } else { None };
+        self.fs.walk(start, path, path, follow, &mut hops, &mut depth)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Inodes<S: SectorSize, V: Volume<u8, S>> {
+    fs: Synced<Ext2<S, V>>,
+    log_block_size: u32,
+    inode_size: usize,
+    inodes_per_group: usize,
+    inodes_count: usize,
+    index: usize,
+    /// Part of an inode table read ahead: the block group, the index in
+    /// the group of the first inode in it, and its bytes
+    table: Option<(usize, usize, Vec<u8>)>,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Iterator for Inodes<S, V> {
+    type Item = Inode<S, V>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index <= self.inodes_count {
+            let block_group = (self.index - 1) / self.inodes_per_group;
+            let index = (self.index - 1) % self.inodes_per_group;
+            self.index += 1;
+
+            let fs = self.fs.read();
+            let inodes_block =
+                fs.block_groups.inner[block_group].inode_table_block;
+            let (inode_size, log_block_size) =
+                (self.inode_size, self.log_block_size);
+            // an address past the end fails the read rather than wraps
+            let address = |index: usize| {
+                Address::with_block_size(inodes_block, 0, log_block_size)
+                    .saturating_add(Address::from(index * inode_size))
             };
-            raw.map(|(raw, offset)| {
-                Inode::new(
-                    self.fs.clone(),
-                    raw,
-                    offset,
-                    (self.index - 1) as u32,
-                )
-            })
+
+            // read as much of the table as fits in the readahead at once,
+            // instead of one inode at a time. Should that fail, as it does
+            // with a bad sector in it, only the inode itself is read, so the
+            // ones before the bad sector are still found.
+            let cached = match self.table {
+                Some((group, first, ref bytes)) => {
+                    group == block_group
+                        && index >= first
+                        && (index - first + 1) * self.inode_size
+                            <= bytes.len()
+                }
+                None => false,
+            };
+            if !cached {
+                let count = (INODE_READAHEAD / self.inode_size)
+                    .max(1)
+                    .min(self.inodes_per_group - index);
+                let range = address(index)..address(index + count);
+                match fs.volume.slice(range) {
+                    Ok(bytes) => {
+                        self.table = Some((block_group, index, bytes.to_vec()))
+                    }
+                    Err(_) => {
+                        let (raw, addr) = RawInode::find_inode(
+                            &fs.volume,
+                            address(index),
+                            inode_size,
+                        )
+                        .ok()?;
+                        let large_file = fs.large_files();
+                        let fs = self.fs.clone();
+                        let num = (self.index - 1) as u32;
+                        return Some(Inode::new(
+                            fs, raw, addr, num, large_file,
+                        ));
+                    }
+                }
+            }
+
+            let (_, first, ref bytes) = *self.table.as_ref()?;
+            let start = (index - first) * self.inode_size;
+            let mut raw = [0; 128];
+            raw.copy_from_slice(&bytes[start..start + 128]);
+            Some(Inode::new(
+                self.fs.clone(),
+                RawInode::from_bytes(&raw),
+                address(index),
+                (self.index - 1) as u32,
+                fs.large_files(),
+            ))
         } else {
             None
         }
@@ -306,6 +1271,14 @@ pub struct Inode<S: SectorSize, V: Volume<u8, S>> {
     inner: RawInode,
     addr: Address<S>,
     num: u32,
+    /// Whether the file system had the large file feature when the inode
+    /// was read, which decides how its size is read
+    large_file: bool,
+    /// Whether writes through `File::write` go to the end of the file
+    append: bool,
+    /// Where `File::read` and `File::write` carry on from. `File::read`
+    /// only borrows the file, hence the lock.
+    position: Mutex<u64>,
 }
 
 impl<S: SectorSize, V: Volume<u8, S>> Clone for Inode<S, V> {
@@ -315,6 +1288,9 @@ impl<S: SectorSize, V: Volume<u8, S>> Clone for Inode<S, V> {
             inner: self.inner,
             addr: self.addr,
             num: self.num,
+            append: self.append,
+            large_file: self.large_file,
+            position: Mutex::new(*self.position.lock()),
         }
     }
 }
@@ -325,540 +1301,6005 @@ impl<S: SectorSize, V: Volume<u8, S>> Inode<S, V> {
         inner: RawInode,
         addr: Address<S>,
         num: u32,
+        large_file: bool,
     ) -> Inode<S, V> {
         Inode {
             fs,
             inner,
             addr,
             num,
+            large_file,
+            append: false,
+            position: Mutex::new(0),
         }
     }
 
-    pub fn read_to_end(&self, buf: &mut Vec<u8>) -> Result<usize, Error> {
-        let total_size = self.size();
-        let capacity = buf.capacity();
-        if capacity < total_size {
-            buf.reserve_exact(total_size - capacity);
-        }
-        unsafe {
-            buf.set_len(total_size);
-        }
-        let size = self.read(&mut buf[..]);
-        unsafe {
-            buf.set_len(if let Ok(size) = size { size } else { 0 });
+    /// Reads the whole file into `buf`, replacing what it held, wherever the
+    /// position is
+    pub fn read_to_end(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        buf.clear();
+        buf.resize(self.size(), 0);
+        match self.read_at(0, buf) {
+            Ok(size) => {
+                buf.truncate(size);
+                Ok(size)
+            }
+            Err(err) => {
+                buf.clear();
+                Err(err)
+            }
         }
-        size
     }
 
-    pub fn blocks(&self) -> InodeBlocks<S, V> {
-        InodeBlocks {
-            inode: self.clone(),
-            index: 0,
-        }
+    /// Reads into `buf` from byte `offset` of the file, returning the number
+    /// of bytes read, which is 0 at or past the end of the file. The
+    /// position is left alone, so readers sharing a file can each read
+    /// where they like, from as many threads as they like.
+    ///
+    /// The access time is updated as the atime policy of the file system
+    /// has it, see `Ext2::set_atime_policy`.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read_data(offset, buf)?;
+        self.touch_atime();
+        Ok(read)
     }
 
-    pub fn directory(&self) -> Option<Directory<S, V>> {
-        if self.is_dir() {
-            Some(Directory {
-                blocks: self.blocks(),
-                offset: 0,
-                buffer: None,
-                block_size: {
-                    let fs = self.fs.inner();
-                    fs.block_size()
-                },
-            })
-        } else {
-            None
+    fn read_data(&self, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let (block_size, log_block_size) = {
+            let fs = self.fs.read();
+            (fs.block_size() as u64, fs.log_block_size())
+        };
+        let size = self.size64();
+        if offset >= size {
+            return Ok(0);
         }
-    }
+        let len = (buf.len() as u64).min(size - offset) as usize;
+        let end = offset + len as u64;
+        // as many blocks at a time as an indirect block maps
+        let batch = block_size as usize / 4;
+        let mut index = (offset >> log_block_size) as usize;
+        let last = ((end - 1) >> log_block_size) as usize;
+
+        while index <= last {
+            // the file system is locked a batch at a time, so that readers
+            // take turns rather than wait out each other's reads
+            let fs = self.fs.read();
+            let count = batch.min(last + 1 - index);
+            let blocks = self.map_blocks(&fs, index, count)?;
+
+            // each run of blocks that follow each other on the volume, or
+            // of holes, is read in one go
+            let mut run = 0;
+            while run < count {
+                let first = blocks[run];
+                let mut next = run + 1;
+                while next < count
+                    && match (first, blocks[next]) {
+                        (Some(first), Some(block)) => {
+                            block.get() as u64
+                                == first.get() as u64 + (next - run) as u64
+                        }
+                        (None, None) => true,
+                        _ => false,
+                    }
+                {
+                    next += 1;
+                }
 
-    pub fn is_dir(&self) -> bool {
-        use sys::inode::TypePerm;
-        { self.inner.type_perm }.contains(TypePerm::DIRECTORY)
-    }
+                let run_start = ((index + run) as u64) << log_block_size;
+                let run_end = ((index + next) as u64) << log_block_size;
+                let from = offset.max(run_start);
+                let to = end.min(run_end);
+                let buf =
+                    &mut buf[(from - offset) as usize..(to - offset) as usize];
+                match first {
+                    Some(block) => {
+                        let start = Address::with_block_size(
+                            block.get(),
+                            (from - run_start) as i32,
+                            log_block_size,
+                        );
+                        let end = Address::with_block_size(
+                            block.get(),
+                            (to - run_start) as i32,
+                            log_block_size,
+                        );
+                        let data = fs
+                            .volume
+                            .slice(start..end)
+                            .map_err(|err| err.into())?;
+                        buf.copy_from_slice(&data);
+                    }
+                    // holes read back as zeroes
+                    None => {
+                        for byte in buf {
+                            *byte = 0;
+                        }
+                    }
+                }
+                run = next;
+            }
+            index += count;
+        }
 
-    pub fn block(&self, index: usize) -> Option<NonZeroU32> {
-        self.try_block(index).ok().and_then(|block| block)
+        Ok(len)
     }
 
-    pub fn try_block(
-        &self,
-        mut index: usize,
-    ) -> Result<Option<NonZeroU32>, Error> {
-        // number of blocks in direct table: 12
-        // number of blocks in indirect table: block_size/4
-        //   why?
-        //     - a block is n bytes long
-        //     - a block address occupies 32 bits, or 4 bytes
-        //     - thus, n/4
-        // number of blocks in doubly table: (block_size/4)^2
-        //   why?
-        //     - every entry in the doubly table points to another block
-        //     - that's n/4 blocks, where n is the block size
-        //     - every block contains n/4 block pointers
-        //     - that's n/4 blocks with n/4 pointers each = (n/4)^2
-        // number of blocks in triply table: (block_size/4)^3
-
-        fn block_index<S: SectorSize, V: Volume<u8, S>>(
-            volume: &V,
-            block: u32,
-            index: usize,
-            log_block_size: u32,
-        ) -> Result<Option<NonZeroU32>, Error> {
-            let offset = (index * 4) as i32;
-            let end = offset + 4;
-            let addr = Address::with_block_size(block, offset, log_block_size);
-            let end = Address::with_block_size(block, end, log_block_size);
-            let block = volume.slice(addr..end);
-            match block {
-                Ok(block) => unsafe {
-                    Ok(NonZeroU32::new(block.dynamic_cast::<u32>().0))
-                },
-                Err(err) => Err(err.into()),
+    /// Stamps this file as accessed now if the atime policy calls for it.
+    /// As with Linux, failing to do so doesn't fail the read.
+    fn touch_atime(&self) {
+        // decided under the read lock, so that reads only lock for writing
+        // when there is an access time to write
+        let due = {
+            let fs = self.fs.read();
+            if fs.options.atime == AtimePolicy::Never || fs.options.read_only {
+                return;
             }
+            fs.read_inode(self.num).is_ok_and(|raw| {
+                fs.options.atime.wants_update(&raw, fs.now())
+            })
+        };
+        if !due {
+            return;
         }
-
-        let fs = self.fs.inner();
-
-        let bs4 = fs.block_size() / 4;
-        let log_block_size = fs.log_block_size();
-
-        if index < 12 {
-            return Ok(NonZeroU32::new(self.inner.direct_pointer[index]));
+        // another reader may have written it in between the two locks
+        let mut fs = self.fs.inner();
+        let mut raw = match fs.read_inode(self.num) {
+            Ok(raw) => raw,
+            Err(_) => return,
+        };
+        let now = fs.now();
+        if fs.options.atime.wants_update(&raw, now) {
+            raw.atime = now;
+            let _ = fs.write_inode(self.num, &raw);
         }
+    }
 
-        index -= 12;
-
-        if index < bs4 {
-            let block = self.inner.indirect_pointer;
-            return block_index(&fs.volume, block, index, log_block_size);
+    /// Reads block `index` of the file without copying it where the volume
+    /// can lend its bytes, as in-memory and mapped volumes can; others hand
+    /// over a buffer of their own. A hole is `FileBlock::Zeroes`. The last
+    /// block stops at the end of the file, and blocks past it are
+    /// `Error::OutOfBounds`.
+    ///
+    /// The block borrows from `fs`, the locked file system, which stays
+    /// locked for as long as the block is around:
+    ///
+    /// ```
+    /// # use ext2::fs::sync::{FileBlock, Synced};
+    /// # use ext2::fs::Ext2;
+    /// # use ext2::sector::Size512;
+    /// let volume = std::fs::read("ext2.img").unwrap();
+    /// let synced = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+    /// let readme = synced.lookup(b"/home/funky/README.md").unwrap();
+    /// let fs = synced.inner();
+    /// match readme.read_block(&fs, 0).unwrap() {
+    ///     FileBlock::Data(data) => assert!(!data.is_mutated()),
+    ///     FileBlock::Zeroes(_) => unreachable!(),
+    /// }
+    /// ```
+    pub fn read_block<'a>(
+        &self,
+        fs: &'a Ext2<S, V>,
+        index: u32,
+    ) -> Result<FileBlock<'a, S>> {
+        let block_size = fs.block_size() as u64;
+        let start = index as u64 * block_size;
+        let size = self.size64();
+        if start >= size {
+            return Err(Error::OutOfBounds {
+                index: index as usize,
+            });
         }
+        let len = block_size.min(size - start) as usize;
+        let block = match self.lookup_block(fs, index as usize)? {
+            Some(block) => block.get(),
+            None => return Ok(FileBlock::Zeroes(len)),
+        };
+        let log_block_size = fs.log_block_size();
+        let start = Address::with_block_size(block, 0, log_block_size);
+        let end = Address::with_block_size(block, len as i32, log_block_size);
+        fs.volume
+            .slice(start..end)
+            .map(FileBlock::Data)
+            .map_err(|err| err.into())
+    }
 
-        index -= bs4;
-
-        if index < bs4 * bs4 {
-            let indirect_index = index >> (log_block_size + 2);
-            let block = match block_index(
-                &fs.volume,
-                self.inner.doubly_indirect,
-                indirect_index,
-                log_block_size,
-            ) {
-                Ok(Some(block)) => block.get(),
-                Ok(None) => return Ok(None),
-                Err(err) => return Err(err),
-            };
-            return block_index(
-                &fs.volume,
-                block,
-                index & (bs4 - 1),
-                log_block_size,
-            );
+    /// Fills `buf` from byte `offset` of the file like `read_at`, failing
+    /// with `Error::UnexpectedEof` if the file ends first
+    pub fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> Result<()> {
+        let read = self.read_at(offset, buf)?;
+        if read < buf.len() {
+            return Err(Error::UnexpectedEof {
+                offset: offset + read as u64,
+            });
         }
+        Ok(())
+    }
 
-        index -= bs4 * bs4;
-
-        if index < bs4 * bs4 * bs4 {
-            let doubly_index = index >> (2 * log_block_size + 4);
-            let indirect = match block_index(
-                &fs.volume,
-                self.inner.triply_indirect,
-                doubly_index,
-                log_block_size,
-            ) {
-                Ok(Some(block)) => block.get(),
-                Ok(None) => return Ok(None),
-                Err(err) => return Err(err),
-            };
-            let indirect_index = (index >> (log_block_size + 2)) & (bs4 - 1);
-            let block = match block_index(
-                &fs.volume,
-                indirect as u32,
-                indirect_index,
-                log_block_size,
-            ) {
-                Ok(Some(block)) => block.get(),
-                Ok(None) => return Ok(None),
-                Err(err) => return Err(err),
+    /// Writes `buf` at byte `offset` of the file, returning the number of
+    /// bytes written.
+    ///
+    /// Missing blocks, including any indirect blocks needed to reach them, are
+    /// allocated on the way. If the volume runs out of space part way, the
+    /// write stops short; it only fails if nothing could be written. The file
+    /// size grows if the write ends past the current end of file.
+    pub fn write_at(
+        &mut self,
+        offset: u64,
+        buf: &[u8],
+    ) -> Result<usize> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(offset == self.size64())?;
+        // before any block is written, in case the size can't be stored
+        self.fit_size(&mut fs, offset.saturating_add(buf.len() as u64))?;
+
+        let block_size = fs.block_size() as u64;
+        let log_block_size = fs.log_block_size();
+        let mut written = 0;
+
+        while written < buf.len() {
+            let position = offset + written as u64;
+            let index = (position >> log_block_size) as usize;
+            let block_offset = position & (block_size - 1);
+            let len =
+                ((block_size - block_offset) as usize).min(buf.len() - written);
+            let data = &buf[written..written + len];
+            let block = match self.lookup_block(&fs, index)? {
+                Some(block) => block.get(),
+                // a hole already reads back as zeroes
+                None if fs.options.sparse_writes
+                    && data.iter().all(|&b| b == 0) =>
+                {
+                    written += len;
+                    continue;
+                }
+                None => match self.map_block(&mut fs, index) {
+                    Ok(block) => block,
+                    Err(_) if written > 0 => break,
+                    Err(err) => {
+                        self.write_back(&mut fs)?;
+                        return Err(err);
+                    }
+                },
             };
-            return block_index(
-                &fs.volume,
+
+            let addr = Address::with_block_size(
                 block,
-                index & (bs4 - 1),
+                block_offset as i32,
                 log_block_size,
             );
+            let data = data.to_vec();
+            fs.commit(data, addr)?;
+            written += len;
         }
 
-        Ok(None)
-    }
-
-    pub fn in_use(&self) -> bool {
-        self.inner.hard_links > 0
-    }
-
-    pub fn uid(&self) -> u16 {
-        self.inner.uid
-    }
-
-    pub fn sectors(&self) -> usize {
-        self.inner.sectors_count as usize
-    }
+        if written > 0 {
+            let end = offset + written as u64;
+            if end > self.size64() {
+                self.set_size(&mut fs, end)?;
+            }
+            self.inner.mtime = fs.now();
+            self.write_back(&mut fs)?;
+        }
 
-    pub fn size32(&self) -> u32 {
-        self.inner.size_low
+        Ok(written)
     }
 
-    pub fn size64(&self) -> u64 {
-        self.inner.size_low as u64 | (self.inner.size_high as u64) << 32
+    /// Re-reads the raw inode from the inode table, discarding any stale copy
+    fn reload(&mut self, fs: &Ext2<S, V>) -> Result<()> {
+        self.inner = fs.read_inode(self.num)?;
+        self.large_file = fs.large_files();
+        Ok(())
     }
 
-    #[cfg(target_pointer_width = "64")]
-    #[inline]
-    pub fn size(&self) -> usize {
-        self.size64() as usize
+    /// Writes the raw inode back to its slot in the inode table
+    fn write_back(&self, fs: &mut Ext2<S, V>) -> Result<()> {
+        fs.write_inode(self.num, &self.inner)
     }
 
-    #[cfg(target_pointer_width = "32")]
-    #[inline]
-    pub fn size(&self) -> usize {
-        self.size32() as usize
+    /// Fails with `Error::OperationNotPermitted` if this inode has the
+    /// `IMMUTABLE` flag, or the `APPEND_ONLY` flag and the change isn't
+    /// `appending` to it: adding to the end of a file, or adding entries to a
+    /// directory
+    fn check_flags(&self, appending: bool) -> Result<()> {
+        let flags = { self.inner.flags };
+        if flags.contains(Flags::IMMUTABLE)
+            || (flags.contains(Flags::APPEND_ONLY) && !appending)
+        {
+            return Err(Error::OperationNotPermitted { inode: self.num });
+        }
+        Ok(())
     }
-}
 
-impl<S: SectorSize, V: Volume<u8, S>> File for Inode<S, V> {
-    type Error = Error;
+    /// Returns the physical block backing logical block `index`, allocating
+    /// it and any missing indirect blocks on the way. Newly allocated blocks
+    /// are zeroed.
+    ///
+    /// The block pointers and sector count are only updated in memory; it's up
+    /// to the caller to write the inode back, even if this fails part way.
+    fn map_block(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        index: usize,
+    ) -> Result<u32> {
+        let bs4 = fs.block_size() / 4;
+        let log_block_size = fs.log_block_size();
+        let log_bs4 = log_block_size - 2;
+
+        // the block pointer slot in the inode, and the indices into each level
+        // of indirect blocks below it
+        let slot = (0..15)
+            .rev()
+            .find(|&slot| slot_layout(slot, bs4).0 <= index)
+            .unwrap_or(0);
+        let (first, depth) = slot_layout(slot, bs4);
+        let relative = index - first;
+        if relative >= bs4.pow(depth as u32) {
+            return Err(Error::OutOfBounds { index });
+        }
+        let mut indices = [0; 3];
+        for (level, index) in indices[..depth].iter_mut().enumerate() {
+            let shift = (depth - 1 - level) as u32 * log_bs4;
+            *index = (relative >> shift) & (bs4 - 1);
+        }
 
-    fn read(&self, buf: &mut [u8]) -> Result<usize, Error> {
-        let total_size = self.size();
-        let block_size = {
-            let fs = self.fs.inner();
-            fs.block_size()
+        // aim right after the previous block of the file, or at the start of
+        // the inode's group for the first one
+        let previous = match index.checked_sub(1) {
+            Some(previous) => self.lookup_block(fs, previous)?,
+            None => None,
         };
-        let mut offset = 0;
+        let mut goal = match previous {
+            Some(previous) => previous.get() + 1,
+            None => {
+                let superblock = &fs.superblock.inner;
+                superblock
+                    .inode_group_and_index(self.num)
+                    .and_then(|(group, _)| superblock.group_first_block(group))
+                    .unwrap_or(superblock.first_data_block)
+            }
+        };
+
+        let mut block = self.block_pointer(slot);
+        if block == 0 {
+            block = self.allocate_zeroed(fs, goal)?;
+            goal = block + 1;
+            self.set_block_pointer(slot, block);
+        }
 
-        for block in self.blocks() {
-            match block {
-                Ok((data, _)) => {
-                    let data_size = block_size
-                        .min(total_size - offset)
-                        .min(buf.len() - offset);
-                    let end = offset + data_size;
-                    buf[offset..end].copy_from_slice(&data[..data_size]);
-                    offset += data_size;
+        for &index in &indices[..depth] {
+            block = match block_index(&fs.volume, block, index, log_block_size)?
+            {
+                Some(next) => next.get(),
+                None => {
+                    let next = self.allocate_zeroed(fs, goal)?;
+                    goal = next + 1;
+                    set_block_index(
+                        fs,
+                        block,
+                        index,
+                        next,
+                        log_block_size,
+                    )?;
+                    next
                 }
-                Err(err) => return Err(err),
-            }
+            };
         }
 
-        Ok(offset)
+        Ok(block)
     }
 
-    fn write(&mut self, _buf: &[u8]) -> Result<usize, Self::Error> {
-        unimplemented!()
+    /// Allocates a zeroed block near `goal` and accounts for it in the
+    /// inode's sector count. The reserved blocks are only available if the
+    /// owner of the inode may use them.
+    fn allocate_zeroed(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        goal: u32,
+    ) -> Result<u32> {
+        let privileged = fs
+            .superblock
+            .inner
+            .may_use_reserved(self.inner.uid, self.inner.gid);
+        let block = fs.allocate_block_for(self.num, goal, privileged)?;
+        let offset = Address::with_block_size(block, 0, fs.log_block_size());
+        let zeroes = vec![0; fs.block_size()];
+        fs.commit(zeroes, offset)?;
+        self.inner.sectors_count = { self.inner.sectors_count }
+            .saturating_add((fs.block_size() >> 9) as u32);
+        Ok(block)
     }
 
-    fn flush(&mut self) -> Result<(), Self::Error> {
-        unimplemented!()
+    /// Returns the `slot`th block pointer of the inode: the 12 direct pointers
+    /// followed by the singly, doubly and triply indirect pointers
+    fn block_pointer(&self, slot: usize) -> u32 {
+        match slot {
+            0..=11 => self.inner.direct_pointer[slot],
+            12 => self.inner.indirect_pointer,
+            13 => self.inner.doubly_indirect,
+            _ => self.inner.triply_indirect,
+        }
     }
 
-    fn seek(&mut self, _pos: SeekFrom) -> Result<u64, Self::Error> {
-        unimplemented!()
+    fn set_block_pointer(&mut self, slot: usize, block: u32) {
+        match slot {
+            0..=11 => self.inner.direct_pointer[slot] = block,
+            12 => self.inner.indirect_pointer = block,
+            13 => self.inner.doubly_indirect = block,
+            _ => self.inner.triply_indirect = block,
+        }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct InodeBlocks<S: SectorSize, V: Volume<u8, S>> {
-    inode: Inode<S, V>,
-    index: usize,
-}
+    /// Looks up the entry named `name` in this directory
+    fn entry(&self, name: &[u8]) -> Result<Option<DirectoryEntry<S, V>>> {
+        let mut dir = match self.directory() {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        if self.is_indexed() {
+            // an index that doesn't add up is no reason not to find the
+            // entry the slow way
+            if let Ok(found) = self.dx_find(name) {
+                return Ok(found);
+            }
+        }
+        dir.find(name)
+    }
 
-impl<S: SectorSize, V: Volume<u8, S>> Iterator for InodeBlocks<S, V> {
-    type Item = Result<(Vec<u8>, Address<S>), Error>;
+    /// Whether this is a directory with a hash tree index worth using
+    fn is_indexed(&self) -> bool {
+        let fs = self.fs.read();
+        let features = fs.superblock.inner.features_opt();
+        features.contains(FeaturesOptional::HASH_INDEX)
+            && { self.inner.flags }.contains(Flags::HASH_DIR)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let block = self.inode.try_block(self.index);
-        let block = match block {
-            Ok(Some(ok)) => ok,
-            Ok(None) => return None,
-            Err(err) => return Some(Err(err)),
-        };
+    /// Looks up the entry named `name` through the hash tree index of this
+    /// directory, reading only the nodes on the way to the leaf the hash of
+    /// the name falls in, and the leaves after it holding colliding hashes.
+    /// Anything in the tree that doesn't add up is `Error::Corrupt`.
+    fn dx_find(&self, name: &[u8]) -> Result<Option<DirectoryEntry<S, V>>> {
+        let fs = self.fs.read();
+        let superblock = &fs.superblock.inner;
+        let root = DxRoot::from_block(&self.dir_block(&fs, 0)?)?;
+        let mut version = root.hash_version;
+        if version <= DX_HASH_TEA
+            && superblock.flags() & FLAGS_UNSIGNED_HASH != 0
+        {
+            version += DX_HASH_LEGACY_UNSIGNED;
+        }
+        let (hash, _) = dx_hash(name, version, superblock.hash_seed())
+            .ok_or(Error::Corrupt {
+                reason: "hash tree with an unknown hash version",
+            })?;
+
+        // the nodes from the root down, each with the entry followed
+        let mut path = vec![(root.entries, 0)];
+        loop {
+            let level = path.len() - 1;
+            let at = dx_probe(&path[level].0, hash);
+            path[level].1 = at;
+            if level == root.indirect_levels as usize {
+                break;
+            }
+            let block = self.dir_block(&fs, path[level].0[at].block)?;
+            path.push((dx_node_entries(&block)?, 0));
+        }
 
-        self.index += 1;
-        let fs = self.inode.fs.inner();
+        let features = superblock.features_req();
+        loop {
+            let block = {
+                let (ref entries, at) = path[path.len() - 1];
+                self.dir_block(&fs, entries[at].block)?
+            };
+            for entry in DirEntryIter::new(&block, features) {
+                let entry = entry?;
+                if entry.header.in_use() && entry.name == name {
+                    return Ok(Some(DirectoryEntry {
+                        name: name.to_vec(),
+                        ino: entry.header.inode,
+                        file_type: entry.header.file_type,
+                        fs: self.fs.clone(),
+                        dir: None,
+                    }));
+                }
+            }
+
+            // names sharing a hash may carry on into the next leaf, which
+            // is then marked by the lowest bit of its hash
+            let mut level = path.len() - 1;
+            loop {
+                path[level].1 += 1;
+                if path[level].1 < path[level].0.len() {
+                    break;
+                }
+                if level == 0 {
+                    return Ok(None);
+                }
+                level -= 1;
+            }
+            let next = path[level].0[path[level].1];
+            if next.hash & !1 != hash {
+                return Ok(None);
+            }
+            for level in level + 1..path.len() {
+                let block = {
+                    let (ref entries, at) = path[level - 1];
+                    self.dir_block(&fs, entries[at].block)?
+                };
+                path[level] = (dx_node_entries(&block)?, 0);
+            }
+        }
+    }
 
+    /// Reads logical block `index` of this directory, which has to be
+    /// mapped and within its size
+    fn dir_block(&self, fs: &Ext2<S, V>, index: u32) -> Result<Vec<u8>> {
+        let block_size = fs.block_size() as u64;
+        if index as u64 >= self.size64() / block_size {
+            return Err(Error::Corrupt {
+                reason: "hash tree pointing past the directory",
+            });
+        }
+        let block = self.lookup_block(fs, index as usize)?.ok_or(
+            Error::Corrupt {
+                reason: "hash tree pointing at a hole",
+            },
+        )?;
         let block = block.get();
         let log_block_size = fs.log_block_size();
-        let offset = Address::with_block_size(block, 0, log_block_size);
+        let start = Address::with_block_size(block, 0, log_block_size);
         let end = Address::with_block_size(block + 1, 0, log_block_size);
-
-        let slice = fs
-            .volume
-            .slice(offset..end)
-            .map(|slice| (slice.to_vec(), offset))
-            .map_err(|err| err.into());
-        Some(slice)
+        fs.volume
+            .slice(start..end)
+            .map(|slice| slice.to_vec())
+            .map_err(|err| err.into())
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct Directory<S: SectorSize, V: Volume<u8, S>> {
-    blocks: InodeBlocks<S, V>,
-    offset: usize,
-    buffer: Option<Vec<u8>>,
-    block_size: usize,
-}
-
-impl<S: SectorSize, V: Volume<u8, S>> Dir<DirectoryEntry, Error>
-    for Directory<S, V>
-{
-}
 
-impl<S: SectorSize, V: Volume<u8, S>> Iterator for Directory<S, V> {
-    type Item = Result<DirectoryEntry, Error>;
+    /// Inserts an entry named `name` pointing at inode `inode` into this
+    /// directory. The first entry with enough slack after its name is split;
+    /// if there is none, a new block is appended to the directory.
+    pub(crate) fn add_entry(
+        &mut self,
+        name: &[u8],
+        inode: u32,
+        file_type: u8,
+    ) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        self.add_entry_locked(&mut fs, name, inode, file_type)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.buffer.is_none() || self.offset >= self.block_size {
-            self.buffer = match self.blocks.next() {
-                None => return None,
-                Some(Ok((block, _))) => Some(block),
-                Some(Err(err)) => return Some(Err(err)),
+    /// Inserts an entry like `add_entry`, with the write lock on the file
+    /// system already held as `fs`
+    fn add_entry_locked(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        name: &[u8],
+        inode: u32,
+        file_type: u8,
+    ) -> Result<()> {
+        check_name(name)?;
+        fs.check_writable()?;
+        self.reload(fs)?;
+        self.check_flags(true)?;
+
+        let block_size = fs.block_size();
+        let log_block_size = fs.log_block_size();
+        fs.dentry_cache.invalidate(self.num, name);
+        let file_type = entry_type(fs, file_type);
+        let needed = dirent_len(name.len());
+        let blocks = self.size().div_ceil(block_size);
+        let features = fs.superblock.inner.features_req();
+
+        // every entry is visited so that duplicates are caught even after a
+        // free slot has been found
+        let mut slot = None;
+        for index in 0..blocks {
+            let block = match self.lookup_block(fs, index)? {
+                Some(block) => block.get(),
+                None => continue,
             };
+            let offset = Address::with_block_size(block, 0, log_block_size);
+            let end = Address::with_block_size(block + 1, 0, log_block_size);
+            let data = fs
+                .volume
+                .slice(offset..end)
+                .map_err(|err| err.into())?
+                .to_vec();
+
+            let mut position = 0;
+            let mut found = None;
+            while position + 8 <= block_size {
+                let header =
+                    DirEntryHeader::from_bytes(&data[position..], features);
+                let entry_inode = header.inode;
+                let rec_len = header.rec_len as usize;
+                if rec_len < 8 || position + rec_len > block_size {
+                    break;
+                }
+                let name_len = header.name_len as usize;
+                let used = if entry_inode == 0 {
+                    0
+                } else {
+                    dirent_len(name_len)
+                };
+
+                if entry_inode != 0
+                    && used <= rec_len
+                    && &data[position + 8..position + 8 + name_len] == name
+                {
+                    return Err(Error::AlreadyExists {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    });
+                }
+                if slot.is_none() && found.is_none() && rec_len >= used + needed
+                {
+                    found = Some((position, rec_len, used));
+                }
 
-            self.offset = 0;
-        }
-
-        let buffer = &self.buffer.as_ref().unwrap()[self.offset..];
+                position += rec_len;
+            }
 
-        let inode = buffer[0] as u32
-            | (buffer[1] as u32) << 8
-            | (buffer[2] as u32) << 16
-            | (buffer[3] as u32) << 24;
-        if inode == 0 {
-            return None;
+            if let Some(found) = found {
+                slot = Some((offset, data, found));
+            }
         }
 
-        let size = buffer[4] as u16 | (buffer[5] as u16) << 8;
-        let len = buffer[6];
-        let ty = buffer[7];
+        // entries go wherever there's room, which an index knows nothing
+        // about, so it can't be trusted anymore
+        self.inner.flags = { self.inner.flags } - Flags::HASH_DIR;
 
-        let name = buffer[8..8 + len as usize].to_vec();
+        if let Some((offset, mut data, (position, rec_len, used))) = slot {
+            let (start, len) = if used == 0 {
+                (position, rec_len)
+            } else {
+                write_u16(&mut data[position + 4..], used as u16);
+                (position + used, rec_len - used)
+            };
+            write_dirent(&mut data[start..], inode, len, name, file_type);
+            fs.commit(data, offset)?;
 
-        self.offset += size as usize;
+            let now = fs.now();
+            self.inner.mtime = now;
+            self.inner.ctime = now;
+            return self.write_back(fs);
+        }
 
-        Some(Ok(DirectoryEntry {
-            name,
-            inode: inode as usize,
-            ty,
-        }))
+        let block = match self.map_block(fs, blocks) {
+            Ok(block) => block,
+            Err(err) => {
+                self.write_back(fs)?;
+                return Err(err);
+            }
+        };
+        let mut data = vec![0; block_size];
+        write_dirent(&mut data, inode, block_size, name, file_type);
+        let offset = Address::with_block_size(block, 0, log_block_size);
+        fs.commit(data, offset)?;
+
+        let size = self.size64() + block_size as u64;
+        self.inner.size_low = size as u32;
+        let now = fs.now();
+        self.inner.mtime = now;
+        self.inner.ctime = now;
+        self.write_back(fs)
     }
-}
 
-#[derive(Clone)]
-pub struct DirectoryEntry {
+    /// Removes the entry named `name` from this directory, returning the
+    /// inode it pointed at. The entry is merged into the one before it, or
+    /// has its inode cleared if it's the first entry of its block.
+    pub(crate) fn remove_entry(&mut self, name: &[u8]) -> Result<u32> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        self.remove_entry_locked(&mut fs, name)
+    }
+
+    /// Removes an entry like `remove_entry`, with the write lock on the file
+    /// system already held as `fs`
+    fn remove_entry_locked(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        name: &[u8],
+    ) -> Result<u32> {
+        if name == b"." || name == b".." {
+            return Err(Error::InvalidName {
+                name: String::from_utf8_lossy(name).into_owned(),
+            });
+        }
+
+        self.update_entry(fs, name, |data, position, previous| match previous {
+            Some(previous) => {
+                let rec_len = read_u16(&data[position + 4..]) as usize;
+                let merged = position - previous + rec_len;
+                write_u16(&mut data[previous + 4..], merged as u16);
+            }
+            None => write_u32(&mut data[position..], 0),
+        })
+    }
+
+    /// Points the existing entry named `name` at inode `inode` instead,
+    /// returning the inode it pointed at before. The write lock on the file
+    /// system is already held as `fs`.
+    fn replace_entry_locked(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        name: &[u8],
+        inode: u32,
+        file_type: u8,
+    ) -> Result<u32> {
+        let file_type = entry_type(fs, file_type);
+        self.update_entry(fs, name, |data, position, _| {
+            write_u32(&mut data[position..], inode);
+            data[position + 7] = file_type;
+        })
+    }
+
+    /// Finds the entry named `name` and lets `update` modify the block that
+    /// holds it, given the entry's offset and the offset of the entry before
+    /// it in the same block. The block is then written back and the inode of
+    /// the entry returned.
+    fn update_entry<F>(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        name: &[u8],
+        update: F,
+    ) -> Result<u32>
+    where
+        F: FnOnce(&mut [u8], usize, Option<usize>),
+    {
+        fs.check_writable()?;
+        self.reload(fs)?;
+        self.check_flags(false)?;
+        fs.dentry_cache.invalidate(self.num, name);
+
+        let block_size = fs.block_size();
+        let log_block_size = fs.log_block_size();
+        let blocks = self.size().div_ceil(block_size);
+        let features = fs.superblock.inner.features_req();
+
+        for index in 0..blocks {
+            let block = match self.lookup_block(fs, index)? {
+                Some(block) => block.get(),
+                None => continue,
+            };
+            let offset = Address::with_block_size(block, 0, log_block_size);
+            let end = Address::with_block_size(block + 1, 0, log_block_size);
+            let mut data = fs
+                .volume
+                .slice(offset..end)
+                .map_err(|err| err.into())?
+                .to_vec();
+
+            let mut position = 0;
+            let mut previous = None;
+            while position + 8 <= block_size {
+                let header =
+                    DirEntryHeader::from_bytes(&data[position..], features);
+                let entry_inode = header.inode;
+                let rec_len = header.rec_len as usize;
+                if rec_len < 8 || position + rec_len > block_size {
+                    break;
+                }
+                let name_len = header.name_len as usize;
+
+                if entry_inode != 0
+                    && dirent_len(name_len) <= rec_len
+                    && &data[position + 8..position + 8 + name_len] == name
+                {
+                    update(&mut data, position, previous);
+                    fs.commit(data, offset)?;
+
+                    let now = fs.now();
+                    self.inner.mtime = now;
+                    self.inner.ctime = now;
+                    self.write_back(fs)?;
+                    return Ok(entry_inode);
+                }
+
+                previous = Some(position);
+                position += rec_len;
+            }
+        }
+
+        Err(Error::NotFound {
+            name: String::from_utf8_lossy(name).into_owned(),
+            parent: self.num,
+        })
+    }
+
+    /// Looks up the entry named `name` like `entry`, returning the inode it
+    /// points at, by reading every block of this directory through `fs`
+    /// rather than taking the lock on the file system
+    fn find_locked(&self, fs: &Ext2<S, V>, name: &[u8]) -> Result<Option<u32>> {
+        let mut found = None;
+        self.scan_locked(fs, |inode, entry| {
+            if entry == name {
+                found = Some(inode);
+            }
+            found.is_some()
+        })?;
+        Ok(found)
+    }
+
+    /// Whether this directory holds nothing but `.` and `..`, read through
+    /// `fs` like `find_locked`
+    fn is_empty_locked(&self, fs: &Ext2<S, V>) -> Result<bool> {
+        let mut empty = true;
+        self.scan_locked(fs, |_, name| {
+            empty = name == b"." || name == b"..";
+            !empty
+        })?;
+        Ok(empty)
+    }
+
+    /// Calls `visit` with the inode and name of the entries in use in this
+    /// directory, in order, until it returns `true`
+    fn scan_locked<F>(&self, fs: &Ext2<S, V>, mut visit: F) -> Result<()>
+    where
+        F: FnMut(u32, &[u8]) -> bool,
+    {
+        let block_size = fs.block_size();
+        let log_block_size = fs.log_block_size();
+        let features = fs.superblock.inner.features_req();
+        for index in 0..self.size().div_ceil(block_size) {
+            let block = match self.lookup_block(fs, index)? {
+                Some(block) => block.get(),
+                None => continue,
+            };
+            let offset = Address::with_block_size(block, 0, log_block_size);
+            let end = Address::with_block_size(block + 1, 0, log_block_size);
+            let data = fs.volume.slice(offset..end).map_err(|err| err.into())?;
+            for entry in DirEntryIter::new(&data, features) {
+                let entry = entry?;
+                let inode = entry.header.inode;
+                if entry.header.in_use() && visit(inode, entry.name) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a link to this inode
+    pub(crate) fn link(&mut self) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        self.link_locked(&mut fs)
+    }
+
+    /// Adds a link like `link`, with the write lock on the file system
+    /// already held as `fs`
+    fn link_locked(&mut self, fs: &mut Ext2<S, V>) -> Result<()> {
+        self.reload(fs)?;
+
+        self.inner.hard_links += 1;
+        self.inner.ctime = fs.now();
+        self.write_back(fs)
+    }
+
+    /// Gives a new directory its first block, holding the `.` and `..`
+    /// entries, with `..` pointing at `parent`
+    fn init_directory(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        parent: u32,
+    ) -> Result<()> {
+        let block_size = fs.block_size();
+        let block = match self.map_block(fs, 0) {
+            Ok(block) => block,
+            Err(err) => {
+                self.write_back(fs)?;
+                return Err(err);
+            }
+        };
+
+        let file_type = entry_type(fs, inode::DIRECTORY);
+        let mut data = vec![0; block_size];
+        let dot_len = dirent_len(1);
+        write_dirent(&mut data, self.num, dot_len, b".", file_type);
+        write_dirent(
+            &mut data[dot_len..],
+            parent,
+            block_size - dot_len,
+            b"..",
+            file_type,
+        );
+        let offset = Address::with_block_size(block, 0, fs.log_block_size());
+        fs.commit(data, offset)?;
+
+        self.inner.size_low = block_size as u32;
+        self.write_back(fs)
+    }
+
+    /// Drops one link to this inode. Once the last link is gone, all of its
+    /// blocks are freed, its deletion time is set and the inode itself is
+    /// returned to the free pool.
+    pub(crate) fn unlink(&mut self) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        self.unlink_locked(&mut fs)
+    }
+
+    /// Drops a link like `unlink`, with the write lock on the file system
+    /// already held as `fs`
+    fn unlink_locked(&mut self, fs: &mut Ext2<S, V>) -> Result<()> {
+        self.reload(fs)?;
+
+        let now = fs.now();
+        self.inner.hard_links = self.inner.hard_links.saturating_sub(1);
+        self.inner.ctime = now;
+        if self.inner.hard_links > 0 {
+            return self.write_back(fs);
+        }
+        self.destroy(fs)
+    }
+
+    /// Frees all blocks of this inode and the inode itself, regardless of
+    /// how many links to it are left
+    fn destroy(&mut self, fs: &mut Ext2<S, V>) -> Result<()> {
+        let freed = self.free_blocks(fs);
+        self.inner.hard_links = 0;
+        self.inner.dtime = fs.now();
+        self.write_back(fs)?;
+        freed?;
+        fs.free_inode(self.num)
+    }
+
+    /// Frees every data and indirect block of this inode and clears its block
+    /// pointers. Fast symlinks keep their target in the block pointers, and
+    /// devices their device number, so they are left alone, unless the inode
+    /// has the `SECURE_DEL` flag.
+    ///
+    /// Like `map_block`, this only updates the inode in memory.
+    fn free_blocks(&mut self, fs: &mut Ext2<S, V>) -> Result<()> {
+        if self.is_fast_symlink(fs) || self.device().is_some() {
+            if { self.inner.flags }.contains(Flags::SECURE_DEL) {
+                for slot in 0..15 {
+                    self.set_block_pointer(slot, 0);
+                }
+            }
+            return Ok(());
+        }
+        self.shrink_blocks(fs, 0)
+    }
+
+    /// Frees every block past the first `keep` logical blocks, including
+    /// indirect blocks that no longer point at anything, and clears the
+    /// pointers to them. If the inode has the `SECURE_DEL` flag, the blocks
+    /// are zeroed before they are freed.
+    ///
+    /// Like `map_block`, this only updates the inode in memory.
+    fn shrink_blocks(
+        &mut self,
+        fs: &mut Ext2<S, V>,
+        keep: usize,
+    ) -> Result<()> {
+        let bs4 = fs.block_size() / 4;
+        let sectors = (fs.block_size() >> 9) as u32;
+        let secure = { self.inner.flags }.contains(Flags::SECURE_DEL);
+        for slot in 0..15 {
+            let block = self.block_pointer(slot);
+            if block == 0 {
+                continue;
+            }
+
+            let (first, depth) = slot_layout(slot, bs4);
+            let keep = keep.saturating_sub(first);
+            let (freed, emptied) = shrink_tree(fs, block, depth, keep, secure)?;
+            self.inner.sectors_count =
+                { self.inner.sectors_count }.saturating_sub(freed * sectors);
+            if emptied {
+                self.set_block_pointer(slot, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the permission bits of this inode with `perm`, keeping its
+    /// file type
+    pub fn set_permissions(&mut self, perm: Permissions) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(false)?;
+
+        self.inner.type_perm = TypePerm::new(self.file_type(), perm);
+        self.inner.ctime = fs.now();
+        self.write_back(&mut fs)
+    }
+
+    /// Sets the user and group IDs of this inode
+    pub fn set_owner(&mut self, uid: u16, gid: u16) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(false)?;
+
+        self.inner.uid = uid;
+        self.inner.gid = gid;
+        self.inner.ctime = fs.now();
+        self.write_back(&mut fs)
+    }
+
+    /// Replaces the flags of this inode with `flags`, as `chattr` does. This
+    /// is how `IMMUTABLE` and `APPEND_ONLY` are taken off again, so neither
+    /// keeps it from being called.
+    pub fn set_flags(&mut self, flags: Flags) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+
+        self.inner.flags = flags;
+        self.inner.ctime = fs.now();
+        self.write_back(&mut fs)
+    }
+
+    /// Sets the access and modification times of this inode, leaving those
+    /// passed as `None` as they are, and its change time to the current time
+    /// according to the clock of the filesystem
+    pub fn set_times(
+        &mut self,
+        atime: Option<u32>,
+        mtime: Option<u32>,
+    ) -> Result<()> {
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(false)?;
+
+        if let Some(atime) = atime {
+            self.inner.atime = atime;
+        }
+        if let Some(mtime) = mtime {
+            self.inner.mtime = mtime;
+        }
+        self.inner.ctime = fs.now();
+        self.write_back(&mut fs)
+    }
+
+    /// Sets the size of this file to `size` bytes, leaving a hole if it grows
+    pub fn set_len(&mut self, size: u64) -> Result<()> {
+        self.truncate(size, false)
+    }
+
+    /// Sets the size of this file to `size` bytes. Shrinking frees every block
+    /// past the new end, along with the indirect blocks left empty. Growing
+    /// allocates zeroed blocks for the new range if `allocate` is set, and
+    /// leaves a hole otherwise.
+    pub fn truncate(&mut self, size: u64, allocate: bool) -> Result<()> {
+        if self.is_dir() {
+            return Err(Error::IsADirectory {
+                inode: self.num,
+                name: String::new(),
+            });
+        }
+
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(false)?;
+
+        let block_size = fs.block_size() as u64;
+        let old_blocks = self.size64().div_ceil(block_size) as usize;
+        let blocks = size.div_ceil(block_size) as usize;
+        if size < self.size64() {
+            let mut shrunk = self.shrink_blocks(&mut fs, blocks);
+            if shrunk.is_ok() {
+                shrunk = self.zero_tail(&mut fs, size);
+            }
+            if let Err(err) = shrunk {
+                self.write_back(&mut fs)?;
+                return Err(err);
+            }
+        } else if allocate {
+            for index in old_blocks..blocks {
+                if let Err(err) = self.map_block(&mut fs, index) {
+                    // don't leave blocks behind past the end of the file
+                    let freed = self.shrink_blocks(&mut fs, old_blocks);
+                    self.write_back(&mut fs)?;
+                    freed?;
+                    return Err(err);
+                }
+            }
+        }
+
+        self.set_size(&mut fs, size)?;
+        let now = fs.now();
+        self.inner.mtime = now;
+        self.inner.ctime = now;
+        self.write_back(&mut fs)
+    }
+
+    /// Allocates zeroed blocks for every byte from the end of the file up to
+    /// `len` that isn't backed by one yet, so that writing there later needs
+    /// no allocations. The file size is left as it is if `keep_size` is set,
+    /// and grows to `len` otherwise. If the volume runs out of space part
+    /// way, the blocks allocated past the end of the file are freed again.
+    ///
+    /// Note that `e2fsck` reports files with blocks past their end as having
+    /// the wrong size.
+    pub fn preallocate(
+        &mut self,
+        len: u64,
+        keep_size: bool,
+    ) -> Result<()> {
+        if self.is_dir() {
+            return Err(Error::IsADirectory {
+                inode: self.num,
+                name: String::new(),
+            });
+        }
+
+        let synced = self.fs.clone();
+        let mut fs = synced.inner();
+        fs.check_writable()?;
+        self.reload(&fs)?;
+        self.check_flags(true)?;
+
+        let block_size = fs.block_size() as u64;
+        let first = (self.size64() / block_size) as usize;
+        let old_blocks = self.size64().div_ceil(block_size) as usize;
+        let blocks = len.div_ceil(block_size) as usize;
+        // the blocks past the end of the file left by an earlier call come
+        // first, so everything past them is allocated here
+        let mut keep = old_blocks;
+        while keep < blocks && self.lookup_block(&fs, keep)?.is_some() {
+            keep += 1;
+        }
+        for index in first..blocks {
+            if self.lookup_block(&fs, index)?.is_some() {
+                continue;
+            }
+            if let Err(err) = self.map_block(&mut fs, index) {
+                let freed = self.shrink_blocks(&mut fs, keep);
+                self.write_back(&mut fs)?;
+                freed?;
+                return Err(err);
+            }
+        }
+
+        let now = fs.now();
+        if !keep_size && len > self.size64() {
+            self.set_size(&mut fs, len)?;
+            self.inner.mtime = now;
+        }
+        self.inner.ctime = now;
+        self.write_back(&mut fs)
+    }
+
+    /// Zeroes the rest of the block holding byte `size`, so that the file
+    /// reads back zeroes if it grows again
+    fn zero_tail(&self, fs: &mut Ext2<S, V>, size: u64) -> Result<()> {
+        let log_block_size = fs.log_block_size();
+        let block_size = fs.block_size();
+        let start = (size & (block_size as u64 - 1)) as usize;
+        if start == 0 {
+            return Ok(());
+        }
+        let index = (size >> log_block_size) as usize;
+        let block = match self.lookup_block(fs, index)? {
+            Some(block) => block.get(),
+            None => return Ok(()),
+        };
+        let addr =
+            Address::with_block_size(block, start as i32, log_block_size);
+        let zeroes = vec![0; block_size - start];
+        fs.commit(zeroes, addr)
+    }
+
+    /// The directory entry type matching the mode of this inode
+    fn dirent_type(&self) -> u8 {
+        self.file_type().dirent()
+    }
+
+    /// Whether this is a symlink whose target is stored in the inode itself
+    fn is_fast_symlink(&self, fs: &Ext2<S, V>) -> bool {
+        let xattr_sectors = if self.inner.ext_attribute_block != 0 {
+            (fs.block_size() >> 9) as u32
+        } else {
+            0
+        };
+        self.is_symlink()
+            && self.size64() < 60
+            && self.inner.sectors_count == xattr_sectors
+    }
+
+    /// The major and minor numbers of a character or block device, see
+    /// `RawInode::device`
+    pub fn device(&self) -> Option<(u32, u32)> {
+        self.inner.device()
+    }
+
+    /// The names of the extended attributes of this file, prefix included,
+    /// in the order they're stored. Attributes whose name index has no
+    /// known prefix are left out, as Linux leaves them out.
+    pub fn list_xattrs(&self) -> Result<Vec<Vec<u8>>> {
+        let mut names = Vec::new();
+        if let Some((number, block)) = self.xattr_block()? {
+            for entry in XattrIter::new(&block, number)? {
+                names.extend(entry?.full_name());
+            }
+        }
+        Ok(names)
+    }
+
+    /// The value of the extended attribute `name` of this file, prefix
+    /// included, or `None` if it has no such attribute. ACLs are given in
+    /// the format `getxattr(2)` has them in rather than the one they're
+    /// stored in.
+    pub fn get_xattr<N: AsRef<[u8]>>(
+        &self,
+        name: N,
+    ) -> Result<Option<Vec<u8>>> {
+        let (number, block) = match self.xattr_block()? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        for entry in XattrIter::new(&block, number)? {
+            let entry = entry?;
+            if entry.full_name().as_deref() != Some(name.as_ref()) {
+                continue;
+            }
+            return match entry.name_index {
+                xattr::INDEX_POSIX_ACL_ACCESS
+                | xattr::INDEX_POSIX_ACL_DEFAULT => {
+                    xattr::acl_to_xattr(entry.value).map(Some)
+                }
+                _ => Ok(Some(entry.value.to_vec())),
+            };
+        }
+        Ok(None)
+    }
+
+    /// The access ACL of this file, from its `system.posix_acl_access`
+    /// attribute, or `None` if it has none and its permission bits are all
+    /// there is to its access control
+    pub fn get_acl(&self) -> Result<Option<Acl>> {
+        self.get_xattr(b"system.posix_acl_access")?
+            .map(|value| Acl::from_xattr(&value))
+            .transpose()
+    }
+
+    /// The ACL new entries of this directory inherit, from its
+    /// `system.posix_acl_default` attribute, or `None` if it has none
+    pub fn get_default_acl(&self) -> Result<Option<Acl>> {
+        self.get_xattr(b"system.posix_acl_default")?
+            .map(|value| Acl::from_xattr(&value))
+            .transpose()
+    }
+
+    /// The number and contents of the block holding the extended attributes
+    /// of this file, if it has one. Revision 0 file systems have none.
+    fn xattr_block(&self) -> Result<Option<(u32, Vec<u8>)>> {
+        let fs = self.fs.read();
+        let block = self.inner.ext_attribute_block;
+        if block == 0 || fs.superblock.inner.is_rev_0() {
+            return Ok(None);
+        }
+        fs.check_block(block)?;
+        let log_block_size = fs.log_block_size();
+        let start = Address::with_block_size(block, 0, log_block_size);
+        let end = Address::with_block_size(block + 1, 0, log_block_size);
+        let data = fs.volume.slice(start..end).map_err(|err| err.into())?;
+        Ok(Some((block, data.to_vec())))
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type() == FileType::Symlink
+    }
+
+    /// Returns the target of this symlink. Targets shorter than 60 bytes are
+    /// usually stored in place of the block pointers; longer ones live in the
+    /// first data block.
+    pub fn read_link(&self) -> Result<Vec<u8>> {
+        if !self.is_symlink() {
+            return Err(Error::NotASymlink { inode: self.num });
+        }
+
+        let fs = self.fs.read();
+        let size = self.size();
+        if self.is_fast_symlink(&fs) {
+            let mut target = Vec::with_capacity(60);
+            for slot in 0..15 {
+                target
+                    .extend_from_slice(&self.block_pointer(slot).to_le_bytes());
+            }
+            target.truncate(size);
+            return Ok(target);
+        }
+
+        let block_size = fs.block_size();
+        if size >= block_size {
+            return Err(Error::OutOfBounds { index: size });
+        }
+        let block = self
+            .lookup_block(&fs, 0)?
+            .ok_or(Error::InodeNotFound { inode: self.num })?;
+        let log_block_size = fs.log_block_size();
+        let offset = Address::with_block_size(block.get(), 0, log_block_size);
+        let end =
+            Address::with_block_size(block.get(), size as i32, log_block_size);
+        let target = fs.volume.slice(offset..end).map_err(|err| err.into())?;
+        Ok(target.to_vec())
+    }
+
+    /// Returns the target of this symlink as a `String`, failing if it isn't
+    /// valid UTF-8
+    pub fn read_link_str(&self) -> Result<String> {
+        String::from_utf8(self.read_link()?)
+            .map_err(|_| Error::InvalidUtf8 { inode: self.num })
+    }
+
+    pub fn blocks(&self) -> InodeBlocks<S, V> {
+        InodeBlocks {
+            inode: self.clone(),
+            walk: None,
+            index: 0,
+        }
+    }
+
+    pub fn directory(&self) -> Option<Directory<S, V>> {
+        self.entries_from(0)
+    }
+
+    /// Lists the entries of this directory from `pos`, a position given by
+    /// `Directory::pos`, as `getdents` carries on from the offset of the
+    /// directory. If entries were added or removed since, so that `pos`
+    /// no longer falls on an entry, the listing resumes at the first entry
+    /// after it in its block.
+    pub fn entries_from(&self, pos: u64) -> Option<Directory<S, V>> {
+        if self.is_dir() {
+            let fs = self.fs.read();
+            let block_size = fs.block_size();
+            let mut blocks = self.blocks();
+            blocks.index = (pos / block_size as u64) as usize;
+            Some(Directory {
+                blocks,
+                offset: (pos % block_size as u64) as usize,
+                buffer: None,
+                block_size,
+                features: fs.superblock.inner.features_req(),
+                path: None,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The type of this file, from its mode
+    pub fn file_type(&self) -> FileType {
+        self.inner.type_perm().file_type()
+    }
+
+    /// The permission bits of this file, from its mode
+    pub fn permissions(&self) -> Permissions {
+        self.inner.type_perm().permissions()
+    }
+
+    /// The inode number
+    pub fn ino(&self) -> u32 {
+        self.num
+    }
+
+    /// The metadata of this file, as of when the inode was last read
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(self.num, &self.inner, self.large_file)
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == FileType::Directory
+    }
+
+    pub fn block(&self, index: usize) -> Option<NonZeroU32> {
+        self.try_block(index).ok().and_then(|block| block)
+    }
+
+    pub fn try_block(&self, index: usize) -> Result<Option<NonZeroU32>> {
+        let fs = self.fs.read();
+        self.lookup_block(&fs, index)
+    }
+
+    /// The physical block holding block `logical_block` of the file, as
+    /// the `FIBMAP` ioctl gives, or `None` for a hole. Only indirect blocks
+    /// are read on the way, never the data block. A block past the end of
+    /// the file is `None` too, as is any block of a symlink whose target is
+    /// stored in the inode itself.
+    pub fn bmap(&self, logical_block: u32) -> Result<Option<u32>> {
+        let fs = self.fs.read();
+        let blocks = (self.size64() + fs.block_size() as u64 - 1)
+            >> fs.log_block_size();
+        if logical_block as u64 >= blocks || self.is_fast_symlink(&fs) {
+            return Ok(None);
+        }
+        let block = self.lookup_block(&fs, logical_block as usize)?;
+        Ok(block.map(NonZeroU32::get))
+    }
+
+    /// Lists the file as runs of blocks contiguous on the volume, and runs
+    /// of holes, in order, like the `FIEMAP` ioctl. Together the runs cover
+    /// every block up to the end of the file. The map is read as the runs
+    /// are, holding on to no more than one indirect block per level of the
+    /// block tree.
+    pub fn extents(&self) -> Extents<S, V> {
+        let fs = self.fs.read();
+        let blocks = (self.size64() + fs.block_size() as u64 - 1)
+            >> fs.log_block_size();
+        let blocks = if self.is_fast_symlink(&fs) { 0 } else { blocks };
+        Extents {
+            fs: self.fs.clone(),
+            walk: BlockWalk::new(&self.inner, &fs.superblock.inner),
+            index: 0,
+            blocks,
+            pending: None,
+            done: false,
+        }
+    }
+
+    fn lookup_block(
+        &self,
+        fs: &Ext2<S, V>,
+        index: usize,
+    ) -> Result<Option<NonZeroU32>> {
+        let log_block_size = fs.log_block_size();
+        self.lookup_block_with(fs, index, &mut |block, index, _| {
+            block_index(&fs.volume, block, index, log_block_size)
+        })
+    }
+
+    /// Maps blocks `first..first + count` of the file like `lookup_block`,
+    /// reading each indirect block on the way once rather than once for
+    /// every block it maps
+    fn map_blocks(
+        &self,
+        fs: &Ext2<S, V>,
+        first: usize,
+        count: usize,
+    ) -> Result<Vec<Option<NonZeroU32>>> {
+        let mut cache = PointerCache::default();
+        (first..first + count)
+            .map(|index| {
+                self.lookup_block_with(fs, index, &mut |block, index, level| {
+                    cache.pointer(fs, block, index, level)
+                })
+            })
+            .collect()
+    }
+
+    /// Maps block `index` of the file like `lookup_block`, reading the
+    /// pointers in indirect blocks through `pointer`. It's given the
+    /// indirect block, the index of the pointer in it and how many more
+    /// levels of indirect blocks there are below, 0 for the pointer to a
+    /// data block.
+    fn lookup_block_with<F>(
+        &self,
+        fs: &Ext2<S, V>,
+        index: usize,
+        pointer: &mut F,
+    ) -> Result<Option<NonZeroU32>>
+    where
+        F: FnMut(u32, usize, usize) -> Result<Option<NonZeroU32>>,
+    {
+        // every pointer on the way comes off the volume, and may point
+        // anywhere if it's corrupt
+        let check = |block: Option<NonZeroU32>| match block {
+            Some(block) => fs.check_block(block.get()).map(|_| Some(block)),
+            None => Ok(None),
+        };
+
+        let log_block_size = fs.log_block_size();
+        let path = BlockPath::new(&self.inner, index as u64, log_block_size);
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut block = match check(NonZeroU32::new(path.root))? {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        let depth = path.indices().len();
+        for (level, &index) in path.indices().iter().enumerate() {
+            let below = depth - level - 1;
+            block = match check(pointer(block.get(), index, below)?)? {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(block))
+    }
+
+    pub fn in_use(&self) -> bool {
+        self.inner.hard_links > 0
+    }
+
+    pub fn uid(&self) -> u16 {
+        self.inner.uid
+    }
+
+    pub fn sectors(&self) -> usize {
+        self.inner.sectors_count as usize
+    }
+
+    pub fn size32(&self) -> u32 {
+        self.inner.size_low
+    }
+
+    /// The size of the file, as `RawInode::size` gives it. Whether the file
+    /// system has the large file feature is as it was when the inode was
+    /// last read.
+    pub fn size64(&self) -> u64 {
+        self.inner.size_with(self.large_file)
+    }
+
+    /// Turns on the large file feature if the file is to reach `size`
+    /// bytes, and it takes more than 32 bits
+    fn fit_size(&mut self, fs: &mut Ext2<S, V>, size: u64) -> Result<()> {
+        if size >> 32 != 0 && !self.large_file {
+            fs.enable_large_files()?;
+            self.large_file = true;
+        }
+        Ok(())
+    }
+
+    /// Sets the size of the file to `size`, see `fit_size`
+    fn set_size(&mut self, fs: &mut Ext2<S, V>, size: u64) -> Result<()> {
+        self.fit_size(fs, size)?;
+        self.inner.size_low = size as u32;
+        self.inner.size_high = (size >> 32) as u32;
+        Ok(())
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size64() as usize
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size32() as usize
+    }
+}
+
+/// Frees `block` and, if it's an indirect block `depth` levels above the data,
+/// every block below it. Returns the number of blocks freed. With `secure`
+/// set, the blocks are zeroed before they are freed.
+fn free_tree<S: SectorSize, V: Volume<u8, S>>(
+    fs: &mut Ext2<S, V>,
+    block: u32,
+    depth: usize,
+    secure: bool,
+) -> Result<u32> {
+    let mut walk = BlockWalk::tree(block, depth, &fs.superblock.inner);
+    let mut freed = 0;
+    while let Some(block) = walk.next(&fs.volume) {
+        let block = block?.block();
+        // the walk has read the pointers of an indirect block by now, so it
+        // can go right away
+        if secure {
+            let offset =
+                Address::with_block_size(block, 0, fs.log_block_size());
+            let zeroes = vec![0; fs.block_size()];
+            fs.commit(zeroes, offset)?;
+        }
+        fs.free_block(block)?;
+        freed += 1;
+    }
+    Ok(freed)
+}
+
+/// Describes the blocks reached through the `slot`th block pointer of an inode
+/// as the first logical block below it and the number of levels of indirect
+/// blocks between the pointer and the data, given `bs4` pointers per block
+fn slot_layout(slot: usize, bs4: usize) -> (usize, usize) {
+    match slot {
+        0..=11 => (slot, 0),
+        12 => (12, 1),
+        13 => (12 + bs4, 2),
+        _ => (12 + bs4 + bs4 * bs4, 3),
+    }
+}
+
+/// Frees every block below `block`, which is `depth` levels of indirect blocks
+/// above the data, past the first `keep` data blocks. Returns the number of
+/// blocks freed and whether `block` itself was freed. With `secure` set, the
+/// blocks are zeroed before they are freed.
+fn shrink_tree<S: SectorSize, V: Volume<u8, S>>(
+    fs: &mut Ext2<S, V>,
+    block: u32,
+    depth: usize,
+    keep: usize,
+    secure: bool,
+) -> Result<(u32, bool)> {
+    if keep == 0 {
+        return Ok((free_tree(fs, block, depth, secure)?, true));
+    }
+    let bs4 = fs.block_size() / 4;
+    let coverage = bs4.pow(depth as u32);
+    if keep >= coverage {
+        return Ok((0, false));
+    }
+    fs.check_block(block)?;
+
+    let log_block_size = fs.log_block_size();
+    let offset = Address::with_block_size(block, 0, log_block_size);
+    let end = Address::with_block_size(block + 1, 0, log_block_size);
+    let mut pointers = fs
+        .volume
+        .slice(offset..end)
+        .map_err(|err| err.into())?
+        .to_vec();
+
+    let coverage = coverage / bs4;
+    let mut freed = 0;
+    let mut changed = false;
+    for index in keep / coverage..bs4 {
+        let pointer = read_u32(&pointers[index * 4..]);
+        if pointer == 0 {
+            continue;
+        }
+        let keep = keep.saturating_sub(index * coverage);
+        let (count, emptied) =
+            shrink_tree(fs, pointer, depth - 1, keep, secure)?;
+        freed += count;
+        if emptied {
+            write_u32(&mut pointers[index * 4..], 0);
+            changed = true;
+        }
+    }
+
+    if changed {
+        fs.commit(pointers, offset)?;
+    }
+    Ok((freed, false))
+}
+
+/// Reads the `index`th block pointer stored in the indirect block `block`
+fn block_index<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+    block: u32,
+    index: usize,
+    log_block_size: u32,
+) -> Result<Option<NonZeroU32>> {
+    let offset = (index * 4) as i32;
+    let end = offset + 4;
+    let addr = Address::with_block_size(block, offset, log_block_size);
+    let end = Address::with_block_size(block, end, log_block_size);
+    let block = volume.slice(addr..end).map_err(|err| err.into())?;
+    if block.len() < 4 {
+        return Err(Error::AddressOutOfBounds {
+            sector: end.sector(),
+            offset: end.offset(),
+            size: end.sector_size(),
+        });
+    }
+    Ok(NonZeroU32::new(read_u32(&block)))
+}
+
+/// The indirect block last read at each level of the block tree of a file,
+/// with its pointers, so that mapping consecutive blocks reads every indirect
+/// block once
+#[derive(Debug, Clone, Default)]
+struct PointerCache {
+    levels: [Option<(u32, Vec<u8>)>; 3],
+}
+
+impl PointerCache {
+    /// Reads the `index`th pointer of the indirect block `block`, `level`
+    /// levels above the data blocks, as for `Inode::lookup_block_with`
+    fn pointer<S: SectorSize, V: Volume<u8, S>>(
+        &mut self,
+        fs: &Ext2<S, V>,
+        block: u32,
+        index: usize,
+        level: usize,
+    ) -> Result<Option<NonZeroU32>> {
+        let hit = match self.levels[level] {
+            Some((cached, _)) => cached == block,
+            None => false,
+        };
+        if !hit {
+            let log_block_size = fs.log_block_size();
+            let start = Address::with_block_size(block, 0, log_block_size);
+            let end = Address::with_block_size(block + 1, 0, log_block_size);
+            let data = fs.volume.slice(start..end).map_err(|err| err.into())?;
+            if data.len() < fs.block_size() {
+                return Err(Error::AddressOutOfBounds {
+                    sector: end.sector(),
+                    offset: end.offset(),
+                    size: end.sector_size(),
+                });
+            }
+            self.levels[level] = Some((block, data.to_vec()));
+        }
+        let pointers = &self.levels[level].as_ref().unwrap().1;
+        Ok(NonZeroU32::new(read_u32(&pointers[index * 4..])))
+    }
+}
+
+/// Stores `value` as the `index`th block pointer of the indirect block `block`
+fn set_block_index<S: SectorSize, V: Volume<u8, S>>(
+    fs: &mut Ext2<S, V>,
+    block: u32,
+    index: usize,
+    value: u32,
+    log_block_size: u32,
+) -> Result<()> {
+    let addr =
+        Address::with_block_size(block, (index * 4) as i32, log_block_size);
+    fs.commit(value.to_le_bytes().to_vec(), addr)
+}
+
+/// Fails with `Error::NotAbsolute` unless `path` starts at the root
+pub(crate) fn check_absolute(path: &[u8]) -> Result<()> {
+    if path.first() != Some(&b'/') {
+        return Err(Error::NotAbsolute {
+            name: String::from_utf8_lossy(path).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Splits a path into its parent directory and its last component. The
+/// parent of a single relative component is the empty path.
+fn split_relative(path: &[u8]) -> (&[u8], &[u8]) {
+    match split_path(path) {
+        Ok(split) => split,
+        Err(_) => (b"", path),
+    }
+}
+
+/// Splits an absolute path into its parent directory and its last component
+fn split_path(abs_path: &[u8]) -> Result<(&[u8], &[u8])> {
+    let mut path = abs_path;
+    while path.len() > 1 && path[path.len() - 1] == b'/' {
+        path = &path[..path.len() - 1];
+    }
+    match path.iter().rposition(|byte| *byte == b'/') {
+        Some(0) => Ok((b"/", &path[1..])),
+        Some(index) => Ok((&path[..index], &path[index + 1..])),
+        None => Err(Error::NotAbsolute {
+            name: String::from_utf8_lossy(abs_path).into_owned(),
+        }),
+    }
+}
+
+/// The type byte to store in a directory entry for `file_type`, which is only
+/// recorded if the file system has the filetype feature
+fn entry_type<S: SectorSize, V: Volume<u8, S>>(
+    fs: &Ext2<S, V>,
+    file_type: u8,
+) -> u8 {
+    if { fs.superblock.inner.features_req }
+        .contains(FeaturesRequired::REQ_DIRECTORY_TYPE)
+    {
+        file_type
+    } else {
+        0
+    }
+}
+
+/// Checks that `name` can be stored in a directory entry
+fn check_name(name: &[u8]) -> Result<()> {
+    if name.len() > MAX_NAME_LEN {
+        return Err(Error::NameTooLong {
+            name: String::from_utf8_lossy(name).into_owned(),
+        });
+    }
+    Ok(())
+}
+
+/// Writes a directory entry header followed by its name at the start of `buf`
+pub(crate) fn write_dirent(
+    buf: &mut [u8],
+    inode: u32,
+    rec_len: usize,
+    name: &[u8],
+    file_type: u8,
+) {
+    write_u32(buf, inode);
+    write_u16(&mut buf[4..], rec_len as u16);
+    buf[6] = name.len() as u8;
+    buf[7] = file_type;
+    buf[8..8 + name.len()].copy_from_slice(name);
+}
+
+fn read_u16(buf: &[u8]) -> u16 {
+    buf[0] as u16 | (buf[1] as u16) << 8
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    buf[0] as u32
+        | (buf[1] as u32) << 8
+        | (buf[2] as u32) << 16
+        | (buf[3] as u32) << 24
+}
+
+fn write_u16(buf: &mut [u8], value: u16) {
+    buf[..2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(buf: &mut [u8], value: u32) {
+    buf[..4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Calls `field` with the name and value of each field of `options`, as its
+/// derived `Debug` implementation writes them. The builders of genfs 0.1,
+/// `OpenOptions` and `DirOptions`, keep their fields private and have no
+/// getters, so this is the only way of reading them back. `options_debug`
+/// in the tests pins the representation relied on, so that a genfs upgrade
+/// changing it fails there rather than silently here.
+///
+/// The representation is scanned as it's written, without allocating. A
+/// value nested in braces or parentheses is reported whole, unless it's, or
+/// its name is, longer than `OptionField::CAPACITY`.
+fn option_fields<T: Debug, F: FnMut(&str, &str)>(options: &T, field: F) {
+    use core::fmt::Write;
+
+    let mut scanner = OptionScanner {
+        field,
+        depth: 0,
+        in_value: false,
+        name: OptionField::default(),
+        value: OptionField::default(),
+    };
+    let _ = write!(scanner, "{:?}", options);
+}
+
+/// A name or value being scanned by `option_fields`
+#[derive(Default)]
+struct OptionField {
+    bytes: [u8; OptionField::CAPACITY],
+    len: usize,
+}
+
+impl OptionField {
+    const CAPACITY: usize = 32;
+
+    fn push(&mut self, byte: u8) {
+        if self.len < OptionField::CAPACITY {
+            self.bytes[self.len] = byte;
+        }
+        self.len += 1;
+    }
+
+    /// The trimmed contents, unless they overflowed
+    fn get(&self) -> Option<&str> {
+        if self.len > OptionField::CAPACITY {
+            return None;
+        }
+        core::str::from_utf8(&self.bytes[..self.len])
+            .ok()
+            .map(str::trim)
+    }
+}
+
+struct OptionScanner<F> {
+    field: F,
+    /// How many braces and parentheses are open; the fields of the options
+    /// are at depth 1
+    depth: usize,
+    in_value: bool,
+    name: OptionField,
+    value: OptionField,
+}
+
+impl<F: FnMut(&str, &str)> fmt::Write for OptionScanner<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &byte in s.as_bytes() {
+            let depth = self.depth;
+            match byte {
+                b'{' | b'(' => self.depth += 1,
+                b'}' | b')' => self.depth = self.depth.saturating_sub(1),
+                _ => (),
+            }
+            match byte {
+                // the name of the type, up to the opening brace
+                _ if depth == 0 => (),
+                // a field ends where the next begins, or the options do
+                b',' | b'}' if depth == 1 => {
+                    if let (true, Some(name), Some(value)) =
+                        (self.in_value, self.name.get(), self.value.get())
+                    {
+                        (self.field)(name, value);
+                    }
+                    self.in_value = false;
+                    self.name = OptionField::default();
+                    self.value = OptionField::default();
+                }
+                b':' if depth == 1 && !self.in_value => self.in_value = true,
+                _ if self.in_value => self.value.push(byte),
+                _ => self.name.push(byte),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The flags of a `genfs::OpenOptions`, including the custom ones this crate
+/// understands.
+///
+/// `OpenOptions` doesn't expose its fields, so they are recovered from its
+/// `Debug` representation, see `option_fields`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct OpenFlags {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    nofollow: bool,
+}
+
+impl OpenFlags {
+    fn new<P: Debug>(options: &OpenOptions<P>) -> OpenFlags {
+        let mut flags = OpenFlags::default();
+        option_fields(options, |name, value| {
+            let set = value == "true";
+            match name {
+                "read" => flags.read = set,
+                "write" => flags.write = set,
+                "append" => flags.append = set,
+                "truncate" => flags.truncate = set,
+                "create" => flags.create = set,
+                "create_new" => flags.create_new = set,
+                "flags" => {
+                    let custom = value.parse::<u32>().unwrap_or(0);
+                    flags.nofollow = custom & O_NOFOLLOW != 0;
+                }
+                _ => (),
+            }
+        });
+        flags
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> File for Inode<S, V> {
+    type Error = Error;
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut position = self.position.lock();
+        let read = self.read_at(*position, buf)?;
+        *position += read as u64;
+        Ok(read)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let offset = if self.append {
+            // someone else may have grown the file in the meantime
+            let synced = self.fs.clone();
+            let fs = synced.read();
+            self.reload(&fs)?;
+            self.size64()
+        } else {
+            *self.position.get_mut()
+        };
+        let written = self.write_at(offset, buf)?;
+        *self.position.get_mut() = offset + written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // every write is committed to the volume immediately
+        Ok(())
+    }
+
+    /// Moves the position, which may go past the end of the file but not
+    /// before its start
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(delta) => (*self.position.get_mut(), delta),
+            SeekFrom::End(delta) => {
+                let synced = self.fs.clone();
+                self.reload(&synced.read())?;
+                (self.size64(), delta)
+            }
+        };
+        let position = if delta < 0 {
+            base.checked_sub(delta.unsigned_abs())
+                .ok_or(Error::InvalidSeek { base, delta })?
+        } else {
+            base.checked_add(delta as u64)
+                .ok_or(Error::ArithmeticOverflow)?
+        };
+        *self.position.get_mut() = position;
+        Ok(position)
+    }
+}
+
+#[cfg(any(test, feature = "embedded-io"))]
+impl<S: SectorSize, V: Volume<u8, S>> embedded_io::ErrorType for Inode<S, V> {
+    type Error = Error;
+}
+
+/// Reads from the position as `File::read` does
+#[cfg(any(test, feature = "embedded-io"))]
+impl<S: SectorSize, V: Volume<u8, S>> embedded_io::Read for Inode<S, V> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        File::read(self, buf)
+    }
+}
+
+/// Writes at the position, or at the end if opened for appending, as
+/// `File::write` does
+#[cfg(any(test, feature = "embedded-io"))]
+impl<S: SectorSize, V: Volume<u8, S>> embedded_io::Write for Inode<S, V> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        File::write(self, buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        File::flush(self)
+    }
+}
+
+#[cfg(any(test, feature = "embedded-io"))]
+impl<S: SectorSize, V: Volume<u8, S>> embedded_io::Seek for Inode<S, V> {
+    fn seek(&mut self, pos: embedded_io::SeekFrom) -> Result<u64> {
+        let pos = match pos {
+            embedded_io::SeekFrom::Start(offset) => SeekFrom::Start(offset),
+            embedded_io::SeekFrom::End(delta) => SeekFrom::End(delta),
+            embedded_io::SeekFrom::Current(delta) => SeekFrom::Current(delta),
+        };
+        File::seek(self, pos)
+    }
+}
+
+/// Reads from the position as `File::read` does, so that a file can be
+/// handed to anything taking an `io::Read`:
+///
+/// ```
+/// # use ext2::fs::sync::Synced;
+/// # use ext2::fs::Ext2;
+/// # use ext2::sector::Size512;
+/// use std::io;
+///
+/// let volume = std::fs::read("ext2.img")?;
+/// let fs = Synced::<Ext2<Size512, _>>::new(volume)?;
+/// let mut readme = fs.lookup(b"/home/funky/README.md")?;
+/// io::copy(&mut readme, &mut io::stdout())?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl<S: SectorSize, V: Volume<u8, S>> std::io::Read for Inode<S, V> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(File::read(self, buf)?)
+    }
+}
+
+/// Writes at the position, or at the end if opened for appending, as
+/// `File::write` does
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl<S: SectorSize, V: Volume<u8, S>> std::io::Write for Inode<S, V> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(File::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(File::flush(self)?)
+    }
+}
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+impl<S: SectorSize, V: Volume<u8, S>> std::io::Seek for Inode<S, V> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            std::io::SeekFrom::Start(offset) => SeekFrom::Start(offset),
+            std::io::SeekFrom::End(delta) => SeekFrom::End(delta),
+            std::io::SeekFrom::Current(delta) => SeekFrom::Current(delta),
+        };
+        Ok(File::seek(self, pos)?)
+    }
+}
+
+/// A block of a file as read by `Inode::read_block`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileBlock<'a, S: SectorSize> {
+    /// The bytes of the block, borrowed from the volume if it lends them
+    Data(VolumeSlice<'a, u8, S>),
+    /// A hole of this many bytes, which reads back as zeroes
+    Zeroes(usize),
+}
+
+impl<'a, S: SectorSize> FileBlock<'a, S> {
+    pub fn len(&self) -> usize {
+        match *self {
+            FileBlock::Data(ref data) => data.len(),
+            FileBlock::Zeroes(len) => len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the bytes of the block, zeroes for a hole
+    pub fn to_vec(&self) -> Vec<u8> {
+        match *self {
+            FileBlock::Data(ref data) => data.to_vec(),
+            FileBlock::Zeroes(len) => vec![0; len],
+        }
+    }
+}
+
+/// Iterator over the data blocks of an inode, up to the first hole
+#[derive(Debug, Clone)]
+pub struct InodeBlocks<S: SectorSize, V: Volume<u8, S>> {
+    inode: Inode<S, V>,
+    /// Started by the first call to `next`, as `Inode::blocks` may be
+    /// called with the file system locked
+    walk: Option<BlockWalk>,
+    index: usize,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Iterator for InodeBlocks<S, V> {
+    type Item = Result<(Vec<u8>, Address<S>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fs = self.inode.fs.read();
+        let inode = &self.inode.inner;
+        let walk = self.walk.get_or_insert_with(|| {
+            BlockWalk::new(inode, &fs.superblock.inner)
+        });
+        let block = loop {
+            match walk.next(&fs.volume)? {
+                Ok(InodeBlock::Data { index, block }) => {
+                    // a directory listing may start further in
+                    if index < self.index as u64 {
+                        continue;
+                    }
+                    if index != self.index as u64 {
+                        return None;
+                    }
+                    break block;
+                }
+                Ok(InodeBlock::Indirect { .. }) => continue,
+                Err(err) => return Some(Err(err)),
+            }
+        };
+        self.index += 1;
+
+        let log_block_size = fs.log_block_size();
+        let offset = Address::with_block_size(block, 0, log_block_size);
+        let end = Address::with_block_size(block + 1, 0, log_block_size);
+
+        let slice = fs
+            .volume
+            .slice(offset..end)
+            .map(|slice| (slice.to_vec(), offset))
+            .map_err(|err| err.into());
+        Some(slice)
+    }
+}
+
+/// A run of blocks of a file, as listed by `Inode::extents`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// Index in the file of the first block of the run
+    pub logical: u32,
+    /// Block on the volume the run starts at, `None` for a hole
+    pub physical: Option<u32>,
+    /// Number of blocks in the run
+    pub len: u32,
+}
+
+/// Iterator over the runs of blocks of a file, see `Inode::extents`
+#[derive(Debug, Clone)]
+pub struct Extents<S: SectorSize, V: Volume<u8, S>> {
+    fs: Synced<Ext2<S, V>>,
+    walk: BlockWalk,
+    /// Index of the first block of the next run
+    index: u64,
+    /// Number of blocks in the file
+    blocks: u64,
+    /// The data block after the last run, walked while looking for its end
+    pending: Option<(u64, u32)>,
+    done: bool,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Extents<S, V> {
+    /// Walks to the next data block within the file
+    fn data(&mut self, fs: &Ext2<S, V>) -> Result<Option<(u64, u32)>> {
+        if let Some(data) = self.pending.take() {
+            return Ok(Some(data));
+        }
+        while let Some(block) = self.walk.next(&fs.volume) {
+            if let InodeBlock::Data { index, block } = block? {
+                if index >= self.blocks {
+                    break;
+                }
+                return Ok(Some((index, block)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The next run, either of holes or of data blocks
+    fn run(&mut self) -> Result<Extent> {
+        let fs = self.fs.clone();
+        let fs = fs.read();
+        let logical = self.index;
+        let (index, first) = match self.data(&fs)? {
+            Some((index, block)) if index == logical => (index, block),
+            next => {
+                // holes up to the next data block or the end of the file
+                self.pending = next;
+                self.index = next.map_or(self.blocks, |(index, _)| index);
+                return Ok(Extent {
+                    logical: logical as u32,
+                    physical: None,
+                    len: (self.index - logical) as u32,
+                });
+            }
+        };
+
+        let mut len = 1;
+        while let Some((next, block)) = self.data(&fs)? {
+            if next != index + len as u64
+                || first.checked_add(len) != Some(block)
+            {
+                self.pending = Some((next, block));
+                break;
+            }
+            len += 1;
+        }
+        self.index += len as u64;
+        Ok(Extent {
+            logical: logical as u32,
+            physical: Some(first),
+            len,
+        })
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Iterator for Extents<S, V> {
+    type Item = Result<Extent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.index >= self.blocks {
+            return None;
+        }
+        let run = self.run();
+        self.done = run.is_err();
+        Some(run)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Directory<S: SectorSize, V: Volume<u8, S>> {
+    blocks: InodeBlocks<S, V>,
+    offset: usize,
+    buffer: Option<Vec<u8>>,
+    block_size: usize,
+    features: FeaturesRequired,
+    /// The path the directory was listed by, if it was through
+    /// `Fs::read_dir`
+    path: Option<Arc<[u8]>>,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Dir<DirectoryEntry<S, V>, Error>
+    for Directory<S, V>
+{
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Directory<S, V> {
+    /// Inserts an entry named `name` pointing at inode `inode` into this
+    /// directory, splitting the first entry with enough slack or appending a
+    /// new block. `file_type` is only recorded if the file system has the
+    /// filetype feature.
+    pub fn add_entry(
+        &mut self,
+        name: &[u8],
+        inode: u32,
+        file_type: u8,
+    ) -> Result<()> {
+        self.blocks.inode.add_entry(name, inode, file_type)
+    }
+
+    /// Removes the entry named `name` from this directory and returns the
+    /// inode it pointed at, whose link count is left to the caller. `.` and
+    /// `..` can't be removed.
+    pub fn remove_entry(&mut self, name: &[u8]) -> Result<u32> {
+        self.blocks.inode.remove_entry(name)
+    }
+
+    /// Looks for the entry named `name` among the entries not iterated over
+    /// yet. Blocks are read one at a time, only as long as the entry hasn't
+    /// been found, and names are compared in place.
+    pub fn find(
+        &mut self,
+        name: &[u8],
+    ) -> Result<Option<DirectoryEntry<S, V>>> {
+        while let Some(filled) = self.fill() {
+            filled?;
+            let buffer = self.buffer.as_ref().unwrap();
+            let mut entries =
+                DirEntryIter::at(buffer, self.offset, self.features);
+            // whatever comes of it, the block is done with
+            self.offset = self.block_size;
+            for entry in entries.by_ref() {
+                let entry = entry?;
+                if entry.header.in_use() && entry.name == name {
+                    self.offset = entries.offset();
+                    return Ok(Some(self.entry(&entry)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the next block of the directory once the current one has been
+    /// gone through, returning `None` past the end of the directory
+    fn fill(&mut self) -> Option<Result<()>> {
+        if self.buffer.is_some() && self.offset < self.block_size {
+            return Some(Ok(()));
+        }
+
+        // a corrupt directory may have far more blocks mapped than its size
+        // covers
+        let read = (self.blocks.index * self.block_size) as u64;
+        if read >= self.blocks.inode.size64() {
+            return None;
+        }
+        // the first block read may be resumed in, see `Inode::entries_from`
+        let resume = match self.buffer {
+            Some(_) => 0,
+            None => self.offset,
+        };
+        let block = match self.blocks.next()? {
+            Ok((block, _)) => block,
+            Err(err) => return Some(Err(err)),
+        };
+        let mut entries = DirEntryIter::new(&block, self.features);
+        while entries.offset() < resume {
+            match entries.next() {
+                Some(Ok(_)) => (),
+                // a corrupt entry on the way is where to go on from
+                _ => break,
+            }
+        }
+        self.offset = entries.offset();
+        self.buffer = Some(block);
+        Some(Ok(()))
+    }
+
+    /// The position of the next entry in the directory, in bytes from the
+    /// start, for `Inode::entries_from` to carry on from
+    pub fn pos(&self) -> u64 {
+        let block_size = self.block_size as u64;
+        let index = self.blocks.index as u64;
+        match self.buffer {
+            Some(_) if self.offset < self.block_size => {
+                (index - 1) * block_size + self.offset as u64
+            }
+            Some(_) => index * block_size,
+            None => index * block_size + self.offset as u64,
+        }
+    }
+
+    fn entry(&self, entry: &DirEntryRef) -> DirectoryEntry<S, V> {
+        DirectoryEntry {
+            name: entry.name.to_vec(),
+            ino: entry.header.inode,
+            file_type: entry.header.file_type,
+            fs: self.blocks.inode.fs.clone(),
+            dir: self.path.clone(),
+        }
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Iterator for Directory<S, V> {
+    type Item = Result<DirectoryEntry<S, V>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Err(err) = self.fill()? {
+                return Some(Err(err));
+            }
+
+            let buffer = self.buffer.as_ref().unwrap();
+            let mut entries =
+                DirEntryIter::at(buffer, self.offset, self.features);
+            let entry = match entries.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(err)) => {
+                    // give up on the rest of this block
+                    self.offset = self.block_size;
+                    return Some(Err(err));
+                }
+                None => {
+                    self.offset = self.block_size;
+                    continue;
+                }
+            };
+            self.offset = entries.offset();
+
+            // unused entries have a zero inode; skip over them
+            if entry.header.in_use() {
+                return Some(Ok(self.entry(&entry)));
+            }
+        }
+    }
+}
+
+/// An entry of a directory, as read from the directory block alone. The
+/// inode it points at is only loaded when asked for through `inode` or
+/// `metadata`.
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry<S: SectorSize, V: Volume<u8, S>> {
+    /// The name as stored, which needn't be UTF-8
     pub name: Vec<u8>,
-    pub inode: usize,
-    pub ty: u8,
+    pub ino: u32,
+    /// The type of the inode, if the file system records it in entries
+    pub file_type: Option<FileType>,
+    fs: Synced<Ext2<S, V>>,
+    /// The path of the directory it was listed from, if known
+    dir: Option<Arc<[u8]>>,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> DirectoryEntry<S, V> {
+    /// The name for display, with bytes that aren't UTF-8 replaced by
+    /// `U+FFFD`
+    pub fn name_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.name)
+    }
+
+    /// Loads the inode this entry points at
+    pub fn inode(&self) -> Result<Inode<S, V>> {
+        self.fs
+            .inode_nth(self.ino as usize)
+            .ok_or(Error::InodeNotFound { inode: self.ino })
+    }
+
+    /// Loads the metadata of the inode this entry points at
+    pub fn metadata(&self) -> Result<Metadata> {
+        Ok(self.inode()?.metadata())
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> DirEntry for DirectoryEntry<S, V> {
+    type Path = [u8];
+    type PathOwned = Vec<u8>;
+    type Metadata = Metadata;
+    type FileType = Option<FileType>;
+    type Error = Error;
+
+    /// The path of the directory joined with the name for entries listed
+    /// through `Fs::read_dir`, and the name alone for those of directories
+    /// opened some other way, whose path isn't known
+    fn path(&self) -> Self::PathOwned {
+        let mut path = match self.dir {
+            Some(ref dir) => dir.to_vec(),
+            None => return self.name.clone(),
+        };
+        if path.last() != Some(&b'/') {
+            path.push(b'/');
+        }
+        path.extend_from_slice(&self.name);
+        path
+    }
+
+    fn metadata(&self) -> Result<Self::Metadata, Self::Error> {
+        DirectoryEntry::metadata(self)
+    }
+
+    /// The type of the inode, loaded from the inode itself if the file
+    /// system doesn't record it in entries
+    fn file_type(&self) -> Result<Self::FileType, Self::Error> {
+        match self.file_type {
+            Some(file_type) => Ok(Some(file_type)),
+            None => Ok(Some(self.inode()?.file_type())),
+        }
+    }
+
+    fn file_name(&self) -> &Self::Path {
+        &self.name
+    }
 }
 
-impl DirEntry for DirectoryEntry {
-    type Path = [u8];
-    type PathOwned = Vec<u8>;
-    type Metadata = (); // TODO
-    type FileType = u8; // TODO: enum FileType
-    type Error = Error;
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use genfs::{
+        DirEntry, DirOptions, File as GenFile, Fs, OpenOptions, SeekFrom,
+    };
+
+    use error::Error;
+    use fs::mkfs::Builder;
+    use fs::DEFAULT_INODE_CACHE;
+    use sector::{Address, SectorSize, Size512};
+    use sys::acl::{AclEntry, AclPerm, AclTag};
+    use sys::inode::{
+        self, FileType, Flags, InodeBlock, Permissions, TypePerm,
+    };
+    use sys::superblock::FeaturesRequired;
+    use sys::xattr::{self, XattrIter};
+    use volume::{Fault, Faulty, Metered, Overlay, Volume};
+
+    use super::{
+        block_index, dirent_len, option_fields, read_u16, read_u32,
+        split_path, AtimePolicy, Directory, Ext2, FileBlock, Inode,
+        MountOptions, OpenFlags, Synced, Usage, O_NOFOLLOW,
+    };
+
+    /// Runs `e2fsck -fn` over `volume`, returning whether it found the
+    /// filesystem clean. Always succeeds if `e2fsck` isn't installed, or
+    /// under Miri, which can't spawn processes.
+    pub(crate) fn fsck(volume: &[u8]) -> bool {
+        fsck_with(volume, &[])
+    }
+
+    /// Like `fsck`, passing `args` on to `e2fsck`
+    pub(crate) fn fsck_with(volume: &[u8], args: &[&str]) -> bool {
+        use std::process::Command;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        if cfg!(miri) {
+            return true;
+        }
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ext2-rs-{}-{}.img",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst),
+        ));
+        std::fs::write(&path, volume).unwrap();
+        let output = Command::new("e2fsck")
+            .arg("-fn")
+            .args(args)
+            .arg(&path)
+            .output();
+        std::fs::remove_file(&path).unwrap();
+        match output {
+            Ok(output) => {
+                if !output.status.success() {
+                    println!("{}", String::from_utf8_lossy(&output.stdout));
+                }
+                output.status.success()
+            }
+            Err(_) => true,
+        }
+    }
+
+    /// Formats a fresh image of `size` bytes with 1 KiB blocks and 128 byte
+    /// inodes using `mke2fs`, or returns `None` if it isn't installed or
+    /// under Miri
+    pub(crate) fn mkfs(size: usize) -> Option<Vec<u8>> {
+        use std::process::Command;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        if cfg!(miri) {
+            return None;
+        }
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ext2-rs-mkfs-{}-{}.img",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst),
+        ));
+        std::fs::write(&path, vec![0; size]).unwrap();
+        let output = Command::new("mke2fs")
+            .args(["-q", "-F", "-t", "ext2", "-b", "1024", "-I", "128"])
+            .arg(&path)
+            .output();
+        let volume = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        match output {
+            Ok(output) if output.status.success() => Some(volume),
+            _ => None,
+        }
+    }
+
+    /// Runs the `debugfs` `requests` on a copy of `volume`, or returns
+    /// `None` if `debugfs` can't be run
+    pub(crate) fn debugfs(
+        volume: &[u8],
+        requests: &[&str],
+    ) -> Option<Vec<u8>> {
+        use std::process::Command;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        if cfg!(miri) {
+            return None;
+        }
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "ext2-rs-debugfs-{}-{}.img",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst),
+        ));
+        std::fs::write(&path, volume).unwrap();
+        let mut ran = true;
+        for request in requests {
+            let output = Command::new("debugfs")
+                .args(["-w", "-R", request])
+                .arg(&path)
+                .output();
+            let output = match output {
+                Ok(output) => output,
+                Err(_) => {
+                    ran = false;
+                    break;
+                }
+            };
+            // a failed request leaves more than the version on stderr
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.lines().count() <= 1, "{}: {}", request, stderr);
+        }
+        let volume = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        if ran {
+            Some(volume)
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn file() {
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file);
+
+        assert!(
+            fs.is_ok(),
+            "Err({:?})",
+            fs.err().unwrap_or_else(|| unreachable!()),
+        );
+
+        let fs = fs.unwrap();
+        let inner = fs.inner();
+
+        let vers = inner.version();
+        println!("version: {}.{}", vers.0, vers.1);
+        assert_eq!(128, inner.inode_size());
+    }
+
+    #[test]
+    fn inodes() {
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file);
+
+        assert!(
+            fs.is_ok(),
+            "Err({:?})",
+            fs.err().unwrap_or_else(|| unreachable!()),
+        );
+
+        let fs = fs.unwrap();
+
+        let inodes = fs.inodes().filter(|inode| inode.in_use());
+        for inode in inodes {
+            println!("{:?}", inode);
+        }
+    }
+
+    #[test]
+    fn open_inode() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let count = fs.inner().total_inodes_count() as u32;
+        // the iteration covers the last inode too
+        assert_eq!(fs.inodes().count(), count as usize);
+
+        // the bad blocks inode has no links, but it's reserved
+        assert_eq!(fs.open_inode(1).unwrap().num, 1);
+        assert!(fs.open_inode(2).unwrap().is_dir());
+        let readme = fs.lookup("/home/funky/README.md").unwrap();
+        let inode = fs.open_inode(readme.num).unwrap();
+        assert_eq!(inode.size(), readme.size());
+        for &ino in &[0, count, count + 1] {
+            match fs.open_inode(ino) {
+                Err(Error::InodeNotFound { inode }) => assert_eq!(inode, ino),
+                other => panic!("{:?}", other.map(|inode| inode.num)),
+            }
+        }
+        assert_eq!(fs.open_inode_unlinked(count).unwrap().num, count);
+        assert!(fs.open_inode_unlinked(count + 1).is_err());
+
+        let names = fs
+            .read_dir_inode(2)
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect::<Vec<_>>();
+        assert!(names.contains(&b"home".to_vec()));
+        match fs.read_dir_inode(readme.num) {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // once unlinked, only recovery finds it
+        fs.unlink("/home/funky/README.md").unwrap();
+        assert!(fs.open_inode(readme.num).is_err());
+        let inode = fs.open_inode_unlinked(readme.num).unwrap();
+        assert_eq!({ inode.inner.hard_links }, 0);
+    }
+
+    #[test]
+    fn inode_blocks() {
+        use std::str;
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+
+        let inodes = fs.inodes().filter(|inode| {
+            inode.in_use() && inode.uid() == 1000 && inode.size() < 1024
+        });
+        for inode in inodes {
+            println!("{:?}", inode);
+            let size = inode.size();
+            for block in inode.blocks() {
+                let (data, _) = block.unwrap();
+                assert_eq!(data.len(), {
+                    let fs = fs.inner();
+                    fs.block_size()
+                });
+                println!("{:?}", &data[..size]);
+                let _ = str::from_utf8(&data[..size])
+                    .map(|string| println!("{}", string));
+            }
+        }
+    }
+
+    #[test]
+    fn read_inode() {
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+
+        let inodes = fs.inodes().filter(|inode| {
+            inode.in_use() && inode.uid() == 1000 && inode.size() < 1024
+        });
+        for inode in inodes {
+            let mut buf = vec![0; inode.size()];
+            let size = inode.read(&mut buf[..]);
+            assert!(size.is_ok());
+            let size = size.unwrap();
+            assert_eq!(size, inode.size());
+            buf.truncate(size);
+        }
+    }
+
+    #[test]
+    fn read_big() {
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+
+        let inodes = fs.inodes().filter(|inode| {
+            inode.in_use() && inode.uid() == 1000 && inode.size() == 537600
+        });
+        for inode in inodes {
+            let mut buf = vec![0; inode.size()];
+            let size = inode.read(&mut buf[..]);
+            assert!(size.is_ok());
+            let size = size.unwrap();
+            assert_eq!(size, inode.size());
+            buf.truncate(size);
+
+            for (i, &x) in buf.iter().enumerate() {
+                if i & 1 == 0 {
+                    assert_eq!(x, b'u', "{}", i);
+                } else {
+                    assert_eq!(x, b'\n', "{}", i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn walkdir() {
+        use std::str;
+
+        fn walk<S: SectorSize, V: Volume<u8, S>>(
+            inode: Inode<S, V>,
+            name: String,
+        ) {
+            if let Some(dir) = inode.directory() {
+                for entry in dir {
+                    assert!(entry.is_ok());
+                    let entry = entry.unwrap();
+                    let entry_name = str::from_utf8(&entry.name).unwrap_or("?");
+                    println!("{}/{} => {}", name, entry_name, entry.ino);
+                    if entry_name != "." && entry_name != ".." {
+                        walk(
+                            entry.inode().unwrap(),
+                            format!("{}/{}", name, entry_name),
+                        );
+                    }
+                }
+            }
+        }
+
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+
+        let root = fs.root_inode();
+        walk(root, String::new());
+    }
+
+    #[test]
+    fn find() {
+        use std::str;
+        let file = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+
+        let found = fs.open(b"/home/funky/README.md", &OpenOptions::new());
+
+        assert!(found.is_ok());
+        let inode = found.unwrap();
+        let mut vec = Vec::new();
+        assert!(inode.read_to_end(&mut vec).is_ok());
+        println!("{}", str::from_utf8(&vec).unwrap());
+    }
+
+    #[test]
+    fn seek() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut content = Vec::new();
+        let mut readme = fs
+            .open(b"/home/funky/README.md", &OpenOptions::new())
+            .unwrap();
+        readme.read_to_end(&mut content).unwrap();
+        let size = content.len() as u64;
+
+        let mut buf = [0; 10];
+        assert_eq!(readme.seek(SeekFrom::End(-5)).unwrap(), size - 5);
+        assert_eq!(readme.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], &content[content.len() - 5..]);
+        assert_eq!(readme.read(&mut buf).unwrap(), 0);
+        let past = readme.seek(SeekFrom::Start(size + 100)).unwrap();
+        assert_eq!(past, size + 100);
+        assert_eq!(readme.read(&mut buf).unwrap(), 0);
+
+        readme.seek(SeekFrom::Start(0)).unwrap();
+        match readme.seek(SeekFrom::Current(-1)) {
+            Err(Error::InvalidSeek { base: 0, delta: -1 }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(readme.seek(SeekFrom::Current(3)).unwrap(), 3);
+        assert_eq!(readme.read(&mut buf[..4]).unwrap(), 4);
+        assert_eq!(&buf[..4], &content[3..7]);
+        assert_eq!(readme.seek(SeekFrom::Current(-2)).unwrap(), 5);
+        // reading at an offset leaves the position alone
+        assert_eq!(readme.read_at(1, &mut buf[..2]).unwrap(), 2);
+        assert_eq!(&buf[..2], &content[1..3]);
+        assert_eq!(readme.read(&mut buf[..1]).unwrap(), 1);
+        assert_eq!(buf[0], content[5]);
+
+        // writes carry on from the position, leaving a hole behind a seek
+        // past the end
+        let mut file = fs
+            .open(b"/seeked", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        let data = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        assert_eq!(file.write(&data).unwrap(), 3000);
+        assert_eq!(file.seek(SeekFrom::Current(0)).unwrap(), 3000);
+        let mut part = vec![0; 1500];
+        assert_eq!(file.read_at(1000, &mut part).unwrap(), 1500);
+        assert!(part == data[1000..2500]);
+        file.seek(SeekFrom::End(5000)).unwrap();
+        assert_eq!(file.write(b"end").unwrap(), 3);
+        assert_eq!(file.size(), 8003);
+        let mut written = Vec::new();
+        file.read_to_end(&mut written).unwrap();
+        assert!(written[..3000] == data[..]);
+        assert!(written[3000..8000].iter().all(|&byte| byte == 0));
+        assert_eq!(&written[8000..], b"end");
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn embedded_io() {
+        use embedded_io::{Error as _, ErrorKind, Read, Seek, Write};
+
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        for path in &["/home/funky/README.md", "/home/funky/unl"] {
+            let mut native = Vec::new();
+            fs.lookup(path).unwrap().read_to_end(&mut native).unwrap();
+            let mut file =
+                fs.open(path.as_bytes(), &OpenOptions::new()).unwrap();
+            let mut content = Vec::new();
+            let mut buf = [0; 1000];
+            loop {
+                let read = Read::read(&mut file, &mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                content.extend_from_slice(&buf[..read]);
+            }
+            assert!(content == native);
+
+            let end = embedded_io::SeekFrom::End(-5);
+            let size = native.len() as u64;
+            assert_eq!(Seek::seek(&mut file, end).unwrap(), size - 5);
+            file.read_exact(&mut buf[..5]).unwrap();
+            assert_eq!(&buf[..5], &native[native.len() - 5..]);
+        }
+
+        let mut readme = fs
+            .open(b"/home/funky/README.md", OpenOptions::new().write(true))
+            .unwrap();
+        let start = embedded_io::SeekFrom::Start(4);
+        assert_eq!(Seek::seek(&mut readme, start).unwrap(), 4);
+        readme.write_all(b"TEST").unwrap();
+        Write::flush(&mut readme).unwrap();
+        let mut buf = [0; 4];
+        readme.read_at(4, &mut buf).unwrap();
+        assert_eq!(&buf, b"TEST");
+        assert!(fsck(&fs.inner().volume));
+
+        let before = embedded_io::SeekFrom::Current(-100);
+        let err = Seek::seek(&mut readme, before).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn std_io() {
+        use std::io::{self, Read, Seek, Write};
+
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        for path in &["/home/funky/README.md", "/home/funky/unl"] {
+            let mut native = Vec::new();
+            fs.lookup(path).unwrap().read_to_end(&mut native).unwrap();
+            let mut file =
+                fs.open(path.as_bytes(), &OpenOptions::new()).unwrap();
+            let mut content = Vec::new();
+            let copied = io::copy(&mut file, &mut content).unwrap();
+            assert_eq!(copied, native.len() as u64);
+            assert!(content == native);
+
+            let end = io::SeekFrom::End(-5);
+            let size = native.len() as u64;
+            assert_eq!(Seek::seek(&mut file, end).unwrap(), size - 5);
+            let mut tail = String::new();
+            Read::read_to_string(&mut file, &mut tail).unwrap();
+            assert_eq!(tail.as_bytes(), &native[native.len() - 5..]);
+        }
+
+        let mut readme = fs
+            .open(b"/home/funky/README.md", OpenOptions::new().write(true))
+            .unwrap();
+        Seek::seek(&mut readme, io::SeekFrom::Start(4)).unwrap();
+        Write::write_all(&mut readme, b"TEST").unwrap();
+        Write::flush(&mut readme).unwrap();
+        let mut buf = [0; 4];
+        readme.read_at(4, &mut buf).unwrap();
+        assert_eq!(&buf, b"TEST");
+        assert!(fsck(&fs.inner().volume));
+
+        let before = io::SeekFrom::Current(-100);
+        let err = Seek::seek(&mut readme, before).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*err, Error::InvalidSeek { base: 8, delta: -100 }));
+    }
+
+    #[test]
+    fn read_at_threads() {
+        use std::sync::Arc;
+        use std::thread;
+
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs
+            .open(b"/shared", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        let data = (0..64 << 10).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        file.write(&data).unwrap();
+        let sum = |bytes: &[u8]| {
+            bytes.iter().fold(0_u32, |sum, &byte| {
+                sum.wrapping_mul(31).wrapping_add(byte as u32)
+            })
+        };
+
+        // each thread reads its quarter in chunks that straddle blocks
+        let file = Arc::new(file);
+        let quarter = data.len() / 4;
+        let readers = (0..4)
+            .map(|i| {
+                let file = file.clone();
+                thread::spawn(move || {
+                    let mut part = vec![0; quarter];
+                    let start = i * quarter;
+                    for (j, chunk) in part.chunks_mut(700).enumerate() {
+                        let offset = (start + j * 700) as u64;
+                        file.read_exact_at(offset, chunk).unwrap();
+                    }
+                    part
+                })
+            })
+            .collect::<Vec<_>>();
+        for (i, reader) in readers.into_iter().enumerate() {
+            let part = reader.join().unwrap();
+            let expected = &data[i * quarter..(i + 1) * quarter];
+            assert_eq!(sum(&part), sum(expected));
+        }
+
+        let mut buf = [0; 10];
+        match file.read_exact_at(data.len() as u64 - 4, &mut buf) {
+            Err(Error::UnexpectedEof { offset }) => {
+                assert_eq!(offset, data.len() as u64)
+            }
+            other => panic!("{:?}", other),
+        }
+        file.read_exact_at(data.len() as u64 - 10, &mut buf).unwrap();
+        assert_eq!(&buf[..], &data[data.len() - 10..]);
+    }
+
+    #[test]
+    fn read_block() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs
+            .open(b"/blocks", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        let data = (0..2500).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        file.write_at(0, &data).unwrap();
+        file.write_at(5000, b"tail").unwrap();
+
+        {
+            let inner = fs.inner();
+            // an in-memory volume lends its bytes
+            match file.read_block(&inner, 1).unwrap() {
+                FileBlock::Data(block) => {
+                    assert!(!block.is_mutated());
+                    assert!(block[..] == data[1024..2048]);
+                }
+                other => panic!("{:?}", other),
+            }
+            let partial = file.read_block(&inner, 2).unwrap().to_vec();
+            assert!(partial[..452] == data[2048..]);
+            assert!(partial[452..].iter().all(|&byte| byte == 0));
+            let hole = file.read_block(&inner, 3).unwrap();
+            assert_eq!(hole, FileBlock::Zeroes(1024));
+            let tail = file.read_block(&inner, 4).unwrap();
+            assert_eq!(tail.len(), 5004 - 4096);
+            match file.read_block(&inner, 5) {
+                Err(Error::OutOfBounds { index: 5 }) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+
+        // one reading from a file hands over a copy
+        let volume = RefCell::new(File::open("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        let mut content = Vec::new();
+        readme.read_to_end(&mut content).unwrap();
+        let inner = fs.inner();
+        match readme.read_block(&inner, 0).unwrap() {
+            FileBlock::Data(block) => {
+                assert!(block.is_mutated());
+                assert!(block[..] == content[..block.len()]);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_in_place() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let mut inode = fs
+            .open(b"/home/funky/README.md", OpenOptions::new().write(true))
+            .unwrap();
+        let data = vec![b'x'; 100];
+        assert_eq!(inode.write(&data).unwrap(), 100);
+        assert_eq!(inode.size(), 100);
+
+        let inode = fs
+            .open(b"/home/funky/README.md", &OpenOptions::new())
+            .unwrap();
+        assert_eq!(inode.size(), 100);
+        let mut vec = Vec::new();
+        inode.read_to_end(&mut vec).unwrap();
+        assert_eq!(vec, data);
+    }
+
+    #[test]
+    fn write_allocates() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let mut inode = fs
+            .open(b"/home/funky/README.md", OpenOptions::new().write(true))
+            .unwrap();
+        // README.md occupies a single 1 KiB block
+        let data = vec![b'y'; 2000];
+        assert_eq!(inode.write_at(0, &data).unwrap(), 2000);
+        assert_eq!(inode.size(), 2000);
+        assert_eq!({ inode.inner.sectors_count }, 4);
+        let mut vec = Vec::new();
+        inode.read_to_end(&mut vec).unwrap();
+        assert_eq!(vec, data);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn write_short_out_of_space() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let mut inode = fs.create_file(b"/big", Permissions::U_READ).unwrap();
+        let data = vec![b'z'; 4 << 20];
+        let written = inode.write_at(0, &data).unwrap();
+        assert!(written > 0 && written < data.len());
+        assert_eq!(inode.size(), written);
+        assert_eq!({ fs.inner().superblock.inner.free_blocks_count }, 0);
+        match inode.write_at(written as u64, &data) {
+            Err(Error::OutOfSpace) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn write_sparse() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let free = fs.inner().free_block_count();
+
+        // a 4 MiB image can't hold 10 MiB, but a hole costs nothing
+        let mut inode =
+            fs.create_file(b"/sparse", Permissions::U_READ).unwrap();
+        assert_eq!(inode.write_at(10 << 20, b"tail").unwrap(), 4);
+        assert_eq!(inode.size(), (10 << 20) + 4);
+        // the data block plus a singly and a doubly indirect block
+        assert_eq!({ inode.inner.sectors_count }, 3 * 2);
+        assert_eq!(fs.inner().free_block_count(), free - 3);
+        let mut data = Vec::new();
+        inode.read_to_end(&mut data).unwrap();
+        assert!(data[..10 << 20].iter().all(|&b| b == 0));
+        assert_eq!(&data[10 << 20..], b"tail");
+        assert!(fsck(&fs.inner().volume));
+
+        // zeroes written into the hole only take up blocks if asked to
+        fs.inner().set_sparse_writes(true);
+        let mut zeroes = vec![0; 5 << 20];
+        zeroes[3000] = b'x';
+        assert_eq!(inode.write_at(1024, &zeroes).unwrap(), zeroes.len());
+        assert_eq!({ inode.inner.sectors_count }, 4 * 2);
+        fs.inner().set_sparse_writes(false);
+        assert_eq!(inode.write_at(0, &zeroes[..2048]).unwrap(), 2048);
+        assert_eq!({ inode.inner.sectors_count }, 6 * 2);
+        data.clear();
+        inode.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), (10 << 20) + 4);
+        assert_eq!(data[4024], b'x');
+        assert_eq!(data.iter().filter(|&&b| b != 0).count(), 5);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn write_doubly_indirect() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let mut inode = fs
+            .open(b"/home/funky/unl", OpenOptions::new().write(true))
+            .unwrap();
+        let size = inode.size();
+        // logical block 300 is reached through the doubly indirect block
+        let offset = 300 * 1024 + 512;
+        let data = vec![b'z'; 4096];
+        assert_eq!(inode.write_at(offset, &data).unwrap(), 4096);
+        assert_eq!(inode.size(), size);
+
+        let physical = inode.block(300).unwrap().get() as usize;
+        {
+            let inner = fs.inner();
+            let raw = &inner.volume[physical * 1024 + 512..][..512];
+            assert!(raw.iter().all(|&x| x == b'z'));
+        }
+
+        let mut vec = Vec::new();
+        inode.read_to_end(&mut vec).unwrap();
+        let offset = offset as usize;
+        assert!(vec[offset..offset + 4096].iter().all(|&x| x == b'z'));
+        assert_eq!(&vec[offset - 2..offset], b"u\n");
+        assert_eq!(&vec[offset + 4096..offset + 4098], b"u\n");
+    }
+
+    #[test]
+    fn options_debug() {
+        // what `option_fields` reads the options of genfs from
+        let mut options = DirOptions::<Permissions>::new();
+        options.recursive(true).custom_flags(8);
+        assert_eq!(
+            format!("{:?}", options),
+            "DirOptions { recursive: true, mode: (empty), flags: 8 }"
+        );
+
+        let mut fields = Vec::new();
+        option_fields(&options, |name, value| {
+            fields.push(format!("{}={}", name, value))
+        });
+        assert_eq!(fields, ["recursive=true", "mode=(empty)", "flags=8"]);
+
+        let mut options = OpenOptions::<Permissions>::new();
+        options.read(true).create_new(true).custom_flags(O_NOFOLLOW);
+        assert_eq!(
+            format!("{:?}", options),
+            format!(
+                "OpenOptions {{ read: true, write: false, append: false, \
+                 truncate: false, create: false, create_new: true, \
+                 mode: (empty), flags: {} }}",
+                O_NOFOLLOW
+            )
+        );
+    }
+
+    #[test]
+    fn open_flags() {
+        let flags = OpenFlags::new(OpenOptions::<()>::new().read(true));
+        assert!(flags.read && !flags.write && !flags.create);
+        let flags = OpenFlags::new(
+            OpenOptions::<()>::new().write(true).create_new(true),
+        );
+        assert!(flags.write && flags.create_new && !flags.create);
+        assert!(!flags.append && !flags.truncate && !flags.nofollow);
+        let flags = OpenFlags::new(
+            OpenOptions::<()>::new().read(true).custom_flags(O_NOFOLLOW),
+        );
+        assert!(flags.read && flags.nofollow);
+    }
+
+    #[test]
+    fn create() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let free_inodes = { fs.inner().superblock.inner.free_inodes_count };
+        let mut inode = fs
+            .open(
+                b"/home/funky/new.txt",
+                OpenOptions::new().write(true).create(true),
+            )
+            .unwrap();
+        assert_eq!(inode.size(), 0);
+        assert_eq!(
+            { fs.inner().superblock.inner.free_inodes_count },
+            free_inodes - 1
+        );
+        assert_eq!(inode.write(b"hello").unwrap(), 5);
+
+        let found = fs.lookup(b"/home/funky/new.txt").unwrap();
+        assert_eq!(found.num, inode.num);
+        let perm = { found.inner.type_perm };
+        assert_eq!(perm.bits(), 0o100644);
+
+        // opening with `create` again doesn't create a second entry
+        let again = fs
+            .open(
+                b"/home/funky/new.txt",
+                OpenOptions::new().write(true).create(true),
+            )
+            .unwrap();
+        assert_eq!(again.num, inode.num);
+
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn create_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        match fs.open(
+            b"/home/funky/README.md",
+            OpenOptions::new().write(true).create_new(true),
+        ) {
+            Err(Error::AlreadyExists { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        match fs.open(
+            b"/nope/file.txt",
+            OpenOptions::new().write(true).create(true),
+        ) {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        match fs.open(
+            b"/home/funky/README.md/file.txt",
+            OpenOptions::new().write(true).create(true),
+        ) {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        // without `create`, a missing file is still an error
+        assert!(fs
+            .open(b"/home/funky/new.txt", OpenOptions::new().write(true))
+            .is_err());
+    }
+
+    #[test]
+    fn create_grows_directory() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let perm = Permissions::U_READ | Permissions::U_WRITE;
+        for i in 0..100 {
+            let path = format!("/home/funky/file-{:04}.txt", i);
+            fs.create_file(path.as_bytes(), perm).unwrap();
+        }
+
+        let dir = fs.lookup(b"/home/funky").unwrap();
+        assert!(dir.size() > 1024);
+        let names = dir
+            .directory()
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect::<Vec<_>>();
+        assert_eq!(names.len(), 105);
+        for i in 0..100 {
+            let name = format!("file-{:04}.txt", i);
+            assert!(names.contains(&name.into_bytes()));
+        }
+
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    /// Returns the unused space at the end of directory block `index`
+    fn slack(dir: &Inode<Size512, Vec<u8>>, index: usize) -> usize {
+        let block = dir.block(index).unwrap().get() as usize;
+        let fs = dir.fs.inner();
+        let data = &fs.volume[block * 1024..(block + 1) * 1024];
+        let mut position = 0;
+        loop {
+            let rec_len = read_u16(&data[position + 4..]) as usize;
+            if position + rec_len == 1024 {
+                return rec_len - dirent_len(data[position + 6] as usize);
+            }
+            position += rec_len;
+        }
+    }
+
+    #[test]
+    fn add_entry_full_block() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut target =
+            fs.create_file(b"/home/target", Permissions::U_READ).unwrap();
+
+        let mut dir = fs.lookup(b"/home/funky").unwrap();
+        let mut links = 0;
+        let mut free = slack(&dir, 0);
+        while free > 0 {
+            let len = if free - 8 > 255 { 200 } else { free - 8 };
+            let name = vec![b'a' + links as u8; len];
+            dir.directory()
+                .unwrap()
+                .add_entry(&name, target.num, inode::FILE)
+                .unwrap();
+            dir = fs.lookup(b"/home/funky").unwrap();
+            links += 1;
+            free = slack(&dir, 0);
+        }
+        assert_eq!(dir.size(), 1024);
+
+        dir.directory()
+            .unwrap()
+            .add_entry(b"overflow", target.num, inode::FILE)
+            .unwrap();
+        let dir = fs.lookup(b"/home/funky").unwrap();
+        assert_eq!(dir.size(), 2048);
+        assert_eq!(slack(&dir, 1), 1024 - dirent_len(8));
+        let entry = dir.entry(b"overflow").unwrap().unwrap();
+        assert_eq!(entry.ino, target.num);
+        assert_eq!(entry.file_type, Some(FileType::File));
+
+        target.reload(&fs.inner()).unwrap();
+        target.inner.hard_links += links + 1;
+        target.write_back(&mut fs.inner()).unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn add_entry_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let dir = fs.lookup(b"/home/funky").unwrap();
+
+        match dir.directory().unwrap().add_entry(&[b'x'; 256], 14, 1) {
+            Err(Error::NameTooLong { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match dir.directory().unwrap().add_entry(b"README.md", 14, 1) {
+            Err(Error::AlreadyExists { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        let mut path = b"/home/".to_vec();
+        path.extend_from_slice(&[b'x'; 256]);
+        match fs.create_file(&path, Permissions::U_READ) {
+            Err(Error::NameTooLong { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        let names = dir
+            .directory()
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().name == b"README.md")
+            .count();
+        assert_eq!(names, 1);
+    }
+
+    #[test]
+    fn add_entry_without_filetype() {
+        // made with `mke2fs -r 0`, whose entries have a 16 bit name length
+        // and no type
+        let volume = std::fs::read("fixtures/rev0.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let features = fs.inner().superblock.inner.features_req();
+        assert!(!features.contains(FeaturesRequired::REQ_DIRECTORY_TYPE));
+
+        let dir = fs.lookup(b"/docs").unwrap();
+        let entry = dir.entry(b"hello.txt").unwrap().unwrap();
+        assert_eq!(entry.ino, 13);
+        assert_eq!(entry.file_type, None);
+        let mut data = Vec::new();
+        fs.lookup(b"/docs/hello.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"hello from revision 0\n");
+
+        fs.create_file(b"/docs/untyped", Permissions::U_READ).unwrap();
+        let entry = dir.entry(b"untyped").unwrap().unwrap();
+        assert_eq!(entry.file_type, None);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn readdir_leaves_inodes_alone() {
+        let volume = Faulty::new(std::fs::read("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let dir = fs.lookup(b"/home/funky").unwrap();
+        {
+            let mut fs = fs.inner();
+            let start = fs.block_groups()[0].inode_table_block() as u64 * 2;
+            let sectors = (fs.inodes_count() * fs.inode_size() / 512) as u64;
+            fs.volume.inject(Fault::FailSectors(start..start + sectors));
+        }
+
+        // the types come from the entries themselves
+        let entries = dir
+            .directory()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.name.clone(), entry.file_type)
+            })
+            .collect::<Vec<_>>();
+        let parent = (b"..".to_vec(), Some(FileType::Directory));
+        let readme = (b"README.md".to_vec(), Some(FileType::File));
+        assert!(entries.contains(&parent) && entries.contains(&readme));
+
+        // and the inodes are only read when asked for
+        let entry = dir.entry(b"README.md").unwrap().unwrap();
+        assert!(entry.metadata().is_err());
+        fs.inner().volume.clear();
+        assert_eq!(entry.metadata().unwrap().ino(), entry.ino);
+    }
+
+    #[test]
+    fn find_stops_early() {
+        let volume = Metered::new(std::fs::read("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        fs.mkdir(b"/many", Permissions::U_READ | Permissions::U_EXEC)
+            .unwrap();
+        for i in 0..200 {
+            let path = format!("/many/file-{:03}", i);
+            fs.create_file(path.as_bytes(), Permissions::U_READ).unwrap();
+        }
+        let dir = fs.lookup(b"/many").unwrap();
+        assert_eq!(dir.size(), 4096);
+
+        // the first entry is in the first block, which is all that's read
+        fs.inner().volume.reset();
+        let entry = dir.directory().unwrap().find(b"file-000").unwrap();
+        assert_eq!(entry.unwrap().name, b"file-000");
+        let stats = fs.inner().volume.stats();
+        assert_eq!((stats.reads, stats.bytes_read), (1, 1024));
+
+        fs.inner().volume.reset();
+        let entry = dir.directory().unwrap().find(b"file-199").unwrap();
+        assert_eq!(entry.unwrap().name, b"file-199");
+        assert_eq!(fs.inner().volume.stats().reads, 4);
+
+        let mut entries = dir.directory().unwrap();
+        assert!(entries.find(b"missing").unwrap().is_none());
+        // finding picks up where iteration left off
+        let mut entries = dir.directory().unwrap();
+        assert_eq!(entries.next().unwrap().unwrap().name, b".");
+        assert!(entries.find(b".").unwrap().is_none());
+        assert_eq!(fs.lookup(b"/many/file-123").unwrap().size(), 0);
+    }
+
+    #[test]
+    fn resume_listing() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        fs.mkdir(b"/paged", Permissions::U_READ | Permissions::U_EXEC)
+            .unwrap();
+        for i in 0..150 {
+            let path = format!("/paged/file-{:03}", i);
+            fs.create_file(path.as_bytes(), Permissions::U_READ).unwrap();
+        }
+        let dir = fs.lookup(b"/paged").unwrap();
+        assert_eq!(dir.size(), 3072);
+        let names = |entries: Directory<Size512, Vec<u8>>| {
+            entries.map(|entry| entry.unwrap().name).collect::<Vec<_>>()
+        };
+        let all = names(dir.directory().unwrap());
+        assert_eq!(all.len(), 152);
+
+        // two entries a call, as `getdents` with a small buffer would
+        let mut paged = Vec::new();
+        let mut pos = 0;
+        loop {
+            let mut entries = dir.entries_from(pos).unwrap();
+            let page = entries
+                .by_ref()
+                .take(2)
+                .map(|entry| entry.unwrap().name)
+                .collect::<Vec<_>>();
+            if page.is_empty() {
+                break;
+            }
+            assert!(entries.pos() > pos);
+            pos = entries.pos();
+            paged.extend(page);
+        }
+        assert_eq!(paged, all);
+        assert_eq!(pos, 3072);
+        assert!(dir.entries_from(pos).unwrap().next().is_none());
+
+        // removing the entry to resume at skips to the one after it
+        let mut entries = dir.directory().unwrap();
+        entries.by_ref().take(70).for_each(drop);
+        let pos = entries.pos();
+        assert_eq!(entries.next().unwrap().unwrap().name, all[70]);
+        let mut path = b"/paged/".to_vec();
+        path.extend_from_slice(&all[70]);
+        fs.unlink(&path).unwrap();
+        let dir = fs.lookup(b"/paged").unwrap();
+        assert_eq!(names(dir.entries_from(pos).unwrap()), &all[71..]);
+        // as does a position in the middle of an entry
+        assert_eq!(names(dir.entries_from(pos + 4).unwrap()), &all[71..]);
+        let rest = [&all[1..70], &all[71..]].concat();
+        assert_eq!(names(dir.entries_from(1).unwrap()), rest);
+    }
+
+    #[test]
+    fn byte_paths() {
+        // made with `mke2fs -d` from a tree with Latin-1 names:
+        // /r\xe9pertoire/caf\xe9
+        let volume = std::fs::read("fixtures/latin1.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let path = &b"/r\xe9pertoire/caf\xe9"[..];
+        let mut data = Vec::new();
+        fs.lookup(path).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"bytes, not UTF-8\n");
+        // the UTF-8 spelling is another name altogether
+        match fs.lookup("/r\u{e9}pertoire") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+
+        let dir = fs.lookup(&b"/r\xe9pertoire"[..]).unwrap();
+        let entry = dir
+            .directory()
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|entry| entry.ino == 13)
+            .unwrap();
+        assert_eq!(entry.name, b"caf\xe9");
+        assert_eq!(entry.name_lossy(), "caf\u{fffd}");
+
+        for path in &[
+            &b"//r\xe9pertoire///caf\xe9"[..],
+            b"/./r\xe9pertoire/./caf\xe9",
+            b"/../../r\xe9pertoire/../r\xe9pertoire/caf\xe9",
+        ] {
+            assert_eq!(fs.lookup(path).unwrap().num, 13);
+        }
+        assert_eq!(fs.lookup("/..").unwrap().num, 2);
+        assert_eq!(fs.lookup(&b"/r\xe9pertoire/"[..]).unwrap().num, 12);
+        for path in &[
+            &b"/r\xe9pertoire/caf\xe9/"[..],
+            b"/r\xe9pertoire/caf\xe9/.",
+            b"/r\xe9pertoire/caf\xe9/..",
+        ] {
+            match fs.lookup(path) {
+                Err(Error::NotADirectory { inode: 13, .. }) => (),
+                other => panic!("{:?}", other.map(|inode| inode.num)),
+            }
+        }
+
+        fs.create_file(&b"/r\xe9pertoire/na\xefve"[..], Permissions::U_READ)
+            .unwrap();
+        fs.rename(&b"/r\xe9pertoire/na\xefve"[..], "/plain").unwrap();
+        assert!(!fs.lookup("/plain").unwrap().is_dir());
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn coalesced_reads() {
+        let volume = Metered::new(std::fs::read("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs
+            .open(b"/big", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        let data = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        file.write(&data).unwrap();
+
+        // 1024 blocks, laid out in a few runs, in a few reads
+        fs.inner().volume.reset();
+        let mut read = Vec::new();
+        fs.lookup(b"/big").unwrap().read_to_end(&mut read).unwrap();
+        assert!(read == data);
+        let reads = fs.inner().volume.stats().reads;
+        assert!(reads < 50, "{} reads", reads);
+
+        // the inode tables are read in chunks too
+        fs.inner().volume.reset();
+        assert_eq!(fs.inodes().count(), 1024);
+        assert!(fs.inner().volume.stats().reads < 32);
+    }
+
+    #[test]
+    fn bmap() {
+        let fs = Synced::<Ext2<Size512, _>>::new(
+            std::fs::read("ext2.img").unwrap(),
+        )
+        .unwrap();
+        let mut file = fs
+            .open(b"/sparse", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        // with 1 KiB blocks: direct, indirect (12 + 256 blocks), doubly
+        // (65,536 more) and triply indirect
+        let blocks = [0_u32, 11, 12, 200, 267, 268, 5000, 65_804, 70_000];
+        for &block in &blocks {
+            file.seek(SeekFrom::Start(block as u64 * 1024)).unwrap();
+            file.write(&block.to_le_bytes()).unwrap();
+        }
+
+        let file = fs.lookup(b"/sparse").unwrap();
+        for &block in &blocks {
+            let physical = file.bmap(block).unwrap().unwrap() as usize;
+            let data = &fs.inner().volume[physical * 1024..][..4];
+            assert_eq!(data, &block.to_le_bytes()[..]);
+        }
+        // holes between the blocks written, in the middle of the indirect
+        // ranges as well
+        for &hole in &[1, 100, 268 + 256, 65_803, 65_805] {
+            assert_eq!(file.bmap(hole).unwrap(), None);
+        }
+        // past the end of the file
+        assert_eq!(file.bmap(70_001).unwrap(), None);
+        assert_eq!(file.bmap(!0).unwrap(), None);
+
+        let link = symlink(&fs, b"/link", b"sparse");
+        assert_eq!(link.bmap(0).unwrap(), None);
+    }
+
+    #[test]
+    fn devices() {
+        // made by debugfs: 300:1000 takes the new encoding, the others the
+        // old one
+        let volume = std::fs::read("fixtures/devices.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let nodes: [(&[u8], _); 5] = [
+            (b"/dev/null", Some((1, 3))),
+            (b"/dev/sda", Some((8, 0))),
+            (b"/dev/big", Some((300, 1000))),
+            (b"/dev/fifo", None),
+            (b"/dev", None),
+        ];
+        for &(path, device) in &nodes {
+            assert_eq!(fs.lookup(path).unwrap().device(), device);
+        }
+
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o666);
+        fs.mknod(b"/null", FileType::CharDevice, perm, 1, 3).unwrap();
+        fs.mknod(b"/big", FileType::BlockDevice, perm, 4095, 0xfffff)
+            .unwrap();
+        let fifo = fs.mknod(b"/fifo", FileType::Fifo, perm, 1, 3).unwrap();
+        assert_eq!(fifo.device(), None);
+        assert_eq!(fs.lookup(b"/null").unwrap().device(), Some((1, 3)));
+        let big = fs.lookup(b"/big").unwrap();
+        assert_eq!(big.device(), Some((4095, 0xfffff)));
+        assert_eq!({ big.inner.direct_pointer }[0], 0);
+        // a block device has the directory bit set, but isn't one
+        assert_eq!(big.file_type(), FileType::BlockDevice);
+        assert!(!big.is_dir());
+        assert!(big.directory().is_none());
+        assert_eq!(big.permissions(), perm);
+        let entry = fs.lookup(b"/").unwrap().entry(b"big").unwrap();
+        assert_eq!(entry.unwrap().file_type, Some(FileType::BlockDevice));
+        assert!(fsck(&fs.inner().volume));
+
+        match fs.mknod(b"/huge", FileType::CharDevice, perm, 4096, 0) {
+            Err(Error::InvalidDevice { major: 4096, .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.mknod(b"/file", FileType::File, perm, 0, 0) {
+            Err(Error::Unsupported { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // the device number isn't taken for blocks to free
+        fs.unlink(b"/big").unwrap();
+        fs.unlink(b"/null").unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn entry_paths() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let paths = |dir: &[u8]| {
+            fs.read_dir(dir)
+                .unwrap()
+                .map(|entry| DirEntry::path(&entry.unwrap()))
+                .collect::<Vec<_>>()
+        };
+        assert!(paths(b"/home/funky")
+            .contains(&b"/home/funky/README.md".to_vec()));
+        assert!(paths(b"/home/").contains(&b"/home/funky".to_vec()));
+        assert!(paths(b"/").contains(&b"/home".to_vec()));
+
+        // listed from the inode, only the name is known
+        let entry = fs
+            .lookup(b"/home/funky")
+            .unwrap()
+            .directory()
+            .unwrap()
+            .find(b"README.md")
+            .unwrap()
+            .unwrap();
+        assert_eq!(DirEntry::path(&entry), b"README.md");
+    }
+
+    #[test]
+    fn special_files() {
+        let volume = std::fs::read("fixtures/devices.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut o = OpenOptions::new();
+        o.read(true);
+        for path in &["/dev/null", "/dev/sda", "/dev/fifo"] {
+            match fs.open(path.as_bytes(), &o) {
+                Err(Error::SpecialFile { ref name, .. }) if name == path => (),
+                other => panic!("{:?}", other),
+            }
+        }
+        let metadata = fs.metadata(b"/dev/sda").unwrap();
+        assert_eq!(metadata.file_type(), FileType::BlockDevice);
+        assert!(!metadata.is_dir());
+
+        let types = fs
+            .read_dir(b"/dev")
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let file_type = DirEntry::file_type(&entry).unwrap().unwrap();
+                assert_eq!(file_type, entry.metadata().unwrap().file_type());
+                (entry.name, file_type)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                (b".".to_vec(), FileType::Directory),
+                (b"..".to_vec(), FileType::Directory),
+                (b"null".to_vec(), FileType::CharDevice),
+                (b"sda".to_vec(), FileType::BlockDevice),
+                (b"big".to_vec(), FileType::CharDevice),
+                (b"fifo".to_vec(), FileType::Fifo),
+            ]
+        );
+
+        // without types in the entries, they come from the inodes
+        let volume = std::fs::read("fixtures/rev0.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o644);
+        fs.mknod(b"/socket", FileType::Socket, perm, 0, 0).unwrap();
+        let entry = fs
+            .lookup(b"/")
+            .unwrap()
+            .entry(b"socket")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.file_type, None);
+        assert_eq!(
+            DirEntry::file_type(&entry).unwrap(),
+            Some(FileType::Socket)
+        );
+        match fs.open(b"/socket", &o) {
+            Err(Error::SpecialFile { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn metadata() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let readme = fs.metadata(b"/home/funky/README.md").unwrap();
+        assert_eq!(readme.ino(), 14);
+        assert_eq!(readme.file_type(), FileType::File);
+        assert!(readme.is_file() && !readme.is_dir());
+        assert_eq!(readme.permissions().bits(), 0o644);
+        assert_eq!(readme.len(), 31);
+        let owner = (readme.uid(), readme.gid(), readme.nlink());
+        assert_eq!(owner, (1000, 1000, 1));
+        assert_eq!(readme.created(), 0x5aafeb42);
+        assert_eq!(readme.accessed(), 0x5aafeb4d);
+        assert_eq!(readme.modified(), 0x5aafeb26);
+        assert_eq!(readme.blocks(), 2);
+        assert_eq!(readme.flags(), Flags::empty());
+        assert_eq!(readme.device(), None);
+
+        // every way to it agrees
+        let inode = fs.lookup(b"/home/funky/README.md").unwrap();
+        assert_eq!(inode.metadata(), readme);
+        let entry = fs
+            .lookup(b"/home/funky")
+            .unwrap()
+            .entry(b"README.md")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.metadata().unwrap(), readme);
+        let path = &b"/home/funky/README.md"[..];
+        assert_eq!(Fs::metadata(&fs, path).unwrap(), readme);
+
+        symlink(&fs, b"/home/link", b"funky/README.md");
+        assert_eq!(fs.metadata(b"/home/link").unwrap(), readme);
+        let link = fs.symlink_metadata(b"/home/link").unwrap();
+        assert!(link.is_symlink());
+        assert_eq!(link.len(), 15);
+        assert_eq!(link.permissions().bits(), 0o777);
+
+        let dir = fs.metadata(b"/home").unwrap();
+        assert!(dir.is_dir());
+        assert_eq!((dir.ino(), dir.len()), (12, 1024));
+
+        let volume = std::fs::read("fixtures/devices.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let null = fs.metadata(b"/dev/null").unwrap();
+        assert_eq!(null.device(), Some((1, 3)));
+        assert_eq!(null.permissions().bits(), 0o666);
+
+        let volume = std::fs::read("fixtures/large.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert_eq!(fs.metadata(b"/big").unwrap().len(), 5 << 30);
+    }
+
+    #[test]
+    fn disk_usage() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        // sizes and block counts as debugfs has them; /home/funky/unl has
+        // 525 blocks of data and 4 indirect ones
+        let usage = fs.disk_usage(b"/").unwrap();
+        assert_eq!(
+            usage,
+            Usage {
+                apparent_size: 1024 * 3 + 12288 + 31 + 537600 + 13312,
+                allocated_size: (2 * 4 + 24 + 1058 + 28) * 512,
+                files: 3,
+                directories: 4,
+            }
+        );
+        let readme = fs.disk_usage(b"/home/funky/README.md").unwrap();
+        assert_eq!((readme.apparent_size, readme.allocated_size), (31, 1024));
+        assert_eq!((readme.files, readme.directories), (1, 0));
+
+        // a second link to README.md doesn't count twice
+        let mut inode = fs.lookup(b"/home/funky/README.md").unwrap();
+        inode.link().unwrap();
+        let mut home = fs.lookup(b"/home").unwrap().directory().unwrap();
+        home.add_entry(b"readme", 14, inode::FILE).unwrap();
+        assert_eq!(fs.disk_usage(b"/").unwrap(), usage);
+        let funky = fs.disk_usage(b"/home/funky").unwrap();
+        assert_eq!(funky.files, 3);
+
+        // nor does a symlink count what it points at
+        symlink(&fs, b"/link", b"/home/funky/unl");
+        let with_link = fs.disk_usage(b"/").unwrap();
+        assert_eq!(with_link.files, 4);
+        assert_eq!(with_link.apparent_size, usage.apparent_size + 15);
+        assert_eq!(fs.disk_usage(b"/link").unwrap().apparent_size, 537600);
+    }
+
+    #[test]
+    fn xattrs() {
+        // made by mke2fs and debugfs's ea_set, with 128-byte inodes so that
+        // every attribute is in a block
+        let volume = std::fs::read("fixtures/xattr.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert_eq!(
+            fs.list_xattrs(b"/hello").unwrap(),
+            vec![
+                b"user.comment".to_vec(),
+                b"trusted.note".to_vec(),
+                b"security.selinux".to_vec(),
+            ]
+        );
+        let get = |path: &str, name: &str| {
+            fs.get_xattr(path, name).unwrap().map(String::from_utf8)
+        };
+        assert_eq!(
+            get("/hello", "security.selinux"),
+            Some(Ok("system_u:object_r:bin_t:s0".to_owned()))
+        );
+        assert_eq!(get("/hello", "user.comment"), Some(Ok("a comment".into())));
+        assert_eq!(get("/hello", "user.missing"), None);
+        // the prefix is part of the name
+        assert_eq!(get("/hello", "comment"), None);
+        assert_eq!(get("/plain", "user.comment"), None);
+        assert!(fs.list_xattrs(b"/plain").unwrap().is_empty());
+
+        // cap_net_raw+ep, as getfattr -e hex shows it
+        let capability = fs.get_xattr(b"/ping", "security.capability");
+        assert_eq!(
+            capability.unwrap().unwrap(),
+            [1, 0, 0, 2, 0, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        // u::rw-,u:1000:r--,g::r--,m::r--,o::---
+        let acl = fs.get_xattr(b"/acl", "system.posix_acl_access");
+        let mut expected = vec![2, 0, 0, 0];
+        for &(tag, perm, id) in &[
+            (1_u16, 6_u16, !0_u32),
+            (2, 4, 1000),
+            (4, 4, !0),
+            (0x10, 4, !0),
+            (0x20, 0, !0),
+        ] {
+            expected.extend_from_slice(&tag.to_le_bytes());
+            expected.extend_from_slice(&perm.to_le_bytes());
+            expected.extend_from_slice(&id.to_le_bytes());
+        }
+        assert_eq!(acl.unwrap().unwrap(), expected);
+
+        // a symlink has none of its own, and is followed
+        symlink(&fs, b"/link", b"hello");
+        assert_eq!(fs.list_xattrs(b"/link").unwrap().len(), 3);
+
+        let hello = fs.lookup(b"/hello").unwrap();
+        let block = { hello.inner.ext_attribute_block };
+        let start = block as usize * 1024;
+        fs.inner().volume[start + 3] = 0;
+        match hello.list_xattrs() {
+            Err(Error::BadXattrMagic { block: number, .. }) => {
+                assert_eq!(number, block)
+            }
+            other => panic!("{:?}", other),
+        }
+        fs.inner().volume[start + 3] = 0xea;
+        // the value of user.comment running past the block end
+        fs.inner().volume[start + 41] = 0x10;
+        match hello.get_xattr("security.selinux") {
+            Err(Error::Corrupt { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn acls() {
+        // /shared got u::rw-,u:1000:rw-,g::r--,m::rw-,o::r-- and /dir a
+        // default of u::rwx,g::r-x,g:100:r-x,m::r-x,o::r-x, which are stored
+        // as these
+        let volume = std::fs::read("fixtures/xattr.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let stored = |path: &[u8], index| {
+            let inode = fs.lookup(path).unwrap();
+            let (number, block) = inode.xattr_block().unwrap().unwrap();
+            let entry = XattrIter::new(&block, number)
+                .unwrap()
+                .map(|entry| entry.unwrap())
+                .find(|entry| entry.name_index == index)
+                .unwrap();
+            entry.value.to_vec()
+        };
+        assert_eq!(
+            stored(b"/shared", xattr::INDEX_POSIX_ACL_ACCESS),
+            [
+                1, 0, 0, 0, 1, 0, 6, 0, 2, 0, 6, 0, 0xe8, 3, 0, 0, 4, 0, 4, 0,
+                0x10, 0, 6, 0, 0x20, 0, 4, 0
+            ]
+        );
+        assert_eq!(
+            stored(b"/dir", xattr::INDEX_POSIX_ACL_DEFAULT),
+            [
+                1, 0, 0, 0, 1, 0, 7, 0, 4, 0, 5, 0, 8, 0, 5, 0, 100, 0, 0, 0,
+                0x10, 0, 5, 0, 0x20, 0, 5, 0
+            ]
+        );
+
+        let rw = AclPerm::READ | AclPerm::WRITE;
+        let rx = AclPerm::READ | AclPerm::EXECUTE;
+        let entry = |tag, perm| AclEntry { tag, perm };
+        let shared = fs.get_acl(b"/shared").unwrap().unwrap();
+        assert_eq!(
+            shared.entries,
+            vec![
+                entry(AclTag::UserObj, rw),
+                entry(AclTag::User(1000), rw),
+                entry(AclTag::GroupObj, AclPerm::READ),
+                entry(AclTag::Mask, rw),
+                entry(AclTag::Other, AclPerm::READ),
+            ]
+        );
+        assert_eq!(fs.get_default_acl(b"/shared").unwrap(), None);
+        let dir = fs.get_default_acl(b"/dir").unwrap().unwrap();
+        assert_eq!(
+            dir.entries,
+            vec![
+                entry(AclTag::UserObj, AclPerm::all()),
+                entry(AclTag::GroupObj, rx),
+                entry(AclTag::Group(100), rx),
+                entry(AclTag::Mask, rx),
+                entry(AclTag::Other, rx),
+            ]
+        );
+        assert_eq!(fs.get_acl(b"/dir").unwrap(), None);
+        assert_eq!(fs.get_acl(b"/plain").unwrap(), None);
+
+        // 1000 owns the file, so its own entry is what counts for it; were
+        // the file root's, the named entry would grant it the same
+        let metadata = fs.metadata(b"/shared").unwrap();
+        let (owner, group) = (metadata.uid() as u32, metadata.gid() as u32);
+        assert_eq!(owner, 1000);
+        let check = |owner, uid, gid, groups: &[u32]| {
+            shared.effective_permissions(owner, group, uid, gid, groups)
+        };
+        assert_eq!(check(owner, 1000, 5, &[]), rw);
+        assert_eq!(check(0, 1000, 5, &[]), rw);
+        assert_eq!(check(owner, 1001, 5, &[group]), AclPerm::READ);
+        assert_eq!(check(owner, 1001, 5, &[]), AclPerm::READ);
+    }
+
+    #[test]
+    fn large_files() {
+        // made by mke2fs and debugfs: /big is a sparse 5 GiB file ending in
+        // a line of text
+        let volume = std::fs::read("fixtures/large.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume.clone()).unwrap();
+        let big = fs.lookup(b"/big").unwrap();
+        assert_eq!(big.size64(), 5 << 30);
+        {
+            let inner = fs.inner();
+            assert!(inner.large_files());
+            assert_eq!(big.inner.size(&inner.superblock.inner), 5 << 30);
+        }
+        let mut end = [0; 20];
+        big.read_exact_at((5 << 30) - 20, &mut end).unwrap();
+        assert_eq!(&end, b"end of a 5 GiB file\n");
+        let mut o = OpenOptions::new();
+        o.read(true);
+        let mut file = fs.open(b"/big", &o).unwrap();
+        assert_eq!(file.seek(SeekFrom::End(-20)).unwrap(), (5 << 30) - 20);
+
+        // directories keep their ACL block where files keep the upper half
+        // of their size
+        let mut dir = fs.lookup(b"/dir").unwrap();
+        dir.inner.size_high = 1;
+        assert_eq!(dir.size64(), 1024);
+
+        // without the large file feature, only the lower half counts
+        let mut volume = volume;
+        volume[1024 + 100] &= !2;
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert!(!fs.inner().large_files());
+        assert_eq!(fs.lookup(b"/big").unwrap().size64(), 1 << 30);
+
+        // and growing a file past 4 GiB turns it on
+        let mut file = fs
+            .open(b"/grown", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        file.seek(SeekFrom::Start(4 << 30)).unwrap();
+        file.write(b"past 4 GiB").unwrap();
+        assert!(fs.inner().large_files());
+        let grown = fs.lookup(b"/grown").unwrap();
+        assert_eq!(grown.size64(), (4 << 30) + 10);
+        assert!(fsck(&fs.inner().volume));
+
+        // which revision 0 file systems don't have
+        let volume = std::fs::read("fixtures/rev0.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs
+            .open(b"/grown", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        file.seek(SeekFrom::Start(4 << 30)).unwrap();
+        match file.write(b"past 4 GiB") {
+            Err(Error::Unsupported { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(fs.lookup(b"/grown").unwrap().size64(), 0);
+    }
+
+    #[test]
+    fn block_walk() {
+        let fs = Synced::<Ext2<Size512, _>>::new(
+            std::fs::read("ext2.img").unwrap(),
+        )
+        .unwrap();
+        let mut file = fs
+            .open(b"/sparse", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        let indices = [0_u64, 11, 12, 300, 70_000];
+        for &index in &indices {
+            file.seek(SeekFrom::Start(index * 1024)).unwrap();
+            file.write(b"x").unwrap();
+        }
+
+        let file = fs.lookup(b"/sparse").unwrap();
+        let blocks = {
+            let inner = fs.inner();
+            file.inner
+                .blocks::<Size512, _>(&inner.volume, &inner.superblock.inner)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap()
+        };
+        // every block is there once, data and indirect blocks alike
+        assert_eq!(
+            blocks.len() as u32 * 2,
+            { file.inner.sectors_count }
+        );
+        let data = blocks
+            .iter()
+            .filter_map(|block| match *block {
+                InodeBlock::Data { index, block } => Some((index, block)),
+                InodeBlock::Indirect { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            data.iter().map(|&(index, _)| index).collect::<Vec<_>>(),
+            indices
+        );
+        for &(index, block) in &data {
+            assert_eq!(file.bmap(index as u32).unwrap(), Some(block));
+        }
+        // each indirect block comes before the blocks it points at
+        let depths = blocks
+            .iter()
+            .filter_map(|block| match *block {
+                InodeBlock::Indirect { depth, .. } => Some(depth),
+                InodeBlock::Data { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(depths, vec![1, 2, 1, 3, 2, 1]);
+        match blocks[2] {
+            InodeBlock::Indirect { block, depth: 1 } => {
+                assert_eq!(block, { file.inner.indirect_pointer })
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn extents() {
+        let fs = Synced::<Ext2<Size512, _>>::new(
+            std::fs::read("ext2.img").unwrap(),
+        )
+        .unwrap();
+        let mut file = fs
+            .open(b"/sparse", OpenOptions::new().write(true).create(true))
+            .unwrap();
+        // runs on either side of the indirect blocks, with holes between
+        // them and in the middle of the doubly indirect range
+        let data = (0..40 << 10).map(|i| (i % 241) as u8).collect::<Vec<_>>();
+        for &block in &[0_u64, 300, 70_000] {
+            file.seek(SeekFrom::Start(block * 1024)).unwrap();
+            file.write(&data).unwrap();
+        }
+        file.seek(SeekFrom::Start((70_040 << 10) + 100)).unwrap();
+        file.write(b"partial last block").unwrap();
+
+        let file = fs.lookup(b"/sparse").unwrap();
+        let extents =
+            file.extents().collect::<Result<Vec<_>, _>>().unwrap();
+        let total = extents.iter().map(|extent| extent.len).sum::<u32>();
+        // every block up to the partial last one
+        assert_eq!(file.size(), 70_040 * 1024 + 118);
+        assert_eq!(total, 70_041);
+        let mut logical = 0;
+        for pair in extents.windows(2) {
+            // runs are maximal
+            assert!(pair[0].physical.is_some() || pair[1].physical.is_some());
+            if let (Some(a), Some(b)) = (pair[0].physical, pair[1].physical) {
+                assert_ne!(a + pair[0].len, b);
+            }
+        }
+        let holes = extents
+            .iter()
+            .filter(|extent| extent.physical.is_none())
+            .map(|extent| (extent.logical, extent.len))
+            .collect::<Vec<_>>();
+        assert_eq!(holes, vec![(40, 260), (340, 69_660)]);
+
+        // reading through the extents gives the same bytes as reading the
+        // file
+        let mut read = Vec::new();
+        for extent in &extents {
+            assert_eq!(extent.logical, logical);
+            logical += extent.len;
+            let len = extent.len as usize * 1024;
+            match extent.physical {
+                Some(block) => {
+                    let start = block as usize * 1024;
+                    read.extend_from_slice(
+                        &fs.inner().volume[start..start + len],
+                    );
+                }
+                None => read.resize(read.len() + len, 0),
+            }
+        }
+        read.truncate(file.size());
+        let mut expected = Vec::new();
+        file.read_to_end(&mut expected).unwrap();
+        assert!(read == expected);
+
+        assert_eq!(
+            fs.lookup(b"/home/funky/README.md")
+                .unwrap()
+                .extents()
+                .count(),
+            1
+        );
+        let link = symlink(&fs, b"/link", b"sparse");
+        assert_eq!(link.extents().count(), 0);
+    }
+
+    #[test]
+    fn htree_lookup() {
+        // made by Linux: /big holds name-00000 to name-49999, all links to
+        // the same inode, and `last`, indexed by a two level hash tree
+        let image = std::fs::read("fixtures/htree.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(Metered::new(image.clone()))
+            .unwrap();
+        let dir = fs.lookup(b"/big").unwrap();
+        assert!({ dir.inner.flags }.contains(Flags::HASH_DIR));
+        assert!(dir.is_indexed());
+
+        // the root, a node and a leaf, and the indirect blocks mapping them,
+        // rather than the 1202 blocks of the directory
+        let first = dir.entry(b"name-00000").unwrap().unwrap();
+        fs.inner().volume.reset();
+        let entry = dir.entry(b"name-31337").unwrap().unwrap();
+        assert_eq!(entry.name, b"name-31337");
+        assert!(fs.inner().volume.stats().reads < 10);
+        for i in 0..50_000 {
+            let name = format!("name-{:05}", i);
+            let entry = dir.dx_find(name.as_bytes()).unwrap();
+            assert_eq!(entry.unwrap().ino, first.ino);
+        }
+        assert!(dir.entry(b"name-50000").unwrap().is_none());
+
+        let mut data = Vec::new();
+        fs.lookup(b"/big/last")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"hello from an indexed directory\n");
+        // leaves are ordinary blocks to readdir
+        assert_eq!(dir.directory().unwrap().count(), 50_003);
+
+        // a root that doesn't add up leaves the slow way
+        let fs = Synced::<Ext2<Size512, _>>::new(image).unwrap();
+        let dir = fs.lookup(b"/big").unwrap();
+        let root = dir.block(0).unwrap().get() as usize * 1024;
+        fs.inner().volume[root + 29] = 9;
+        assert!(dir.dx_find(b"last").is_err());
+        assert!(dir.entry(b"last").unwrap().is_some());
+        fs.inner().volume[root + 29] = 8;
+
+        // adding an entry leaves the index behind
+        fs.create_file(b"/big/new", Permissions::U_READ).unwrap();
+        let dir = fs.lookup(b"/big").unwrap();
+        assert!(!dir.is_indexed());
+        assert!(dir.entry(b"new").unwrap().is_some());
+        assert!(dir.entry(b"name-31337").unwrap().is_some());
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    /// Drops the last link to inode `num` and returns it to the free pool
+    fn release(fs: &Synced<Ext2<Size512, Vec<u8>>>, num: u32) {
+        let mut inode = fs.inode_nth(num as usize).unwrap();
+        inode.inner.hard_links = 0;
+        inode.write_back(&mut fs.inner()).unwrap();
+        fs.inner().free_inode(num).unwrap();
+    }
+
+    #[test]
+    fn remove_entry() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        // fill the first block, then put three entries in the second one
+        let mut names = Vec::new();
+        for i in 0.. {
+            let path = format!("/home/funky/file-{:04}", i);
+            fs.create_file(path.as_bytes(), Permissions::U_READ).unwrap();
+            let dir = fs.lookup(b"/home/funky").unwrap();
+            if dir.size() > 1024 {
+                names.push(path[12..].to_string());
+                if names.len() == 3 {
+                    break;
+                }
+            }
+        }
+        let mut dir = fs.lookup(b"/home/funky").unwrap();
+        let block = dir.block(1).unwrap().get() as usize * 1024;
+        let first = {
+            let fs = fs.inner();
+            read_u32(&fs.volume[block..])
+        };
+
+        let entries = dir.directory().unwrap().count();
+
+        // middle, last and then first entry of the block
+        for (removed, &index) in [1, 2, 0].iter().enumerate() {
+            let num = dir
+                .directory()
+                .unwrap()
+                .remove_entry(names[index].as_bytes())
+                .unwrap();
+            release(&fs, num);
+            dir = fs.lookup(b"/home/funky").unwrap();
+            assert!(dir.entry(names[index].as_bytes()).unwrap().is_none());
+            let count = dir.directory().unwrap().map(Result::unwrap).count();
+            assert_eq!(count, entries - removed - 1);
+        }
+
+        {
+            let fs = fs.inner();
+            let data = &fs.volume[block..block + 1024];
+            assert!(first != 0);
+            assert_eq!(read_u32(data), 0);
+            assert_eq!(read_u16(&data[4..]), 1024);
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn remove_entry_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let dir = fs.lookup(b"/home/funky").unwrap();
+
+        for name in &[&b"."[..], &b".."[..]] {
+            match dir.directory().unwrap().remove_entry(name) {
+                Err(Error::InvalidName { .. }) => (),
+                other => panic!("{:?}", other),
+            }
+        }
+        match dir.directory().unwrap().remove_entry(b"missing") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(dir.directory().unwrap().count(), 5);
+    }
+
+    #[test]
+    fn unlink_large_file() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let (free_blocks, free_inodes) = {
+            let fs = fs.inner();
+            let superblock = &fs.superblock.inner;
+            (superblock.free_blocks_count, superblock.free_inodes_count)
+        };
+
+        // 2 MiB reaches into the doubly indirect blocks
+        let mut file = fs.create_file(b"/big", Permissions::U_READ).unwrap();
+        {
+            let mut fs = fs.inner();
+            for index in 0..2048 {
+                file.map_block(&mut fs, index).unwrap();
+            }
+            file.inner.size_low = 2048 * 1024;
+            file.write_back(&mut fs).unwrap();
+            assert!(fs.superblock.inner.free_blocks_count < free_blocks - 2048);
+        }
+        assert!(fsck(&fs.inner().volume));
+
+        let mut fs = fs;
+        fs.remove_file(b"/big").unwrap();
+        match fs.lookup(b"/big") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        {
+            let fs = fs.inner();
+            let superblock = &fs.superblock.inner;
+            assert_eq!({ superblock.free_blocks_count }, free_blocks);
+            assert_eq!({ superblock.free_inodes_count }, free_inodes);
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn unlink_existing() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let unl = fs.lookup(b"/home/funky/unl").unwrap();
+        let free_blocks = fs.inner().superblock.inner.free_blocks_count;
+
+        fs.unlink(b"/home/funky/unl").unwrap();
+        let freed = fs.inner().superblock.inner.free_blocks_count - free_blocks;
+        assert_eq!(freed, unl.inner.sectors_count / 2);
+        assert!(fsck(&fs.inner().volume));
+
+        match fs.unlink(b"/home/funky") {
+            Err(Error::IsADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.unlink(b"/home/funky/unl") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn unlink_secure() {
+        const MAGIC: &[u8] = b"ext2-rs secure deletion magic ";
+        let contains_magic =
+            |volume: &[u8]| volume.windows(MAGIC.len()).any(|w| w == MAGIC);
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        // spill over into an indirect block
+        let data = MAGIC.repeat(20 * 1024 / MAGIC.len());
+
+        let mut file = fs.create_file(b"/secret", Permissions::U_READ).unwrap();
+        file.write_at(0, &data).unwrap();
+        let indirect = { file.inner.indirect_pointer };
+        file.inner.flags = Flags::SECURE_DEL;
+        file.write_back(&mut fs.inner()).unwrap();
+        fs.unlink(b"/secret").unwrap();
+        {
+            let fs = fs.inner();
+            assert!(!contains_magic(&fs.volume));
+            let offset = indirect as usize * 1024;
+            assert!(fs.volume[offset..offset + 1024].iter().all(|&b| b == 0));
+        }
+
+        // shredding works regardless of the flag
+        let mut file = fs.create_file(b"/shred", Permissions::U_READ).unwrap();
+        file.write_at(0, &data).unwrap();
+        let free_blocks = fs.inner().free_block_count();
+        fs.shred(b"/shred").unwrap();
+        assert!(!contains_magic(&fs.inner().volume));
+        assert_eq!(fs.inner().free_block_count(), free_blocks + 21);
+        assert!(fsck(&fs.inner().volume));
+
+        match fs.shred(b"/home") {
+            Err(Error::IsADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // without the flag, the data is left behind
+        let mut file = fs.create_file(b"/plain", Permissions::U_READ).unwrap();
+        file.write_at(0, &data).unwrap();
+        fs.unlink(b"/plain").unwrap();
+        assert!(contains_magic(&fs.inner().volume));
+    }
+
+    #[test]
+    fn unlink_hard_link() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        fs.lookup(b"/home")
+            .unwrap()
+            .add_entry(b"README.md", readme.num, inode::FILE)
+            .unwrap();
+        readme.inner.hard_links += 1;
+        readme.write_back(&mut fs.inner()).unwrap();
+
+        fs.unlink(b"/home/funky/README.md").unwrap();
+        let readme = fs.lookup(b"/home/README.md").unwrap();
+        assert_eq!({ readme.inner.hard_links }, 1);
+        assert!(readme.block(0).is_some());
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn mkdir() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let (dirs, links) = {
+            let home = fs.lookup(b"/home").unwrap();
+            let fs = fs.inner();
+            (fs.block_groups.inner[0].dirs_count, home.inner.hard_links)
+        };
+
+        let dir = fs
+            .mkdir(b"/home/new", Permissions::from_bits_truncate(0o750))
+            .unwrap();
+        assert!(dir.is_dir());
+        assert_eq!(dir.size(), 1024);
+        assert_eq!({ dir.inner.hard_links }, 2);
+        assert_eq!({ dir.inner.type_perm }.bits(), 0o40750);
+
+        let entries = dir
+            .directory()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.name, entry.ino, entry.file_type)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (b".".to_vec(), dir.num, Some(FileType::Directory)),
+                (b"..".to_vec(), 12, Some(FileType::Directory)),
+            ]
+        );
+
+        let home = fs.lookup(b"/home").unwrap();
+        assert_eq!({ home.inner.hard_links }, links + 1);
+        assert_eq!({ fs.inner().block_groups.inner[0].dirs_count }, dirs + 1);
+        assert_eq!(fs.lookup(b"/home/new/..").unwrap().num, 12);
+
+        fs.create_file(b"/home/new/file", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/home/new/sub", Permissions::U_READ).unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn mkdir_spread() {
+        let mut volume = vec![0_u8; 64 << 20];
+        Builder::new().build::<Size512, _>(&mut volume).unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        for i in 0..100 {
+            let path = format!("/dir{}", i);
+            let perm = Permissions::from_bits_truncate(0o755);
+            fs.mkdir(path.as_bytes(), perm).unwrap();
+        }
+
+        let fs = fs.inner();
+        let dirs: Vec<_> = fs
+            .block_groups
+            .inner
+            .iter()
+            .map(|descr| descr.dirs_count)
+            .collect();
+        assert_eq!(dirs.iter().sum::<u16>(), 102);
+        assert!(dirs.iter().filter(|&&count| count > 0).count() > 4);
+        assert!(dirs.iter().all(|&count| count < 25), "{:?}", dirs);
+        assert!(fsck(&fs.volume));
+    }
+
+    #[test]
+    fn mkdir_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        match fs.mkdir(b"/missing/new", Permissions::U_READ) {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        match fs.mkdir(b"/home/funky/README.md/new", Permissions::U_READ) {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        match fs.mkdir(b"/home/funky", Permissions::U_READ) {
+            Err(Error::AlreadyExists { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+    }
+
+    #[test]
+    fn create_dir_recursive() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        match fs.create_dir(b"/a/b", &DirOptions::new()) {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        fs.create_dir(b"/a/b/c", DirOptions::new().recursive(true))
+            .unwrap();
+        fs.create_dir(b"/a/b/d", DirOptions::new().recursive(true))
+            .unwrap();
+        assert!(fs.lookup(b"/a/b/c").unwrap().is_dir());
+        assert!(fs.lookup(b"/a/b/d").unwrap().is_dir());
+        assert_eq!({ fs.lookup(b"/a/b").unwrap().inner.hard_links }, 4);
+
+        match fs
+            .create_dir(b"/home/funky/u/x", DirOptions::new().recursive(true))
+        {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn rmdir() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let (free_blocks, free_inodes, dirs, links) = {
+            let home = fs.lookup(b"/home").unwrap();
+            let fs = fs.inner();
+            (
+                fs.superblock.inner.free_blocks_count,
+                fs.superblock.inner.free_inodes_count,
+                fs.block_groups.inner[0].dirs_count,
+                home.inner.hard_links,
+            )
+        };
+
+        fs.mkdir(b"/home/new", Permissions::U_READ).unwrap();
+        fs.create_file(b"/home/new/file", Permissions::U_READ).unwrap();
+        match fs.rmdir(b"/home/new") {
+            Err(Error::DirectoryNotEmpty { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        fs.unlink(b"/home/new/file").unwrap();
+        fs.remove_dir(b"/home/new").unwrap();
+
+        match fs.lookup(b"/home/new") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        {
+            let home = fs.lookup(b"/home").unwrap();
+            let fs = fs.inner();
+            assert_eq!({ fs.superblock.inner.free_blocks_count }, free_blocks);
+            assert_eq!({ fs.superblock.inner.free_inodes_count }, free_inodes);
+            assert_eq!({ fs.block_groups.inner[0].dirs_count }, dirs);
+            assert_eq!({ home.inner.hard_links }, links);
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn rmdir_grown() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        fs.mkdir(b"/grown", Permissions::U_READ).unwrap();
+        for i in 0..100 {
+            let path = format!("/grown/file-{:04}.txt", i);
+            fs.create_file(path.as_bytes(), Permissions::U_READ).unwrap();
+        }
+        assert!(fs.lookup(b"/grown").unwrap().size() > 1024);
+        for i in 0..100 {
+            let path = format!("/grown/file-{:04}.txt", i);
+            fs.unlink(path.as_bytes()).unwrap();
+        }
+
+        fs.rmdir(b"/grown").unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn rmdir_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        match fs.rmdir(b"/") {
+            Err(Error::InvalidName { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rmdir(b"/home") {
+            Err(Error::DirectoryNotEmpty { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rmdir(b"/home/funky/README.md") {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        fs.mkdir(b"/home/new", Permissions::U_READ).unwrap();
+        match fs.rmdir(b"/home/new/.") {
+            Err(Error::InvalidName { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn rename_file() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap().num;
+
+        fs.rename(b"/home/funky/README.md", b"/home/funky/README")
+            .unwrap();
+        Fs::rename(&mut fs, b"/home/funky/README", b"/home/README").unwrap();
+        assert_eq!(fs.lookup(b"/home/README").unwrap().num, readme);
+        for path in &[&b"/home/funky/README.md"[..], b"/home/funky/README"] {
+            match fs.lookup(path) {
+                Err(Error::NotFound { .. }) => (),
+                other => panic!("{:?}", other.map(|inode| inode.num)),
+            }
+        }
+
+        // onto itself
+        fs.rename(b"/home/README", b"/home/README").unwrap();
+        assert_eq!(fs.lookup(b"/home/README").unwrap().num, readme);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn rename_overwrite() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let free_blocks = fs.inner().superblock.inner.free_blocks_count;
+        let unl = fs.lookup(b"/home/funky/unl").unwrap();
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap().num;
+
+        fs.rename(b"/home/funky/README.md", b"/home/funky/unl")
+            .unwrap();
+        assert_eq!(fs.lookup(b"/home/funky/unl").unwrap().num, readme);
+        let freed = fs.inner().superblock.inner.free_blocks_count - free_blocks;
+        assert_eq!(freed, unl.inner.sectors_count / 2);
+
+        fs.mkdir(b"/a", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/b", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/a/sub", Permissions::U_READ).unwrap();
+        fs.rename(b"/a", b"/b").unwrap();
+        assert!(fs.lookup(b"/b/sub").unwrap().is_dir());
+        assert!(fsck(&fs.inner().volume));
+    }
 
-    fn path(&self) -> Self::PathOwned {
-        unimplemented!()
+    #[test]
+    fn rename_dir() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let links = fs.lookup(b"/home").unwrap().inner.hard_links;
+
+        fs.mkdir(b"/home/dir", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/home/dir/sub", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/other", Permissions::U_READ).unwrap();
+        fs.rename(b"/home/dir", b"/other/moved").unwrap();
+
+        let moved = fs.lookup(b"/other/moved").unwrap();
+        let other = fs.lookup(b"/other").unwrap();
+        assert_eq!(moved.entry(b"..").unwrap().unwrap().ino, other.num);
+        assert_eq!({ other.inner.hard_links }, 3);
+        assert_eq!({ fs.lookup(b"/home").unwrap().inner.hard_links }, links);
+        assert!(fs.lookup(b"/other/moved/sub").unwrap().is_dir());
+        assert!(fsck(&fs.inner().volume));
     }
 
-    fn metadata(&self) -> Result<Self::Metadata, Self::Error> {
-        unimplemented!()
+    #[test]
+    fn rename_errors() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        fs.mkdir(b"/a", Permissions::U_READ).unwrap();
+        fs.mkdir(b"/a/b", Permissions::U_READ).unwrap();
+
+        match fs.rename(b"/a", b"/a/b/c") {
+            Err(Error::InvalidRename { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rename(b"/home/funky/README.md", b"/a") {
+            Err(Error::IsADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rename(b"/a/b", b"/home/funky/README.md") {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rename(b"/a/b", b"/home") {
+            Err(Error::DirectoryNotEmpty { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.rename(b"/a/b", b"/a/b/..") {
+            Err(Error::InvalidName { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(fsck(&fs.inner().volume));
     }
 
-    fn file_type(&self) -> Result<Self::FileType, Self::Error> {
-        Ok(self.ty)
+    #[test]
+    fn rename_atomic() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o644);
+        fs.create_file(b"/target", perm).unwrap();
+
+        // nothing changes while a reader holds the lock, and what it sees
+        // then is never a rename half done: the target is there, and no
+        // other entry links to the file it was replaced with
+        let done = Arc::new(AtomicBool::new(false));
+        let readers = (0..2)
+            .map(|_| {
+                let (fs, done) = (fs.clone(), done.clone());
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let guard = fs.read();
+                        let target = fs.lookup(b"/target").unwrap();
+                        let root = fs.root_inode().directory().unwrap();
+                        let links = root
+                            .map(|entry| entry.unwrap().ino)
+                            .filter(|&ino| ino == target.num)
+                            .count();
+                        let expected = { target.inner.hard_links };
+                        assert_eq!(links, expected as usize);
+                        drop(guard);
+                        std::thread::yield_now();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for i in 0..200 {
+            let path = format!("/new{}", i);
+            let mut file = fs.create_file(&path, perm).unwrap();
+            file.write_at(0, format!("version {}", i).as_bytes())
+                .unwrap();
+            fs.rename(&path, b"/target").unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for thread in readers {
+            thread.join().unwrap();
+        }
+
+        let mut contents = Vec::new();
+        fs.lookup(b"/target")
+            .unwrap()
+            .read_to_end(&mut contents)
+            .unwrap();
+        assert_eq!(contents, b"version 199");
+        assert!(fsck(&fs.inner().volume));
     }
 
-    fn file_name(&self) -> &Self::Path {
-        &self.name
+    /// Creates a symlink to `target` at `path`, stored in the inode itself if
+    /// it's short enough
+    pub(crate) fn symlink(
+        fs: &Synced<Ext2<Size512, Vec<u8>>>,
+        path: &[u8],
+        target: &[u8],
+    ) -> Inode<Size512, Vec<u8>> {
+        let mut link = fs.create_file(path, Permissions::U_READ).unwrap();
+        let mut inner = fs.inner();
+        if target.len() < 60 {
+            let mut pointers = [0; 60];
+            pointers[..target.len()].copy_from_slice(target);
+            for (slot, pointer) in pointers.chunks(4).enumerate() {
+                link.set_block_pointer(slot, read_u32(pointer));
+            }
+        } else {
+            let block = link.map_block(&mut inner, 0).unwrap() as usize;
+            inner.volume[block * 1024..block * 1024 + target.len()]
+                .copy_from_slice(target);
+        }
+        link.inner.type_perm =
+            TypePerm::SYMLINK | TypePerm::from_bits_truncate(0o777);
+        link.inner.size_low = target.len() as u32;
+        link.write_back(&mut inner).unwrap();
+        drop(inner);
+
+        let (parent, name) = split_path(path).unwrap();
+        fs.lookup(parent)
+            .unwrap()
+            .replace_entry_locked(
+                &mut fs.inner(),
+                name,
+                link.num,
+                inode::SYMLINK,
+            )
+            .unwrap();
+        link
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::cell::RefCell;
-    use std::fs::File;
+    #[test]
+    fn read_link() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let fast = symlink(&fs, b"/fast", b"home/funky/README.md");
+        assert_eq!(fast.read_link().unwrap(), b"home/funky/README.md");
+        assert_eq!(fast.read_link_str().unwrap(), "home/funky/README.md");
+
+        let target = [b"/home/funky/".to_vec(), vec![b'x'; 100]].concat();
+        let slow = symlink(&fs, b"/slow", &target);
+        assert_eq!(slow.read_link().unwrap(), target);
+        assert_eq!(fs.read_link(b"/slow").unwrap(), target);
+
+        let invalid = symlink(&fs, b"/invalid", b"\xff\xfe");
+        assert_eq!(invalid.read_link().unwrap(), b"\xff\xfe");
+        match invalid.read_link_str() {
+            Err(Error::InvalidUtf8 { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        match fs.read_link(b"/home/funky/README.md") {
+            Err(Error::NotASymlink { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
 
-    use genfs::{File as GenFile, Fs, OpenOptions};
+    #[test]
+    fn dir_handles() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let home = fs.open_dir("/home").unwrap();
+        assert_eq!(home.ino(), fs.lookup("/home").unwrap().num);
+        let readme = fs.lookup("/home/funky/README.md").unwrap().num;
+        assert_eq!(home.metadata_at("funky/README.md").unwrap().num, readme);
+        let absolute = home.metadata_at("/home/funky/README.md").unwrap();
+        assert_eq!(absolute.num, readme);
+        assert_eq!(home.metadata_at("..").unwrap().num, 2);
+        assert_eq!(home.metadata_at("").unwrap().num, home.ino());
+
+        let mut read = OpenOptions::new();
+        read.read(true);
+        let funky = home.open_dir_at("funky").unwrap();
+        let names = funky
+            .read_dir_at(".")
+            .unwrap()
+            .map(|entry| entry.unwrap().name)
+            .collect::<Vec<_>>();
+        assert!(names.contains(&b"README.md".to_vec()));
+        let mut data = Vec::new();
+        funky
+            .open_at("README.md", &read)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert!(!data.is_empty());
+        match funky.open_dir_at("README.md") {
+            Err(Error::NotADirectory { .. }) => (),
+            other => panic!("{:?}", other.map(|dir| dir.ino())),
+        }
+
+        let mut create = OpenOptions::new();
+        create.write(true).create(true);
+        let new = funky.open_at("new", &create).unwrap();
+        assert_eq!(fs.lookup("/home/funky/new").unwrap().num, new.num);
+
+        // a handle kept beneath its directory can't be left through `..`,
+        // absolute paths or absolute symlinks
+        let up = symlink(&fs, b"/home/funky/up", b"../..");
+        symlink(&fs, b"/home/funky/root", b"/home");
+        symlink(&fs, b"/home/funky/back", b"../funky/README.md");
+        fs.mkdir("/home/funky/sub", Permissions::U_READ | Permissions::U_EXEC)
+            .unwrap();
+        symlink(&fs, b"/home/funky/sub/readme", b"../README.md");
+        let jail = fs.open_dir("/home/funky").unwrap().beneath();
+        for path in &["..", "../funky", ".//..", "/home", "up", "root", "back"]
+        {
+            match jail.metadata_at(path) {
+                Err(Error::EscapesBeneath { .. }) => (),
+                other => panic!("{}: {:?}", path, other.map(|inode| inode.num)),
+            }
+        }
+        // going up is fine as long as it stays inside
+        assert_eq!(jail.symlink_metadata_at("up").unwrap().num, up.num);
+        assert_eq!(jail.metadata_at("sub/../README.md").unwrap().num, readme);
+        assert_eq!(jail.metadata_at("sub/readme").unwrap().num, readme);
+        let sub = jail.open_dir_at("sub").unwrap();
+        match sub.open_at("../README.md", &read) {
+            Err(Error::EscapesBeneath { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
 
-    use sector::{SectorSize, Size512};
-    use volume::Volume;
+    #[test]
+    fn lookup_context() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        match fs.lookup(b"/home/funky/missing/deeper") {
+            Err(Error::NotFound { name, parent: 13 }) => {
+                assert_eq!(name, "/home/funky/missing")
+            }
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        match fs.lookup(b"/home/funky/README.md/deeper") {
+            Err(Error::NotADirectory { name, inode: 14 }) => {
+                assert_eq!(name, "/home/funky/README.md")
+            }
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
 
-    use super::{Ext2, Inode, Synced};
+        // point the first block of /home, inode 12, past the end
+        let mut volume = std::fs::read("ext2.img").unwrap();
+        let pointer = 20 * 1024 + 11 * 128 + 40;
+        volume[pointer..pointer + 4].copy_from_slice(&[0, 0, 1, 0]);
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        match fs.lookup(b"/home/funky") {
+            Err(Error::WithComponent {
+                offset: 6,
+                inode: 12,
+                source,
+            }) => match *source {
+                Error::Corrupt { .. } => (),
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+    }
 
     #[test]
-    fn file() {
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file);
+    fn lookup_symlinks() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap().num;
+
+        // a chain of 10 links, the first one absolute and the rest relative
+        symlink(&fs, b"/link-0", b"/home/funky/README.md");
+        for i in 1..10 {
+            let path = format!("/link-{}", i);
+            let target = format!("link-{}", i - 1);
+            symlink(&fs, path.as_bytes(), target.as_bytes());
+        }
+        assert_eq!(fs.lookup(b"/link-9").unwrap().num, readme);
+        assert!(fs.lookup_nofollow(b"/link-9").unwrap().is_symlink());
+
+        // relative to the directory holding the link, in the middle of a path
+        symlink(&fs, b"/home/dir", b"funky/");
+        assert_eq!(fs.lookup(b"/home/dir/README.md").unwrap().num, readme);
+        assert_eq!(fs.lookup(b"/home/dir/..").unwrap().num, 12);
+
+        let link = fs
+            .open(
+                b"/link-9",
+                OpenOptions::new().read(true).custom_flags(O_NOFOLLOW),
+            )
+            .unwrap();
+        assert_eq!(link.read_link().unwrap(), b"link-8");
+        let file = fs.open(b"/link-9", OpenOptions::new().read(true)).unwrap();
+        assert_eq!(file.num, readme);
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        assert!(
-            fs.is_ok(),
-            "Err({:?})",
-            fs.err().unwrap_or_else(|| unreachable!()),
-        );
+    #[test]
+    fn lookup_symlink_loops() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        symlink(&fs, b"/self", b"self");
+        symlink(&fs, b"/a", b"/b");
+        symlink(&fs, b"/b", b"a");
+        for path in &[&b"/self"[..], b"/a", b"/b/c"] {
+            match fs.lookup(path) {
+                Err(Error::TooManySymlinks { .. }) => (),
+                other => panic!("{:?}", other.map(|inode| inode.num)),
+            }
+        }
+        assert!(fs.lookup_nofollow(b"/a").unwrap().is_symlink());
 
-        let fs = fs.unwrap();
-        let inner = fs.inner();
+        // the links themselves can still be removed
+        fs.unlink(b"/a").unwrap();
+        match fs.lookup(b"/b") {
+            Err(Error::NotFound { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
+        }
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        let vers = inner.version();
-        println!("version: {}.{}", vers.0, vers.1);
-        assert_eq!(128, inner.inode_size());
+    #[test]
+    fn truncate_shrink() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut unl = fs.lookup(b"/home/funky/unl").unwrap();
+        let mut original = Vec::new();
+        unl.read_to_end(&mut original).unwrap();
+        assert_eq!(original.len(), 537600);
+
+        // halfway through the doubly indirect blocks, then the singly
+        // indirect block, the direct blocks and finally nothing at all
+        for &size in &[400 * 1024 + 100, 100 * 1024, 5 * 1024 - 1, 0] {
+            let free_blocks = fs.inner().superblock.inner.free_blocks_count;
+            let sectors = unl.inner.sectors_count;
+            unl.set_len(size as u64).unwrap();
+
+            let freed =
+                fs.inner().superblock.inner.free_blocks_count - free_blocks;
+            assert_eq!(freed, (sectors - unl.inner.sectors_count) / 2);
+            assert_eq!(unl.size(), size);
+            let mut data = Vec::new();
+            unl.read_to_end(&mut data).unwrap();
+            assert!(data == original[..size]);
+            assert!(fsck(&fs.inner().volume));
+        }
+        assert_eq!({ unl.inner.sectors_count }, 0);
+        assert_eq!({ unl.inner.indirect_pointer }, 0);
+        assert_eq!({ unl.inner.doubly_indirect }, 0);
     }
 
     #[test]
-    fn inodes() {
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file);
+    fn truncate_grow() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        let mut original = Vec::new();
+        readme.read_to_end(&mut original).unwrap();
+
+        // shrinking mid-block zeroes the rest, so growing reads back zeroes
+        readme.set_len(10).unwrap();
+        readme.set_len(300 * 1024).unwrap();
+        assert_eq!({ readme.inner.sectors_count }, 2);
+        let mut data = Vec::new();
+        readme.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 300 * 1024);
+        assert_eq!(data[..10], original[..10]);
+        assert!(data[10..].iter().all(|&byte| byte == 0));
+        assert!(fsck(&fs.inner().volume));
+
+        let free_blocks = fs.inner().superblock.inner.free_blocks_count;
+        readme.truncate(400 * 1024, true).unwrap();
+        // only the new range gets allocated, all of it below the doubly
+        // indirect block and the first indirect block it points at
+        let used = free_blocks - fs.inner().superblock.inner.free_blocks_count;
+        assert_eq!(used, 100 + 2);
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        assert!(
-            fs.is_ok(),
-            "Err({:?})",
-            fs.err().unwrap_or_else(|| unreachable!()),
-        );
+    #[test]
+    fn preallocate() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        let free_blocks = fs.inner().free_block_count();
+
+        // 99 data blocks past the first one, and an indirect block
+        readme.preallocate(100 * 1024, true).unwrap();
+        assert_eq!(readme.size(), 31);
+        assert_eq!({ readme.inner.sectors_count }, 2 * 101);
+        assert_eq!(fs.inner().free_block_count(), free_blocks - 100);
+
+        // writing into the preallocated range allocates nothing
+        let data = vec![b'x'; 50 * 1024];
+        readme.write_at(40 * 1024, &data).unwrap();
+        assert_eq!(readme.size(), 90 * 1024);
+        assert_eq!(fs.inner().free_block_count(), free_blocks - 100);
+        // nor does preallocating it again
+        readme.preallocate(100 * 1024, true).unwrap();
+        assert_eq!(fs.inner().free_block_count(), free_blocks - 100);
+
+        let mut content = Vec::new();
+        readme.read_to_end(&mut content).unwrap();
+        assert!(content[31..40 * 1024].iter().all(|&byte| byte == 0));
+        assert_eq!(content[40 * 1024..], data[..]);
+
+        readme.preallocate(120 * 1024, false).unwrap();
+        assert_eq!(readme.size(), 120 * 1024);
+        assert_eq!(fs.inner().free_block_count(), free_blocks - 120);
+        assert!(fsck(&fs.inner().volume));
+
+        match fs.lookup(b"/home").unwrap().preallocate(1 << 20, true) {
+            Err(Error::IsADirectory { .. }) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
 
-        let fs = fs.unwrap();
+    #[test]
+    fn preallocate_out_of_space() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs.create_file(b"/log", Permissions::U_WRITE).unwrap();
+        file.preallocate(10 * 1024, true).unwrap();
+        let free_blocks = fs.inner().free_block_count();
+
+        match file.preallocate(8 << 20, true) {
+            Err(Error::OutOfSpace) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        // the blocks from the first call stay
+        assert_eq!(fs.inner().free_block_count(), free_blocks);
+        assert_eq!({ file.inner.sectors_count }, 2 * 10);
+        assert_eq!(file.size(), 0);
+        // e2fsck takes blocks past the end of a file for a wrong size
+        file.set_len(10 * 1024).unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        let inodes = fs.inodes().filter(|inode| inode.in_use());
-        for inode in inodes {
-            println!("{:?}", inode);
+    #[test]
+    fn overlay() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let overlay = Overlay::new(volume.clone());
+        let fs = Synced::<Ext2<Size512, _>>::new(overlay).unwrap();
+        let mut file = fs.create_file(b"/new", Permissions::U_READ).unwrap();
+        file.write_at(0, b"written to the overlay").unwrap();
+        fs.unlink(b"/home/funky/unl").unwrap();
+
+        let fs = fs.inner();
+        assert_eq!(fs.volume.base(), &volume);
+        assert!(!fs.volume.diff().is_empty());
+
+        let mut copy = volume.clone();
+        fs.volume.commit_to(&mut copy).unwrap();
+        assert!(fsck(&copy));
+        let fs = Synced::<Ext2<Size512, _>>::new(copy).unwrap();
+        let mut data = Vec::new();
+        fs.lookup(b"/new").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"written to the overlay");
+        assert!(fs.lookup(b"/home/funky/unl").is_err());
+    }
+
+    #[test]
+    fn open_truncate() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let readme = fs
+            .open(
+                b"/home/funky/README.md",
+                OpenOptions::new().write(true).truncate(true),
+            )
+            .unwrap();
+        assert_eq!(readme.size(), 0);
+        assert_eq!({ readme.inner.sectors_count }, 0);
+        match fs.open(b"/home", OpenOptions::new().write(true).truncate(true)) {
+            Err(Error::IsADirectory { .. }) => (),
+            other => panic!("{:?}", other.map(|inode| inode.num)),
         }
+        assert!(fsck(&fs.inner().volume));
     }
 
     #[test]
-    fn inode_blocks() {
-        use std::str;
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+    fn append() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let options = OpenOptions::new().append(true).create(true).clone();
+
+        // 600 KiB crosses into the singly and then the doubly indirect blocks
+        let mut expected = Vec::new();
+        for i in 0..600 {
+            let chunk = vec![i as u8; 1000 + i % 48];
+            let mut file = fs.open(b"/appended", &options).unwrap();
+            assert_eq!(file.write(&chunk).unwrap(), chunk.len());
+            expected.extend_from_slice(&chunk);
+        }
 
-        let inodes = fs.inodes().filter(|inode| {
-            inode.in_use() && inode.uid() == 1000 && inode.size() < 1024
-        });
-        for inode in inodes {
-            println!("{:?}", inode);
-            let size = inode.size();
-            for block in inode.blocks() {
-                let (data, _) = block.unwrap();
-                assert_eq!(data.len(), {
-                    let fs = fs.inner();
-                    fs.block_size()
-                });
-                println!("{:?}", &data[..size]);
-                let _ = str::from_utf8(&data[..size])
-                    .map(|string| println!("{}", string));
-            }
+        let file = fs.lookup(b"/appended").unwrap();
+        assert_eq!(file.size(), expected.len());
+        assert!(file.inner.indirect_pointer != 0);
+        assert!(file.inner.doubly_indirect != 0);
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        assert!(data == expected);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn append_triply_indirect() {
+        let volume = match mkfs(80 << 20) {
+            Some(volume) => volume,
+            None => return,
+        };
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs
+            .open(b"/large", OpenOptions::new().append(true).create(true))
+            .unwrap();
+
+        // 70 MiB is past the 12 + 256 + 256 * 256 blocks below the doubly
+        // indirect block
+        let chunk = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        for _ in 0..70 {
+            assert_eq!(file.write(&chunk).unwrap(), chunk.len());
         }
+        assert_eq!(file.size(), 70 << 20);
+        assert!(file.inner.triply_indirect != 0);
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        assert!(data.chunks(1 << 20).all(|part| part == &chunk[..]));
+        assert!(fsck(&fs.inner().volume));
+
+        let free_blocks = fs.inner().superblock.inner.free_blocks_count;
+        let sectors = file.inner.sectors_count;
+        file.set_len(0).unwrap();
+        let freed = fs.inner().superblock.inner.free_blocks_count - free_blocks;
+        assert_eq!(freed, sectors / 2);
+        assert!(fsck(&fs.inner().volume));
     }
 
     #[test]
-    fn read_inode() {
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+    fn set_permissions() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        fs.inner().set_clock(|| 0x12345678);
+        let offset =
+            fs.inner().inode_address(14).unwrap().into_index() as usize;
+        let before = fs.inner().volume[offset..offset + 128].to_vec();
+        assert_eq!(
+            { fs.lookup(b"/home/funky/README.md").unwrap().inner.type_perm },
+            TypePerm::FILE | TypePerm::from_bits_truncate(0o644)
+        );
 
-        let inodes = fs.inodes().filter(|inode| {
-            inode.in_use() && inode.uid() == 1000 && inode.size() < 1024
-        });
-        for inode in inodes {
-            let mut buf = Vec::with_capacity(inode.size());
-            unsafe {
-                buf.set_len(inode.size());
-            }
-            let size = inode.read(&mut buf[..]);
-            assert!(size.is_ok());
-            let size = size.unwrap();
-            assert_eq!(size, inode.size());
-            unsafe {
-                buf.set_len(size);
+        // the file type is kept
+        let perm = Permissions::U_READ;
+        fs.set_permissions(b"/home/funky/README.md", perm).unwrap();
+        let after = fs.inner().volume[offset..offset + 128].to_vec();
+        for (i, (&old, &new)) in before.iter().zip(&after).enumerate() {
+            match i {
+                // i_mode
+                0 => assert_eq!(new, 0x00),
+                1 => assert_eq!(new, 0x81),
+                // i_ctime
+                12..=15 => assert_eq!(new, 0x12345678u32.to_le_bytes()[i - 12]),
+                _ => assert_eq!(old, new, "byte {} changed", i),
             }
         }
+        assert!(fsck(&fs.inner().volume));
     }
 
     #[test]
-    fn read_big() {
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+    fn set_owner() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        symlink(&fs, b"/home/link", b"funky/README.md");
+        fs.set_owner(b"/home/link", 1000, 100).unwrap();
+
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        assert_eq!(readme.uid(), 1000);
+        assert_eq!({ readme.inner.gid }, 100);
+        let link = fs.lookup_nofollow(b"/home/link").unwrap();
+        assert_eq!(link.uid(), 0);
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        let inodes = fs.inodes().filter(|inode| {
-            inode.in_use() && inode.uid() == 1000 && inode.size() == 537600
-        });
-        for inode in inodes {
-            let mut buf = Vec::with_capacity(inode.size());
-            unsafe {
-                buf.set_len(inode.size());
-            }
-            let size = inode.read(&mut buf[..]);
-            assert!(size.is_ok());
-            let size = size.unwrap();
-            assert_eq!(size, inode.size());
-            unsafe {
-                buf.set_len(size);
+    #[test]
+    fn read_only() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        fs.inner().set_read_only(true);
+        let before = fs.inner().volume.clone();
+
+        let perm = Permissions::U_READ;
+        match fs.set_permissions(b"/home/funky/README.md", perm) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.set_owner(b"/home/funky/README.md", 1, 1) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match readme.write_at(0, b"hello") {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.create_file(b"/home/new", perm) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result.map(|inode| inode.num)),
+        }
+        match fs.unlink(b"/home/funky/README.md") {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(fs.inner().volume == before);
+
+        fs.inner().set_read_only(false);
+        fs.set_permissions(b"/home/funky/README.md", perm).unwrap();
+    }
+
+    #[test]
+    fn atime_policies() {
+        let volume = Metered::new(std::fs::read("ext2.img").unwrap());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let path = b"/home/funky/README.md";
+        let readme = fs.lookup(path).unwrap();
+        // README.md was last read an hour after it was last changed
+        let atime = 0x5aafeb4d;
+        let read = |expected_atime: u32| {
+            let writes = fs.inner().volume.stats().writes;
+            let mut buf = [0; 8];
+            assert_eq!(readme.read_at(0, &mut buf).unwrap(), 8);
+            let after = fs.lookup(path).unwrap();
+            assert_eq!({ after.inner.atime }, expected_atime);
+            fs.inner().volume.stats().writes - writes
+        };
+
+        // reads write nothing by default
+        fs.inner().set_clock(|| 0x5aafeb4d + 3600);
+        assert_eq!(fs.inner().atime_policy(), AtimePolicy::Never);
+        assert_eq!(read(atime), 0);
+
+        // relatime leaves an atime that's newer than the mtime and ctime
+        // alone for a day
+        fs.inner().set_atime_policy(AtimePolicy::Relatime);
+        assert_eq!(read(atime), 0);
+        fs.inner().set_clock(|| 0x5aafeb4d + 86400);
+        assert!(read(0x5aafeb4d + 86400) > 0);
+        fs.inner().set_clock(|| 0x5aafeb4d + 86400 + 60);
+        assert_eq!(read(0x5aafeb4d + 86400), 0);
+        // but not past a change
+        fs.set_times(path, None, Some(0x5aafeb4d + 86400 + 30)).unwrap();
+        assert!(read(0x5aafeb4d + 86400 + 60) > 0);
+
+        // strict updates come with every read at a new time
+        fs.inner().set_atime_policy(AtimePolicy::Always);
+        fs.inner().set_clock(|| 0x5aafeb4d + 86400 + 120);
+        assert!(read(0x5aafeb4d + 86400 + 120) > 0);
+        fs.inner().set_clock(|| 0x5aafeb4d + 86400 + 180);
+        assert!(read(0x5aafeb4d + 86400 + 180) > 0);
+
+        // the flag turns them off whatever the policy, as does mounting
+        // read-only
+        fs.set_flags(path, Flags::DONT_ATIME).unwrap();
+        fs.inner().set_clock(|| 0x5aafeb4d + 86400 * 2);
+        assert_eq!(read(0x5aafeb4d + 86400 + 180), 0);
+        fs.set_flags(path, Flags::empty()).unwrap();
+        fs.inner().set_read_only(true);
+        assert_eq!(read(0x5aafeb4d + 86400 + 180), 0);
+        fs.inner().set_read_only(false);
+        assert!(read(0x5aafeb4d + 86400 * 2) > 0);
+    }
+
+    #[test]
+    fn mount_options() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut options = MountOptions::new();
+        options.read_only(true);
+        let fs = Synced::<Ext2<Size512, _>>::new_with_options(
+            volume.clone(),
+            &options,
+        )
+        .unwrap();
+        let path = b"/home/funky/README.md";
+        match fs.set_permissions(path, Permissions::U_READ) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        // the lowest layers refuse too, should a check above them be missed
+        let readme = fs.lookup(path).unwrap();
+        match fs.inner().write_inode(14, &readme.inner) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.inner().commit(vec![0; 4], Address::from(4096_usize)) {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        match fs.inner().write_superblock() {
+            Err(Error::ReadOnlyFilesystem) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(fs.inner().volume == volume);
+
+        let mut options = MountOptions::new();
+        options.clock(|| 1234).max_symlinks(1);
+        let fs =
+            Synced::<Ext2<Size512, _>>::new_with_options(volume, &options)
+                .unwrap();
+        assert!(!fs.inner().is_read_only());
+        fs.set_permissions(path, Permissions::U_READ).unwrap();
+        assert_eq!({ fs.lookup(path).unwrap().inner.ctime }, 1234);
+        symlink(&fs, b"/one", b"home/funky/README.md");
+        symlink(&fs, b"/two", b"one");
+        fs.lookup(b"/one").unwrap();
+        match fs.lookup(b"/two") {
+            Err(Error::TooManySymlinks { .. }) => (),
+            result => panic!("unexpected {:?}", result.map(|inode| inode.num)),
+        }
+    }
+
+    #[test]
+    fn inode_cache() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o755);
+        let prefix = "/home/funky/a/b/c";
+        for dir in &["/home/funky/a", "/home/funky/a/b", prefix] {
+            fs.mkdir(dir, perm).unwrap();
+        }
+        for i in 0..100 {
+            fs.create_file(format!("{}/{}", prefix, i), perm).unwrap();
+        }
+        let volume = fs.inner().volume.clone();
+
+        let reads = |capacity| {
+            let mut options = MountOptions::new();
+            options.inode_cache(capacity);
+            let fs = Synced::<Ext2<Size512, _>>::new_with_options(
+                Metered::new(volume.clone()),
+                &options,
+            )
+            .unwrap();
+            for i in 0..100 {
+                fs.lookup(format!("{}/{}", prefix, i)).unwrap();
             }
+            let reads = fs.inner().volume.stats().reads;
+            reads
+        };
+        // the root and the five directories under it are read once rather
+        // than for every path, and the files are read once either way
+        assert_eq!(reads(0) - reads(DEFAULT_INODE_CACHE), 99 * 6);
+        // with no more room than the path takes, only what's been looked
+        // up since the clock last passed by is kept
+        assert!(reads(7) < reads(0));
+
+        // writing an inode drops the copy in the cache
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut raw = fs.inner().read_inode(14).unwrap();
+        raw.uid = 7;
+        fs.inner().write_inode(14, &raw).unwrap();
+        assert_eq!({ fs.inner().read_inode(14).unwrap().uid }, 7);
+        assert_eq!(fs.lookup("/home/funky/README.md").unwrap().uid(), 7);
+    }
 
-            for (i, &x) in buf.iter().enumerate() {
-                if i & 1 == 0 {
-                    assert_eq!(x, b'u', "{}", i);
-                } else {
-                    assert_eq!(x, b'\n', "{}", i);
-                }
+    #[test]
+    fn dentry_cache() {
+        let volume = match mkfs(8 << 20) {
+            Some(volume) => volume,
+            None => return,
+        };
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o755);
+        let mut paths = Vec::new();
+        for dir in 0..10 {
+            fs.mkdir(format!("/{}", dir), perm).unwrap();
+            for file in 0..100 {
+                let path = format!("/{}/{}", dir, file);
+                fs.create_file(&path, perm).unwrap();
+                paths.push(path);
+            }
+        }
+        let volume = fs.inner().volume.clone();
+
+        let mut options = MountOptions::new();
+        options.inode_cache(2048).dentry_cache(2048);
+        let fs = Synced::<Ext2<Size512, _>>::new_with_options(
+            Metered::new(volume),
+            &options,
+        )
+        .unwrap();
+        let stat_all = || {
+            for path in &paths {
+                fs.lookup(path).unwrap().metadata();
             }
+            assert!(fs.lookup("/0/missing").is_err());
+        };
+        stat_all();
+        assert!(fs.inner().volume.stats().reads > 0);
+        fs.inner().volume.reset();
+        // the second time, neither directories nor inodes are read
+        stat_all();
+        assert_eq!(fs.inner().volume.stats().reads, 0);
+
+        // names created, removed or renamed since aren't taken from the cache
+        fs.create_file("/0/missing", perm).unwrap();
+        assert!(fs.lookup("/0/missing").is_ok());
+        fs.unlink("/0/0").unwrap();
+        assert!(fs.lookup("/0/0").is_err());
+        fs.rename("/0/1", "/1/moved").unwrap();
+        assert!(fs.lookup("/0/1").is_err());
+        let moved = fs.lookup("/1/moved").unwrap().metadata().ino();
+        // over an entry in the cache, too
+        fs.rename("/1/moved", "/1/2").unwrap();
+        assert_eq!(fs.lookup("/1/2").unwrap().metadata().ino(), moved);
+        // and a directory that's gone takes its entries along
+        for file in 2..100 {
+            fs.unlink(format!("/0/{}", file)).unwrap();
         }
+        fs.unlink("/0/missing").unwrap();
+        fs.rmdir("/0").unwrap();
+        fs.mkdir("/0", perm).unwrap();
+        assert!(fs.lookup("/0/5").is_err());
+        assert!(fsck(fs.inner().volume.inner()));
     }
 
     #[test]
-    fn walkdir() {
-        use std::str;
+    fn concurrent_readers() {
+        fn shareable<T: Send + Sync>() {}
+        shareable::<Synced<Ext2<Size512, Vec<u8>>>>();
+
+        let volume = std::fs::read("ext2.img").unwrap();
+        let mut options = MountOptions::new();
+        options.dentry_cache(64);
+        let fs = Synced::<Ext2<Size512, _>>::new_with_options(volume, &options)
+            .unwrap();
+        let read = |fs: &Synced<Ext2<Size512, Vec<u8>>>, path: &str| {
+            let mut contents = Vec::new();
+            fs.lookup(path).unwrap().read_to_end(&mut contents).unwrap();
+            contents
+        };
+        let (readme, unl) = (
+            read(&fs, "/home/funky/README.md"),
+            read(&fs, "/home/funky/unl"),
+        );
 
-        fn walk<S: SectorSize, V: Volume<u8, S>>(
-            fs: &Synced<Ext2<S, V>>,
-            inode: Inode<S, V>,
-            name: String,
-        ) {
-            if let Some(dir) = inode.directory() {
-                for entry in dir {
-                    assert!(entry.is_ok());
-                    let entry = entry.unwrap();
-                    let entry_name = str::from_utf8(&entry.name).unwrap_or("?");
-                    println!("{}/{} => {}", name, entry_name, entry.inode,);
-                    if entry_name != "." && entry_name != ".." {
-                        walk(
-                            fs,
-                            fs.inode_nth(entry.inode).unwrap(),
-                            format!("{}/{}", name, entry_name),
-                        );
-                    }
+        let perm = Permissions::from_bits_truncate(0o644);
+        let writer = {
+            let fs = fs.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    fs.create_file(format!("/home/funky/new{}", i), perm)
+                        .unwrap();
                 }
-            }
+            })
+        };
+        let readers = (0..8)
+            .map(|_| {
+                let fs = fs.clone();
+                let (readme, unl) = (readme.clone(), unl.clone());
+                std::thread::spawn(move || {
+                    assert!(read(&fs, "/home/funky/unl") == unl);
+                    for _ in 0..100 {
+                        assert_eq!(read(&fs, "/home/funky/README.md"), readme);
+                        let dir = fs.lookup("/home/funky").unwrap();
+                        assert!(dir.directory().unwrap().count() >= 4);
+                        assert!(fs.lookup("/home/funky/missing").is_err());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in readers {
+            thread.join().unwrap();
+        }
+        writer.join().unwrap();
+        for i in 0..50 {
+            assert!(fs.lookup(format!("/home/funky/new{}", i)).is_ok());
         }
+        assert!(fsck(&fs.inner().volume));
+
+        // lookups and reads go through while another reader holds the
+        // lock, which they would wait out forever if they locked it for
+        // writing; atime updates included, once one has been written
+        let mut options = MountOptions::new();
+        options.atime(AtimePolicy::Relatime);
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new_with_options(volume, &options)
+            .unwrap();
+        read(&fs, "/home/funky/README.md");
+        let guard = fs.read();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let reader = {
+            let fs = fs.clone();
+            std::thread::spawn(move || {
+                let contents = read(&fs, "/home/funky/README.md");
+                let entries = fs.lookup("/home/funky").unwrap().directory();
+                sender.send((contents, entries.unwrap().count())).unwrap();
+            })
+        };
+        let timeout = std::time::Duration::from_secs(30);
+        let (contents, count) = receiver
+            .recv_timeout(timeout)
+            .expect("a reader waited for the other");
+        assert_eq!(contents, readme);
+        assert!(count >= 4);
+        drop(guard);
+        reader.join().unwrap();
+    }
 
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+    /// Fails unless `result` is `Error::OperationNotPermitted`
+    fn not_permitted<T>(result: Result<T, Error>) {
+        match result {
+            Err(Error::OperationNotPermitted { .. }) => (),
+            Err(err) => panic!("unexpected {:?}", err),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
 
-        let root = fs.root_inode();
-        walk(&fs, root, String::new());
+    #[test]
+    fn immutable() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o644);
+        let readme = b"/home/funky/README.md";
+        fs.inner().set_clock(|| 0x5b000000);
+        fs.set_flags(readme, Flags::IMMUTABLE).unwrap();
+        assert_eq!({ fs.lookup(readme).unwrap().inner.ctime }, 0x5b000000);
+        fs.create_file(b"/home/other", perm).unwrap();
+        let before = fs.inner().volume.clone();
+
+        let mut file = fs.lookup(readme).unwrap();
+        not_permitted(file.write_at(0, b"hello"));
+        not_permitted(file.write_at(31, b"hello"));
+        not_permitted(file.set_len(0));
+        not_permitted(file.set_len(100));
+        not_permitted(file.preallocate(4096, true));
+        not_permitted(fs.set_permissions(readme, perm));
+        not_permitted(fs.set_owner(readme, 1, 1));
+        not_permitted(fs.set_times(readme, Some(1), Some(1)));
+        not_permitted(fs.unlink(readme));
+        not_permitted(fs.shred(readme));
+        not_permitted(fs.rename(readme, b"/home/moved"));
+        not_permitted(fs.rename(b"/home/other", readme));
+        not_permitted(fs.open(readme, OpenOptions::new().write(true)));
+        not_permitted(fs.open(readme, OpenOptions::new().append(true)));
+        // reading is still fine
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents.len(), 31);
+        fs.open(readme, OpenOptions::new().read(true)).unwrap();
+        assert!(fs.inner().volume == before);
+
+        // an immutable directory's entries can't change either
+        let funky = b"/home/funky";
+        fs.set_flags(funky, Flags::IMMUTABLE).unwrap();
+        let before_dir = fs.inner().volume.clone();
+        fs.set_flags(readme, Flags::empty()).unwrap();
+        not_permitted(fs.create_file(b"/home/funky/new", perm));
+        not_permitted(fs.mkdir(b"/home/funky/new", perm));
+        not_permitted(fs.unlink(readme));
+        not_permitted(fs.rename(readme, b"/home/moved"));
+        not_permitted(fs.rename(b"/home/other", b"/home/funky/other"));
+        not_permitted(fs.rmdir(funky));
+        not_permitted(fs.rename(funky, b"/home/moved"));
+        let mut create = OpenOptions::new();
+        create.write(true).create(true);
+        not_permitted(fs.open(b"/home/funky/new", &create));
+        fs.set_flags(readme, Flags::IMMUTABLE).unwrap();
+        assert!(fs.inner().volume == before_dir);
+
+        // until the flag is taken off again
+        fs.set_flags(funky, Flags::empty()).unwrap();
+        fs.set_flags(readme, Flags::empty()).unwrap();
+        fs.lookup(readme).unwrap().write_at(0, b"hello").unwrap();
+        fs.unlink(readme).unwrap();
+        assert!(fsck(&fs.inner().volume));
     }
 
     #[test]
-    fn find() {
-        use std::str;
-        let file = RefCell::new(File::open("ext2.img").unwrap());
-        let fs = Synced::<Ext2<Size512, _>>::new(file).unwrap();
+    fn append_only() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o644);
+        let readme = b"/home/funky/README.md";
+        fs.create_file(b"/home/other", perm).unwrap();
+        fs.set_flags(readme, Flags::APPEND_ONLY).unwrap();
+
+        // writes at the end of the file are all that's allowed
+        let mut file = fs.lookup(readme).unwrap();
+        not_permitted(file.write_at(0, b"hello"));
+        not_permitted(file.write_at(40, b"hello"));
+        assert_eq!(file.write_at(31, b"hello").unwrap(), 5);
+        assert_eq!(file.size64(), 36);
+        not_permitted(file.set_len(0));
+        not_permitted(file.set_len(100));
+        not_permitted(fs.set_permissions(readme, perm));
+        not_permitted(fs.set_owner(readme, 1, 1));
+        not_permitted(fs.set_times(readme, Some(1), Some(1)));
+        not_permitted(fs.unlink(readme));
+        not_permitted(fs.shred(readme));
+        not_permitted(fs.rename(readme, b"/home/moved"));
+        not_permitted(fs.rename(b"/home/other", readme));
+        not_permitted(fs.open(readme, OpenOptions::new().write(true)));
+        let mut truncate = OpenOptions::new();
+        truncate.append(true).truncate(true);
+        not_permitted(fs.open(readme, &truncate));
+        let mut appended = fs.open(readme, OpenOptions::new().append(true));
+        appended.as_mut().unwrap().write(b"!").unwrap();
+        assert_eq!(fs.lookup(readme).unwrap().size64(), 37);
+
+        // an append-only directory takes new entries, but keeps its old ones
+        let funky = b"/home/funky";
+        fs.set_flags(readme, Flags::empty()).unwrap();
+        fs.set_flags(funky, Flags::APPEND_ONLY).unwrap();
+        fs.create_file(b"/home/funky/new", perm).unwrap();
+        fs.mkdir(b"/home/funky/dir", perm).unwrap();
+        fs.rename(b"/home/other", b"/home/funky/other").unwrap();
+        not_permitted(fs.unlink(readme));
+        not_permitted(fs.rmdir(b"/home/funky/dir"));
+        not_permitted(fs.rename(readme, b"/home/moved"));
+        not_permitted(fs.rename(readme, b"/home/funky/moved"));
+        not_permitted(fs.rename(b"/home/funky/new", readme));
+        fs.create_file(b"/home/third", perm).unwrap();
+        not_permitted(fs.rename(b"/home/third", b"/home/funky/new"));
+        assert!(fsck(&fs.inner().volume));
+
+        fs.set_flags(funky, Flags::empty()).unwrap();
+        fs.unlink(readme).unwrap();
+        fs.rmdir(b"/home/funky/dir").unwrap();
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        let found = fs.open(b"/home/funky/README.md", &OpenOptions::new());
+    #[test]
+    fn set_times() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let path = b"/home/funky/README.md";
+        let original = fs.lookup(path).unwrap().inner;
+
+        fs.inner().set_clock(|| 3000);
+        fs.set_times(path, Some(1000), Some(2000)).unwrap();
+        let readme = fs.lookup(path).unwrap();
+        assert_eq!({ readme.inner.atime }, 1000);
+        assert_eq!({ readme.inner.mtime }, 2000);
+        assert_eq!({ readme.inner.ctime }, 3000);
+
+        fs.inner().set_clock(|| 4000);
+        fs.set_times(path, None, Some(500)).unwrap();
+        let readme = fs.lookup(path).unwrap();
+        assert_eq!({ readme.inner.atime }, 1000);
+        assert_eq!({ readme.inner.mtime }, 500);
+        assert_eq!({ readme.inner.ctime }, 4000);
+
+        // neither time given still counts as a change
+        fs.inner().set_clock(|| 5000);
+        fs.set_times(path, None, None).unwrap();
+        let readme = fs.lookup(path).unwrap();
+        assert_eq!({ readme.inner.atime }, 1000);
+        assert_eq!({ readme.inner.mtime }, 500);
+        assert_eq!({ readme.inner.ctime }, 5000);
+        assert_eq!({ readme.inner.size_low }, { original.size_low });
+        assert!(fsck(&fs.inner().volume));
+    }
 
-        assert!(found.is_ok());
-        let inode = found.unwrap();
-        let mut vec = Vec::new();
-        assert!(inode.read_to_end(&mut vec).is_ok());
-        println!("{}", str::from_utf8(&vec).unwrap());
+    #[test]
+    fn reserved_blocks() {
+        let mut volume = vec![0_u8; 4 << 20];
+        Builder::new().build::<Size512, _>(&mut volume).unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let reserved = fs.inner().reserved_block_count();
+        assert_eq!(reserved, 4096 * 5 / 100);
+
+        let mut file = fs.create_file(b"/file", Permissions::U_READ).unwrap();
+        file.set_owner(1000, 1000).unwrap();
+        let data = vec![b'x'; 4 << 20];
+        let written = file.write_at(0, &data).unwrap();
+        assert!(written < data.len());
+        assert_eq!(fs.inner().free_block_count(), reserved);
+        match file.write_at(written as u64, &data) {
+            Err(Error::OutOfSpace) => (),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(fsck(&fs.inner().volume));
+
+        // the superblock may name another user allowed into the reserve
+        fs.inner().superblock.inner.block_uid = 1000;
+        let more = file.write_at(written as u64, &data[..10240]).unwrap();
+        assert_eq!(more, 10240);
+        fs.inner().superblock.inner.block_uid = 0;
+
+        // which root always is
+        let reserved = fs.inner().free_block_count();
+        file.set_owner(0, 0).unwrap();
+        let more = file.write_at(written as u64 + 10240, &data).unwrap();
+        assert!(more >= (reserved - 1) * 1024);
+        assert_eq!(fs.inner().free_block_count(), 0);
+        assert!(fsck(&fs.inner().volume));
+
+        fs.inner().set_reserved_block_count(0).unwrap();
+        let volume = fs.inner().volume.clone();
+        let fs = Ext2::<Size512, _>::new(volume).unwrap();
+        assert_eq!(fs.reserved_block_count(), 0);
+    }
+
+    #[test]
+    fn large_inodes() {
+        // made with `mke2fs -I 256`, the extra bytes holding crtime
+        let volume = std::fs::read("fixtures/inode256.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert_eq!(fs.inner().inode_size(), 256);
+
+        let mut data = Vec::new();
+        let mut hello = fs.lookup(b"/docs/hello.txt").unwrap();
+        hello.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"hello from a 256 byte inode\n");
+
+        // writing an inode leaves its extra bytes alone
+        let offset = fs.inner().inode_address(hello.num).unwrap();
+        let index = offset.into_index() as usize;
+        let extra = fs.inner().volume[index + 128..index + 256].to_vec();
+        assert_ne!(extra, vec![0; 128]);
+        hello.set_permissions(Permissions::U_READ).unwrap();
+        assert_eq!(&fs.inner().volume[index + 128..index + 256], &extra[..]);
+
+        let mut file =
+            fs.create_file(b"/docs/new", Permissions::U_READ).unwrap();
+        file.write_at(0, b"next to it").unwrap();
+        let num = fs.lookup(b"/docs/new").unwrap().num;
+        let raw = fs.inner().read_inode(num).unwrap();
+        assert_eq!({ raw.size_low }, 10);
+        assert!(fsck(&fs.inner().volume));
+    }
+
+    #[test]
+    fn fuzz_mount() {
+        // walks whatever a mount turns up, reading at most a block or so of
+        // every file along the way
+        fn walk(fs: &Synced<Ext2<Size512, Vec<u8>>>, path: &[u8], depth: u8) {
+            let dir = match fs.lookup(path) {
+                Ok(dir) => dir,
+                Err(_) => return,
+            };
+            let mut buf = [0; 1536];
+            let _ = dir.read(&mut buf);
+            let _ = dir.read_link();
+            let entries = match dir.directory() {
+                Some(entries) => entries,
+                None => return,
+            };
+            for entry in entries.take(16).filter_map(Result::ok) {
+                let _ = entry.metadata();
+                if depth < 3 && entry.name != b"." && entry.name != b".." {
+                    let mut path = path.to_vec();
+                    if path.len() > 1 {
+                        path.push(b'/');
+                    }
+                    path.extend_from_slice(&entry.name);
+                    walk(fs, &path, depth + 1);
+                }
+            }
+        }
+
+        let image = std::fs::read("ext2.img").unwrap();
+        // the superblock and descriptors, the bitmaps, the first inodes, and
+        // the directory blocks of /, /home and /home/funky, besides anywhere
+        let regions = [
+            (1024, 2048),
+            (18 * 1024, 2048),
+            (20 * 1024, 2048),
+            (148 * 1024, 1024),
+            (3585 * 1024, 2048),
+            (0, image.len()),
+        ];
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let runs = if cfg!(miri) { 4 } else { 1000 };
+        for _ in 0..runs {
+            let mut volume = image.clone();
+            for _ in 0..1 + random() % 32 {
+                let (start, len) = regions[random() as usize % regions.len()];
+                let index = start + random() as usize % len;
+                // small values make for plausible counts and pointers
+                volume[index] = match random() % 4 {
+                    0 => 0,
+                    1 => 0xff,
+                    2 => random() as u8 % 16,
+                    _ => random() as u8,
+                };
+            }
+            // every failure has to be an `Err`
+            let fs = match Synced::<Ext2<Size512, _>>::new_forced(volume) {
+                Ok(fs) => fs,
+                Err(_) => continue,
+            };
+            let _ = fs.inner().stats();
+            let _ = fs.inner().counted_stats();
+            for inode in fs.inodes().take(16) {
+                let _ = inode.read_link();
+            }
+            walk(&fs, b"/", 0);
+            let perm = Permissions::U_READ;
+            if let Ok(mut file) = fs.create_file(b"/home/new", perm) {
+                let _ = file.write_at(0, &[1; 3000]);
+                let _ = file.write_at(300_000, &[1; 3000]);
+                let _ = file.set_len(10);
+            }
+            let _ = fs.mkdir(b"/home/dir", perm);
+            let _ = fs.rename(b"/home/funky/README.md", b"/home/dir/README");
+            let _ = fs.unlink(b"/home/dir/README");
+            let _ = fs.rmdir(b"/home/dir");
+            let _ = fs.rmdir(b"/home/funky");
+        }
+    }
+
+    #[test]
+    fn block_pointer_byte_order() {
+        // pointers are little-endian whatever the host's byte order
+        let mut volume = vec![0_u8; 4096];
+        volume[2048 + 12..2048 + 16].copy_from_slice(&[4, 3, 2, 1]);
+        let pointer = block_index::<Size512, _>(&volume, 2, 3, 10).unwrap();
+        assert_eq!(pointer.unwrap().get(), 0x0102_0304);
+        assert!(block_index::<Size512, _>(&volume, 2, 2, 10)
+            .unwrap()
+            .is_none());
+        assert!(block_index::<Size512, _>(&volume, 4, 0, 10).is_err());
     }
 }
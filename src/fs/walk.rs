@@ -0,0 +1,367 @@
+//! Depth-first walks over directory trees
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::SectorSize;
+use sys::inode::FileType;
+use volume::Volume;
+
+use super::sync::{Directory, DirectoryEntry, Synced};
+use super::Ext2;
+
+/// Walks the tree under the directory at `root`, see `Walk`
+pub fn walk<S: SectorSize, V: Volume<u8, S>, P: AsRef<[u8]>>(
+    fs: &Synced<Ext2<S, V>>,
+    root: P,
+) -> Walk<S, V> {
+    Walk {
+        fs: fs.clone(),
+        root: Some(root.as_ref().to_vec()),
+        stack: Vec::new(),
+        pending: None,
+        follow_links: false,
+        max_depth: !0,
+    }
+}
+
+/// A depth-first walk over a directory tree, returning every entry under
+/// the root directory along with its path: the root's path and the names
+/// leading to the entry, joined by slashes. A directory comes right before
+/// the entries in it; `.` and `..` and the root itself aren't returned.
+///
+/// Only the directories on the way to the current entry are kept open, so
+/// the memory a walk takes grows with the depth of the tree rather than its
+/// size. They're also what directory cycles are told apart by: a directory
+/// turning out to be one of those it was reached through is returned as
+/// `Error::DirectoryCycle` instead of being walked again. Corruption can
+/// lead to such a cycle, as can symlinks when they're followed.
+///
+/// An error reading a directory is returned in place of the entries it
+/// keeps from being read, and the walk carries on after it.
+#[derive(Debug)]
+pub struct Walk<S: SectorSize, V: Volume<u8, S>> {
+    fs: Synced<Ext2<S, V>>,
+    /// The path of the root, until the walk starts
+    root: Option<Vec<u8>>,
+    stack: Vec<Level<S, V>>,
+    /// The directory returned last, to be walked on the next call unless
+    /// `prune` is called, or the error finding out what it is
+    pending: Option<Result<(Vec<u8>, u32)>>,
+    follow_links: bool,
+    max_depth: usize,
+}
+
+/// A directory being walked
+#[derive(Debug)]
+struct Level<S: SectorSize, V: Volume<u8, S>> {
+    path: Vec<u8>,
+    ino: u32,
+    entries: Directory<S, V>,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Walk<S, V> {
+    /// Whether to walk the directories symlinks point at, which is off by
+    /// default. A symlink that can't be resolved is returned but not
+    /// walked.
+    pub fn follow_links(mut self, follow: bool) -> Walk<S, V> {
+        self.follow_links = follow;
+        self
+    }
+
+    /// Returns entries at most `depth` levels below the root, the entries
+    /// of the root itself being one level below it
+    pub fn max_depth(mut self, depth: usize) -> Walk<S, V> {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Keeps the walk from descending into the directory returned last, as
+    /// `find -prune` does. Has no effect if that entry wasn't a directory.
+    pub fn prune(&mut self) {
+        self.pending = None;
+    }
+
+    fn enter_root(&mut self, root: Vec<u8>) -> Result<()> {
+        let inode = self.fs.lookup(&root)?;
+        match inode.directory() {
+            Some(entries) if self.max_depth > 0 => {
+                self.stack.push(Level {
+                    path: root,
+                    ino: inode.ino(),
+                    entries,
+                });
+                Ok(())
+            }
+            Some(_) => Ok(()),
+            None => Err(Error::NotADirectory {
+                inode: inode.ino(),
+                name: String::from_utf8_lossy(&root).into_owned(),
+            }),
+        }
+    }
+
+    fn descend(&mut self, path: Vec<u8>, ino: u32) -> Result<()> {
+        let name = || String::from_utf8_lossy(&path).into_owned();
+        if self.stack.iter().any(|level| level.ino == ino) {
+            return Err(Error::DirectoryCycle {
+                inode: ino,
+                name: name(),
+            });
+        }
+        let inode = self
+            .fs
+            .inode_nth(ino as usize)
+            .ok_or(Error::InodeNotFound { inode: ino })?;
+        let entries =
+            inode.directory().ok_or_else(|| Error::NotADirectory {
+                inode: ino,
+                name: name(),
+            })?;
+        self.stack.push(Level { path, ino, entries });
+        Ok(())
+    }
+
+    /// The directory to walk for `entry`, found at `path`, if it's one or
+    /// a symlink to one that's to be followed
+    fn directory(
+        &self,
+        path: &[u8],
+        entry: &DirectoryEntry<S, V>,
+    ) -> Result<Option<u32>> {
+        let file_type = match entry.file_type {
+            Some(file_type) => file_type,
+            None => entry.inode()?.file_type(),
+        };
+        match file_type {
+            FileType::Directory => Ok(Some(entry.ino)),
+            FileType::Symlink if self.follow_links => {
+                match self.fs.lookup(path) {
+                    Ok(ref inode) if inode.is_dir() => Ok(Some(inode.ino())),
+                    _ => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Iterator for Walk<S, V> {
+    type Item = Result<(Vec<u8>, DirectoryEntry<S, V>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            if let Err(err) = self.enter_root(root) {
+                return Some(Err(err));
+            }
+        }
+        match self.pending.take() {
+            Some(Ok((path, ino))) => {
+                if let Err(err) = self.descend(path, ino) {
+                    return Some(Err(err));
+                }
+            }
+            Some(Err(err)) => return Some(Err(err)),
+            None => (),
+        }
+
+        loop {
+            let depth = self.stack.len();
+            let (entry, path) = {
+                let level = self.stack.last_mut()?;
+                let entry = match level.entries.next() {
+                    Some(Ok(entry)) => entry,
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    }
+                };
+                if entry.name == b"." || entry.name == b".." {
+                    continue;
+                }
+                let mut path = level.path.clone();
+                if path.last() != Some(&b'/') {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(&entry.name);
+                (entry, path)
+            };
+
+            if depth < self.max_depth {
+                self.pending = match self.directory(&path, &entry) {
+                    Ok(Some(ino)) => Some(Ok((path.clone(), ino))),
+                    Ok(None) => None,
+                    Err(err) => Some(Err(err)),
+                };
+            }
+            return Some(Ok((path, entry)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use error::Error;
+    use fs::sync::tests::symlink;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::Size512;
+    use sys::inode::{self, Permissions};
+
+    use super::{walk, Walk};
+
+    type TestWalk = Walk<Size512, Vec<u8>>;
+
+    fn paths(walk: TestWalk) -> Vec<String> {
+        walk.map(|item| {
+            let (path, _) = item.unwrap();
+            str::from_utf8(&path).unwrap().to_owned()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn depth_first() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert_eq!(
+            paths(walk(&fs, b"/")),
+            vec![
+                "/lost+found",
+                "/home",
+                "/home/funky",
+                "/home/funky/README.md",
+                "/home/funky/unl",
+                "/home/funky/u",
+            ]
+        );
+        assert_eq!(
+            paths(walk(&fs, b"/home/")),
+            vec![
+                "/home/funky",
+                "/home/funky/README.md",
+                "/home/funky/unl",
+                "/home/funky/u",
+            ]
+        );
+        assert_eq!(
+            paths(walk(&fs, b"/").max_depth(2)),
+            vec!["/lost+found", "/home", "/home/funky"]
+        );
+        assert!(paths(walk(&fs, b"/").max_depth(0)).is_empty());
+
+        let (path, entry) = walk(&fs, b"/home").last().unwrap().unwrap();
+        assert_eq!(path, b"/home/funky/u");
+        assert_eq!(entry.name, b"u");
+        assert_eq!(entry.ino, 18);
+
+        match walk(&fs, b"/home/funky/README.md").next() {
+            Some(Err(Error::NotADirectory { inode: 14, .. })) => (),
+            other => panic!("{:?}", other.map(|item| item.map(|_| ()))),
+        }
+        match walk(&fs, b"/missing").next() {
+            Some(Err(Error::NotFound { .. })) => (),
+            other => panic!("{:?}", other.map(|item| item.map(|_| ()))),
+        }
+    }
+
+    #[test]
+    fn prune() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut walk = walk(&fs, b"/");
+        let mut paths = Vec::new();
+        while let Some(item) = walk.next() {
+            let (path, _) = item.unwrap();
+            if path == b"/home/funky" {
+                walk.prune();
+            }
+            paths.push(path);
+        }
+        assert_eq!(
+            paths,
+            vec![
+                b"/lost+found".to_vec(),
+                b"/home".to_vec(),
+                b"/home/funky".to_vec(),
+            ]
+        );
+    }
+
+    #[test]
+    fn symlinks() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        symlink(&fs, b"/home/link", b"funky");
+        symlink(&fs, b"/home/dangling", b"nowhere");
+        let all = paths(walk(&fs, b"/home"));
+        assert!(all.contains(&"/home/link".to_owned()));
+        assert!(all.contains(&"/home/dangling".to_owned()));
+        assert!(!all.iter().any(|path| path.starts_with("/home/link/")));
+
+        let all = paths(walk(&fs, b"/home").follow_links(true));
+        assert!(all.contains(&"/home/link/README.md".to_owned()));
+        assert!(all.contains(&"/home/funky/README.md".to_owned()));
+
+        // a link back up is a cycle once followed
+        symlink(&fs, b"/home/funky/up", b"..");
+        let mut cycles = walk(&fs, b"/home")
+            .follow_links(true)
+            .filter_map(|item| match item {
+                Err(Error::DirectoryCycle { inode, name }) => {
+                    Some((inode, name))
+                }
+                Err(err) => panic!("{}", err),
+                Ok(_) => None,
+            })
+            .collect::<Vec<_>>();
+        cycles.sort();
+        assert_eq!(
+            cycles,
+            vec![
+                (12, "/home/funky/up".to_owned()),
+                (12, "/home/link/up".to_owned()),
+            ]
+        );
+        assert_eq!(paths(walk(&fs, b"/home")).len(), 7);
+    }
+
+    #[test]
+    fn corrupt_cycle() {
+        let volume = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let perm = Permissions::from_bits_truncate(0o755);
+        fs.mkdir(b"/a", perm).unwrap();
+        fs.mkdir(b"/a/b", perm).unwrap();
+        // an entry deep down pointing back at the top
+        let a = fs.lookup(b"/a").unwrap().ino();
+        let mut b = fs.lookup(b"/a/b").unwrap().directory().unwrap();
+        b.add_entry(b"loop", a, inode::DIRECTORY).unwrap();
+        fs.mkdir(b"/c", perm).unwrap();
+
+        let items = walk(&fs, b"/")
+            .map(|item| item.map(|(path, _)| path))
+            .collect::<Vec<_>>();
+        let errors = items.iter().filter(|item| item.is_err()).count();
+        assert_eq!(errors, 1);
+        match items.iter().position(|item| item.is_err()) {
+            Some(index) => {
+                assert_eq!(items[index - 1].as_ref().unwrap(), b"/a/b/loop");
+                match items[index] {
+                    Err(Error::DirectoryCycle { inode, ref name }) => {
+                        assert_eq!(inode, a);
+                        assert_eq!(name, "/a/b/loop");
+                    }
+                    ref other => panic!("{:?}", other),
+                }
+            }
+            None => unreachable!(),
+        }
+        // the walk goes on past the cycle
+        assert_eq!(items.last().unwrap().as_ref().unwrap(), b"/c");
+    }
+}
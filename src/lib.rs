@@ -1,19 +1,20 @@
-#![feature(const_fn)]
-#![feature(step_trait)]
-#![feature(step_trait_ext)]
-#![cfg_attr(all(not(test), feature = "no_std"), no_std)]
+#![cfg_attr(all(not(test), not(feature = "std"), feature = "no_std"), no_std)]
 
 #[macro_use]
 extern crate alloc;
 #[macro_use]
 extern crate bitflags;
 extern crate genfs;
+#[cfg(any(test, feature = "embedded-io"))]
+extern crate embedded_io;
 extern crate spin;
 
-#[cfg(any(test, not(feature = "no_std")))]
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
 extern crate core;
 
 pub mod error;
+#[cfg(any(test, feature = "ffi"))]
+pub mod ffi;
 pub mod fs;
 pub mod sector;
 pub mod sys;
@@ -32,4 +33,90 @@ mod tests {
         assert_eq!(size_of::<BlockGroupDescriptor>(), 32);
         assert_eq!(size_of::<Inode>(), 128);
     }
+
+    // dumped from ext2.img: the superblock, the descriptor of group 0 and
+    // inode 14, /home/funky/README.md
+    const SUPERBLOCK: &[u8; 1024] =
+        include_bytes!("../fixtures/superblock.bin");
+    const BLOCK_GROUP: &[u8; 32] =
+        include_bytes!("../fixtures/block_group.bin");
+    const INODE: &[u8; 128] = include_bytes!("../fixtures/inode.bin");
+
+    #[test]
+    fn superblock_bytes() {
+        let superblock = Superblock::from_bytes(SUPERBLOCK);
+        assert_eq!(superblock.magic(), EXT2_MAGIC);
+        assert_eq!(superblock.inodes_count(), 1024);
+        assert_eq!(superblock.blocks_count(), 4096);
+        assert_eq!(superblock.first_data_block(), 1);
+        assert_eq!(superblock.inode_size(), 128);
+        assert_eq!(
+            superblock.features_req(),
+            FeaturesRequired::REQ_DIRECTORY_TYPE
+        );
+        assert_eq!(
+            superblock.features_ronly(),
+            FeaturesROnly::RONLY_SPARSE | FeaturesROnly::RONLY_FILE_SIZE_64
+        );
+        assert_eq!(&superblock.to_bytes()[..], &SUPERBLOCK[..]);
+
+        // bits without a name survive the round trip
+        let mut bytes = *SUPERBLOCK;
+        bytes[96] |= 0x80;
+        assert_eq!(&Superblock::from_bytes(&bytes).to_bytes()[..], &bytes[..]);
+    }
+
+    #[test]
+    fn block_group_bytes() {
+        let descriptor = BlockGroupDescriptor::from_bytes(BLOCK_GROUP);
+        assert_eq!(descriptor.block_usage_addr(), 18);
+        assert_eq!(descriptor.inode_usage_addr(), 19);
+        assert_eq!(descriptor.inode_table_block(), 20);
+        assert_eq!(descriptor.free_blocks_count(), 3388);
+        assert_eq!(descriptor.free_inodes_count(), 1008);
+        assert_eq!(&descriptor.to_bytes(), BLOCK_GROUP);
+    }
+
+    #[test]
+    fn inode_bytes() {
+        let inode = Inode::from_bytes(INODE);
+        assert_eq!(
+            inode.type_perm(),
+            TypePerm::FILE | TypePerm::from_bits_truncate(0o644)
+        );
+        assert_eq!(inode.uid(), 1000);
+        assert_eq!(inode.size_low(), 31);
+        assert_eq!(inode.hard_links(), 1);
+        assert_eq!(&inode.to_bytes()[..], &INODE[..]);
+
+        let mut bytes = *INODE;
+        bytes[32] = 0x01;
+        bytes[34] = 0x80;
+        assert_eq!(&Inode::from_bytes(&bytes).to_bytes()[..], &bytes[..]);
+    }
+
+    #[test]
+    fn unpacked_data() {
+        let superblock = Superblock::from_bytes(SUPERBLOCK);
+        let mut data = SuperblockData::from(superblock);
+        assert_eq!(data.magic, EXT2_MAGIC);
+        assert_eq!(data.fs_id, superblock.uuid().0);
+        assert_eq!(&Superblock::from(data).to_bytes()[..], &SUPERBLOCK[..]);
+        data.mnt_count += 1;
+        assert_eq!(Superblock::from(data).mnt_count(), data.mnt_count);
+
+        let descriptor = BlockGroupDescriptor::from_bytes(BLOCK_GROUP);
+        let data = BlockGroupDescriptorData::from(descriptor);
+        assert_eq!(data.inode_table_block, 20);
+        assert_eq!(&BlockGroupDescriptor::from(data).to_bytes(), BLOCK_GROUP);
+
+        let inode = Inode::from_bytes(INODE);
+        let mut data = InodeData::from(inode);
+        assert_eq!(data.direct_pointer, inode.direct_pointer());
+        assert_eq!(&Inode::from(data).to_bytes()[..], &INODE[..]);
+        // fields of the unpacked copy can be borrowed
+        let pointers = &mut data.direct_pointer;
+        pointers[0] = 0;
+        assert_eq!(Inode::from(data).direct_pointer()[0], 0);
+    }
 }
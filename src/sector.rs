@@ -1,7 +1,6 @@
 use core::fmt::{self, Debug, Display, LowerHex};
-use core::iter::Step;
 use core::marker::PhantomData;
-use core::ops::{Add, Sub};
+use core::ops::{Add, Range, Sub};
 
 pub trait SectorSize: Clone + Copy + PartialEq + PartialOrd + 'static {
     // log_sector_size = log_2(sector_size)
@@ -34,19 +33,31 @@ impl SectorSize for Size4096 {
     const LOG_SIZE: u32 = 12;
 }
 
-/// Address in a physical sector
+/// Address in a physical sector. Every address is also a byte index that
+/// fits in a `u64`, so the sector of one is at most `u64::MAX >> LOG_SIZE`.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
 pub struct Address<S: SectorSize> {
-    sector: u32,
+    sector: u64,
     offset: u32,
     _phantom: PhantomData<S>,
 }
 
 impl<S: SectorSize> Address<S> {
+    /// Creates an address from its parts as they are
     ///
     /// # Safety
-    pub unsafe fn new_unchecked(sector: u32, offset: u32) -> Address<S> {
-        assert!((offset as usize) < S::SIZE, "offset out of sector bounds");
+    ///
+    /// `offset` must be less than the sector size, and `sector` at most
+    /// `u64::MAX >> S::LOG_SIZE`.
+    pub unsafe fn new_unchecked(sector: u64, offset: u32) -> Address<S> {
+        debug_assert!(
+            (offset as usize) < S::SIZE,
+            "offset out of sector bounds"
+        );
+        debug_assert!(
+            sector <= u64::MAX >> S::LOG_SIZE,
+            "sector out of address bounds"
+        );
 
         Address {
             sector,
@@ -55,26 +66,61 @@ impl<S: SectorSize> Address<S> {
         }
     }
 
-    pub fn new(sector: u32, offset: i32) -> Address<S> {
-        let index = ((sector as i64) << S::LOG_SIZE) + offset as i64;
-        let sector = (index >> S::LOG_SIZE) as u32;
-        let offset = index as u32 & S::OFFSET_MASK;
-        unsafe { Address::new_unchecked(sector, offset) }
+    /// Panics if the address is before the first byte or past the last
+    /// one a `u64` can index
+    pub fn new(sector: u64, offset: i32) -> Address<S> {
+        let index = ((sector as i128) << S::LOG_SIZE) + offset as i128;
+        Address::from_wide_index(index)
     }
 
+    /// Panics like `Address::new`, which can't happen for blocks of less
+    /// than 4 GiB and offsets that don't go back before the first block
     pub fn with_block_size(
         block: u32,
         offset: i32,
         log_block_size: u32,
     ) -> Address<S> {
-        let index = ((block as i64) << log_block_size) + offset as i64;
-        let sector = (index >> S::LOG_SIZE) as u32;
-        let offset = index as u32 & S::OFFSET_MASK;
-        unsafe { Address::new_unchecked(sector, offset) }
+        let index = ((block as i128) << log_block_size) + offset as i128;
+        Address::from_wide_index(index)
+    }
+
+    fn from_wide_index(index: i128) -> Address<S> {
+        assert!(
+            0 <= index && index <= u64::MAX as i128,
+            "address out of bounds"
+        );
+        Address::from(index as u64)
+    }
+
+    /// Adds `rhs` to this address, or returns `None` if the sum is past the
+    /// last byte a `u64` can index
+    pub fn checked_add(self, rhs: Address<S>) -> Option<Address<S>> {
+        self.into_index()
+            .checked_add(rhs.into_index())
+            .map(Address::from)
+    }
+
+    /// Subtracts `rhs` from this address, or returns `None` if `rhs` is the
+    /// larger of the two
+    pub fn checked_sub(self, rhs: Address<S>) -> Option<Address<S>> {
+        self.into_index()
+            .checked_sub(rhs.into_index())
+            .map(Address::from)
+    }
+
+    /// Adds `rhs` to this address, stopping at the last byte a `u64` can
+    /// index
+    pub fn saturating_add(self, rhs: Address<S>) -> Address<S> {
+        Address::from(self.into_index().saturating_add(rhs.into_index()))
+    }
+
+    /// Subtracts `rhs` from this address, stopping at the first byte
+    pub fn saturating_sub(self, rhs: Address<S>) -> Address<S> {
+        Address::from(self.into_index().saturating_sub(rhs.into_index()))
     }
 
     pub fn into_index(self) -> u64 {
-        ((self.sector as u64) << S::LOG_SIZE) + self.offset as u64
+        (self.sector << S::LOG_SIZE) + self.offset as u64
     }
 
     pub const fn sector_size(&self) -> usize {
@@ -85,39 +131,61 @@ impl<S: SectorSize> Address<S> {
         S::LOG_SIZE
     }
 
-    pub fn sector(&self) -> u32 {
+    pub fn sector(&self) -> u64 {
         self.sector
     }
 
     pub fn offset(&self) -> u32 {
         self.offset
     }
-}
 
-unsafe impl<S: SectorSize> Step for Address<S> {
-    fn steps_between(start: &Self, end: &Self) -> Option<usize> {
-        if end.sector >= start.sector {
-            Some(end.sector as usize - start.sector as usize)
-        } else {
-            None
+    /// Iterates over the sectors `range` touches, starting at `range.start`
+    /// and then at the start of each following sector before `range.end`
+    pub fn sectors(range: Range<Address<S>>) -> Sectors<S> {
+        Sectors {
+            next: range.start,
+            end: range.end,
         }
     }
+}
+
+/// Iterator of `Address::sectors`
+#[derive(Clone, Debug)]
+pub struct Sectors<S: SectorSize> {
+    next: Address<S>,
+    end: Address<S>,
+}
 
-    fn forward_checked(start: Self, count: usize) -> Option<Self> {
-        start
-            .sector
-            .checked_add(count as u32)
-            .map(|sector| Address::new(sector, 0))
+impl<S: SectorSize> Iterator for Sectors<S> {
+    type Item = Address<S>;
+
+    fn next(&mut self) -> Option<Address<S>> {
+        if self.next >= self.end {
+            return None;
+        }
+        let address = self.next;
+        self.next = if address.sector < self.end.sector {
+            Address::new(address.sector + 1, 0)
+        } else {
+            self.end
+        };
+        Some(address)
     }
 
-    fn backward_checked(start: Self, count: usize) -> Option<Self> {
-        start
-            .sector
-            .checked_sub(count as u32)
-            .map(|sector| Address::new(sector, 0))
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = if self.next >= self.end {
+            0
+        } else {
+            // the last sector counts even if `end` is at its start
+            let last = self.end.sector - (self.end.offset == 0) as u64;
+            (last - self.next.sector) as usize + 1
+        };
+        (len, Some(len))
     }
 }
 
+impl<S: SectorSize> ExactSizeIterator for Sectors<S> {}
+
 impl<S: SectorSize> Debug for Address<S> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let name = format!("Address<{}>", S::SIZE);
@@ -143,36 +211,33 @@ impl<S: SectorSize> LowerHex for Address<S> {
 impl<S: SectorSize> From<u64> for Address<S> {
     fn from(idx: u64) -> Address<S> {
         let sector = idx >> S::LOG_SIZE;
-        let offset = idx & S::OFFSET_MASK as u64;
-        Address::new(sector as u32, offset as i32)
+        let offset = idx as u32 & S::OFFSET_MASK;
+        unsafe { Address::new_unchecked(sector, offset) }
     }
 }
 
 impl<S: SectorSize> From<usize> for Address<S> {
     fn from(idx: usize) -> Address<S> {
-        let sector = idx >> S::LOG_SIZE;
-        let offset = idx & S::OFFSET_MASK as usize;
-        Address::new(sector as u32, offset as i32)
+        Address::from(idx as u64)
     }
 }
 
+/// Panics on overflow; see `Address::checked_add` for a fallible version.
+/// Only meant for addresses known to be in range, never for those computed
+/// from on-disk values, which a corrupt file system can make overflow.
 impl<S: SectorSize> Add for Address<S> {
     type Output = Address<S>;
     fn add(self, rhs: Address<S>) -> Address<S> {
-        Address::new(
-            self.sector + rhs.sector,
-            (self.offset + rhs.offset) as i32,
-        )
+        self.checked_add(rhs).expect("address overflow")
     }
 }
 
+/// Panics on underflow; see `Address::checked_sub` for a fallible
+/// version. Like `Add`, only meant for addresses known to be in range.
 impl<S: SectorSize> Sub for Address<S> {
     type Output = Address<S>;
     fn sub(self, rhs: Address<S>) -> Address<S> {
-        Address::new(
-            self.sector - rhs.sector,
-            self.offset as i32 - rhs.offset as i32,
-        )
+        self.checked_sub(rhs).expect("address underflow")
     }
 }
 
@@ -202,6 +267,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sectors() {
+        let start = Address::<Size512>::new(1, 256);
+        let end = Address::<Size512>::new(4, 0);
+        let sectors = Address::sectors(start..end);
+        assert_eq!(sectors.len(), 3);
+        assert_eq!(
+            sectors.collect::<Vec<_>>(),
+            vec![start, Address::new(2, 0), Address::new(3, 0)]
+        );
+
+        let end = Address::<Size512>::new(4, 1);
+        assert_eq!(Address::sectors(start..end).len(), 4);
+        assert_eq!(Address::sectors(end..start).count(), 0);
+        assert_eq!(Address::sectors(start..start).len(), 0);
+    }
+
+    /// Checks every way of building an address of byte `index` against the
+    /// others
+    fn check_index<S: SectorSize>(index: u64) {
+        let address = Address::<S>::from(index);
+        assert_eq!(address.into_index(), index);
+        assert_eq!(address.sector(), index >> S::LOG_SIZE);
+        assert_eq!(address.offset() as u64, index & S::OFFSET_MASK as u64);
+        assert_eq!(Address::<S>::from(index as usize), address);
+        assert_eq!(
+            Address::new(address.sector(), address.offset() as i32),
+            address
+        );
+        if index >= 4096 {
+            let back = Address::<S>::new(address.sector(), -4096);
+            assert_eq!(
+                back.into_index(),
+                (index & !(S::OFFSET_MASK as u64)) - 4096
+            );
+            assert_eq!(
+                address - Address::from(4096_u64),
+                Address::from(index - 4096)
+            );
+        }
+        if index >> 12 <= u32::MAX as u64 {
+            let block = (index >> 12) as u32;
+            let offset = (index & 4095) as i32;
+            assert_eq!(Address::with_block_size(block, offset, 12), address);
+        }
+        match index.checked_add(1 << 20) {
+            Some(sum) => assert_eq!(
+                address.checked_add(Address::from(1_u64 << 20)),
+                Some(Address::from(sum))
+            ),
+            None => assert!(address
+                .checked_add(Address::from(1_u64 << 20))
+                .is_none()),
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        // 4 GiB, where byte indices stop fitting a `u32`, 2 TiB, where
+        // 512-byte sectors do, 16 TiB, the most 4 KiB blocks of ext2 reach,
+        // and the end of the address space
+        let boundaries = [1 << 32, 1 << 41, 1 << 44, u64::MAX];
+        // a linear congruential generator, for offsets around each
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for &boundary in boundaries.iter() {
+            let mut offsets = (0..=1024_u64).collect::<Vec<_>>();
+            for _ in 0..1024 {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                offsets.push(state >> 40);
+            }
+            for &offset in offsets.iter() {
+                for &index in [
+                    boundary.wrapping_sub(offset),
+                    boundary.saturating_add(offset),
+                ]
+                .iter()
+                {
+                    check_index::<Size512>(index);
+                    check_index::<Size1024>(index);
+                    check_index::<Size2048>(index);
+                    check_index::<Size4096>(index);
+                }
+            }
+        }
+
+        // the last block of the largest ext2 file system ends right at
+        // 16 TiB
+        let last = Address::<Size512>::with_block_size(u32::MAX, 4096, 12);
+        assert_eq!(last.into_index(), 1 << 44);
+        assert_eq!(last.sector(), 1 << 35);
+    }
+
+    #[test]
+    #[should_panic(expected = "address out of bounds")]
+    fn before_the_start() {
+        Address::<Size512>::new(0, -1);
+    }
+
     #[test]
     fn arithmetic() {
         assert_eq!(
@@ -233,5 +398,25 @@ mod tests {
         let b = Address::<Size512>::new(0, 256);
         assert_eq!(a - b, Address::<Size512>::new(3, 256));
         assert_eq!((a - b).into_index(), 1792);
+
+        let last = Address::<Size512>::new(u64::MAX >> 9, 0);
+        let one = Address::<Size512>::from(1_u64);
+        assert_eq!(
+            last.checked_add(Address::new(0, 511)).unwrap().offset(),
+            511
+        );
+        assert!(last.checked_add(Address::new(1, 0)).is_none());
+        assert!(one.checked_sub(last).is_none());
+        assert_eq!(last.checked_sub(last), Some(Address::new(0, 0)));
+
+        let end = Address::<Size512>::from(u64::MAX);
+        assert_eq!(last.saturating_add(last), end);
+        assert_eq!(end.saturating_add(one), end);
+        assert_eq!(
+            one.saturating_add(Address::new(1, 0)),
+            Address::new(1, 1)
+        );
+        assert_eq!(one.saturating_sub(last), Address::new(0, 0));
+        assert_eq!(end.saturating_sub(one), Address::from(u64::MAX - 1));
     }
 }
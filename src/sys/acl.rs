@@ -0,0 +1,276 @@
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+
+/// Version of the ACL format of the `system.posix_acl_*` attributes
+pub const ACL_XATTR_VERSION: u32 = 2;
+
+/// Tag of the entry for the owner of the file
+pub const ACL_USER_OBJ: u16 = 0x01;
+/// Tag of the entries for other users
+pub const ACL_USER: u16 = 0x02;
+/// Tag of the entry for the owning group of the file
+pub const ACL_GROUP_OBJ: u16 = 0x04;
+/// Tag of the entries for other groups
+pub const ACL_GROUP: u16 = 0x08;
+/// Tag of the entry bounding what entries for users other than the owner
+/// and for groups may grant
+pub const ACL_MASK: u16 = 0x10;
+/// Tag of the entry for everyone else
+pub const ACL_OTHER: u16 = 0x20;
+
+bitflags! {
+    /// What an ACL entry grants
+    #[derive(Default)]
+    pub struct AclPerm: u16 {
+        const EXECUTE = 0x1;
+        const WRITE = 0x2;
+        const READ = 0x4;
+    }
+}
+
+/// Who an ACL entry applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AclTag {
+    /// The owner of the file
+    UserObj,
+    /// The user with this ID
+    User(u32),
+    /// The owning group of the file
+    GroupObj,
+    /// The group with this ID
+    Group(u32),
+    /// The upper bound of what `User`, `GroupObj` and `Group` entries grant
+    Mask,
+    /// Everyone else
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    pub perm: AclPerm,
+}
+
+/// A POSIX access control list, as the `system.posix_acl_access` and
+/// `system.posix_acl_default` extended attributes hold
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Acl {
+    /// The entries in the order they're stored
+    pub entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    /// Parses an ACL in the format `getxattr(2)` gives it in: a version
+    /// header followed by entries of a tag, permissions and an ID.
+    ///
+    /// Besides the sizes and tags, the entries are checked to make up a
+    /// valid ACL: exactly one entry each for the owner, the owning group and
+    /// everyone else, and a mask if there are entries for other users or
+    /// groups.
+    pub fn from_xattr(bytes: &[u8]) -> Result<Acl> {
+        let corrupt = |reason| Error::Corrupt { reason };
+        if bytes.len() < 4
+            || u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+                != ACL_XATTR_VERSION
+        {
+            return Err(corrupt("unknown ACL version"));
+        }
+        let chunks = bytes[4..].chunks_exact(8);
+        if !chunks.remainder().is_empty() {
+            return Err(corrupt("ACL entry cut short"));
+        }
+
+        let mut entries = Vec::with_capacity(chunks.len());
+        for entry in chunks {
+            let tag = u16::from_le_bytes([entry[0], entry[1]]);
+            let perm = u16::from_le_bytes([entry[2], entry[3]]);
+            let id =
+                u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]);
+            let tag = match tag {
+                ACL_USER_OBJ => AclTag::UserObj,
+                ACL_USER => AclTag::User(id),
+                ACL_GROUP_OBJ => AclTag::GroupObj,
+                ACL_GROUP => AclTag::Group(id),
+                ACL_MASK => AclTag::Mask,
+                ACL_OTHER => AclTag::Other,
+                _ => return Err(corrupt("unknown ACL entry tag")),
+            };
+            let perm = AclPerm::from_bits(perm)
+                .ok_or_else(|| corrupt("unknown ACL permission bits"))?;
+            entries.push(AclEntry { tag, perm });
+        }
+
+        let count = |matches: fn(&AclTag) -> bool| {
+            entries.iter().filter(|entry| matches(&entry.tag)).count()
+        };
+        let named =
+            count(|tag| matches!(*tag, AclTag::User(_) | AclTag::Group(_)));
+        let masks = count(|tag| *tag == AclTag::Mask);
+        if count(|tag| *tag == AclTag::UserObj) != 1
+            || count(|tag| *tag == AclTag::GroupObj) != 1
+            || count(|tag| *tag == AclTag::Other) != 1
+            || masks > 1
+            || (named > 0 && masks == 0)
+        {
+            return Err(corrupt("ACL without the entries it needs"));
+        }
+        Ok(Acl { entries })
+    }
+
+    /// The permission bits of the entry tagged `tag`, if there is one
+    pub fn get(&self, tag: AclTag) -> Option<AclPerm> {
+        self.entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map(|entry| entry.perm)
+    }
+
+    /// What the ACL grants the user `uid`, in the group `gid` and the
+    /// supplementary `groups`, on a file owned by `owner` and `group`, as
+    /// POSIX.1e has it checked: the owner gets what its entry says, another
+    /// user with an entry what that says as far as the mask allows, a
+    /// member of any group with an entry what those entries say together
+    /// as far as the mask allows, and anyone else what the other entry
+    /// says. Privileges such as the superuser's aren't taken into account.
+    pub fn effective_permissions(
+        &self,
+        owner: u32,
+        group: u32,
+        uid: u32,
+        gid: u32,
+        groups: &[u32],
+    ) -> AclPerm {
+        let mask = self.get(AclTag::Mask).unwrap_or_else(AclPerm::all);
+        if uid == owner {
+            return self.get(AclTag::UserObj).unwrap_or_default();
+        }
+        if let Some(perm) = self.get(AclTag::User(uid)) {
+            return perm & mask;
+        }
+
+        let member = |id: u32| id == gid || groups.contains(&id);
+        let mut matched: Option<AclPerm> = None;
+        for entry in &self.entries {
+            let matches = match entry.tag {
+                AclTag::GroupObj => member(group),
+                AclTag::Group(id) => member(id),
+                _ => false,
+            };
+            if matches {
+                matched = Some(matched.unwrap_or_default() | entry.perm);
+            }
+        }
+        match matched {
+            Some(perm) => perm & mask,
+            None => self.get(AclTag::Other).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+
+    use super::{Acl, AclEntry, AclPerm, AclTag};
+
+    const RW: AclPerm = AclPerm::from_bits_truncate(6);
+    const R: AclPerm = AclPerm::READ;
+
+    /// An ACL in the format of the extended attributes
+    fn xattr(entries: &[(u16, u16, u32)]) -> Vec<u8> {
+        let mut bytes = vec![2, 0, 0, 0];
+        for &(tag, perm, id) in entries {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&perm.to_le_bytes());
+            bytes.extend_from_slice(&id.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn corrupt(bytes: &[u8]) {
+        match Acl::from_xattr(bytes) {
+            Err(Error::Corrupt { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        // u::rw-,u:1000:rw-,g::r--,m::rw-,o::r--
+        let acl = Acl::from_xattr(&xattr(&[
+            (1, 6, !0),
+            (2, 6, 1000),
+            (4, 4, !0),
+            (0x10, 6, !0),
+            (0x20, 4, !0),
+        ]))
+        .unwrap();
+        let entry = |tag, perm| AclEntry { tag, perm };
+        assert_eq!(
+            acl.entries,
+            vec![
+                entry(AclTag::UserObj, RW),
+                entry(AclTag::User(1000), RW),
+                entry(AclTag::GroupObj, R),
+                entry(AclTag::Mask, RW),
+                entry(AclTag::Other, R),
+            ]
+        );
+        assert_eq!(acl.get(AclTag::User(1000)), Some(RW));
+        assert_eq!(acl.get(AclTag::User(1001)), None);
+    }
+
+    #[test]
+    fn malformed() {
+        let minimal = [(1, 6, !0), (4, 4, !0), (0x20, 4, !0)];
+        assert!(Acl::from_xattr(&xattr(&minimal)).is_ok());
+
+        let mut bytes = xattr(&minimal);
+        bytes[0] = 1;
+        corrupt(&bytes);
+        let bytes = xattr(&minimal);
+        corrupt(&bytes[..bytes.len() - 2]);
+        corrupt(&xattr(&[(1, 6, !0), (4, 4, !0), (0x40, 4, !0)]));
+        corrupt(&xattr(&[(1, 6, !0), (4, 0x10, !0), (0x20, 4, !0)]));
+        // no owner entry, or a named entry without a mask
+        corrupt(&xattr(&[(4, 4, !0), (0x20, 4, !0)]));
+        corrupt(&xattr(&[(1, 6, !0), (2, 6, 7), (4, 4, !0), (0x20, 4, !0)]));
+    }
+
+    #[test]
+    fn effective_permissions() {
+        // u::rw-,u:1000:rwx,g::r-x,g:100:-w-,m::rw-,o::---
+        let acl = Acl::from_xattr(&xattr(&[
+            (1, 6, !0),
+            (2, 7, 1000),
+            (4, 5, !0),
+            (8, 2, 100),
+            (0x10, 6, !0),
+            (0x20, 0, !0),
+        ]))
+        .unwrap();
+        let check = |uid, gid, groups: &[u32]| {
+            acl.effective_permissions(0, 50, uid, gid, groups)
+        };
+        // the owner isn't bound by the mask
+        assert_eq!(check(0, 0, &[]), RW);
+        // a named user is
+        assert_eq!(check(1000, 0, &[]), RW);
+        assert_eq!(check(1000, 50, &[]), RW);
+        // the owning group, masked
+        assert_eq!(check(7, 50, &[]), R);
+        // every matching group counts
+        assert_eq!(check(7, 1, &[50, 100]), RW);
+        assert_eq!(check(7, 100, &[]), AclPerm::WRITE);
+        assert_eq!(check(7, 1, &[2]), AclPerm::empty());
+
+        // without a mask, the owning group gets all of its entry
+        let minimal =
+            Acl::from_xattr(&xattr(&[(1, 6, !0), (4, 5, !0), (0x20, 4, !0)]))
+                .unwrap();
+        let read_exec = AclPerm::READ | AclPerm::EXECUTE;
+        assert_eq!(minimal.effective_permissions(0, 50, 7, 50, &[]), read_exec);
+        assert_eq!(minimal.effective_permissions(0, 50, 7, 1, &[]), R);
+    }
+}
@@ -0,0 +1,140 @@
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+use volume::Volume;
+
+/// A usage bitmap tracks which blocks or inodes of a block group are in use.
+/// Each block group has one block usage bitmap and one inode usage bitmap,
+/// each occupying a single block, located by the `block_usage_addr` and
+/// `inode_usage_addr` fields of the group's `BlockGroupDescriptor`.
+///
+/// Bit `n` (least significant bit first within each byte) is set if the `n`th
+/// block or inode of the group is in use. Only the first `len` bits are
+/// meaningful; a partial last group leaves the remaining bits unused.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bitmap {
+    inner: Vec<u8>,
+    len: usize,
+}
+
+impl Bitmap {
+    /// Creates a bitmap over `inner`, of which the first `len` bits are
+    /// meaningful
+    pub fn new(inner: Vec<u8>, len: usize) -> Bitmap {
+        assert!(len <= inner.len() * 8, "bitmap length out of bounds");
+        Bitmap { inner, len }
+    }
+
+    /// Reads a bitmap of `size` bytes located at `offset`
+    pub fn find<S: SectorSize, V: Volume<u8, S>>(
+        haystack: &V,
+        offset: Address<S>,
+        size: usize,
+        len: usize,
+    ) -> Result<(Bitmap, Address<S>)> {
+        if len > size * 8 {
+            return Err(Error::Corrupt {
+                reason: "bitmap longer than its block",
+            });
+        }
+        let end = offset
+            .checked_add(Address::from(size))
+            .ok_or(Error::ArithmeticOverflow)?;
+        let inner = haystack
+            .slice(offset..end)
+            .map_err(|err| err.into())?
+            .to_vec();
+        Ok((Bitmap::new(inner, len), offset))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "bitmap index out of bounds");
+        self.inner[index >> 3] & (1 << (index & 7)) != 0
+    }
+
+    pub fn set(&mut self, index: usize) {
+        assert!(index < self.len, "bitmap index out of bounds");
+        self.inner[index >> 3] |= 1 << (index & 7);
+    }
+
+    pub fn clear(&mut self, index: usize) {
+        assert!(index < self.len, "bitmap index out of bounds");
+        self.inner[index >> 3] &= !(1 << (index & 7));
+    }
+
+    /// Returns the index of the first unused bit, if any
+    pub fn find_first_free(&self) -> Option<usize> {
+        self.inner
+            .iter()
+            .enumerate()
+            .find(|&(_, &byte)| byte != 0xff)
+            .map(|(i, &byte)| i * 8 + (!byte).trailing_zeros() as usize)
+            .filter(|&index| index < self.len)
+    }
+
+    /// Returns the number of unused bits
+    pub fn count_free(&self) -> usize {
+        (0..self.len).filter(|&index| !self.get(index)).count()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sector::Size512;
+
+    #[test]
+    fn set_clear() {
+        let mut bitmap = Bitmap::new(vec![0; 4], 30);
+        assert_eq!(bitmap.find_first_free(), Some(0));
+        bitmap.set(0);
+        bitmap.set(9);
+        assert!(bitmap.get(0));
+        assert!(bitmap.get(9));
+        assert!(!bitmap.get(8));
+        assert_eq!(bitmap.as_bytes(), &[0x01, 0x02, 0, 0]);
+        assert_eq!(bitmap.find_first_free(), Some(1));
+        assert_eq!(bitmap.count_free(), 28);
+        bitmap.clear(0);
+        assert_eq!(bitmap.as_bytes(), &[0x00, 0x02, 0, 0]);
+    }
+
+    #[test]
+    fn partial() {
+        // only the first 12 bits are meaningful; the rest must never be handed
+        // out even though they're clear
+        let bitmap = Bitmap::new(vec![0xff, 0x0f, 0, 0], 12);
+        assert_eq!(bitmap.find_first_free(), None);
+        assert_eq!(bitmap.count_free(), 0);
+
+        let bitmap = Bitmap::new(vec![0xff, 0x07, 0, 0], 12);
+        assert_eq!(bitmap.find_first_free(), Some(11));
+    }
+
+    #[test]
+    fn find() {
+        let mut volume = vec![0_u8; 4096];
+        volume[2048] = 0x7f;
+        let (bitmap, _) =
+            Bitmap::find(&volume, Address::<Size512>::new(4, 0), 1024, 8192)
+                .unwrap();
+        assert_eq!(bitmap.find_first_free(), Some(7));
+    }
+}
@@ -3,9 +3,10 @@ use core::mem;
 
 use alloc::vec::Vec;
 
-use error::Error;
+use error::{Error, Result};
 use sector::{Address, SectorSize};
-use volume::Volume;
+use sys::Fields;
+use volume::{Volume, VolumeCommit};
 
 /// The Block Group Descriptor Table contains a descriptor for each block group
 /// within the file system. The number of block groups within the file system,
@@ -20,20 +21,20 @@ use volume::Volume;
 /// Remember that blocks are numbered starting at 0, and that block numbers
 /// don't usually correspond to physical block addresses.
 #[repr(C, packed)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct BlockGroupDescriptor {
     /// Block address of block usage bitmap
-    pub block_usage_addr: u32,
+    pub(crate) block_usage_addr: u32,
     /// Block address of inode usage bitmap
-    pub inode_usage_addr: u32,
+    pub(crate) inode_usage_addr: u32,
     /// Starting block address of inode table
-    pub inode_table_block: u32,
+    pub(crate) inode_table_block: u32,
     /// Number of unallocated blocks in group
-    pub free_blocks_count: u16,
+    pub(crate) free_blocks_count: u16,
     /// Number of unallocated inodes in group
-    pub free_inodes_count: u16,
+    pub(crate) free_inodes_count: u16,
     /// Number of directories in group
-    pub dirs_count: u16,
+    pub(crate) dirs_count: u16,
     #[doc(hidden)]
     _reserved: [u8; 14],
 }
@@ -52,14 +53,80 @@ impl Debug for BlockGroupDescriptor {
 }
 
 impl BlockGroupDescriptor {
-    ///
-    /// # Safety
-    pub unsafe fn find_descriptor<S: SectorSize, V: Volume<u8, S>>(
+    getters! {
+        /// Block address of block usage bitmap
+        block_usage_addr: u32;
+        /// Block address of inode usage bitmap
+        inode_usage_addr: u32;
+        /// Starting block address of inode table
+        inode_table_block: u32;
+        /// Number of unallocated blocks in group
+        free_blocks_count: u16;
+        /// Number of unallocated inodes in group
+        free_inodes_count: u16;
+        /// Number of directories in group
+        dirs_count: u16;
+    }
+}
+
+/// The fields of a `BlockGroupDescriptor`, unpacked so that they can be
+/// borrowed and assigned to freely. Converts to and from
+/// `BlockGroupDescriptor`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockGroupDescriptorData {
+    /// Block address of block usage bitmap
+    pub block_usage_addr: u32,
+    /// Block address of inode usage bitmap
+    pub inode_usage_addr: u32,
+    /// Starting block address of inode table
+    pub inode_table_block: u32,
+    /// Number of unallocated blocks in group
+    pub free_blocks_count: u16,
+    /// Number of unallocated inodes in group
+    pub free_inodes_count: u16,
+    /// Number of directories in group
+    pub dirs_count: u16,
+    /// Reserved, carried along so that conversions round trip
+    pub reserved: [u8; 14],
+}
+
+impl From<BlockGroupDescriptor> for BlockGroupDescriptorData {
+    fn from(descriptor: BlockGroupDescriptor) -> BlockGroupDescriptorData {
+        BlockGroupDescriptorData {
+            block_usage_addr: descriptor.block_usage_addr,
+            inode_usage_addr: descriptor.inode_usage_addr,
+            inode_table_block: descriptor.inode_table_block,
+            free_blocks_count: descriptor.free_blocks_count,
+            free_inodes_count: descriptor.free_inodes_count,
+            dirs_count: descriptor.dirs_count,
+            reserved: descriptor._reserved,
+        }
+    }
+}
+
+impl From<BlockGroupDescriptorData> for BlockGroupDescriptor {
+    fn from(data: BlockGroupDescriptorData) -> BlockGroupDescriptor {
+        BlockGroupDescriptor {
+            block_usage_addr: data.block_usage_addr,
+            inode_usage_addr: data.inode_usage_addr,
+            inode_table_block: data.inode_table_block,
+            free_blocks_count: data.free_blocks_count,
+            free_inodes_count: data.free_inodes_count,
+            dirs_count: data.dirs_count,
+            _reserved: data.reserved,
+        }
+    }
+}
+
+impl BlockGroupDescriptor {
+    /// Reads the descriptor at `offset`
+    pub fn find_descriptor<S: SectorSize, V: Volume<u8, S>>(
         haystack: &V,
         offset: Address<S>,
-    ) -> Result<(BlockGroupDescriptor, Address<S>), Error> {
-        let end =
-            offset + Address::from(mem::size_of::<BlockGroupDescriptor>());
+    ) -> Result<(BlockGroupDescriptor, Address<S>)> {
+        let end = offset
+            .checked_add(Address::from(mem::size_of::<BlockGroupDescriptor>()))
+            .ok_or(Error::ArithmeticOverflow)?;
         if haystack.size() < end {
             return Err(Error::AddressOutOfBounds {
                 sector: end.sector(),
@@ -68,22 +135,32 @@ impl BlockGroupDescriptor {
             });
         }
 
-        let descriptor = haystack
-            .slice_unchecked(offset..end)
-            .dynamic_cast::<BlockGroupDescriptor>();
+        let slice = haystack.slice(offset..end).map_err(|err| err.into())?;
+        if slice.len() < mem::size_of::<BlockGroupDescriptor>() {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        let mut bytes = [0; 32];
+        bytes.copy_from_slice(&slice[..32]);
 
-        Ok(descriptor)
+        Ok((BlockGroupDescriptor::from_bytes(&bytes), offset))
     }
 
-    ///
-    /// # Safety
-    pub unsafe fn find_descriptor_table<S: SectorSize, V: Volume<u8, S>>(
+    /// Reads a table of `count` descriptors starting at `offset`
+    pub fn find_descriptor_table<S: SectorSize, V: Volume<u8, S>>(
         haystack: &V,
         offset: Address<S>,
         count: usize,
-    ) -> Result<(Vec<BlockGroupDescriptor>, Address<S>), Error> {
+    ) -> Result<(Vec<BlockGroupDescriptor>, Address<S>)> {
+        let size = count
+            .checked_mul(mem::size_of::<BlockGroupDescriptor>())
+            .ok_or(Error::ArithmeticOverflow)?;
         let end = offset
-            + Address::from(count * mem::size_of::<BlockGroupDescriptor>());
+            .checked_add(Address::from(size))
+            .ok_or(Error::ArithmeticOverflow)?;
         if haystack.size() < end {
             return Err(Error::AddressOutOfBounds {
                 sector: end.sector(),
@@ -95,7 +172,10 @@ impl BlockGroupDescriptor {
         let mut vec = Vec::with_capacity(count);
         for i in 0..count {
             let offset = offset
-                + Address::from(i * mem::size_of::<BlockGroupDescriptor>());
+                .checked_add(Address::from(
+                    i * mem::size_of::<BlockGroupDescriptor>(),
+                ))
+                .ok_or(Error::ArithmeticOverflow)?;
             vec.push({
                 BlockGroupDescriptor::find_descriptor(haystack, offset)?.0
             });
@@ -103,6 +183,72 @@ impl BlockGroupDescriptor {
 
         Ok((vec, offset))
     }
+
+    /// Parses a descriptor from its on-disk, little-endian representation
+    pub fn from_bytes(bytes: &[u8; 32]) -> BlockGroupDescriptor {
+        let mut fields = Fields::new(bytes);
+        let mut descriptor = BlockGroupDescriptor {
+            block_usage_addr: fields.u32(),
+            inode_usage_addr: fields.u32(),
+            inode_table_block: fields.u32(),
+            free_blocks_count: fields.u16(),
+            free_inodes_count: fields.u16(),
+            dirs_count: fields.u16(),
+            _reserved: [0; 14],
+        };
+        fields.bytes(&mut descriptor._reserved);
+        debug_assert_eq!(
+            fields.offset(),
+            mem::size_of::<BlockGroupDescriptor>()
+        );
+        descriptor
+    }
+
+    /// Serializes the descriptor into its on-disk, little-endian
+    /// representation
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        let mut offset = 0;
+        {
+            let mut put = |field: &[u8]| {
+                bytes[offset..offset + field.len()].copy_from_slice(field);
+                offset += field.len();
+            };
+            put(&{ self.block_usage_addr }.to_le_bytes());
+            put(&{ self.inode_usage_addr }.to_le_bytes());
+            put(&{ self.inode_table_block }.to_le_bytes());
+            put(&{ self.free_blocks_count }.to_le_bytes());
+            put(&{ self.free_inodes_count }.to_le_bytes());
+            put(&{ self.dirs_count }.to_le_bytes());
+            put(&self._reserved);
+        }
+        debug_assert_eq!(offset, mem::size_of::<BlockGroupDescriptor>());
+        bytes
+    }
+
+    /// Writes the descriptor to `offset`, its entry in a descriptor table
+    pub fn write_descriptor<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &mut V,
+        offset: Address<S>,
+    ) -> Result<()> {
+        let commit = VolumeCommit::new(self.to_bytes().to_vec(), offset);
+        volume.commit(Some(commit)).map_err(|err| err.into())
+    }
+
+    /// Writes a whole descriptor table to `offset`
+    pub fn write_table<S: SectorSize, V: Volume<u8, S>>(
+        table: &[BlockGroupDescriptor],
+        volume: &mut V,
+        offset: Address<S>,
+    ) -> Result<()> {
+        let mut bytes = Vec::with_capacity(mem::size_of_val(table));
+        for descriptor in table {
+            bytes.extend_from_slice(&descriptor.to_bytes());
+        }
+        let commit = VolumeCommit::new(bytes, offset);
+        volume.commit(Some(commit)).map_err(|err| err.into())
+    }
 }
 
 #[cfg(test)]
@@ -113,13 +259,11 @@ mod tests {
     #[test]
     fn find() {
         let volume = vec![0_u8; 4096];
-        let table = unsafe {
-            BlockGroupDescriptor::find_descriptor_table(
-                &volume,
-                Address::<Size512>::new(4, 0),
-                8,
-            )
-        };
+        let table = BlockGroupDescriptor::find_descriptor_table(
+            &volume,
+            Address::<Size512>::new(4, 0),
+            8,
+        );
         assert!(
             table.is_ok(),
             "Err({:?})",
@@ -128,4 +272,47 @@ mod tests {
         let table = table.unwrap_or_else(|_| unreachable!());
         assert_eq!(table.0.len(), 8);
     }
+
+    #[test]
+    fn end_of_address_space() {
+        use sys::bitmap::Bitmap;
+        use sys::inode::Inode;
+        use sys::superblock::Superblock;
+        use volume::size::Size;
+        use volume::SparseMem;
+
+        // where a corrupt superblock or descriptor could point the parsers,
+        // which must fail rather than overflow
+        let volume = SparseMem::<Size512>::with_size(Size::Unbounded);
+        let end = |back: u64| Address::<Size512>::from(u64::MAX - back);
+        let overflow = |result: Result<()>| match result {
+            Err(Error::ArithmeticOverflow) => (),
+            other => panic!("unexpected {:?}", other),
+        };
+        overflow(
+            BlockGroupDescriptor::find_descriptor(&volume, end(16))
+                .map(|_| ()),
+        );
+        overflow(
+            BlockGroupDescriptor::find_descriptor_table(&volume, end(100), 4)
+                .map(|_| ()),
+        );
+        overflow(
+            BlockGroupDescriptor::find_descriptor_table(
+                &volume,
+                end(100),
+                usize::MAX,
+            )
+            .map(|_| ()),
+        );
+        overflow(Superblock::find_at(&volume, end(1000)).map(|_| ()));
+        overflow(Inode::find_inode(&volume, end(100), 128).map(|_| ()));
+        overflow(Bitmap::find(&volume, end(100), 1024, 8192).map(|_| ()));
+
+        // right up to the end is fine
+        let (table, _) =
+            BlockGroupDescriptor::find_descriptor_table(&volume, end(64), 2)
+                .unwrap();
+        assert_eq!(table.len(), 2);
+    }
 }
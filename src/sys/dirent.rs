@@ -0,0 +1,255 @@
+use error::{Error, Result};
+use sys::inode::FileType;
+use sys::superblock::FeaturesRequired;
+
+/// Size of a directory entry header, the part before the name
+pub const HEADER_SIZE: usize = 8;
+
+/// The longest file name a directory entry can hold, in bytes
+pub const MAX_NAME_LEN: usize = 255;
+
+/// The fixed part of a directory entry, which is followed by the name and
+/// padding up to `rec_len` bytes. An entry with a zero inode is unused.
+///
+/// With the filetype feature, `name_len` is a single byte and the one after
+/// it holds the type of the inode. Without it, as on revision 0 file
+/// systems, `name_len` takes up both bytes and `file_type` is `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntryHeader {
+    pub inode: u32,
+    pub rec_len: u16,
+    pub name_len: u16,
+    pub file_type: Option<FileType>,
+}
+
+impl DirEntryHeader {
+    /// Parses a header from the start of `bytes`, which must hold at least
+    /// `HEADER_SIZE` bytes, laid out as on a file system with `features`
+    pub fn from_bytes(
+        bytes: &[u8],
+        features: FeaturesRequired,
+    ) -> DirEntryHeader {
+        let (name_len, file_type) =
+            if features.contains(FeaturesRequired::REQ_DIRECTORY_TYPE) {
+                (bytes[6] as u16, Some(FileType::from_dirent(bytes[7])))
+            } else {
+                (u16::from_le_bytes([bytes[6], bytes[7]]), None)
+            };
+        DirEntryHeader {
+            inode: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            rec_len: u16::from_le_bytes([bytes[4], bytes[5]]),
+            name_len,
+            file_type,
+        }
+    }
+
+    /// Whether the entry points at an inode
+    pub fn in_use(&self) -> bool {
+        self.inode != 0
+    }
+}
+
+/// A directory entry borrowed from its block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirEntryRef<'a> {
+    /// Offset of the entry in the block
+    pub offset: usize,
+    pub header: DirEntryHeader,
+    pub name: &'a [u8],
+}
+
+/// Walks the entries of a directory block, unused ones included.
+///
+/// Each entry is checked before it's returned: its `rec_len` must be a
+/// multiple of 4, big enough for its header and name, and within the block.
+/// The first entry failing a check ends the walk with `Error::Corrupt`, so
+/// a corrupt block can neither loop forever nor be read out of bounds.
+#[derive(Debug, Clone)]
+pub struct DirEntryIter<'a> {
+    block: &'a [u8],
+    offset: usize,
+    features: FeaturesRequired,
+    done: bool,
+}
+
+impl<'a> DirEntryIter<'a> {
+    /// Walks `block` of a file system with the required `features`, which
+    /// decide how entries are laid out
+    pub fn new(
+        block: &'a [u8],
+        features: FeaturesRequired,
+    ) -> DirEntryIter<'a> {
+        DirEntryIter::at(block, 0, features)
+    }
+
+    /// Starts walking `block` from the entry at `offset`
+    pub fn at(
+        block: &'a [u8],
+        offset: usize,
+        features: FeaturesRequired,
+    ) -> DirEntryIter<'a> {
+        DirEntryIter {
+            block,
+            offset,
+            features,
+            done: false,
+        }
+    }
+
+    /// Offset of the next entry in the block
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    fn fail(
+        &mut self,
+        reason: &'static str,
+    ) -> Option<Result<DirEntryRef<'a>>> {
+        self.done = true;
+        Some(Err(Error::Corrupt { reason }))
+    }
+}
+
+impl<'a> Iterator for DirEntryIter<'a> {
+    type Item = Result<DirEntryRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.block.len() {
+            return None;
+        }
+        let rest = &self.block[self.offset..];
+        if rest.len() < HEADER_SIZE {
+            return self.fail("directory entry header crosses the block end");
+        }
+
+        let header = DirEntryHeader::from_bytes(rest, self.features);
+        let rec_len = header.rec_len as usize;
+        if rec_len == 0 {
+            return self.fail("directory entry of length 0");
+        }
+        if rec_len & 3 != 0 {
+            return self.fail("misaligned directory entry length");
+        }
+        if rec_len > rest.len() {
+            return self.fail("directory entry crosses the block end");
+        }
+        if header.name_len as usize > MAX_NAME_LEN {
+            return self.fail("directory entry name too long");
+        }
+        if rec_len < dirent_len(header.name_len as usize) {
+            return self.fail("directory entry too short for its name");
+        }
+
+        let entry = DirEntryRef {
+            offset: self.offset,
+            header,
+            name: &rest[HEADER_SIZE..HEADER_SIZE + header.name_len as usize],
+        };
+        self.offset += rec_len;
+        Some(Ok(entry))
+    }
+}
+
+/// Size of a directory entry with a name of `name_len` bytes, padded to 4 bytes
+pub fn dirent_len(name_len: usize) -> usize {
+    (HEADER_SIZE + name_len + 3) & !3
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use sys::inode::FileType;
+    use sys::superblock::FeaturesRequired;
+
+    use super::{dirent_len, DirEntryHeader, DirEntryIter};
+
+    const FILETYPE: FeaturesRequired = FeaturesRequired::REQ_DIRECTORY_TYPE;
+
+    /// A block of `entries`, each as an inode, a record length and a name
+    fn block(entries: &[(u32, u16, &[u8])], len: usize) -> Vec<u8> {
+        let mut block = vec![0; len];
+        let mut offset = 0;
+        for &(inode, rec_len, name) in entries {
+            let buf = &mut block[offset..];
+            buf[..4].copy_from_slice(&inode.to_le_bytes());
+            buf[4..6].copy_from_slice(&rec_len.to_le_bytes());
+            buf[6] = name.len() as u8;
+            buf[8..8 + name.len()].copy_from_slice(name);
+            offset += rec_len as usize;
+        }
+        block
+    }
+
+    fn corrupt(block: &[u8]) -> usize {
+        let mut iter = DirEntryIter::new(block, FILETYPE);
+        let valid = iter.by_ref().take_while(Result::is_ok).count();
+        // the error ends the walk
+        assert!(iter.next().is_none());
+        match DirEntryIter::new(block, FILETYPE).nth(valid) {
+            Some(Err(Error::Corrupt { .. })) => valid,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn walk() {
+        let block =
+            block(&[(2, 12, b"."), (0, 12, b""), (12, 40, b"file")], 64);
+        let entries = DirEntryIter::new(&block, FILETYPE)
+            .map(Result::unwrap)
+            .map(|entry| (entry.offset, entry.header.inode, entry.name))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![(0, 2, &b"."[..]), (12, 0, &b""[..]), (24, 12, &b"file"[..])]
+        );
+
+        let header = DirEntryHeader::from_bytes(&block[24..], FILETYPE);
+        assert_eq!(header.rec_len, 40);
+        assert!(header.in_use());
+        assert_eq!(dirent_len(4), 12);
+        assert_eq!(DirEntryIter::at(&block, 12, FILETYPE).count(), 2);
+    }
+
+    #[test]
+    fn layouts() {
+        let mut block = block(&[(12, 16, b"dir")], 16);
+        block[7] = 2;
+        let header = DirEntryHeader::from_bytes(&block, FILETYPE);
+        assert_eq!(header.name_len, 3);
+        assert_eq!(header.file_type, Some(FileType::Directory));
+
+        // without the filetype feature, the type byte is part of the length
+        let header =
+            DirEntryHeader::from_bytes(&block, FeaturesRequired::empty());
+        assert_eq!(header.name_len, 0x203);
+        assert_eq!(header.file_type, None);
+        let mut long = block.clone();
+        long.resize(0x210, 0);
+        long[4..6].copy_from_slice(&0x210u16.to_le_bytes());
+        match DirEntryIter::new(&long, FeaturesRequired::empty()).next() {
+            Some(Err(Error::Corrupt { reason })) => {
+                assert_eq!(reason, "directory entry name too long")
+            }
+            other => panic!("expected a corrupt entry, got {:?}", other),
+        }
+        block[7] = 0;
+        let entry = DirEntryIter::new(&block, FeaturesRequired::empty())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.name, b"dir");
+        assert_eq!(entry.header.file_type, None);
+    }
+
+    #[test]
+    fn malformed() {
+        // a zero length would never get anywhere
+        assert_eq!(corrupt(&block(&[(2, 12, b"."), (3, 0, b"")], 64)), 1);
+        assert_eq!(corrupt(&block(&[(2, 14, b".")], 64)), 0);
+        assert_eq!(corrupt(&block(&[(2, 68, b".")], 64)), 0);
+        assert_eq!(corrupt(&block(&[(2, 12, b"longer name")], 64)), 0);
+        // a header cut short by the end of the block
+        assert_eq!(corrupt(&block(&[(2, 60, b".")], 64)), 1);
+    }
+}
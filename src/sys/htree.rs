@@ -0,0 +1,402 @@
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+
+/// Legacy hash of names, treating them as signed chars
+pub const DX_HASH_LEGACY: u8 = 0;
+/// Half MD4 hash of names, treating them as signed chars
+pub const DX_HASH_HALF_MD4: u8 = 1;
+/// TEA hash of names, treating them as signed chars
+pub const DX_HASH_TEA: u8 = 2;
+/// Legacy hash of names, treating them as unsigned chars
+pub const DX_HASH_LEGACY_UNSIGNED: u8 = 3;
+/// Half MD4 hash of names, treating them as unsigned chars
+pub const DX_HASH_HALF_MD4_UNSIGNED: u8 = 4;
+/// TEA hash of names, treating them as unsigned chars
+pub const DX_HASH_TEA_UNSIGNED: u8 = 5;
+
+/// Seed used when the superblock's is all zeroes
+const DEFAULT_SEED: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+/// Hashes `name` the way `version` says to, returning the major and minor
+/// hashes. The major hash is what indexes are sorted by; its lowest bit is
+/// always clear. Returns `None` for an unknown version.
+pub fn dx_hash(name: &[u8], version: u8, seed: [u32; 4]) -> Option<(u32, u32)> {
+    let mut buf = if seed == [0; 4] { DEFAULT_SEED } else { seed };
+    let signed = version < DX_HASH_LEGACY_UNSIGNED;
+
+    let (hash, minor) = match version {
+        DX_HASH_LEGACY | DX_HASH_LEGACY_UNSIGNED => {
+            (legacy_hash(name, signed), 0)
+        }
+        DX_HASH_HALF_MD4 | DX_HASH_HALF_MD4_UNSIGNED => {
+            let mut input = [0; 8];
+            for rest in rests(name, 32) {
+                str_to_hash_buf(rest, &mut input, signed);
+                half_md4_transform(&mut buf, &input);
+            }
+            (buf[1], buf[2])
+        }
+        DX_HASH_TEA | DX_HASH_TEA_UNSIGNED => {
+            let mut input = [0; 4];
+            for rest in rests(name, 16) {
+                str_to_hash_buf(rest, &mut input, signed);
+                tea_transform(&mut buf, &input);
+            }
+            (buf[0], buf[1])
+        }
+        _ => return None,
+    };
+
+    let hash = hash & !1;
+    // the largest hash marks the end of a directory for readdir cookies
+    let hash = if hash == 0x7fff_ffff << 1 {
+        0x7fff_fffe << 1
+    } else {
+        hash
+    };
+    Some((hash, minor))
+}
+
+/// Yields what's left of `name` after every `step` bytes of it
+fn rests(name: &[u8], step: usize) -> impl Iterator<Item = &[u8]> {
+    (0..name.len())
+        .step_by(step)
+        .map(move |start| &name[start..])
+}
+
+/// The character at `byte`, sign extended for signed hashes
+fn char(byte: u8, signed: bool) -> u32 {
+    if signed {
+        byte as i8 as i32 as u32
+    } else {
+        byte as u32
+    }
+}
+
+fn legacy_hash(name: &[u8], signed: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12a3_fe2d_u32, 0x37ab_e8f9_u32);
+    for &byte in name {
+        let mut hash = hash1
+            .wrapping_add(hash0 ^ char(byte, signed).wrapping_mul(7_152_373));
+        if hash & 0x8000_0000 != 0 {
+            hash = hash.wrapping_sub(0x7fff_ffff);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// Packs the start of `rest` into `buf` big-endian four bytes at a time,
+/// padding with the length of `rest`
+fn str_to_hash_buf(rest: &[u8], buf: &mut [u32], signed: bool) {
+    let pad = rest.len() as u32 | (rest.len() as u32) << 8;
+    let pad = pad | pad << 16;
+    let rest = &rest[..rest.len().min(buf.len() * 4)];
+    for (i, word) in buf.iter_mut().enumerate() {
+        *word = pad;
+        if i * 4 < rest.len() {
+            for &byte in rest[i * 4..].iter().take(4) {
+                *word = char(byte, signed).wrapping_add(*word << 8);
+            }
+        }
+    }
+}
+
+fn half_md4_transform(buf: &mut [u32; 4], input: &[u32; 8]) {
+    fn f(x: u32, y: u32, z: u32) -> u32 {
+        z ^ (x & (y ^ z))
+    }
+    fn g(x: u32, y: u32, z: u32) -> u32 {
+        (x & y).wrapping_add((x ^ y) & z)
+    }
+    fn h(x: u32, y: u32, z: u32) -> u32 {
+        x ^ y ^ z
+    }
+    const K2: u32 = 0o13240474631;
+    const K3: u32 = 0o15666365641;
+
+    let [mut a, mut b, mut c, mut d] = *buf;
+    macro_rules! round {
+        (
+            $f:ident,
+            $a:ident,
+            $b:ident,
+            $c:ident,
+            $d:ident,
+            $x:expr,
+            $s:expr
+        ) => {
+            $a = $a
+                .wrapping_add($f($b, $c, $d))
+                .wrapping_add($x)
+                .rotate_left($s);
+        };
+    }
+
+    round!(f, a, b, c, d, input[0], 3);
+    round!(f, d, a, b, c, input[1], 7);
+    round!(f, c, d, a, b, input[2], 11);
+    round!(f, b, c, d, a, input[3], 19);
+    round!(f, a, b, c, d, input[4], 3);
+    round!(f, d, a, b, c, input[5], 7);
+    round!(f, c, d, a, b, input[6], 11);
+    round!(f, b, c, d, a, input[7], 19);
+
+    round!(g, a, b, c, d, input[1].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[3].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[5].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[7].wrapping_add(K2), 13);
+    round!(g, a, b, c, d, input[0].wrapping_add(K2), 3);
+    round!(g, d, a, b, c, input[2].wrapping_add(K2), 5);
+    round!(g, c, d, a, b, input[4].wrapping_add(K2), 9);
+    round!(g, b, c, d, a, input[6].wrapping_add(K2), 13);
+
+    round!(h, a, b, c, d, input[3].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[7].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[2].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[6].wrapping_add(K3), 15);
+    round!(h, a, b, c, d, input[1].wrapping_add(K3), 3);
+    round!(h, d, a, b, c, input[5].wrapping_add(K3), 9);
+    round!(h, c, d, a, b, input[0].wrapping_add(K3), 11);
+    round!(h, b, c, d, a, input[4].wrapping_add(K3), 15);
+
+    buf[0] = buf[0].wrapping_add(a);
+    buf[1] = buf[1].wrapping_add(b);
+    buf[2] = buf[2].wrapping_add(c);
+    buf[3] = buf[3].wrapping_add(d);
+}
+
+fn tea_transform(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9e37_79b9;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum = 0_u32;
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            ((b1 << 4).wrapping_add(a))
+                ^ b1.wrapping_add(sum)
+                ^ ((b1 >> 5).wrapping_add(b)),
+        );
+        b1 = b1.wrapping_add(
+            ((b0 << 4).wrapping_add(c))
+                ^ b0.wrapping_add(sum)
+                ^ ((b0 >> 5).wrapping_add(d)),
+        );
+    }
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// An entry of a hash tree node: the blocks of the directory holding names
+/// whose hashes are at least `hash`, up to the next entry's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DxEntry {
+    pub hash: u32,
+    /// Logical block of the directory, either another node or a leaf
+    pub block: u32,
+}
+
+/// The root of a hash tree, which takes the place of the first block of an
+/// indexed directory. Fake `.` and `..` entries, the second of which covers
+/// the rest of the block, hide it from code unaware of indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DxRoot {
+    pub hash_version: u8,
+    /// Number of levels of nodes between the root and the leaves
+    pub indirect_levels: u8,
+    /// The entries, the first of which has an implicit hash of 0
+    pub entries: Vec<DxEntry>,
+}
+
+impl DxRoot {
+    /// Parses the root out of the first block of a directory. Anything that
+    /// doesn't look like a root Linux would write is `Error::Corrupt`.
+    pub fn from_block(block: &[u8]) -> Result<DxRoot> {
+        let corrupt = |reason| Err(Error::Corrupt { reason });
+        if block.len() < 40 {
+            return corrupt("hash tree root block too small");
+        }
+        let dot_len = read_u16(&block[4..]) as usize;
+        if dot_len != 12 || block[6] != 1 || block[8] != b'.' {
+            return corrupt("hash tree root without `.`");
+        }
+        let dotdot_len = read_u16(&block[16..]) as usize;
+        if dotdot_len != block.len() - 12 || block[18] != 2 {
+            return corrupt("hash tree root without `..`");
+        }
+
+        let hash_version = block[28];
+        let info_len = block[29] as usize;
+        let indirect_levels = block[30];
+        if info_len != 8 {
+            return corrupt("hash tree root info of unknown length");
+        }
+        if indirect_levels > 1 {
+            return corrupt("hash tree too deep");
+        }
+        if block[31] != 0 {
+            return corrupt("hash tree root with unknown flags");
+        }
+
+        Ok(DxRoot {
+            hash_version,
+            indirect_levels,
+            entries: dx_entries(&block[24 + info_len..])?,
+        })
+    }
+}
+
+/// Parses the entries of an interior node of a hash tree, which is hidden
+/// behind an unused directory entry covering its whole block
+pub fn dx_node_entries(block: &[u8]) -> Result<Vec<DxEntry>> {
+    if block.len() < 16
+        || read_u32(block) != 0
+        || read_u16(&block[4..]) as usize != block.len()
+    {
+        return Err(Error::Corrupt {
+            reason: "hash tree node without a covering entry",
+        });
+    }
+    dx_entries(&block[8..])
+}
+
+/// Parses the entries from the limit and count at the start of `bytes`,
+/// which runs to the end of the block
+fn dx_entries(bytes: &[u8]) -> Result<Vec<DxEntry>> {
+    let limit = read_u16(bytes) as usize;
+    let count = read_u16(&bytes[2..]) as usize;
+    if limit != bytes.len() / 8 || count == 0 || count > limit {
+        return Err(Error::Corrupt {
+            reason: "hash tree node with a bad entry count",
+        });
+    }
+
+    let mut entries = Vec::with_capacity(count);
+    for (i, entry) in bytes.chunks_exact(8).take(count).enumerate() {
+        entries.push(DxEntry {
+            // the count and limit take the place of the first hash
+            hash: if i == 0 { 0 } else { read_u32(entry) },
+            // the top byte is reserved
+            block: read_u32(&entry[4..]) & 0x00ff_ffff,
+        });
+    }
+    if entries.windows(2).any(|pair| pair[0].hash > pair[1].hash) {
+        return Err(Error::Corrupt {
+            reason: "hash tree node out of order",
+        });
+    }
+    Ok(entries)
+}
+
+/// The index of the entry of `entries` covering `hash`: the last one whose
+/// hash isn't greater
+pub fn dx_probe(entries: &[DxEntry], hash: u32) -> usize {
+    entries[1..].partition_point(|entry| entry.hash <= hash)
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A name, a hash version and a seed, and the hashes they give
+    type Case<'a> = (&'a [u8], u8, [u32; 4], (u32, u32));
+
+    #[test]
+    fn hashes() {
+        // from `debugfs -R "dx_hash -h <version> -s <seed> <name>"`
+        let seed = [0xbbb4_de0e, 0x3e41_cc4b, 0xd83d_36a0, 0x213a_ea13];
+        let long = b"abcdefghijklmnopqrstuvwxyz0123456789ABCD";
+        let cases: &[Case] = &[
+            (b"name-00000", DX_HASH_LEGACY, seed, (0xbaf1_31b2, 0)),
+            (b"caf\xe9", DX_HASH_LEGACY, seed, (0x65f2_3bce, 0)),
+            (b"caf\xe9", DX_HASH_LEGACY_UNSIGNED, seed, (0x7c38_49d0, 0)),
+            (b"a", DX_HASH_HALF_MD4, [0; 4], (0xd5fa_7d7a, 0xacb4_8187)),
+            (
+                b"name-00000",
+                DX_HASH_HALF_MD4,
+                seed,
+                (0xf521_a746, 0x8143_2860),
+            ),
+            (long, DX_HASH_HALF_MD4, seed, (0x2435_da7e, 0x5436_b58b)),
+            (
+                b"caf\xe9",
+                DX_HASH_HALF_MD4,
+                seed,
+                (0x278f_47fa, 0xc7f0_5bf2),
+            ),
+            (
+                b"caf\xe9",
+                DX_HASH_HALF_MD4_UNSIGNED,
+                seed,
+                (0x90c8_717a, 0xe5fb_24ac),
+            ),
+            (b"a", DX_HASH_TEA, [0; 4], (0x6d0e_a4c0, 0xc189_22df)),
+            (b"name-00000", DX_HASH_TEA, seed, (0x18af_3882, 0x3a94_6008)),
+            (long, DX_HASH_TEA, seed, (0xbc90_0c02, 0xc90d_a02d)),
+            (
+                b"caf\xe9",
+                DX_HASH_TEA_UNSIGNED,
+                seed,
+                (0x1994_d06a, 0x49a1_7443),
+            ),
+        ];
+        for &(name, version, seed, hash) in cases {
+            assert_eq!(dx_hash(name, version, seed), Some(hash));
+        }
+        assert_eq!(dx_hash(b"a", 6, seed), None);
+    }
+
+    #[test]
+    fn root() {
+        let mut block = vec![0; 1024];
+        block[..4].copy_from_slice(&2_u32.to_le_bytes());
+        block[4..6].copy_from_slice(&12_u16.to_le_bytes());
+        block[6] = 1;
+        block[8] = b'.';
+        block[12..16].copy_from_slice(&2_u32.to_le_bytes());
+        block[16..18].copy_from_slice(&1012_u16.to_le_bytes());
+        block[18] = 2;
+        block[20..22].copy_from_slice(b"..");
+        block[28] = DX_HASH_HALF_MD4;
+        block[29] = 8;
+        // limit, count and three entries
+        block[32..34].copy_from_slice(&124_u16.to_le_bytes());
+        block[34..36].copy_from_slice(&3_u16.to_le_bytes());
+        for (i, &(hash, leaf)) in
+            [(0, 1), (0x1000, 2), (0x8000, 3)].iter().enumerate()
+        {
+            let entry = &mut block[32 + i * 8..];
+            if i > 0 {
+                entry[..4].copy_from_slice(&(hash as u32).to_le_bytes());
+            }
+            entry[4..8].copy_from_slice(&(leaf as u32).to_le_bytes());
+        }
+
+        let root = DxRoot::from_block(&block).unwrap();
+        assert_eq!(root.hash_version, DX_HASH_HALF_MD4);
+        assert_eq!(root.indirect_levels, 0);
+        let leaves = [0, 0xfff, 0x1000, 0x7ffe, 0x8000, !1]
+            .iter()
+            .map(|&hash| root.entries[dx_probe(&root.entries, hash)].block)
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, [1, 1, 2, 2, 3, 3]);
+
+        // a count past the limit
+        block[34] = 125;
+        assert!(DxRoot::from_block(&block).is_err());
+        block[34] = 3;
+        block[30] = 2;
+        assert!(DxRoot::from_block(&block).is_err());
+    }
+}
@@ -1,9 +1,14 @@
 use core::fmt::{self, Debug};
+use core::marker::PhantomData;
 use core::mem;
 
-use error::Error;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
 use sector::{Address, SectorSize};
-use volume::Volume;
+use sys::superblock::{FeaturesROnly, Superblock};
+use sys::Fields;
+use volume::{Volume, VolumeCommit};
 
 /// An inode is a structure on the disk that represents a file, directory,
 /// symbolic link, etc. Inodes do not contain the data of the file / directory /
@@ -13,59 +18,59 @@ use volume::Volume;
 /// array of inodes it is responsible for, and conversely every inode within a
 /// file system belongs to one of such tables (and one of such block groups).
 #[repr(C, packed)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Default)]
 pub struct Inode {
     /// Type and Permissions (see below)
-    pub type_perm: TypePerm,
+    pub(crate) type_perm: TypePerm,
     /// User ID
-    pub uid: u16,
+    pub(crate) uid: u16,
     /// Lower 32 bits of size in bytes
-    pub size_low: u32,
+    pub(crate) size_low: u32,
     /// Last Access Time (in POSIX time)
-    pub atime: u32,
+    pub(crate) atime: u32,
     /// Creation Time (in POSIX time)
-    pub ctime: u32,
+    pub(crate) ctime: u32,
     /// Last Modification time (in POSIX time)
-    pub mtime: u32,
+    pub(crate) mtime: u32,
     /// Deletion time (in POSIX time)
-    pub dtime: u32,
+    pub(crate) dtime: u32,
     /// Group ID
-    pub gid: u16,
+    pub(crate) gid: u16,
     /// Count of hard links (directory entries) to this inode. When this
     /// reaches 0, the data blocks are marked as unallocated.
-    pub hard_links: u16,
+    pub(crate) hard_links: u16,
     /// Count of disk sectors (not Ext2 blocks) in use by this inode, not
     /// counting the actual inode structure nor directory entries linking
     /// to the inode.
-    pub sectors_count: u32,
+    pub(crate) sectors_count: u32,
     /// Flags
-    pub flags: Flags,
+    pub(crate) flags: Flags,
     /// Operating System Specific value #1
-    pub _os_specific_1: [u8; 4],
+    pub(crate) os_specific_1: [u8; 4],
     /// Direct block pointers
-    pub direct_pointer: [u32; 12],
+    pub(crate) direct_pointer: [u32; 12],
     /// Singly Indirect Block Pointer (Points to a block that is a list of
     /// block pointers to data)
-    pub indirect_pointer: u32,
+    pub(crate) indirect_pointer: u32,
     /// Doubly Indirect Block Pointer (Points to a block that is a list of
     /// block pointers to Singly Indirect Blocks)
-    pub doubly_indirect: u32,
+    pub(crate) doubly_indirect: u32,
     /// Triply Indirect Block Pointer (Points to a block that is a list of
     /// block pointers to Doubly Indirect Blocks)
-    pub triply_indirect: u32,
+    pub(crate) triply_indirect: u32,
     /// Generation number (Primarily used for NFS)
-    pub gen_number: u32,
+    pub(crate) gen_number: u32,
     /// In Ext2 version 0, this field is reserved. In version >= 1,
     /// Extended attribute block (File ACL).
-    pub ext_attribute_block: u32,
+    pub(crate) ext_attribute_block: u32,
     /// In Ext2 version 0, this field is reserved. In version >= 1, Upper
     /// 32 bits of file size (if feature bit set) if it's a file,
     /// Directory ACL if it's a directory
-    pub size_high: u32,
+    pub(crate) size_high: u32,
     /// Block address of fragment
-    pub frag_block_addr: u32,
+    pub(crate) frag_block_addr: u32,
     /// Operating System Specific Value #2
-    pub _os_specific_2: [u8; 12],
+    pub(crate) os_specific_2: [u8; 12],
 }
 
 impl Debug for Inode {
@@ -82,7 +87,7 @@ impl Debug for Inode {
             .field("hard_links", &{ self.hard_links })
             .field("sectors_count", &{ self.sectors_count })
             .field("flags", &{ self.flags })
-            .field("os_specific_1", &self._os_specific_1)
+            .field("os_specific_1", &self.os_specific_1)
             .field("direct_pointer", &{ self.direct_pointer })
             .field("indirect_pointer", &{ self.indirect_pointer })
             .field("doubly_indirect", &{ self.doubly_indirect })
@@ -91,24 +96,197 @@ impl Debug for Inode {
             .field("ext_attribute_block", &{ self.ext_attribute_block })
             .field("size_high", &{ self.size_high })
             .field("frag_block_addr", &{ self.frag_block_addr })
-            .field("os_specific_2", &self._os_specific_2)
+            .field("os_specific_2", &self.os_specific_2)
             .finish()
     }
 }
 
 impl Inode {
-    ///
-    /// # Safety
-    pub unsafe fn find_inode<S: SectorSize, V: Volume<u8, S>>(
+    getters! {
+        /// Type and Permissions (see below)
+        type_perm: TypePerm;
+        /// User ID
+        uid: u16;
+        /// Lower 32 bits of size in bytes
+        size_low: u32;
+        /// Last Access Time (in POSIX time)
+        atime: u32;
+        /// Creation Time (in POSIX time)
+        ctime: u32;
+        /// Last Modification time (in POSIX time)
+        mtime: u32;
+        /// Deletion time (in POSIX time)
+        dtime: u32;
+        /// Group ID
+        gid: u16;
+        /// Count of hard links (directory entries) to this inode. When this
+        /// reaches 0, the data blocks are marked as unallocated.
+        hard_links: u16;
+        /// Count of disk sectors (not Ext2 blocks) in use by this inode, not
+        /// counting the actual inode structure nor directory entries linking
+        /// to the inode.
+        sectors_count: u32;
+        /// Flags
+        flags: Flags;
+        /// Operating System Specific value #1
+        os_specific_1: [u8; 4];
+        /// Direct block pointers
+        direct_pointer: [u32; 12];
+        /// Singly Indirect Block Pointer (Points to a block that is a list of
+        /// block pointers to data)
+        indirect_pointer: u32;
+        /// Doubly Indirect Block Pointer (Points to a block that is a list of
+        /// block pointers to Singly Indirect Blocks)
+        doubly_indirect: u32;
+        /// Triply Indirect Block Pointer (Points to a block that is a list of
+        /// block pointers to Doubly Indirect Blocks)
+        triply_indirect: u32;
+        /// Generation number (Primarily used for NFS)
+        gen_number: u32;
+        /// In Ext2 version 0, this field is reserved. In version >= 1,
+        /// Extended attribute block (File ACL).
+        ext_attribute_block: u32;
+        /// In Ext2 version 0, this field is reserved. In version >= 1, Upper
+        /// 32 bits of file size (if feature bit set) if it's a file,
+        /// Directory ACL if it's a directory
+        size_high: u32;
+        /// Block address of fragment
+        frag_block_addr: u32;
+        /// Operating System Specific Value #2
+        os_specific_2: [u8; 12];
+    }
+}
+
+/// The fields of an `Inode`, unpacked so that they can be borrowed and
+/// assigned to freely. Converts to and from `Inode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InodeData {
+    /// Type and Permissions (see below)
+    pub type_perm: TypePerm,
+    /// User ID
+    pub uid: u16,
+    /// Lower 32 bits of size in bytes
+    pub size_low: u32,
+    /// Last Access Time (in POSIX time)
+    pub atime: u32,
+    /// Creation Time (in POSIX time)
+    pub ctime: u32,
+    /// Last Modification time (in POSIX time)
+    pub mtime: u32,
+    /// Deletion time (in POSIX time)
+    pub dtime: u32,
+    /// Group ID
+    pub gid: u16,
+    /// Count of hard links (directory entries) to this inode. When this
+    /// reaches 0, the data blocks are marked as unallocated.
+    pub hard_links: u16,
+    /// Count of disk sectors (not Ext2 blocks) in use by this inode, not
+    /// counting the actual inode structure nor directory entries linking
+    /// to the inode.
+    pub sectors_count: u32,
+    /// Flags
+    pub flags: Flags,
+    /// Operating System Specific value #1
+    pub os_specific_1: [u8; 4],
+    /// Direct block pointers
+    pub direct_pointer: [u32; 12],
+    /// Singly Indirect Block Pointer (Points to a block that is a list of
+    /// block pointers to data)
+    pub indirect_pointer: u32,
+    /// Doubly Indirect Block Pointer (Points to a block that is a list of
+    /// block pointers to Singly Indirect Blocks)
+    pub doubly_indirect: u32,
+    /// Triply Indirect Block Pointer (Points to a block that is a list of
+    /// block pointers to Doubly Indirect Blocks)
+    pub triply_indirect: u32,
+    /// Generation number (Primarily used for NFS)
+    pub gen_number: u32,
+    /// In Ext2 version 0, this field is reserved. In version >= 1,
+    /// Extended attribute block (File ACL).
+    pub ext_attribute_block: u32,
+    /// In Ext2 version 0, this field is reserved. In version >= 1, Upper
+    /// 32 bits of file size (if feature bit set) if it's a file,
+    /// Directory ACL if it's a directory
+    pub size_high: u32,
+    /// Block address of fragment
+    pub frag_block_addr: u32,
+    /// Operating System Specific Value #2
+    pub os_specific_2: [u8; 12],
+}
+
+impl From<Inode> for InodeData {
+    fn from(inode: Inode) -> InodeData {
+        InodeData {
+            type_perm: inode.type_perm,
+            uid: inode.uid,
+            size_low: inode.size_low,
+            atime: inode.atime,
+            ctime: inode.ctime,
+            mtime: inode.mtime,
+            dtime: inode.dtime,
+            gid: inode.gid,
+            hard_links: inode.hard_links,
+            sectors_count: inode.sectors_count,
+            flags: inode.flags,
+            os_specific_1: inode.os_specific_1,
+            direct_pointer: inode.direct_pointer,
+            indirect_pointer: inode.indirect_pointer,
+            doubly_indirect: inode.doubly_indirect,
+            triply_indirect: inode.triply_indirect,
+            gen_number: inode.gen_number,
+            ext_attribute_block: inode.ext_attribute_block,
+            size_high: inode.size_high,
+            frag_block_addr: inode.frag_block_addr,
+            os_specific_2: inode.os_specific_2,
+        }
+    }
+}
+
+impl From<InodeData> for Inode {
+    fn from(data: InodeData) -> Inode {
+        Inode {
+            type_perm: data.type_perm,
+            uid: data.uid,
+            size_low: data.size_low,
+            atime: data.atime,
+            ctime: data.ctime,
+            mtime: data.mtime,
+            dtime: data.dtime,
+            gid: data.gid,
+            hard_links: data.hard_links,
+            sectors_count: data.sectors_count,
+            flags: data.flags,
+            os_specific_1: data.os_specific_1,
+            direct_pointer: data.direct_pointer,
+            indirect_pointer: data.indirect_pointer,
+            doubly_indirect: data.doubly_indirect,
+            triply_indirect: data.triply_indirect,
+            gen_number: data.gen_number,
+            ext_attribute_block: data.ext_attribute_block,
+            size_high: data.size_high,
+            frag_block_addr: data.frag_block_addr,
+            os_specific_2: data.os_specific_2,
+        }
+    }
+}
+
+impl Inode {
+    /// Reads the inode at `offset`, which takes up `size` bytes of its
+    /// inode table. Only the first 128 bytes are read: the rest of larger
+    /// inodes is left alone, and `write_inode` leaves it as it is on the
+    /// volume.
+    pub fn find_inode<S: SectorSize, V: Volume<u8, S>>(
         haystack: &V,
         offset: Address<S>,
         size: usize,
-    ) -> Result<(Inode, Address<S>), Error> {
-        if size != mem::size_of::<Inode>() {
-            unimplemented!("inodes with a size != 128");
+    ) -> Result<(Inode, Address<S>)> {
+        if size < mem::size_of::<Inode>() {
+            return Err(Error::BadInodeSize { size });
         }
 
-        let end = offset + Address::from(size);
+        let end = offset
+            .checked_add(Address::from(mem::size_of::<Inode>()))
+            .ok_or(Error::ArithmeticOverflow)?;
         if haystack.size() < end {
             return Err(Error::AddressOutOfBounds {
                 sector: end.sector(),
@@ -117,15 +295,438 @@ impl Inode {
             });
         }
 
-        let inode = haystack
-            .slice_unchecked(offset..end)
-            .dynamic_cast::<Inode>();
+        let slice = haystack.slice(offset..end).map_err(|err| err.into())?;
+        if slice.len() < mem::size_of::<Inode>() {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        let mut bytes = [0; 128];
+        bytes.copy_from_slice(&slice[..128]);
+
+        Ok((Inode::from_bytes(&bytes), offset))
+    }
+
+    /// Writes the inode back to `offset`, which is usually its slot in an
+    /// inode table. Only the first 128 bytes of the slot are touched.
+    pub fn write_inode<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &mut V,
+        offset: Address<S>,
+    ) -> Result<()> {
+        let commit = VolumeCommit::new(self.to_bytes().to_vec(), offset);
+        volume.commit(Some(commit)).map_err(|err| err.into())
+    }
+
+    /// Parses an inode from its on-disk, little-endian representation
+    pub fn from_bytes(bytes: &[u8; 128]) -> Inode {
+        let mut fields = Fields::new(bytes);
+        let mut inode = Inode {
+            // the flags keep bits this crate has no name for, so that they
+            // survive being written back
+            type_perm: unsafe { TypePerm::from_bits_unchecked(fields.u16()) },
+            uid: fields.u16(),
+            size_low: fields.u32(),
+            atime: fields.u32(),
+            ctime: fields.u32(),
+            mtime: fields.u32(),
+            dtime: fields.u32(),
+            gid: fields.u16(),
+            hard_links: fields.u16(),
+            sectors_count: fields.u32(),
+            flags: unsafe { Flags::from_bits_unchecked(fields.u32()) },
+            ..Inode::default()
+        };
+        fields.bytes(&mut inode.os_specific_1);
+        let mut direct_pointer = [0; 12];
+        for pointer in direct_pointer.iter_mut() {
+            *pointer = fields.u32();
+        }
+        inode.direct_pointer = direct_pointer;
+        inode.indirect_pointer = fields.u32();
+        inode.doubly_indirect = fields.u32();
+        inode.triply_indirect = fields.u32();
+        inode.gen_number = fields.u32();
+        inode.ext_attribute_block = fields.u32();
+        inode.size_high = fields.u32();
+        inode.frag_block_addr = fields.u32();
+        fields.bytes(&mut inode.os_specific_2);
+        debug_assert_eq!(fields.offset(), mem::size_of::<Inode>());
+        inode
+    }
+
+    /// The size of the file in bytes on a file system described by
+    /// `superblock`. `size_high` only holds the upper half of the size of
+    /// regular files, and only with the large file feature: directories
+    /// keep their ACL block there, for one.
+    pub fn size(&self, superblock: &Superblock) -> u64 {
+        let large_file = superblock
+            .features_ronly()
+            .contains(FeaturesROnly::RONLY_FILE_SIZE_64);
+        self.size_with(large_file)
+    }
+
+    /// The size of the file like `size`, whether or not the file system has
+    /// the large file feature being `large_file`
+    pub(crate) fn size_with(&self, large_file: bool) -> u64 {
+        let size = self.size_low as u64;
+        match FileType::from_type_perm(self.type_perm) {
+            FileType::File if large_file => {
+                size | (self.size_high as u64) << 32
+            }
+            _ => size,
+        }
+    }
+
+    /// The major and minor numbers of a character or block device, `None`
+    /// for any other kind of file. Linux keeps numbers up to 255 in the old
+    /// 16-bit encoding in the first block pointer, leaving it zero and using
+    /// the second one for the new 32-bit encoding otherwise.
+    pub fn device(&self) -> Option<(u32, u32)> {
+        match FileType::from_type_perm(self.type_perm) {
+            FileType::CharDevice | FileType::BlockDevice => (),
+            _ => return None,
+        }
+        let pointers = { self.direct_pointer };
+        let old = pointers[0];
+        if old != 0 {
+            return Some(((old >> 8) & 0xff, old & 0xff));
+        }
+        let new = pointers[1];
+        Some(((new & 0xfff00) >> 8, (new & 0xff) | ((new >> 12) & 0xfff00)))
+    }
+
+    /// Stores the device number of `major` and `minor` in the new encoding,
+    /// see `device`. Majors take 12 bits and minors 20.
+    pub fn set_device(&mut self, major: u32, minor: u32) {
+        let mut pointers = [0; 12];
+        pointers[1] =
+            (minor & 0xff) | ((major & 0xfff) << 8) | ((minor & !0xff) << 12);
+        self.direct_pointer = pointers;
+    }
+
+    /// Serializes the inode into its on-disk, little-endian representation
+    pub fn to_bytes(&self) -> [u8; 128] {
+        let mut bytes = [0; 128];
+        let mut offset = 0;
+        {
+            let mut put = |field: &[u8]| {
+                bytes[offset..offset + field.len()].copy_from_slice(field);
+                offset += field.len();
+            };
+            put(&{ self.type_perm }.bits().to_le_bytes());
+            put(&{ self.uid }.to_le_bytes());
+            put(&{ self.size_low }.to_le_bytes());
+            put(&{ self.atime }.to_le_bytes());
+            put(&{ self.ctime }.to_le_bytes());
+            put(&{ self.mtime }.to_le_bytes());
+            put(&{ self.dtime }.to_le_bytes());
+            put(&{ self.gid }.to_le_bytes());
+            put(&{ self.hard_links }.to_le_bytes());
+            put(&{ self.sectors_count }.to_le_bytes());
+            put(&{ self.flags }.bits().to_le_bytes());
+            put(&self.os_specific_1);
+            for pointer in { self.direct_pointer }.iter() {
+                put(&pointer.to_le_bytes());
+            }
+            put(&{ self.indirect_pointer }.to_le_bytes());
+            put(&{ self.doubly_indirect }.to_le_bytes());
+            put(&{ self.triply_indirect }.to_le_bytes());
+            put(&{ self.gen_number }.to_le_bytes());
+            put(&{ self.ext_attribute_block }.to_le_bytes());
+            put(&{ self.size_high }.to_le_bytes());
+            put(&{ self.frag_block_addr }.to_le_bytes());
+            put(&self.os_specific_2);
+        }
+        debug_assert_eq!(offset, mem::size_of::<Inode>());
+        bytes
+    }
+
+    /// Walks the blocks of this inode, the data blocks in order and each
+    /// indirect block before those it points at, reading the indirect
+    /// blocks off `volume` of a file system described by `superblock`. No
+    /// more than one indirect block per level is held at a time.
+    pub fn blocks<'a, S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &'a V,
+        superblock: &Superblock,
+    ) -> BlockIter<'a, S, V> {
+        BlockIter {
+            walk: BlockWalk::new(self, superblock),
+            volume,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// A block of an inode, as walked by `BlockWalk`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeBlock {
+    /// A block of the contents of the file, with its index in the file
+    Data { index: u64, block: u32 },
+    /// A block of pointers, `depth` levels of indirect blocks above the
+    /// data, so 1 for a singly indirect block
+    Indirect { block: u32, depth: usize },
+}
+
+impl InodeBlock {
+    /// The number of the block on the volume
+    pub fn block(&self) -> u32 {
+        match *self {
+            InodeBlock::Data { block, .. } => block,
+            InodeBlock::Indirect { block, .. } => block,
+        }
+    }
+}
+
+/// An indirect block being walked
+#[derive(Debug, Clone)]
+struct Level {
+    pointers: Vec<u32>,
+    /// Index of the next pointer to visit
+    next: usize,
+    /// Levels of indirect blocks below the pointers
+    depth: usize,
+}
+
+/// Walks the blocks of an inode like `Inode::blocks`, without borrowing the
+/// volume in between steps: each step is given it instead. This lets the
+/// blocks walked be freed or overwritten along the way, as the indirect
+/// block a step reads is kept until all of its pointers have been visited.
+///
+/// A pointer outside of the file system is `Error::Corrupt`, and so is an
+/// indirect block that can't be read in full; either ends the walk.
+#[derive(Debug, Clone)]
+pub struct BlockWalk {
+    /// The pointers in the inode, or the one the walk starts from, with the
+    /// levels of indirect blocks below each
+    roots: Vec<(u32, usize)>,
+    /// Index of the next root to visit
+    root: usize,
+    levels: Vec<Level>,
+    /// Index in the file of the next data block
+    index: u64,
+    first_data_block: u32,
+    blocks_count: u32,
+    log_block_size: u32,
+    done: bool,
+}
+
+impl BlockWalk {
+    /// Walks every block of `inode`
+    pub fn new(inode: &Inode, superblock: &Superblock) -> BlockWalk {
+        let mut roots = { inode.direct_pointer }
+            .iter()
+            .map(|&block| (block, 0))
+            .collect::<Vec<_>>();
+        roots.push(({ inode.indirect_pointer }, 1));
+        roots.push(({ inode.doubly_indirect }, 2));
+        roots.push(({ inode.triply_indirect }, 3));
+        BlockWalk::with_roots(roots, superblock)
+    }
+
+    /// Walks `block` and, if it's `depth` levels of indirect blocks above
+    /// the data, every block below it. Indices of data blocks are counted
+    /// from the first one below `block`.
+    pub fn tree(
+        block: u32,
+        depth: usize,
+        superblock: &Superblock,
+    ) -> BlockWalk {
+        BlockWalk::with_roots(vec![(block, depth)], superblock)
+    }
+
+    fn with_roots(
+        roots: Vec<(u32, usize)>,
+        superblock: &Superblock,
+    ) -> BlockWalk {
+        BlockWalk {
+            roots,
+            root: 0,
+            levels: Vec::new(),
+            index: 0,
+            first_data_block: superblock.first_data_block,
+            blocks_count: superblock.blocks_count,
+            log_block_size: superblock.log_block_size + 10,
+            done: false,
+        }
+    }
+
+    /// Steps to the next block, reading it off `volume` if it's an indirect
+    /// block
+    pub fn next<S: SectorSize, V: Volume<u8, S>>(
+        &mut self,
+        volume: &V,
+    ) -> Option<Result<InodeBlock>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let (block, depth) = match self.levels.last_mut() {
+                Some(level) if level.next < level.pointers.len() => {
+                    level.next += 1;
+                    (level.pointers[level.next - 1], level.depth)
+                }
+                Some(_) => {
+                    self.levels.pop();
+                    continue;
+                }
+                None if self.root < self.roots.len() => {
+                    self.root += 1;
+                    self.roots[self.root - 1]
+                }
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+
+            if block == 0 {
+                // a hole as big as everything the pointer would reach
+                let pointers = 1_u64 << (self.log_block_size - 2);
+                self.index += pointers.pow(depth as u32);
+                continue;
+            }
+            let visited = self.visit(volume, block, depth);
+            if visited.is_err() {
+                self.done = true;
+            }
+            return Some(visited);
+        }
+    }
+
+    fn visit<S: SectorSize, V: Volume<u8, S>>(
+        &mut self,
+        volume: &V,
+        block: u32,
+        depth: usize,
+    ) -> Result<InodeBlock> {
+        if block < self.first_data_block || block >= self.blocks_count {
+            return Err(Error::Corrupt {
+                reason: "block pointer outside the filesystem",
+            });
+        }
+        if depth == 0 {
+            self.index += 1;
+            return Ok(InodeBlock::Data {
+                index: self.index - 1,
+                block,
+            });
+        }
+
+        let start = Address::with_block_size(block, 0, self.log_block_size);
+        let end = Address::with_block_size(block + 1, 0, self.log_block_size);
+        let data = volume.slice(start..end).map_err(|err| err.into())?;
+        if data.len() < 1 << self.log_block_size {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        let pointers = data
+            .chunks(4)
+            .map(|pointer| {
+                u32::from_le_bytes([
+                    pointer[0], pointer[1], pointer[2], pointer[3],
+                ])
+            })
+            .collect();
+        self.levels.push(Level {
+            pointers,
+            next: 0,
+            depth: depth - 1,
+        });
+        Ok(InodeBlock::Indirect { block, depth })
+    }
+}
+
+/// The way from an inode to one block of its file: the pointer in the inode
+/// to start from, then the index of the pointer to follow in each level of
+/// indirect blocks below it, topmost first. Following it is left to whoever
+/// reads the volume, so that files are mapped the same way whether the
+/// volume blocks or not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPath {
+    /// The pointer in the inode, 0 for a hole
+    pub root: u32,
+    indices: [usize; 3],
+    depth: usize,
+}
+
+impl BlockPath {
+    /// Finds the way to block `index` of `inode`, on a file system with
+    /// blocks of `1 << log_block_size` bytes, or `None` if the block tree
+    /// can't hold a block that far into the file
+    pub fn new(
+        inode: &Inode,
+        index: u64,
+        log_block_size: u32,
+    ) -> Option<BlockPath> {
+        // a block holds block_size / 4 pointers, so each level of indirect
+        // blocks multiplies the blocks below a pointer by that much: the
+        // indirect block maps block_size / 4 blocks, the doubly indirect one
+        // (block_size / 4)^2 and the triply indirect one (block_size / 4)^3
+        let log_bs4 = log_block_size - 2;
+        let bs4 = 1_u64 << log_bs4;
+        let path = |root: u32, depth: usize, index: u64| {
+            let mut indices = [0; 3];
+            for (level, slot) in indices[..depth].iter_mut().enumerate() {
+                let shift = log_bs4 * (depth - 1 - level) as u32;
+                *slot = ((index >> shift) & (bs4 - 1)) as usize;
+            }
+            Some(BlockPath {
+                root,
+                indices,
+                depth,
+            })
+        };
 
-        Ok(inode)
+        if index < 12 {
+            return path(inode.direct_pointer[index as usize], 0, 0);
+        }
+        let index = index - 12;
+        if index < bs4 {
+            return path(inode.indirect_pointer, 1, index);
+        }
+        let index = index - bs4;
+        if index < bs4 * bs4 {
+            return path(inode.doubly_indirect, 2, index);
+        }
+        let index = index - bs4 * bs4;
+        if index < bs4 * bs4 * bs4 {
+            return path(inode.triply_indirect, 3, index);
+        }
+        None
+    }
+
+    /// The index of the pointer to follow in each level of indirect blocks,
+    /// topmost first, none for a direct pointer
+    pub fn indices(&self) -> &[usize] {
+        &self.indices[..self.depth]
+    }
+}
+
+/// Iterator over the blocks of an inode, see `Inode::blocks`
+#[derive(Debug, Clone)]
+pub struct BlockIter<'a, S: SectorSize, V: 'a + Volume<u8, S>> {
+    walk: BlockWalk,
+    volume: &'a V,
+    _phantom: PhantomData<S>,
+}
+
+impl<'a, S: SectorSize, V: Volume<u8, S>> Iterator for BlockIter<'a, S, V> {
+    type Item = Result<InodeBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walk.next(self.volume)
     }
 }
 
 bitflags! {
+    #[derive(Default)]
     pub struct TypePerm: u16 {
         /// FIFO
         const FIFO = 0x1000;
@@ -169,6 +770,83 @@ bitflags! {
 }
 
 bitflags! {
+    /// The permission bits of a mode, the low 12 bits of `TypePerm`
+    #[derive(Default)]
+    pub struct Permissions: u16 {
+        /// Other—execute permission
+        const O_EXEC = 0x001;
+        /// Other—write permission
+        const O_WRITE = 0x002;
+        /// Other—read permission
+        const O_READ = 0x004;
+        /// Group—execute permission
+        const G_EXEC = 0x008;
+        /// Group—write permission
+        const G_WRITE = 0x010;
+        /// Group—read permission
+        const G_READ = 0x020;
+        /// User—execute permission
+        const U_EXEC = 0x040;
+        /// User—write permission
+        const U_WRITE = 0x080;
+        /// User—read permission
+        const U_READ = 0x100;
+        /// Sticky Bit
+        const STICKY = 0x200;
+        /// Set group ID
+        const SET_GID = 0x400;
+        /// Set user ID
+        const SET_UID = 0x800;
+    }
+}
+
+impl TypePerm {
+    /// The mode of a file of type `file_type` with the permission bits of
+    /// `perm`. `FileType::Unknown` leaves the type field zero.
+    pub fn new(file_type: FileType, perm: Permissions) -> TypePerm {
+        let kind = match file_type {
+            FileType::Unknown => TypePerm::empty(),
+            FileType::File => TypePerm::FILE,
+            FileType::Directory => TypePerm::DIRECTORY,
+            FileType::CharDevice => TypePerm::CHAR_DEVICE,
+            FileType::BlockDevice => TypePerm::BLOCK_DEVICE,
+            FileType::Fifo => TypePerm::FIFO,
+            FileType::Socket => TypePerm::SOCKET,
+            FileType::Symlink => TypePerm::SYMLINK,
+        };
+        kind | TypePerm::from_bits_truncate(perm.bits())
+    }
+
+    /// Splits this mode into the type of the file and its permission bits
+    pub fn split(self) -> (FileType, Permissions) {
+        (self.file_type(), self.permissions())
+    }
+
+    /// The permission bits of this mode
+    pub fn permissions(self) -> Permissions {
+        Permissions::from_bits_truncate(self.bits())
+    }
+
+    /// The type of a file with this mode. The type takes up the top four
+    /// bits as a number rather than as flags: a block device has both the
+    /// character device and the directory bit set, so no single bit tells
+    /// the types apart.
+    pub fn file_type(self) -> FileType {
+        match self.bits() & 0xf000 {
+            0x1000 => FileType::Fifo,
+            0x2000 => FileType::CharDevice,
+            0x4000 => FileType::Directory,
+            0x6000 => FileType::BlockDevice,
+            0x8000 => FileType::File,
+            0xa000 => FileType::Symlink,
+            0xc000 => FileType::Socket,
+            _ => FileType::Unknown,
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Default)]
     pub struct Flags: u32 {
         /// Secure deletion (not used)
         const SECURE_DEL = 0x00000001;
@@ -187,7 +865,7 @@ bitflags! {
         /// Last accessed time should not updated
         const DONT_ATIME = 0x00000080;
         /// Hash indexed directory
-        const HASH_DIR = 0x00010000;
+        const HASH_DIR = 0x00001000;
         /// AFS directory
         const AFS_DIR = 0x00020000;
         /// Journal file data
@@ -197,17 +875,146 @@ bitflags! {
 
 /// Unknown entry type
 pub const UNKNOWN: u8 = 0;
-/// FIFO entry type
-pub const FIFO: u8 = 1;
-/// Character device entry type
-pub const CHAR_DEVICE: u8 = 2;
+/// Regular file entry type
+pub const FILE: u8 = 1;
 /// Directory entry type
-pub const DIRECTORY: u8 = 3;
+pub const DIRECTORY: u8 = 2;
+/// Character device entry type
+pub const CHAR_DEVICE: u8 = 3;
 /// Block device entry type
 pub const BLOCK_DEVICE: u8 = 4;
-/// Regular file entry type
-pub const FILE: u8 = 5;
-/// Symbolic link entry type
-pub const SYMLINK: u8 = 6;
+/// FIFO entry type
+pub const FIFO: u8 = 5;
 /// Unix socket entry type
-pub const SOCKET: u8 = 7;
+pub const SOCKET: u8 = 6;
+/// Symbolic link entry type
+pub const SYMLINK: u8 = 7;
+
+/// The type of a file as recorded in a directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Unknown,
+    File,
+    Directory,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    Symlink,
+}
+
+impl FileType {
+    /// The type for the entry type byte `ty`; values past `SYMLINK` are
+    /// `Unknown`
+    pub fn from_dirent(ty: u8) -> FileType {
+        match ty {
+            FILE => FileType::File,
+            DIRECTORY => FileType::Directory,
+            CHAR_DEVICE => FileType::CharDevice,
+            BLOCK_DEVICE => FileType::BlockDevice,
+            FIFO => FileType::Fifo,
+            SOCKET => FileType::Socket,
+            SYMLINK => FileType::Symlink,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// The type of a file with mode `type_perm`, see `TypePerm::file_type`
+    pub fn from_type_perm(type_perm: TypePerm) -> FileType {
+        type_perm.file_type()
+    }
+
+    /// The entry type byte for this type
+    pub fn dirent(self) -> u8 {
+        match self {
+            FileType::Unknown => UNKNOWN,
+            FileType::File => FILE,
+            FileType::Directory => DIRECTORY,
+            FileType::CharDevice => CHAR_DEVICE,
+            FileType::BlockDevice => BLOCK_DEVICE,
+            FileType::Fifo => FIFO,
+            FileType::Socket => SOCKET,
+            FileType::Symlink => SYMLINK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileType, Permissions, TypePerm};
+
+    #[test]
+    fn file_types() {
+        let types = [
+            FileType::Unknown,
+            FileType::Fifo,
+            FileType::CharDevice,
+            FileType::Unknown,
+            FileType::Directory,
+            FileType::Unknown,
+            FileType::BlockDevice,
+            FileType::Unknown,
+            FileType::File,
+            FileType::Unknown,
+            FileType::Symlink,
+            FileType::Unknown,
+            FileType::Socket,
+            FileType::Unknown,
+            FileType::Unknown,
+            FileType::Unknown,
+        ];
+        for (nibble, &file_type) in types.iter().enumerate() {
+            // the permission bits make no difference
+            for &perm in &[0, 0o644, 0o7777] {
+                let bits = (nibble as u16) << 12 | perm;
+                let type_perm = TypePerm::from_bits_truncate(bits);
+                assert_eq!(type_perm.bits(), bits);
+                assert_eq!(type_perm.file_type(), file_type, "{:o}", bits);
+                assert_eq!(FileType::from_type_perm(type_perm), file_type);
+            }
+            assert_eq!(FileType::from_dirent(file_type.dirent()), file_type);
+        }
+
+        // a block device has every bit of a character device and of a
+        // directory
+        let block = TypePerm::BLOCK_DEVICE;
+        assert!(block.contains(TypePerm::CHAR_DEVICE | TypePerm::DIRECTORY));
+        assert_eq!(block.file_type(), FileType::BlockDevice);
+        assert_eq!(TypePerm::SYMLINK.file_type(), FileType::Symlink);
+        assert_eq!(TypePerm::SOCKET.file_type(), FileType::Socket);
+    }
+
+    #[test]
+    fn split() {
+        let perm = Permissions::from_bits_truncate(0o4755);
+        assert!(perm.contains(Permissions::SET_UID | Permissions::U_EXEC));
+        for &file_type in &[
+            FileType::File,
+            FileType::Directory,
+            FileType::CharDevice,
+            FileType::BlockDevice,
+            FileType::Fifo,
+            FileType::Socket,
+            FileType::Symlink,
+        ] {
+            let type_perm = TypePerm::new(file_type, perm);
+            assert_eq!(type_perm.bits() & 0o7777, 0o4755);
+            assert_eq!(type_perm.split(), (file_type, perm));
+        }
+        assert_eq!(
+            TypePerm::new(FileType::File, Permissions::U_READ),
+            TypePerm::FILE | TypePerm::U_READ
+        );
+        assert_eq!(TypePerm::new(FileType::Unknown, perm).bits(), 0o4755);
+    }
+
+    #[test]
+    fn block_device_is_not_a_directory() {
+        let perm = Permissions::from_bits_truncate(0o660);
+        let (file_type, split) =
+            TypePerm::new(FileType::BlockDevice, perm).split();
+        assert_eq!(file_type, FileType::BlockDevice);
+        assert_ne!(file_type, FileType::Directory);
+        assert_eq!(split, perm);
+    }
+}
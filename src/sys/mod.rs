@@ -1,3 +1,74 @@
+/// Defines a public getter for each of the listed fields of a packed
+/// structure, returning a copy of the field. Taking a reference to a field
+/// of a packed structure is undefined behaviour if it's unaligned, copying it
+/// never is.
+macro_rules! getters {
+    ($($(#[$attr:meta])* $field:ident: $ty:ty;)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $field(&self) -> $ty {
+                self.$field
+            }
+        )*
+    };
+}
+
 pub mod superblock;
 pub mod block_group;
 pub mod inode;
+pub mod bitmap;
+pub mod summary;
+pub mod dirent;
+pub mod htree;
+pub mod xattr;
+pub mod acl;
+
+/// Reads the fields of an on-disk structure one after the other, each
+/// little-endian
+pub(crate) struct Fields<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(bytes: &'a [u8]) -> Fields<'a> {
+        Fields { bytes, offset: 0 }
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Fills `field` with the next bytes
+    pub fn bytes(&mut self, field: &mut [u8]) {
+        field.copy_from_slice(&self.bytes[self.offset..][..field.len()]);
+        self.offset += field.len();
+    }
+
+    pub fn u8(&mut self) -> u8 {
+        let mut buf = [0; 1];
+        self.bytes(&mut buf);
+        buf[0]
+    }
+
+    pub fn u16(&mut self) -> u16 {
+        let mut buf = [0; 2];
+        self.bytes(&mut buf);
+        u16::from_le_bytes(buf)
+    }
+
+    pub fn i16(&mut self) -> i16 {
+        self.u16() as i16
+    }
+
+    pub fn u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.bytes(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    pub fn i32(&mut self) -> i32 {
+        self.u32() as i32
+    }
+}
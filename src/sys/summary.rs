@@ -0,0 +1,346 @@
+//! Human-readable reports of the superblock and the block group descriptor
+//! table, laid out like the output of `dumpe2fs`
+
+use core::fmt::{self, Display};
+use core::mem;
+use core::str;
+
+use sys::block_group::BlockGroupDescriptor;
+use sys::superblock::*;
+
+/// Names of the optional features, by bit, as `e2fsprogs` spells them
+const OPTIONAL_NAMES: [&str; 6] = [
+    "dir_prealloc",
+    "imagic_inodes",
+    "has_journal",
+    "ext_attr",
+    "resize_inode",
+    "dir_index",
+];
+/// Names of the required features, by bit
+const REQUIRED_NAMES: [&str; 4] =
+    ["compression", "filetype", "needs_recovery", "journal_dev"];
+/// Names of the read-only features, by bit
+const RONLY_NAMES: [&str; 3] = ["sparse_super", "large_file", "btree_dir"];
+
+/// The report returned by `Superblock::summary`
+pub struct SuperblockSummary<'a> {
+    superblock: &'a Superblock,
+}
+
+/// The report returned by `BlockGroupDescriptor::table_summary`
+pub struct TableSummary<'a> {
+    superblock: &'a Superblock,
+    table: &'a [BlockGroupDescriptor],
+}
+
+impl Superblock {
+    /// Returns a report of the superblock's fields, one per line, as
+    /// `dumpe2fs -h` would print them
+    pub fn summary(&self) -> SuperblockSummary<'_> {
+        SuperblockSummary { superblock: self }
+    }
+}
+
+impl BlockGroupDescriptor {
+    /// Returns a report of where the metadata of each group in `table` is
+    /// and how much of it is free, as `dumpe2fs` would print it after the
+    /// superblock
+    pub fn table_summary<'a>(
+        table: &'a [BlockGroupDescriptor],
+        superblock: &'a Superblock,
+    ) -> TableSummary<'a> {
+        TableSummary { superblock, table }
+    }
+}
+
+/// Writes a line of `key` and `value`, with the values of consecutive lines
+/// lined up
+fn line(f: &mut fmt::Formatter, key: &str, value: &dyn Display) -> fmt::Result {
+    writeln!(f, "{:<26}{}", key, value)
+}
+
+/// Displays a C-style string, or `default` if it's empty
+struct Name<'a>(&'a [u8], &'static str);
+
+impl<'a> Display for Name<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match str::from_utf8(self.0) {
+            _ if self.0.is_empty() => f.write_str(self.1),
+            Ok(name) => f.write_str(name),
+            Err(_) => write!(f, "{}", self.0.escape_ascii()),
+        }
+    }
+}
+
+/// Displays the names of the features set, separated by spaces
+struct Features(FeaturesOptional, FeaturesRequired, FeaturesROnly);
+
+impl Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = [
+            (self.0.bits(), &OPTIONAL_NAMES[..], 'C'),
+            (self.1.bits(), &REQUIRED_NAMES[..], 'I'),
+            (self.2.bits(), &RONLY_NAMES[..], 'R'),
+        ];
+        let mut first = true;
+        for &(bits, names, kind) in words.iter() {
+            for bit in (0..32).filter(|bit| bits & (1 << bit) != 0) {
+                if !first {
+                    f.write_str(" ")?;
+                }
+                first = false;
+                match names.get(bit) {
+                    Some(name) => f.write_str(name)?,
+                    // what e2fsprogs calls features it doesn't know
+                    None => write!(f, "FEATURE_{}{}", kind, bit)?,
+                }
+            }
+        }
+        if first {
+            f.write_str("(none)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Displays a POSIX time like `ctime(3)` does, in UTC, or `n/a` if it's 0
+struct Time(u32);
+
+impl Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const DAYS: [&str; 7] =
+            ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep",
+            "Oct", "Nov", "Dec",
+        ];
+        if self.0 == 0 {
+            return f.write_str("n/a");
+        }
+        let days = self.0 / 86400;
+        let seconds = self.0 % 86400;
+
+        // the days since March 1st, 0000 in 400 year eras, which end with
+        // leap days
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+            - day_of_era / 146_096)
+            / 365;
+        let day_of_year = day_of_era
+            - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 2 } else { month - 10 };
+        let year = year_of_era + era * 400 + if month < 2 { 1 } else { 0 };
+
+        write!(
+            f,
+            "{} {} {:>2} {:02}:{:02}:{:02} {}",
+            // 1970-01-01 was a Thursday
+            DAYS[((self.0 / 86400 + 4) % 7) as usize],
+            MONTHS[month as usize],
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            year
+        )
+    }
+}
+
+impl<'a> Display for SuperblockSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sb = self.superblock;
+        let state = if sb.state() & FS_CLEAN != 0 {
+            "clean"
+        } else {
+            "not clean"
+        };
+        let errors = if sb.state() & FS_ERR != 0 {
+            " with errors"
+        } else {
+            ""
+        };
+        let errors_behavior = match sb.errors() {
+            ERR_IGNORE => "Continue",
+            ERR_RONLY => "Remount read-only",
+            ERR_PANIC => "Panic",
+            _ => "Unknown (continue)",
+        };
+        let os = match sb.creator_os() {
+            OS_LINUX => "Linux",
+            OS_HURD => "GNU/Hurd",
+            OS_MASIX => "Masix",
+            OS_FREEBSD => "FreeBSD",
+            OS_LITE => "Lites",
+            _ => "(unknown os)",
+        };
+        let revision = match sb.rev_major() {
+            0 => " (original)",
+            1 => " (dynamic)",
+            _ => " (unknown)",
+        };
+        let inode_blocks = sb.inodes_per_group() as usize
+            * sb.inode_size() as usize
+            / sb.block_size();
+
+        line(
+            f,
+            "Filesystem volume name:",
+            &Name(sb.volume_name(), "<none>"),
+        )?;
+        line(
+            f,
+            "Last mounted on:",
+            &Name(sb.last_mounted(), "<not available>"),
+        )?;
+        line(f, "Filesystem UUID:", &sb.uuid())?;
+        line(
+            f,
+            "Filesystem magic number:",
+            &format_args!("{:#06X}", sb.magic()),
+        )?;
+        line(
+            f,
+            "Filesystem revision #:",
+            &format_args!("{}{}", sb.rev_major(), revision),
+        )?;
+        line(
+            f,
+            "Filesystem features:",
+            &Features(
+                sb.features_opt(),
+                sb.features_req(),
+                sb.features_ronly(),
+            ),
+        )?;
+        line(f, "Filesystem state:", &format_args!("{}{}", state, errors))?;
+        line(f, "Errors behavior:", &errors_behavior)?;
+        line(f, "Filesystem OS type:", &os)?;
+        line(f, "Inode count:", &sb.inodes_count())?;
+        line(f, "Block count:", &sb.blocks_count())?;
+        line(f, "Reserved block count:", &sb.reserved_blocks())?;
+        line(f, "Free blocks:", &sb.free_blocks_count())?;
+        line(f, "Free inodes:", &sb.free_inodes_count())?;
+        line(f, "First block:", &sb.first_data_block())?;
+        line(f, "Block size:", &sb.block_size())?;
+        line(f, "Fragment size:", &sb.frag_size())?;
+        if sb.reserved_gdt_blocks() != 0 {
+            line(f, "Reserved GDT blocks:", &sb.reserved_gdt_blocks())?;
+        }
+        line(f, "Blocks per group:", &sb.blocks_per_group())?;
+        line(f, "Fragments per group:", &sb.frags_per_group())?;
+        line(f, "Inodes per group:", &sb.inodes_per_group())?;
+        line(f, "Inode blocks per group:", &inode_blocks)?;
+        line(f, "Last mount time:", &Time(sb.mtime()))?;
+        line(f, "Last write time:", &Time(sb.wtime()))?;
+        line(f, "Mount count:", &sb.mnt_count())?;
+        line(f, "Maximum mount count:", &sb.max_mnt_count())?;
+        line(f, "Last checked:", &Time(sb.lastcheck()))?;
+        if sb.checkinterval() == 0 {
+            line(f, "Check interval:", &"0 (<none>)")?;
+        } else {
+            line(f, "Check interval:", &sb.checkinterval())?;
+        }
+        line(f, "Reserved blocks uid:", &sb.block_uid())?;
+        line(f, "Reserved blocks gid:", &sb.block_gid())?;
+        line(f, "First inode:", &sb.first_inode())?;
+        line(f, "Inode size:", &sb.inode_size())
+    }
+}
+
+impl<'a> Display for TableSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sb = self.superblock;
+        let descriptor_blocks =
+            mem::size_of_val(self.table).div_ceil(sb.block_size()) as u32;
+        for (group, descriptor) in self.table.iter().enumerate() {
+            let group = group as u32;
+            let start = sb.first_data_block() + group * sb.blocks_per_group();
+            let end = (start + sb.blocks_per_group()).min(sb.blocks_count());
+            writeln!(f, "Group {}: (Blocks {}-{})", group, start, end - 1)?;
+            if sb.has_backup(group) {
+                let kind = if group == 0 { "Primary" } else { "Backup" };
+                writeln!(
+                    f,
+                    "  {} superblock at {}, Group descriptors at {}-{}",
+                    kind,
+                    start,
+                    start + 1,
+                    start + descriptor_blocks
+                )?;
+                let reserved = sb.reserved_gdt_blocks() as u32;
+                if reserved != 0 {
+                    let first = start + descriptor_blocks + 1;
+                    writeln!(
+                        f,
+                        "  Reserved GDT blocks at {}-{}",
+                        first,
+                        first + reserved - 1
+                    )?;
+                }
+            }
+            let at = |f: &mut fmt::Formatter, what: &str, block: u32| {
+                write!(f, "  {} at {}", what, block)
+            };
+            at(f, "Block bitmap", descriptor.block_usage_addr())?;
+            writeln!(f, " (+{})", descriptor.block_usage_addr() - start)?;
+            at(f, "Inode bitmap", descriptor.inode_usage_addr())?;
+            writeln!(f, " (+{})", descriptor.inode_usage_addr() - start)?;
+            let inode_blocks =
+                (sb.inodes_per_group() as usize * sb.inode_size() as usize)
+                    .div_ceil(sb.block_size()) as u32;
+            at(f, "Inode table", descriptor.inode_table_block())?;
+            writeln!(
+                f,
+                "-{} (+{})",
+                descriptor.inode_table_block() + inode_blocks - 1,
+                descriptor.inode_table_block() - start
+            )?;
+            writeln!(
+                f,
+                "  {} free blocks, {} free inodes, {} directories",
+                descriptor.free_blocks_count(),
+                descriptor.free_inodes_count(),
+                descriptor.dirs_count()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sys::block_group::BlockGroupDescriptor;
+    use sys::superblock::Superblock;
+
+    use super::Time;
+
+    #[test]
+    fn time() {
+        assert_eq!(Time(0).to_string(), "n/a");
+        assert_eq!(Time(1).to_string(), "Thu Jan  1 00:00:01 1970");
+        assert_eq!(Time(951_782_400).to_string(), "Tue Feb 29 00:00:00 2000");
+        assert_eq!(Time(1_521_668_110).to_string(), "Wed Mar 21 21:35:10 2018");
+        assert_eq!(Time(u32::MAX).to_string(), "Sun Feb  7 06:28:15 2106");
+    }
+
+    #[test]
+    fn golden() {
+        let superblock = Superblock::from_bytes(include_bytes!(
+            "../../fixtures/superblock.bin"
+        ));
+        let descriptor = BlockGroupDescriptor::from_bytes(include_bytes!(
+            "../../fixtures/block_group.bin"
+        ));
+        let report = format!(
+            "{}\n\n{}",
+            superblock.summary(),
+            BlockGroupDescriptor::table_summary(&[descriptor], &superblock)
+        );
+        assert_eq!(report, include_str!("../../fixtures/dumpe2fs.txt"));
+    }
+}
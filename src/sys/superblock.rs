@@ -1,9 +1,14 @@
-use core::fmt::{self, Debug};
+use core::fmt::{self, Debug, Display};
 use core::mem;
+use core::str;
 
-use error::Error;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
 use sector::{Address, SectorSize};
-use volume::Volume;
+use sys::inode::Inode;
+use sys::Fields;
+use volume::{Volume, VolumeCommit};
 
 /// Ext2 signature (0xef53), used to help confirm the presence of Ext2 on a
 /// volume
@@ -32,6 +37,11 @@ pub const OS_FREEBSD: u32 = 3;
 /// Creator OS is a BSD4.4-Lite derivative
 pub const OS_LITE: u32 = 4;
 
+/// Directory hashes were computed treating names as signed chars
+pub const FLAGS_SIGNED_HASH: u32 = 0x1;
+/// Directory hashes were computed treating names as unsigned chars
+pub const FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
 /// The Superblock contains all information about the layout of the file system
 /// and possibly contains other important information like what optional
 /// features were used to create the file system.
@@ -45,105 +55,114 @@ pub const OS_LITE: u32 = 4;
 pub struct Superblock {
     // taken from https://wiki.osdev.org/Ext2
     /// Total number of inodes in file system
-    pub inodes_count: u32,
+    pub(crate) inodes_count: u32,
     /// Total number of blocks in file system
-    pub blocks_count: u32,
+    pub(crate) blocks_count: u32,
     /// Number of blocks reserved for superuser (see offset 80)
-    pub r_blocks_count: u32,
+    pub(crate) r_blocks_count: u32,
     /// Total number of unallocated blocks
-    pub free_blocks_count: u32,
+    pub(crate) free_blocks_count: u32,
     /// Total number of unallocated inodes
-    pub free_inodes_count: u32,
+    pub(crate) free_inodes_count: u32,
     /// Block number of the block containing the superblock
-    pub first_data_block: u32,
+    pub(crate) first_data_block: u32,
     /// log2 (block size) - 10. (In other words, the number to shift 1,024
     /// to the left by to obtain the block size)
-    pub log_block_size: u32,
+    pub(crate) log_block_size: u32,
     /// log2 (fragment size) - 10. (In other words, the number to shift
     /// 1,024 to the left by to obtain the fragment size)
-    pub log_frag_size: i32,
+    pub(crate) log_frag_size: i32,
     /// Number of blocks in each block group
-    pub blocks_per_group: u32,
+    pub(crate) blocks_per_group: u32,
     /// Number of fragments in each block group
-    pub frags_per_group: u32,
+    pub(crate) frags_per_group: u32,
     /// Number of inodes in each block group
-    pub inodes_per_group: u32,
+    pub(crate) inodes_per_group: u32,
     /// Last mount time (in POSIX time)
-    pub mtime: u32,
+    pub(crate) mtime: u32,
     /// Last written time (in POSIX time)
-    pub wtime: u32,
+    pub(crate) wtime: u32,
     /// Number of times the volume has been mounted since its last
     /// consistency check (fsck)
-    pub mnt_count: u16,
+    pub(crate) mnt_count: u16,
     /// Number of mounts allowed before a consistency check (fsck) must be
     /// done
-    pub max_mnt_count: i16,
+    pub(crate) max_mnt_count: i16,
     /// Ext2 signature (0xef53), used to help confirm the presence of Ext2
     /// on a volume
-    pub magic: u16,
+    pub(crate) magic: u16,
     /// File system state (see `FS_CLEAN` and `FS_ERR`)
-    pub state: u16,
+    pub(crate) state: u16,
     /// What to do when an error is detected (see `ERR_IGNORE`, `ERR_RONLY` and
     /// `ERR_PANIC`)
-    pub errors: u16,
+    pub(crate) errors: u16,
     /// Minor portion of version (combine with Major portion below to
     /// construct full version field)
-    pub rev_minor: u16,
+    pub(crate) rev_minor: u16,
     /// POSIX time of last consistency check (fsck)
-    pub lastcheck: u32,
+    pub(crate) lastcheck: u32,
     /// Interval (in POSIX time) between forced consistency checks (fsck)
-    pub checkinterval: u32,
+    pub(crate) checkinterval: u32,
     /// Operating system ID from which the filesystem on this volume was
     /// created
-    pub creator_os: u32,
+    pub(crate) creator_os: u32,
     /// Major portion of version (combine with Minor portion above to
     /// construct full version field)
-    pub rev_major: u32,
+    pub(crate) rev_major: u32,
     /// User ID that can use reserved blocks
-    pub block_uid: u16,
+    pub(crate) block_uid: u16,
     /// Group ID that can use reserved blocks
-    pub block_gid: u16,
+    pub(crate) block_gid: u16,
 
     /// First non-reserved inode in file system.
-    pub first_inode: u32,
+    pub(crate) first_inode: u32,
     /// SectorSize of each inode structure in bytes.
-    pub inode_size: u16,
+    pub(crate) inode_size: u16,
     /// Block group that this superblock is part of (if backup copy)
-    pub block_group: u16,
+    pub(crate) block_group: u16,
     /// Optional features present (features that are not required to read
     /// or write, but usually result in a performance increase)
-    pub features_opt: FeaturesOptional,
+    pub(crate) features_opt: FeaturesOptional,
     /// Required features present (features that are required to be
     /// supported to read or write)
-    pub features_req: FeaturesRequired,
+    pub(crate) features_req: FeaturesRequired,
     /// Features that if not supported, the volume must be mounted
     /// read-only)
-    pub features_ronly: FeaturesROnly,
+    pub(crate) features_ronly: FeaturesROnly,
     /// File system ID (what is output by blkid)
-    pub fs_id: [u8; 16],
+    pub(crate) fs_id: [u8; 16],
     /// Volume name (C-style string: characters terminated by a 0 byte)
-    pub volume_name: [u8; 16],
+    pub(crate) volume_name: [u8; 16],
     /// Path volume was last mounted to (C-style string: characters
     /// terminated by a 0 byte)
-    pub last_mnt_path: [u8; 64],
+    pub(crate) last_mnt_path: [u8; 64],
     /// Compression algorithms used (see Required features above)
-    pub compression: u32,
+    pub(crate) compression: u32,
     /// Number of blocks to preallocate for files
-    pub prealloc_blocks_files: u8,
+    pub(crate) prealloc_blocks_files: u8,
     /// Number of blocks to preallocate for directories
-    pub prealloc_blocks_dirs: u8,
+    pub(crate) prealloc_blocks_dirs: u8,
     #[doc(hidden)]
     _unused: [u8; 2],
     /// Journal ID (same style as the File system ID above)
-    pub journal_id: [u8; 16],
+    pub(crate) journal_id: [u8; 16],
     /// Journal inode
-    pub journal_inode: u32,
+    pub(crate) journal_inode: u32,
     /// Journal device
-    pub journal_dev: u32,
+    pub(crate) journal_dev: u32,
     /// Head of orphan inode list
-    pub journal_orphan_head: u32,
+    pub(crate) journal_orphan_head: u32,
+    /// Seed of the hash of names in indexed directories
+    pub(crate) hash_seed: [u32; 4],
+    /// Hash of names used by default for new indexed directories
+    pub(crate) def_hash_version: u8,
     #[doc(hidden)]
-    _reserved: [u8; 788],
+    _unused_ext4: [u8; 99],
+    /// Miscellaneous flags (see `FLAGS_SIGNED_HASH` and
+    /// `FLAGS_UNSIGNED_HASH`)
+    pub(crate) flags: u32,
+    #[doc(hidden)]
+    _reserved: [u8; 668],
 }
 
 impl Debug for Superblock {
@@ -190,18 +209,357 @@ impl Debug for Superblock {
             .field("journal_inode", &{ self.journal_inode })
             .field("journal_dev", &{ self.journal_dev })
             .field("journal_orphan_head", &{ self.journal_orphan_head })
+            .field("hash_seed", &{ self.hash_seed })
+            .field("def_hash_version", &self.def_hash_version)
+            .field("flags", &{ self.flags })
             .finish()
     }
 }
 
 impl Superblock {
-    ///
-    /// # Safety
-    pub unsafe fn find<S: SectorSize, V: Volume<u8, S>>(
+    getters! {
+        /// Total number of inodes in file system
+        inodes_count: u32;
+        /// Total number of blocks in file system
+        blocks_count: u32;
+        /// Number of blocks reserved for superuser (see offset 80)
+        r_blocks_count: u32;
+        /// Total number of unallocated blocks
+        free_blocks_count: u32;
+        /// Total number of unallocated inodes
+        free_inodes_count: u32;
+        /// Block number of the block containing the superblock
+        first_data_block: u32;
+        /// log2 (block size) - 10. (In other words, the number to shift 1,024
+        /// to the left by to obtain the block size)
+        log_block_size: u32;
+        /// log2 (fragment size) - 10. (In other words, the number to shift
+        /// 1,024 to the left by to obtain the fragment size)
+        log_frag_size: i32;
+        /// Number of blocks in each block group
+        blocks_per_group: u32;
+        /// Number of fragments in each block group
+        frags_per_group: u32;
+        /// Number of inodes in each block group
+        inodes_per_group: u32;
+        /// Last mount time (in POSIX time)
+        mtime: u32;
+        /// Last written time (in POSIX time)
+        wtime: u32;
+        /// Number of times the volume has been mounted since its last
+        /// consistency check (fsck)
+        mnt_count: u16;
+        /// Number of mounts allowed before a consistency check (fsck) must be
+        /// done
+        max_mnt_count: i16;
+        /// Ext2 signature (0xef53), used to help confirm the presence of Ext2
+        /// on a volume
+        magic: u16;
+        /// File system state (see `FS_CLEAN` and `FS_ERR`)
+        state: u16;
+        /// What to do when an error is detected (see `ERR_IGNORE`,
+        /// `ERR_RONLY` and `ERR_PANIC`)
+        errors: u16;
+        /// Minor portion of version (combine with Major portion below to
+        /// construct full version field)
+        rev_minor: u16;
+        /// POSIX time of last consistency check (fsck)
+        lastcheck: u32;
+        /// Interval (in POSIX time) between forced consistency checks (fsck)
+        checkinterval: u32;
+        /// Operating system ID from which the filesystem on this volume was
+        /// created
+        creator_os: u32;
+        /// Major portion of version (combine with Minor portion above to
+        /// construct full version field)
+        rev_major: u32;
+        /// User ID that can use reserved blocks
+        block_uid: u16;
+        /// Group ID that can use reserved blocks
+        block_gid: u16;
+        /// Block group that this superblock is part of (if backup copy)
+        block_group: u16;
+        /// Compression algorithms used (see Required features above)
+        compression: u32;
+        /// Number of blocks to preallocate for files
+        prealloc_blocks_files: u8;
+        /// Number of blocks to preallocate for directories
+        prealloc_blocks_dirs: u8;
+        /// Journal ID (same style as the File system ID above)
+        journal_id: [u8; 16];
+        /// Journal inode
+        journal_inode: u32;
+        /// Journal device
+        journal_dev: u32;
+        /// Head of orphan inode list
+        journal_orphan_head: u32;
+        /// Seed of the hash of names in indexed directories
+        hash_seed: [u32; 4];
+        /// Hash of names used by default for new indexed directories
+        def_hash_version: u8;
+        /// Miscellaneous flags (see `FLAGS_SIGNED_HASH` and
+        /// `FLAGS_UNSIGNED_HASH`)
+        flags: u32;
+    }
+}
+
+/// The fields of a `Superblock`, unpacked so that they can be borrowed and
+/// assigned to freely. Converts to and from `Superblock`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SuperblockData {
+    /// Total number of inodes in file system
+    pub inodes_count: u32,
+    /// Total number of blocks in file system
+    pub blocks_count: u32,
+    /// Number of blocks reserved for superuser (see offset 80)
+    pub r_blocks_count: u32,
+    /// Total number of unallocated blocks
+    pub free_blocks_count: u32,
+    /// Total number of unallocated inodes
+    pub free_inodes_count: u32,
+    /// Block number of the block containing the superblock
+    pub first_data_block: u32,
+    /// log2 (block size) - 10. (In other words, the number to shift 1,024
+    /// to the left by to obtain the block size)
+    pub log_block_size: u32,
+    /// log2 (fragment size) - 10. (In other words, the number to shift
+    /// 1,024 to the left by to obtain the fragment size)
+    pub log_frag_size: i32,
+    /// Number of blocks in each block group
+    pub blocks_per_group: u32,
+    /// Number of fragments in each block group
+    pub frags_per_group: u32,
+    /// Number of inodes in each block group
+    pub inodes_per_group: u32,
+    /// Last mount time (in POSIX time)
+    pub mtime: u32,
+    /// Last written time (in POSIX time)
+    pub wtime: u32,
+    /// Number of times the volume has been mounted since its last
+    /// consistency check (fsck)
+    pub mnt_count: u16,
+    /// Number of mounts allowed before a consistency check (fsck) must be
+    /// done
+    pub max_mnt_count: i16,
+    /// Ext2 signature (0xef53), used to help confirm the presence of Ext2
+    /// on a volume
+    pub magic: u16,
+    /// File system state (see `FS_CLEAN` and `FS_ERR`)
+    pub state: u16,
+    /// What to do when an error is detected (see `ERR_IGNORE`, `ERR_RONLY` and
+    /// `ERR_PANIC`)
+    pub errors: u16,
+    /// Minor portion of version (combine with Major portion below to
+    /// construct full version field)
+    pub rev_minor: u16,
+    /// POSIX time of last consistency check (fsck)
+    pub lastcheck: u32,
+    /// Interval (in POSIX time) between forced consistency checks (fsck)
+    pub checkinterval: u32,
+    /// Operating system ID from which the filesystem on this volume was
+    /// created
+    pub creator_os: u32,
+    /// Major portion of version (combine with Minor portion above to
+    /// construct full version field)
+    pub rev_major: u32,
+    /// User ID that can use reserved blocks
+    pub block_uid: u16,
+    /// Group ID that can use reserved blocks
+    pub block_gid: u16,
+    /// First non-reserved inode in file system.
+    pub first_inode: u32,
+    /// SectorSize of each inode structure in bytes.
+    pub inode_size: u16,
+    /// Block group that this superblock is part of (if backup copy)
+    pub block_group: u16,
+    /// Optional features present (features that are not required to read
+    /// or write, but usually result in a performance increase)
+    pub features_opt: FeaturesOptional,
+    /// Required features present (features that are required to be
+    /// supported to read or write)
+    pub features_req: FeaturesRequired,
+    /// Features that if not supported, the volume must be mounted
+    /// read-only)
+    pub features_ronly: FeaturesROnly,
+    /// File system ID (what is output by blkid)
+    pub fs_id: [u8; 16],
+    /// Volume name (C-style string: characters terminated by a 0 byte)
+    pub volume_name: [u8; 16],
+    /// Path volume was last mounted to (C-style string: characters
+    /// terminated by a 0 byte)
+    pub last_mnt_path: [u8; 64],
+    /// Compression algorithms used (see Required features above)
+    pub compression: u32,
+    /// Number of blocks to preallocate for files
+    pub prealloc_blocks_files: u8,
+    /// Number of blocks to preallocate for directories
+    pub prealloc_blocks_dirs: u8,
+    /// Unused, carried along so that conversions round trip
+    pub unused: [u8; 2],
+    /// Journal ID (same style as the File system ID above)
+    pub journal_id: [u8; 16],
+    /// Journal inode
+    pub journal_inode: u32,
+    /// Journal device
+    pub journal_dev: u32,
+    /// Head of orphan inode list
+    pub journal_orphan_head: u32,
+    /// Seed of the hash of names in indexed directories
+    pub hash_seed: [u32; 4],
+    /// Hash of names used by default for new indexed directories
+    pub def_hash_version: u8,
+    /// Fields of later revisions, carried along so that conversions round
+    /// trip
+    pub unused_ext4: [u8; 99],
+    /// Miscellaneous flags (see `FLAGS_SIGNED_HASH` and
+    /// `FLAGS_UNSIGNED_HASH`)
+    pub flags: u32,
+    /// Reserved, carried along so that conversions round trip
+    pub reserved: [u8; 668],
+}
+
+impl Default for SuperblockData {
+    fn default() -> SuperblockData {
+        Superblock::default().into()
+    }
+}
+
+impl From<Superblock> for SuperblockData {
+    fn from(superblock: Superblock) -> SuperblockData {
+        SuperblockData {
+            inodes_count: superblock.inodes_count,
+            blocks_count: superblock.blocks_count,
+            r_blocks_count: superblock.r_blocks_count,
+            free_blocks_count: superblock.free_blocks_count,
+            free_inodes_count: superblock.free_inodes_count,
+            first_data_block: superblock.first_data_block,
+            log_block_size: superblock.log_block_size,
+            log_frag_size: superblock.log_frag_size,
+            blocks_per_group: superblock.blocks_per_group,
+            frags_per_group: superblock.frags_per_group,
+            inodes_per_group: superblock.inodes_per_group,
+            mtime: superblock.mtime,
+            wtime: superblock.wtime,
+            mnt_count: superblock.mnt_count,
+            max_mnt_count: superblock.max_mnt_count,
+            magic: superblock.magic,
+            state: superblock.state,
+            errors: superblock.errors,
+            rev_minor: superblock.rev_minor,
+            lastcheck: superblock.lastcheck,
+            checkinterval: superblock.checkinterval,
+            creator_os: superblock.creator_os,
+            rev_major: superblock.rev_major,
+            block_uid: superblock.block_uid,
+            block_gid: superblock.block_gid,
+            first_inode: superblock.first_inode,
+            inode_size: superblock.inode_size,
+            block_group: superblock.block_group,
+            features_opt: superblock.features_opt,
+            features_req: superblock.features_req,
+            features_ronly: superblock.features_ronly,
+            fs_id: superblock.fs_id,
+            volume_name: superblock.volume_name,
+            last_mnt_path: superblock.last_mnt_path,
+            compression: superblock.compression,
+            prealloc_blocks_files: superblock.prealloc_blocks_files,
+            prealloc_blocks_dirs: superblock.prealloc_blocks_dirs,
+            unused: superblock._unused,
+            journal_id: superblock.journal_id,
+            journal_inode: superblock.journal_inode,
+            journal_dev: superblock.journal_dev,
+            journal_orphan_head: superblock.journal_orphan_head,
+            hash_seed: superblock.hash_seed,
+            def_hash_version: superblock.def_hash_version,
+            unused_ext4: superblock._unused_ext4,
+            flags: superblock.flags,
+            reserved: superblock._reserved,
+        }
+    }
+}
+
+impl From<SuperblockData> for Superblock {
+    fn from(data: SuperblockData) -> Superblock {
+        Superblock {
+            inodes_count: data.inodes_count,
+            blocks_count: data.blocks_count,
+            r_blocks_count: data.r_blocks_count,
+            free_blocks_count: data.free_blocks_count,
+            free_inodes_count: data.free_inodes_count,
+            first_data_block: data.first_data_block,
+            log_block_size: data.log_block_size,
+            log_frag_size: data.log_frag_size,
+            blocks_per_group: data.blocks_per_group,
+            frags_per_group: data.frags_per_group,
+            inodes_per_group: data.inodes_per_group,
+            mtime: data.mtime,
+            wtime: data.wtime,
+            mnt_count: data.mnt_count,
+            max_mnt_count: data.max_mnt_count,
+            magic: data.magic,
+            state: data.state,
+            errors: data.errors,
+            rev_minor: data.rev_minor,
+            lastcheck: data.lastcheck,
+            checkinterval: data.checkinterval,
+            creator_os: data.creator_os,
+            rev_major: data.rev_major,
+            block_uid: data.block_uid,
+            block_gid: data.block_gid,
+            first_inode: data.first_inode,
+            inode_size: data.inode_size,
+            block_group: data.block_group,
+            features_opt: data.features_opt,
+            features_req: data.features_req,
+            features_ronly: data.features_ronly,
+            fs_id: data.fs_id,
+            volume_name: data.volume_name,
+            last_mnt_path: data.last_mnt_path,
+            compression: data.compression,
+            prealloc_blocks_files: data.prealloc_blocks_files,
+            prealloc_blocks_dirs: data.prealloc_blocks_dirs,
+            _unused: data.unused,
+            journal_id: data.journal_id,
+            journal_inode: data.journal_inode,
+            journal_dev: data.journal_dev,
+            journal_orphan_head: data.journal_orphan_head,
+            hash_seed: data.hash_seed,
+            def_hash_version: data.def_hash_version,
+            _unused_ext4: data.unused_ext4,
+            flags: data.flags,
+            _reserved: data.reserved,
+        }
+    }
+}
+
+impl Default for Superblock {
+    fn default() -> Superblock {
+        // every field is an integer, a byte array or a set of flags, all of
+        // which are valid when zeroed
+        unsafe { mem::zeroed() }
+    }
+}
+
+impl Superblock {
+    /// Reads the primary superblock, at byte 1024 of the volume, and checks
+    /// its magic number
+    pub fn find<S: SectorSize, V: Volume<u8, S>>(
         haystack: &V,
-    ) -> Result<(Superblock, Address<S>), Error> {
+    ) -> Result<(Superblock, Address<S>)> {
         let offset = Address::from(1024_usize);
-        let end = offset + Address::from(mem::size_of::<Superblock>());
+        Superblock::find_at(haystack, offset)
+            .map(|superblock| (superblock, offset))
+    }
+
+    /// Reads the superblock at `offset`, where a backup copy would be.
+    /// Like `find`, only the magic number is checked.
+    pub fn find_at<S: SectorSize, V: Volume<u8, S>>(
+        haystack: &V,
+        offset: Address<S>,
+    ) -> Result<Superblock> {
+        let end = offset
+            .checked_add(Address::from(mem::size_of::<Superblock>()))
+            .ok_or(Error::ArithmeticOverflow)?;
         if haystack.size() < end {
             return Err(Error::AddressOutOfBounds {
                 sector: end.sector(),
@@ -210,21 +568,373 @@ impl Superblock {
             });
         }
 
-        let superblock = {
-            haystack
-                .slice_unchecked(offset..end)
-                .dynamic_cast::<Superblock>()
-        };
+        let slice = haystack.slice(offset..end).map_err(|err| err.into())?;
+        if slice.len() < mem::size_of::<Superblock>() {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        let mut bytes = [0; 1024];
+        bytes.copy_from_slice(&slice[..1024]);
+        let superblock = Superblock::from_bytes(&bytes);
 
-        if superblock.0.magic != EXT2_MAGIC {
+        if superblock.magic != EXT2_MAGIC {
             Err(Error::BadMagic {
-                magic: superblock.0.magic,
+                magic: superblock.magic,
             })
         } else {
             Ok(superblock)
         }
     }
 
+    /// Checks that the superblock describes a filesystem that can be laid
+    /// out at all, failing with the error for the first field that doesn't
+    /// add up. `find` only checks the magic number.
+    pub fn check(&self) -> Result<()> {
+        if self.log_block_size > 6 {
+            return Err(Error::BadLogBlockSize {
+                log_block_size: self.log_block_size,
+            });
+        }
+        // each group's usage bitmaps take up a single block
+        let bits = self.block_size() as u32 * 8;
+        if self.blocks_per_group == 0
+            || self.inodes_per_group == 0
+            || self.blocks_per_group > bits
+            || self.inodes_per_group > bits
+        {
+            return Err(Error::BadGroupSize {
+                blocks_per_group: self.blocks_per_group,
+                inodes_per_group: self.inodes_per_group,
+            });
+        }
+        if self.free_inodes_count > self.inodes_count {
+            return Err(Error::BadFreeInodesCount {
+                inodes_count: self.inodes_count,
+                free_inodes_count: self.free_inodes_count,
+            });
+        }
+        if !(0..=6).contains(&{ self.log_frag_size }) {
+            return Err(Error::Corrupt {
+                reason: "fragment size out of range",
+            });
+        }
+        if self.first_inode() < 11 || self.first_inode() > self.inodes_count {
+            return Err(Error::Corrupt {
+                reason: "first non-reserved inode out of range",
+            });
+        }
+        let inode_size = self.inode_size() as usize;
+        if inode_size < mem::size_of::<Inode>()
+            || !inode_size.is_power_of_two()
+            || inode_size > self.block_size()
+        {
+            return Err(Error::BadInodeSize { size: inode_size });
+        }
+        // block 0 holds the superblock with 1 KiB blocks, the boot sector
+        // and the superblock otherwise
+        let first_data_block = if self.log_block_size == 0 { 1 } else { 0 };
+        if self.first_data_block != first_data_block
+            || self.blocks_count <= self.first_data_block
+        {
+            return Err(Error::BadFirstDataBlock {
+                first_data_block: self.first_data_block,
+                blocks_count: self.blocks_count,
+            });
+        }
+        self.block_group_count().map(|_| ()).map_err(
+            |(by_blocks, by_inodes)| Error::BadBlockGroupCount {
+                by_blocks,
+                by_inodes,
+            },
+        )
+    }
+
+    /// Parses a superblock from its on-disk, little-endian representation
+    pub fn from_bytes(bytes: &[u8; 1024]) -> Superblock {
+        let mut fields = Fields::new(bytes);
+        let mut sb = Superblock {
+            inodes_count: fields.u32(),
+            blocks_count: fields.u32(),
+            r_blocks_count: fields.u32(),
+            free_blocks_count: fields.u32(),
+            free_inodes_count: fields.u32(),
+            first_data_block: fields.u32(),
+            log_block_size: fields.u32(),
+            log_frag_size: fields.i32(),
+            blocks_per_group: fields.u32(),
+            frags_per_group: fields.u32(),
+            inodes_per_group: fields.u32(),
+            mtime: fields.u32(),
+            wtime: fields.u32(),
+            mnt_count: fields.u16(),
+            max_mnt_count: fields.i16(),
+            magic: fields.u16(),
+            state: fields.u16(),
+            errors: fields.u16(),
+            rev_minor: fields.u16(),
+            lastcheck: fields.u32(),
+            checkinterval: fields.u32(),
+            creator_os: fields.u32(),
+            rev_major: fields.u32(),
+            block_uid: fields.u16(),
+            block_gid: fields.u16(),
+            first_inode: fields.u32(),
+            inode_size: fields.u16(),
+            block_group: fields.u16(),
+            // unknown features are kept, so that they survive being written
+            // back
+            features_opt: unsafe {
+                FeaturesOptional::from_bits_unchecked(fields.u32())
+            },
+            features_req: unsafe {
+                FeaturesRequired::from_bits_unchecked(fields.u32())
+            },
+            features_ronly: unsafe {
+                FeaturesROnly::from_bits_unchecked(fields.u32())
+            },
+            ..Superblock::default()
+        };
+        fields.bytes(&mut sb.fs_id);
+        fields.bytes(&mut sb.volume_name);
+        fields.bytes(&mut sb.last_mnt_path);
+        sb.compression = fields.u32();
+        sb.prealloc_blocks_files = fields.u8();
+        sb.prealloc_blocks_dirs = fields.u8();
+        fields.bytes(&mut sb._unused);
+        fields.bytes(&mut sb.journal_id);
+        sb.journal_inode = fields.u32();
+        sb.journal_dev = fields.u32();
+        sb.journal_orphan_head = fields.u32();
+        sb.hash_seed = [fields.u32(), fields.u32(), fields.u32(), fields.u32()];
+        sb.def_hash_version = fields.u8();
+        fields.bytes(&mut sb._unused_ext4);
+        sb.flags = fields.u32();
+        fields.bytes(&mut sb._reserved);
+        debug_assert_eq!(fields.offset(), mem::size_of::<Superblock>());
+        sb
+    }
+
+    /// Serializes the superblock into its on-disk, little-endian
+    /// representation
+    pub fn to_bytes(&self) -> [u8; 1024] {
+        let mut bytes = [0; 1024];
+        let mut offset = 0;
+        {
+            let mut put = |field: &[u8]| {
+                bytes[offset..offset + field.len()].copy_from_slice(field);
+                offset += field.len();
+            };
+            put(&{ self.inodes_count }.to_le_bytes());
+            put(&{ self.blocks_count }.to_le_bytes());
+            put(&{ self.r_blocks_count }.to_le_bytes());
+            put(&{ self.free_blocks_count }.to_le_bytes());
+            put(&{ self.free_inodes_count }.to_le_bytes());
+            put(&{ self.first_data_block }.to_le_bytes());
+            put(&{ self.log_block_size }.to_le_bytes());
+            put(&{ self.log_frag_size }.to_le_bytes());
+            put(&{ self.blocks_per_group }.to_le_bytes());
+            put(&{ self.frags_per_group }.to_le_bytes());
+            put(&{ self.inodes_per_group }.to_le_bytes());
+            put(&{ self.mtime }.to_le_bytes());
+            put(&{ self.wtime }.to_le_bytes());
+            put(&{ self.mnt_count }.to_le_bytes());
+            put(&{ self.max_mnt_count }.to_le_bytes());
+            put(&{ self.magic }.to_le_bytes());
+            put(&{ self.state }.to_le_bytes());
+            put(&{ self.errors }.to_le_bytes());
+            put(&{ self.rev_minor }.to_le_bytes());
+            put(&{ self.lastcheck }.to_le_bytes());
+            put(&{ self.checkinterval }.to_le_bytes());
+            put(&{ self.creator_os }.to_le_bytes());
+            put(&{ self.rev_major }.to_le_bytes());
+            put(&{ self.block_uid }.to_le_bytes());
+            put(&{ self.block_gid }.to_le_bytes());
+            put(&{ self.first_inode }.to_le_bytes());
+            put(&{ self.inode_size }.to_le_bytes());
+            put(&{ self.block_group }.to_le_bytes());
+            put(&{ self.features_opt }.bits().to_le_bytes());
+            put(&{ self.features_req }.bits().to_le_bytes());
+            put(&{ self.features_ronly }.bits().to_le_bytes());
+            put(&self.fs_id);
+            put(&self.volume_name);
+            put(&self.last_mnt_path);
+            put(&{ self.compression }.to_le_bytes());
+            put(&[self.prealloc_blocks_files, self.prealloc_blocks_dirs]);
+            put(&self._unused);
+            put(&self.journal_id);
+            put(&{ self.journal_inode }.to_le_bytes());
+            put(&{ self.journal_dev }.to_le_bytes());
+            put(&{ self.journal_orphan_head }.to_le_bytes());
+            for word in { self.hash_seed }.iter() {
+                put(&word.to_le_bytes());
+            }
+            put(&[self.def_hash_version]);
+            put(&self._unused_ext4);
+            put(&{ self.flags }.to_le_bytes());
+            put(&self._reserved);
+        }
+        debug_assert_eq!(offset, mem::size_of::<Superblock>());
+        bytes
+    }
+
+    /// Writes the superblock to `offset`, which is byte 1024 of the volume
+    /// for the primary copy
+    pub fn write_back<S: SectorSize, V: Volume<u8, S>>(
+        &self,
+        volume: &mut V,
+        offset: Address<S>,
+    ) -> Result<()> {
+        let commit = VolumeCommit::new(self.to_bytes().to_vec(), offset);
+        volume.commit(Some(commit)).map_err(|err| err.into())
+    }
+
+    /// Whether block group `group` holds a backup of the superblock and the
+    /// block group descriptor table. Without the sparse superblock feature
+    /// every group does; with it, only groups 0 and 1 and the groups that
+    /// are powers of 3, 5 or 7.
+    pub fn has_backup(&self, group: u32) -> bool {
+        !{ self.features_ronly }.contains(FeaturesROnly::RONLY_SPARSE)
+            || group == 0
+            || is_sparse_group(group)
+    }
+
+    /// Lists the block groups other than group 0 that hold backups of the
+    /// superblock and the block group descriptor table
+    pub fn backup_groups(&self) -> Vec<u32> {
+        let count = groups(
+            self.blocks_count.saturating_sub(self.first_data_block),
+            self.blocks_per_group,
+        );
+        (1..count).filter(|&group| self.has_backup(group)).collect()
+    }
+
+    /// Number of blocks only the superuser may allocate once every other
+    /// block is in use
+    pub fn reserved_blocks(&self) -> u32 {
+        self.r_blocks_count
+    }
+
+    /// Whether a user with IDs `uid` and `gid` may allocate the reserved
+    /// blocks: root and the user or group named in the superblock can. As in
+    /// Linux, the default reserved group of 0 doesn't count.
+    pub fn may_use_reserved(&self, uid: u16, gid: u16) -> bool {
+        uid == 0
+            || uid == self.block_uid
+            || (self.block_gid != 0 && gid == self.block_gid)
+    }
+
+    /// Whether this is a revision 0 superblock, which predates every field
+    /// from `first_inode` on. Those fields read as their revision 0 values.
+    pub(crate) fn is_rev_0(&self) -> bool {
+        self.rev_major == 0
+    }
+
+    /// First non-reserved inode in file system, 11 on revision 0
+    pub fn first_inode(&self) -> u32 {
+        if self.is_rev_0() {
+            11
+        } else {
+            self.first_inode
+        }
+    }
+
+    /// Size of each inode structure in bytes, 128 on revision 0
+    pub fn inode_size(&self) -> u16 {
+        if self.is_rev_0() {
+            128
+        } else {
+            self.inode_size
+        }
+    }
+
+    /// Optional features present (features that are not required to read
+    /// or write, but usually result in a performance increase)
+    pub fn features_opt(&self) -> FeaturesOptional {
+        if self.is_rev_0() {
+            FeaturesOptional::empty()
+        } else {
+            self.features_opt
+        }
+    }
+
+    /// Required features present (features that are required to be
+    /// supported to read or write)
+    pub fn features_req(&self) -> FeaturesRequired {
+        if self.is_rev_0() {
+            FeaturesRequired::empty()
+        } else {
+            self.features_req
+        }
+    }
+
+    /// Features that if not supported, the volume must be mounted
+    /// read-only
+    pub fn features_ronly(&self) -> FeaturesROnly {
+        if self.is_rev_0() {
+            FeaturesROnly::empty()
+        } else {
+            self.features_ronly
+        }
+    }
+
+    /// File system ID (what is output by blkid), nil on revision 0
+    pub fn uuid(&self) -> Uuid {
+        if self.is_rev_0() {
+            Uuid([0; 16])
+        } else {
+            Uuid(self.fs_id)
+        }
+    }
+
+    /// Volume name, without the 0 bytes padding it. Empty on revision 0.
+    pub fn volume_name(&self) -> &[u8] {
+        if self.is_rev_0() {
+            &[]
+        } else {
+            trim_nul(&self.volume_name)
+        }
+    }
+
+    /// Path volume was last mounted to, without the 0 bytes padding it.
+    /// Empty on revision 0.
+    pub fn last_mounted(&self) -> &[u8] {
+        if self.is_rev_0() {
+            &[]
+        } else {
+            trim_nul(&self.last_mnt_path)
+        }
+    }
+
+    /// Number of blocks reserved after the descriptor table for it to grow
+    /// into, with the resize inode feature
+    pub fn reserved_gdt_blocks(&self) -> u16 {
+        if { self.features_opt() }.contains(FeaturesOptional::SELF_RESIZE) {
+            u16::from_le_bytes(self._unused)
+        } else {
+            0
+        }
+    }
+
+    /// Required features set that this crate doesn't support. Reading a
+    /// filesystem with any of them would misinterpret it.
+    pub fn unsupported_features_req(&self) -> FeaturesRequired {
+        self.features_req() - FeaturesRequired::SUPPORTED
+    }
+
+    /// Read-only features set that this crate doesn't support. The
+    /// filesystem can still be read, but writing to it would corrupt it.
+    pub fn unsupported_features_ronly(&self) -> FeaturesROnly {
+        self.features_ronly() - FeaturesROnly::SUPPORTED
+    }
+
+    /// The volume name as a string, or `None` if it isn't UTF-8
+    pub fn label(&self) -> Option<&str> {
+        str::from_utf8(self.volume_name()).ok()
+    }
+
     #[inline]
     pub fn block_size(&self) -> usize {
         1024 << self.log_block_size
@@ -235,19 +945,97 @@ impl Superblock {
         1024 << self.log_frag_size
     }
 
+    /// Number of block groups, counted both from the number of blocks and
+    /// from the number of inodes. Fails with both counts if they disagree.
     pub fn block_group_count(&self) -> Result<u32, (u32, u32)> {
-        let blocks_mod = self.blocks_count % self.blocks_per_group;
-        let inodes_mod = self.inodes_count % self.inodes_per_group;
-        let blocks_inc = if blocks_mod == 0 { 0 } else { 1 };
-        let inodes_inc = if inodes_mod == 0 { 0 } else { 1 };
-        let by_blocks = self.blocks_count / self.blocks_per_group + blocks_inc;
-        let by_inodes = self.inodes_count / self.inodes_per_group + inodes_inc;
+        // block 0 isn't part of any group with 1 KiB blocks
+        let blocks_count =
+            self.blocks_count.saturating_sub(self.first_data_block);
+        let by_blocks = groups(blocks_count, self.blocks_per_group);
+        let by_inodes = groups(self.inodes_count, self.inodes_per_group);
         if by_blocks == by_inodes {
             Ok(by_blocks)
         } else {
             Err((by_blocks, by_inodes))
         }
     }
+
+    /// Number of blocks the inode table of each group takes up
+    pub fn inode_table_blocks(&self) -> u32 {
+        (self.inodes_per_group as u64 * self.inode_size() as u64)
+            .div_ceil(self.block_size() as u64) as u32
+    }
+
+    /// Block the primary block group descriptor table starts at, the one
+    /// after the superblock: 2 with 1 KiB blocks, 1 otherwise
+    pub fn gdt_start_block(&self) -> u32 {
+        self.first_data_block + 1
+    }
+
+    /// Returns the first block of group `group`, or `None` if it would be
+    /// past the last block a filesystem can have
+    pub fn group_first_block(&self, group: u32) -> Option<u32> {
+        group
+            .checked_mul(self.blocks_per_group)?
+            .checked_add(self.first_data_block)
+    }
+
+    /// Number of blocks in group `group`: the last group may be shorter
+    /// than the others, and groups past it have none
+    pub fn group_block_count(&self, group: u32) -> u32 {
+        match self.group_first_block(group) {
+            Some(first) => self
+                .blocks_count
+                .saturating_sub(first)
+                .min(self.blocks_per_group),
+            None => 0,
+        }
+    }
+
+    /// Returns the group inode `inode` belongs to and its index in the
+    /// group's inode table, or `None` if there is no such inode
+    pub fn inode_group_and_index(&self, inode: u32) -> Option<(u32, u32)> {
+        if inode == 0 || inode > self.inodes_count || self.inodes_per_group == 0
+        {
+            return None;
+        }
+        Some((
+            (inode - 1) / self.inodes_per_group,
+            (inode - 1) % self.inodes_per_group,
+        ))
+    }
+
+    /// Returns the group block `block` belongs to and its index in the
+    /// group, or `None` if it's outside of every group
+    pub fn block_group_and_index(&self, block: u32) -> Option<(u32, u32)> {
+        if block < self.first_data_block
+            || block >= self.blocks_count
+            || self.blocks_per_group == 0
+        {
+            return None;
+        }
+        let relative = block - self.first_data_block;
+        Some((
+            relative / self.blocks_per_group,
+            relative % self.blocks_per_group,
+        ))
+    }
+
+    /// Returns the group block `block` belongs to, or `None` if it's
+    /// outside of every group
+    pub fn block_group_of(&self, block: u32) -> Option<u32> {
+        self.block_group_and_index(block).map(|(group, _)| group)
+    }
+}
+
+/// Number of groups of `per_group` needed to hold `count`, or 0 if the groups
+/// are empty
+fn groups(count: u32, per_group: u32) -> u32 {
+    if per_group == 0 {
+        0
+    } else {
+        count.div_ceil(per_group)
+    }
 }
 
 bitflags! {
@@ -283,6 +1071,12 @@ bitflags! {
     }
 }
 
+impl FeaturesRequired {
+    /// The required features this crate knows how to handle
+    pub const SUPPORTED: FeaturesRequired =
+        FeaturesRequired::REQ_DIRECTORY_TYPE;
+}
+
 bitflags! {
     /// ROnly features. If these are not supported; remount as read-only
     pub struct FeaturesROnly: u32 {
@@ -295,6 +1089,60 @@ bitflags! {
     }
 }
 
+impl FeaturesROnly {
+    /// The read-only features this crate knows how to keep intact when
+    /// writing
+    pub const SUPPORTED: FeaturesROnly = FeaturesROnly::from_bits_truncate(
+        FeaturesROnly::RONLY_SPARSE.bits()
+            | FeaturesROnly::RONLY_FILE_SIZE_64.bits(),
+    );
+}
+
+/// The ID of a filesystem, as output by blkid
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    pub fn is_nil(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0)
+    }
+}
+
+impl Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i == 4 || i == 6 || i == 8 || i == 10 {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether group `group` holds backups of the superblock and descriptor
+/// table with the sparse superblock feature: group 1 and the groups that
+/// are powers of 3, 5 or 7 do
+pub(crate) fn is_sparse_group(group: u32) -> bool {
+    group == 1
+        || [3, 5, 7].iter().any(|&base| {
+            let mut power = base;
+            while power < group as u64 {
+                power *= base;
+            }
+            power == group as u64
+        })
+}
+
+/// Cuts a C-style string at its first 0 byte
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let len = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    &bytes[..len]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -306,7 +1154,7 @@ mod tests {
         // magic
         volume[1024 + 56] = EXT2_MAGIC as u8;
         volume[1024 + 57] = (EXT2_MAGIC >> 8) as u8;
-        let superblock = unsafe { Superblock::find::<Size512, _>(&volume) };
+        let superblock = Superblock::find::<Size512, _>(&volume);
         assert!(
             superblock.is_ok(),
             "Err({:?})",
@@ -320,11 +1168,218 @@ mod tests {
         use std::fs::File;
 
         let file = RefCell::new(File::open("ext2.img").unwrap());
-        let superblock = unsafe { Superblock::find::<Size512, _>(&file) };
+        let superblock = Superblock::find::<Size512, _>(&file);
         assert!(
             superblock.is_ok(),
             "Err({:?})",
             superblock.err().unwrap_or_else(|| unreachable!()),
         );
     }
+    #[test]
+    fn extended_fields() {
+        use std::fs;
+
+        let volume = fs::read("ext2.img").unwrap();
+        let (mut superblock, _) =
+            Superblock::find::<Size512, _>(&volume).unwrap();
+        assert_eq!(superblock.first_inode(), 11);
+        assert_eq!(superblock.inode_size(), 128);
+        assert_eq!(
+            superblock.features_req(),
+            FeaturesRequired::REQ_DIRECTORY_TYPE
+        );
+        assert_eq!(
+            superblock.uuid().to_string(),
+            "b6eb7283-2f21-4e15-8f0b-082b2ef69005"
+        );
+        assert_eq!(superblock.volume_name(), b"");
+        assert_eq!(superblock.label(), Some(""));
+        assert_eq!(superblock.last_mounted(), b"");
+
+        superblock.volume_name = *b"scratch\0\0\0\0\0\0\0\0\0";
+        superblock.last_mnt_path[..4].copy_from_slice(b"/mnt");
+        assert_eq!(superblock.label(), Some("scratch"));
+        assert_eq!(superblock.last_mounted(), b"/mnt");
+        superblock.volume_name[0] = 0xff;
+        assert_eq!(superblock.label(), None);
+
+        // revision 0 predates the fields, whatever the bytes hold
+        superblock.rev_major = 0;
+        superblock.first_inode = 0xdead;
+        superblock.inode_size = 0xbeef;
+        assert_eq!(superblock.first_inode(), 11);
+        assert_eq!(superblock.inode_size(), 128);
+        assert!(superblock.features_opt().is_empty());
+        assert!(superblock.features_req().is_empty());
+        assert!(superblock.features_ronly().is_empty());
+        assert!(superblock.uuid().is_nil());
+        assert_eq!(superblock.volume_name(), b"");
+        assert_eq!(superblock.last_mounted(), b"");
+    }
+    #[test]
+    fn check() {
+        let bytes = include_bytes!("../../fixtures/superblock.bin");
+        let valid = Superblock::from_bytes(bytes);
+        assert!(valid.check().is_ok());
+
+        let mut superblock = valid;
+        superblock.log_block_size = 7;
+        match superblock.check() {
+            Err(Error::BadLogBlockSize { log_block_size: 7 }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        let mut superblock = valid;
+        superblock.blocks_per_group = 0;
+        match superblock.check() {
+            Err(Error::BadGroupSize {
+                blocks_per_group: 0,
+                inodes_per_group: 1024,
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // more inodes than bits in a bitmap block
+        let mut superblock = valid;
+        superblock.inodes_per_group = 8193;
+        match superblock.check() {
+            Err(Error::BadGroupSize {
+                inodes_per_group: 8193,
+                ..
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        let mut superblock = valid;
+        superblock.log_frag_size = -1;
+        match superblock.check() {
+            Err(Error::Corrupt { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        let mut superblock = valid;
+        superblock.first_inode = 1025;
+        match superblock.check() {
+            Err(Error::Corrupt { .. }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        let mut superblock = valid;
+        superblock.free_inodes_count = 1025;
+        match superblock.check() {
+            Err(Error::BadFreeInodesCount {
+                inodes_count: 1024,
+                free_inodes_count: 1025,
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+
+        // 1 KiB blocks start at block 1, larger ones at block 0
+        let mut superblock = valid;
+        superblock.first_data_block = 0;
+        match superblock.check() {
+            Err(Error::BadFirstDataBlock {
+                first_data_block: 0,
+                ..
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+        superblock.log_block_size = 2;
+        superblock.blocks_count = 1024;
+        superblock.inodes_count = 256;
+        superblock.inodes_per_group = 256;
+        superblock.free_inodes_count = 0;
+        assert!(superblock.check().is_ok());
+        superblock.first_data_block = 1;
+        assert!(superblock.check().is_err());
+        superblock.first_data_block = 0;
+        superblock.blocks_count = 0;
+        assert!(superblock.check().is_err());
+
+        let mut superblock = valid;
+        superblock.inodes_per_group = 256;
+        match superblock.check() {
+            Err(Error::BadBlockGroupCount {
+                by_blocks: 1,
+                by_inodes: 4,
+            }) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+    #[test]
+    fn geometry() {
+        // blocks, inodes per group and inode table blocks per group for 4
+        // groups of each block size, the last one short
+        for &(log_block_size, blocks_per_group, inodes_per_group, table) in &[
+            (0, 8192, 2048, 256),
+            (1, 16384, 4096, 256),
+            (2, 32768, 8192, 256),
+        ] {
+            let first_data_block = if log_block_size == 0 { 1 } else { 0 };
+            let superblock = Superblock {
+                log_block_size,
+                first_data_block,
+                blocks_per_group,
+                inodes_per_group,
+                blocks_count: first_data_block + 3 * blocks_per_group + 100,
+                inodes_count: 4 * inodes_per_group,
+                rev_major: 1,
+                first_inode: 11,
+                inode_size: 128,
+                ..Superblock::default()
+            };
+            assert!(superblock.check().is_ok());
+            assert_eq!(superblock.block_size(), 1024 << log_block_size);
+            assert_eq!(superblock.block_group_count(), Ok(4));
+            assert_eq!(superblock.inode_table_blocks(), table);
+            assert_eq!(superblock.gdt_start_block(), first_data_block + 1);
+
+            assert_eq!(superblock.inode_group_and_index(0), None);
+            assert_eq!(superblock.inode_group_and_index(1), Some((0, 0)));
+            assert_eq!(
+                superblock.inode_group_and_index(inodes_per_group + 1),
+                Some((1, 0))
+            );
+            assert_eq!(
+                superblock.inode_group_and_index(4 * inodes_per_group),
+                Some((3, inodes_per_group - 1))
+            );
+            assert_eq!(
+                superblock.inode_group_and_index(4 * inodes_per_group + 1),
+                None
+            );
+
+            let third = first_data_block + 2 * blocks_per_group;
+            assert_eq!(superblock.group_first_block(2), Some(third));
+            assert_eq!(superblock.block_group_of(third), Some(2));
+            assert_eq!(superblock.block_group_of(third - 1), Some(1));
+            assert_eq!(
+                superblock.block_group_and_index(third + 5),
+                Some((2, 5))
+            );
+            assert_eq!(
+                superblock.block_group_of(superblock.blocks_count),
+                None
+            );
+            assert_eq!(superblock.group_block_count(2), blocks_per_group);
+            assert_eq!(superblock.group_block_count(3), 100);
+            assert_eq!(superblock.group_block_count(4), 0);
+            assert_eq!(superblock.group_first_block(u32::MAX), None);
+        }
+
+        // with 1 KiB blocks, block 0 belongs to no group
+        let superblock = Superblock {
+            first_data_block: 1,
+            blocks_per_group: 8192,
+            blocks_count: 8193,
+            ..Superblock::default()
+        };
+        assert_eq!(superblock.block_group_of(0), None);
+        assert_eq!(superblock.block_group_of(1), Some(0));
+
+        // nonsense geometry gives no answers rather than panicking
+        let superblock = Superblock::default();
+        assert_eq!(superblock.inode_group_and_index(1), None);
+        assert_eq!(superblock.block_group_of(1), None);
+    }
 }
@@ -0,0 +1,379 @@
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+
+/// Magic number at the start of an extended attribute block
+pub const XATTR_MAGIC: u32 = 0xEA02_0000;
+/// Size of the header of an extended attribute block
+pub const HEADER_SIZE: usize = 32;
+/// Size of the fixed part of an entry, which is followed by the name and
+/// padding up to 4 bytes
+pub const ENTRY_HEADER_SIZE: usize = 16;
+
+/// `user.` attributes
+pub const INDEX_USER: u8 = 1;
+/// The access ACL, `system.posix_acl_access`
+pub const INDEX_POSIX_ACL_ACCESS: u8 = 2;
+/// The default ACL of a directory, `system.posix_acl_default`
+pub const INDEX_POSIX_ACL_DEFAULT: u8 = 3;
+/// `trusted.` attributes
+pub const INDEX_TRUSTED: u8 = 4;
+/// `security.` attributes, such as SELinux labels and file capabilities
+pub const INDEX_SECURITY: u8 = 6;
+/// Other `system.` attributes
+pub const INDEX_SYSTEM: u8 = 7;
+/// `system.richacl`
+pub const INDEX_RICHACL: u8 = 8;
+
+/// The prefix the name of an attribute with the name index `index` gets,
+/// or `None` for an index with no known prefix
+pub fn prefix(index: u8) -> Option<&'static [u8]> {
+    match index {
+        INDEX_USER => Some(b"user."),
+        INDEX_POSIX_ACL_ACCESS => Some(b"system.posix_acl_access"),
+        INDEX_POSIX_ACL_DEFAULT => Some(b"system.posix_acl_default"),
+        INDEX_TRUSTED => Some(b"trusted."),
+        INDEX_SECURITY => Some(b"security."),
+        INDEX_SYSTEM => Some(b"system."),
+        INDEX_RICHACL => Some(b"system.richacl"),
+        _ => None,
+    }
+}
+
+/// The header of an extended attribute block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XattrHeader {
+    pub magic: u32,
+    /// Number of inodes sharing the block
+    pub refcount: u32,
+    /// Number of blocks the attributes take up, always 1
+    pub blocks: u32,
+    /// Hash of all the attributes, letting identical blocks be shared
+    pub hash: u32,
+}
+
+impl XattrHeader {
+    /// Parses a header from the start of `bytes`, which must hold at least
+    /// `HEADER_SIZE` bytes
+    pub fn from_bytes(bytes: &[u8]) -> XattrHeader {
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ])
+        };
+        XattrHeader {
+            magic: u32_at(0),
+            refcount: u32_at(4),
+            blocks: u32_at(8),
+            hash: u32_at(12),
+        }
+    }
+}
+
+/// An extended attribute borrowed from its block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XattrEntryRef<'a> {
+    /// Which prefix the name has, see `prefix`
+    pub name_index: u8,
+    /// The name without its prefix
+    pub name: &'a [u8],
+    pub value: &'a [u8],
+    /// Hash of the name and value
+    pub hash: u32,
+}
+
+impl<'a> XattrEntryRef<'a> {
+    /// The name with its prefix, as `getfattr` shows it, or `None` if the
+    /// name index is unknown
+    pub fn full_name(&self) -> Option<Vec<u8>> {
+        prefix(self.name_index).map(|prefix| {
+            let mut name = prefix.to_vec();
+            name.extend_from_slice(self.name);
+            name
+        })
+    }
+}
+
+/// Walks the attributes of an extended attribute block.
+///
+/// Each entry is checked before it's returned: its header and name must be
+/// within the block, and so must its value. The first entry failing a check
+/// ends the walk with `Error::Corrupt`, as does a list of entries that runs
+/// off the end of the block rather than ending with four zero bytes.
+#[derive(Debug, Clone)]
+pub struct XattrIter<'a> {
+    block: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> XattrIter<'a> {
+    /// Walks the attributes of `block`, the block numbered `number`, after
+    /// checking its header: a block without the magic number is
+    /// `Error::BadXattrMagic`
+    pub fn new(block: &'a [u8], number: u32) -> Result<XattrIter<'a>> {
+        if block.len() < HEADER_SIZE {
+            return Err(Error::Corrupt {
+                reason: "extended attribute block too short for its header",
+            });
+        }
+        let header = XattrHeader::from_bytes(block);
+        if header.magic != XATTR_MAGIC {
+            return Err(Error::BadXattrMagic {
+                block: number,
+                magic: header.magic,
+            });
+        }
+        if header.blocks != 1 {
+            return Err(Error::Corrupt {
+                reason: "extended attributes spread over several blocks",
+            });
+        }
+        Ok(XattrIter {
+            block,
+            offset: HEADER_SIZE,
+            done: false,
+        })
+    }
+
+    fn fail(
+        &mut self,
+        reason: &'static str,
+    ) -> Option<Result<XattrEntryRef<'a>>> {
+        self.done = true;
+        Some(Err(Error::Corrupt { reason }))
+    }
+}
+
+impl<'a> Iterator for XattrIter<'a> {
+    type Item = Result<XattrEntryRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let rest = &self.block[self.offset..];
+        if rest.len() < 4 {
+            return self.fail("extended attribute list crosses the block end");
+        }
+        // the list ends with a zero name length and index
+        if rest[..4] == [0; 4] {
+            self.done = true;
+            return None;
+        }
+        if rest.len() < ENTRY_HEADER_SIZE {
+            return self.fail("extended attribute entry crosses the block end");
+        }
+
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes([
+                rest[offset],
+                rest[offset + 1],
+                rest[offset + 2],
+                rest[offset + 3],
+            ])
+        };
+        let name_len = rest[0] as usize;
+        let name_index = rest[1];
+        let value_offset = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        let value_inum = u32_at(4);
+        let value_size = u32_at(8) as usize;
+        let hash = u32_at(12);
+
+        let len = entry_len(name_len);
+        if len > rest.len() {
+            return self.fail("extended attribute name crosses the block end");
+        }
+        if value_inum != 0 {
+            return self.fail("extended attribute value in an inode");
+        }
+        if value_offset + value_size > self.block.len() {
+            return self.fail("extended attribute value crosses the block end");
+        }
+
+        let entry = XattrEntryRef {
+            name_index,
+            name: &rest[ENTRY_HEADER_SIZE..ENTRY_HEADER_SIZE + name_len],
+            value: &self.block[value_offset..value_offset + value_size],
+            hash,
+        };
+        self.offset += len;
+        Some(Ok(entry))
+    }
+}
+
+/// Size of an entry with a name of `name_len` bytes, padded to 4 bytes
+pub fn entry_len(name_len: usize) -> usize {
+    (ENTRY_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// Tags of POSIX ACL entries that apply to the owner, the group, the mask
+/// and others, which the on-disk format stores without an ID
+const ACL_SHORT_TAGS: [u16; 4] = [0x01, 0x04, 0x10, 0x20];
+/// Version of the on-disk ACL format
+const ACL_DISK_VERSION: u32 = 1;
+/// Version of the ACL format of the `system.posix_acl_*` attributes
+const ACL_XATTR_VERSION: u32 = 2;
+
+/// Converts an ACL from the compact format ext2 stores it in to the one the
+/// `system.posix_acl_access` and `system.posix_acl_default` attributes have
+/// through `getxattr(2)`, where every entry has an ID, -1 if it has none
+pub fn acl_to_xattr(value: &[u8]) -> Result<Vec<u8>> {
+    let corrupt = || Error::Corrupt {
+        reason: "malformed ACL",
+    };
+    if value.len() < 4
+        || u32::from_le_bytes([value[0], value[1], value[2], value[3]])
+            != ACL_DISK_VERSION
+    {
+        return Err(corrupt());
+    }
+
+    let mut acl = ACL_XATTR_VERSION.to_le_bytes().to_vec();
+    let mut rest = &value[4..];
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(corrupt());
+        }
+        let tag = u16::from_le_bytes([rest[0], rest[1]]);
+        let (entry, id) = if ACL_SHORT_TAGS.contains(&tag) {
+            (4, (!0_u32).to_le_bytes())
+        } else if rest.len() >= 8 {
+            (8, [rest[4], rest[5], rest[6], rest[7]])
+        } else {
+            return Err(corrupt());
+        };
+        acl.extend_from_slice(&rest[..4]);
+        acl.extend_from_slice(&id);
+        rest = &rest[entry..];
+    }
+    Ok(acl)
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+
+    use super::{
+        acl_to_xattr, entry_len, prefix, XattrIter, HEADER_SIZE, XATTR_MAGIC,
+    };
+
+    /// A block of `entries`, each as a name index, a name and a value
+    fn block(entries: &[(u8, &[u8], &[u8])]) -> Vec<u8> {
+        let mut block = vec![0; 1024];
+        block[..4].copy_from_slice(&XATTR_MAGIC.to_le_bytes());
+        block[4..8].copy_from_slice(&1_u32.to_le_bytes());
+        block[8..12].copy_from_slice(&1_u32.to_le_bytes());
+        let mut offset = HEADER_SIZE;
+        let mut end = block.len();
+        for &(index, name, value) in entries {
+            end -= (value.len() + 3) & !3;
+            block[end..end + value.len()].copy_from_slice(value);
+            let entry = &mut block[offset..];
+            entry[0] = name.len() as u8;
+            entry[1] = index;
+            entry[2..4].copy_from_slice(&(end as u16).to_le_bytes());
+            entry[8..12].copy_from_slice(&(value.len() as u32).to_le_bytes());
+            entry[16..16 + name.len()].copy_from_slice(name);
+            offset += entry_len(name.len());
+        }
+        block
+    }
+
+    fn corrupt(block: &[u8]) {
+        let mut iter = XattrIter::new(block, 1).unwrap();
+        match iter.by_ref().find(Result::is_err) {
+            Some(Err(Error::Corrupt { .. })) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn walk() {
+        let block = block(&[
+            (1, b"comment", b"a comment"),
+            (6, b"selinux", b"system_u:object_r:bin_t:s0"),
+            (2, b"", b"\x01\x00\x00\x00"),
+            (42, b"what", b""),
+        ]);
+        let entries = XattrIter::new(&block, 1)
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.full_name(), entry.value)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries,
+            vec![
+                (Some(b"user.comment".to_vec()), &b"a comment"[..]),
+                (
+                    Some(b"security.selinux".to_vec()),
+                    &b"system_u:object_r:bin_t:s0"[..]
+                ),
+                (
+                    Some(b"system.posix_acl_access".to_vec()),
+                    &b"\x01\x00\x00\x00"[..]
+                ),
+                (None, &b""[..]),
+            ]
+        );
+        assert_eq!(prefix(4), Some(&b"trusted."[..]));
+        assert_eq!(entry_len(7), 24);
+    }
+
+    #[test]
+    fn malformed() {
+        let good = block(&[(1, b"comment", b"a comment")]);
+        let mut bad = good.clone();
+        bad[3] = 0;
+        match XattrIter::new(&bad, 7) {
+            Err(Error::BadXattrMagic { block: 7, magic }) => {
+                assert_eq!(magic, 0x0002_0000)
+            }
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+
+        // a value past the end of the block
+        let mut bad = good.clone();
+        bad[HEADER_SIZE + 8..HEADER_SIZE + 12]
+            .copy_from_slice(&100_u32.to_le_bytes());
+        corrupt(&bad);
+        // a value stored in an inode, which ext2 can't do
+        let mut bad = good.clone();
+        bad[HEADER_SIZE + 4] = 12;
+        corrupt(&bad);
+        // entries running into the end of the block
+        let mut bad = vec![0xff; 64];
+        bad[..HEADER_SIZE].copy_from_slice(&good[..HEADER_SIZE]);
+        corrupt(&bad);
+    }
+
+    #[test]
+    fn acls() {
+        // u::rw-,u:1000:r--,g::r--,m::r--,o::--- as ext2 stores it
+        let disk = [
+            1, 0, 0, 0, 1, 0, 6, 0, 2, 0, 4, 0, 0xe8, 3, 0, 0, 4, 0, 4, 0,
+            0x10, 0, 4, 0, 0x20, 0, 0, 0,
+        ];
+        let mut xattr = vec![2, 0, 0, 0];
+        for &(tag, perm, id) in &[
+            (1_u16, 6_u16, !0_u32),
+            (2, 4, 1000),
+            (4, 4, !0),
+            (0x10, 4, !0),
+            (0x20, 0, !0),
+        ] {
+            xattr.extend_from_slice(&tag.to_le_bytes());
+            xattr.extend_from_slice(&perm.to_le_bytes());
+            xattr.extend_from_slice(&id.to_le_bytes());
+        }
+        assert_eq!(acl_to_xattr(&disk).unwrap(), xattr);
+        assert!(acl_to_xattr(&disk[..14]).is_err());
+        assert!(acl_to_xattr(&xattr).is_err());
+    }
+}
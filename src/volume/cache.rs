@@ -0,0 +1,496 @@
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// When writes to a `Cached` volume reach the volume underneath
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Writes only update the cache; dirty sectors are written out on
+    /// `flush`, or when they're evicted to make room for another write
+    WriteBack,
+    /// Writes go straight to the volume, updating any cached copies on the
+    /// way
+    WriteThrough,
+}
+
+/// A cached copy of one sector
+struct Line {
+    sector: u64,
+    data: Vec<u8>,
+    dirty: bool,
+    /// Value of the use clock when the line was last touched
+    used: u64,
+}
+
+struct State {
+    lines: Vec<Line>,
+    /// Maps sectors to their line in `lines`
+    index: BTreeMap<u64, usize>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Keeps the most recently used sectors of another volume in memory, up to a
+/// fixed number of them, evicting the least recently used one to make room
+/// for another. Reads of cached sectors don't touch the volume underneath.
+///
+/// Dirty sectors only ever get evicted by commits, as reads have no way of
+/// writing them out; reads that find every line dirty go to the volume
+/// without caching what they read. With `WritePolicy::WriteBack`, writes are
+/// lost unless the volume is flushed before it's dropped.
+///
+/// Serving a read from the cache allocates nothing besides the slice handed
+/// back, which has to own a copy of the data: a borrowed one could be
+/// evicted under its feet by the next read.
+pub struct Cached<V: Volume<u8, S>, S: SectorSize> {
+    volume: V,
+    capacity: usize,
+    policy: WritePolicy,
+    state: RefCell<State>,
+    _phantom: PhantomData<S>,
+}
+
+impl<V: Volume<u8, S>, S: SectorSize> Cached<V, S> {
+    /// Wraps `volume` with a cache of `capacity` sectors
+    pub fn new(
+        volume: V,
+        capacity: usize,
+        policy: WritePolicy,
+    ) -> Cached<V, S> {
+        Cached {
+            volume,
+            capacity,
+            policy,
+            state: RefCell::new(State {
+                lines: Vec::with_capacity(capacity),
+                index: BTreeMap::new(),
+                clock: 0,
+                hits: 0,
+                misses: 0,
+            }),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the volume underneath, which may lag behind the cache until
+    /// it's flushed
+    pub fn inner(&self) -> &V {
+        &self.volume
+    }
+
+    /// Returns the number of sector reads served from the cache
+    pub fn hits(&self) -> u64 {
+        self.state.borrow().hits
+    }
+
+    /// Returns the number of sector reads that had to go to the volume
+    pub fn misses(&self) -> u64 {
+        self.state.borrow().misses
+    }
+
+    /// Resets the hit and miss counts to zero
+    pub fn reset_stats(&self) {
+        let mut state = self.state.borrow_mut();
+        state.hits = 0;
+        state.misses = 0;
+    }
+
+    /// Reads sector `sector` from the volume underneath. The last sector may
+    /// come out short if the volume doesn't end on a sector boundary.
+    fn read_sector(&self, sector: u64) -> Result<Vec<u8>> {
+        let start = Address::new(sector, 0);
+        let mut end = Address::new(sector + 1, 0);
+        if let Size::Bounded(size) = self.volume.size() {
+            if size < end {
+                end = size;
+            }
+        }
+        let slice = self.volume.slice(start..end).map_err(|err| err.into())?;
+        Ok(slice.to_vec())
+    }
+
+    /// Copies the bytes of `sector` starting at `offset` into `buf`, reading
+    /// and caching the sector on a miss
+    fn read_into(
+        &self,
+        sector: u64,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<()> {
+        let mut state = self.state.borrow_mut();
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some(&line) = state.index.get(&sector) {
+            state.hits += 1;
+            let line = &mut state.lines[line];
+            line.used = clock;
+            buf.copy_from_slice(&line.data[offset..offset + buf.len()]);
+            return Ok(());
+        }
+
+        state.misses += 1;
+        let data = self.read_sector(sector)?;
+        buf.copy_from_slice(&data[offset..offset + buf.len()]);
+        if let Some(line) = state.free_line(self.capacity, false) {
+            state.install(line, sector, data, false, clock);
+        }
+        Ok(())
+    }
+
+    /// Writes `data` at `offset` into the cached copy of `sector`, reading
+    /// the sector first if it isn't cached and `data` doesn't cover it
+    fn write_into(
+        &mut self,
+        sector: u64,
+        offset: usize,
+        data: &[u8],
+    ) -> Result<()> {
+        let clock = {
+            let state = self.state.get_mut();
+            state.clock += 1;
+            state.clock
+        };
+        let cached = self.state.get_mut().index.get(&sector).cloned();
+        if let Some(line) = cached {
+            let dirty = self.policy == WritePolicy::WriteBack;
+            let line = &mut self.state.get_mut().lines[line];
+            line.data[offset..offset + data.len()].copy_from_slice(data);
+            line.dirty |= dirty;
+            line.used = clock;
+            return Ok(());
+        }
+        if self.policy == WritePolicy::WriteThrough {
+            // the volume has already been written to
+            return Ok(());
+        }
+
+        let contents = if offset == 0 && data.len() == S::SIZE {
+            data.to_vec()
+        } else {
+            let mut contents = self.read_sector(sector)?;
+            contents[offset..offset + data.len()].copy_from_slice(data);
+            contents
+        };
+        let line = match self.state.get_mut().free_line(self.capacity, true) {
+            Some(line) => line,
+            None => {
+                // no cache at all, so write straight through
+                let commit =
+                    VolumeCommit::new(contents, Address::new(sector, 0));
+                return self
+                    .volume
+                    .commit(Some(commit))
+                    .map_err(|err| err.into());
+            }
+        };
+        let evicted = {
+            let state = self.state.get_mut();
+            state.lines.get(line).and_then(|line| {
+                if line.dirty {
+                    Some((line.sector, line.data.clone()))
+                } else {
+                    None
+                }
+            })
+        };
+        if let Some((sector, data)) = evicted {
+            let commit = VolumeCommit::new(data, Address::new(sector, 0));
+            self.volume.commit(Some(commit)).map_err(|err| err.into())?;
+        }
+        self.state
+            .get_mut()
+            .install(line, sector, contents, true, clock);
+        Ok(())
+    }
+}
+
+impl State {
+    /// Picks the line to put a newly cached sector in: an unused one while
+    /// there's room, then the least recently used one, skipping dirty lines
+    /// unless `dirty` is set. Returns `lines.len()` for a line that has yet
+    /// to be created.
+    fn free_line(&self, capacity: usize, dirty: bool) -> Option<usize> {
+        if self.lines.len() < capacity {
+            return Some(self.lines.len());
+        }
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|&(_, line)| dirty || !line.dirty)
+            .min_by_key(|&(_, line)| line.used)
+            .map(|(index, _)| index)
+    }
+
+    /// Caches `data` as the contents of `sector` in line `line`, evicting
+    /// whatever it held
+    fn install(
+        &mut self,
+        line: usize,
+        sector: u64,
+        data: Vec<u8>,
+        dirty: bool,
+        used: u64,
+    ) {
+        let new = Line {
+            sector,
+            data,
+            dirty,
+            used,
+        };
+        if line == self.lines.len() {
+            self.lines.push(new);
+        } else {
+            let old = core::mem::replace(&mut self.lines[line], new);
+            self.index.remove(&old.sector);
+        }
+        self.index.insert(sector, line);
+    }
+}
+
+impl<V: Volume<u8, S>, S: SectorSize> Volume<u8, S> for Cached<V, S> {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        self.volume.size()
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let start = slice.address().into_index();
+        let end = Address::<S>::from(start + slice.len() as u64);
+        if self.size() < end {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+
+        let mut written = 0;
+        while written < slice.len() {
+            let position = Address::<S>::from(start + written as u64);
+            let offset = position.offset() as usize;
+            let len = (S::SIZE - offset).min(slice.len() - written);
+            self.write_into(
+                position.sector(),
+                offset,
+                &slice[written..written + len],
+            )?;
+            written += len;
+        }
+        if self.policy == WritePolicy::WriteThrough {
+            self.volume.commit(Some(slice)).map_err(|err| err.into())?;
+        }
+        Ok(())
+    }
+
+    /// Writes out every dirty sector, in ascending order, then flushes the
+    /// volume underneath
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let state = self.state.get_mut();
+        for (&sector, &line) in state.index.iter() {
+            let line = &mut state.lines[line];
+            if !line.dirty {
+                continue;
+            }
+            let commit =
+                VolumeCommit::new(line.data.clone(), Address::new(sector, 0));
+            self.volume.commit(Some(commit)).map_err(|err| err.into())?;
+            line.dirty = false;
+        }
+        self.volume.flush().map_err(|err| err.into())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, u8, S> {
+        self.slice(range).unwrap_or_else(|err| {
+            panic!("couldn't read from Cached volume: {:?}", err)
+        })
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+        if self.size() < range.end {
+            return Err(Error::AddressOutOfBounds {
+                sector: range.end.sector(),
+                offset: range.end.offset(),
+                size: range.end.sector_size(),
+            });
+        }
+        let start = range.start.into_index();
+        let len = range.end.into_index().saturating_sub(start) as usize;
+        let mut buf = vec![0; len];
+        let mut read = 0;
+        while read < len {
+            let position = Address::<S>::from(start + read as u64);
+            let offset = position.offset() as usize;
+            let chunk = (S::SIZE - offset).min(len - read);
+            self.read_into(
+                position.sector(),
+                offset,
+                &mut buf[read..read + chunk],
+            )?;
+            read += chunk;
+        }
+        Ok(VolumeSlice::new_owned(buf, range.start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::sync::tests::fsck;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use sys::inode::Permissions;
+    use volume::tests::Recorder;
+    use volume::{Volume, VolumeCommit};
+
+    use super::{Cached, WritePolicy};
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / 3) as u8).collect()
+    }
+
+    fn read(
+        volume: &Cached<Recorder, Size512>,
+        start: u64,
+        end: u64,
+    ) -> Vec<u8> {
+        volume
+            .slice(Address::from(start)..Address::from(end))
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn lru() {
+        let data = pattern(8 * 512);
+        let volume =
+            Cached::new(Recorder::new(data.clone()), 2, WritePolicy::WriteBack);
+
+        assert_eq!(read(&volume, 0, 1024), data[..1024]);
+        assert_eq!((volume.hits(), volume.misses()), (0, 2));
+        assert_eq!(read(&volume, 100, 200), data[100..200]);
+        assert_eq!((volume.hits(), volume.misses()), (1, 2));
+        // sector 1 is the least recently used, so it makes room for 2
+        assert_eq!(read(&volume, 1024, 1100), data[1024..1100]);
+        assert_eq!(read(&volume, 0, 10), data[..10]);
+        assert_eq!((volume.hits(), volume.misses()), (2, 3));
+        assert_eq!(read(&volume, 600, 610), data[600..610]);
+        assert_eq!((volume.hits(), volume.misses()), (2, 4));
+        assert_eq!(volume.inner().reads.get(), 4);
+
+        volume.reset_stats();
+        assert_eq!((volume.hits(), volume.misses()), (0, 0));
+        assert!(volume
+            .slice(Address::new(8, 0)..Address::new(8, 1))
+            .is_err());
+    }
+
+    #[test]
+    fn write_back() {
+        let mut data = pattern(8 * 512);
+        let mut volume =
+            Cached::new(Recorder::new(data.clone()), 4, WritePolicy::WriteBack);
+
+        for &(start, len) in &[(2048, 512), (700, 10), (100, 1000)] {
+            let bytes = vec![0xaa; len];
+            data[start..start + len].copy_from_slice(&bytes);
+            let commit = VolumeCommit::new(bytes, Address::from(start));
+            volume.commit(Some(commit)).unwrap();
+        }
+        assert!(volume.inner().commits.is_empty());
+        assert_eq!(read(&volume, 0, 4096), data);
+
+        // dirty sectors go out in ascending order
+        volume.flush().unwrap();
+        let addresses: Vec<_> = volume
+            .inner()
+            .commits
+            .iter()
+            .map(|&(addr, _)| addr)
+            .collect();
+        assert_eq!(addresses, [0, 512, 1024, 2048]);
+        assert_eq!(volume.inner().flushes, 1);
+        assert_eq!(volume.inner().inner, data);
+
+        // a full cache writes out the dirty sectors it evicts
+        let mut volume: Cached<_, Size512> =
+            Cached::new(Recorder::new(data.clone()), 2, WritePolicy::WriteBack);
+        for &sector in &[3, 5, 6] {
+            let commit =
+                VolumeCommit::new(vec![1; 512], Address::new(sector, 0));
+            volume.commit(Some(commit)).unwrap();
+        }
+        assert_eq!(volume.inner().commits, [(3 * 512, 512)]);
+    }
+
+    #[test]
+    fn write_through() {
+        let mut data = pattern(8 * 512);
+        let mut volume = Cached::new(
+            Recorder::new(data.clone()),
+            4,
+            WritePolicy::WriteThrough,
+        );
+        assert_eq!(read(&volume, 0, 512), data[..512]);
+
+        let commit = VolumeCommit::new(vec![0xbb; 1000], Address::new(0, 100));
+        volume.commit(Some(commit)).unwrap();
+        data[100..1100].iter_mut().for_each(|byte| *byte = 0xbb);
+        assert_eq!(volume.inner().inner, data);
+        // the cached sector was updated rather than dropped
+        let reads = volume.inner().reads.get();
+        assert_eq!(read(&volume, 0, 512), data[..512]);
+        assert_eq!(volume.inner().reads.get(), reads);
+    }
+
+    #[test]
+    fn directory() {
+        let image = std::fs::read("ext2.img").unwrap();
+        let volume =
+            Cached::new(Recorder::new(image), 256, WritePolicy::WriteBack);
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+
+        let list = || {
+            let dir = fs.lookup(b"/home/funky").unwrap();
+            dir.directory()
+                .unwrap()
+                .map(|entry| entry.unwrap().name)
+                .collect::<Vec<_>>()
+        };
+        let first = list();
+        let reads = fs.inner().volume.inner().reads.get();
+        fs.inner().volume.reset_stats();
+        assert_eq!(list(), first);
+        assert_eq!(fs.inner().volume.inner().reads.get(), reads);
+        assert_eq!(fs.inner().volume.misses(), 0);
+        assert!(fs.inner().volume.hits() > 0);
+
+        let mut file = fs.create_file(b"/cached", Permissions::U_READ).unwrap();
+        file.write_at(0, &pattern(3000)).unwrap();
+        fs.inner().flush().unwrap();
+        assert!(fsck(&fs.inner().volume.inner().inner));
+    }
+}
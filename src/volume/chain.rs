@@ -0,0 +1,272 @@
+use core::ops::Range;
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use error::Error;
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// Presents two volumes as one, the second following straight after the
+/// first. Slices and commits crossing the seam between them are split and
+/// stitched back together, which costs a copy; those that don't cross it
+/// are passed through as they are.
+///
+/// Longer chains nest, as in `Chain<A, Chain<B, C>>`. Keeping the parts as
+/// type parameters rather than a list of `dyn Volume`s means every part can
+/// have its own error type and no call goes through a vtable, at the price
+/// of the number of parts being fixed at compile time.
+///
+/// If the first volume is `Size::Unbounded` there's no seam, and the second
+/// is never reached.
+pub struct Chain<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A, B> Chain<A, B> {
+    pub fn new(first: A, second: B) -> Chain<A, B> {
+        Chain { first, second }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+/// Returns the index at which the volume following `first` starts
+fn seam<T: Clone, S: SectorSize, V: Volume<T, S>>(first: &V) -> u64 {
+    match first.size() {
+        Size::Bounded(size) => size.into_index(),
+        Size::Unbounded => u64::MAX,
+    }
+}
+
+/// Stitches together the ends of a slice taken from either side of the seam
+fn stitch<'a, T: Clone, S: SectorSize>(
+    index: Address<S>,
+    head: VolumeSlice<'a, T, S>,
+    tail: VolumeSlice<'a, T, S>,
+) -> VolumeSlice<'a, T, S> {
+    let mut data = Vec::with_capacity(head.len() + tail.len());
+    data.extend_from_slice(&head);
+    data.extend_from_slice(&tail);
+    VolumeSlice {
+        inner: Cow::Owned(data),
+        index,
+    }
+}
+
+impl<T: Clone, S: SectorSize, A: Volume<T, S>, B: Volume<T, S>> Volume<T, S>
+    for Chain<A, B>
+{
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        match (self.first.size(), self.second.size()) {
+            (Size::Bounded(first), Size::Bounded(second)) => Size::Bounded(
+                Address::from(first.into_index() + second.into_index()),
+            ),
+            _ => Size::Unbounded,
+        }
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<T, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let start = slice.address().into_index();
+        let end = Address::<S>::from(start + slice.len() as u64);
+        if self.size() < end {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+
+        let seam = seam(&self.first);
+        if end.into_index() <= seam {
+            return self.first.commit(Some(slice)).map_err(|err| err.into());
+        }
+        let (head, tail) = if start < seam {
+            let mut head = slice.into_inner();
+            let tail = head.split_off((seam - start) as usize);
+            (Some(head), tail)
+        } else {
+            (None, slice.into_inner())
+        };
+        if let Some(head) = head {
+            let commit = VolumeCommit::new(head, Address::from(start));
+            self.first.commit(Some(commit)).map_err(|err| err.into())?;
+        }
+        let address = Address::from(start.max(seam) - seam);
+        let commit = VolumeCommit::new(tail, address);
+        self.second.commit(Some(commit)).map_err(|err| err.into())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.first.flush().map_err(|err| err.into())?;
+        self.second.flush().map_err(|err| err.into())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, T, S> {
+        let seam = seam(&self.first);
+        let (start, end) = (range.start.into_index(), range.end.into_index());
+        if end <= seam {
+            return self.first.slice_unchecked(range);
+        }
+        let tail = self.second.slice_unchecked(
+            Address::from(start.max(seam) - seam)..Address::from(end - seam),
+        );
+        if start >= seam {
+            return VolumeSlice {
+                inner: tail.inner,
+                index: range.start,
+            };
+        }
+        let head = self.first.slice_unchecked(range.start..Address::from(seam));
+        stitch(range.start, head, tail)
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, T, S>, Self::Error> {
+        if self.size() < range.end {
+            return Err(Error::AddressOutOfBounds {
+                sector: range.end.sector(),
+                offset: range.end.offset(),
+                size: range.end.sector_size(),
+            });
+        }
+
+        let seam = seam(&self.first);
+        let (start, end) = (range.start.into_index(), range.end.into_index());
+        if end <= seam {
+            return self.first.slice(range).map_err(|err| err.into());
+        }
+        let tail = self
+            .second
+            .slice(
+                Address::from(start.max(seam) - seam)
+                    ..Address::from(end - seam),
+            )
+            .map_err(|err| err.into())?;
+        if start >= seam {
+            return Ok(VolumeSlice {
+                inner: tail.inner,
+                index: range.start,
+            });
+        }
+        let head = self
+            .first
+            .slice(range.start..Address::from(seam))
+            .map_err(|err| err.into())?;
+        Ok(stitch(range.start, head, tail))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::sync::tests::fsck;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use sys::inode::Permissions;
+    use volume::size::Size;
+    use volume::{Volume, VolumeCommit};
+
+    use super::Chain;
+
+    fn read(chain: &Chain<Vec<u8>, Vec<u8>>, start: u64, end: u64) -> Vec<u8> {
+        chain
+            .slice(Address::<Size512>::from(start)..Address::from(end))
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn seam() {
+        let first = (0..1000).map(|i| i as u8).collect::<Vec<u8>>();
+        let second = (0..700).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+        let whole = [&first[..], &second[..]].concat();
+        let mut chain = Chain::new(first.clone(), second.clone());
+        assert_eq!(
+            Volume::<u8, Size512>::size(&chain),
+            Size::Bounded(Address::from(1700_u64))
+        );
+
+        // starting in the first volume and ending in the second
+        let slice = chain
+            .slice(Address::<Size512>::new(1, 400)..Address::new(2, 100))
+            .unwrap();
+        assert_eq!(slice.address(), Address::new(1, 400));
+        assert_eq!(&slice[..], &whole[912..1124]);
+        assert_eq!(read(&chain, 0, 1700), whole);
+        // either side of the seam on its own is borrowed as it is
+        let slice = chain
+            .slice(Address::<Size512>::from(10_u64)..Address::from(1000_u64))
+            .unwrap();
+        assert!(!slice.is_mutated());
+        let slice = chain
+            .slice(Address::<Size512>::from(1000_u64)..Address::from(1010_u64))
+            .unwrap();
+        assert!(!slice.is_mutated());
+        assert_eq!(slice.address().into_index(), 1000);
+        assert_eq!(&slice[..], &second[..10]);
+
+        let commit = VolumeCommit::new(
+            vec![0xff; 100],
+            Address::<Size512>::from(950_u64),
+        );
+        chain.commit(Some(commit)).unwrap();
+        assert!(chain.first()[950..].iter().all(|&b| b == 0xff));
+        assert!(chain.second()[..50].iter().all(|&b| b == 0xff));
+        assert_eq!(chain.first()[..950], first[..950]);
+        assert_eq!(chain.second()[50..], second[50..]);
+
+        let commit =
+            VolumeCommit::new(vec![1; 10], Address::<Size512>::from(1690_u64));
+        chain.commit(Some(commit)).unwrap();
+        assert_eq!(read(&chain, 1690, 1700), [1; 10]);
+        let commit =
+            VolumeCommit::new(vec![1; 10], Address::<Size512>::from(1691_u64));
+        assert!(chain.commit(Some(commit)).is_err());
+        assert!(chain
+            .slice(Address::<Size512>::new(0, 0)..Address::from(1701_u64))
+            .is_err());
+    }
+
+    #[test]
+    fn split_filesystem() {
+        let mut image = std::fs::read("ext2.img").unwrap();
+        // the seam falls inside a block
+        let second = image.split_off(1_000_000);
+        let fs =
+            Synced::<Ext2<Size512, _>>::new(Chain::new(image, second)).unwrap();
+        let mut file = fs.create_file(b"/split", Permissions::U_READ).unwrap();
+        file.write_at(0, &vec![3; 1 << 20]).unwrap();
+
+        let fs = fs.inner();
+        let whole = [&fs.volume.first()[..], &fs.volume.second()[..]].concat();
+        assert!(fsck(&whole));
+    }
+}
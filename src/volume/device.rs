@@ -0,0 +1,493 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// Size of a block device sector, in bytes
+pub const DEVICE_SECTOR_SIZE: usize = 512;
+
+/// A disk that can only be read and written in whole 512-byte sectors, such
+/// as the one behind a kernel's disk driver. Sectors are addressed by their
+/// logical block address (LBA).
+///
+/// Reads take `&self` because volumes are read through shared references;
+/// drivers that need exclusive access for a read can wrap their state in a
+/// `RefCell` or a lock.
+pub trait BlockDevice {
+    type Error: Into<Error>;
+
+    /// Returns the number of sectors on the device
+    fn sector_count(&self) -> u64;
+
+    /// Reads sector `lba` into `buf`
+    fn read_sector(
+        &self,
+        lba: u64,
+        buf: &mut [u8; DEVICE_SECTOR_SIZE],
+    ) -> Result<(), Self::Error>;
+
+    /// Writes `buf` to sector `lba`
+    fn write_sector(
+        &mut self,
+        lba: u64,
+        buf: &[u8; DEVICE_SECTOR_SIZE],
+    ) -> Result<(), Self::Error>;
+
+    /// Reads `count` consecutive sectors starting at `lba` into `buf`, which
+    /// holds exactly `count` sectors. Drivers that can transfer several
+    /// sectors at once should override this; by default it reads them one at
+    /// a time.
+    fn read_sectors(
+        &self,
+        lba: u64,
+        count: usize,
+        buf: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        let mut sector = [0; DEVICE_SECTOR_SIZE];
+        for i in 0..count {
+            self.read_sector(lba + i as u64, &mut sector)?;
+            buf[i * DEVICE_SECTOR_SIZE..][..DEVICE_SECTOR_SIZE]
+                .copy_from_slice(&sector);
+        }
+        Ok(())
+    }
+
+    /// Writes `buf`, a whole number of sectors, to consecutive sectors
+    /// starting at `lba`. Like `read_sectors`, this writes them one at a time
+    /// unless overridden.
+    fn write_sectors(
+        &mut self,
+        lba: u64,
+        buf: &[u8],
+    ) -> Result<(), Self::Error> {
+        let mut sector = [0; DEVICE_SECTOR_SIZE];
+        for (i, chunk) in buf.chunks(DEVICE_SECTOR_SIZE).enumerate() {
+            sector.copy_from_slice(chunk);
+            self.write_sector(lba + i as u64, &sector)?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure every write so far has reached the disk
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Adapts a `BlockDevice` to the byte-addressed `Volume` interface. Every
+/// read or commit turns into a single `read_sectors` or `write_sectors` call
+/// covering the sectors it touches; commits that start or end mid-sector
+/// read those sectors first so that the rest of them is preserved.
+pub struct BlockDeviceVolume<D: BlockDevice, S: SectorSize> {
+    device: D,
+    _phantom: PhantomData<S>,
+}
+
+impl<D: BlockDevice, S: SectorSize> BlockDeviceVolume<D, S> {
+    pub fn new(device: D) -> BlockDeviceVolume<D, S> {
+        BlockDeviceVolume {
+            device,
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn device(&self) -> &D {
+        &self.device
+    }
+
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    /// Fails with `Error::AddressOutOfBounds` if `end` lies past the end of
+    /// the device
+    fn check_bounds(&self, end: Address<S>) -> Result<()> {
+        if self.size() < end {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the device sectors covering the bytes in `start..end`
+    fn sectors(start: u64, end: u64) -> Range<u64> {
+        let size = DEVICE_SECTOR_SIZE as u64;
+        start / size..end.div_ceil(size)
+    }
+
+    /// Reads the device sectors in `sectors` into `buf`
+    fn read(&self, sectors: Range<u64>, buf: &mut [u8]) -> Result<()> {
+        let count = (sectors.end - sectors.start) as usize;
+        self.device
+            .read_sectors(sectors.start, count, buf)
+            .map_err(|err| err.into())
+    }
+}
+
+impl<D: BlockDevice, S: SectorSize> Volume<u8, S> for BlockDeviceVolume<D, S> {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        let len = self.device.sector_count() * DEVICE_SECTOR_SIZE as u64;
+        Size::Bounded(Address::from(len))
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        if slice.is_empty() {
+            return Ok(());
+        }
+        let start = slice.address().into_index();
+        let end = start + slice.len() as u64;
+        self.check_bounds(Address::from(end))?;
+
+        let sectors = Self::sectors(start, end);
+        let base = sectors.start * DEVICE_SECTOR_SIZE as u64;
+        let mut buf = vec![
+            0;
+            (sectors.end - sectors.start) as usize
+                * DEVICE_SECTOR_SIZE
+        ];
+        // keep what's around the commit in the sectors at either end
+        let head = start != base;
+        let tail = !end.is_multiple_of(DEVICE_SECTOR_SIZE as u64);
+        if head {
+            let first = sectors.start..sectors.start + 1;
+            self.read(first, &mut buf[..DEVICE_SECTOR_SIZE])?;
+        }
+        if tail && !(head && sectors.end - sectors.start == 1) {
+            let last = sectors.end - 1..sectors.end;
+            let len = buf.len();
+            self.read(last, &mut buf[len - DEVICE_SECTOR_SIZE..])?;
+        }
+
+        let offset = (start - base) as usize;
+        buf[offset..offset + slice.len()].copy_from_slice(&slice);
+        self.device
+            .write_sectors(sectors.start, &buf)
+            .map_err(|err| err.into())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.device.flush().map_err(|err| err.into())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, u8, S> {
+        self.slice(range).unwrap_or_else(|err| {
+            panic!("couldn't read from BlockDeviceVolume: {:?}", err)
+        })
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+        self.check_bounds(range.end)?;
+        let start = range.start.into_index();
+        let end = range.end.into_index();
+        if end <= start {
+            return Ok(VolumeSlice::new_owned(Vec::new(), range.start));
+        }
+
+        let sectors = Self::sectors(start, end);
+        let mut buf = vec![
+            0;
+            (sectors.end - sectors.start) as usize
+                * DEVICE_SECTOR_SIZE
+        ];
+        self.read(sectors.clone(), &mut buf)?;
+        let offset =
+            (start - sectors.start * DEVICE_SECTOR_SIZE as u64) as usize;
+        buf.truncate(offset + (end - start) as usize);
+        buf.drain(..offset);
+        Ok(VolumeSlice::new_owned(buf, range.start))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use error::{Error, Result};
+    use fs::sync::tests::fsck;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size1024, Size512};
+    use sys::inode::Permissions;
+    use volume::{Volume, VolumeCommit};
+
+    use super::{BlockDevice, BlockDeviceVolume, DEVICE_SECTOR_SIZE};
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Request {
+        Read(u64, usize),
+        Write(u64, usize),
+        Flush,
+    }
+
+    /// An in-memory device that logs every driver call, transferring one
+    /// sector per call
+    struct Mock {
+        data: Vec<u8>,
+        log: RefCell<Vec<Request>>,
+    }
+
+    impl Mock {
+        fn new(data: Vec<u8>) -> Mock {
+            Mock {
+                data,
+                log: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn take_log(&self) -> Vec<Request> {
+            self.log.borrow_mut().drain(..).collect()
+        }
+    }
+
+    impl BlockDevice for Mock {
+        type Error = Error;
+
+        fn sector_count(&self) -> u64 {
+            (self.data.len() / DEVICE_SECTOR_SIZE) as u64
+        }
+
+        fn read_sector(
+            &self,
+            lba: u64,
+            buf: &mut [u8; DEVICE_SECTOR_SIZE],
+        ) -> Result<()> {
+            self.log.borrow_mut().push(Request::Read(lba, 1));
+            let start = lba as usize * DEVICE_SECTOR_SIZE;
+            buf.copy_from_slice(&self.data[start..start + DEVICE_SECTOR_SIZE]);
+            Ok(())
+        }
+
+        fn write_sector(
+            &mut self,
+            lba: u64,
+            buf: &[u8; DEVICE_SECTOR_SIZE],
+        ) -> Result<()> {
+            self.log.borrow_mut().push(Request::Write(lba, 1));
+            let start = lba as usize * DEVICE_SECTOR_SIZE;
+            self.data[start..start + DEVICE_SECTOR_SIZE].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.log.borrow_mut().push(Request::Flush);
+            Ok(())
+        }
+    }
+
+    /// A `Mock` that transfers any number of sectors per call
+    struct Multi(Mock);
+
+    impl BlockDevice for Multi {
+        type Error = Error;
+
+        fn sector_count(&self) -> u64 {
+            self.0.sector_count()
+        }
+
+        fn read_sector(
+            &self,
+            lba: u64,
+            buf: &mut [u8; DEVICE_SECTOR_SIZE],
+        ) -> Result<()> {
+            self.0.read_sector(lba, buf)
+        }
+
+        fn write_sector(
+            &mut self,
+            lba: u64,
+            buf: &[u8; DEVICE_SECTOR_SIZE],
+        ) -> Result<()> {
+            self.0.write_sector(lba, buf)
+        }
+
+        fn read_sectors(
+            &self,
+            lba: u64,
+            count: usize,
+            buf: &mut [u8],
+        ) -> Result<()> {
+            self.0.log.borrow_mut().push(Request::Read(lba, count));
+            let start = lba as usize * DEVICE_SECTOR_SIZE;
+            buf.copy_from_slice(&self.0.data[start..start + buf.len()]);
+            Ok(())
+        }
+
+        fn write_sectors(&mut self, lba: u64, buf: &[u8]) -> Result<()> {
+            let count = buf.len() / DEVICE_SECTOR_SIZE;
+            self.0.log.borrow_mut().push(Request::Write(lba, count));
+            let start = lba as usize * DEVICE_SECTOR_SIZE;
+            self.0.data[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.0.flush()
+        }
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / 7) as u8).collect()
+    }
+
+    #[test]
+    fn read() {
+        let data = pattern(8 * 512);
+        let volume = BlockDeviceVolume::<_, Size512>::new(Multi(Mock::new(
+            data.clone(),
+        )));
+        let read = |start: u64, end: u64| {
+            volume
+                .slice(Address::from(start)..Address::from(end))
+                .unwrap()
+                .to_vec()
+        };
+
+        // aligned, mid-sector and across sectors, each in a single call
+        assert_eq!(read(512, 1536), data[512..1536]);
+        assert_eq!(volume.device().0.take_log(), [Request::Read(1, 2)]);
+        assert_eq!(read(700, 710), data[700..710]);
+        assert_eq!(volume.device().0.take_log(), [Request::Read(1, 1)]);
+        assert_eq!(read(500, 2100), data[500..2100]);
+        assert_eq!(volume.device().0.take_log(), [Request::Read(0, 5)]);
+        assert!(read(100, 100).is_empty());
+        assert!(volume.device().0.take_log().is_empty());
+
+        let end = Address::<Size512>::from(8 * 512 + 1_u64);
+        match volume.slice(Address::new(7, 0)..end) {
+            Err(Error::AddressOutOfBounds {
+                sector: 8,
+                offset: 1,
+                size: 512,
+            }) => (),
+            other => panic!("{:?}", other.map(|slice| slice.to_vec())),
+        }
+        assert!(volume.device().0.take_log().is_empty());
+    }
+
+    #[test]
+    fn read_single_sectors() {
+        let data = pattern(8 * 512);
+        let volume =
+            BlockDeviceVolume::<_, Size512>::new(Mock::new(data.clone()));
+        let range = Address::from(500_u64)..Address::from(1600_u64);
+        assert_eq!(volume.slice(range).unwrap().to_vec(), data[500..1600]);
+        assert_eq!(
+            volume.device().take_log(),
+            [
+                Request::Read(0, 1),
+                Request::Read(1, 1),
+                Request::Read(2, 1),
+                Request::Read(3, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn write() {
+        let mut data = pattern(8 * 512);
+        let mut volume = BlockDeviceVolume::<_, Size512>::new(Multi(
+            Mock::new(data.clone()),
+        ));
+        let mut write = |volume: &mut BlockDeviceVolume<Multi, Size512>,
+                         start: usize,
+                         len: usize| {
+            let bytes = vec![0xaa; len];
+            data[start..start + len].copy_from_slice(&bytes);
+            let commit = VolumeCommit::new(bytes, Address::from(start));
+            volume.commit(Some(commit)).unwrap();
+            assert_eq!(volume.device().0.data, data);
+            volume.device().0.take_log()
+        };
+
+        // whole sectors need no reads
+        assert_eq!(write(&mut volume, 1024, 1024), [Request::Write(2, 2)]);
+        // a partial sector is read first
+        assert_eq!(
+            write(&mut volume, 600, 10),
+            [Request::Read(1, 1), Request::Write(1, 1)]
+        );
+        // as are both ends of a commit spanning several
+        assert_eq!(
+            write(&mut volume, 1000, 1100),
+            [
+                Request::Read(1, 1),
+                Request::Read(4, 1),
+                Request::Write(1, 4)
+            ]
+        );
+        // but not an aligned start
+        assert_eq!(
+            write(&mut volume, 2560, 600),
+            [Request::Read(6, 1), Request::Write(5, 2)]
+        );
+
+        let commit =
+            VolumeCommit::<_, Size512>::new(vec![0; 10], Address::new(7, 510));
+        assert!(volume.commit(Some(commit)).is_err());
+        assert!(volume.device().0.take_log().is_empty());
+
+        volume.flush().unwrap();
+        assert_eq!(volume.device().0.take_log(), [Request::Flush]);
+    }
+
+    #[test]
+    fn write_single_sectors() {
+        let mut volume =
+            BlockDeviceVolume::<_, Size512>::new(Mock::new(pattern(8 * 512)));
+        let commit = VolumeCommit::new(vec![1; 1024], Address::from(1000_u64));
+        volume.commit(Some(commit)).unwrap();
+        assert_eq!(
+            volume.device().take_log(),
+            [
+                Request::Read(1, 1),
+                Request::Read(3, 1),
+                Request::Write(1, 1),
+                Request::Write(2, 1),
+                Request::Write(3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn filesystem() {
+        // larger volume sectors work on top of 512-byte device sectors
+        let image = std::fs::read("ext2.img").unwrap();
+        let device = Multi(Mock::new(image));
+        let volume = BlockDeviceVolume::<_, Size1024>::new(device);
+        let fs = Synced::<Ext2<Size1024, _>>::new(volume).unwrap();
+
+        let mut data = Vec::new();
+        let readme = fs.lookup(b"/home/funky/README.md").unwrap();
+        readme.read_to_end(&mut data).unwrap();
+        assert_eq!(data.len(), 31);
+
+        let mut file = fs.create_file(b"/device", Permissions::U_READ).unwrap();
+        file.write_at(0, &pattern(5000)).unwrap();
+        let fs = fs.inner();
+        assert!(fsck(&fs.volume.device().0.data));
+    }
+}
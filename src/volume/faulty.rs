@@ -0,0 +1,289 @@
+use core::cell::Cell;
+use core::ops::Range;
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// A fault `Faulty` injects into the accesses to its volume. Reads and
+/// commits are counted from zero, separately, from the moment the volume is
+/// wrapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Fails the `n`th read
+    FailRead(usize),
+    /// Fails every read touching one of the sectors in the range
+    FailSectors(Range<u64>),
+    /// Cuts the `read`th read short by `missing` bytes
+    ShortRead { read: usize, missing: usize },
+    /// Fails the `n`th commit
+    FailCommit(usize),
+}
+
+/// Wraps a volume to make accesses to it fail according to a schedule of
+/// `Fault`s, to exercise the error paths of code reading from volumes.
+///
+/// Only `slice` can fail a read: `slice_unchecked` has no way to report an
+/// error, so it counts as a read but only ever comes out short.
+///
+/// Available in tests and with the `testing` feature.
+pub struct Faulty<V> {
+    volume: V,
+    faults: Vec<Fault>,
+    reads: Cell<usize>,
+    commits: usize,
+}
+
+impl<V> Faulty<V> {
+    /// Wraps `volume`, with no faults scheduled yet
+    pub fn new(volume: V) -> Faulty<V> {
+        Faulty {
+            volume,
+            faults: Vec::new(),
+            reads: Cell::new(0),
+            commits: 0,
+        }
+    }
+
+    /// Adds `fault` to the schedule
+    pub fn inject(&mut self, fault: Fault) {
+        self.faults.push(fault);
+    }
+
+    /// Drops every fault scheduled so far
+    pub fn clear(&mut self) {
+        self.faults.clear();
+    }
+
+    /// Returns the number of reads made so far, faulty or not
+    pub fn reads(&self) -> usize {
+        self.reads.get()
+    }
+
+    /// Returns the number of commits made so far, faulty or not
+    pub fn commits(&self) -> usize {
+        self.commits
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.volume
+    }
+
+    pub fn into_inner(self) -> V {
+        self.volume
+    }
+
+    /// Counts a read of `range`, returning whether it should fail and how
+    /// many bytes to leave out of it
+    fn read<S: SectorSize>(&self, range: &Range<Address<S>>) -> (bool, usize) {
+        let read = self.reads.get();
+        self.reads.set(read + 1);
+        let last = if range.end.offset() == 0 {
+            range.end.sector().saturating_sub(1)
+        } else {
+            range.end.sector()
+        };
+        let sectors = range.start.sector()..last + 1;
+
+        let mut fail = false;
+        let mut missing = 0;
+        for fault in self.faults.iter() {
+            match *fault {
+                Fault::FailRead(n) => fail |= n == read,
+                Fault::FailSectors(ref faulty) => {
+                    fail |=
+                        faulty.start < sectors.end && sectors.start < faulty.end
+                }
+                Fault::ShortRead {
+                    read: n,
+                    missing: m,
+                } if n == read => missing = missing.max(m),
+                _ => (),
+            }
+        }
+        (fail, missing)
+    }
+}
+
+fn injected() -> Error {
+    Error::Other(String::from("injected fault"))
+}
+
+/// Leaves the last `missing` elements out of `slice`
+fn shorten<T: Clone, S: SectorSize>(
+    slice: VolumeSlice<'_, T, S>,
+    missing: usize,
+) -> VolumeSlice<'_, T, S> {
+    if missing == 0 {
+        return slice;
+    }
+    let len = slice.len().saturating_sub(missing);
+    let inner = match slice.inner {
+        Cow::Borrowed(inner) => Cow::Borrowed(&inner[..len]),
+        Cow::Owned(mut inner) => {
+            inner.truncate(len);
+            Cow::Owned(inner)
+        }
+    };
+    VolumeSlice {
+        inner,
+        index: slice.index,
+    }
+}
+
+impl<T: Clone, S: SectorSize, V: Volume<T, S>> Volume<T, S> for Faulty<V> {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        self.volume.size()
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<T, S>>,
+    ) -> Result<(), Self::Error> {
+        if slice.is_none() {
+            return Ok(());
+        }
+        let commit = self.commits;
+        self.commits += 1;
+        if self.faults.contains(&Fault::FailCommit(commit)) {
+            return Err(injected());
+        }
+        self.volume.commit(slice).map_err(|err| err.into())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.volume.flush().map_err(|err| err.into())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, T, S> {
+        let (_, missing) = self.read(&range);
+        shorten(self.volume.slice_unchecked(range), missing)
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, T, S>, Self::Error> {
+        let (fail, missing) = self.read(&range);
+        if fail {
+            return Err(injected());
+        }
+        let slice = self.volume.slice(range).map_err(|err| err.into())?;
+        Ok(shorten(slice, missing))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::{Error, Result};
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use volume::{Volume, VolumeCommit};
+
+    use super::{Fault, Faulty};
+
+    fn mount(
+        volume: Faulty<Vec<u8>>,
+    ) -> Result<Ext2<Size512, Faulty<Vec<u8>>>> {
+        Ext2::new(volume)
+    }
+
+    fn image() -> Faulty<Vec<u8>> {
+        Faulty::new(std::fs::read("ext2.img").unwrap())
+    }
+
+    fn is_injected<T>(result: Result<T>) -> bool {
+        match result {
+            Err(Error::Other(ref msg)) => msg == "injected fault",
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn schedule() {
+        let mut volume =
+            Faulty::new((0..2048).map(|i| i as u8).collect::<Vec<_>>());
+        volume.inject(Fault::FailRead(1));
+        volume.inject(Fault::FailSectors(2..3));
+        volume.inject(Fault::ShortRead {
+            read: 2,
+            missing: 10,
+        });
+        volume.inject(Fault::FailCommit(0));
+        let range = |start: u64, end: u64| {
+            Address::<Size512>::from(start)..Address::from(end)
+        };
+
+        assert!(volume.slice(range(0, 100)).is_ok());
+        assert!(is_injected(volume.slice(range(0, 100))));
+        let short = volume.slice(range(0, 100)).unwrap();
+        assert_eq!(&short[..], &volume.inner()[..90]);
+        assert!(!short.is_mutated());
+        // sector 2 is touched by its first byte, but not by its end
+        assert!(volume.slice(range(0, 1024)).is_ok());
+        assert!(is_injected(volume.slice(range(1000, 1025))));
+        assert!(volume.slice(range(1536, 2048)).is_ok());
+        assert_eq!(volume.reads(), 6);
+
+        let commit =
+            VolumeCommit::new(vec![0; 4], Address::<Size512>::new(0, 0));
+        assert!(is_injected(volume.commit(Some(commit))));
+        let commit =
+            VolumeCommit::new(vec![0; 4], Address::<Size512>::new(0, 0));
+        assert!(volume.commit(Some(commit)).is_ok());
+        assert_eq!(volume.commits(), 2);
+
+        volume.clear();
+        assert!(volume.slice(range(1000, 1025)).is_ok());
+    }
+
+    #[test]
+    fn superblock_read() {
+        let mut volume = image();
+        // the superblock takes up bytes 1024 to 2047
+        volume.inject(Fault::FailSectors(2..4));
+        assert!(is_injected(mount(volume)));
+
+        let mut volume = image();
+        volume.inject(Fault::FailRead(0));
+        assert!(is_injected(mount(volume)));
+    }
+
+    #[test]
+    fn descriptor_read() {
+        // the descriptor table follows the superblock, in block 2
+        let mut volume = image();
+        volume.inject(Fault::FailSectors(4..6));
+        assert!(is_injected(mount(volume)));
+    }
+
+    #[test]
+    fn inode_table_read() {
+        let fs = Synced::with_inner(mount(image()).unwrap());
+        {
+            let mut fs = fs.inner();
+            let sector = fs.inode_address(20).unwrap().sector();
+            fs.volume.inject(Fault::FailSectors(sector..sector + 1));
+        }
+
+        // the iteration stops at the first inode sharing a sector with 20
+        assert_eq!(512 / fs.inner().inode_size(), 4);
+        assert_eq!(fs.inodes().count(), 16);
+        assert!(is_injected(fs.inner().read_inode(20)));
+        assert!(is_injected(fs.inner().read_inode(17)));
+        assert!(fs.inner().read_inode(16).is_ok());
+        assert!(fs.inner().read_inode(21).is_ok());
+    }
+}
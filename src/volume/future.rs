@@ -0,0 +1,320 @@
+//! Volumes read and written without blocking, for file systems driven by
+//! an executor, see `fs::future`
+
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit};
+
+/// Like `Volume`, for storage whose accesses complete later rather than
+/// block, such as a driver waiting on an interrupt. Only `core::future` is
+/// involved, so any executor can drive it.
+///
+/// An access returning `Poll::Pending` has to arrange for the waker of `cx`
+/// to be woken once it can make progress, and is then polled again with the
+/// same arguments until it's ready. An access that isn't polled again is
+/// abandoned.
+pub trait AsyncVolume<T: Clone, S: SectorSize> {
+    /// Converted into the crate's `Error` on the way up
+    type Error: Into<Error>;
+
+    fn size(&self) -> Size<S>;
+
+    /// Fills `buf` with the contents of the volume from `start` on
+    fn poll_read(
+        &self,
+        cx: &mut Context<'_>,
+        start: Address<S>,
+        buf: &mut [T],
+    ) -> Poll<Result<(), Self::Error>>;
+
+    /// Writes `data` to the volume at `start`
+    fn poll_write(
+        &mut self,
+        cx: &mut Context<'_>,
+        start: Address<S>,
+        data: &[T],
+    ) -> Poll<Result<(), Self::Error>>;
+
+    /// Makes sure every write so far has reached the underlying storage
+    fn poll_flush(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads `len` elements from `start` on
+    fn read(&self, start: Address<S>, len: usize) -> Read<'_, T, S, Self>
+    where
+        Self: Sized,
+        T: Default,
+    {
+        Read {
+            volume: self,
+            start,
+            buf: Some(vec![T::default(); len]),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Writes `data` at `start`
+    fn write<'a>(
+        &'a mut self,
+        start: Address<S>,
+        data: &'a [T],
+    ) -> Write<'a, T, S, Self>
+    where
+        Self: Sized,
+    {
+        Write {
+            volume: self,
+            start,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Future of `AsyncVolume::read`
+pub struct Read<'a, T, S: SectorSize, V: 'a> {
+    volume: &'a V,
+    start: Address<S>,
+    buf: Option<Vec<T>>,
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T, S, V> Future for Read<'a, T, S, V>
+where
+    T: Clone,
+    S: SectorSize,
+    V: AsyncVolume<T, S>,
+{
+    type Output = Result<Vec<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut buf = this.buf.take().expect("read polled after completion");
+        match this.volume.poll_read(cx, this.start, &mut buf) {
+            Poll::Pending => {
+                this.buf = Some(buf);
+                Poll::Pending
+            }
+            Poll::Ready(result) => {
+                Poll::Ready(result.map(|_| buf).map_err(|err| err.into()))
+            }
+        }
+    }
+}
+
+impl<'a, T, S: SectorSize, V> Unpin for Read<'a, T, S, V> {}
+
+/// Future of `AsyncVolume::write`
+pub struct Write<'a, T: 'a, S: SectorSize, V: 'a> {
+    volume: &'a mut V,
+    start: Address<S>,
+    data: &'a [T],
+    _phantom: PhantomData<T>,
+}
+
+impl<'a, T, S, V> Future for Write<'a, T, S, V>
+where
+    T: Clone,
+    S: SectorSize,
+    V: AsyncVolume<T, S>,
+{
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.volume
+            .poll_write(cx, this.start, this.data)
+            .map_err(|err| err.into())
+    }
+}
+
+impl<'a, T, S: SectorSize, V> Unpin for Write<'a, T, S, V> {}
+
+/// Turns a `Volume` into an `AsyncVolume` whose accesses are all ready the
+/// first time they're polled, blocking in the meantime if the volume does.
+/// Mostly useful for tests, and for volumes in memory.
+pub struct Blocking<V> {
+    volume: V,
+}
+
+impl<V> Blocking<V> {
+    pub fn new(volume: V) -> Blocking<V> {
+        Blocking { volume }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.volume
+    }
+
+    pub fn into_inner(self) -> V {
+        self.volume
+    }
+}
+
+impl<T: Clone, S: SectorSize, V: Volume<T, S>> AsyncVolume<T, S>
+    for Blocking<V>
+{
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        self.volume.size()
+    }
+
+    fn poll_read(
+        &self,
+        _cx: &mut Context<'_>,
+        start: Address<S>,
+        buf: &mut [T],
+    ) -> Poll<Result<(), Error>> {
+        let end = match start.checked_add(Address::from(buf.len())) {
+            Some(end) => end,
+            None => return Poll::Ready(Err(Error::ArithmeticOverflow)),
+        };
+        let slice = match self.volume.slice(start..end) {
+            Ok(slice) => slice,
+            Err(err) => return Poll::Ready(Err(err.into())),
+        };
+        if slice.len() < buf.len() {
+            return Poll::Ready(Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            }));
+        }
+        buf.clone_from_slice(&slice[..buf.len()]);
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(
+        &mut self,
+        _cx: &mut Context<'_>,
+        start: Address<S>,
+        data: &[T],
+    ) -> Poll<Result<(), Error>> {
+        let commit = VolumeCommit::new(data.to_vec(), start);
+        Poll::Ready(self.volume.commit(Some(commit)).map_err(|err| err.into()))
+    }
+
+    fn poll_flush(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Poll::Ready(self.volume.flush().map_err(|err| err.into()))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::cell::Cell;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use error::Error;
+    use sector::{Address, SectorSize, Size512};
+    use volume::size::Size;
+
+    use super::{AsyncVolume, Blocking};
+
+    /// Polls `future` until it's ready, as an executor with nothing else to
+    /// run would
+    pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Makes every access wait for one more poll before going through to
+    /// the volume, waking the task right away
+    pub(crate) struct Stalling<V> {
+        pub volume: V,
+        pub stalled: Cell<bool>,
+        pub polls: Cell<usize>,
+    }
+
+    impl<V> Stalling<V> {
+        pub fn new(volume: V) -> Stalling<V> {
+            Stalling {
+                volume,
+                stalled: Cell::new(false),
+                polls: Cell::new(0),
+            }
+        }
+
+        fn stall(&self, cx: &mut Context<'_>) -> bool {
+            self.polls.set(self.polls.get() + 1);
+            let stalled = !self.stalled.get();
+            self.stalled.set(stalled);
+            if stalled {
+                cx.waker().wake_by_ref();
+            }
+            stalled
+        }
+    }
+
+    impl<S: SectorSize, V: AsyncVolume<u8, S>> AsyncVolume<u8, S>
+        for Stalling<V>
+    {
+        type Error = V::Error;
+
+        fn size(&self) -> Size<S> {
+            self.volume.size()
+        }
+
+        fn poll_read(
+            &self,
+            cx: &mut Context<'_>,
+            start: Address<S>,
+            buf: &mut [u8],
+        ) -> Poll<Result<(), V::Error>> {
+            if self.stall(cx) {
+                return Poll::Pending;
+            }
+            self.volume.poll_read(cx, start, buf)
+        }
+
+        fn poll_write(
+            &mut self,
+            cx: &mut Context<'_>,
+            start: Address<S>,
+            data: &[u8],
+        ) -> Poll<Result<(), V::Error>> {
+            if self.stall(cx) {
+                return Poll::Pending;
+            }
+            self.volume.poll_write(cx, start, data)
+        }
+    }
+
+    #[test]
+    fn blocking() {
+        let mut volume = Stalling::new(Blocking::new(vec![0_u8; 4096]));
+        let start = Address::<Size512>::from(1000_u64);
+        block_on(volume.write(start, b"hello")).unwrap();
+        assert_eq!(&volume.volume.inner()[1000..1005], b"hello");
+        let read = block_on(volume.read(start, 5)).unwrap();
+        assert_eq!(read, b"hello");
+        // each access waited once
+        assert_eq!(volume.polls.get(), 4);
+
+        let end = Address::<Size512>::from(4000_u64);
+        match block_on(volume.read(end, 100)) {
+            Err(Error::AddressOutOfBounds { .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Reading a GUID partition table, falling back to its backup copy at the
+//! end of the disk if the primary one is damaged
+
+use core::char;
+use core::fmt::{self, Display};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::mbr::MBR_SECTOR_SIZE;
+use super::size::Size;
+use super::{Volume, VolumeSlice};
+
+/// The size of the sectors GPT addresses are counted in, whatever the sector
+/// size of the volume holding it
+pub const GPT_SECTOR_SIZE: u64 = MBR_SECTOR_SIZE;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+const HEADER_MIN_SIZE: usize = 92;
+const ENTRY_MIN_SIZE: usize = 128;
+/// Entry arrays larger than this are taken for corruption rather than read
+const ENTRIES_MAX_SIZE: usize = 1 << 20;
+
+/// A GUID, as laid out on disk: the first three fields little-endian, the
+/// rest as bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The partition type of Linux filesystems,
+    /// 0FC63DAF-8483-4772-8E79-3D69D8477DE4
+    pub const LINUX_FILESYSTEM: Guid = Guid([
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69,
+        0xd8, 0x47, 0x7d, 0xe4,
+    ]);
+
+    pub fn is_nil(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0)
+    }
+}
+
+impl Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6]
+        )?;
+        write!(f, "{:02X}{:02X}-", b[8], b[9])?;
+        b[10..]
+            .iter()
+            .try_for_each(|byte| write!(f, "{:02X}", byte))
+    }
+}
+
+/// A used entry of the partition table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    /// The last sector of the partition, inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptEntry {
+    /// Returns the offset of the partition in bytes
+    pub fn start(&self) -> u64 {
+        self.first_lba * GPT_SECTOR_SIZE
+    }
+
+    /// Returns the length of the partition in bytes
+    pub fn len(&self) -> u64 {
+        (self.last_lba + 1).saturating_sub(self.first_lba) * GPT_SECTOR_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_lba < self.first_lba
+    }
+
+    fn parse(entry: &[u8]) -> GptEntry {
+        let units = entry[56..128]
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .take_while(|&unit| unit != 0);
+        let name = char::decode_utf16(units)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        GptEntry {
+            type_guid: guid(&entry[0..16]),
+            unique_guid: guid(&entry[16..32]),
+            first_lba: read_u64(entry, 32),
+            last_lba: read_u64(entry, 40),
+            attributes: read_u64(entry, 48),
+            name,
+        }
+    }
+}
+
+/// Reads the used entries of the GUID partition table of `volume`, in table
+/// order. If the primary header at LBA 1 or its entries are corrupt, the
+/// backup header at the last LBA of the volume is read instead.
+pub fn partitions<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+) -> Result<Vec<GptEntry>> {
+    let primary = match read_table(volume, 1) {
+        Ok(entries) => return Ok(entries),
+        Err(err) => err,
+    };
+    let last = match volume.size() {
+        Size::Bounded(size) => size.into_index() / GPT_SECTOR_SIZE,
+        Size::Unbounded => return Err(primary),
+    };
+    if last < 2 {
+        return Err(primary);
+    }
+    // the primary table's error is the more telling one
+    read_table(volume, last - 1).map_err(|_| primary)
+}
+
+/// Reads the partitions of `volume` with the Linux filesystem type, which is
+/// where an ext2 filesystem would be
+pub fn find_linux_filesystem_partitions<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+) -> Result<Vec<GptEntry>> {
+    let mut entries = partitions(volume)?;
+    entries.retain(|entry| entry.type_guid == Guid::LINUX_FILESYSTEM);
+    Ok(entries)
+}
+
+/// Reads and checks the header at `lba` and the entries it points to
+fn read_table<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+    lba: u64,
+) -> Result<Vec<GptEntry>> {
+    let invalid = |reason| Error::InvalidPartitionTable { reason };
+    let header = read(volume, lba * GPT_SECTOR_SIZE, GPT_SECTOR_SIZE)?;
+    if &header[..8] != SIGNATURE {
+        return Err(invalid("missing GPT signature"));
+    }
+    let header_size = read_u32(&header, 12) as usize;
+    if header_size < HEADER_MIN_SIZE || header_size > header.len() {
+        return Err(invalid("bad GPT header size"));
+    }
+    let mut copy = header[..header_size].to_vec();
+    copy[16..20].copy_from_slice(&[0; 4]);
+    if crc32(&copy) != read_u32(&header, 16) {
+        return Err(invalid("GPT header checksum mismatch"));
+    }
+    if read_u64(&header, 24) != lba {
+        return Err(invalid("GPT header is not where it says it is"));
+    }
+
+    let entries_lba = read_u64(&header, 72);
+    let count = read_u32(&header, 80) as usize;
+    let entry_size = read_u32(&header, 84) as usize;
+    if entry_size < ENTRY_MIN_SIZE || !entry_size.is_multiple_of(8) {
+        return Err(invalid("bad GPT entry size"));
+    }
+    let len = count
+        .checked_mul(entry_size)
+        .filter(|&len| len <= ENTRIES_MAX_SIZE)
+        .ok_or_else(|| invalid("GPT entry array too large"))?;
+    let array = read(volume, entries_lba * GPT_SECTOR_SIZE, len as u64)?;
+    if crc32(&array) != read_u32(&header, 88) {
+        return Err(invalid("GPT entry array checksum mismatch"));
+    }
+
+    Ok(array
+        .chunks(entry_size)
+        .filter(|entry| !guid(&entry[0..16]).is_nil())
+        .map(GptEntry::parse)
+        .collect())
+}
+
+fn read<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+    start: u64,
+    len: u64,
+) -> Result<VolumeSlice<'_, u8, S>> {
+    volume
+        .slice(Address::from(start)..Address::from(start + len))
+        .map_err(|err| err.into())
+}
+
+fn guid(bytes: &[u8]) -> Guid {
+    let mut guid = [0; 16];
+    guid.copy_from_slice(bytes);
+    Guid(guid)
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(buf)
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(buf)
+}
+
+/// The CRC-32 used by GPT, the same as zlib's
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::Size512;
+    use volume::OffsetVolume;
+
+    use super::{crc32, find_linux_filesystem_partitions, partitions, Guid};
+
+    const ENTRIES: usize = 128;
+    const ENTRY_SIZE: usize = 128;
+    const ARRAY_SECTORS: u64 = (ENTRIES * ENTRY_SIZE / 512) as u64;
+
+    fn put_u32(bytes: &mut [u8], at: usize, value: u32) {
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(bytes: &mut [u8], at: usize, value: u64) {
+        bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Writes the primary and backup tables of a disk holding `parts`, each
+    /// a type, first and last LBA and name
+    fn write_gpt(disk: &mut [u8], parts: &[(Guid, u64, u64, &str)]) {
+        let last = disk.len() as u64 / 512 - 1;
+        let mut array = vec![0; ENTRIES * ENTRY_SIZE];
+        for (i, &(kind, first, end, name)) in parts.iter().enumerate() {
+            let entry = &mut array[i * ENTRY_SIZE..][..ENTRY_SIZE];
+            entry[0..16].copy_from_slice(&kind.0);
+            entry[16] = i as u8 + 1;
+            put_u64(entry, 32, first);
+            put_u64(entry, 40, end);
+            for (j, unit) in name.encode_utf16().enumerate() {
+                entry[56 + j * 2..58 + j * 2]
+                    .copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+
+        for &(lba, backup, entries) in
+            &[(1, last, 2), (last, 1, last - ARRAY_SECTORS)]
+        {
+            let mut header = vec![0; 512];
+            header[..8].copy_from_slice(b"EFI PART");
+            put_u32(&mut header, 8, 0x0001_0000);
+            put_u32(&mut header, 12, 92);
+            put_u64(&mut header, 24, lba);
+            put_u64(&mut header, 32, backup);
+            put_u64(&mut header, 40, 2 + ARRAY_SECTORS);
+            put_u64(&mut header, 48, last - ARRAY_SECTORS - 1);
+            put_u64(&mut header, 72, entries);
+            put_u32(&mut header, 80, ENTRIES as u32);
+            put_u32(&mut header, 84, ENTRY_SIZE as u32);
+            put_u32(&mut header, 88, crc32(&array));
+            let crc = crc32(&header[..92]);
+            put_u32(&mut header, 16, crc);
+
+            let at = entries as usize * 512;
+            disk[at..at + array.len()].copy_from_slice(&array);
+            let at = lba as usize * 512;
+            disk[at..at + 512].copy_from_slice(&header);
+        }
+    }
+
+    const BASIC_DATA: Guid = Guid([
+        0xa2, 0xa0, 0xd0, 0xeb, 0xe5, 0xb9, 0x33, 0x44, 0x87, 0xc0, 0x68, 0xb6,
+        0xb7, 0x26, 0x99, 0xc7,
+    ]);
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn guid_display() {
+        assert_eq!(
+            format!("{}", Guid::LINUX_FILESYSTEM),
+            "0FC63DAF-8483-4772-8E79-3D69D8477DE4"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let mut disk = vec![0; 512 * 1024];
+        write_gpt(
+            &mut disk,
+            &[
+                (BASIC_DATA, 34, 99, "EFI"),
+                (Guid::LINUX_FILESYSTEM, 100, 899, "root \u{1f980}"),
+            ],
+        );
+        let parts = partitions::<Size512, _>(&disk).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name, "EFI");
+        assert_eq!(parts[0].type_guid, BASIC_DATA);
+        assert_eq!(parts[1].name, "root \u{1f980}");
+        assert_eq!(parts[1].unique_guid.0[0], 2);
+        assert_eq!((parts[1].start(), parts[1].len()), (100 * 512, 800 * 512));
+
+        let linux = find_linux_filesystem_partitions::<Size512, _>(&disk);
+        assert_eq!(linux.unwrap(), &parts[1..]);
+
+        // a corrupt primary header falls back to the backup one
+        disk[512 + 40] ^= 1;
+        assert_eq!(partitions::<Size512, _>(&disk).unwrap(), parts);
+        // as do corrupt primary entries
+        let mut copy = vec![0; disk.len()];
+        write_gpt(&mut copy, &[(BASIC_DATA, 34, 99, "EFI")]);
+        copy[1024 + 60] ^= 1;
+        assert_eq!(partitions::<Size512, _>(&copy).unwrap(), &parts[..1]);
+
+        // with both copies corrupt there's nothing to go by
+        let last = disk.len() - 512;
+        disk[last + 40] ^= 1;
+        assert!(partitions::<Size512, _>(&disk).is_err());
+        assert!(partitions::<Size512, _>(&vec![0; 4096]).is_err());
+    }
+
+    #[test]
+    fn mount() {
+        let image = std::fs::read("ext2.img").unwrap();
+        let mut disk = vec![0; (2048 + 64) * 512 + image.len()];
+        let last = 2048 + image.len() as u64 / 512 - 1;
+        write_gpt(&mut disk, &[(Guid::LINUX_FILESYSTEM, 2048, last, "")]);
+        disk[2048 * 512..][..image.len()].copy_from_slice(&image);
+
+        let part =
+            &find_linux_filesystem_partitions::<Size512, _>(&disk).unwrap()[0];
+        let volume = OffsetVolume::new(disk.clone(), part.start(), part.len());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert!(fs.lookup(b"/home/funky/README.md").is_ok());
+    }
+}
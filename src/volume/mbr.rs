@@ -0,0 +1,127 @@
+//! Reading the partition table of a classic master boot record
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::Volume;
+
+/// The size of the sectors MBR addresses are counted in, whatever the sector
+/// size of the volume holding it
+pub const MBR_SECTOR_SIZE: u64 = 512;
+
+const TABLE_OFFSET: usize = 446;
+const ENTRY_SIZE: usize = 16;
+const SIGNATURE: u16 = 0xaa55;
+
+/// A primary partition, as listed in the MBR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    /// The partition type, 0x83 for Linux filesystems
+    pub kind: u8,
+    pub bootable: bool,
+    /// The first sector of the partition
+    pub start_lba: u32,
+    /// The number of sectors in the partition
+    pub sectors: u32,
+}
+
+impl Partition {
+    /// Returns the offset of the partition in bytes
+    pub fn start(&self) -> u64 {
+        self.start_lba as u64 * MBR_SECTOR_SIZE
+    }
+
+    /// Returns the length of the partition in bytes
+    pub fn len(&self) -> u64 {
+        self.sectors as u64 * MBR_SECTOR_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sectors == 0
+    }
+}
+
+/// Reads the four primary partition entries from the MBR at the start of
+/// `volume`. Unused entries come out as `None`, keeping the others at their
+/// index so that entry `i` is partition `i + 1`. Fails with `BadMagic` if the
+/// boot signature is missing.
+pub fn partitions<S: SectorSize, V: Volume<u8, S>>(
+    volume: &V,
+) -> Result<[Option<Partition>; 4]> {
+    let mbr = volume
+        .slice(Address::from(0_u64)..Address::from(MBR_SECTOR_SIZE))
+        .map_err(|err| err.into())?;
+    let signature = u16::from_le_bytes([mbr[510], mbr[511]]);
+    if signature != SIGNATURE {
+        return Err(Error::BadMagic { magic: signature });
+    }
+
+    let mut partitions = [None; 4];
+    for (i, partition) in partitions.iter_mut().enumerate() {
+        let entry = &mbr[TABLE_OFFSET + i * ENTRY_SIZE..][..ENTRY_SIZE];
+        let kind = entry[4];
+        let sectors =
+            u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]);
+        if kind == 0 || sectors == 0 {
+            continue;
+        }
+        *partition = Some(Partition {
+            kind,
+            bootable: entry[0] & 0x80 != 0,
+            start_lba: u32::from_le_bytes([
+                entry[8], entry[9], entry[10], entry[11],
+            ]),
+            sectors,
+        });
+    }
+    Ok(partitions)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use error::Error;
+    use sector::Size512;
+
+    use super::{partitions, Partition};
+
+    /// Writes an MBR listing `(kind, start_lba, sectors)` as its partitions
+    pub(crate) fn write_mbr(disk: &mut [u8], entries: &[(u8, u32, u32)]) {
+        for (i, &(kind, start, sectors)) in entries.iter().enumerate() {
+            let entry = &mut disk[446 + i * 16..][..16];
+            entry[4] = kind;
+            entry[8..12].copy_from_slice(&start.to_le_bytes());
+            entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        }
+        disk[510] = 0x55;
+        disk[511] = 0xaa;
+    }
+
+    #[test]
+    fn parse() {
+        let mut disk = vec![0; 1024];
+        write_mbr(&mut disk, &[(0x0c, 63, 100), (0, 0, 0), (0x83, 200, 50)]);
+        disk[446] = 0x80;
+        let parts = partitions::<Size512, _>(&disk).unwrap();
+        assert_eq!(
+            parts[0],
+            Some(Partition {
+                kind: 0x0c,
+                bootable: true,
+                start_lba: 63,
+                sectors: 100,
+            })
+        );
+        assert_eq!(parts[1], None);
+        let linux = parts[2].unwrap();
+        assert!(!linux.bootable);
+        assert_eq!((linux.start(), linux.len()), (200 * 512, 50 * 512));
+        assert_eq!(parts[3], None);
+
+        disk[511] = 0;
+        match partitions::<Size512, _>(&disk) {
+            Err(Error::BadMagic { magic: 0x55 }) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        assert!(partitions::<Size512, _>(&vec![0; 100]).is_err());
+    }
+}
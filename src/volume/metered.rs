@@ -0,0 +1,221 @@
+use core::cell::{Cell, RefCell};
+use core::ops::Range;
+
+use alloc::collections::BTreeSet;
+
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// I/O statistics gathered by `Metered`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of slices taken
+    pub reads: u64,
+    /// Number of commits
+    pub writes: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// Number of sectors read, counting a sector once per read touching it
+    pub sectors_read: u64,
+    /// Number of sectors written, counting a sector once per commit
+    /// touching it
+    pub sectors_written: u64,
+    /// Number of distinct sectors read from or written to, if they're being
+    /// tracked
+    pub distinct_sectors: Option<u64>,
+}
+
+/// Counts the accesses made to a volume, passing them through unchanged.
+///
+/// Counting is a handful of integer additions per access. Counting distinct
+/// sectors needs to remember every sector seen, so it's off unless the
+/// volume is created with `with_distinct_sectors`.
+pub struct Metered<V> {
+    volume: V,
+    stats: Cell<Stats>,
+    distinct: Option<RefCell<BTreeSet<u64>>>,
+}
+
+impl<V> Metered<V> {
+    pub fn new(volume: V) -> Metered<V> {
+        Metered {
+            volume,
+            stats: Cell::new(Stats::default()),
+            distinct: None,
+        }
+    }
+
+    /// Like `new`, also counting the distinct sectors touched
+    pub fn with_distinct_sectors(volume: V) -> Metered<V> {
+        Metered {
+            distinct: Some(RefCell::new(BTreeSet::new())),
+            ..Metered::new(volume)
+        }
+    }
+
+    /// Returns the statistics gathered since the volume was wrapped or
+    /// `reset` was last called
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.get();
+        stats.distinct_sectors = self
+            .distinct
+            .as_ref()
+            .map(|distinct| distinct.borrow().len() as u64);
+        stats
+    }
+
+    /// Sets every statistic back to zero
+    pub fn reset(&self) {
+        self.stats.set(Stats::default());
+        if let Some(ref distinct) = self.distinct {
+            distinct.borrow_mut().clear();
+        }
+    }
+
+    pub fn inner(&self) -> &V {
+        &self.volume
+    }
+
+    pub fn into_inner(self) -> V {
+        self.volume
+    }
+
+    /// Counts an access to the bytes `[start, end)`, returning the number of
+    /// sectors it touches
+    fn touch<S: SectorSize>(&self, start: Address<S>, end: Address<S>) -> u64 {
+        if start >= end {
+            return 0;
+        }
+        let first = start.sector();
+        let last = Address::<S>::from(end.into_index() - 1).sector();
+        if let Some(ref distinct) = self.distinct {
+            distinct.borrow_mut().extend(first..=last);
+        }
+        last - first + 1
+    }
+
+    /// Counts a read of `range`
+    fn count_read<S: SectorSize>(&self, range: &Range<Address<S>>) {
+        let sectors = self.touch(range.start, range.end);
+        let mut stats = self.stats.get();
+        stats.reads += 1;
+        stats.bytes_read += range
+            .end
+            .into_index()
+            .saturating_sub(range.start.into_index());
+        stats.sectors_read += sectors;
+        self.stats.set(stats);
+    }
+}
+
+impl<T: Clone, S: SectorSize, V: Volume<T, S>> Volume<T, S> for Metered<V> {
+    type Error = V::Error;
+
+    fn size(&self) -> Size<S> {
+        self.volume.size()
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<T, S>>,
+    ) -> Result<(), Self::Error> {
+        if let Some(ref slice) = slice {
+            let start = slice.address();
+            let end = Address::from(start.into_index() + slice.len() as u64);
+            let sectors = self.touch(start, end);
+            let mut stats = self.stats.get();
+            stats.writes += 1;
+            stats.bytes_written += slice.len() as u64;
+            stats.sectors_written += sectors;
+            self.stats.set(stats);
+        }
+        self.volume.commit(slice)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.volume.flush()
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, T, S> {
+        self.count_read(&range);
+        self.volume.slice_unchecked(range)
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, T, S>, Self::Error> {
+        self.count_read(&range);
+        self.volume.slice(range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use volume::{Volume, VolumeCommit};
+
+    use super::{Metered, Stats};
+
+    #[test]
+    fn stats() {
+        let mut volume = Metered::with_distinct_sectors(vec![0_u8; 4096]);
+        volume
+            .slice(Address::<Size512>::new(0, 500)..Address::new(1, 100))
+            .unwrap();
+        volume
+            .slice(Address::<Size512>::new(1, 0)..Address::new(2, 0))
+            .unwrap();
+        let commit =
+            VolumeCommit::new(vec![1; 512], Address::<Size512>::new(5, 0));
+        volume.commit(Some(commit)).unwrap();
+        assert_eq!(
+            volume.stats(),
+            Stats {
+                reads: 2,
+                writes: 1,
+                bytes_read: 624,
+                bytes_written: 512,
+                sectors_read: 3,
+                sectors_written: 1,
+                distinct_sectors: Some(3),
+            }
+        );
+
+        volume.reset();
+        assert_eq!(
+            volume.stats(),
+            Stats {
+                distinct_sectors: Some(0),
+                ..Stats::default()
+            }
+        );
+        let volume = Metered::new(vec![0_u8; 4096]);
+        volume
+            .slice(Address::<Size512>::new(0, 0)..Address::new(8, 0))
+            .unwrap();
+        assert_eq!(volume.stats().sectors_read, 8);
+        assert_eq!(volume.stats().distinct_sectors, None);
+    }
+
+    #[test]
+    fn lookup_reads() {
+        let image = std::fs::read("ext2.img").unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(Metered::new(image)).unwrap();
+        fs.inner().volume.reset();
+        fs.lookup(b"/home/funky/README.md").unwrap();
+        // an inode and a directory block of 1 KiB for each of /, home and
+        // funky, and README.md's inode: reading anything else, such as the
+        // superblock, on the way would show up here
+        let stats = fs.inner().volume.stats();
+        assert!(stats.sectors_read <= 10, "{:?}", stats);
+        assert_eq!(stats.writes, 0);
+    }
+}
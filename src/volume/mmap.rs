@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io;
+use std::ops::Range;
+use std::os::raw::{c_int, c_long, c_void};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        offset: c_long,
+    ) -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int;
+}
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MS_SYNC: c_int = 4;
+
+/// A volume backed by a shared memory map of a file. Slices borrow straight
+/// from the mapping instead of copying, and commits write into it.
+///
+/// The mapping lives as long as the volume, and slices borrow the volume, so
+/// none of them can outlive it. Commits need the volume mutably, so no slice
+/// can observe one half done either.
+///
+/// Only available on Linux.
+pub struct MmapVolume {
+    ptr: *mut u8,
+    len: usize,
+    writable: bool,
+}
+
+// the mapping is plain memory owned by the volume, only ever written to
+// through `&mut self`
+unsafe impl Send for MmapVolume {}
+unsafe impl Sync for MmapVolume {}
+
+impl MmapVolume {
+    /// Maps the whole of `file` read-only. Commits to the volume fail.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be truncated or modified by anyone else while it's
+    /// mapped: the former turns reads into crashes, the latter changes
+    /// memory borrowed by slices under their feet.
+    pub unsafe fn map(file: &File) -> Result<MmapVolume> {
+        MmapVolume::map_with(file, false)
+    }
+
+    /// Maps the whole of `file`, which must be open for reading and writing,
+    /// so that commits are written through to the file.
+    ///
+    /// # Safety
+    ///
+    /// See `map`.
+    pub unsafe fn map_mut(file: &File) -> Result<MmapVolume> {
+        MmapVolume::map_with(file, true)
+    }
+
+    unsafe fn map_with(file: &File, writable: bool) -> Result<MmapVolume> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mapping nothing is an error, but there's nothing to read either
+            return Ok(MmapVolume {
+                ptr: ptr::NonNull::dangling().as_ptr(),
+                len,
+                writable,
+            });
+        }
+
+        let prot = if writable {
+            PROT_READ | PROT_WRITE
+        } else {
+            PROT_READ
+        };
+        let ptr =
+            mmap(ptr::null_mut(), len, prot, MAP_SHARED, file.as_raw_fd(), 0);
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(MmapVolume {
+            ptr: ptr as *mut u8,
+            len,
+            writable,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+impl Drop for MmapVolume {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                munmap(self.ptr as *mut c_void, self.len);
+            }
+        }
+    }
+}
+
+impl<S: SectorSize> Volume<u8, S> for MmapVolume {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        Size::Bounded(Address::from(self.len))
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        if !self.writable {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "commit to a read-only memory map",
+            )
+            .into());
+        }
+        let index = slice.address().into_index() as usize;
+        let end = index + slice.len();
+        if end > self.len {
+            let end = Address::<S>::from(end);
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+        self.as_mut_slice()[index..end].copy_from_slice(&slice);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        if self.writable && self.len > 0 {
+            let result =
+                unsafe { msync(self.ptr as *mut c_void, self.len, MS_SYNC) };
+            if result != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, u8, S> {
+        let index = range.start;
+        let range =
+            range.start.into_index() as usize..range.end.into_index() as usize;
+        VolumeSlice::new(self.as_slice().get_unchecked(range), index)
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+        let size = Volume::<u8, S>::size(self);
+        if range.start <= range.end && size >= range.end {
+            unsafe { Ok(self.slice_unchecked(range)) }
+        } else {
+            Err(Error::AddressOutOfBounds {
+                sector: range.end.sector(),
+                offset: range.end.offset(),
+                size: range.end.sector_size(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use sys::inode::Permissions;
+    use volume::{Volume, VolumeCommit};
+
+    use super::MmapVolume;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn mmap_volume() {
+        let path = std::env::temp_dir()
+            .join(format!("ext2-rs-mmap-{}.img", std::process::id()));
+        fs::copy("ext2.img", &path).unwrap();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let volume = unsafe { MmapVolume::map_mut(&file).unwrap() };
+
+        // slices borrow from the mapping, here the superblock magic
+        let range = Address::<Size512>::new(2, 56)..Address::new(2, 58);
+        let slice = volume.slice(range).unwrap();
+        assert!(!slice.is_mutated());
+        assert_eq!(&slice[..], &[0x53, 0xef]);
+        drop(slice);
+
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs.create_file(b"/mapped", Permissions::U_READ).unwrap();
+        file.write_at(0, b"through the map").unwrap();
+        fs.inner().flush().unwrap();
+        drop(file);
+        drop(fs);
+
+        let volume = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut data = Vec::new();
+        fs.lookup(b"/mapped")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"through the map");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn mmap_read_only() {
+        let file = File::open("ext2.img").unwrap();
+        let mut volume = unsafe { MmapVolume::map(&file).unwrap() };
+        let size = Volume::<u8, Size512>::size(&volume);
+        assert_eq!(size, Address::<Size512>::from(4_u64 << 20));
+
+        let commit =
+            VolumeCommit::<_, Size512>::new(vec![0], Address::new(0, 0));
+        assert!(volume.commit(Some(commit)).is_err());
+        let end = Address::<Size512>::from((4_u64 << 20) + 1);
+        assert!(volume.slice(Address::new(0, 0)..end).is_err());
+    }
+}
@@ -6,33 +6,53 @@ use alloc::borrow::{Cow, ToOwned};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
 
-use error::Error;
+use error::{Error, Result};
 use sector::{Address, SectorSize};
 
 pub mod size;
 use self::size::Size;
 
+mod overlay;
+pub use self::overlay::Overlay;
+
+/// A random-access store the filesystem lives on, read through slices and
+/// written to through commits
 pub trait Volume<T: Clone, S: SectorSize> {
+    /// Converted into the crate's `Error` on the way up. Errors of the
+    /// volume's own wrapped with `Error::volume` can be had back with
+    /// `Error::volume_error`.
     type Error: Into<Error>;
 
     fn size(&self) -> Size<S>;
 
+    /// Writes the contents of `slice` at its address. Passing `None` is a
+    /// no-op. A commit that would cross the end of a `Size::Bounded` volume
+    /// fails without writing anything rather than being truncated.
+    ///
+    /// Implementations may buffer commits until `flush` is called, but
+    /// slices taken after a commit must see its contents.
     fn commit(
         &mut self,
         slice: Option<VolumeCommit<T, S>>,
     ) -> Result<(), Self::Error>;
 
+    /// Makes sure every commit so far has reached the underlying storage.
+    /// Volumes that apply commits immediately have nothing to do.
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
     ///
     /// # Safety
     unsafe fn slice_unchecked(
         &self,
         range: Range<Address<S>>,
-    ) -> VolumeSlice<T, S>;
+    ) -> VolumeSlice<'_, T, S>;
 
     fn slice(
         &self,
         range: Range<Address<S>>,
-    ) -> Result<VolumeSlice<T, S>, Self::Error>;
+    ) -> Result<VolumeSlice<'_, T, S>, Self::Error>;
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -81,15 +101,19 @@ impl<'a, T: Clone, S: SectorSize> VolumeSlice<'a, T, S> {
 }
 
 impl<'a, S: SectorSize> VolumeSlice<'a, u8, S> {
+    /// Reinterprets the start of the slice as a `T`, in the byte order of
+    /// the host. On-disk structures, which are little-endian, are read with
+    /// their `from_bytes` constructors instead.
     ///
     /// # Safety
     pub unsafe fn dynamic_cast<T: Copy>(&self) -> (T, Address<S>) {
         assert!(self.inner.len() >= mem::size_of::<T>());
         let index = self.index;
-        let cast = mem::transmute_copy(self.inner.as_ptr().as_ref().unwrap());
+        let cast = (self.inner.as_ptr() as *const T).read_unaligned();
         (cast, index)
     }
 
+    /// Views the bytes of `cast`, in the byte order of the host
     pub fn from_cast<T: Copy>(
         cast: &'a T,
         index: Address<S>,
@@ -165,6 +189,16 @@ impl<T: Clone, S: SectorSize> VolumeCommit<T, S> {
     }
 }
 
+impl<S: SectorSize> VolumeCommit<u8, S> {
+    pub fn from_cast<T: Copy>(
+        cast: &T,
+        index: Address<S>,
+    ) -> VolumeCommit<u8, S> {
+        let inner = VolumeSlice::from_cast(cast, index).to_vec();
+        VolumeCommit::new(inner, index)
+    }
+}
+
 impl<T: Clone, S: SectorSize> AsRef<[T]> for VolumeCommit<T, S> {
     fn as_ref(&self) -> &[T] {
         self.inner.as_ref()
@@ -208,15 +242,24 @@ macro_rules! impl_slice {
                 &mut self,
                 slice: Option<VolumeCommit<T, S>>,
             ) -> Result<(), Self::Error> {
-                slice.map(|slice| {
-                    let index = slice.address().into_index() as usize;
-                    let end = index + slice.as_ref().len();
-                    // XXX: it would be much better to drop the contents of dst
-                    // and move the contents of slice instead of cloning
-                    let dst =
-                        &mut <Self as AsMut<[T]>>::as_mut(self)[index..end];
-                    dst.clone_from_slice(slice.as_ref());
-                });
+                let slice = match slice {
+                    Some(slice) => slice,
+                    None => return Ok(()),
+                };
+                let index = slice.address().into_index() as usize;
+                let end = index + slice.as_ref().len();
+                if end > <Self as AsRef<[T]>>::as_ref(self).len() {
+                    let end = Address::<S>::from(end);
+                    return Err(Error::AddressOutOfBounds {
+                        sector: end.sector(),
+                        offset: end.offset(),
+                        size: end.sector_size(),
+                    });
+                }
+                // XXX: it would be much better to drop the contents of dst
+                // and move the contents of slice instead of cloning
+                let dst = &mut <Self as AsMut<[T]>>::as_mut(self)[index..end];
+                dst.clone_from_slice(slice.as_ref());
                 Ok(())
             }
 
@@ -237,7 +280,8 @@ macro_rules! impl_slice {
                 &'a self,
                 range: Range<Address<S>>,
             ) -> Result<VolumeSlice<'a, T, S>, Self::Error> {
-                if self.size() >= range.end {
+                // a reversed range is as out of bounds as one past the end
+                if range.start <= range.end && self.size() >= range.end {
                     unsafe { Ok(self.slice_unchecked(range)) }
                 } else {
                     Err(Error::AddressOutOfBounds {
@@ -261,13 +305,57 @@ impl_slice!(&'b mut [T], 'b);
 impl_slice!(Vec<T>);
 impl_slice!(Box<[T]>);
 
-#[cfg(any(test, not(feature = "no_std")))]
+mod cache;
+pub use self::cache::{Cached, WritePolicy};
+
+mod chain;
+pub use self::chain::Chain;
+
+mod device;
+pub use self::device::{BlockDevice, BlockDeviceVolume, DEVICE_SECTOR_SIZE};
+
+#[cfg(any(test, feature = "testing"))]
+mod faulty;
+#[cfg(any(test, feature = "testing"))]
+pub use self::faulty::{Fault, Faulty};
+
+#[cfg(any(test, feature = "async"))]
+pub mod future;
+#[cfg(any(test, feature = "async"))]
+pub use self::future::{AsyncVolume, Blocking};
+
+pub mod gpt;
+pub mod mbr;
+
+mod metered;
+pub use self::metered::{Metered, Stats};
+
+mod shared;
+pub use self::shared::Shared;
+
+mod sparse;
+pub use self::sparse::SparseMem;
+
+mod offset;
+pub use self::offset::OffsetVolume;
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
+pub use self::file::FileVolume;
+
+#[cfg(all(target_os = "linux", any(test, feature = "mmap")))]
+mod mmap;
+#[cfg(all(target_os = "linux", any(test, feature = "mmap")))]
+pub use self::mmap::MmapVolume;
+
+#[cfg(any(test, feature = "std", not(feature = "no_std")))]
 mod file {
     use std::cell::RefCell;
-    use std::fs::File;
+    use std::fs::{File, OpenOptions};
     use std::io::{self, Read, Seek, SeekFrom, Write};
     use std::ops::Range;
+    use std::path::Path;
 
+    use error::{Error, Result};
     use sector::{Address, SectorSize};
 
     use super::size::Size;
@@ -289,26 +377,35 @@ mod file {
             &mut self,
             slice: Option<VolumeCommit<u8, S>>,
         ) -> Result<(), Self::Error> {
-            slice
-                .map(|slice| {
-                    let index = slice.address();
-                    let mut refmut = self.borrow_mut();
-                    refmut
-                        .seek(SeekFrom::Start(index.into_index()))
-                        .and_then(|_| refmut.write(slice.as_ref()))
-                        .map(|_| ())
-                })
-                .unwrap_or(Ok(()))
+            let slice = match slice {
+                Some(slice) => slice,
+                None => return Ok(()),
+            };
+            let index = slice.address();
+            let end = index.into_index() + slice.len() as u64;
+            if Volume::<u8, S>::size(self) < Address::<S>::from(end) {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "commit past the end of the volume",
+                ));
+            }
+            let mut refmut = self.borrow_mut();
+            refmut
+                .seek(SeekFrom::Start(index.into_index()))
+                .and_then(|_| refmut.write_all(slice.as_ref()))
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.borrow_mut().flush()
         }
 
         unsafe fn slice_unchecked(
             &self,
             range: Range<Address<S>>,
-        ) -> VolumeSlice<u8, S> {
+        ) -> VolumeSlice<'_, u8, S> {
             let index = range.start;
-            let len = range.end - range.start;
-            let mut vec = Vec::with_capacity(len.into_index() as usize);
-            vec.set_len(len.into_index() as usize);
+            let len = range.end.saturating_sub(range.start);
+            let mut vec = vec![0; len.into_index() as usize];
             let mut refmut = self.borrow_mut();
             refmut
                 .seek(SeekFrom::Start(index.into_index()))
@@ -322,14 +419,12 @@ mod file {
         fn slice(
             &self,
             range: Range<Address<S>>,
-        ) -> Result<VolumeSlice<u8, S>, Self::Error> {
+        ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
             let index = range.start;
-            let mut vec = Vec::with_capacity(
-                (range.end - range.start).into_index() as usize,
-            );
-            unsafe {
-                vec.set_len((range.end - range.start).into_index() as usize);
-            }
+            let len = range.end.checked_sub(range.start).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "reversed range")
+            })?;
+            let mut vec = vec![0; len.into_index() as usize];
             let mut refmut = self.borrow_mut();
             refmut
                 .seek(SeekFrom::Start(index.into_index()))
@@ -337,13 +432,181 @@ mod file {
                 .map(move |_| VolumeSlice::new_owned(vec, index))
         }
     }
+
+    /// A volume backed by a file, such as a disk image, read and written in
+    /// place rather than loaded into memory as a whole
+    #[derive(Debug)]
+    pub struct FileVolume {
+        file: File,
+    }
+
+    impl FileVolume {
+        /// Wraps `file`. It only needs to be opened for writing if the
+        /// filesystem is going to be modified.
+        pub fn new(file: File) -> FileVolume {
+            FileVolume { file }
+        }
+
+        /// Opens the file at `path` for reading and writing
+        pub fn open<P: AsRef<Path>>(path: P) -> Result<FileVolume> {
+            let file = OpenOptions::new().read(true).write(true).open(path)?;
+            Ok(FileVolume::new(file))
+        }
+
+        /// Returns the underlying file
+        pub fn into_inner(self) -> File {
+            self.file
+        }
+
+        /// Fails with `Error::AddressOutOfBounds` if `end` lies past the end
+        /// of the file
+        fn check_bounds<S: SectorSize>(
+            &self,
+            end: Address<S>,
+        ) -> Result<()> {
+            if Volume::<u8, S>::size(self) < end {
+                return Err(Error::AddressOutOfBounds {
+                    sector: end.sector(),
+                    offset: end.offset(),
+                    size: end.sector_size(),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    impl<S: SectorSize> Volume<u8, S> for FileVolume {
+        type Error = Error;
+
+        fn size(&self) -> Size<S> {
+            Size::Bounded(
+                self.file
+                    .metadata()
+                    .map(|data| Address::from(data.len()))
+                    .unwrap_or_else(|_| Address::new(0, 0)),
+            )
+        }
+
+        fn commit(
+            &mut self,
+            slice: Option<VolumeCommit<u8, S>>,
+        ) -> Result<(), Self::Error> {
+            let slice = match slice {
+                Some(slice) => slice,
+                None => return Ok(()),
+            };
+            let index = slice.address().into_index();
+            self.check_bounds(Address::<S>::from(index + slice.len() as u64))?;
+            self.file.seek(SeekFrom::Start(index))?;
+            self.file.write_all(slice.as_ref())?;
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.file.sync_data()?;
+            Ok(())
+        }
+
+        unsafe fn slice_unchecked(
+            &self,
+            range: Range<Address<S>>,
+        ) -> VolumeSlice<'_, u8, S> {
+            self.slice(range).unwrap_or_else(|err| {
+                panic!("couldn't read from FileVolume: {:?}", err)
+            })
+        }
+
+        fn slice(
+            &self,
+            range: Range<Address<S>>,
+        ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+            self.check_bounds(range.end)?;
+            let index = range.start;
+            let len = range.end.checked_sub(range.start).ok_or(
+                Error::AddressOutOfBounds {
+                    sector: index.sector(),
+                    offset: index.offset(),
+                    size: index.sector_size(),
+                },
+            )?;
+            let mut vec = vec![0; len.into_index() as usize];
+            let mut file = &self.file;
+            file.seek(SeekFrom::Start(index.into_index()))?;
+            file.read_exact(&mut vec)?;
+            Ok(VolumeSlice::new_owned(vec, index))
+        }
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use core::cell::Cell;
+    use core::fmt;
+
     use super::*;
     use sector::{Address, Size512};
 
+    /// An in-memory volume that records the address and length of every
+    /// commit, so that tests can check the order writes happen in, and
+    /// counts reads
+    pub(crate) struct Recorder {
+        pub inner: Vec<u8>,
+        pub commits: Vec<(u64, usize)>,
+        pub flushes: usize,
+        pub reads: Cell<usize>,
+    }
+
+    impl Recorder {
+        pub fn new(inner: Vec<u8>) -> Recorder {
+            Recorder {
+                inner,
+                commits: Vec::new(),
+                flushes: 0,
+                reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl<S: SectorSize> Volume<u8, S> for Recorder {
+        type Error = Error;
+
+        fn size(&self) -> Size<S> {
+            Volume::<u8, S>::size(&self.inner)
+        }
+
+        fn commit(
+            &mut self,
+            slice: Option<VolumeCommit<u8, S>>,
+        ) -> Result<(), Self::Error> {
+            if let Some(ref slice) = slice {
+                self.commits
+                    .push((slice.address().into_index(), slice.len()));
+            }
+            self.inner.commit(slice)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.flushes += 1;
+            Ok(())
+        }
+
+        unsafe fn slice_unchecked(
+            &self,
+            range: Range<Address<S>>,
+        ) -> VolumeSlice<'_, u8, S> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.slice_unchecked(range)
+        }
+
+        fn slice(
+            &self,
+            range: Range<Address<S>>,
+        ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+            self.reads.set(self.reads.get() + 1);
+            self.inner.slice(range)
+        }
+    }
+
     #[test]
     fn volume() {
         let mut volume = vec![0; 1024];
@@ -367,4 +630,136 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn file_volume() {
+        let path = std::env::temp_dir()
+            .join(format!("ext2-rs-volume-{}.img", std::process::id()));
+        std::fs::write(&path, vec![7; 1000]).unwrap();
+        let mut volume = FileVolume::open(&path).unwrap();
+
+        let size = Volume::<u8, Size512>::size(&volume);
+        assert_eq!(size, Address::<Size512>::from(1000_u64));
+        let range = Address::<Size512>::new(1, 0)..Address::new(1, 100);
+        assert_eq!(volume.slice(range).unwrap().to_vec(), vec![7; 100]);
+
+        let commit =
+            VolumeCommit::<_, Size512>::new(vec![1; 8], Address::new(1, 0));
+        volume.commit(Some(commit)).unwrap();
+        Volume::<u8, Size512>::flush(&mut volume).unwrap();
+        let range = Address::<Size512>::new(0, 510)..Address::new(1, 10);
+        let slice = volume.slice(range).unwrap();
+        assert_eq!(slice.to_vec(), [7, 7, 1, 1, 1, 1, 1, 1, 1, 1, 7, 7]);
+
+        let range = Address::<Size512>::new(1, 400)..Address::new(1, 500);
+        match volume.slice(range) {
+            Err(Error::AddressOutOfBounds {
+                sector: 1,
+                offset: 500,
+                size: 512,
+            }) => (),
+            other => panic!("{:?}", other.map(|slice| slice.to_vec())),
+        }
+        let commit =
+            VolumeCommit::<_, Size512>::new(vec![1; 8], Address::new(1, 490));
+        assert!(volume.commit(Some(commit)).is_err());
+
+        drop(volume);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(data.len(), 1000);
+        assert_eq!(data[512..520], [1; 8]);
+    }
+
+    #[test]
+    fn commit_out_of_bounds() {
+        let mut volume = vec![0; 1024];
+        let commit =
+            VolumeCommit::<_, Size512>::new(vec![1; 512], Address::new(1, 256));
+        assert!(volume.commit(Some(commit)).is_err());
+        assert!(volume.iter().all(|&x| x == 0));
+        assert!(volume.commit(None::<VolumeCommit<_, Size512>>).is_ok());
+    }
+
+    /// A driver error, as a block device would report it
+    #[derive(Debug, PartialEq)]
+    struct CrcError {
+        sector: u64,
+    }
+
+    impl fmt::Display for CrcError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "CRC mismatch in sector {}", self.sector)
+        }
+    }
+
+    impl From<CrcError> for Error {
+        fn from(err: CrcError) -> Error {
+            Error::volume(err)
+        }
+    }
+
+    /// A volume failing every read past `bad` with a `CrcError`
+    struct Crc {
+        inner: Vec<u8>,
+        bad: u64,
+    }
+
+    impl Volume<u8, Size512> for Crc {
+        type Error = CrcError;
+
+        fn size(&self) -> Size<Size512> {
+            self.inner.size()
+        }
+
+        fn commit(
+            &mut self,
+            _slice: Option<VolumeCommit<u8, Size512>>,
+        ) -> Result<(), CrcError> {
+            Ok(())
+        }
+
+        unsafe fn slice_unchecked(
+            &self,
+            range: Range<Address<Size512>>,
+        ) -> VolumeSlice<'_, u8, Size512> {
+            self.inner.slice_unchecked(range)
+        }
+
+        fn slice(
+            &self,
+            range: Range<Address<Size512>>,
+        ) -> Result<VolumeSlice<'_, u8, Size512>, CrcError> {
+            if range.end.sector() > self.bad {
+                return Err(CrcError {
+                    sector: range.end.sector(),
+                });
+            }
+            Ok(self.inner.slice(range).unwrap())
+        }
+    }
+
+    #[test]
+    fn volume_errors() {
+        use fs::sync::Synced;
+        use fs::Ext2;
+
+        // the directory blocks of /home/funky are at 3586
+        let volume = Crc {
+            inner: std::fs::read("ext2.img").unwrap(),
+            bad: 3586 * 2,
+        };
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        assert!(fs.lookup(b"/home").is_ok());
+        let err = match fs.lookup(b"/home/funky/README.md") {
+            Err(err) => err,
+            Ok(_) => panic!("read past the bad sector"),
+        };
+        assert_eq!(
+            err.volume_error::<CrcError>(),
+            Some(&CrcError { sector: 3587 * 2 })
+        );
+        assert!(err.volume_error::<fmt::Error>().is_none());
+        assert_eq!(err.errno(), ::error::errno::EIO);
+    }
 }
@@ -0,0 +1,237 @@
+use core::ops::Range;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// Exposes the bytes `[start, start + len)` of another volume as a volume of
+/// its own, such as a partition of a whole disk. Addresses are relative to
+/// `start`, and nothing outside the window can be read or written.
+pub struct OffsetVolume<V> {
+    volume: V,
+    start: u64,
+    len: u64,
+}
+
+impl<V> OffsetVolume<V> {
+    /// Wraps `volume`, which only needs to be as large as the window once
+    /// it's read from or written to
+    pub fn new(volume: V, start: u64, len: u64) -> OffsetVolume<V> {
+        OffsetVolume { volume, start, len }
+    }
+
+    /// Returns the offset of the window into the volume underneath
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the volume underneath
+    pub fn inner(&self) -> &V {
+        &self.volume
+    }
+
+    /// Drops the window, returning the volume underneath
+    pub fn into_inner(self) -> V {
+        self.volume
+    }
+
+    /// Fails with an address relative to the window if `end` lies past it
+    fn check_bounds<S: SectorSize>(&self, end: Address<S>) -> Result<()> {
+        if end.into_index() > self.len {
+            Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn translate<S: SectorSize>(&self, address: Address<S>) -> Address<S> {
+        Address::from(self.start + address.into_index())
+    }
+}
+
+impl<T: Clone, S: SectorSize, V: Volume<T, S>> Volume<T, S>
+    for OffsetVolume<V>
+{
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        let len = match self.volume.size() {
+            Size::Bounded(size) => {
+                size.into_index().saturating_sub(self.start).min(self.len)
+            }
+            Size::Unbounded => self.len,
+        };
+        Size::Bounded(Address::from(len))
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<T, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let end = Address::<S>::from(
+            slice.address().into_index() + slice.len() as u64,
+        );
+        self.check_bounds(end)?;
+        let address = self.translate(slice.address());
+        let commit = VolumeCommit::new(slice.into_inner(), address);
+        self.volume.commit(Some(commit)).map_err(|err| err.into())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.volume.flush().map_err(|err| err.into())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, T, S> {
+        let slice = self.volume.slice_unchecked(
+            self.translate(range.start)..self.translate(range.end),
+        );
+        VolumeSlice {
+            inner: slice.inner,
+            index: range.start,
+        }
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, T, S>, Self::Error> {
+        self.check_bounds(range.end)?;
+        let slice = self
+            .volume
+            .slice(self.translate(range.start)..self.translate(range.end))
+            .map_err(|err| err.into())?;
+        Ok(VolumeSlice {
+            inner: slice.inner,
+            index: range.start,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use fs::sync::tests::fsck;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use sys::inode::Permissions;
+    use volume::mbr::tests::write_mbr;
+    use volume::mbr::{self, MBR_SECTOR_SIZE};
+    use volume::size::Size;
+    use volume::sparse::SparseMem;
+    use volume::{Volume, VolumeCommit};
+
+    use super::OffsetVolume;
+
+    #[test]
+    fn window() {
+        let base = (0..2048).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut volume = OffsetVolume::new(base.clone(), 300, 1000);
+        assert_eq!(
+            Volume::<u8, Size512>::size(&volume),
+            Size::Bounded(Address::from(1000_u64))
+        );
+
+        let slice = volume
+            .slice(Address::<Size512>::new(0, 10)..Address::new(1, 0))
+            .unwrap();
+        assert_eq!(slice.address(), Address::new(0, 10));
+        assert_eq!(&slice[..], &base[310..812]);
+
+        let commit =
+            VolumeCommit::new(vec![0xff; 10], Address::<Size512>::new(1, 478));
+        volume.commit(Some(commit)).unwrap();
+        assert!(volume.inner()[1300 - 10..1300].iter().all(|&b| b == 0xff));
+        assert_eq!(volume.inner()[1300], base[1300]);
+
+        // errors are in the window's own coordinates
+        let end = Address::<Size512>::from(1001_u64);
+        match volume.slice(Address::new(0, 0)..end) {
+            Err(Error::AddressOutOfBounds {
+                sector: 1,
+                offset: 489,
+                size: 512,
+            }) => (),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+        let commit =
+            VolumeCommit::new(vec![0; 2], Address::<Size512>::new(1, 487));
+        assert!(volume.commit(Some(commit)).is_err());
+
+        // a window running past the end of the volume is cut short
+        let volume = OffsetVolume::new(base, 1500, 1000);
+        assert_eq!(
+            Volume::<u8, Size512>::size(&volume),
+            Size::Bounded(Address::from(548_u64))
+        );
+    }
+
+    #[test]
+    fn partition() {
+        let image = std::fs::read("ext2.img").unwrap();
+        let sectors = image.len() as u32 / MBR_SECTOR_SIZE as u32;
+        let mut disk = vec![0; 2048 * 512 + 1024 * 512 + image.len()];
+        write_mbr(&mut disk, &[(0x0b, 2048, 1024), (0x83, 3072, sectors)]);
+        disk[3072 * 512..].copy_from_slice(&image);
+
+        let part = mbr::partitions::<Size512, _>(&disk).unwrap()[1].unwrap();
+        let volume = OffsetVolume::new(disk, part.start(), part.len());
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut data = Vec::new();
+        fs.lookup(b"/home/funky/README.md")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data.len(), 31);
+
+        let mut file = fs.create_file(b"/inside", Permissions::U_READ).unwrap();
+        file.write_at(0, &[7; 5000]).unwrap();
+        drop(file);
+        let disk = fs.inner().volume.inner().clone();
+        assert!(disk[..3072 * 512].iter().skip(512).all(|&b| b == 0));
+        assert!(fsck(&disk[3072 * 512..]));
+    }
+
+    #[test]
+    fn past_2_tib() {
+        // sector 2^32 + 3, where 32-bit sectors would wrap around to 3
+        let start = (2 << 40) + 3 * 512;
+        let image = std::fs::read("ext2.img").unwrap();
+        let len = image.len() as u64;
+        let mut disk = SparseMem::<Size512>::new(start + len);
+        let commit = VolumeCommit::new(image, Address::from(start));
+        disk.commit(Some(commit)).unwrap();
+
+        let volume = OffsetVolume::new(disk, start, len);
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut data = Vec::new();
+        fs.lookup(b"/home/funky/README.md")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data.len(), 31);
+
+        let mut file = fs.create_file(b"/beyond", Permissions::U_READ).unwrap();
+        file.write_at(0, &[7; 5000]).unwrap();
+        drop(file);
+        let fs = fs.inner();
+        let disk = fs.volume.inner();
+        let low = Address::<Size512>::new(0, 0)..Address::from(len + 4096);
+        assert!(disk.slice(low).unwrap().iter().all(|&b| b == 0));
+        let window = Address::from(start)..Address::from(start + len);
+        assert!(fsck(&disk.slice(window).unwrap()));
+    }
+}
@@ -0,0 +1,228 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// A copy-on-write layer over a volume. Writes land in an in-memory copy of
+/// the sectors they touch and never reach the base volume; reads see those
+/// copies in place of the base's sectors. This makes it safe to run the
+/// write path against a real disk image.
+///
+/// The changes can be inspected with `diff` and written out with
+/// `commit_to`.
+pub struct Overlay<S: SectorSize, V: Volume<u8, S>> {
+    base: V,
+    dirty: BTreeMap<u64, Vec<u8>>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Overlay<S, V> {
+    /// Wraps `base`, which is only ever read from
+    pub fn new(base: V) -> Overlay<S, V> {
+        Overlay {
+            base,
+            dirty: BTreeMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the underlying volume, as it was before any writes
+    pub fn base(&self) -> &V {
+        &self.base
+    }
+
+    /// Drops the overlay along with all writes, returning the base volume
+    pub fn into_inner(self) -> V {
+        self.base
+    }
+
+    /// Drops all writes made so far
+    pub fn discard(&mut self) {
+        self.dirty.clear();
+    }
+
+    /// Returns the address and contents of every sector written to, in
+    /// order of their addresses
+    pub fn diff(&self) -> Vec<(Address<S>, &[u8])> {
+        self.dirty
+            .iter()
+            .map(|(&sector, data)| (Address::new(sector, 0), &data[..]))
+            .collect()
+    }
+
+    /// Writes every sector written to so far into `volume`, which would
+    /// usually be a writable handle to the base volume. The overlay itself
+    /// is left as it is.
+    pub fn commit_to<W: Volume<u8, S>>(
+        &self,
+        volume: &mut W,
+    ) -> Result<()> {
+        for (&sector, data) in self.dirty.iter() {
+            let commit =
+                VolumeCommit::new(data.clone(), Address::new(sector, 0));
+            volume.commit(Some(commit)).map_err(|err| err.into())?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current contents of sector `sector`, which may be short
+    /// if it's the last one of the volume
+    fn sector(&self, sector: u64) -> Result<Vec<u8>> {
+        if let Some(data) = self.dirty.get(&sector) {
+            return Ok(data.clone());
+        }
+        let start = Address::new(sector, 0);
+        let mut end = Address::new(sector + 1, 0);
+        if let Size::Bounded(size) = self.base.size() {
+            if size < end {
+                end = size;
+            }
+        }
+        let slice = self.base.slice(start..end).map_err(|err| err.into())?;
+        Ok(slice.to_vec())
+    }
+
+    /// Replaces the bytes of `slice`, read from the base volume, with those
+    /// of the sectors written to
+    fn patch<'a>(
+        &self,
+        slice: VolumeSlice<'a, u8, S>,
+    ) -> VolumeSlice<'a, u8, S> {
+        let start = slice.address().into_index();
+        let end = start + slice.len() as u64;
+        let first = slice.address().sector();
+        let last = Address::<S>::from(end).sector();
+        let mut dirty = self.dirty.range(first..=last).peekable();
+        if dirty.peek().is_none() {
+            return slice;
+        }
+
+        let mut data = slice.to_vec();
+        for (&sector, contents) in dirty {
+            let sector_start = Address::<S>::new(sector, 0).into_index();
+            let from = start.max(sector_start);
+            let to = end.min(sector_start + contents.len() as u64);
+            if from < to {
+                data[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(
+                        &contents[(from - sector_start) as usize
+                            ..(to - sector_start) as usize],
+                    );
+            }
+        }
+        VolumeSlice::new_owned(data, slice.address())
+    }
+}
+
+impl<S: SectorSize, V: Volume<u8, S>> Volume<u8, S> for Overlay<S, V> {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        self.base.size()
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let start = slice.address().into_index();
+        let end = Address::<S>::from(start + slice.len() as u64);
+        if self.size() < end {
+            return Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            });
+        }
+
+        let mut written = 0;
+        while written < slice.len() {
+            let position = Address::<S>::from(start + written as u64);
+            let offset = position.offset() as usize;
+            let mut data = self.sector(position.sector())?;
+            let len = (data.len() - offset).min(slice.len() - written);
+            data[offset..offset + len]
+                .copy_from_slice(&slice[written..written + len]);
+            self.dirty.insert(position.sector(), data);
+            written += len;
+        }
+        Ok(())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, u8, S> {
+        self.patch(self.base.slice_unchecked(range))
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+        let slice = self.base.slice(range).map_err(|err| err.into())?;
+        Ok(self.patch(slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sector::{Address, Size512};
+    use volume::{Volume, VolumeCommit};
+
+    use super::Overlay;
+
+    #[test]
+    fn overlay() {
+        let base = (0..2048).map(|i| i as u8).collect::<Vec<u8>>();
+        let mut overlay = Overlay::<Size512, _>::new(base.clone());
+
+        // spanning the end of sector 0 and the start of sector 1
+        let commit = VolumeCommit::new(vec![0xff; 100], Address::new(0, 450));
+        overlay.commit(Some(commit)).unwrap();
+        assert_eq!(overlay.base(), &base);
+
+        let read =
+            |overlay: &Overlay<Size512, Vec<u8>>, start: u64, end: u64| {
+                overlay
+                    .slice(Address::from(start)..Address::from(end))
+                    .unwrap()
+                    .to_vec()
+            };
+        assert_eq!(read(&overlay, 400, 450), base[400..450]);
+        assert!(read(&overlay, 450, 550).iter().all(|&b| b == 0xff));
+        assert_eq!(read(&overlay, 550, 2048), base[550..]);
+        let mut expected = base.clone();
+        expected[450..550].iter_mut().for_each(|b| *b = 0xff);
+        assert_eq!(read(&overlay, 0, 2048), expected);
+
+        let diff = overlay.diff();
+        assert_eq!(diff.len(), 2);
+        assert_eq!(diff[0].0.into_index(), 0);
+        assert_eq!(diff[0].1, &expected[..512]);
+        assert_eq!(diff[1].0.into_index(), 512);
+        assert_eq!(diff[1].1, &expected[512..1024]);
+
+        let mut copy = base.clone();
+        overlay.commit_to(&mut copy).unwrap();
+        assert_eq!(copy, expected);
+
+        overlay.discard();
+        assert!(overlay.diff().is_empty());
+        assert_eq!(read(&overlay, 0, 2048), base);
+
+        let commit = VolumeCommit::new(vec![0; 10], Address::new(4, 0));
+        assert!(overlay.commit(Some(commit)).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use core::ops::Range;
+
+use alloc::borrow::Cow;
+use alloc::sync::Arc;
+
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use error::Error;
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// A volume behind a reader-writer lock, so that several filesystem handles
+/// can use it at once: `Volume` is implemented for `&Shared<V>` and
+/// `Arc<Shared<V>>`, each of which can be handed to its own `Ext2`.
+///
+/// Slices are taken under the read lock and commits under the write lock,
+/// so a slice sees either all of a commit or none of it, however many
+/// sectors either spans. Since the lock is released before a slice is
+/// returned, slices are always copies. Only single commits are atomic: an
+/// operation made of several commits, such as writing to a file, can still
+/// be observed half done.
+///
+/// Each `Ext2` keeps its own copy of the superblock and group descriptors,
+/// so only one of the handles sharing a volume should ever write to it.
+pub struct Shared<V> {
+    volume: RwLock<V>,
+}
+
+impl<V> Shared<V> {
+    pub fn new(volume: V) -> Shared<V> {
+        Shared {
+            volume: RwLock::new(volume),
+        }
+    }
+
+    /// Locks the volume for reading, blocking writers until the guard is
+    /// dropped
+    pub fn read(&self) -> RwLockReadGuard<'_, V> {
+        self.volume.read()
+    }
+
+    /// Locks the volume for writing, blocking everyone else until the guard
+    /// is dropped
+    pub fn write(&self) -> RwLockWriteGuard<'_, V> {
+        self.volume.write()
+    }
+
+    pub fn into_inner(self) -> V {
+        self.volume.into_inner()
+    }
+}
+
+/// Copies `slice` so that it outlives the lock it was taken under
+fn owned<'a, T: Clone, S: SectorSize>(
+    slice: &VolumeSlice<'_, T, S>,
+) -> VolumeSlice<'a, T, S> {
+    VolumeSlice {
+        inner: Cow::Owned(slice.to_vec()),
+        index: slice.address(),
+    }
+}
+
+macro_rules! impl_shared {
+    ($volume:ty $( , $lt:lifetime )* ) => {
+        impl<$( $lt, )* T: Clone, S: SectorSize, V: Volume<T, S>> Volume<T, S>
+            for $volume
+        {
+            type Error = Error;
+
+            fn size(&self) -> Size<S> {
+                self.read().size()
+            }
+
+            fn commit(
+                &mut self,
+                slice: Option<VolumeCommit<T, S>>,
+            ) -> Result<(), Self::Error> {
+                self.write().commit(slice).map_err(|err| err.into())
+            }
+
+            fn flush(&mut self) -> Result<(), Self::Error> {
+                self.write().flush().map_err(|err| err.into())
+            }
+
+            unsafe fn slice_unchecked(
+                &self,
+                range: Range<Address<S>>,
+            ) -> VolumeSlice<'_, T, S> {
+                let volume = self.read();
+                let slice = volume.slice_unchecked(range);
+                owned(&slice)
+            }
+
+            fn slice(
+                &self,
+                range: Range<Address<S>>,
+            ) -> Result<VolumeSlice<'_, T, S>, Self::Error> {
+                let volume = self.read();
+                let slice = volume.slice(range).map_err(|err| err.into())?;
+                Ok(owned(&slice))
+            }
+        }
+    };
+}
+
+impl_shared!(&'a Shared<V>, 'a);
+impl_shared!(Arc<Shared<V>>);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use volume::{Volume, VolumeCommit};
+
+    use super::Shared;
+
+    #[test]
+    fn no_torn_reads() {
+        let shared = Arc::new(Shared::new(vec![0_u8; 16 * 512]));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = shared.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut reads = 0;
+                    while !done.load(Ordering::SeqCst) || reads == 0 {
+                        let slice = shared
+                            .slice(
+                                Address::<Size512>::new(1, 100)
+                                    ..Address::new(13, 0),
+                            )
+                            .unwrap();
+                        let first = slice[0];
+                        assert!(slice.iter().all(|&byte| byte == first));
+                        reads += 1;
+                    }
+                })
+            })
+            .collect();
+
+        let mut writer = shared.clone();
+        for i in 0..2000 {
+            let commit = VolumeCommit::new(
+                vec![i as u8; 14 * 512],
+                Address::<Size512>::new(1, 0),
+            );
+            writer.commit(Some(commit)).unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn concurrent_handles() {
+        let shared = Shared::new(std::fs::read("ext2.img").unwrap());
+        let a = Synced::<Ext2<Size512, _>>::new(&shared).unwrap();
+        let b = Synced::<Ext2<Size512, _>>::new(&shared).unwrap();
+
+        let first = a.lookup(b"/home/funky/README.md").unwrap();
+        let second = b.lookup(b"/home/funky/README.md").unwrap();
+        let (mut x, mut y) = (Vec::new(), Vec::new());
+        first.read_to_end(&mut x).unwrap();
+        second.read_to_end(&mut y).unwrap();
+        assert_eq!(x.len(), 31);
+        assert_eq!(x, y);
+    }
+}
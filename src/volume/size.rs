@@ -1,5 +1,5 @@
-use core::fmt::{self, Display};
 use core::cmp::Ordering;
+use core::fmt::{self, Display};
 
 use sector::{Address, SectorSize};
 
@@ -34,10 +34,10 @@ impl<S: SectorSize> Display for Size<S> {
 
 impl<S: SectorSize> PartialEq for Size<S> {
     fn eq(&self, rhs: &Self) -> bool {
-        match (self, rhs) {
-            (&Size::Unbounded, _) => false,
-            (_, &Size::Unbounded) => false,
-            (&Size::Bounded(ref a), &Size::Bounded(ref b)) => a.eq(b),
+        match (*self, *rhs) {
+            (Size::Unbounded, _) => false,
+            (_, Size::Unbounded) => false,
+            (Size::Bounded(a), Size::Bounded(b)) => a.eq(&b),
         }
     }
 }
@@ -53,11 +53,11 @@ impl<S: SectorSize> PartialEq<Address<S>> for Size<S> {
 
 impl<S: SectorSize> PartialOrd for Size<S> {
     fn partial_cmp(&self, rhs: &Self) -> Option<Ordering> {
-        match (self, rhs) {
-            (&Size::Unbounded, &Size::Unbounded) => None,
-            (&Size::Unbounded, _) => Some(Ordering::Greater),
-            (_, &Size::Unbounded) => Some(Ordering::Less),
-            (&Size::Bounded(ref a), &Size::Bounded(ref b)) => a.partial_cmp(b),
+        match (*self, *rhs) {
+            (Size::Unbounded, Size::Unbounded) => None,
+            (Size::Unbounded, _) => Some(Ordering::Greater),
+            (_, Size::Unbounded) => Some(Ordering::Less),
+            (Size::Bounded(a), Size::Bounded(b)) => a.partial_cmp(&b),
         }
     }
 }
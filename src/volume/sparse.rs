@@ -0,0 +1,225 @@
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+
+use error::{Error, Result};
+use sector::{Address, SectorSize};
+
+use super::size::Size;
+use super::{Volume, VolumeCommit, VolumeSlice};
+
+/// An in-memory volume that only stores the sectors written to, so that its
+/// size can be far larger than the memory it takes up. Sectors never
+/// written to read as zeroes, and writing zeroes to them leaves them
+/// unstored.
+pub struct SparseMem<S: SectorSize> {
+    sectors: BTreeMap<u64, Box<[u8]>>,
+    size: Size<S>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: SectorSize> SparseMem<S> {
+    /// Creates a volume of `size` bytes, all of them zeroes
+    pub fn new(size: u64) -> SparseMem<S> {
+        SparseMem::with_size(Size::Bounded(Address::from(size)))
+    }
+
+    /// Creates a volume of `size`, which may be `Size::Unbounded`
+    pub fn with_size(size: Size<S>) -> SparseMem<S> {
+        SparseMem {
+            sectors: BTreeMap::new(),
+            size,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the number of sectors stored
+    pub fn allocated_sectors(&self) -> usize {
+        self.sectors.len()
+    }
+
+    fn check_bounds(&self, end: Address<S>) -> Result<()> {
+        if self.size < end {
+            Err(Error::AddressOutOfBounds {
+                sector: end.sector(),
+                offset: end.offset(),
+                size: end.sector_size(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    fn read(&self, range: Range<Address<S>>) -> VolumeSlice<'static, u8, S> {
+        let start = range.start.into_index();
+        let end = range.end.into_index().max(start);
+        let mut data = vec![0; (end - start) as usize];
+        if end > start {
+            let last = Address::<S>::from(end - 1).sector();
+            for (&sector, contents) in
+                self.sectors.range(range.start.sector()..=last)
+            {
+                let sector_start = Address::<S>::new(sector, 0).into_index();
+                let from = start.max(sector_start);
+                let to = end.min(sector_start + S::SIZE as u64);
+                data[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(
+                        &contents[(from - sector_start) as usize
+                            ..(to - sector_start) as usize],
+                    );
+            }
+        }
+        VolumeSlice::new_owned(data, range.start)
+    }
+}
+
+impl<S: SectorSize> Volume<u8, S> for SparseMem<S> {
+    type Error = Error;
+
+    fn size(&self) -> Size<S> {
+        self.size
+    }
+
+    fn commit(
+        &mut self,
+        slice: Option<VolumeCommit<u8, S>>,
+    ) -> Result<(), Self::Error> {
+        let slice = match slice {
+            Some(slice) => slice,
+            None => return Ok(()),
+        };
+        let start = slice.address().into_index();
+        self.check_bounds(Address::from(start + slice.len() as u64))?;
+
+        let mut written = 0;
+        while written < slice.len() {
+            let position = Address::<S>::from(start + written as u64);
+            let offset = position.offset() as usize;
+            let len = (S::SIZE - offset).min(slice.len() - written);
+            let data = &slice[written..written + len];
+            written += len;
+
+            let sector = match self.sectors.get_mut(&position.sector()) {
+                Some(sector) => sector,
+                None if data.iter().all(|&byte| byte == 0) => continue,
+                None => self
+                    .sectors
+                    .entry(position.sector())
+                    .or_insert_with(|| vec![0; S::SIZE].into_boxed_slice()),
+            };
+            sector[offset..offset + len].copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    unsafe fn slice_unchecked(
+        &self,
+        range: Range<Address<S>>,
+    ) -> VolumeSlice<'_, u8, S> {
+        self.read(range)
+    }
+
+    fn slice(
+        &self,
+        range: Range<Address<S>>,
+    ) -> Result<VolumeSlice<'_, u8, S>, Self::Error> {
+        self.check_bounds(range.end)?;
+        Ok(self.read(range))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fs::mkfs::Builder;
+    use fs::sync::Synced;
+    use fs::Ext2;
+    use sector::{Address, Size512};
+    use sys::inode::Permissions;
+    use volume::{Volume, VolumeCommit};
+
+    use super::SparseMem;
+
+    #[test]
+    fn sparse() {
+        let mut volume = SparseMem::<Size512>::new(1 << 40);
+        let range = Address::new(1000, 0)..Address::new(1002, 0);
+        assert!(volume.slice(range.clone()).unwrap().iter().all(|&b| b == 0));
+
+        let commit = VolumeCommit::new(vec![0; 4096], Address::new(8, 0));
+        volume.commit(Some(commit)).unwrap();
+        assert_eq!(volume.allocated_sectors(), 0);
+        let commit = VolumeCommit::new(vec![7; 100], Address::new(1000, 462));
+        volume.commit(Some(commit)).unwrap();
+        assert_eq!(volume.allocated_sectors(), 2);
+        // zeroes do overwrite sectors that are stored
+        let commit = VolumeCommit::new(vec![0; 10], Address::new(1000, 462));
+        volume.commit(Some(commit)).unwrap();
+        assert_eq!(volume.allocated_sectors(), 2);
+
+        let slice = volume.slice(range).unwrap();
+        assert!(slice[..472].iter().all(|&b| b == 0));
+        assert!(slice[472..562].iter().all(|&b| b == 7));
+        assert!(slice[562..].iter().all(|&b| b == 0));
+
+        let end = Address::<Size512>::from((1_u64 << 40) + 1);
+        assert!(volume.slice(Address::new(0, 0)..end).is_err());
+        let commit =
+            VolumeCommit::new(vec![1; 2], Address::from((1_u64 << 40) - 1));
+        assert!(volume.commit(Some(commit)).is_err());
+    }
+
+    #[test]
+    fn triply_indirect() {
+        let mut volume = SparseMem::<Size512>::new(2 << 30);
+        Builder::new()
+            .bytes_per_inode(1 << 20)
+            .build::<Size512, _>(&mut volume)
+            .unwrap();
+        let fs = Synced::<Ext2<Size512, _>>::new(volume).unwrap();
+        let mut file = fs.create_file(b"/huge", Permissions::U_READ).unwrap();
+
+        // block 1 Mi is well past the 12 + 256 + 256 * 256 blocks below the
+        // triply indirect block
+        let index = 1 << 20;
+        let data = (0..4096).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        file.write_at(index << 10, &data).unwrap();
+        assert_eq!(file.size() as u64, (index << 10) + 4096);
+        assert!(fs.inner().volume.allocated_sectors() < 4096);
+
+        // walk the three levels by hand
+        let num = fs
+            .root_inode()
+            .directory()
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .find(|entry| entry.name == b"huge")
+            .unwrap()
+            .ino;
+        let fs = fs.inner();
+        let read = |block: u32, start: usize, len: usize| {
+            let start =
+                Address::<Size512>::with_block_size(block, start as i32, 10);
+            let end = start + Address::from(len);
+            fs.volume.slice(start..end).unwrap().to_vec()
+        };
+        let entry = |block: u32, i: usize| {
+            let bytes = read(block, i * 4, 4);
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        };
+        let triply = fs.read_inode(num).unwrap().triply_indirect;
+        let index = index as usize - 12 - 256 - 256 * 256;
+        let doubly = entry(triply, index >> 16);
+        let indirect = entry(doubly, (index >> 8) & 255);
+        let block = entry(indirect, index & 255);
+        assert!(doubly != 0 && indirect != 0 && block != 0);
+        assert_eq!(read(block, 0, 1024), &data[..1024]);
+        // the blocks before are holes
+        assert_eq!(entry(indirect, (index & 255) - 1), 0);
+        drop(fs);
+        assert_eq!(file.block(1 << 20).unwrap().get(), block);
+        assert!(file.block((1 << 20) - 1).is_none());
+    }
+}